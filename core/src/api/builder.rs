@@ -3,19 +3,30 @@
 //! Builder pattern for creating MePassa clients.
 
 use libp2p::{identity::Keypair, PeerId};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use super::client::Client;
+use super::events::{ClientEvent, EventBus};
+use super::outbox::OfflineStore;
+use super::policy::EncryptionPolicy;
 use crate::{
-    crypto::session::SessionManager,
-    identity::Identity,
-    network::{MessageEvent, NetworkManager},
-    storage::{Database, migrate, needs_migration},
+    crypto::{encrypt_for_storage, session::SessionManager},
+    group::GroupEvent,
+    identity::{
+        BackupContents, FileIdentityStorage, Identity, IdentityStorage, KeyProtector,
+        NoProtection, PreKeyDirectory, PreKeyPool, PreKeyPoolStore, SignedPreKeyPolicy,
+        PREKEY_POOL_SIZE,
+    },
+    identity_client::IdentityServerDirectory,
+    network::{retry::RetryPolicy, FrameLimits, MessageEvent, NetworkEvent, NetworkManager},
+    storage::{Database, MessageStatus, NewContact, NewMessage, migrate, needs_migration},
+    store_client::StoreForwardClient,
+    sync::DeviceDirectory,
     utils::error::{MePassaError, Result},
 };
 #[cfg(any(feature = "voip", feature = "video"))]
-use crate::voip::{CallManager, VoIPIntegration};
+use crate::voip::{CallEvent, CallManager, VoIPIntegration};
 use std::sync::Arc;
 use tokio::sync::{mpsc, RwLock};
 
@@ -24,6 +35,24 @@ pub struct ClientBuilder {
     data_dir: Option<PathBuf>,
     keypair: Option<Keypair>,
     bootstrap_peers: Vec<(libp2p::PeerId, libp2p::Multiaddr)>,
+    relay_server: Option<(libp2p::PeerId, libp2p::Multiaddr)>,
+    encryption_policy: EncryptionPolicy,
+    frame_limits: FrameLimits,
+    retry_policy: RetryPolicy,
+    offline_store: Option<Arc<dyn OfflineStore>>,
+    store_server_url: Option<String>,
+    device_directory: Option<Arc<dyn DeviceDirectory>>,
+    prekey_directory: Option<Arc<dyn PreKeyDirectory>>,
+    signed_prekey_policy: SignedPreKeyPolicy,
+    identity_server_url: Option<String>,
+    key_protector: Arc<dyn KeyProtector>,
+    restore: Option<Restore>,
+}
+
+/// Backup to restore on `build`
+enum Restore {
+    Sealed { backup: Vec<u8>, passphrase: String },
+    Opened(Box<BackupContents>),
 }
 
 impl ClientBuilder {
@@ -33,6 +62,18 @@ impl ClientBuilder {
             data_dir: None,
             keypair: None,
             bootstrap_peers: Vec::new(),
+            relay_server: None,
+            encryption_policy: EncryptionPolicy::default(),
+            frame_limits: FrameLimits::default(),
+            retry_policy: RetryPolicy::default(),
+            offline_store: None,
+            store_server_url: None,
+            device_directory: None,
+            prekey_directory: None,
+            signed_prekey_policy: SignedPreKeyPolicy::default(),
+            identity_server_url: None,
+            key_protector: Arc::new(NoProtection),
+            restore: None,
         }
     }
 
@@ -54,6 +95,102 @@ impl ClientBuilder {
         self
     }
 
+    /// Set the circuit relay server (usually a bootstrap node) for reservations and circuits
+    pub fn relay_server(mut self, peer_id: libp2p::PeerId, addr: libp2p::Multiaddr) -> Self {
+        self.relay_server = Some((peer_id, addr));
+        self
+    }
+
+    /// Set the encryption policy (defaults to `EncryptionPolicy::RequireE2E`)
+    pub fn encryption_policy(mut self, policy: EncryptionPolicy) -> Self {
+        self.encryption_policy = policy;
+        self
+    }
+
+    /// Set the maximum frame sizes the wire codecs accept
+    pub fn frame_limits(mut self, limits: FrameLimits) -> Self {
+        self.frame_limits = limits;
+        self
+    }
+
+    /// Set the retry policy for queued outgoing messages
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Set the store-and-forward server used once direct delivery gave up
+    pub fn offline_store(mut self, store: Arc<dyn OfflineStore>) -> Self {
+        self.offline_store = Some(store);
+        self
+    }
+
+    /// Use the Message Store at `url`, signing requests with the client's key
+    ///
+    /// Ignored when a store was set with `offline_store`.
+    pub fn store_server(mut self, url: impl Into<String>) -> Self {
+        self.store_server_url = Some(url.into());
+        self
+    }
+
+    /// Set the directory that publishes the devices linked to each account
+    pub fn device_directory(mut self, directory: Arc<dyn DeviceDirectory>) -> Self {
+        self.device_directory = Some(directory);
+        self
+    }
+
+    /// Set the directory that hands out our one-time prekeys
+    pub fn prekey_directory(mut self, directory: Arc<dyn PreKeyDirectory>) -> Self {
+        self.prekey_directory = Some(directory);
+        self
+    }
+
+    /// Set how often the signed prekey is rotated and how long replaced ones
+    /// keep working (defaults to weekly, kept two weeks)
+    ///
+    /// Rotation happens in `Client::run_prekey_maintenance`.
+    pub fn signed_prekey_policy(mut self, policy: SignedPreKeyPolicy) -> Self {
+        self.signed_prekey_policy = policy;
+        self
+    }
+
+    /// Publish linked devices and prekeys on the Identity Server at `url`
+    ///
+    /// Ignored for what was set with `device_directory` or `prekey_directory`.
+    pub fn identity_server(mut self, url: impl Into<String>) -> Self {
+        self.identity_server_url = Some(url.into());
+        self
+    }
+
+    /// Protect the identity secret and the database key on disk with
+    /// `protector` (defaults to `NoProtection`)
+    ///
+    /// An unprotected data directory is moved to `protector` on `build`;
+    /// one protected by another kind of protector fails to open.
+    pub fn key_protector(mut self, protector: Arc<dyn KeyProtector>) -> Self {
+        self.key_protector = protector;
+        self
+    }
+
+    /// Restore the identity, prekeys, contacts and history of a backup made
+    /// with `Client::export_backup` (instead of a `keypair`)
+    ///
+    /// The client keeps the backed-up peer id. `build` fails on a wrong
+    /// passphrase or if the data directory holds another identity.
+    pub fn restore_from_backup(mut self, backup: Vec<u8>, passphrase: impl Into<String>) -> Self {
+        self.restore = Some(Restore::Sealed {
+            backup,
+            passphrase: passphrase.into(),
+        });
+        self
+    }
+
+    /// Restore a backup that was already opened
+    pub(crate) fn restore_from_contents(mut self, contents: BackupContents) -> Self {
+        self.restore = Some(Restore::Opened(Box::new(contents)));
+        self
+    }
+
     /// Build the client
     pub async fn build(self) -> Result<Client> {
         // Get or create data directory
@@ -66,37 +203,35 @@ impl ClientBuilder {
             MePassaError::Other(format!("Failed to create data directory: {}", e))
         })?;
 
-        // Get or generate keypair
-        let keypair = if let Some(keypair) = self.keypair {
-            keypair
-        } else {
-            // Try to load from file, or generate new one
-            let keypair_path = data_dir.join("identity.key");
-            if keypair_path.exists() {
-                // Load keypair from file
-                match load_keypair_from_file(&keypair_path) {
-                    Ok(kp) => kp,
-                    Err(e) => {
-                        tracing::warn!("Failed to load keypair from file: {}, generating new one", e);
-                        Keypair::generate_ed25519()
-                    }
-                }
-            } else {
-                // Generate new keypair and save to file
-                let keypair = Keypair::generate_ed25519();
-                if let Err(e) = save_keypair_to_file(&keypair, &keypair_path) {
-                    tracing::warn!("Failed to save keypair to file: {}", e);
-                }
-                keypair
-            }
+        let restored = match self.restore {
+            Some(Restore::Sealed { backup, passphrase }) => Some(
+                // Argon2 takes a while on purpose; keep it off the async workers
+                tokio::task::spawn_blocking(move || BackupContents::open(&backup, &passphrase))
+                    .await
+                    .map_err(|e| MePassaError::Other(format!("Backup task failed: {}", e)))??,
+            ),
+            Some(Restore::Opened(contents)) => Some(*contents),
+            None => None,
         };
 
-        // Create identity (convert from libp2p keypair)
-        let our_keypair = crate::identity::Keypair::from_libp2p_keypair(&keypair)?;
-        let mut identity = Identity::from_keypair(our_keypair);
-        identity.init_prekey_pool(100);
-        let storage_key = identity.storage_key()?;
-        let identity = Arc::new(RwLock::new(identity));
+        // The identity the caller asked for, if any
+        let wanted = match (&restored, self.keypair) {
+            (Some(contents), _) => Some(crate::identity::Keypair::from_bytes(&contents.keypair)?),
+            (None, Some(keypair)) => Some(crate::identity::Keypair::from_libp2p_keypair(&keypair)?),
+            (None, None) => None,
+        };
+        let mut identity = {
+            let data_dir = data_dir.clone();
+            let protector = Arc::clone(&self.key_protector);
+            // Unwrapping may run a KDF; keep it off the async workers
+            tokio::task::spawn_blocking(move || open_identity(&data_dir, protector, wanted))
+                .await
+                .map_err(|e| MePassaError::Other(format!("Identity task failed: {}", e)))??
+        };
+        let keypair = identity.keypair().to_libp2p_keypair()?;
+        let storage_key = identity.storage_key();
+        let group_keypair = identity.keypair().clone();
+        let session_keypair = identity.keypair().clone();
 
         // Open database
         let db_path = data_dir.join("mepassa.db");
@@ -107,26 +242,82 @@ impl ClientBuilder {
             migrate(&database)?;
         }
 
+        // Prekeys stay the same across restarts so handed-out bundles keep working
+        let prekey_store = PreKeyPoolStore::new(database.clone(), storage_key);
+        let prekey_pool = match restored.as_ref().and_then(|contents| contents.prekey_pool.as_ref()) {
+            Some(state) => PreKeyPool::from_state(identity.keypair().clone(), state)?,
+            None => match prekey_store.load(identity.keypair().clone())? {
+                Some(pool) => pool,
+                None => PreKeyPool::new(identity.keypair().clone(), PREKEY_POOL_SIZE),
+            },
+        };
+        prekey_store.save(&prekey_pool)?;
+        identity.set_prekey_pool(prekey_pool);
+        let identity = Arc::new(RwLock::new(identity));
+
         // Get peer ID from libp2p keypair
         let peer_id = libp2p::PeerId::from(keypair.public());
 
         // Ensure local peer exists as contact (required for FOREIGN KEY constraints)
         ensure_local_contact_exists(&database, &peer_id.to_string(), &keypair)?;
 
+        if let Some(contents) = &restored {
+            restore_backup_data(&database, &storage_key, &peer_id.to_string(), contents)?;
+        }
+
+        let offline_store = match (self.offline_store, self.store_server_url) {
+            (Some(store), _) => Some(store),
+            (None, Some(url)) => Some(Arc::new(StoreForwardClient::new(url, keypair.clone())?)
+                as Arc<dyn OfflineStore>),
+            (None, None) => None,
+        };
+
+        let identity_server = self
+            .identity_server_url
+            .map(|url| IdentityServerDirectory::new(url, keypair.clone()).map(Arc::new))
+            .transpose()
+            .map_err(|e| MePassaError::Other(e.to_string()))?;
+        let device_directory = self.device_directory.or_else(|| {
+            identity_server
+                .clone()
+                .map(|server| server as Arc<dyn DeviceDirectory>)
+        });
+        let prekey_directory = self
+            .prekey_directory
+            .or_else(|| identity_server.map(|server| server as Arc<dyn PreKeyDirectory>));
+
         // Create network manager
-        let network = NetworkManager::new(keypair)?;
+        let mut network = NetworkManager::with_frame_limits(keypair, self.frame_limits)?;
+        let (network_event_tx, mut network_event_rx) = mpsc::unbounded_channel();
+        network.set_event_sender(network_event_tx);
         let network_arc = Arc::new(RwLock::new(network));
 
-        let callbacks: Arc<RwLock<Vec<Box<dyn super::events::EventCallback>>>> =
-            Arc::new(RwLock::new(Vec::new()));
-        let callbacks_for_events = Arc::clone(&callbacks);
-
+        let events = Arc::new(EventBus::new());
         let (event_tx, mut event_rx) = mpsc::unbounded_channel();
 
+        // Create Group Manager (FASE 15)
+        // database.clone() shares the same SQLite connection
+        let group_manager = Arc::new(
+            crate::group::GroupManager::new(
+                peer_id.to_string(),
+                Arc::new(database.clone()),
+                group_keypair,
+                storage_key,
+            )
+            .map_err(|e| MePassaError::Other(format!("Failed to create group manager: {}", e)))?
+        );
+        let mut group_event_rx = group_manager.subscribe();
+
+        // Initialize group manager (load existing groups)
+        let group_topics = group_manager.init().await.map_err(|e| {
+            MePassaError::Other(format!("Failed to initialize group manager: {}", e))
+        })?;
+
         // Create message handler for processing incoming messages
         // IMPORTANT: database.clone() shares the same SQLite connection (via internal Arc<Mutex>)
         // This ensures messages stored by MessageHandler are visible to Client
-        let session_manager = SessionManager::new();
+        let session_manager = SessionManager::with_storage(database.clone(), storage_key)?
+            .with_identity(session_keypair);
         let message_handler = Arc::new(crate::network::MessageHandler::new(
             peer_id.to_string(),
             Arc::new(database.clone()), // Shares the same SQLite connection!
@@ -135,7 +326,11 @@ impl ClientBuilder {
             session_manager.clone(),
             storage_key,
             Some(event_tx),
-        ));
+        )
+        .with_group_manager(Arc::clone(&group_manager))
+        .with_prekey_store(prekey_store, prekey_directory)
+        .with_device_directory(device_directory.clone())
+        .with_signed_prekey_policy(self.signed_prekey_policy));
 
         // Set message handler in network manager
         {
@@ -153,28 +348,29 @@ impl ClientBuilder {
             }
         }
 
+        if let Some((peer_id, addr)) = self.relay_server {
+            tracing::info!("Using relay server {} at {}", peer_id, addr);
+            network_arc.write().await.set_relay(peer_id, addr);
+        }
+
         // Create VoIP components (only if feature is enabled)
         #[cfg(any(feature = "voip", feature = "video"))]
         let call_manager = Arc::new(CallManager::new());
         #[cfg(any(feature = "voip", feature = "video"))]
+        let mut call_event_rx = call_manager.subscribe_events().await;
+        #[cfg(any(feature = "voip", feature = "video"))]
         let voip_integration = Arc::new(
             VoIPIntegration::new(Arc::clone(&network_arc), Arc::clone(&call_manager)).await,
         );
 
-        // Create Group Manager (FASE 15)
-        // database.clone() shares the same SQLite connection
-        let group_manager = Arc::new(
-            crate::group::GroupManager::new(
-                peer_id.to_string(),
-                Arc::new(database.clone()),
-            )
-            .map_err(|e| MePassaError::Other(format!("Failed to create group manager: {}", e)))?
-        );
-
-        // Initialize group manager (load existing groups)
-        group_manager.init().await.map_err(|e| {
-            MePassaError::Other(format!("Failed to initialize group manager: {}", e))
-        })?;
+        // Route GossipSub messages to the group manager and subscribe to our groups
+        {
+            let mut network = network_arc.write().await;
+            network.set_group_manager(Arc::clone(&group_manager));
+            for topic in &group_topics {
+                network.subscribe_topic(topic)?;
+            }
+        }
 
         // Create client (keep network as Arc since it's shared with VoIPIntegration)
         // Note: database.clone() shares the same SQLite connection with MessageHandler
@@ -184,9 +380,13 @@ impl ClientBuilder {
             network_arc,
             database, // Client owns the database (shares connection via internal Arc<Mutex>)
             data_dir,
-            Arc::clone(&callbacks),
+            Arc::clone(&events),
             session_manager.clone(),
             storage_key,
+            self.encryption_policy,
+            self.retry_policy,
+            offline_store,
+            device_directory,
             #[cfg(any(feature = "voip", feature = "video"))]
             call_manager,
             #[cfg(any(feature = "voip", feature = "video"))]
@@ -194,26 +394,133 @@ impl ClientBuilder {
             group_manager,
         );
 
+        // One forwarding task per source keeps each source's events in order
+        let message_events = Arc::clone(&events);
         tokio::spawn(async move {
             while let Some(event) = event_rx.recv().await {
                 if let Some(client_event) = map_message_event(event) {
-                    let callbacks = callbacks_for_events.read().await;
-                    for callback in callbacks.iter() {
-                        callback.on_event(client_event.clone());
-                    }
+                    message_events.emit(client_event);
+                }
+            }
+        });
+
+        let network_events = Arc::clone(&events);
+        tokio::spawn(async move {
+            while let Some(event) = network_event_rx.recv().await {
+                network_events.emit(map_network_event(event));
+            }
+        });
+
+        let group_events = Arc::clone(&events);
+        tokio::spawn(async move {
+            while let Some(event) = group_event_rx.recv().await {
+                if let Some(client_event) = map_group_event(event) {
+                    group_events.emit(client_event);
                 }
             }
         });
 
+        #[cfg(any(feature = "voip", feature = "video"))]
+        {
+            let call_events = Arc::clone(&events);
+            tokio::spawn(async move {
+                while let Some(event) = call_event_rx.recv().await {
+                    if let Some(client_event) = map_call_event(event) {
+                        call_events.emit(client_event);
+                    }
+                }
+            });
+        }
+
         Ok(client)
     }
 }
 
-fn map_message_event(event: MessageEvent) -> Option<super::events::ClientEvent> {
+fn map_network_event(event: NetworkEvent) -> ClientEvent {
+    match event {
+        NetworkEvent::ReachabilityChanged { reachability } => {
+            ClientEvent::ReachabilityChanged { reachability }
+        }
+        NetworkEvent::PeerConnected { peer_id } => ClientEvent::PeerConnected { peer_id },
+        NetworkEvent::PeerDisconnected { peer_id } => ClientEvent::PeerDisconnected { peer_id },
+        NetworkEvent::PeerDiscovered { peer_id, addresses } => ClientEvent::PeerDiscovered {
+            peer_id,
+            addresses: addresses.iter().map(|addr| addr.to_string()).collect(),
+        },
+        NetworkEvent::Online => ClientEvent::NetworkOnline,
+        NetworkEvent::Offline => ClientEvent::NetworkOffline,
+    }
+}
+
+fn map_group_event(event: GroupEvent) -> Option<ClientEvent> {
+    Some(match event {
+        GroupEvent::GroupCreated { group } => ClientEvent::GroupCreated {
+            group_id: group.id,
+            name: group.name,
+        },
+        GroupEvent::GroupJoined { group_id } => ClientEvent::GroupJoined { group_id },
+        GroupEvent::GroupLeft { group_id } => ClientEvent::GroupLeft { group_id },
+        GroupEvent::GroupUpdated { group } => ClientEvent::GroupUpdated {
+            group_id: group.id,
+            name: group.name,
+        },
+        GroupEvent::MemberAdded { group_id, peer_id } => ClientEvent::GroupMemberAdded {
+            group_id,
+            peer_id: PeerId::from_str(&peer_id).ok()?,
+        },
+        GroupEvent::MemberRemoved { group_id, peer_id } => ClientEvent::GroupMemberRemoved {
+            group_id,
+            peer_id: PeerId::from_str(&peer_id).ok()?,
+        },
+        GroupEvent::MessageReceived { message, content } => ClientEvent::GroupMessageReceived {
+            from: PeerId::from_str(&message.sender_peer_id).ok()?,
+            group_id: message.group_id,
+            message_id: message.message_id,
+            content,
+            timestamp: message.timestamp,
+        },
+        GroupEvent::MessageRejected {
+            group_id,
+            message_id,
+            sender_peer_id,
+            reason,
+        } => ClientEvent::GroupMessageRejected {
+            group_id,
+            message_id,
+            from: PeerId::from_str(&sender_peer_id).ok()?,
+            reason,
+        },
+    })
+}
+
+/// Call events for the application (signaling and media stay internal)
+#[cfg(any(feature = "voip", feature = "video"))]
+fn map_call_event(event: CallEvent) -> Option<ClientEvent> {
+    match event {
+        CallEvent::IncomingCall {
+            call_id,
+            from_peer_id,
+        } => Some(ClientEvent::IncomingCall {
+            call_id,
+            from: PeerId::from_str(&from_peer_id).ok()?,
+        }),
+        CallEvent::StateChanged { call_id, new_state } => Some(ClientEvent::CallStateChanged {
+            call_id,
+            state: format!("{:?}", new_state),
+        }),
+        CallEvent::Ended { call_id, reason } => Some(ClientEvent::CallEnded {
+            call_id,
+            reason: format!("{:?}", reason),
+        }),
+        _ => None,
+    }
+}
+
+fn map_message_event(event: MessageEvent) -> Option<ClientEvent> {
     match event {
         MessageEvent::MessageReceived { message_id, message, .. } => {
             let from = PeerId::from_str(&message.sender_peer_id).ok()?;
-            Some(super::events::ClientEvent::MessageReceived {
+            Some(ClientEvent::MessageReceived {
                 message_id,
                 from,
                 message,
@@ -221,12 +528,20 @@ fn map_message_event(event: MessageEvent) -> Option<super::events::ClientEvent>
         }
         MessageEvent::MessageDelivered {
             message_id,
+            status,
             to_peer_id,
-            ..
         } => {
             let to_peer_id = to_peer_id?;
             let to = PeerId::from_str(&to_peer_id).ok()?;
-            Some(super::events::ClientEvent::MessageDelivered { message_id, to })
+            Some(if status == MessageStatus::Failed {
+                ClientEvent::MessageFailed {
+                    message_id,
+                    to,
+                    reason: "rejected by recipient".to_string(),
+                }
+            } else {
+                ClientEvent::MessageDelivered { message_id, to }
+            })
         }
         MessageEvent::MessageRead {
             message_id,
@@ -234,7 +549,7 @@ fn map_message_event(event: MessageEvent) -> Option<super::events::ClientEvent>
             read_at,
         } => {
             let by = PeerId::from_str(&by_peer_id).ok()?;
-            Some(super::events::ClientEvent::MessageRead {
+            Some(ClientEvent::MessageRead {
                 message_id,
                 by,
                 read_at,
@@ -246,11 +561,38 @@ fn map_message_event(event: MessageEvent) -> Option<super::events::ClientEvent>
         } => {
             let peer_id = PeerId::from_str(&from_peer_id).ok()?;
             Some(if is_typing {
-                super::events::ClientEvent::TypingStarted { peer_id }
+                ClientEvent::TypingStarted { peer_id }
             } else {
-                super::events::ClientEvent::TypingStopped { peer_id }
+                ClientEvent::TypingStopped { peer_id }
             })
         }
+        MessageEvent::MediaProgress {
+            message_id,
+            media_hash,
+            bytes_received,
+            total_bytes,
+        } => Some(ClientEvent::MediaProgress {
+            message_id,
+            media_hash,
+            bytes_received,
+            total_bytes,
+        }),
+        MessageEvent::DeviceLinked {
+            account_peer_id,
+            device_peer_id,
+        } => Some(ClientEvent::DeviceLinked {
+            account_peer_id: PeerId::from_str(&account_peer_id).ok()?,
+            device_peer_id: PeerId::from_str(&device_peer_id).ok()?,
+        }),
+        MessageEvent::DeviceSynced {
+            device_peer_id,
+            messages,
+            changes,
+        } => Some(ClientEvent::DeviceSynced {
+            device_peer_id: PeerId::from_str(&device_peer_id).ok()?,
+            messages: messages as u32,
+            changes: changes as u32,
+        }),
     }
 }
 
@@ -266,15 +608,94 @@ fn load_keypair_from_file(path: &std::path::Path) -> Result<Keypair> {
     })
 }
 
-/// Save a keypair to a file
-fn save_keypair_to_file(keypair: &Keypair, path: &std::path::Path) -> Result<()> {
-    let bytes = keypair.to_protobuf_encoding().map_err(|e| {
-        MePassaError::Other(format!("Failed to encode keypair: {}", e))
-    })?;
+/// Identity of the data directory, created if there is none
+///
+/// An `identity.key` from before keys were protected is moved into the
+/// identity storage. Fails if `wanted` isn't the stored identity.
+fn open_identity(
+    data_dir: &Path,
+    protector: Arc<dyn KeyProtector>,
+    wanted: Option<crate::identity::Keypair>,
+) -> Result<Identity> {
+    let storage = FileIdentityStorage::with_protector(data_dir, protector);
+    let mut stored = storage.load_identity()?;
+
+    let legacy_path = data_dir.join("identity.key");
+    if stored.is_none() && legacy_path.exists() {
+        let keypair = crate::identity::Keypair::from_libp2p_keypair(&load_keypair_from_file(&legacy_path)?)?;
+        let identity = Identity::from_legacy_keypair(keypair)?;
+        storage.save_identity(&identity)?;
+        std::fs::remove_file(&legacy_path).map_err(|e| {
+            MePassaError::Other(format!("Failed to remove keypair file: {}", e))
+        })?;
+        tracing::info!("Moved identity.key into the identity storage");
+        stored = Some(identity);
+    }
 
-    std::fs::write(path, bytes).map_err(|e| {
-        MePassaError::Other(format!("Failed to write keypair file: {}", e))
-    })
+    match (stored, wanted) {
+        (Some(stored), Some(wanted))
+            if stored.keypair().public_key_bytes() != wanted.public_key_bytes() =>
+        {
+            Err(MePassaError::AlreadyExists(
+                "Data directory holds another identity".to_string(),
+            ))
+        }
+        (Some(stored), _) => Ok(stored),
+        (None, wanted) => {
+            let identity = Identity::from_keypair(wanted.unwrap_or_else(crate::identity::Keypair::generate));
+            storage.save_identity(&identity)?;
+            Ok(identity)
+        }
+    }
+}
+
+/// Store the contacts and messages of a backup; ones we have are kept
+fn restore_backup_data(
+    database: &Database,
+    storage_key: &[u8; 32],
+    local_peer_id: &str,
+    contents: &BackupContents,
+) -> Result<()> {
+    let mut contacts = 0;
+    for contact in &contents.contacts {
+        if contact.peer_id == local_peer_id || database.get_contact_by_peer_id(&contact.peer_id).is_ok() {
+            continue;
+        }
+        database.insert_contact(&NewContact {
+            peer_id: contact.peer_id.clone(),
+            username: contact.username.clone(),
+            display_name: contact.display_name.clone(),
+            public_key: contact.public_key.clone(),
+            prekey_bundle_json: contact.prekey_bundle_json.clone(),
+        })?;
+        contacts += 1;
+    }
+
+    let mut messages = 0;
+    for message in &contents.messages {
+        let Some(peer) = message.conversation_id.strip_prefix("1:1:") else {
+            tracing::warn!("⚠️ Not restoring message {} of {}", message.message_id, message.conversation_id);
+            continue;
+        };
+        let conversation_id = database.get_or_create_conversation(peer)?;
+        let new_message = NewMessage {
+            message_id: message.message_id.clone(),
+            conversation_id,
+            sender_peer_id: message.sender_peer_id.clone(),
+            recipient_peer_id: message.recipient_peer_id.clone(),
+            message_type: message.message_type.clone(),
+            content_encrypted: Some(encrypt_for_storage(storage_key, message.content.as_bytes())?),
+            content_plaintext: None,
+            status: MessageStatus::from_str(&message.status),
+            parent_message_id: message.parent_message_id.clone(),
+        };
+        if database.import_message(&new_message, message.created_at)? {
+            messages += 1;
+        }
+    }
+
+    tracing::info!("Restored {} contacts and {} messages from backup", contacts, messages);
+    Ok(())
 }
 
 impl Default for ClientBuilder {
@@ -352,4 +773,73 @@ mod tests {
 
         assert_eq!(client.local_peer_id(), expected_peer_id);
     }
+
+    #[tokio::test]
+    async fn test_legacy_keypair_file_is_migrated() {
+        let temp_dir = TempDir::new().unwrap();
+        let data_dir = temp_dir.path().to_path_buf();
+
+        let keypair = Keypair::generate_ed25519();
+        let expected_peer_id = libp2p::PeerId::from(keypair.public());
+        std::fs::write(
+            data_dir.join("identity.key"),
+            keypair.to_protobuf_encoding().unwrap(),
+        )
+        .unwrap();
+
+        let client = ClientBuilder::new()
+            .data_dir(data_dir.clone())
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(client.local_peer_id(), expected_peer_id);
+        assert!(!data_dir.join("identity.key").exists());
+        drop(client);
+
+        // The database key stays the one derived before the migration
+        let legacy = Identity::from_legacy_keypair(
+            crate::identity::Keypair::from_libp2p_keypair(&keypair).unwrap(),
+        )
+        .unwrap();
+        let identity = open_identity(&data_dir, Arc::new(NoProtection), None).unwrap();
+        assert_eq!(identity.storage_key(), legacy.storage_key());
+    }
+
+    #[tokio::test]
+    async fn test_builder_with_passphrase() {
+        let temp_dir = TempDir::new().unwrap();
+        let data_dir = temp_dir.path().to_path_buf();
+        let protector = |passphrase: &str| -> Arc<dyn KeyProtector> {
+            let params = crate::crypto::KdfParams {
+                memory_kib: 64,
+                iterations: 1,
+                parallelism: 1,
+            };
+            Arc::new(crate::identity::PassphraseProtector::with_params(passphrase, params))
+        };
+
+        let client = ClientBuilder::new()
+            .data_dir(data_dir.clone())
+            .key_protector(protector("hunter22"))
+            .build()
+            .await
+            .unwrap();
+        let peer_id = client.local_peer_id();
+        drop(client);
+
+        let wrong = ClientBuilder::new()
+            .data_dir(data_dir.clone())
+            .key_protector(protector("hunter2"))
+            .build()
+            .await;
+        assert!(wrong.is_err());
+
+        let reopened = ClientBuilder::new()
+            .data_dir(data_dir)
+            .key_protector(protector("hunter22"))
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(reopened.local_peer_id(), peer_id);
+    }
 }
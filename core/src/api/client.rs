@@ -3,24 +3,64 @@
 //! Public API for MePassa client.
 
 use libp2p::{Multiaddr, PeerId};
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Notify, RwLock};
 use tokio::time::{timeout, Duration};
 
-use super::events::{ClientEvent, EventCallback};
+use super::events::{ClientEvent, EventBus, EventCallback, EventStream};
+use super::outbox::{OfflineMessage, OfflineStore, OUTBOX_POLL_INTERVAL};
+use super::policy::EncryptionPolicy;
 use crate::{
-    crypto::{decrypt_for_storage, encrypt_for_storage, session::SessionManager},
-    identity::Identity,
-    network::NetworkManager,
-    protocol::{pb::message::Payload, EncryptedMessage as ProtoEncryptedMessage, MediaOffer, MediaRequest, Message, MessageType, TextMessage},
-    storage::{contacts::{NewContact, UpdateContact}, Database, MediaType, MessageStatus, NewMessage, StorageError},
+    crypto::{
+        decrypt_for_storage, encrypt_for_storage, generate_media_key, media::MEDIA_CHUNK_SIZE,
+        media_digest,
+        session::SessionManager,
+    },
+    identity::{BackupContact, BackupContents, BackupMessage, Identity},
+    group::AdminAction,
+    network::{
+        message_handler::{encrypt_for_peer, queue_encrypted, MessageHandler, MEDIA_WINDOW},
+        retry::RetryPolicy,
+        ConnectionQuality, NetworkManager, Reachability,
+    },
+    protocol::{pb::{self, message::Payload}, AckStatus, EncryptedMessage as ProtoEncryptedMessage, MediaOffer, MediaRequest, Message, MessageType, TextMessage},
+    storage::{
+        contacts::{NewContact, UpdateContact}, Database, MediaType, MessageStatus, NewMessage,
+        OutboxEntry, StorageError, StoredDevice, UpdateMessage,
+    },
+    sync::{
+        crdt::{self, Change},
+        device::{
+            account_of, is_own_device, DeviceCertificate, DeviceDirectory, LinkRequest,
+            LinkedDevice, LINK_REQUEST_TTL_SECS,
+        },
+        protocol as sync_protocol,
+    },
     utils::error::{MePassaError, Result},
 };
+use prost::Message as _;
 use sha2::{Digest, Sha256};
 #[cfg(any(feature = "voip", feature = "video"))]
 use crate::voip::{CallManager, VoIPIntegration};
 
+/// How long a download may go without progress before it is resumed
+const MEDIA_STALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Resumes attempted before a download is given up
+const MEDIA_MAX_STALLS: u32 = 3;
+
+/// How long an outbox attempt waits for a dial to the recipient to complete
+const OUTBOX_CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long fetched device lists of an account are used before refetching
+const DEVICE_REFRESH_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// How often `run_prekey_maintenance` checks the signed prekey and the
+/// one-time prekeys left on the prekey directory
+pub const PREKEY_MAINTENANCE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
 /// MePassa Client
 ///
 /// Main entry point for using the MePassa P2P messaging platform.
@@ -33,8 +73,8 @@ pub struct Client {
     network: Arc<RwLock<NetworkManager>>,
     /// Local storage (SQLite) - shares connection with MessageHandler via Database::clone()
     database: Database,
-    /// Event callbacks
-    callbacks: Arc<RwLock<Vec<Box<dyn EventCallback>>>>,
+    /// Merged events of all components, delivered to callbacks and streams
+    events: Arc<EventBus>,
     /// Data directory
     data_dir: PathBuf,
     /// Call manager (VoIP)
@@ -49,6 +89,20 @@ pub struct Client {
     session_manager: SessionManager,
     /// Storage encryption key
     storage_key: [u8; 32],
+    /// What to do when a message can't be E2E encrypted
+    encryption_policy: EncryptionPolicy,
+    /// Backoff for queued outgoing messages
+    retry_policy: RetryPolicy,
+    /// Store-and-forward server for messages direct delivery gave up on
+    offline_store: Option<Arc<dyn OfflineStore>>,
+    /// Outbox entries (message, device) with a delivery attempt running
+    outbox_in_flight: Arc<Mutex<HashSet<(String, String)>>>,
+    /// Server publishing the devices linked to each account
+    device_directory: Option<Arc<dyn DeviceDirectory>>,
+    /// When the devices of each account were last fetched from the directory
+    devices_refreshed_at: Arc<Mutex<HashMap<PeerId, std::time::Instant>>>,
+    /// Wakes `run_offline_inbox` when the server has new messages for us
+    offline_notify: Arc<Notify>,
 }
 
 impl Client {
@@ -59,9 +113,13 @@ impl Client {
         network: Arc<RwLock<NetworkManager>>,
         database: Database,
         data_dir: PathBuf,
-        callbacks: Arc<RwLock<Vec<Box<dyn EventCallback>>>>,
+        events: Arc<EventBus>,
         session_manager: SessionManager,
         storage_key: [u8; 32],
+        encryption_policy: EncryptionPolicy,
+        retry_policy: RetryPolicy,
+        offline_store: Option<Arc<dyn OfflineStore>>,
+        device_directory: Option<Arc<dyn DeviceDirectory>>,
         #[cfg(any(feature = "voip", feature = "video"))]
         call_manager: Arc<CallManager>,
         #[cfg(any(feature = "voip", feature = "video"))]
@@ -73,10 +131,17 @@ impl Client {
             identity,
             network,
             database,
-            callbacks,
+            events,
             data_dir,
             session_manager,
             storage_key,
+            encryption_policy,
+            retry_policy,
+            offline_store,
+            outbox_in_flight: Arc::new(Mutex::new(HashSet::new())),
+            device_directory,
+            devices_refreshed_at: Arc::new(Mutex::new(HashMap::new())),
+            offline_notify: Arc::new(Notify::new()),
             #[cfg(any(feature = "voip", feature = "video"))]
             call_manager,
             #[cfg(any(feature = "voip", feature = "video"))]
@@ -95,14 +160,15 @@ impl Client {
         Arc::clone(&self.identity)
     }
 
-    /// Export current prekey bundle as JSON (for sharing)
+    /// Export a prekey bundle as JSON (for sharing)
+    ///
+    /// Every call reserves another one-time prekey, so give each contact
+    /// their own bundle.
     pub async fn get_prekey_bundle_json(&self) -> Result<String> {
-        let mut identity = self.identity.write().await;
-        identity.init_prekey_pool(100);
-        let pool = identity
-            .prekey_pool_mut()
-            .ok_or_else(|| MePassaError::Identity("Prekey pool not initialized".to_string()))?;
-        let bundle = pool.get_bundle();
+        let handler = self.network.read().await.message_handler().ok_or_else(|| {
+            MePassaError::Network("Message handler not initialized".to_string())
+        })?;
+        let bundle = handler.reserve_prekey_bundle().await?;
         serde_json::to_string(&bundle)
             .map_err(|e| MePassaError::Identity(format!("Failed to serialize prekey bundle: {}", e)))
     }
@@ -149,16 +215,22 @@ impl Client {
     where
         C: EventCallback + 'static,
     {
-        let mut callbacks = self.callbacks.write().await;
-        callbacks.push(Box::new(callback));
+        self.events.add_callback(Arc::new(callback));
+    }
+
+    /// Stream of all future client events
+    pub fn event_stream(&self) -> EventStream {
+        self.events.subscribe()
     }
 
-    /// Emit an event to all callbacks
+    /// The event bus, to emit or subscribe from other tasks
+    pub fn event_bus(&self) -> Arc<EventBus> {
+        Arc::clone(&self.events)
+    }
+
+    /// Emit an event on the bus
     async fn emit_event(&self, event: ClientEvent) {
-        let callbacks = self.callbacks.read().await;
-        for callback in callbacks.iter() {
-            callback.on_event(event.clone());
-        }
+        self.events.emit(event);
     }
 
     fn media_dir(&self) -> PathBuf {
@@ -232,6 +304,51 @@ impl Client {
         format!("{:x}", hasher.finalize())
     }
 
+    /// Random per-file key, the media hash derived from it, and the plaintext digest
+    ///
+    /// The hash is keyed so that the identifier visible on the wire reveals
+    /// nothing about the file content.
+    fn new_media_key(data: &[u8]) -> ([u8; 32], String, [u8; 32]) {
+        let media_key = generate_media_key();
+        let media_hash = Self::compute_media_hash(data, Some(&hex::encode(media_key)));
+        (media_key, media_hash, media_digest(data))
+    }
+
+    fn save_media_key(&self, media_hash: &str, media_key: &[u8; 32], digest: &[u8; 32]) -> Result<()> {
+        let key_data = self.encrypt_for_storage(media_key)?;
+        self.database.save_media_key(media_hash, &key_data, digest)?;
+        Ok(())
+    }
+
+    /// Wrap a media offer in the pairwise E2E session with the recipient
+    ///
+    /// Media is never sent in the clear, whatever the encryption policy.
+    fn media_offer_message(
+        &self,
+        to: PeerId,
+        message_id: &str,
+        timestamp: i64,
+        offer: &MediaOffer,
+    ) -> Result<Message> {
+        let encrypted = self
+            .encrypt_message_for_peer(&to, &offer.encode_to_vec())?
+            .ok_or_else(|| {
+                MePassaError::EncryptionRequired(format!(
+                    "no E2E session or prekey bundle for {}, can't send media",
+                    to
+                ))
+            })?;
+
+        Ok(Message {
+            id: message_id.to_string(),
+            sender_peer_id: self.local_peer_id().to_string(),
+            recipient_peer_id: to.to_string(),
+            timestamp,
+            r#type: MessageType::MediaOffer as i32,
+            payload: Some(Payload::Encrypted(encrypted)),
+        })
+    }
+
     /// Start listening on a multiaddr
     pub async fn listen_on(&self, addr: Multiaddr) -> Result<()> {
         let mut network = self.network.write().await;
@@ -242,60 +359,80 @@ impl Client {
     pub async fn connect_to_peer(&self, peer_id: PeerId, addr: Multiaddr) -> Result<()> {
         let mut network = self.network.write().await;
         network.add_peer_to_dht(peer_id, addr.clone());
-        network.dial(peer_id, addr)?;
+        // `PeerConnected` is emitted once the connection is established
+        network.dial(peer_id, addr)
 
-        self.emit_event(ClientEvent::PeerConnected { peer_id }).await;
-        Ok(())
     }
 
     /// Send a text message to a peer
+    ///
+    /// The message goes to every device of the recipient's account and to
+    /// our own other devices, each copy encrypted for that device. Copies to
+    /// other devices than `to` are wrapped in a `DeviceMessage` naming the
+    /// recipient account (and, from a linked device, our certificate).
     pub async fn send_text_message(&self, to: PeerId, content: String) -> Result<String> {
-        self.ensure_peer_connected(to).await;
-
         // Generate message ID
         let message_id = uuid::Uuid::new_v4().to_string();
         let timestamp = chrono::Utc::now().timestamp_millis();
 
-        let (message_type, payload) = match self.encrypt_message_for_peer(&to, content.as_bytes()) {
-            Ok(Some(encrypted_payload)) => (MessageType::Encrypted, Payload::Encrypted(encrypted_payload)),
-            Ok(None) => (
-                MessageType::Text,
-                Payload::Text(TextMessage {
-                    content: content.clone(),
-                    reply_to_id: String::new(),
-                    metadata: std::collections::HashMap::new(),
-                }),
-            ),
-            Err(e) => {
-                tracing::warn!("E2E encryption failed, sending plaintext: {}", e);
-                (
-                    MessageType::Text,
-                    Payload::Text(TextMessage {
-                        content: content.clone(),
-                        reply_to_id: String::new(),
-                        metadata: std::collections::HashMap::new(),
-                    }),
-                )
+        let account = self.account_peer_id();
+        self.refresh_devices_if_stale(&to).await;
+        let sender_device = self.own_certificate()?.map(|certificate| certificate.to_proto());
+
+        let mut outgoing = Vec::new();
+        let mut plaintext_reason = None;
+        for device in self.fan_out_devices(&to, &account)? {
+            // The recipient itself, from a primary device: a plain direct message
+            if device == to && sender_device.is_none() {
+                let (message_type, payload) =
+                    match self.encrypt_message_for_peer(&to, content.as_bytes()) {
+                        Ok(Some(encrypted_payload)) => {
+                            (MessageType::Encrypted, Payload::Encrypted(encrypted_payload))
+                        }
+                        Ok(None) => {
+                            let reason = "no E2E session or prekey bundle for peer".to_string();
+                            self.check_plaintext_allowed(&to, &reason).await?;
+                            plaintext_reason = Some(reason);
+                            (MessageType::Text, Self::text_payload(&content))
+                        }
+                        Err(e) => {
+                            let reason = format!("E2E encryption failed: {}", e);
+                            self.check_plaintext_allowed(&to, &reason).await?;
+                            plaintext_reason = Some(reason);
+                            (MessageType::Text, Self::text_payload(&content))
+                        }
+                    };
+                outgoing.push((device, message_type, payload));
+                continue;
             }
-        };
 
-        // Create protocol message
-        let proto_message = Message {
-            id: message_id.clone(),
-            sender_peer_id: self.local_peer_id().to_string(),
-            recipient_peer_id: to.to_string(),
-            timestamp,
-            r#type: message_type as i32,
-            payload: Some(payload),
-        };
-
-        // Send via network
-        {
-            let mut network = self.network.write().await;
-            network.send_message(to, proto_message)?;
+            let envelope = pb::DeviceMessage {
+                sender_device: sender_device.clone(),
+                recipient_account_peer_id: to.to_string(),
+                content: content.clone(),
+            };
+            match self.encrypt_message_for_peer(&device, &envelope.encode_to_vec()) {
+                Ok(Some(encrypted)) => {
+                    outgoing.push((device, MessageType::DeviceMessage, Payload::Encrypted(encrypted)))
+                }
+                // Device copies are never sent in the clear
+                result if device == to => {
+                    return Err(MePassaError::EncryptionRequired(format!(
+                        "no E2E session with {} for a message from a linked device: {}",
+                        to,
+                        result.err().map(|e| e.to_string()).unwrap_or_default()
+                    )));
+                }
+                result => tracing::warn!(
+                    "⚠️ Skipping device {} for message {}: no E2E session{}",
+                    device,
+                    message_id,
+                    result.err().map(|e| format!(" ({})", e)).unwrap_or_default()
+                ),
+            }
         }
 
-        // Store in database
+        // Store in database; it stays pending until it is on the wire
         let conversation_id = self.database.get_or_create_conversation(&to.to_string())?;
         let new_msg = NewMessage {
             message_id: message_id.clone(),
@@ -305,62 +442,94 @@ impl Client {
             message_type: "text".to_string(),
             content_encrypted: self.encrypt_for_storage(content.as_bytes()).ok(),
             content_plaintext: None,
-            status: MessageStatus::Sent,
+            status: MessageStatus::Pending,
             parent_message_id: None,
         };
         self.database.insert_message(&new_msg)?;
         self.database
             .update_conversation_last_message(&conversation_id, &message_id)?;
 
+        // Queue one copy per device for (re)delivery
+        for (device, message_type, payload) in &outgoing {
+            let proto_message = Message {
+                id: message_id.clone(),
+                sender_peer_id: self.local_peer_id().to_string(),
+                recipient_peer_id: device.to_string(),
+                timestamp,
+                r#type: *message_type as i32,
+                payload: Some(payload.clone()),
+            };
+            let queued = self.encrypt_for_storage(&proto_message.encode_to_vec())?;
+            if *message_type == MessageType::Text {
+                self.database
+                    .enqueue_plaintext_outbox(&message_id, &device.to_string(), &queued)?;
+            } else {
+                self.database
+                    .enqueue_outbox(&message_id, &device.to_string(), &queued)?;
+            }
+        }
+
         // Emit event
-        self.emit_event(ClientEvent::MessageSent {
+        if let Some(reason) = plaintext_reason {
+            tracing::warn!("⚠️ Queued message {} to {} without E2E encryption: {}", message_id, to, reason);
+            self.emit_event(ClientEvent::PlaintextFallback {
+                message_id: message_id.clone(),
+                to,
+                reason,
+            })
+            .await;
+        }
+        self.emit_event(ClientEvent::MessageQueued {
             message_id: message_id.clone(),
             to,
         })
         .await;
 
+        // First attempt right away; `run_outbox` takes over if it doesn't go out
+        for (device, _, _) in outgoing {
+            if let Some(entry) = self
+                .database
+                .get_outbox_entry(&message_id, &device.to_string())?
+            {
+                self.attempt_delivery(entry).await?;
+            }
+        }
+
         Ok(message_id)
     }
 
+    /// Get the encryption policy
+    pub fn encryption_policy(&self) -> EncryptionPolicy {
+        self.encryption_policy
+    }
+
+    /// Check the encryption policy before downgrading a message to plaintext
+    async fn check_plaintext_allowed(&self, to: &PeerId, reason: &str) -> Result<()> {
+        let is_lan_peer = self.network.read().await.is_lan_peer(to);
+        if self.encryption_policy.allows_plaintext(is_lan_peer) {
+            return Ok(());
+        }
+
+        Err(MePassaError::EncryptionRequired(format!(
+            "refusing to send plaintext to {} ({:?}): {}",
+            to, self.encryption_policy, reason
+        )))
+    }
+
+    fn text_payload(content: &str) -> Payload {
+        Payload::Text(TextMessage {
+            content: content.to_string(),
+            reply_to_id: String::new(),
+            metadata: std::collections::HashMap::new(),
+        })
+    }
+
     fn encrypt_message_for_peer(
         &self,
         to: &PeerId,
         plaintext: &[u8],
     ) -> Result<Option<ProtoEncryptedMessage>> {
-        let contact = match self.database.get_contact_by_peer_id(&to.to_string()) {
-            Ok(contact) => contact,
-            Err(_) => return Ok(None),
-        };
-
-        let bundle_json = match contact.prekey_bundle_json {
-            Some(value) => value,
-            None => return Ok(None),
-        };
-
-        let bundle: crate::identity::PreKeyBundle = serde_json::from_str(&bundle_json)
-            .map_err(|e| MePassaError::Crypto(format!("Invalid prekey bundle: {}", e)))?;
-
-        let peer_id = to.to_string();
-        let has_session = self.session_manager.has_session(&peer_id)?;
-
-        let (encrypted, ephemeral_public, signed_prekey_id, one_time_prekey_id) = if has_session {
-            let encrypted = self.session_manager.encrypt_for(&peer_id, plaintext)?;
-            (encrypted, Vec::new(), 0, 0)
-        } else {
-            let (shared_secret, ephemeral_public) = crate::crypto::signal::X3DH::initiate(&bundle)?;
-            self.session_manager.create_session(peer_id.clone(), shared_secret)?;
-            let encrypted = self.session_manager.encrypt_for(&peer_id, plaintext)?;
-            let one_time_prekey_id = bundle.one_time_prekey.as_ref().map(|pk| pk.id).unwrap_or(0);
-            (encrypted, ephemeral_public.to_vec(), bundle.signed_prekey_id, one_time_prekey_id)
-        };
-
-        Ok(Some(ProtoEncryptedMessage {
-            ciphertext: encrypted.ciphertext,
-            nonce: encrypted.nonce.to_vec(),
-            ephemeral_public,
-            signed_prekey_id,
-            one_time_prekey_id,
-        }))
+        encrypt_for_peer(&self.database, &self.session_manager, &to.to_string(), plaintext)
     }
 
     fn encrypt_for_storage(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
@@ -374,30 +543,841 @@ impl Client {
         Ok(text)
     }
 
-    async fn ensure_peer_connected(&self, peer_id: PeerId) {
+    /// Dial the peer if needed, resolving its addresses through the DHT
+    ///
+    /// Returns false when the peer is neither connected nor being dialed.
+    async fn ensure_peer_connected(&self, peer_id: PeerId) -> bool {
         let rx = {
             let mut network = self.network.write().await;
             if network.is_connected(&peer_id) {
                 None
             } else {
-                Some(network.resolve_peer_address(peer_id))
+                Some(network.resolve_peer_addresses(peer_id))
             }
         };
 
-        let Some(rx) = rx else { return };
+        let Some(rx) = rx else { return true };
+
+        let addrs = timeout(Duration::from_secs(5), rx)
+            .await
+            .ok()
+            .and_then(|resolved| resolved.ok())
+            .unwrap_or_default();
+        if addrs.is_empty() {
+            return self.network.read().await.is_connected(&peer_id);
+        }
+
+        let mut network = self.network.write().await;
+        if !network.is_connected(&peer_id) {
+            for addr in &addrs {
+                network.add_peer_to_dht(peer_id, addr.clone());
+            }
+            return network.dial_addresses(peer_id, addrs).is_ok();
+        }
+        true
+    }
+
+    // ═══════════════════════════════════════════════════════════════════════════
+    // Linked devices
+    // ═══════════════════════════════════════════════════════════════════════════
+
+    /// Peer ID of our account: the primary device's, which is ours unless
+    /// this device was linked to another one
+    pub fn account_peer_id(&self) -> PeerId {
+        account_of(&self.database, &self.peer_id.to_string())
+            .parse()
+            .unwrap_or(self.peer_id)
+    }
+
+    /// Devices linked to `account` (not including the account itself)
+    pub fn linked_devices(&self, account: &PeerId) -> Result<Vec<StoredDevice>> {
+        Ok(self.database.list_devices(&account.to_string())?)
+    }
+
+    fn is_own_device(&self, peer_id: &str) -> bool {
+        is_own_device(&self.database, &self.peer_id.to_string(), peer_id)
+    }
+
+    /// Our certificate, if this is a linked (secondary) device
+    fn own_certificate(&self) -> Result<Option<DeviceCertificate>> {
+        match self.database.get_device(&self.peer_id.to_string())? {
+            Some(device) => Ok(Some(DeviceCertificate::from_bytes(&device.certificate)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Devices a direct message to `to` goes to: the recipient and its
+    /// devices, then the devices of our own account (except this one)
+    fn fan_out_devices(&self, to: &PeerId, account: &PeerId) -> Result<Vec<PeerId>> {
+        let mut devices = Vec::new();
+        for owner in [to, account] {
+            devices.push(*owner);
+            for device in self.database.list_devices(&owner.to_string())? {
+                match device.device_peer_id.parse() {
+                    Ok(peer_id) => devices.push(peer_id),
+                    Err(e) => tracing::warn!("⚠️ Skipping stored device {}: {}", device.device_peer_id, e),
+                }
+            }
+        }
+
+        let mut seen = HashSet::new();
+        devices.retain(|device| *device != self.peer_id && seen.insert(*device));
+        Ok(devices)
+    }
 
-        let resolved = match timeout(Duration::from_secs(5), rx).await {
-            Ok(Ok(Some(addr))) => Some(addr),
-            _ => None,
+    /// Fetch the devices of `account` from the device directory
+    ///
+    /// Returns how many devices the directory lists.
+    pub async fn refresh_devices(&self, account: &PeerId) -> Result<usize> {
+        let Some(directory) = &self.device_directory else {
+            return Ok(0);
         };
+        let devices = directory.fetch_devices(account).await?;
+        self.devices_refreshed_at
+            .lock()
+            .expect("device refresh lock poisoned")
+            .insert(*account, std::time::Instant::now());
+
+        let mut count = 0;
+        for device in devices {
+            // Only linking (the device list from our primary) says which
+            // account this device belongs to
+            if device.certificate.account_peer_id != *account
+                || device.certificate.device_peer_id == self.peer_id
+                || device.verify().is_err()
+                || !device.save(&self.database)?
+            {
+                tracing::warn!(
+                    "⚠️ Ignoring device {} listed for {}",
+                    device.certificate.device_peer_id,
+                    account
+                );
+                continue;
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
 
-        if let Some(addr) = resolved {
+    async fn refresh_devices_if_stale(&self, account: &PeerId) {
+        if self.device_directory.is_none() {
+            return;
+        }
+        let fresh = self
+            .devices_refreshed_at
+            .lock()
+            .expect("device refresh lock poisoned")
+            .get(account)
+            .is_some_and(|at| at.elapsed() < DEVICE_REFRESH_INTERVAL);
+        if fresh {
+            return;
+        }
+        if let Err(e) = self.refresh_devices(account).await {
+            tracing::warn!("⚠️ Failed to fetch the devices of {}: {}", account, e);
+        }
+    }
+
+    /// Start linking this device to an account
+    ///
+    /// Returns the payload to show as a QR code; the account's primary device
+    /// scans it with `link_device` within `LINK_REQUEST_TTL_SECS`.
+    pub async fn device_link_request(&self, device_name: &str) -> Result<String> {
+        // Every device and contact of the account gets this bundle, so it
+        // carries no one-time prekey
+        let addresses = self.listening_addresses().await;
+        let now = chrono::Utc::now().timestamp();
+        let request = {
+            let mut identity = self.identity.write().await;
+            identity.init_prekey_pool(100);
+            let prekey_bundle = identity
+                .prekey_pool_mut()
+                .ok_or_else(|| MePassaError::Identity("Prekey pool not initialized".to_string()))?
+                .export_bundle();
+            LinkRequest::new(
+                identity.keypair(),
+                self.peer_id,
+                device_name,
+                prekey_bundle,
+                addresses,
+                now,
+            )
+        };
+        // Reject a bad name here rather than on the primary
+        let payload = request.to_payload();
+        LinkRequest::from_payload(&payload, now)?;
+
+        let handler = self.network.read().await.message_handler().ok_or_else(|| {
+            MePassaError::Network("Message handler not initialized".to_string())
+        })?;
+        handler.await_device_link(now + LINK_REQUEST_TTL_SECS);
+        Ok(payload)
+    }
+
+    /// Link the device that showed `payload` to our account
+    ///
+    /// Only the primary device can link. The new device gets the account's
+    /// device list (and so learns it was linked), as do the existing ones.
+    /// Returns the new device's Peer ID.
+    pub async fn link_device(&self, payload: &str) -> Result<PeerId> {
+        if self.own_certificate()?.is_some() {
+            return Err(MePassaError::Identity(
+                "Only the primary device can link devices".to_string(),
+            ));
+        }
+
+        let now = chrono::Utc::now().timestamp();
+        let request = LinkRequest::from_payload(payload, now)?;
+        let device_peer_id = request.peer_id()?;
+        let certificate = {
+            let identity = self.identity.read().await;
+            // The request may come from a clock slightly ahead of ours
+            DeviceCertificate::issue(
+                identity.keypair(),
+                self.peer_id,
+                &request,
+                now.max(request.created_at),
+            )?
+        };
+        let device = LinkedDevice {
+            certificate,
+            prekey_bundle: request.prekey_bundle.clone(),
+        };
+        if !device.save(&self.database)? {
+            return Err(MePassaError::Identity(format!(
+                "Device {} is linked to another account",
+                device_peer_id
+            )));
+        }
+
+        let addrs = request.multiaddrs();
+        if !addrs.is_empty() {
             let mut network = self.network.write().await;
-            if !network.is_connected(&peer_id) {
-                network.add_peer_to_dht(peer_id, addr.clone());
-                let _ = network.dial(peer_id, addr);
+            for addr in &addrs {
+                network.add_peer_to_dht(device_peer_id, addr.clone());
+            }
+            if let Err(e) = network.dial_addresses(device_peer_id, addrs) {
+                tracing::warn!("⚠️ Failed to dial new device {}: {}", device_peer_id, e);
+            }
+        }
+
+        self.send_device_list().await?;
+
+        if let Some(directory) = &self.device_directory {
+            if let Err(e) = directory.publish_device(&device).await {
+                tracing::warn!("⚠️ Failed to publish device {}: {}", device_peer_id, e);
+            }
+        }
+
+        tracing::info!("🔗 Linked device {} ({})", device_peer_id, request.device_name);
+        self.emit_event(ClientEvent::DeviceLinked {
+            account_peer_id: self.peer_id,
+            device_peer_id,
+        })
+        .await;
+        Ok(device_peer_id)
+    }
+
+    /// Send our account's device list to each of its devices
+    async fn send_device_list(&self) -> Result<()> {
+        let account = self.peer_id.to_string();
+        let mut devices = Vec::new();
+        for stored in self.database.list_devices(&account)? {
+            if let Some(device) = LinkedDevice::from_stored(&stored)? {
+                devices.push(device);
+            }
+        }
+        let list = pb::DeviceList {
+            account_peer_id: account,
+            devices: devices.iter().map(LinkedDevice::to_proto).collect(),
+        }
+        .encode_to_vec();
+
+        for device in devices {
+            let to = device.certificate.device_peer_id;
+            let Some(encrypted) = self.encrypt_message_for_peer(&to, &list)? else {
+                continue;
+            };
+            let message_id = uuid::Uuid::new_v4().to_string();
+            let message = Message {
+                id: message_id.clone(),
+                sender_peer_id: self.peer_id.to_string(),
+                recipient_peer_id: to.to_string(),
+                timestamp: chrono::Utc::now().timestamp_millis(),
+                r#type: MessageType::DeviceList as i32,
+                payload: Some(Payload::Encrypted(encrypted)),
+            };
+            let queued = self.encrypt_for_storage(&message.encode_to_vec())?;
+            self.database
+                .enqueue_outbox(&message_id, &to.to_string(), &queued)?;
+            if let Some(entry) = self.database.get_outbox_entry(&message_id, &to.to_string())? {
+                self.attempt_delivery(entry).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // ═══════════════════════════════════════════════════════════════════════════
+    // Device sync
+    // ═══════════════════════════════════════════════════════════════════════════
+
+    /// Ask our other devices for the changes this one missed (while offline,
+    /// or ones that never arrived)
+    pub fn request_device_sync(&self) -> Result<()> {
+        let request = sync_protocol::sync_request(&self.database, false)?;
+        self.queue_for_own_devices(MessageType::SyncRequest, &request.encode_to_vec())
+    }
+
+    /// Make a change to replicated state and push it to our other devices
+    fn record_change(&self, change: Change) -> Result<()> {
+        let op = crdt::record_change(&self.database, &self.peer_id.to_string(), change)?;
+        let batch = sync_protocol::ops_batch(&[op]);
+        self.queue_for_own_devices(MessageType::SyncBatch, &batch.encode_to_vec())
+    }
+
+    /// Queue a message for each of our other devices; `run_outbox` delivers them
+    fn queue_for_own_devices(&self, message_type: MessageType, plaintext: &[u8]) -> Result<()> {
+        let account = self.account_peer_id();
+        for device in self.fan_out_devices(&account, &account)? {
+            let queued = queue_encrypted(
+                &self.database,
+                &self.session_manager,
+                &self.storage_key,
+                &self.peer_id.to_string(),
+                &device.to_string(),
+                message_type,
+                plaintext,
+            )?;
+            if !queued {
+                tracing::warn!("⚠️ Not syncing device {}: no E2E session", device);
+            }
+        }
+        Ok(())
+    }
+
+    // ═══════════════════════════════════════════════════════════════════════════
+    // Backup
+    // ═══════════════════════════════════════════════════════════════════════════
+
+    /// Export our identity, prekeys and contacts sealed with `passphrase`
+    ///
+    /// With `include_history` the one-to-one text messages go along too.
+    /// Restore with `ClientBuilder::restore_from_backup`, which keeps our
+    /// peer id. Sessions aren't included: contacts start new ones.
+    pub async fn export_backup(&self, passphrase: &str, include_history: bool) -> Result<Vec<u8>> {
+        let (keypair, prekey_pool) = {
+            let identity = self.identity.read().await;
+            (
+                identity.keypair().to_bytes(),
+                identity.prekey_pool().map(|pool| pool.export_state()),
+            )
+        };
+
+        let local_peer_id = self.peer_id.to_string();
+        let contacts = self
+            .database
+            .list_contacts()?
+            .into_iter()
+            .filter(|contact| contact.peer_id != local_peer_id)
+            .map(|contact| BackupContact {
+                peer_id: contact.peer_id,
+                username: contact.username,
+                display_name: contact.display_name,
+                public_key: contact.public_key,
+                prekey_bundle_json: contact.prekey_bundle_json,
+            })
+            .collect();
+
+        let mut messages = Vec::new();
+        if include_history {
+            for message in self.database.recent_messages(u32::MAX as usize)?.into_iter().rev() {
+                let content = match (&message.content_plaintext, &message.content_encrypted) {
+                    (Some(plaintext), _) => plaintext.clone(),
+                    (None, Some(encrypted)) => match self.decrypt_for_storage(encrypted) {
+                        Ok(content) => content,
+                        Err(e) => {
+                            tracing::warn!("⚠️ Not backing up message {}: {}", message.message_id, e);
+                            continue;
+                        }
+                    },
+                    (None, None) => continue,
+                };
+                messages.push(BackupMessage {
+                    message_id: message.message_id,
+                    conversation_id: message.conversation_id,
+                    sender_peer_id: message.sender_peer_id,
+                    recipient_peer_id: message.recipient_peer_id,
+                    message_type: message.message_type,
+                    content,
+                    created_at: message.created_at,
+                    status: message.status.as_str().to_string(),
+                    parent_message_id: message.parent_message_id,
+                });
+            }
+        }
+
+        let contents = BackupContents {
+            keypair,
+            prekey_pool,
+            contacts,
+            messages,
+            created_at: chrono::Utc::now().timestamp(),
+        };
+        // Argon2 takes a while on purpose; keep it off the async workers
+        let passphrase = passphrase.to_string();
+        tokio::task::spawn_blocking(move || contents.seal(&passphrase))
+            .await
+            .map_err(|e| MePassaError::Other(format!("Backup task failed: {}", e)))?
+    }
+
+    // ═══════════════════════════════════════════════════════════════════════════
+    // Prekey maintenance
+    // ═══════════════════════════════════════════════════════════════════════════
+
+    /// Rotate the signed prekey if the policy says it is due, publishing the
+    /// new bundle to the prekey directory; returns whether it was rotated
+    ///
+    /// A linked device also republishes its device entry with the new
+    /// bundle. The primary sends its devices the device list again, with the
+    /// bundles they last published.
+    pub async fn rotate_signed_prekey_if_due(&self) -> Result<bool> {
+        let handler =
+            self.network.read().await.message_handler().ok_or_else(|| {
+                MePassaError::Network("Message handler not initialized".to_string())
+            })?;
+        if !handler.rotate_signed_prekey_if_due().await? {
+            return Ok(false);
+        }
+
+        if let Err(e) = self.republish_devices().await {
+            tracing::warn!("⚠️ Failed to republish devices after rotation: {}", e);
+        }
+        Ok(true)
+    }
+
+    /// Bring the device directory and our devices up to date with the
+    /// current bundles
+    async fn republish_devices(&self) -> Result<()> {
+        let Some(certificate) = self.own_certificate()? else {
+            // Linked devices republish their own entries when they rotate
+            if let Some(directory) = &self.device_directory {
+                for device in directory.fetch_devices(&self.peer_id).await? {
+                    let device_peer_id = device.certificate.device_peer_id.to_string();
+                    if self.database.get_device(&device_peer_id)?.is_some()
+                        && device.certificate.account_peer_id == self.peer_id
+                        && device.verify().is_ok()
+                    {
+                        device.save(&self.database)?;
+                    }
+                }
+            }
+            return self.send_device_list().await;
+        };
+
+        let prekey_bundle = self
+            .identity
+            .read()
+            .await
+            .prekey_pool()
+            .ok_or_else(|| MePassaError::Identity("Prekey pool not initialized".to_string()))?
+            .export_bundle();
+        let device = LinkedDevice {
+            certificate,
+            prekey_bundle,
+        };
+        device.save(&self.database)?;
+        if let Some(directory) = &self.device_directory {
+            directory.publish_device(&device).await?;
+        }
+        Ok(())
+    }
+
+    /// Upload new one-time prekeys if the prekey directory is running out;
+    /// returns how many were uploaded
+    pub async fn replenish_published_prekeys(&self) -> Result<usize> {
+        let handler =
+            self.network.read().await.message_handler().ok_or_else(|| {
+                MePassaError::Network("Message handler not initialized".to_string())
+            })?;
+        handler.replenish_published_prekeys().await
+    }
+
+    /// Rotate the signed prekey and top up the directory's one-time prekeys
+    /// every `PREKEY_MAINTENANCE_INTERVAL` forever
+    ///
+    /// Runs on the client's local task set next to the outbox.
+    pub async fn run_prekey_maintenance(&self) {
+        loop {
+            if let Err(e) = self.rotate_signed_prekey_if_due().await {
+                tracing::warn!("⚠️ Signed prekey rotation failed: {}", e);
+            }
+            if let Err(e) = self.replenish_published_prekeys().await {
+                tracing::warn!("⚠️ One-time prekey upload failed: {}", e);
+            }
+            tokio::time::sleep(PREKEY_MAINTENANCE_INTERVAL).await;
+        }
+    }
+
+    // ═══════════════════════════════════════════════════════════════════════════
+    // Outbox (retried delivery)
+    // ═══════════════════════════════════════════════════════════════════════════
+
+    /// Drive the outbox forever: retry due messages every `OUTBOX_POLL_INTERVAL`
+    ///
+    /// Like the network loop, this runs on the client's local task set.
+    /// Messages queued before a restart are picked up on the first pass.
+    pub async fn run_outbox(&self) {
+        loop {
+            if let Err(e) = self.process_outbox().await {
+                tracing::warn!("⚠️ Outbox pass failed: {}", e);
+            }
+            tokio::time::sleep(OUTBOX_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Attempt every queued message that is due; returns how many were attempted
+    pub async fn process_outbox(&self) -> Result<usize> {
+        let due = self
+            .database
+            .due_outbox_entries(chrono::Utc::now().timestamp_millis())?;
+
+        let mut attempted = 0;
+        for entry in due {
+            if self.attempt_delivery(entry).await? {
+                attempted += 1;
+            }
+        }
+        Ok(attempted)
+    }
+
+    /// One delivery attempt for a queued message (false if another attempt is running)
+    async fn attempt_delivery(&self, entry: OutboxEntry) -> Result<bool> {
+        let key = (entry.message_id.clone(), entry.recipient_peer_id.clone());
+        if !self
+            .outbox_in_flight
+            .lock()
+            .expect("outbox lock poisoned")
+            .insert(key.clone())
+        {
+            return Ok(false);
+        }
+
+        let result = self.deliver_outbox_entry(&entry).await;
+        self.outbox_in_flight
+            .lock()
+            .expect("outbox lock poisoned")
+            .remove(&key);
+        result.map(|_| true)
+    }
+
+    async fn deliver_outbox_entry(&self, entry: &OutboxEntry) -> Result<()> {
+        let to = match entry.recipient_peer_id.parse::<PeerId>() {
+            Ok(to) => to,
+            Err(e) => {
+                tracing::warn!("Dropping queued message {}: bad recipient: {}", entry.message_id, e);
+                return self
+                    .give_up_outbox_entry(entry, format!("bad recipient: {}", e))
+                    .await;
+            }
+        };
+
+        let payload = decrypt_for_storage(&self.storage_key, &entry.payload)?;
+        let mut message = Message::decode(payload.as_slice())
+            .map_err(|e| MePassaError::Protocol(format!("Corrupt outbox entry: {}", e)))?;
+
+        // A plaintext copy goes out encrypted as soon as we can
+        let mut entry = entry.clone();
+        if entry.plaintext {
+            if let Some(sealed) = self.seal_plaintext_entry(&to, &entry, &message)? {
+                (entry, message) = sealed;
+            }
+        }
+        let entry = &entry;
+
+        // Attempts used up without an ACK: hand off to the server or give up
+        if !self.retry_policy.should_retry(entry.attempts) {
+            return self.hand_off_outbox_entry(entry, to, &message).await;
+        }
+
+        // Plaintext is only sent while the policy still allows it (the peer
+        // may have left the LAN since it was queued)
+        let allowed = if entry.plaintext {
+            self.check_plaintext_allowed(&to, "plaintext queued for a LAN peer")
+                .await
+        } else {
+            Ok(())
+        };
+        let sent = match allowed {
+            Err(e) => Err(e),
+            Ok(()) => {
+                // Re-resolve through the DHT in case the peer moved, then send
+                if self.ensure_peer_connected(to).await {
+                    self.wait_for_connection(to, OUTBOX_CONNECT_TIMEOUT).await;
+                }
+                let mut network = self.network.write().await;
+                if network.is_connected(&to) {
+                    network.send_message(to, message).map(|_| true)
+                } else {
+                    Ok(false)
+                }
             }
+        };
+
+        let last_error = match sent {
+            Ok(true) => None,
+            Ok(false) => Some("peer unreachable".to_string()),
+            Err(e) => Some(e.to_string()),
+        };
+
+        // Wait for the ACK (or the next try) according to the backoff
+        let delay = self
+            .retry_policy
+            .next_delay(entry.attempts)
+            .unwrap_or(self.retry_policy.max_delay);
+        self.database.reschedule_outbox(
+            &entry.message_id,
+            &entry.recipient_peer_id,
+            entry.attempts + 1,
+            chrono::Utc::now().timestamp_millis() + delay.as_millis() as i64,
+            last_error.as_deref(),
+        )?;
+
+        match last_error {
+            None => self.mark_sent(entry).await?,
+            Some(e) => tracing::info!(
+                "📭 Message {} to {} not sent (attempt {}): {}",
+                entry.message_id,
+                to,
+                entry.attempts + 1,
+                e
+            ),
         }
+        Ok(())
+    }
+
+    /// Encrypt a queued plaintext copy once a session or prekey bundle exists
+    fn seal_plaintext_entry(
+        &self,
+        to: &PeerId,
+        entry: &OutboxEntry,
+        message: &Message,
+    ) -> Result<Option<(OutboxEntry, Message)>> {
+        let Some(Payload::Text(text)) = &message.payload else {
+            return Ok(None);
+        };
+        let Ok(Some(encrypted)) = self.encrypt_message_for_peer(to, text.content.as_bytes()) else {
+            return Ok(None);
+        };
+
+        let sealed = Message {
+            r#type: MessageType::Encrypted as i32,
+            payload: Some(Payload::Encrypted(encrypted)),
+            ..message.clone()
+        };
+        let queued = self.encrypt_for_storage(&sealed.encode_to_vec())?;
+        self.database
+            .replace_outbox_payload(&entry.message_id, &entry.recipient_peer_id, &queued)?;
+        let Some(entry) = self
+            .database
+            .get_outbox_entry(&entry.message_id, &entry.recipient_peer_id)?
+        else {
+            return Ok(None);
+        };
+
+        tracing::info!("🔐 Queued message {} to {} is now E2E encrypted", entry.message_id, to);
+        Ok(Some((entry, sealed)))
+    }
+
+    /// Direct delivery gave up: store on the server, or stop trying this device
+    ///
+    /// Plaintext copies are never handed to store-and-forward.
+    async fn hand_off_outbox_entry(&self, entry: &OutboxEntry, to: PeerId, message: &Message) -> Result<()> {
+        let reason = match &self.offline_store {
+            Some(store) if !entry.plaintext => match store.store(&to, message).await {
+                Ok(()) => {
+                    tracing::info!("📦 Message {} to {} handed to store-and-forward", entry.message_id, to);
+                    self.database
+                        .remove_outbox_entry(&entry.message_id, &entry.recipient_peer_id)?;
+                    return self.mark_sent(entry).await;
+                }
+                Err(e) => format!("store-and-forward failed: {}", e),
+            },
+            _ => entry
+                .last_error
+                .clone()
+                .unwrap_or_else(|| "no acknowledgment from peer".to_string()),
+        };
+
+        tracing::warn!("❌ Giving up on message {} to {}: {}", entry.message_id, to, reason);
+        self.give_up_outbox_entry(entry, reason).await
+    }
+
+    /// Stop trying one device; the message fails once none of the
+    /// recipient's devices is left to try and none has it
+    async fn give_up_outbox_entry(&self, entry: &OutboxEntry, reason: String) -> Result<()> {
+        self.database
+            .remove_outbox_entry(&entry.message_id, &entry.recipient_peer_id)?;
+        // Copies for our own devices and device lists have no status
+        if self.is_own_device(&entry.recipient_peer_id) {
+            return Ok(());
+        }
+        let Ok(stored) = self.database.get_message(&entry.message_id) else {
+            return Ok(());
+        };
+        if matches!(stored.status, MessageStatus::Delivered | MessageStatus::Read) {
+            return Ok(());
+        }
+        let remaining = self.database.outbox_recipients(&entry.message_id)?;
+        if remaining.iter().any(|peer| !self.is_own_device(peer)) {
+            return Ok(());
+        }
+
+        self.set_message_status(&entry.message_id, MessageStatus::Failed)?;
+        if let Some(to) = stored.recipient_peer_id.and_then(|to| to.parse().ok()) {
+            self.emit_event(ClientEvent::MessageFailed {
+                message_id: entry.message_id.clone(),
+                to,
+                reason,
+            })
+            .await;
+        }
+        Ok(())
+    }
+
+    /// Pending → Sent once a copy is on its way to the recipient (later
+    /// attempts of an already sent message change nothing)
+    async fn mark_sent(&self, entry: &OutboxEntry) -> Result<()> {
+        if self.is_own_device(&entry.recipient_peer_id) {
+            return Ok(());
+        }
+        let Ok(message) = self.database.get_message(&entry.message_id) else {
+            return Ok(());
+        };
+        if message.status != MessageStatus::Pending {
+            return Ok(());
+        }
+        let Some(to) = message.recipient_peer_id.and_then(|to| to.parse().ok()) else {
+            return Ok(());
+        };
+
+        let update = UpdateMessage {
+            sent_at: Some(chrono::Utc::now().timestamp()),
+            status: Some(MessageStatus::Sent),
+            ..Default::default()
+        };
+        self.database.update_message(&entry.message_id, &update)?;
+        self.emit_event(ClientEvent::MessageSent {
+            message_id: entry.message_id.clone(),
+            to,
+        })
+        .await;
+        Ok(())
+    }
+
+    fn set_message_status(&self, message_id: &str, status: MessageStatus) -> Result<()> {
+        let update = UpdateMessage {
+            status: Some(status),
+            ..Default::default()
+        };
+        self.database.update_message(message_id, &update)?;
+        Ok(())
+    }
+
+    /// Give a dial started by `ensure_peer_connected` a moment to complete
+    async fn wait_for_connection(&self, peer_id: PeerId, wait: Duration) {
+        let deadline = tokio::time::Instant::now() + wait;
+        while tokio::time::Instant::now() < deadline {
+            if self.network.read().await.is_connected(&peer_id) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    /// Fetch what the store-and-forward server kept for us, forever
+    ///
+    /// Fetches once on startup and again after each `notify_offline_messages`.
+    pub async fn run_offline_inbox(&self) {
+        loop {
+            match self.fetch_offline_messages().await {
+                Ok(0) => {}
+                Ok(count) => tracing::info!("📬 Processed {} stored messages", count),
+                Err(e) => tracing::warn!("⚠️ Fetching stored messages failed: {}", e),
+            }
+            self.offline_notify.notified().await;
+        }
+    }
+
+    /// The server has new messages for us (e.g. its `messages:<peer_id>`
+    /// notification was relayed by the push service)
+    pub fn notify_offline_messages(&self) {
+        self.offline_notify.notify_one();
+    }
+
+    /// Fetch stored messages, run them through the message handler and delete
+    /// them from the server; returns how many were processed
+    pub async fn fetch_offline_messages(&self) -> Result<usize> {
+        let Some(store) = self.offline_store.clone() else {
+            return Ok(0);
+        };
+        let handler = self
+            .network
+            .read()
+            .await
+            .message_handler()
+            .ok_or_else(|| MePassaError::Other("Message handler not configured".to_string()))?;
+
+        let mut total = 0;
+        loop {
+            let pending = store.fetch(&self.peer_id).await?;
+            if pending.is_empty() {
+                return Ok(total);
+            }
+
+            let mut processed = Vec::with_capacity(pending.len());
+            for offline in &pending {
+                match self.process_offline_message(&handler, offline).await {
+                    Ok(()) => processed.push(offline.message_id.clone()),
+                    // Kept on the server for the next fetch
+                    Err(e) => tracing::warn!("⚠️ Stored message {} not processed: {}", offline.message_id, e),
+                }
+            }
+
+            store.acknowledge(&processed).await?;
+            total += processed.len();
+            if processed.len() < pending.len() {
+                return Ok(total);
+            }
+        }
+    }
+
+    /// Handle one stored message; Err means "try again later"
+    async fn process_offline_message(&self, handler: &MessageHandler, offline: &OfflineMessage) -> Result<()> {
+        let message = match Message::decode(offline.payload.as_slice()) {
+            Ok(message) if message.sender_peer_id == offline.sender_peer_id => message,
+            Ok(_) => {
+                tracing::warn!("Dropping stored message {}: sender mismatch", offline.message_id);
+                return Ok(());
+            }
+            Err(e) => {
+                tracing::warn!("Dropping undecodable stored message {}: {}", offline.message_id, e);
+                return Ok(());
+            }
+        };
+        let Ok(from) = message.sender_peer_id.parse::<PeerId>() else {
+            tracing::warn!("Dropping stored message {}: invalid sender", offline.message_id);
+            return Ok(());
+        };
+
+        let ack = handler.handle_incoming_message(from, message).await?;
+        if ack.status != AckStatus::Received as i32 {
+            tracing::warn!(
+                "Stored message {} from {} rejected: {}",
+                offline.message_id,
+                from,
+                ack.error
+            );
+        }
+        Ok(())
     }
 
     // ═══════════════════════════════════════════════════════════════════════════
@@ -423,13 +1403,8 @@ impl Client {
         let message_id = uuid::Uuid::new_v4().to_string();
         let timestamp = chrono::Utc::now().timestamp_millis();
 
-        // Calculate media hash (salt with message_id to avoid collisions)
-        let mut media_hash = Self::compute_media_hash(&compressed_data, None);
-        if let Ok(Some(_existing)) = self.database.get_media_by_hash(&media_hash) {
-            media_hash = Self::compute_media_hash(&compressed_data, Some(&message_id));
-        }
-
-        let local_path = self.write_media_file(&media_hash, Some(&file_name), &compressed_data)?;
+        // Per-file key, keyed media hash and plaintext digest
+        let (media_key, media_hash, digest) = Self::new_media_key(&compressed_data);
         let media_type = MediaType::Image;
         let placeholder = Self::media_placeholder(&media_type, Some(&file_name), None);
 
@@ -443,19 +1418,13 @@ impl Client {
             width: 0,
             height: 0,
             duration_seconds: 0,
+            media_key: media_key.to_vec(),
+            digest: digest.to_vec(),
         };
-        let message_type = MessageType::MediaOffer;
-        let payload = Payload::MediaOffer(offer);
-
-        // Create protocol message
-        let proto_message = Message {
-            id: message_id.clone(),
-            sender_peer_id: self.local_peer_id().to_string(),
-            recipient_peer_id: to.to_string(),
-            timestamp,
-            r#type: message_type as i32,
-            payload: Some(payload),
-        };
+        // The offer (with the file key) only travels inside the E2E session
+        let proto_message = self.media_offer_message(to, &message_id, timestamp, &offer)?;
+        let local_path = self.write_media_file(&media_hash, Some(&file_name), &compressed_data)?;
+        self.save_media_key(&media_hash, &media_key, &digest)?;
 
         // Send via network
         {
@@ -517,17 +1486,11 @@ impl Client {
     ) -> Result<String> {
         self.ensure_peer_connected(to).await;
 
-        // Calculate media hash
         // Generate message ID
         let message_id = uuid::Uuid::new_v4().to_string();
         let timestamp = chrono::Utc::now().timestamp_millis();
 
-        let mut media_hash = Self::compute_media_hash(audio_data, None);
-        if let Ok(Some(_existing)) = self.database.get_media_by_hash(&media_hash) {
-            media_hash = Self::compute_media_hash(audio_data, Some(&message_id));
-        }
-
-        let local_path = self.write_media_file(&media_hash, Some(&file_name), audio_data)?;
+        let (media_key, media_hash, digest) = Self::new_media_key(audio_data);
         let media_type = MediaType::VoiceMessage;
         let placeholder = Self::media_placeholder(&media_type, Some(&file_name), Some(duration_seconds));
 
@@ -541,18 +1504,13 @@ impl Client {
             width: 0,
             height: 0,
             duration_seconds,
+            media_key: media_key.to_vec(),
+            digest: digest.to_vec(),
         };
-        let message_type = MessageType::MediaOffer;
-        let payload = Payload::MediaOffer(offer);
-
-        let proto_message = Message {
-            id: message_id.clone(),
-            sender_peer_id: self.local_peer_id().to_string(),
-            recipient_peer_id: to.to_string(),
-            timestamp,
-            r#type: message_type as i32,
-            payload: Some(payload),
-        };
+        // The offer (with the file key) only travels inside the E2E session
+        let proto_message = self.media_offer_message(to, &message_id, timestamp, &offer)?;
+        let local_path = self.write_media_file(&media_hash, Some(&file_name), audio_data)?;
+        self.save_media_key(&media_hash, &media_key, &digest)?;
 
         {
             let mut network = self.network.write().await;
@@ -613,17 +1571,11 @@ impl Client {
     ) -> Result<String> {
         self.ensure_peer_connected(to).await;
 
-        // Calculate media hash
         // Generate message ID
         let message_id = uuid::Uuid::new_v4().to_string();
         let timestamp = chrono::Utc::now().timestamp_millis();
 
-        let mut media_hash = Self::compute_media_hash(file_data, None);
-        if let Ok(Some(_existing)) = self.database.get_media_by_hash(&media_hash) {
-            media_hash = Self::compute_media_hash(file_data, Some(&message_id));
-        }
-
-        let local_path = self.write_media_file(&media_hash, Some(&file_name), file_data)?;
+        let (media_key, media_hash, digest) = Self::new_media_key(file_data);
         let media_type = MediaType::Document;
         let placeholder = Self::media_placeholder(&media_type, Some(&file_name), None);
 
@@ -637,18 +1589,13 @@ impl Client {
             width: 0,
             height: 0,
             duration_seconds: 0,
+            media_key: media_key.to_vec(),
+            digest: digest.to_vec(),
         };
-        let message_type = MessageType::MediaOffer;
-        let payload = Payload::MediaOffer(offer);
-
-        let proto_message = Message {
-            id: message_id.clone(),
-            sender_peer_id: self.local_peer_id().to_string(),
-            recipient_peer_id: to.to_string(),
-            timestamp,
-            r#type: message_type as i32,
-            payload: Some(payload),
-        };
+        // The offer (with the file key) only travels inside the E2E session
+        let proto_message = self.media_offer_message(to, &message_id, timestamp, &offer)?;
+        let local_path = self.write_media_file(&media_hash, Some(&file_name), file_data)?;
+        self.save_media_key(&media_hash, &media_key, &digest)?;
 
         {
             let mut network = self.network.write().await;
@@ -717,12 +1664,7 @@ impl Client {
         let message_id = uuid::Uuid::new_v4().to_string();
         let timestamp = chrono::Utc::now().timestamp_millis();
 
-        let mut media_hash = Self::compute_media_hash(video_data, None);
-        if let Ok(Some(_existing)) = self.database.get_media_by_hash(&media_hash) {
-            media_hash = Self::compute_media_hash(video_data, Some(&message_id));
-        }
-
-        let local_path = self.write_media_file(&media_hash, Some(&file_name), video_data)?;
+        let (media_key, media_hash, digest) = Self::new_media_key(video_data);
         let media_type = MediaType::Video;
         let placeholder = Self::media_placeholder(&media_type, Some(&file_name), Some(duration_seconds));
 
@@ -741,18 +1683,13 @@ impl Client {
             width: width.unwrap_or(0),
             height: height.unwrap_or(0),
             duration_seconds,
+            media_key: media_key.to_vec(),
+            digest: digest.to_vec(),
         };
-        let message_type = MessageType::MediaOffer;
-        let payload = Payload::MediaOffer(offer);
-
-        let proto_message = Message {
-            id: message_id.clone(),
-            sender_peer_id: self.local_peer_id().to_string(),
-            recipient_peer_id: to.to_string(),
-            timestamp,
-            r#type: message_type as i32,
-            payload: Some(payload),
-        };
+        // The offer (with the file key) only travels inside the E2E session
+        let proto_message = self.media_offer_message(to, &message_id, timestamp, &offer)?;
+        let local_path = self.write_media_file(&media_hash, Some(&file_name), video_data)?;
+        self.save_media_key(&media_hash, &media_key, &digest)?;
 
         {
             let mut network = self.network.write().await;
@@ -804,12 +1741,18 @@ impl Client {
     }
 
     /// Download media by hash
+    ///
+    /// Asks the sender for a sliding window of chunks starting at the last
+    /// persisted offset, requesting the next window once half of the current
+    /// one has arrived. A stalled transfer (e.g. after a disconnect) resumes
+    /// from the persisted offset instead of starting over. Progress is
+    /// reported through `ClientEvent::MediaProgress`.
     pub async fn download_media(&self, media_hash: &str) -> Result<Vec<u8>> {
         // Read from local storage if available
         if let Ok(Some(media)) = self.database.get_media_by_hash(media_hash) {
-            if let Some(local_path) = media.local_path {
-                if Path::new(&local_path).exists() {
-                    let data = std::fs::read(&local_path)?;
+            if let Some(ref local_path) = media.local_path {
+                if Path::new(local_path).exists() {
+                    let data = std::fs::read(local_path)?;
                     return Ok(data);
                 }
                 tracing::warn!("Media path missing on disk: {}", local_path);
@@ -832,68 +1775,64 @@ impl Client {
                 .parse()
                 .map_err(|_| MePassaError::Network("Invalid peer ID".to_string()))?;
 
-            let mut last_error: Option<MePassaError> = None;
-
-            for _ in 0..3 {
-                self.ensure_peer_connected(peer_id).await;
-
-                let request = MediaRequest {
-                    message_id: media.message_id.clone(),
-                    media_hash: media.media_hash.clone(),
-                    offset: 0,
-                    chunk_size: 64 * 1024,
-                };
-
-                let request_message = Message {
-                    id: uuid::Uuid::new_v4().to_string(),
-                    sender_peer_id: self.local_peer_id().to_string(),
-                    recipient_peer_id: peer_id.to_string(),
-                    timestamp: chrono::Utc::now().timestamp_millis(),
-                    r#type: MessageType::MediaRequest as i32,
-                    payload: Some(Payload::MediaRequest(request)),
-                };
-
-                {
-                    let mut network = self.network.write().await;
-                    network.send_message(peer_id, request_message)?;
-                }
-
-                // Poll for file to appear
-                let wait_result = timeout(Duration::from_secs(10), async {
-                    loop {
-                        if let Ok(Some(updated)) = self.database.get_media_by_hash(media_hash) {
-                            if let Some(path) = updated.local_path {
-                                if Path::new(&path).exists() {
-                                    return Ok::<String, MePassaError>(path);
-                                }
-                            }
+            let handler = self
+                .network
+                .read()
+                .await
+                .message_handler()
+                .ok_or_else(|| MePassaError::Network("No message handler configured".to_string()))?;
+            let mut progress = handler.watch_media_progress();
+
+            let total = media.file_size.unwrap_or(0).max(0) as u64;
+            let window_bytes = MEDIA_WINDOW as u64 * MEDIA_CHUNK_SIZE as u64;
+            let mut requested_until = 0;
+            let mut stalls = 0;
+
+            self.ensure_peer_connected(peer_id).await;
+            loop {
+                progress.borrow_and_update();
+                if let Some(updated) = self.database.get_media_by_hash(media_hash)? {
+                    if let Some(path) = updated.local_path {
+                        if Path::new(&path).exists() {
+                            return Ok(std::fs::read(&path)?);
                         }
-                        tokio::time::sleep(Duration::from_millis(200)).await;
                     }
-                })
-                .await;
+                }
 
-                match wait_result {
-                    Ok(Ok(path)) => {
-                        let data = std::fs::read(&path)?;
-                        return Ok(data);
-                    }
-                    Ok(Err(e)) => {
-                        last_error = Some(e);
+                let received = self.database.get_media_received_bytes(media_hash)?;
+                if requested_until < received {
+                    requested_until = received;
+                }
+                if requested_until < total && requested_until <= received + window_bytes / 2 {
+                    self.request_media_window(peer_id, &media, requested_until)
+                        .await?;
+                    requested_until = (requested_until + window_bytes).min(total);
+                }
+
+                match timeout(MEDIA_STALL_TIMEOUT, progress.changed()).await {
+                    Ok(Ok(())) => stalls = 0,
+                    Ok(Err(_)) => {
+                        return Err(MePassaError::Network("Message handler stopped".to_string()));
                     }
                     Err(_) => {
-                        last_error = Some(MePassaError::Network(
-                            "Timed out waiting for media".to_string(),
-                        ));
+                        stalls += 1;
+                        if stalls > MEDIA_MAX_STALLS {
+                            return Err(MePassaError::Network(
+                                "Timed out waiting for media".to_string(),
+                            ));
+                        }
+                        tracing::warn!(
+                            "Media {} stalled at {}/{} bytes, resuming",
+                            media_hash,
+                            received,
+                            total
+                        );
+                        self.ensure_peer_connected(peer_id).await;
+                        // Ask again from what is actually on disk
+                        requested_until = 0;
                     }
                 }
-
-                tokio::time::sleep(Duration::from_millis(300)).await;
             }
-
-            return Err(last_error.unwrap_or_else(|| {
-                MePassaError::Network("Timed out waiting for media".to_string())
-            }));
         }
 
         Err(MePassaError::NotFound(format!(
@@ -902,6 +1841,35 @@ impl Client {
         )))
     }
 
+    /// Ask the sender of a file for the window of chunks starting at `offset`
+    async fn request_media_window(
+        &self,
+        peer_id: PeerId,
+        media: &crate::storage::Media,
+        offset: u64,
+    ) -> Result<()> {
+        let request = MediaRequest {
+            message_id: media.message_id.clone(),
+            media_hash: media.media_hash.clone(),
+            offset: offset as i64,
+            chunk_size: MEDIA_CHUNK_SIZE as i32,
+            window: MEDIA_WINDOW,
+        };
+
+        let request_message = Message {
+            id: uuid::Uuid::new_v4().to_string(),
+            sender_peer_id: self.local_peer_id().to_string(),
+            recipient_peer_id: peer_id.to_string(),
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            r#type: MessageType::MediaRequest as i32,
+            payload: Some(Payload::MediaRequest(request)),
+        };
+
+        let mut network = self.network.write().await;
+        network.send_message(peer_id, request_message)?;
+        Ok(())
+    }
+
     /// Get media for a conversation
     pub fn get_conversation_media(
         &self,
@@ -943,11 +1911,11 @@ impl Client {
     // Message Actions (Delete & Forward)
     // ═════════════════════════════════════════════════════════════════════
 
-    /// Delete message (soft delete - marks as deleted locally)
+    /// Delete message (soft delete - marks as deleted on our devices)
     pub fn delete_message(&self, message_id: &str) -> Result<()> {
-        self.database
-            .delete_message(message_id)
-            .map_err(|e| MePassaError::Storage(e.to_string()))
+        self.record_change(Change::MessageDeleted {
+            message_id: message_id.to_string(),
+        })
     }
 
     /// Forward message to another peer/group
@@ -1004,21 +1972,18 @@ impl Client {
     // Message Reactions (FASE 16 - TRACK 8)
     // ═════════════════════════════════════════════════════════════════════
 
-    /// Add a reaction to a message
+    /// Add a reaction to a message (our account's, on all our devices)
     pub fn add_reaction(&self, message_id: &str, emoji: &str) -> Result<()> {
-        let reaction_id = uuid::Uuid::new_v4().to_string();
-        let peer_id = self.local_peer_id().to_string();
+        self.database
+            .get_message(message_id)
+            .map_err(|e| MePassaError::Storage(e.to_string()))?;
 
-        let new_reaction = crate::storage::NewReaction {
-            reaction_id,
+        self.record_change(Change::Reaction {
             message_id: message_id.to_string(),
-            peer_id,
+            peer_id: self.account_peer_id().to_string(),
             emoji: emoji.to_string(),
-        };
-
-        self.database
-            .add_reaction(&new_reaction)
-            .map_err(|e| MePassaError::Storage(e.to_string()))?;
+            present: true,
+        })?;
 
         // TODO: Broadcast reaction to other peers via P2P
 
@@ -1027,11 +1992,12 @@ impl Client {
 
     /// Remove a reaction from a message
     pub fn remove_reaction(&self, message_id: &str, emoji: &str) -> Result<()> {
-        let peer_id = self.local_peer_id().to_string();
-
-        self.database
-            .remove_reaction(message_id, &peer_id, emoji)
-            .map_err(|e| MePassaError::Storage(e.to_string()))?;
+        self.record_change(Change::Reaction {
+            message_id: message_id.to_string(),
+            peer_id: self.account_peer_id().to_string(),
+            emoji: emoji.to_string(),
+            present: false,
+        })?;
 
         // TODO: Broadcast reaction removal to other peers via P2P
 
@@ -1072,10 +2038,26 @@ impl Client {
 
     /// Mark conversation as read
     pub fn mark_conversation_read(&self, peer_id: &str) -> Result<()> {
-        let conversation_id = format!("1:1:{}", peer_id);
-        self.database
-            .mark_conversation_read(&conversation_id)
-            .map_err(|e| MePassaError::Storage(e.to_string()))
+        self.record_change(Change::ConversationRead {
+            conversation_id: format!("1:1:{}", peer_id),
+            read_at: chrono::Utc::now().timestamp(),
+        })
+    }
+
+    /// Mute or unmute the conversation with a peer
+    pub fn set_conversation_muted(&self, peer_id: &str, muted: bool) -> Result<()> {
+        self.record_change(Change::ConversationMuted {
+            conversation_id: format!("1:1:{}", peer_id),
+            muted,
+        })
+    }
+
+    /// Archive or unarchive the conversation with a peer
+    pub fn set_conversation_archived(&self, peer_id: &str, archived: bool) -> Result<()> {
+        self.record_change(Change::ConversationArchived {
+            conversation_id: format!("1:1:{}", peer_id),
+            archived,
+        })
     }
 
     /// Get connected peers count
@@ -1084,6 +2066,18 @@ impl Client {
         network.connected_peers()
     }
 
+    /// Get connection state, type (direct, hole punched or relayed) and ping RTT for a peer
+    pub async fn connection_quality(&self, peer_id: &PeerId) -> ConnectionQuality {
+        let network = self.network.read().await;
+        network.connection_quality(peer_id)
+    }
+
+    /// Whether we are reachable from the internet, as confirmed by AutoNAT
+    pub async fn reachability(&self) -> Reachability {
+        let network = self.network.read().await;
+        network.reachability().clone()
+    }
+
     /// Get current listening addresses
     pub async fn listening_addresses(&self) -> Vec<String> {
         let network = self.network.read().await;
@@ -1232,30 +2226,67 @@ impl Client {
     ) -> Result<crate::ffi::FfiGroup> {
         use crate::ffi::FfiGroup;
 
-        let (group, _topic_hash) = self
+        let (group, topic_hash) = self
             .group_manager
             .create_group(name, description)
             .await
             .map_err(|e| MePassaError::Other(format!("Failed to create group: {}", e)))?;
 
+        self.network.write().await.subscribe_topic(&topic_hash)?;
+
         Ok(FfiGroup::from_group(&group, &self.local_peer_id().to_string()))
     }
 
     /// Join an existing group
     pub async fn join_group(&self, group_id: String, group_name: String) -> Result<()> {
-        let _topic_hash = self.group_manager
+        let topic_hash = self.group_manager
             .join_group(group_id, group_name)
             .await
             .map_err(|e| MePassaError::Other(format!("Failed to join group: {}", e)))?;
+        self.network.write().await.subscribe_topic(&topic_hash)?;
         Ok(())
     }
 
     /// Leave a group
     pub async fn leave_group(&self, group_id: String) -> Result<()> {
-        self.group_manager
+        let topic_hash = self.group_manager
             .leave_group(&group_id)
             .await
-            .map_err(|e| MePassaError::Other(format!("Failed to leave group: {}", e)))
+            .map_err(|e| MePassaError::Other(format!("Failed to leave group: {}", e)))?;
+        if let Some(topic_hash) = topic_hash {
+            self.network.write().await.unsubscribe_topic(&topic_hash)?;
+        }
+        Ok(())
+    }
+
+    /// Send a text message to a group (encrypted with our sender key)
+    pub async fn send_group_message(&self, group_id: String, content: String) -> Result<String> {
+        let (topic_hash, message) = self
+            .group_manager
+            .create_text_message(&group_id, &content)
+            .await?;
+
+        {
+            let mut network = self.network.write().await;
+            network.publish(&topic_hash, message.to_bytes()?)?;
+        }
+
+        self.group_manager
+            .store_message(&message, content.as_bytes(), MessageStatus::Sent)?;
+
+        Ok(message.message_id)
+    }
+
+    /// Rotate our sender key for a group and distribute it to every member
+    pub async fn rekey_group(&self, group_id: String) -> Result<()> {
+        self.group_manager.rotate_sender_key(&group_id).await
+    }
+
+    /// Take the group event receiver (can only be called once)
+    pub async fn take_group_event_receiver(
+        &self,
+    ) -> Option<tokio::sync::mpsc::UnboundedReceiver<crate::group::GroupEvent>> {
+        self.group_manager.take_event_receiver().await
     }
 
     /// Add a member to a group (admin only)
@@ -1263,7 +2294,10 @@ impl Client {
         self.group_manager
             .add_member(&group_id, &peer_id)
             .await
-            .map_err(|e| MePassaError::Other(format!("Failed to add member: {}", e)))
+            .map_err(|e| MePassaError::Other(format!("Failed to add member: {}", e)))?;
+
+        self.announce_admin_action(&group_id, AdminAction::AddMember { peer_id })
+            .await
     }
 
     /// Remove a member from a group (admin only)
@@ -1271,7 +2305,32 @@ impl Client {
         self.group_manager
             .remove_member(&group_id, &peer_id)
             .await
-            .map_err(|e| MePassaError::Other(format!("Failed to remove member: {}", e)))
+            .map_err(|e| MePassaError::Other(format!("Failed to remove member: {}", e)))?;
+
+        self.announce_admin_action(&group_id, AdminAction::RemoveMember { peer_id })
+            .await
+    }
+
+    /// Publish an admin action on the group topic
+    ///
+    /// Best effort: the local change is already applied, and members that
+    /// miss the announcement still receive the rotated sender keys.
+    async fn announce_admin_action(&self, group_id: &str, action: AdminAction) -> Result<()> {
+        let (topic_hash, message) = self
+            .group_manager
+            .create_admin_message(group_id, action)
+            .await?;
+
+        let published = self
+            .network
+            .write()
+            .await
+            .publish(&topic_hash, message.to_bytes()?);
+        if let Err(e) = published {
+            tracing::warn!("⚠️  Could not announce admin action in group {}: {}", group_id, e);
+        }
+
+        Ok(())
     }
 
     /// Get all groups
@@ -1313,7 +2372,9 @@ impl Client {
 
 #[cfg(test)]
 mod tests {
-    use crate::api::ClientBuilder;
+    use crate::api::{ClientBuilder, EncryptionPolicy};
+    use crate::utils::error::MePassaError;
+    use libp2p::PeerId;
     use tempfile::TempDir;
 
     #[tokio::test]
@@ -1344,4 +2405,41 @@ mod tests {
         let conversations = client.list_conversations().unwrap();
         assert_eq!(conversations.len(), 0);
     }
+
+    #[tokio::test]
+    async fn test_send_without_bundle_fails_under_strict_policy() {
+        let temp_dir = TempDir::new().unwrap();
+
+        let client = ClientBuilder::new()
+            .data_dir(temp_dir.path().to_path_buf())
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(client.encryption_policy(), EncryptionPolicy::RequireE2E);
+
+        let result = client
+            .send_text_message(PeerId::random(), "secret".to_string())
+            .await;
+
+        assert!(matches!(result, Err(MePassaError::EncryptionRequired(_))));
+        assert!(client.list_conversations().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_lan_only_policy_rejects_non_lan_peer() {
+        let temp_dir = TempDir::new().unwrap();
+
+        let client = ClientBuilder::new()
+            .data_dir(temp_dir.path().to_path_buf())
+            .encryption_policy(EncryptionPolicy::AllowPlaintextOnLan)
+            .build()
+            .await
+            .unwrap();
+
+        let result = client
+            .send_text_message(PeerId::random(), "secret".to_string())
+            .await;
+
+        assert!(matches!(result, Err(MePassaError::EncryptionRequired(_))));
+    }
 }
@@ -1,9 +1,20 @@
 //! Event System
 //!
-//! Events emitted by the MePassa client.
+//! Events emitted by the MePassa client. Message handler, group manager,
+//! network and call events are merged into a single `EventBus`, consumed
+//! through callbacks (`EventCallback`, the FFI `MePassaEventListener`) or
+//! as an async `EventStream`.
 
+use futures::Stream;
 use libp2p::PeerId;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
 
+use crate::network::Reachability;
 use crate::protocol::Message;
 
 /// Events that can be emitted by the MePassa client
@@ -16,18 +27,38 @@ pub enum ClientEvent {
         message: Message,
     },
 
+    /// A message was queued for delivery (status `Pending`)
+    MessageQueued {
+        message_id: String,
+        to: PeerId,
+    },
+
     /// A message was successfully sent
     MessageSent {
         message_id: String,
         to: PeerId,
     },
 
+    /// A message was sent without end-to-end encryption (allowed by policy)
+    PlaintextFallback {
+        message_id: String,
+        to: PeerId,
+        reason: String,
+    },
+
     /// A message delivery confirmation (ACK) was received
     MessageDelivered {
         message_id: String,
         to: PeerId,
     },
 
+    /// A message could not be delivered and was given up on
+    MessageFailed {
+        message_id: String,
+        to: PeerId,
+        reason: String,
+    },
+
     /// A message was read by the recipient
     MessageRead {
         message_id: String,
@@ -45,6 +76,14 @@ pub enum ClientEvent {
         peer_id: PeerId,
     },
 
+    /// A media download made progress
+    MediaProgress {
+        message_id: String,
+        media_hash: String,
+        bytes_received: u64,
+        total_bytes: u64,
+    },
+
     /// Connected to a peer
     PeerConnected {
         peer_id: PeerId,
@@ -67,6 +106,93 @@ pub enum ClientEvent {
     /// Connection to network lost
     NetworkOffline,
 
+    /// AutoNAT confirmed whether we are reachable from the internet
+    ReachabilityChanged {
+        reachability: Reachability,
+    },
+
+    /// We created a group
+    GroupCreated {
+        group_id: String,
+        name: String,
+    },
+
+    /// We were added to a group
+    GroupJoined {
+        group_id: String,
+    },
+
+    /// We left a group
+    GroupLeft {
+        group_id: String,
+    },
+
+    /// Group metadata changed
+    GroupUpdated {
+        group_id: String,
+        name: String,
+    },
+
+    /// A member was added to a group
+    GroupMemberAdded {
+        group_id: String,
+        peer_id: PeerId,
+    },
+
+    /// A member was removed from a group
+    GroupMemberRemoved {
+        group_id: String,
+        peer_id: PeerId,
+    },
+
+    /// A group message was received (`content` is the decrypted payload)
+    GroupMessageReceived {
+        group_id: String,
+        message_id: String,
+        from: PeerId,
+        content: Vec<u8>,
+        timestamp: i64,
+    },
+
+    /// An incoming group message was dropped (bad signature, missing authority, ...)
+    GroupMessageRejected {
+        group_id: String,
+        message_id: String,
+        from: PeerId,
+        reason: String,
+    },
+
+    /// A device was linked to an account (ours, or one we are linked to)
+    DeviceLinked {
+        account_peer_id: PeerId,
+        device_peer_id: PeerId,
+    },
+
+    /// Another device of our account sent us history or state changes
+    DeviceSynced {
+        device_peer_id: PeerId,
+        messages: u32,
+        changes: u32,
+    },
+
+    /// A peer is calling us
+    IncomingCall {
+        call_id: String,
+        from: PeerId,
+    },
+
+    /// A call changed state
+    CallStateChanged {
+        call_id: String,
+        state: String,
+    },
+
+    /// A call ended
+    CallEnded {
+        call_id: String,
+        reason: String,
+    },
+
     /// An error occurred
     Error {
         error: String,
@@ -103,3 +229,218 @@ where
         (self.func)(event)
     }
 }
+
+/// Single ordered event stream of the client
+///
+/// Every source emits into one queue, which gives the bus these guarantees:
+/// - every listener and stream sees every event, in emission order, and all
+///   of them see the same order;
+/// - events of one source (message handler, group manager, network, calls)
+///   keep their relative order;
+/// - listeners are never invoked concurrently; an event emitted while
+///   another one is being delivered (from another task, or from inside a
+///   listener) is queued and delivered right after it.
+#[derive(Default)]
+pub struct EventBus {
+    queue: Mutex<VecDeque<ClientEvent>>,
+    /// Set while some caller of `emit` is delivering the queue
+    delivering: AtomicBool,
+    callbacks: Mutex<Vec<Arc<dyn EventCallback>>>,
+    streams: Mutex<Vec<mpsc::UnboundedSender<ClientEvent>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a listener for all future events
+    pub fn add_callback(&self, callback: Arc<dyn EventCallback>) {
+        self.callbacks
+            .lock()
+            .expect("event callbacks lock poisoned")
+            .push(callback);
+    }
+
+    /// Stream of all future events (ends when the bus is dropped)
+    pub fn subscribe(&self) -> EventStream {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.streams
+            .lock()
+            .expect("event streams lock poisoned")
+            .push(tx);
+        EventStream { rx }
+    }
+
+    /// Queue an event and deliver the queue unless another caller already is
+    pub fn emit(&self, event: ClientEvent) {
+        self.queue
+            .lock()
+            .expect("event queue lock poisoned")
+            .push_back(event);
+
+        loop {
+            if self
+                .delivering
+                .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+                .is_err()
+            {
+                // The caller delivering right now picks our event up
+                return;
+            }
+
+            let guard = DeliveringGuard(&self.delivering);
+            while let Some(event) = self.next_event() {
+                self.deliver(event);
+            }
+            drop(guard);
+
+            // An event queued between our last pop and releasing the flag has
+            // nobody else to deliver it
+            if self.queue.lock().expect("event queue lock poisoned").is_empty() {
+                return;
+            }
+        }
+    }
+
+    fn next_event(&self) -> Option<ClientEvent> {
+        self.queue
+            .lock()
+            .expect("event queue lock poisoned")
+            .pop_front()
+    }
+
+    fn deliver(&self, event: ClientEvent) {
+        // Snapshot, so listeners may register other listeners
+        let callbacks = self
+            .callbacks
+            .lock()
+            .expect("event callbacks lock poisoned")
+            .clone();
+        for callback in &callbacks {
+            callback.on_event(event.clone());
+        }
+
+        self.streams
+            .lock()
+            .expect("event streams lock poisoned")
+            .retain(|tx| tx.send(event.clone()).is_ok());
+    }
+}
+
+/// Clears the delivering flag even if a listener panics
+struct DeliveringGuard<'a>(&'a AtomicBool);
+
+impl Drop for DeliveringGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// Async stream of client events (see `EventBus::subscribe`)
+pub struct EventStream {
+    rx: mpsc::UnboundedReceiver<ClientEvent>,
+}
+
+impl Stream for EventStream {
+    type Item = ClientEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    fn typing() -> ClientEvent {
+        ClientEvent::TypingStarted {
+            peer_id: PeerId::random(),
+        }
+    }
+
+    fn peer_of(event: &ClientEvent) -> PeerId {
+        match event {
+            ClientEvent::TypingStarted { peer_id } => *peer_id,
+            other => panic!("Unexpected event {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_listeners_and_streams_see_the_same_order() {
+        let bus = EventBus::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_cb = Arc::clone(&seen);
+        bus.add_callback(Arc::new(FunctionCallback::new(move |event| {
+            seen_cb.lock().unwrap().push(peer_of(&event));
+        })));
+        let mut stream = bus.subscribe();
+
+        let events: Vec<ClientEvent> = (0..10).map(|_| typing()).collect();
+        let emitted: Vec<PeerId> = events.iter().map(peer_of).collect();
+        for event in events {
+            bus.emit(event);
+        }
+
+        assert_eq!(*seen.lock().unwrap(), emitted);
+        let mut streamed = Vec::new();
+        for _ in 0..emitted.len() {
+            streamed.push(peer_of(&stream.next().await.unwrap()));
+        }
+        assert_eq!(streamed, emitted);
+    }
+
+    #[test]
+    fn test_event_emitted_by_a_listener_is_delivered_after_the_current_one() {
+        let bus = Arc::new(EventBus::new());
+        let first = typing();
+        let second = typing();
+        let (first_peer, second_peer) = (peer_of(&first), peer_of(&second));
+
+        // The first listener reacts to the first event with another event
+        let reentrant_bus = Arc::clone(&bus);
+        let reaction = Mutex::new(Some(second));
+        bus.add_callback(Arc::new(FunctionCallback::new(move |_| {
+            if let Some(event) = reaction.lock().unwrap().take() {
+                reentrant_bus.emit(event);
+            }
+        })));
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_cb = Arc::clone(&seen);
+        bus.add_callback(Arc::new(FunctionCallback::new(move |event| {
+            seen_cb.lock().unwrap().push(peer_of(&event));
+        })));
+
+        bus.emit(first);
+        // The second listener got the first event before the reaction
+        assert_eq!(*seen.lock().unwrap(), vec![first_peer, second_peer]);
+    }
+
+    #[test]
+    fn test_concurrent_emitters_lose_no_events() {
+        let bus = Arc::new(EventBus::new());
+        let count = Arc::new(Mutex::new(0usize));
+        let count_cb = Arc::clone(&count);
+        bus.add_callback(Arc::new(FunctionCallback::new(move |_| {
+            *count_cb.lock().unwrap() += 1;
+        })));
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let bus = Arc::clone(&bus);
+                std::thread::spawn(move || {
+                    for _ in 0..250 {
+                        bus.emit(typing());
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(*count.lock().unwrap(), 1000);
+    }
+}
@@ -5,10 +5,14 @@
 pub mod builder;
 pub mod client;
 pub mod events;
+pub mod outbox;
+pub mod policy;
 
 pub use builder::ClientBuilder;
 pub use client::Client;
-pub use events::{ClientEvent, EventCallback, FunctionCallback};
+pub use events::{ClientEvent, EventBus, EventCallback, EventStream, FunctionCallback};
+pub use outbox::{OfflineMessage, OfflineStore};
+pub use policy::EncryptionPolicy;
 
 use thiserror::Error;
 
@@ -0,0 +1,46 @@
+//! Outbox
+//!
+//! Outgoing direct messages are queued in the `outbox` table before they are
+//! sent and stay there until the recipient's ACK arrives. `Client::run_outbox`
+//! retries them with a `RetryPolicy` (re-resolving the peer through the DHT
+//! each time) and, once the attempts are used up, hands them to the
+//! store-and-forward server.
+//!
+//! Status flow: `Pending` (queued) → `Sent` (on the wire, or stored on the
+//! server) → `Delivered` (ACK) or `Failed` (no route and no server).
+//!
+//! The other direction: `Client::run_offline_inbox` fetches what the server
+//! kept for us on startup and whenever `Client::notify_offline_messages` is
+//! called, runs it through the message handler and deletes it.
+
+use async_trait::async_trait;
+use libp2p::PeerId;
+use std::time::Duration;
+
+use crate::protocol::Message;
+use crate::utils::error::Result;
+
+/// How often the outbox task looks for due messages
+pub const OUTBOX_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Message kept by the store-and-forward server for this peer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OfflineMessage {
+    pub message_id: String,
+    pub sender_peer_id: String,
+    /// Encoded protocol message, as uploaded by the sender
+    pub payload: Vec<u8>,
+}
+
+/// Server that keeps messages for peers that can't be reached directly
+#[async_trait]
+pub trait OfflineStore: Send + Sync {
+    /// Hand a message over for later delivery to `recipient`
+    async fn store(&self, recipient: &PeerId, message: &Message) -> Result<()>;
+
+    /// Messages waiting on the server for `recipient`
+    async fn fetch(&self, recipient: &PeerId) -> Result<Vec<OfflineMessage>>;
+
+    /// Remove processed messages from the server
+    async fn acknowledge(&self, message_ids: &[String]) -> Result<()>;
+}
@@ -0,0 +1,45 @@
+//! Encryption Policy
+//!
+//! Controls what the client does when a message cannot be end-to-end encrypted.
+
+/// Policy applied when no E2E session can be established with a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EncryptionPolicy {
+    /// Refuse to send anything that is not end-to-end encrypted
+    #[default]
+    RequireE2E,
+    /// Allow plaintext only to peers discovered on the local network (mDNS)
+    AllowPlaintextOnLan,
+    /// Always allow plaintext fallback (emits `ClientEvent::PlaintextFallback`)
+    AllowPlaintext,
+}
+
+impl EncryptionPolicy {
+    /// Whether a plaintext message may be sent to a peer
+    pub fn allows_plaintext(&self, is_lan_peer: bool) -> bool {
+        match self {
+            EncryptionPolicy::RequireE2E => false,
+            EncryptionPolicy::AllowPlaintextOnLan => is_lan_peer,
+            EncryptionPolicy::AllowPlaintext => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_policy_is_strict() {
+        assert_eq!(EncryptionPolicy::default(), EncryptionPolicy::RequireE2E);
+    }
+
+    #[test]
+    fn test_allows_plaintext() {
+        assert!(!EncryptionPolicy::RequireE2E.allows_plaintext(true));
+        assert!(!EncryptionPolicy::RequireE2E.allows_plaintext(false));
+        assert!(EncryptionPolicy::AllowPlaintextOnLan.allows_plaintext(true));
+        assert!(!EncryptionPolicy::AllowPlaintextOnLan.allows_plaintext(false));
+        assert!(EncryptionPolicy::AllowPlaintext.allows_plaintext(false));
+    }
+}
@@ -0,0 +1,122 @@
+//! Media encryption
+//!
+//! Every attachment gets its own random AES-256-GCM key. Files are encrypted
+//! chunk by chunk; the media hash, chunk index, offset and last-chunk flag are
+//! bound to each chunk as associated data, so chunks can't be swapped between
+//! files, reordered or truncated unnoticed. The key and the SHA-256 digest of
+//! the plaintext travel inside the E2E-encrypted `MediaOffer`.
+
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::crypto::signal::{
+    decrypt_message_with_aad, encrypt_message_with_aad, EncryptedMessage,
+};
+use crate::utils::error::{MePassaError, Result};
+
+/// Default plaintext chunk size for media transfers
+pub const MEDIA_CHUNK_SIZE: usize = 64 * 1024;
+
+const NONCE_LEN: usize = 12;
+
+/// Generate a random per-file media key
+pub fn generate_media_key() -> [u8; 32] {
+    let mut key = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut key);
+    key
+}
+
+/// SHA-256 digest of a plaintext file
+pub fn media_digest(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+/// Parse a 32-byte key or digest received from the network
+pub fn media_key_from_bytes(bytes: &[u8]) -> Result<[u8; 32]> {
+    bytes
+        .try_into()
+        .map_err(|_| MePassaError::Crypto(format!("Invalid media key length: {}", bytes.len())))
+}
+
+fn chunk_aad(media_hash: &str, index: u32, offset: u64, is_last: bool) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(media_hash.len() + 13);
+    aad.extend_from_slice(media_hash.as_bytes());
+    aad.extend_from_slice(&index.to_be_bytes());
+    aad.extend_from_slice(&offset.to_be_bytes());
+    aad.push(is_last as u8);
+    aad
+}
+
+/// Encrypt one chunk of a file (output: nonce || ciphertext)
+pub fn encrypt_media_chunk(
+    key: &[u8; 32],
+    media_hash: &str,
+    index: u32,
+    offset: u64,
+    is_last: bool,
+    plaintext: &[u8],
+) -> Result<Vec<u8>> {
+    let aad = chunk_aad(media_hash, index, offset, is_last);
+    let encrypted = encrypt_message_with_aad(plaintext, key, &aad)?;
+
+    let mut data = Vec::with_capacity(NONCE_LEN + encrypted.ciphertext.len());
+    data.extend_from_slice(&encrypted.nonce);
+    data.extend_from_slice(&encrypted.ciphertext);
+    Ok(data)
+}
+
+/// Decrypt one chunk of a file, checking its position in the file
+pub fn decrypt_media_chunk(
+    key: &[u8; 32],
+    media_hash: &str,
+    index: u32,
+    offset: u64,
+    is_last: bool,
+    data: &[u8],
+) -> Result<Vec<u8>> {
+    if data.len() < NONCE_LEN {
+        return Err(MePassaError::Crypto("Media chunk too short".to_string()));
+    }
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    let encrypted = EncryptedMessage {
+        nonce: nonce.try_into().expect("nonce length checked"),
+        ciphertext: ciphertext.to_vec(),
+    };
+
+    let aad = chunk_aad(media_hash, index, offset, is_last);
+    decrypt_message_with_aad(&encrypted, key, &aad)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_roundtrip() {
+        let key = generate_media_key();
+        let data = encrypt_media_chunk(&key, "hash", 2, 128, false, b"chunk bytes").unwrap();
+        assert!(!data.windows(11).any(|w| w == b"chunk bytes"));
+
+        let plaintext = decrypt_media_chunk(&key, "hash", 2, 128, false, &data).unwrap();
+        assert_eq!(plaintext, b"chunk bytes");
+    }
+
+    #[test]
+    fn test_chunk_bound_to_position() {
+        let key = generate_media_key();
+        let data = encrypt_media_chunk(&key, "hash", 2, 128, false, b"chunk bytes").unwrap();
+
+        assert!(decrypt_media_chunk(&key, "hash", 3, 128, false, &data).is_err());
+        assert!(decrypt_media_chunk(&key, "hash", 2, 0, false, &data).is_err());
+        assert!(decrypt_media_chunk(&key, "hash", 2, 128, true, &data).is_err());
+        assert!(decrypt_media_chunk(&key, "other", 2, 128, false, &data).is_err());
+        assert!(decrypt_media_chunk(&generate_media_key(), "hash", 2, 128, false, &data).is_err());
+    }
+
+    #[test]
+    fn test_media_key_from_bytes() {
+        let key = generate_media_key();
+        assert_eq!(media_key_from_bytes(&key).unwrap(), key);
+        assert!(media_key_from_bytes(&key[..16]).is_err());
+    }
+}
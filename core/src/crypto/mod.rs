@@ -5,13 +5,15 @@
 pub mod signal;
 pub mod session;
 pub mod ratchet;
-pub mod group;
+pub mod media;
+pub mod passphrase;
 pub mod storage;
 
 pub use signal::{X3DH, EncryptedMessage, encrypt_message, decrypt_message};
-pub use session::{Session, SessionManager};
-pub use ratchet::RatchetState;
-pub use group::{SenderKey, GroupSession, GroupSessionManager};
+pub use session::{PreKeyHeader, Session, SessionManager};
+pub use ratchet::{RatchetHeader, RatchetMessage, RatchetState};
+pub use media::{decrypt_media_chunk, encrypt_media_chunk, generate_media_key, media_digest};
+pub use passphrase::KdfParams;
 pub use storage::{decrypt_for_storage, encrypt_for_storage};
 
 use thiserror::Error;
@@ -0,0 +1,186 @@
+//! Passphrase sealing
+//!
+//! Seals secrets with AES-256-GCM under a key derived from a passphrase with
+//! Argon2id. Used for identity backups and passphrase-protected identities.
+//!
+//! Layout (integers little-endian):
+//!
+//! ```text
+//! magic: [u8; 4] | version: u8 | m_cost: u32 | t_cost: u32 | p_cost: u32
+//!                | salt: [u8; 16] | nonce: [u8; 12] | ciphertext + GCM tag
+//! ```
+//!
+//! Everything before the nonce is authenticated as associated data, so the
+//! KDF parameters can't be lowered without the passphrase.
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::RngCore;
+
+use crate::crypto::signal::{decrypt_message_with_aad, encrypt_message_with_aad, EncryptedMessage};
+use crate::utils::error::{MePassaError, Result};
+
+const MAGIC_LEN: usize = 4;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = MAGIC_LEN + 1 + 3 * 4 + SALT_LEN;
+
+/// Largest Argon2 memory cost accepted when opening (1 GiB), so a crafted
+/// file can't make the device allocate without bound
+const MAX_MEMORY_KIB: u32 = 1 << 20;
+
+/// Argon2id cost parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    /// Memory in KiB
+    pub memory_kib: u32,
+    /// Passes over the memory
+    pub iterations: u32,
+    /// Lanes
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    /// OWASP's recommended minimum for Argon2id (19 MiB, 2 passes), which
+    /// stays bearable on low-end phones
+    fn default() -> Self {
+        Self {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+impl KdfParams {
+    fn derive_key(&self, passphrase: &str, salt: &[u8]) -> Result<[u8; 32]> {
+        if self.memory_kib > MAX_MEMORY_KIB {
+            return Err(MePassaError::Crypto(format!(
+                "KDF memory cost too high: {} KiB",
+                self.memory_kib
+            )));
+        }
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, Some(32))
+            .map_err(|e| MePassaError::Crypto(format!("Invalid KDF parameters: {}", e)))?;
+
+        let mut key = [0u8; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| MePassaError::Crypto(format!("Key derivation failed: {}", e)))?;
+        Ok(key)
+    }
+}
+
+/// Seal `plaintext` under `passphrase`, tagged with `magic` and `version`
+pub fn seal(
+    magic: &[u8; 4],
+    version: u8,
+    plaintext: &[u8],
+    passphrase: &str,
+    params: KdfParams,
+) -> Result<Vec<u8>> {
+    if passphrase.is_empty() {
+        return Err(MePassaError::Crypto("Passphrase is empty".to_string()));
+    }
+
+    let mut salt = [0u8; SALT_LEN];
+    rand::rngs::OsRng.fill_bytes(&mut salt);
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(magic);
+    header.push(version);
+    header.extend_from_slice(&params.memory_kib.to_le_bytes());
+    header.extend_from_slice(&params.iterations.to_le_bytes());
+    header.extend_from_slice(&params.parallelism.to_le_bytes());
+    header.extend_from_slice(&salt);
+
+    let key = params.derive_key(passphrase, &salt)?;
+    let encrypted = encrypt_message_with_aad(plaintext, &key, &header)?;
+
+    let mut sealed = header;
+    sealed.extend_from_slice(&encrypted.nonce);
+    sealed.extend_from_slice(&encrypted.ciphertext);
+    Ok(sealed)
+}
+
+/// Version of a sealed blob, or None if it doesn't start with `magic`
+pub fn sealed_version(magic: &[u8; 4], sealed: &[u8]) -> Option<u8> {
+    if sealed.len() < HEADER_LEN + NONCE_LEN || &sealed[..MAGIC_LEN] != magic {
+        return None;
+    }
+    Some(sealed[MAGIC_LEN])
+}
+
+/// Open a blob sealed by [`seal`] with the same `magic` and `version`
+///
+/// # Errors
+///
+/// Returns error if the blob has another tag, or if the passphrase is wrong
+/// or the blob was altered
+pub fn open(magic: &[u8; 4], version: u8, sealed: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    match sealed_version(magic, sealed) {
+        Some(found) if found == version => {}
+        Some(found) => {
+            return Err(MePassaError::Crypto(format!("Unsupported version: {}", found)));
+        }
+        None => return Err(MePassaError::Crypto("Unrecognized sealed data".to_string())),
+    }
+
+    let (header, rest) = sealed.split_at(HEADER_LEN);
+    let le_u32 = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap());
+    let params = KdfParams {
+        memory_kib: le_u32(MAGIC_LEN + 1),
+        iterations: le_u32(MAGIC_LEN + 5),
+        parallelism: le_u32(MAGIC_LEN + 9),
+    };
+    let key = params.derive_key(passphrase, &header[HEADER_LEN - SALT_LEN..])?;
+
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let encrypted = EncryptedMessage {
+        nonce: nonce.try_into().unwrap(),
+        ciphertext: ciphertext.to_vec(),
+    };
+    decrypt_message_with_aad(&encrypted, &key, header)
+        .map_err(|_| MePassaError::Crypto("Wrong passphrase or corrupted data".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cheap parameters so the tests stay fast in debug builds
+    const TEST_PARAMS: KdfParams = KdfParams {
+        memory_kib: 64,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    #[test]
+    fn test_seal_and_open() {
+        let sealed = seal(b"TEST", 1, b"secret", "correct horse", TEST_PARAMS).unwrap();
+        assert_eq!(sealed_version(b"TEST", &sealed), Some(1));
+        assert_eq!(open(b"TEST", 1, &sealed, "correct horse").unwrap(), b"secret");
+
+        // Salt and nonce are fresh every time
+        let again = seal(b"TEST", 1, b"secret", "correct horse", TEST_PARAMS).unwrap();
+        assert_ne!(sealed, again);
+    }
+
+    #[test]
+    fn test_open_rejects_wrong_passphrase_and_tampering() {
+        let sealed = seal(b"TEST", 1, b"secret", "correct horse", TEST_PARAMS).unwrap();
+        assert!(open(b"TEST", 1, &sealed, "wrong horse").is_err());
+        assert!(open(b"OTHR", 1, &sealed, "correct horse").is_err());
+        assert!(open(b"TEST", 2, &sealed, "correct horse").is_err());
+
+        // Lowering the KDF cost breaks the authentication
+        let mut weakened = sealed.clone();
+        weakened[MAGIC_LEN + 1] = 8;
+        assert!(open(b"TEST", 1, &weakened, "correct horse").is_err());
+
+        let mut flipped = sealed.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert!(open(b"TEST", 1, &flipped, "correct horse").is_err());
+
+        assert!(open(b"TEST", 1, b"TEST", "correct horse").is_err());
+        assert!(seal(b"TEST", 1, b"secret", "", TEST_PARAMS).is_err());
+    }
+}
@@ -1,156 +1,350 @@
 //! Double Ratchet for Forward Secrecy
 //!
-//! This module implements a simplified Double Ratchet algorithm inspired by
-//! the Signal Protocol. It provides forward secrecy by deriving new encryption
-//! keys for each message using HKDF key derivation.
+//! This module implements the Double Ratchet algorithm from the Signal Protocol
+//! on top of the X3DH shared secret:
 //!
-//! **Simplified Implementation:**
-//! - Uses HKDF to derive sending and receiving keys from root keys
-//! - Ratchets forward with each message
-//! - Provides forward secrecy (compromise of current key doesn't affect past messages)
+//! - **Symmetric-key ratchet:** every message is encrypted with a fresh message
+//!   key derived from a chain key, and the chain key is advanced afterwards
+//!   (forward secrecy inside a chain).
+//! - **Diffie-Hellman ratchet:** each side keeps an X25519 ratchet key pair and
+//!   publishes its public half in every message header. Whenever a new remote
+//!   ratchet key is seen, both a new receiving and a new sending chain are
+//!   derived from the root key and a fresh DH output (post-compromise security).
+//! - **Skipped message keys:** message keys for messages that have not arrived
+//!   yet are cached (bounded by [`MAX_SKIP`]) so out-of-order delivery works.
 //!
-//! **Note:** This is a simplified version for MVP. Production should use libsignal-protocol
-//! which includes full Double Ratchet with Diffie-Hellman ratchet steps.
+//! The initiator (Alice) starts the DH ratchet against the responder's signed
+//! prekey; the responder (Bob) uses his signed prekey as his first ratchet key.
 
 use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
 use sha2::Sha256;
+use x25519_dalek::{PublicKey as X25519PublicKey, StaticSecret};
 
-use crate::crypto::signal::{encrypt_message, decrypt_message, EncryptedMessage};
+use crate::crypto::signal::{decrypt_message_with_aad, encrypt_message_with_aad, EncryptedMessage};
 use crate::utils::error::{Result, MePassaError};
 
+/// Maximum number of message keys that may be skipped in a single chain
+pub const MAX_SKIP: u32 = 1000;
+
+/// Maximum number of skipped message keys kept per session
+const MAX_STORED_SKIPPED_KEYS: usize = 2000;
+
+/// Double Ratchet message header
+///
+/// Sent in the clear next to the ciphertext and authenticated as AAD.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RatchetHeader {
+    /// Sender's current ratchet public key (X25519)
+    pub ratchet_public: [u8; 32],
+
+    /// Number of messages sent in the sender's previous sending chain
+    pub previous_counter: u32,
+
+    /// Message number in the current sending chain
+    pub counter: u32,
+}
+
+impl RatchetHeader {
+    /// Serialize header for use as associated data
+    pub fn to_bytes(&self) -> [u8; 40] {
+        let mut bytes = [0u8; 40];
+        bytes[..32].copy_from_slice(&self.ratchet_public);
+        bytes[32..36].copy_from_slice(&self.previous_counter.to_be_bytes());
+        bytes[36..].copy_from_slice(&self.counter.to_be_bytes());
+        bytes
+    }
+}
+
+/// Message produced by the ratchet (header + AES-GCM payload)
+#[derive(Debug, Clone)]
+pub struct RatchetMessage {
+    pub header: RatchetHeader,
+    pub encrypted: EncryptedMessage,
+}
+
+/// Message key cached for a message that has not been received yet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SkippedMessageKey {
+    ratchet_public: [u8; 32],
+    counter: u32,
+    message_key: [u8; 32],
+}
+
 /// Ratchet State
 ///
 /// Maintains the state of the Double Ratchet for a session.
-/// Includes sending and receiving chain keys.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RatchetState {
-    /// Root key (32 bytes) - source of truth for key derivation
+    /// Root key (32 bytes) - mixed with every DH ratchet output
     pub root_key: [u8; 32],
 
-    /// Sending chain key (32 bytes) - ratcheted forward with each sent message
-    pub sending_chain_key: [u8; 32],
+    /// Our current ratchet secret key (X25519)
+    ratchet_secret: [u8; 32],
+
+    /// Our current ratchet public key (X25519)
+    pub ratchet_public: [u8; 32],
 
-    /// Receiving chain key (32 bytes) - ratcheted forward with each received message
-    pub receiving_chain_key: [u8; 32],
+    /// Remote party's current ratchet public key
+    pub remote_ratchet_public: Option<[u8; 32]>,
 
-    /// Sending message number (counter)
-    pub sending_counter: u64,
+    /// Sending chain key - `None` until the first DH ratchet step (responder)
+    pub sending_chain_key: Option<[u8; 32]>,
 
-    /// Receiving message number (counter)
-    pub receiving_counter: u64,
+    /// Receiving chain key - `None` until the first message is received (initiator)
+    pub receiving_chain_key: Option<[u8; 32]>,
+
+    /// Message number in the current sending chain
+    pub sending_counter: u32,
+
+    /// Message number in the current receiving chain
+    pub receiving_counter: u32,
+
+    /// Length of the previous sending chain
+    pub previous_sending_counter: u32,
+
+    /// Message keys for skipped (not yet received) messages, oldest first
+    skipped_keys: Vec<SkippedMessageKey>,
 }
 
 impl RatchetState {
-    /// Create a new ratchet state from a root key (shared secret from X3DH)
+    /// Initialize the ratchet as the X3DH initiator (Alice)
     ///
-    /// `is_initiator`: true for Alice (who initiated X3DH), false for Bob (responder)
-    /// This ensures Alice's sending chain = Bob's receiving chain
-    pub fn new(root_key: [u8; 32], is_initiator: bool) -> Result<Self> {
-        // Derive initial sending and receiving chain keys from root key
-        let (chain_key_0, chain_key_1) = Self::derive_chain_keys(&root_key)?;
-
-        // Alice (initiator): sends with chain_0, receives with chain_1
-        // Bob (responder): sends with chain_1, receives with chain_0
-        let (sending_chain_key, receiving_chain_key) = if is_initiator {
-            (chain_key_0, chain_key_1)
-        } else {
-            (chain_key_1, chain_key_0)
-        };
+    /// `remote_ratchet_public` is the responder's signed prekey, which serves
+    /// as his initial ratchet key.
+    pub fn new_initiator(shared_secret: [u8; 32], remote_ratchet_public: [u8; 32]) -> Result<Self> {
+        let ratchet_secret = StaticSecret::random_from_rng(OsRng);
+        let ratchet_public = X25519PublicKey::from(&ratchet_secret).to_bytes();
+
+        let dh_output = Self::dh(&ratchet_secret.to_bytes(), &remote_ratchet_public);
+        let (root_key, sending_chain_key) = Self::kdf_root(&shared_secret, &dh_output)?;
 
         Ok(Self {
             root_key,
-            sending_chain_key,
-            receiving_chain_key,
+            ratchet_secret: ratchet_secret.to_bytes(),
+            ratchet_public,
+            remote_ratchet_public: Some(remote_ratchet_public),
+            sending_chain_key: Some(sending_chain_key),
+            receiving_chain_key: None,
             sending_counter: 0,
             receiving_counter: 0,
+            previous_sending_counter: 0,
+            skipped_keys: Vec::new(),
         })
     }
 
-    /// Derive sending and receiving chain keys from root key using HKDF
-    fn derive_chain_keys(root_key: &[u8; 32]) -> Result<([u8; 32], [u8; 32])> {
-        let hkdf = Hkdf::<Sha256>::new(Some(b"mepassa-ratchet-v1"), root_key);
+    /// Initialize the ratchet as the X3DH responder (Bob)
+    ///
+    /// `own_ratchet_secret` is the secret of the signed prekey the initiator used.
+    pub fn new_responder(shared_secret: [u8; 32], own_ratchet_secret: [u8; 32]) -> Self {
+        let ratchet_public =
+            X25519PublicKey::from(&StaticSecret::from(own_ratchet_secret)).to_bytes();
+
+        Self {
+            root_key: shared_secret,
+            ratchet_secret: own_ratchet_secret,
+            ratchet_public,
+            remote_ratchet_public: None,
+            sending_chain_key: None,
+            receiving_chain_key: None,
+            sending_counter: 0,
+            receiving_counter: 0,
+            previous_sending_counter: 0,
+            skipped_keys: Vec::new(),
+        }
+    }
+
+    /// X25519 Diffie-Hellman between a secret and a public key
+    fn dh(secret: &[u8; 32], public: &[u8; 32]) -> [u8; 32] {
+        let secret = StaticSecret::from(*secret);
+        secret
+            .diffie_hellman(&X25519PublicKey::from(*public))
+            .to_bytes()
+    }
 
-        let mut sending_chain_key = [0u8; 32];
-        hkdf.expand(b"sending-chain", &mut sending_chain_key)
-            .map_err(|e| MePassaError::Crypto(format!("HKDF expand failed: {}", e)))?;
+    /// Root KDF: derive a new root key and chain key from a DH output
+    fn kdf_root(root_key: &[u8; 32], dh_output: &[u8; 32]) -> Result<([u8; 32], [u8; 32])> {
+        let hkdf = Hkdf::<Sha256>::new(Some(root_key), dh_output);
 
-        let mut receiving_chain_key = [0u8; 32];
-        hkdf.expand(b"receiving-chain", &mut receiving_chain_key)
+        let mut okm = [0u8; 64];
+        hkdf.expand(b"mepassa-ratchet-root-v1", &mut okm)
             .map_err(|e| MePassaError::Crypto(format!("HKDF expand failed: {}", e)))?;
 
-        Ok((sending_chain_key, receiving_chain_key))
+        let mut new_root_key = [0u8; 32];
+        let mut chain_key = [0u8; 32];
+        new_root_key.copy_from_slice(&okm[..32]);
+        chain_key.copy_from_slice(&okm[32..]);
+
+        Ok((new_root_key, chain_key))
     }
 
-    /// Derive a message key from a chain key
-    fn derive_message_key(chain_key: &[u8; 32], counter: u64) -> Result<[u8; 32]> {
-        let hkdf = Hkdf::<Sha256>::new(Some(b"mepassa-message-key-v1"), chain_key);
+    /// Chain KDF: derive the next chain key and a message key from a chain key
+    fn kdf_chain(chain_key: &[u8; 32]) -> Result<([u8; 32], [u8; 32])> {
+        let hkdf = Hkdf::<Sha256>::new(Some(b"mepassa-chain-ratchet-v1"), chain_key);
+
+        let mut next_chain_key = [0u8; 32];
+        hkdf.expand(b"next-chain", &mut next_chain_key)
+            .map_err(|e| MePassaError::Crypto(format!("HKDF expand failed: {}", e)))?;
 
         let mut message_key = [0u8; 32];
-        let info = format!("message-{}", counter);
-        hkdf.expand(info.as_bytes(), &mut message_key)
+        hkdf.expand(b"message-key", &mut message_key)
             .map_err(|e| MePassaError::Crypto(format!("HKDF expand failed: {}", e)))?;
 
-        Ok(message_key)
+        Ok((next_chain_key, message_key))
     }
 
-    /// Ratchet forward the sending chain key
-    fn ratchet_sending_chain(&mut self) -> Result<()> {
-        let hkdf = Hkdf::<Sha256>::new(Some(b"mepassa-chain-ratchet-v1"), &self.sending_chain_key);
+    /// Encrypt a message using the ratchet
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<RatchetMessage> {
+        let chain_key = self
+            .sending_chain_key
+            .ok_or_else(|| MePassaError::Crypto("Ratchet has no sending chain yet".to_string()))?;
+
+        let (next_chain_key, message_key) = Self::kdf_chain(&chain_key)?;
 
-        let mut new_chain_key = [0u8; 32];
-        hkdf.expand(b"next-chain", &mut new_chain_key)
-            .map_err(|e| MePassaError::Crypto(format!("HKDF expand failed: {}", e)))?;
+        let header = RatchetHeader {
+            ratchet_public: self.ratchet_public,
+            previous_counter: self.previous_sending_counter,
+            counter: self.sending_counter,
+        };
+
+        let encrypted = encrypt_message_with_aad(plaintext, &message_key, &header.to_bytes())?;
 
-        self.sending_chain_key = new_chain_key;
+        self.sending_chain_key = Some(next_chain_key);
         self.sending_counter += 1;
 
-        Ok(())
+        Ok(RatchetMessage { header, encrypted })
+    }
+
+    /// Decrypt a message using the ratchet
+    ///
+    /// The state is only updated if decryption succeeds, so forged or corrupted
+    /// messages cannot desynchronize the session.
+    pub fn decrypt(&mut self, message: &RatchetMessage) -> Result<Vec<u8>> {
+        let mut next = self.clone();
+        let plaintext = next.decrypt_in_place(message)?;
+        *self = next;
+        Ok(plaintext)
     }
 
-    /// Ratchet forward the receiving chain key
-    fn ratchet_receiving_chain(&mut self) -> Result<()> {
-        let hkdf = Hkdf::<Sha256>::new(Some(b"mepassa-chain-ratchet-v1"), &self.receiving_chain_key);
+    fn decrypt_in_place(&mut self, message: &RatchetMessage) -> Result<Vec<u8>> {
+        let header = &message.header;
+        let aad = header.to_bytes();
 
-        let mut new_chain_key = [0u8; 32];
-        hkdf.expand(b"next-chain", &mut new_chain_key)
-            .map_err(|e| MePassaError::Crypto(format!("HKDF expand failed: {}", e)))?;
+        // 1. Message from a chain we skipped ahead of earlier
+        if let Some(message_key) = self.take_skipped_key(&header.ratchet_public, header.counter) {
+            return decrypt_message_with_aad(&message.encrypted, &message_key, &aad);
+        }
+
+        // 2. New remote ratchet key: finish the old chain, then DH ratchet step
+        if self.remote_ratchet_public != Some(header.ratchet_public) {
+            self.skip_message_keys(header.previous_counter)?;
+            self.dh_ratchet_step(&header.ratchet_public)?;
+        }
 
-        self.receiving_chain_key = new_chain_key;
+        // 3. Advance the current receiving chain up to this message
+        self.skip_message_keys(header.counter)?;
+
+        let chain_key = self
+            .receiving_chain_key
+            .ok_or_else(|| MePassaError::Crypto("Ratchet has no receiving chain".to_string()))?;
+        let (next_chain_key, message_key) = Self::kdf_chain(&chain_key)?;
+
+        let plaintext = decrypt_message_with_aad(&message.encrypted, &message_key, &aad)?;
+
+        self.receiving_chain_key = Some(next_chain_key);
         self.receiving_counter += 1;
 
+        Ok(plaintext)
+    }
+
+    /// Perform a DH ratchet step after receiving a new remote ratchet key
+    fn dh_ratchet_step(&mut self, remote_ratchet_public: &[u8; 32]) -> Result<()> {
+        self.previous_sending_counter = self.sending_counter;
+        self.sending_counter = 0;
+        self.receiving_counter = 0;
+        self.remote_ratchet_public = Some(*remote_ratchet_public);
+
+        // New receiving chain: our current key × their new key
+        let dh_output = Self::dh(&self.ratchet_secret, remote_ratchet_public);
+        let (root_key, receiving_chain_key) = Self::kdf_root(&self.root_key, &dh_output)?;
+        self.root_key = root_key;
+        self.receiving_chain_key = Some(receiving_chain_key);
+
+        // New sending chain: fresh key pair × their new key
+        let ratchet_secret = StaticSecret::random_from_rng(OsRng);
+        self.ratchet_public = X25519PublicKey::from(&ratchet_secret).to_bytes();
+        self.ratchet_secret = ratchet_secret.to_bytes();
+
+        let dh_output = Self::dh(&self.ratchet_secret, remote_ratchet_public);
+        let (root_key, sending_chain_key) = Self::kdf_root(&self.root_key, &dh_output)?;
+        self.root_key = root_key;
+        self.sending_chain_key = Some(sending_chain_key);
+
         Ok(())
     }
 
-    /// Encrypt a message using the ratchet
-    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<EncryptedMessage> {
-        // Derive message key from current sending chain key
-        let message_key = Self::derive_message_key(&self.sending_chain_key, self.sending_counter)?;
+    /// Store message keys of the current receiving chain up to `until` (exclusive)
+    fn skip_message_keys(&mut self, until: u32) -> Result<()> {
+        let (Some(mut chain_key), Some(remote)) =
+            (self.receiving_chain_key, self.remote_ratchet_public)
+        else {
+            return Ok(());
+        };
 
-        // Encrypt message
-        let encrypted = encrypt_message(plaintext, &message_key)?;
+        if until <= self.receiving_counter {
+            return Ok(());
+        }
 
-        // Ratchet forward sending chain
-        self.ratchet_sending_chain()?;
+        if until - self.receiving_counter > MAX_SKIP {
+            return Err(MePassaError::Crypto(format!(
+                "Too many skipped messages ({} > {})",
+                until - self.receiving_counter,
+                MAX_SKIP
+            )));
+        }
 
-        Ok(encrypted)
-    }
+        while self.receiving_counter < until {
+            let (next_chain_key, message_key) = Self::kdf_chain(&chain_key)?;
+            self.skipped_keys.push(SkippedMessageKey {
+                ratchet_public: remote,
+                counter: self.receiving_counter,
+                message_key,
+            });
+            chain_key = next_chain_key;
+            self.receiving_counter += 1;
+        }
+        self.receiving_chain_key = Some(chain_key);
 
-    /// Decrypt a message using the ratchet
-    pub fn decrypt(&mut self, encrypted: &EncryptedMessage) -> Result<Vec<u8>> {
-        // Derive message key from current receiving chain key
-        let message_key = Self::derive_message_key(&self.receiving_chain_key, self.receiving_counter)?;
+        if self.skipped_keys.len() > MAX_STORED_SKIPPED_KEYS {
+            let excess = self.skipped_keys.len() - MAX_STORED_SKIPPED_KEYS;
+            self.skipped_keys.drain(..excess);
+        }
 
-        // Decrypt message
-        let plaintext = decrypt_message(encrypted, &message_key)?;
+        Ok(())
+    }
 
-        // Ratchet forward receiving chain
-        self.ratchet_receiving_chain()?;
+    /// Remove and return a cached skipped message key
+    fn take_skipped_key(&mut self, ratchet_public: &[u8; 32], counter: u32) -> Option<[u8; 32]> {
+        let index = self
+            .skipped_keys
+            .iter()
+            .position(|k| &k.ratchet_public == ratchet_public && k.counter == counter)?;
+        Some(self.skipped_keys.remove(index).message_key)
+    }
 
-        Ok(plaintext)
+    /// Whether this side can send (false for a responder before the first message)
+    pub fn can_send(&self) -> bool {
+        self.sending_chain_key.is_some()
+    }
+
+    /// Number of cached skipped message keys
+    pub fn skipped_key_count(&self) -> usize {
+        self.skipped_keys.len()
     }
 
     /// Get a snapshot of current counters for debugging
-    pub fn counters(&self) -> (u64, u64) {
+    pub fn counters(&self) -> (u32, u32) {
         (self.sending_counter, self.receiving_counter)
     }
 }
@@ -161,118 +355,191 @@ mod tests {
     use crate::crypto::X3DH;
     use crate::identity::Identity;
 
+    /// Build a matching Alice/Bob ratchet pair from a fixed shared secret
+    fn ratchet_pair(shared_secret: [u8; 32]) -> (RatchetState, RatchetState) {
+        let bob_spk_secret = StaticSecret::random_from_rng(OsRng);
+        let bob_spk_public = X25519PublicKey::from(&bob_spk_secret).to_bytes();
+
+        let alice = RatchetState::new_initiator(shared_secret, bob_spk_public).unwrap();
+        let bob = RatchetState::new_responder(shared_secret, bob_spk_secret.to_bytes());
+        (alice, bob)
+    }
+
     #[test]
     fn test_ratchet_state_creation() {
         let root_key = [42u8; 32];
-        let ratchet = RatchetState::new(root_key, true).unwrap();
-
-        assert_eq!(ratchet.root_key, root_key);
-        assert_eq!(ratchet.sending_counter, 0);
-        assert_eq!(ratchet.receiving_counter, 0);
-        assert_ne!(ratchet.sending_chain_key, root_key); // Should be derived
-        assert_ne!(ratchet.receiving_chain_key, root_key); // Should be derived
-        assert_ne!(ratchet.sending_chain_key, ratchet.receiving_chain_key); // Should be different
+        let (alice, bob) = ratchet_pair(root_key);
+
+        assert_ne!(alice.root_key, root_key); // Mixed with the first DH output
+        assert_eq!(bob.root_key, root_key);
+        assert!(alice.can_send());
+        assert!(!bob.can_send()); // Bob can only reply after receiving
+        assert_eq!(alice.counters(), (0, 0));
+        assert_eq!(alice.remote_ratchet_public, Some(bob.ratchet_public));
     }
 
     #[test]
     fn test_ratchet_encrypt_decrypt() {
-        let root_key = [42u8; 32];
-        let mut alice_ratchet = RatchetState::new(root_key, true).unwrap(); // Alice is initiator
-        let mut bob_ratchet = RatchetState::new(root_key, false).unwrap(); // Bob is responder
+        let (mut alice, mut bob) = ratchet_pair([42u8; 32]);
 
         let plaintext = b"Hello with forward secrecy!";
 
-        // Alice encrypts
-        let encrypted = alice_ratchet.encrypt(plaintext).unwrap();
-        assert_eq!(alice_ratchet.sending_counter, 1);
+        let encrypted = alice.encrypt(plaintext).unwrap();
+        assert_eq!(alice.sending_counter, 1);
+        assert_eq!(encrypted.header.ratchet_public, alice.ratchet_public);
 
-        // Bob decrypts
-        let decrypted = bob_ratchet.decrypt(&encrypted).unwrap();
-        assert_eq!(bob_ratchet.receiving_counter, 1);
+        let decrypted = bob.decrypt(&encrypted).unwrap();
+        assert_eq!(bob.receiving_counter, 1);
 
         assert_eq!(plaintext, decrypted.as_slice());
     }
 
     #[test]
     fn test_ratchet_multiple_messages() {
-        let root_key = [42u8; 32];
-        let mut alice_ratchet = RatchetState::new(root_key, true).unwrap();
-        let mut bob_ratchet = RatchetState::new(root_key, false).unwrap();
+        let (mut alice, mut bob) = ratchet_pair([42u8; 32]);
 
-        // Send 10 messages
         for i in 0..10 {
             let msg = format!("Message {}", i);
+            let encrypted = alice.encrypt(msg.as_bytes()).unwrap();
+            let decrypted = bob.decrypt(&encrypted).unwrap();
+            assert_eq!(msg.as_bytes(), decrypted.as_slice());
+        }
+
+        assert_eq!(alice.sending_counter, 10);
+        assert_eq!(bob.receiving_counter, 10);
+    }
+
+    #[test]
+    fn test_dh_ratchet_ping_pong() {
+        let (mut alice, mut bob) = ratchet_pair([42u8; 32]);
+
+        let mut previous_alice_key = alice.ratchet_public;
+        for round in 0..5 {
+            let msg = format!("Alice round {}", round);
+            let enc = alice.encrypt(msg.as_bytes()).unwrap();
+            assert_eq!(bob.decrypt(&enc).unwrap(), msg.as_bytes());
+
+            let reply = format!("Bob round {}", round);
+            let enc = bob.encrypt(reply.as_bytes()).unwrap();
+            assert_eq!(alice.decrypt(&enc).unwrap(), reply.as_bytes());
+
+            // Every reply triggers a DH ratchet step with a fresh key pair
+            assert_ne!(alice.ratchet_public, previous_alice_key);
+            previous_alice_key = alice.ratchet_public;
+        }
+    }
+
+    #[test]
+    fn test_ratchet_forward_secrecy() {
+        let (mut alice, mut bob) = ratchet_pair([42u8; 32]);
 
-            // Alice encrypts
-            let encrypted = alice_ratchet.encrypt(msg.as_bytes()).unwrap();
+        let encrypted1 = alice.encrypt(b"First message").unwrap();
+        bob.decrypt(&encrypted1).unwrap();
 
-            // Bob decrypts
-            let decrypted = bob_ratchet.decrypt(&encrypted).unwrap();
+        let bob_chain_key_before = bob.receiving_chain_key.unwrap();
 
-            assert_eq!(msg.as_bytes(), decrypted.as_slice());
+        let encrypted2 = alice.encrypt(b"Second message").unwrap();
+        bob.decrypt(&encrypted2).unwrap();
+
+        // Chain keys moved forward
+        assert_ne!(bob.receiving_chain_key.unwrap(), bob_chain_key_before);
+
+        // A message key is single-use: replaying a message fails
+        assert!(bob.decrypt(&encrypted1).is_err());
+    }
+
+    #[test]
+    fn test_ratchet_out_of_order() {
+        let (mut alice, mut bob) = ratchet_pair([42u8; 32]);
+
+        let messages: Vec<RatchetMessage> = (0..5)
+            .map(|i| alice.encrypt(format!("msg {}", i).as_bytes()).unwrap())
+            .collect();
+
+        // Deliver 4, 0, 2, 1, 3
+        for &i in &[4usize, 0, 2, 1, 3] {
+            let decrypted = bob.decrypt(&messages[i]).unwrap();
+            assert_eq!(decrypted, format!("msg {}", i).as_bytes());
         }
 
-        assert_eq!(alice_ratchet.sending_counter, 10);
-        assert_eq!(bob_ratchet.receiving_counter, 10);
+        assert_eq!(bob.skipped_key_count(), 0);
     }
 
     #[test]
-    fn test_ratchet_forward_secrecy() {
-        let root_key = [42u8; 32];
-        let mut alice_ratchet = RatchetState::new(root_key, true).unwrap();
-        let mut bob_ratchet = RatchetState::new(root_key, false).unwrap();
-
-        // Send first message
-        let msg1 = b"First message";
-        let encrypted1 = alice_ratchet.encrypt(msg1).unwrap();
-        let decrypted1 = bob_ratchet.decrypt(&encrypted1).unwrap();
-        assert_eq!(msg1, decrypted1.as_slice());
-
-        // Save chain keys before second message
-        let alice_chain_key_before = alice_ratchet.sending_chain_key;
-        let bob_chain_key_before = bob_ratchet.receiving_chain_key;
-
-        // Send second message
-        let msg2 = b"Second message";
-        let encrypted2 = alice_ratchet.encrypt(msg2).unwrap();
-        let decrypted2 = bob_ratchet.decrypt(&encrypted2).unwrap();
-        assert_eq!(msg2, decrypted2.as_slice());
-
-        // Chain keys should have changed (forward secrecy)
-        assert_ne!(alice_ratchet.sending_chain_key, alice_chain_key_before);
-        assert_ne!(bob_ratchet.receiving_chain_key, bob_chain_key_before);
-
-        // Old keys can't decrypt new messages (forward secrecy test)
-        let msg3 = b"Third message";
-        let encrypted3 = alice_ratchet.encrypt(msg3).unwrap();
-
-        // Try to decrypt with old chain key (should fail)
-        let old_message_key = RatchetState::derive_message_key(&bob_chain_key_before, 2).unwrap();
-        let result = decrypt_message(&encrypted3, &old_message_key);
-        assert!(result.is_err()); // Should fail because key ratcheted forward
+    fn test_ratchet_out_of_order_across_dh_steps() {
+        let (mut alice, mut bob) = ratchet_pair([42u8; 32]);
+
+        // Alice sends two messages; only the first arrives before Bob replies
+        let a0 = alice.encrypt(b"a0").unwrap();
+        let a1 = alice.encrypt(b"a1").unwrap();
+        assert_eq!(bob.decrypt(&a0).unwrap(), b"a0");
+
+        let b0 = bob.encrypt(b"b0").unwrap();
+        assert_eq!(alice.decrypt(&b0).unwrap(), b"b0");
+
+        // Alice's next message uses a new ratchet key
+        let a2 = alice.encrypt(b"a2").unwrap();
+        assert_ne!(a2.header.ratchet_public, a1.header.ratchet_public);
+        assert_eq!(a2.header.previous_counter, 2);
+
+        assert_eq!(bob.decrypt(&a2).unwrap(), b"a2");
+        // a1 arrives late and is decrypted with the stored skipped key
+        assert_eq!(bob.decrypt(&a1).unwrap(), b"a1");
+    }
+
+    #[test]
+    fn test_ratchet_too_many_skipped() {
+        let (mut alice, mut bob) = ratchet_pair([42u8; 32]);
+
+        let mut last = None;
+        for _ in 0..(MAX_SKIP + 2) {
+            last = Some(alice.encrypt(b"spam").unwrap());
+        }
+
+        assert!(bob.decrypt(&last.unwrap()).is_err());
+        // Failed decryption leaves the state untouched
+        assert_eq!(bob.counters(), (0, 0));
+    }
+
+    #[test]
+    fn test_ratchet_tampered_header_rejected() {
+        let (mut alice, mut bob) = ratchet_pair([42u8; 32]);
+
+        let mut encrypted = alice.encrypt(b"hello").unwrap();
+        encrypted.header.previous_counter = 7;
+
+        assert!(bob.decrypt(&encrypted).is_err());
+        assert_eq!(bob.remote_ratchet_public, None);
     }
 
     #[test]
     fn test_ratchet_different_root_keys() {
-        let root_key1 = [42u8; 32];
-        let root_key2 = [99u8; 32];
+        let bob_spk_secret = StaticSecret::random_from_rng(OsRng);
+        let bob_spk_public = X25519PublicKey::from(&bob_spk_secret).to_bytes();
+
+        let mut alice = RatchetState::new_initiator([42u8; 32], bob_spk_public).unwrap();
+        let mut bob = RatchetState::new_responder([99u8; 32], bob_spk_secret.to_bytes());
 
-        let mut alice_ratchet = RatchetState::new(root_key1, true).unwrap();
-        let mut bob_ratchet = RatchetState::new(root_key2, false).unwrap();
+        let encrypted = alice.encrypt(b"This should fail").unwrap();
+        assert!(bob.decrypt(&encrypted).is_err());
+    }
+
+    #[test]
+    fn test_ratchet_state_serialization() {
+        let (mut alice, mut bob) = ratchet_pair([42u8; 32]);
 
-        let plaintext = b"This should fail";
+        let encrypted = alice.encrypt(b"before save").unwrap();
+        bob.decrypt(&encrypted).unwrap();
 
-        // Alice encrypts with root_key1
-        let encrypted = alice_ratchet.encrypt(plaintext).unwrap();
+        let bytes = bincode::serialize(&bob).unwrap();
+        let mut restored: RatchetState = bincode::deserialize(&bytes).unwrap();
 
-        // Bob tries to decrypt with root_key2 (should fail)
-        let result = bob_ratchet.decrypt(&encrypted);
-        assert!(result.is_err());
+        let reply = restored.encrypt(b"after restore").unwrap();
+        assert_eq!(alice.decrypt(&reply).unwrap(), b"after restore");
     }
 
     #[test]
     fn test_e2e_with_x3dh_and_ratchet() {
-        // Bob generates identity
         let bob = Identity::generate(0);
         let mut bob_mut = bob.clone();
 
@@ -291,57 +558,28 @@ mod tests {
         let bob_shared_secret =
             X3DH::respond(&bob_signed_prekey_secret, None, &alice_ephemeral_pub).unwrap();
 
-        // Both should have same shared secret
         assert_eq!(alice_shared_secret, bob_shared_secret);
 
-        // Create ratchets from shared secret
-        let mut alice_ratchet = RatchetState::new(alice_shared_secret, true).unwrap(); // Alice is initiator
-        let mut bob_ratchet = RatchetState::new(bob_shared_secret, false).unwrap(); // Bob is responder
+        // Alice ratchets against Bob's signed prekey; Bob uses it as his ratchet key
+        let mut alice_ratchet =
+            RatchetState::new_initiator(alice_shared_secret, bob_bundle.signed_prekey).unwrap();
+        let mut bob_ratchet = RatchetState::new_responder(bob_shared_secret, bob_signed_prekey_secret);
 
-        // Alice sends encrypted message to Bob using ratchet
         let alice_message = b"Secret with X3DH + Double Ratchet!";
         let encrypted = alice_ratchet.encrypt(alice_message).unwrap();
-
-        // Bob decrypts using ratchet
         let decrypted = bob_ratchet.decrypt(&encrypted).unwrap();
-
         assert_eq!(alice_message, decrypted.as_slice());
 
-        // Send multiple messages to verify ratchet works
         for i in 0..5 {
             let msg = format!("Message {}", i);
-            let enc = alice_ratchet.encrypt(msg.as_bytes()).unwrap();
-            let dec = bob_ratchet.decrypt(&enc).unwrap();
+            let enc = bob_ratchet.encrypt(msg.as_bytes()).unwrap();
+            let dec = alice_ratchet.decrypt(&enc).unwrap();
             assert_eq!(msg.as_bytes(), dec.as_slice());
         }
 
-        assert_eq!(alice_ratchet.sending_counter, 6); // 1 + 5
-        assert_eq!(bob_ratchet.receiving_counter, 6);
-    }
-
-    #[test]
-    fn test_counters() {
-        let root_key = [42u8; 32];
-        let mut alice_ratchet = RatchetState::new(root_key, true).unwrap();
-        let mut bob_ratchet = RatchetState::new(root_key, false).unwrap();
-
-        assert_eq!(alice_ratchet.counters(), (0, 0));
-        assert_eq!(bob_ratchet.counters(), (0, 0));
-
-        // Alice encrypts and sends 5 messages
-        let mut encrypted_messages = Vec::new();
-        for _ in 0..5 {
-            encrypted_messages.push(alice_ratchet.encrypt(b"test").unwrap());
-        }
-
-        assert_eq!(alice_ratchet.counters(), (5, 0));
-
-        // Bob receives and decrypts first 3 messages
-        for i in 0..3 {
-            bob_ratchet.decrypt(&encrypted_messages[i]).unwrap();
-        }
-
-        assert_eq!(alice_ratchet.counters(), (5, 0)); // Alice sent 5
-        assert_eq!(bob_ratchet.counters(), (0, 3)); // Bob received 3
+        // Alice's sending chain restarted after the DH step triggered by Bob's reply
+        assert_eq!(alice_ratchet.counters(), (0, 5));
+        assert_eq!(alice_ratchet.previous_sending_counter, 1);
+        assert_eq!(bob_ratchet.counters(), (5, 1));
     }
 }
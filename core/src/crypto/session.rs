@@ -4,37 +4,95 @@
 //! Each session represents a secure communication channel with a specific peer.
 //!
 //! Session lifecycle:
-//! 1. Initiated via X3DH key agreement (Alice initiates to Bob)
-//! 2. Shared secret derived and stored
-//! 3. Messages encrypted/decrypted using session state
+//! 1. Initiated via X3DH key agreement (Alice initiates to Bob); Alice signs
+//!    her X3DH parameters with her peer id key, so Bob knows who started it
+//! 2. Double Ratchet initialized from the shared secret (Alice against Bob's
+//!    signed prekey, Bob with his signed prekey as first ratchet key)
+//! 3. Messages encrypted/decrypted through the ratchet
 //! 4. Ratchet state advanced with each message (forward secrecy)
+//! 5. When backed by SQLite, every state change is written through to the
+//!    `sessions` table (encrypted with the identity storage key)
 
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 
-use crate::crypto::signal::{encrypt_message, decrypt_message, EncryptedMessage};
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::ratchet::{RatchetMessage, RatchetState};
+use crate::crypto::signal::X3DH;
+use crate::crypto::storage::{decrypt_for_storage, encrypt_for_storage};
+use crate::identity::prekeys::{serde_bytes_64, PreKeyBundle};
+use crate::identity::{Keypair, PublicKey};
+use crate::storage::Database;
 use crate::utils::error::{Result, MePassaError};
 
 /// Session identifier (peer ID)
 pub type SessionId = String;
 
+/// X3DH parameters the initiator attaches to outgoing messages
+///
+/// Sent with every message until the first reply from the responder arrives,
+/// so the responder can build the session from whichever message it sees first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PreKeyHeader {
+    /// Initiator's X3DH ephemeral public key
+    pub ephemeral_public: [u8; 32],
+
+    /// Responder signed prekey used for X3DH
+    pub signed_prekey_id: u32,
+
+    /// Responder one-time prekey used for X3DH (0 if not used)
+    pub one_time_prekey_id: u32,
+
+    /// Initiator's signature over the fields above and the responder's
+    /// identity key (see `PreKeyHeader::signed_content`)
+    #[serde(with = "serde_bytes_64")]
+    pub signature: [u8; 64],
+}
+
+impl PreKeyHeader {
+    /// What the initiator signs with its peer id key
+    ///
+    /// Format: "mepassa-x3dh-v1" || ephemeral_public || signed_prekey_id (BE)
+    /// || one_time_prekey_id (BE) || responder identity key
+    fn signed_content(&self, responder_identity_key: &[u8; 32]) -> Vec<u8> {
+        let mut content = b"mepassa-x3dh-v1".to_vec();
+        content.extend_from_slice(&self.ephemeral_public);
+        content.extend_from_slice(&self.signed_prekey_id.to_be_bytes());
+        content.extend_from_slice(&self.one_time_prekey_id.to_be_bytes());
+        content.extend_from_slice(responder_identity_key);
+        content
+    }
+
+    /// Check that `initiator` started this exchange with the responder whose
+    /// identity key is `responder_identity_key`
+    pub fn verify(&self, initiator: &PublicKey, responder_identity_key: &[u8; 32]) -> Result<()> {
+        initiator.verify(&self.signed_content(responder_identity_key), &self.signature)
+    }
+}
+
 /// E2E Encryption Session
 ///
-/// Represents a secure communication channel with a peer.
-/// In this simplified implementation, we use a static shared secret from X3DH.
-/// A full Signal Protocol implementation would use Double Ratchet for forward secrecy.
-#[derive(Debug, Clone)]
+/// Represents a secure communication channel with a peer, backed by a
+/// Double Ratchet state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Session {
     /// Remote peer ID
     pub peer_id: String,
 
-    /// Shared secret from X3DH (32 bytes)
-    pub shared_secret: [u8; 32],
+    /// Double Ratchet state
+    pub ratchet: RatchetState,
+
+    /// X3DH ephemeral key this session was derived from (ours or the peer's)
+    pub base_ephemeral: [u8; 32],
 
-    /// Send message counter (prevents replay attacks)
+    /// X3DH header to send until the peer replies (initiator only)
+    pub pending_prekey: Option<PreKeyHeader>,
+
+    /// Send message counter
     pub send_counter: u64,
 
-    /// Receive message counter (prevents replay attacks)
+    /// Receive message counter
     pub recv_counter: u64,
 
     /// Session creation timestamp (Unix seconds)
@@ -44,17 +102,62 @@ pub struct Session {
     pub last_used_at: u64,
 }
 
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
 impl Session {
-    /// Create a new session from X3DH shared secret
-    pub fn new(peer_id: String, shared_secret: [u8; 32]) -> Self {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+    /// Create the initiator side of a session from a peer's prekey bundle,
+    /// signing the X3DH header with our `identity` keypair
+    pub fn initiate(peer_id: String, bundle: &PreKeyBundle, identity: &Keypair) -> Result<Self> {
+        let (shared_secret, ephemeral_public) = X3DH::initiate(bundle)?;
+        let ratchet = RatchetState::new_initiator(shared_secret, bundle.signed_prekey)?;
+        let now = unix_now();
+
+        let mut header = PreKeyHeader {
+            ephemeral_public,
+            signed_prekey_id: bundle.signed_prekey_id,
+            one_time_prekey_id: bundle
+                .one_time_prekey
+                .as_ref()
+                .map(|pk| pk.id)
+                .unwrap_or(0),
+            signature: [0u8; 64],
+        };
+        header.signature = identity.sign(&header.signed_content(&bundle.identity_key));
+
+        Ok(Self {
+            peer_id,
+            ratchet,
+            base_ephemeral: ephemeral_public,
+            pending_prekey: Some(header),
+            send_counter: 0,
+            recv_counter: 0,
+            created_at: now,
+            last_used_at: now,
+        })
+    }
+
+    /// Create the responder side of a session
+    ///
+    /// `shared_secret` comes from [`X3DH::respond`], `signed_prekey_secret` is the
+    /// secret of the signed prekey the initiator used.
+    pub fn respond(
+        peer_id: String,
+        shared_secret: [u8; 32],
+        remote_ephemeral: [u8; 32],
+        signed_prekey_secret: [u8; 32],
+    ) -> Self {
+        let now = unix_now();
 
         Self {
             peer_id,
-            shared_secret,
+            ratchet: RatchetState::new_responder(shared_secret, signed_prekey_secret),
+            base_ephemeral: remote_ephemeral,
+            pending_prekey: None,
             send_counter: 0,
             recv_counter: 0,
             created_at: now,
@@ -63,87 +166,170 @@ impl Session {
     }
 
     /// Encrypt a message using this session
-    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<EncryptedMessage> {
-        // Update last used timestamp
-        self.last_used_at = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-
-        // Encrypt using shared secret
-        let encrypted = encrypt_message(plaintext, &self.shared_secret)?;
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<RatchetMessage> {
+        let message = self.ratchet.encrypt(plaintext)?;
 
-        // Increment send counter
+        self.last_used_at = unix_now();
         self.send_counter += 1;
 
-        Ok(encrypted)
+        Ok(message)
     }
 
     /// Decrypt a message using this session
-    pub fn decrypt(&mut self, encrypted: &EncryptedMessage) -> Result<Vec<u8>> {
-        // Update last used timestamp
-        self.last_used_at = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-
-        // Decrypt using shared secret
-        let plaintext = decrypt_message(encrypted, &self.shared_secret)?;
+    pub fn decrypt(&mut self, message: &RatchetMessage) -> Result<Vec<u8>> {
+        let plaintext = self.ratchet.decrypt(message)?;
 
-        // Increment receive counter
+        self.last_used_at = unix_now();
         self.recv_counter += 1;
+        // The peer has the session now; stop sending X3DH parameters
+        self.pending_prekey = None;
 
         Ok(plaintext)
     }
 
     /// Get session age in seconds
     pub fn age(&self) -> u64 {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        now - self.created_at
+        unix_now().saturating_sub(self.created_at)
     }
 
     /// Check if session is stale (not used in 7 days)
     pub fn is_stale(&self) -> bool {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        (now - self.last_used_at) > (7 * 24 * 60 * 60) // 7 days
+        unix_now().saturating_sub(self.last_used_at) > (7 * 24 * 60 * 60) // 7 days
+    }
+}
+
+/// SQLite backing store for sessions
+#[derive(Clone)]
+struct SessionStore {
+    database: Database,
+    storage_key: [u8; 32],
+}
+
+impl std::fmt::Debug for SessionStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionStore").finish_non_exhaustive()
+    }
+}
+
+impl SessionStore {
+    fn save(&self, session: &Session) -> Result<()> {
+        let bytes = bincode::serialize(session)
+            .map_err(|e| MePassaError::Crypto(format!("Session serialize failed: {}", e)))?;
+        let blob = encrypt_for_storage(&self.storage_key, &bytes)?;
+        self.database.save_session(&session.peer_id, &blob)?;
+        Ok(())
+    }
+
+    fn delete(&self, peer_id: &str) -> Result<()> {
+        self.database.delete_session(peer_id)?;
+        Ok(())
+    }
+
+    fn load_all(&self) -> Result<HashMap<SessionId, Session>> {
+        let mut sessions = HashMap::new();
+
+        for stored in self.database.list_sessions()? {
+            let session = decrypt_for_storage(&self.storage_key, &stored.session_data)
+                .and_then(|bytes| {
+                    bincode::deserialize::<Session>(&bytes).map_err(|e| {
+                        MePassaError::Crypto(format!("Session deserialize failed: {}", e))
+                    })
+                });
+
+            match session {
+                Ok(session) => {
+                    sessions.insert(stored.peer_id, session);
+                }
+                Err(e) => {
+                    tracing::warn!("⚠️ Skipping unreadable session for {}: {}", stored.peer_id, e);
+                }
+            }
+        }
+
+        Ok(sessions)
     }
 }
 
 /// Session Manager
 ///
 /// Manages multiple sessions with different peers.
-/// Thread-safe using Arc<RwLock<...>>.
+/// Thread-safe using Arc<RwLock<...>>. Optionally backed by the `sessions`
+/// table so sessions survive restarts.
 #[derive(Debug, Clone)]
 pub struct SessionManager {
     sessions: Arc<RwLock<HashMap<SessionId, Session>>>,
+    store: Option<SessionStore>,
+    /// Signs the X3DH header of the sessions we initiate
+    identity: Option<Keypair>,
 }
 
 impl SessionManager {
-    /// Create a new session manager
+    /// Create a new in-memory session manager
     pub fn new() -> Self {
         Self {
             sessions: Arc::new(RwLock::new(HashMap::new())),
+            store: None,
+            identity: None,
         }
     }
 
-    /// Create a new session with a peer
-    pub fn create_session(&self, peer_id: String, shared_secret: [u8; 32]) -> Result<()> {
-        let session = Session::new(peer_id.clone(), shared_secret);
+    /// Create a session manager persisted in `database`
+    ///
+    /// Existing sessions are loaded immediately; every later change is written
+    /// through, encrypted with `storage_key`.
+    pub fn with_storage(database: Database, storage_key: [u8; 32]) -> Result<Self> {
+        let store = SessionStore {
+            database,
+            storage_key,
+        };
+        let sessions = store.load_all()?;
+
+        tracing::info!("🔐 Loaded {} E2E sessions from storage", sessions.len());
+
+        Ok(Self {
+            sessions: Arc::new(RwLock::new(sessions)),
+            store: Some(store),
+            identity: None,
+        })
+    }
 
-        let mut sessions = self
-            .sessions
-            .write()
-            .map_err(|e| MePassaError::Crypto(format!("Lock error: {}", e)))?;
+    /// Sign the sessions this manager initiates with `identity`
+    ///
+    /// Responders only accept X3DH headers signed by the initiator's peer id
+    /// key; without an identity, `initiate_session` fails.
+    pub fn with_identity(mut self, identity: Keypair) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// Write a session through to storage (no-op for in-memory managers)
+    fn persist(&self, session: &Session) -> Result<()> {
+        match &self.store {
+            Some(store) => store.save(session),
+            None => Ok(()),
+        }
+    }
 
-        sessions.insert(peer_id, session);
+    /// Initiate a session with a peer from their prekey bundle (X3DH as Alice)
+    pub fn initiate_session(&self, peer_id: String, bundle: &PreKeyBundle) -> Result<()> {
+        let identity = self
+            .identity
+            .as_ref()
+            .ok_or_else(|| MePassaError::Crypto("No identity to sign X3DH with".to_string()))?;
+        let session = Session::initiate(peer_id, bundle, identity)?;
+        self.update_session(session)
+    }
 
-        Ok(())
+    /// Accept a session initiated by a peer (X3DH as Bob)
+    pub fn accept_session(
+        &self,
+        peer_id: String,
+        shared_secret: [u8; 32],
+        remote_ephemeral: [u8; 32],
+        signed_prekey_secret: [u8; 32],
+    ) -> Result<()> {
+        let session = Session::respond(peer_id, shared_secret, remote_ephemeral, signed_prekey_secret);
+        self.update_session(session)
     }
 
     /// Get a session by peer ID
@@ -166,24 +352,48 @@ impl SessionManager {
             .write()
             .map_err(|e| MePassaError::Crypto(format!("Lock error: {}", e)))?;
 
+        self.persist(&session)?;
         sessions.insert(session.peer_id.clone(), session);
 
         Ok(())
     }
 
     /// Encrypt a message for a peer
-    pub fn encrypt_for(&self, peer_id: &str, plaintext: &[u8]) -> Result<EncryptedMessage> {
-        let mut session = self.get_session(peer_id)?;
-        let encrypted = session.encrypt(plaintext)?;
-        self.update_session(session)?;
-        Ok(encrypted)
+    ///
+    /// Returns the ratchet message and, while the peer has not replied yet,
+    /// the X3DH header that must travel with it.
+    pub fn encrypt_for(
+        &self,
+        peer_id: &str,
+        plaintext: &[u8],
+    ) -> Result<(RatchetMessage, Option<PreKeyHeader>)> {
+        let mut sessions = self
+            .sessions
+            .write()
+            .map_err(|e| MePassaError::Crypto(format!("Lock error: {}", e)))?;
+
+        let session = sessions
+            .get_mut(peer_id)
+            .ok_or_else(|| MePassaError::Crypto(format!("Session not found: {}", peer_id)))?;
+
+        let message = session.encrypt(plaintext)?;
+        self.persist(session)?;
+        Ok((message, session.pending_prekey))
     }
 
     /// Decrypt a message from a peer
-    pub fn decrypt_from(&self, peer_id: &str, encrypted: &EncryptedMessage) -> Result<Vec<u8>> {
-        let mut session = self.get_session(peer_id)?;
-        let plaintext = session.decrypt(encrypted)?;
-        self.update_session(session)?;
+    pub fn decrypt_from(&self, peer_id: &str, message: &RatchetMessage) -> Result<Vec<u8>> {
+        let mut sessions = self
+            .sessions
+            .write()
+            .map_err(|e| MePassaError::Crypto(format!("Lock error: {}", e)))?;
+
+        let session = sessions
+            .get_mut(peer_id)
+            .ok_or_else(|| MePassaError::Crypto(format!("Session not found: {}", peer_id)))?;
+
+        let plaintext = session.decrypt(message)?;
+        self.persist(session)?;
         Ok(plaintext)
     }
 
@@ -204,6 +414,9 @@ impl SessionManager {
             .write()
             .map_err(|e| MePassaError::Crypto(format!("Lock error: {}", e)))?;
 
+        if let Some(store) = &self.store {
+            store.delete(peer_id)?;
+        }
         sessions.remove(peer_id);
 
         Ok(())
@@ -245,6 +458,9 @@ impl SessionManager {
         let count = stale_keys.len();
 
         for key in stale_keys {
+            if let Some(store) = &self.store {
+                store.delete(&key)?;
+            }
             sessions.remove(&key);
         }
 
@@ -261,113 +477,201 @@ impl Default for SessionManager {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::crypto::X3DH;
     use crate::identity::Identity;
 
+    /// Alice and Bob sessions from a real X3DH exchange (Bob without one-time prekeys)
+    fn alice_and_bob() -> (Session, Session) {
+        let alice = Keypair::generate();
+        let mut bob = Identity::generate(0);
+
+        let bob_signed_prekey_secret = bob.prekey_pool().unwrap().signed_prekey().secret_bytes();
+        let bob_bundle = bob.prekey_pool_mut().unwrap().get_bundle();
+
+        let alice_session = Session::initiate("bob".to_string(), &bob_bundle, &alice).unwrap();
+        let header = alice_session.pending_prekey.unwrap();
+        header
+            .verify(&alice.public_key(), &bob.keypair().public_key_bytes())
+            .unwrap();
+
+        let bob_shared_secret =
+            X3DH::respond(&bob_signed_prekey_secret, None, &header.ephemeral_public).unwrap();
+        let bob_session = Session::respond(
+            "alice".to_string(),
+            bob_shared_secret,
+            header.ephemeral_public,
+            bob_signed_prekey_secret,
+        );
+
+        (alice_session, bob_session)
+    }
+
     #[test]
     fn test_session_creation() {
-        let shared_secret = [42u8; 32];
-        let session = Session::new("peer_123".to_string(), shared_secret);
-
-        assert_eq!(session.peer_id, "peer_123");
-        assert_eq!(session.shared_secret, shared_secret);
-        assert_eq!(session.send_counter, 0);
-        assert_eq!(session.recv_counter, 0);
-        assert!(session.age() < 2); // Less than 2 seconds old
-        assert!(!session.is_stale());
+        let (alice, bob) = alice_and_bob();
+
+        assert_eq!(alice.peer_id, "bob");
+        assert_eq!(alice.send_counter, 0);
+        assert_eq!(alice.recv_counter, 0);
+        assert!(alice.pending_prekey.is_some());
+        assert!(bob.pending_prekey.is_none());
+        assert_eq!(alice.base_ephemeral, bob.base_ephemeral);
+        assert!(alice.age() < 2); // Less than 2 seconds old
+        assert!(!alice.is_stale());
     }
 
     #[test]
     fn test_session_encrypt_decrypt() {
-        let shared_secret = [42u8; 32];
-        let mut session = Session::new("peer_123".to_string(), shared_secret);
+        let (mut alice, mut bob) = alice_and_bob();
 
         let plaintext = b"Hello, MePassa!";
 
-        // Encrypt
-        let encrypted = session.encrypt(plaintext).unwrap();
-        assert_eq!(session.send_counter, 1);
+        let encrypted = alice.encrypt(plaintext).unwrap();
+        assert_eq!(alice.send_counter, 1);
 
-        // Decrypt
-        let decrypted = session.decrypt(&encrypted).unwrap();
-        assert_eq!(session.recv_counter, 1);
+        let decrypted = bob.decrypt(&encrypted).unwrap();
+        assert_eq!(bob.recv_counter, 1);
 
         assert_eq!(plaintext, decrypted.as_slice());
     }
 
     #[test]
-    fn test_session_manager_create_and_get() {
-        let manager = SessionManager::new();
-        let shared_secret = [42u8; 32];
+    fn test_pending_prekey_cleared_on_reply() {
+        let (mut alice, mut bob) = alice_and_bob();
+
+        let encrypted = alice.encrypt(b"hi bob").unwrap();
+        bob.decrypt(&encrypted).unwrap();
+        assert!(alice.pending_prekey.is_some());
+
+        let reply = bob.encrypt(b"hi alice").unwrap();
+        alice.decrypt(&reply).unwrap();
+        assert!(alice.pending_prekey.is_none());
+    }
+
+    #[test]
+    fn test_prekey_header_signed_by_initiator() {
+        let alice = Keypair::generate();
+        let mallory = Keypair::generate();
+        let mut bob = Identity::generate(1);
+        let bob_key = bob.keypair().public_key_bytes();
+        let bundle = bob.prekey_pool_mut().unwrap().reserve_bundle();
+
+        let header = Session::initiate("bob".to_string(), &bundle, &alice)
+            .unwrap()
+            .pending_prekey
+            .unwrap();
+        assert!(header.verify(&alice.public_key(), &bob_key).is_ok());
 
-        // Create session
-        manager
-            .create_session("peer_123".to_string(), shared_secret)
+        // Mallory can't pass her exchange off as Alice's
+        let forged = Session::initiate("bob".to_string(), &bundle, &mallory)
+            .unwrap()
+            .pending_prekey
             .unwrap();
+        assert!(forged.verify(&alice.public_key(), &bob_key).is_err());
+
+        // nor reuse Alice's signature for other X3DH parameters
+        let swapped = PreKeyHeader {
+            signature: header.signature,
+            ..forged
+        };
+        assert!(swapped.verify(&alice.public_key(), &bob_key).is_err());
+        let without_prekey = PreKeyHeader {
+            one_time_prekey_id: 0,
+            ..header
+        };
+        assert!(without_prekey.verify(&alice.public_key(), &bob_key).is_err());
+
+        // and it only holds for the responder it was made for
+        let carol = Keypair::generate();
+        assert!(header
+            .verify(&alice.public_key(), &carol.public_key_bytes())
+            .is_err());
+
+        // A manager without an identity can't initiate
+        assert!(SessionManager::new()
+            .initiate_session("bob".to_string(), &bundle)
+            .is_err());
+    }
+
+    #[test]
+    fn test_session_manager_create_and_get() {
+        let manager = SessionManager::new().with_identity(Keypair::generate());
+        let mut bob = Identity::generate(0);
+        let bundle = bob.prekey_pool_mut().unwrap().get_bundle();
+
+        manager.initiate_session("peer_123".to_string(), &bundle).unwrap();
 
-        // Check exists
         assert!(manager.has_session("peer_123").unwrap());
 
-        // Get session
         let session = manager.get_session("peer_123").unwrap();
         assert_eq!(session.peer_id, "peer_123");
-        assert_eq!(session.shared_secret, shared_secret);
+        assert_eq!(session.ratchet.remote_ratchet_public, Some(bundle.signed_prekey));
     }
 
     #[test]
     fn test_session_manager_encrypt_decrypt() {
-        let manager = SessionManager::new();
-        let shared_secret = [42u8; 32];
-
-        manager
-            .create_session("peer_123".to_string(), shared_secret)
-            .unwrap();
+        let (alice, bob) = alice_and_bob();
+        let alice_manager = SessionManager::new();
+        let bob_manager = SessionManager::new();
+        alice_manager.update_session(alice).unwrap();
+        bob_manager.update_session(bob).unwrap();
 
         let plaintext = b"Hello from session manager!";
 
-        // Encrypt
-        let encrypted = manager.encrypt_for("peer_123", plaintext).unwrap();
-
-        // Decrypt
-        let decrypted = manager.decrypt_from("peer_123", &encrypted).unwrap();
+        let (encrypted, prekey) = alice_manager.encrypt_for("bob", plaintext).unwrap();
+        assert!(prekey.is_some());
 
+        let decrypted = bob_manager.decrypt_from("alice", &encrypted).unwrap();
         assert_eq!(plaintext, decrypted.as_slice());
 
+        let (reply, prekey) = bob_manager.encrypt_for("alice", b"ack").unwrap();
+        assert!(prekey.is_none());
+        assert_eq!(alice_manager.decrypt_from("bob", &reply).unwrap(), b"ack");
+
         // Check counters were updated
-        let session = manager.get_session("peer_123").unwrap();
+        let session = alice_manager.get_session("bob").unwrap();
         assert_eq!(session.send_counter, 1);
         assert_eq!(session.recv_counter, 1);
+        assert!(session.pending_prekey.is_none());
+    }
+
+    #[test]
+    fn test_failed_decrypt_keeps_session_usable() {
+        let (alice, bob) = alice_and_bob();
+        let alice_manager = SessionManager::new();
+        let bob_manager = SessionManager::new();
+        alice_manager.update_session(alice).unwrap();
+        bob_manager.update_session(bob).unwrap();
+
+        let (mut forged, _) = alice_manager.encrypt_for("bob", b"original").unwrap();
+        forged.encrypted.ciphertext[0] ^= 0xff;
+        assert!(bob_manager.decrypt_from("alice", &forged).is_err());
+
+        let (encrypted, _) = alice_manager.encrypt_for("bob", b"next").unwrap();
+        assert_eq!(bob_manager.decrypt_from("alice", &encrypted).unwrap(), b"next");
     }
 
     #[test]
     fn test_session_manager_remove() {
         let manager = SessionManager::new();
-        let shared_secret = [42u8; 32];
+        let (alice, _) = alice_and_bob();
+        manager.update_session(alice).unwrap();
 
-        manager
-            .create_session("peer_123".to_string(), shared_secret)
-            .unwrap();
+        assert!(manager.has_session("bob").unwrap());
 
-        assert!(manager.has_session("peer_123").unwrap());
-
-        manager.remove_session("peer_123").unwrap();
+        manager.remove_session("bob").unwrap();
 
-        assert!(!manager.has_session("peer_123").unwrap());
+        assert!(!manager.has_session("bob").unwrap());
     }
 
     #[test]
     fn test_session_manager_list_sessions() {
-        let manager = SessionManager::new();
+        let manager = SessionManager::new().with_identity(Keypair::generate());
+        let mut bob = Identity::generate(0);
+        let bundle = bob.prekey_pool_mut().unwrap().get_bundle();
 
-        manager
-            .create_session("peer_1".to_string(), [1u8; 32])
-            .unwrap();
-        manager
-            .create_session("peer_2".to_string(), [2u8; 32])
-            .unwrap();
-        manager
-            .create_session("peer_3".to_string(), [3u8; 32])
-            .unwrap();
+        for peer in ["peer_1", "peer_2", "peer_3"] {
+            manager.initiate_session(peer.to_string(), &bundle).unwrap();
+        }
 
         let sessions = manager.list_sessions().unwrap();
         assert_eq!(sessions.len(), 3);
@@ -388,75 +692,83 @@ mod tests {
             .unwrap_err()
             .to_string()
             .contains("Session not found"));
+
+        assert!(manager.encrypt_for("nonexistent", b"x").is_err());
     }
 
     #[test]
-    fn test_e2e_alice_to_bob_with_sessions() {
-        // Bob generates identity WITHOUT one-time prekeys (to avoid bundle consumption issues in tests)
-        let bob = Identity::generate(0);
-        let mut bob_mut = bob.clone();
-
-        // Bob saves his signed prekey secret
-        let bob_signed_prekey_secret = bob_mut
-            .prekey_pool()
-            .unwrap()
-            .signed_prekey()
-            .secret_bytes();
-
-        // Get Bob's prekey bundle (no one-time prekey)
-        let bob_bundle = bob_mut.prekey_pool_mut().unwrap().get_bundle();
+    fn test_sessions_survive_reload() {
+        let db = Database::in_memory().unwrap();
+        crate::storage::init_schema(&db).unwrap();
+        let storage_key = [7u8; 32];
 
-        // Alice initiates X3DH
-        let (alice_shared_secret, alice_ephemeral_pub) = X3DH::initiate(&bob_bundle).unwrap();
-
-        // Alice creates session with Bob
+        let (alice, bob) = alice_and_bob();
         let alice_manager = SessionManager::new();
-        alice_manager
-            .create_session(bob.peer_id().to_string(), alice_shared_secret)
-            .unwrap();
+        alice_manager.update_session(alice).unwrap();
 
-        // Bob responds to X3DH (no one-time prekey)
-        let bob_shared_secret =
-            X3DH::respond(&bob_signed_prekey_secret, None, &alice_ephemeral_pub).unwrap();
+        let bob_manager = SessionManager::with_storage(db.clone(), storage_key).unwrap();
+        bob_manager.update_session(bob).unwrap();
 
-        // Bob creates session with Alice
-        let bob_manager = SessionManager::new();
-        bob_manager
-            .create_session("alice".to_string(), bob_shared_secret)
-            .unwrap();
+        let (encrypted, _) = alice_manager.encrypt_for("bob", b"before restart").unwrap();
+        bob_manager.decrypt_from("alice", &encrypted).unwrap();
 
-        // Alice sends encrypted message to Bob
-        let alice_message = b"Secret message from Alice!";
-        let encrypted = alice_manager
-            .encrypt_for(&bob.peer_id().to_string(), alice_message)
-            .unwrap();
+        // "Restart": drop the manager and load the state back from SQLite
+        drop(bob_manager);
+        let bob_manager = SessionManager::with_storage(db.clone(), storage_key).unwrap();
+        assert!(bob_manager.has_session("alice").unwrap());
 
-        // Bob decrypts message from Alice
-        let decrypted = bob_manager.decrypt_from("alice", &encrypted).unwrap();
+        let (encrypted, _) = alice_manager.encrypt_for("bob", b"after restart").unwrap();
+        assert_eq!(
+            bob_manager.decrypt_from("alice", &encrypted).unwrap(),
+            b"after restart"
+        );
 
-        assert_eq!(alice_message, decrypted.as_slice());
+        let (reply, _) = bob_manager.encrypt_for("alice", b"reply").unwrap();
+        assert_eq!(alice_manager.decrypt_from("bob", &reply).unwrap(), b"reply");
     }
 
     #[test]
-    fn test_multiple_messages_in_session() {
-        let manager = SessionManager::new();
-        let shared_secret = [42u8; 32];
+    fn test_persisted_sessions_are_encrypted() {
+        let db = Database::in_memory().unwrap();
+        crate::storage::init_schema(&db).unwrap();
+
+        let (alice, _) = alice_and_bob();
+        let root_key = alice.ratchet.root_key;
+        let manager = SessionManager::with_storage(db.clone(), [7u8; 32]).unwrap();
+        manager.update_session(alice).unwrap();
+
+        let stored = db.list_sessions().unwrap();
+        assert_eq!(stored.len(), 1);
+        assert!(!stored[0]
+            .session_data
+            .windows(root_key.len())
+            .any(|w| w == root_key));
+
+        // A different storage key cannot read the sessions back
+        let other = SessionManager::with_storage(db.clone(), [8u8; 32]).unwrap();
+        assert_eq!(other.session_count().unwrap(), 0);
+
+        manager.remove_session("bob").unwrap();
+        assert!(db.list_sessions().unwrap().is_empty());
+    }
 
-        manager
-            .create_session("peer_123".to_string(), shared_secret)
-            .unwrap();
+    #[test]
+    fn test_multiple_messages_in_session() {
+        let (mut alice, mut bob) = alice_and_bob();
 
-        // Send 10 messages
+        // Interleave both directions to exercise the DH ratchet
         for i in 0..10 {
             let msg = format!("Message {}", i);
-            let encrypted = manager.encrypt_for("peer_123", msg.as_bytes()).unwrap();
-            let decrypted = manager.decrypt_from("peer_123", &encrypted).unwrap();
-            assert_eq!(msg.as_bytes(), decrypted.as_slice());
+            if i % 3 == 2 {
+                let encrypted = bob.encrypt(msg.as_bytes()).unwrap();
+                assert_eq!(alice.decrypt(&encrypted).unwrap(), msg.as_bytes());
+            } else {
+                let encrypted = alice.encrypt(msg.as_bytes()).unwrap();
+                assert_eq!(bob.decrypt(&encrypted).unwrap(), msg.as_bytes());
+            }
         }
 
-        // Check counters
-        let session = manager.get_session("peer_123").unwrap();
-        assert_eq!(session.send_counter, 10);
-        assert_eq!(session.recv_counter, 10);
+        assert_eq!(alice.send_counter + bob.send_counter, 10);
+        assert_eq!(alice.recv_counter + bob.recv_counter, 10);
     }
 }
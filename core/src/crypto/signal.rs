@@ -7,7 +7,7 @@
 //! Note: This is a simplified implementation for MVP. Production should use libsignal-protocol.
 
 use aes_gcm::{
-    aead::{Aead, KeyInit, OsRng as AeadOsRng},
+    aead::{Aead, KeyInit, OsRng as AeadOsRng, Payload},
     Aes256Gcm, Nonce,
 };
 use hkdf::Hkdf;
@@ -166,6 +166,52 @@ pub fn decrypt_message(encrypted: &EncryptedMessage, key: &[u8; 32]) -> Result<V
     Ok(plaintext)
 }
 
+/// Encrypt a message using AES-256-GCM, authenticating `aad` alongside it
+///
+/// Used by the Double Ratchet to bind the message header to the ciphertext.
+pub fn encrypt_message_with_aad(
+    plaintext: &[u8],
+    key: &[u8; 32],
+    aad: &[u8],
+) -> Result<EncryptedMessage> {
+    let cipher = Aes256Gcm::new(key.into());
+
+    let mut nonce_bytes = [0u8; 12];
+    rand::rngs::OsRng.fill_bytes(&mut nonce_bytes);
+    let nonce = Nonce::from_slice(&nonce_bytes);
+
+    let ciphertext = cipher
+        .encrypt(nonce, Payload { msg: plaintext, aad })
+        .map_err(|e| MePassaError::Crypto(format!("Encryption failed: {}", e)))?;
+
+    Ok(EncryptedMessage {
+        nonce: nonce_bytes,
+        ciphertext,
+    })
+}
+
+/// Decrypt a message using AES-256-GCM, verifying the associated data `aad`
+pub fn decrypt_message_with_aad(
+    encrypted: &EncryptedMessage,
+    key: &[u8; 32],
+    aad: &[u8],
+) -> Result<Vec<u8>> {
+    let cipher = Aes256Gcm::new(key.into());
+    let nonce = Nonce::from_slice(&encrypted.nonce);
+
+    let plaintext = cipher
+        .decrypt(
+            nonce,
+            Payload {
+                msg: encrypted.ciphertext.as_ref(),
+                aad,
+            },
+        )
+        .map_err(|e| MePassaError::Crypto(format!("Decryption failed: {}", e)))?;
+
+    Ok(plaintext)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -225,6 +271,21 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_encrypt_decrypt_with_aad() {
+        let key = [42u8; 32];
+        let plaintext = b"Header-bound message";
+
+        let encrypted = encrypt_message_with_aad(plaintext, &key, b"header-1").unwrap();
+
+        assert_eq!(
+            decrypt_message_with_aad(&encrypted, &key, b"header-1").unwrap(),
+            plaintext
+        );
+        // Tampered associated data must be rejected
+        assert!(decrypt_message_with_aad(&encrypted, &key, b"header-2").is_err());
+    }
+
     #[test]
     fn test_nonce_randomness() {
         let key = [42u8; 32];
@@ -5,13 +5,15 @@
 //! where the Client runs in a dedicated tokio task and receives commands via channels.
 
 use std::path::PathBuf;
-use std::sync::OnceLock;
+use std::sync::{Arc, Mutex, OnceLock};
 use tokio::sync::{mpsc, oneshot};
 
 use super::types::{
-    self as types, FfiConversation, FfiGroup, FfiMessage, FfiReaction, MePassaFfiError,
+    self as types, FfiConnectionQuality, FfiConversation, FfiGroup, FfiMessage, FfiReaction,
+    MePassaFfiError,
 };
-use crate::api::{Client, ClientBuilder};
+use crate::api::{Client, ClientBuilder, ClientEvent, FunctionCallback};
+use crate::identity::{BackupContents, KeyProtector, PassphraseProtector};
 
 use std::thread;
 use tokio::task::LocalSet;
@@ -49,6 +51,9 @@ where
 // Global client handle (initialized once)
 static CLIENT_HANDLE: OnceLock<ClientHandle> = OnceLock::new();
 
+// Held while the client starts, so concurrent constructors start it once
+static STARTING: Mutex<()> = Mutex::new(());
+
 /// Handle to communicate with the Client running in a dedicated task
 struct ClientHandle {
     sender: mpsc::UnboundedSender<ClientCommand>,
@@ -67,6 +72,17 @@ enum ClientCommand {
         prekey_bundle_json: String,
         response: oneshot::Sender<Result<(), MePassaFfiError>>,
     },
+    AccountPeerId {
+        response: oneshot::Sender<String>,
+    },
+    DeviceLinkRequest {
+        device_name: String,
+        response: oneshot::Sender<Result<String, MePassaFfiError>>,
+    },
+    LinkDevice {
+        payload: String,
+        response: oneshot::Sender<Result<String, MePassaFfiError>>,
+    },
     ListenOn {
         multiaddr: libp2p::Multiaddr,
         response: oneshot::Sender<Result<(), MePassaFfiError>>,
@@ -99,9 +115,31 @@ enum ClientCommand {
         peer_id: String,
         response: oneshot::Sender<Result<(), MePassaFfiError>>,
     },
+    SetConversationMuted {
+        peer_id: String,
+        muted: bool,
+        response: oneshot::Sender<Result<(), MePassaFfiError>>,
+    },
+    SetConversationArchived {
+        peer_id: String,
+        archived: bool,
+        response: oneshot::Sender<Result<(), MePassaFfiError>>,
+    },
+    RequestDeviceSync {
+        response: oneshot::Sender<Result<(), MePassaFfiError>>,
+    },
+    ExportBackup {
+        passphrase: String,
+        include_history: bool,
+        response: oneshot::Sender<Result<Vec<u8>, MePassaFfiError>>,
+    },
     ConnectedPeersCount {
         response: oneshot::Sender<Result<u32, MePassaFfiError>>,
     },
+    GetConnectionQuality {
+        peer_id: libp2p::PeerId,
+        response: oneshot::Sender<Result<FfiConnectionQuality, MePassaFfiError>>,
+    },
     ListeningAddresses {
         response: oneshot::Sender<Result<Vec<String>, MePassaFfiError>>,
     },
@@ -197,6 +235,11 @@ enum ClientCommand {
     GetGroups {
         response: oneshot::Sender<Result<Vec<FfiGroup>, MePassaFfiError>>,
     },
+    SendGroupMessage {
+        group_id: String,
+        content: String,
+        response: oneshot::Sender<Result<String, MePassaFfiError>>,
+    },
     // Media commands (FASE 16 - Mídia & Polimento)
     SendImageMessage {
         to_peer_id: String,
@@ -233,6 +276,12 @@ enum ClientCommand {
         media_hash: String,
         response: oneshot::Sender<Result<Vec<u8>, MePassaFfiError>>,
     },
+    RegisterMediaProgressCallback {
+        callback: Box<dyn crate::FfiMediaProgressCallback>,
+    },
+    AddEventListener {
+        listener: Box<dyn crate::MePassaEventListener>,
+    },
     GetConversationMedia {
         conversation_id: String,
         media_type: Option<types::FfiMediaType>,
@@ -301,6 +350,27 @@ async fn run_client_task_arc(
                     .map_err(|e| e.into());
                 let _ = response.send(result);
             }
+            ClientCommand::AccountPeerId { response } => {
+                let _ = response.send(client.account_peer_id().to_string());
+            }
+            ClientCommand::DeviceLinkRequest {
+                device_name,
+                response,
+            } => {
+                let result = client
+                    .device_link_request(&device_name)
+                    .await
+                    .map_err(|e| e.into());
+                let _ = response.send(result);
+            }
+            ClientCommand::LinkDevice { payload, response } => {
+                let result = client
+                    .link_device(&payload)
+                    .await
+                    .map(|device_peer_id| device_peer_id.to_string())
+                    .map_err(|e| e.into());
+                let _ = response.send(result);
+            }
             ClientCommand::ListenOn {
                 multiaddr,
                 response,
@@ -365,10 +435,49 @@ async fn run_client_task_arc(
                     .map_err(|e| e.into());
                 let _ = response.send(result);
             }
+            ClientCommand::SetConversationMuted {
+                peer_id,
+                muted,
+                response,
+            } => {
+                let result = client
+                    .set_conversation_muted(&peer_id, muted)
+                    .map_err(|e| e.into());
+                let _ = response.send(result);
+            }
+            ClientCommand::SetConversationArchived {
+                peer_id,
+                archived,
+                response,
+            } => {
+                let result = client
+                    .set_conversation_archived(&peer_id, archived)
+                    .map_err(|e| e.into());
+                let _ = response.send(result);
+            }
+            ClientCommand::RequestDeviceSync { response } => {
+                let result = client.request_device_sync().map_err(|e| e.into());
+                let _ = response.send(result);
+            }
+            ClientCommand::ExportBackup {
+                passphrase,
+                include_history,
+                response,
+            } => {
+                let result = client
+                    .export_backup(&passphrase, include_history)
+                    .await
+                    .map_err(|e| e.into());
+                let _ = response.send(result);
+            }
             ClientCommand::ConnectedPeersCount { response } => {
                 let result = Ok(client.connected_peers_count().await as u32);
                 let _ = response.send(result);
             }
+            ClientCommand::GetConnectionQuality { peer_id, response } => {
+                let result = Ok(client.connection_quality(&peer_id).await.into());
+                let _ = response.send(result);
+            }
             ClientCommand::ListeningAddresses { response } => {
                 let result = Ok(client.listening_addresses().await);
                 let _ = response.send(result);
@@ -520,6 +629,17 @@ async fn run_client_task_arc(
                     .map_err(|e| e.into());
                 let _ = response.send(result);
             }
+            ClientCommand::SendGroupMessage {
+                group_id,
+                content,
+                response,
+            } => {
+                let result = client
+                    .send_group_message(group_id, content)
+                    .await
+                    .map_err(|e| e.into());
+                let _ = response.send(result);
+            }
             // Media command handlers (FASE 16)
             ClientCommand::SendImageMessage {
                 to_peer_id,
@@ -634,6 +754,29 @@ async fn run_client_task_arc(
                     .map_err(|e| e.into());
                 let _ = response.send(result);
             }
+            ClientCommand::AddEventListener { listener } => {
+                client.register_callback(listener).await;
+            }
+            ClientCommand::RegisterMediaProgressCallback { callback } => {
+                client
+                    .register_callback(FunctionCallback::new(move |event| {
+                        if let ClientEvent::MediaProgress {
+                            message_id,
+                            media_hash,
+                            bytes_received,
+                            total_bytes,
+                        } = event
+                        {
+                            callback.on_media_progress(
+                                message_id,
+                                media_hash,
+                                bytes_received,
+                                total_bytes,
+                            );
+                        }
+                    }))
+                    .await;
+            }
             ClientCommand::GetConversationMedia {
                 conversation_id,
                 media_type,
@@ -729,9 +872,50 @@ pub struct MePassaClient {
 impl MePassaClient {
     /// Create new client and initialize the global client task
     pub fn new(data_dir: String) -> Result<Self, MePassaFfiError> {
+        Self::start(data_dir, None, None)
+    }
+
+    /// Create the client with the identity protected by a passphrase
+    pub fn with_passphrase(data_dir: String, passphrase: String) -> Result<Self, MePassaFfiError> {
+        Self::start(data_dir, None, Some(Arc::new(PassphraseProtector::new(passphrase))))
+    }
+
+    /// Create the client with the identity protected by the platform keystore
+    pub fn with_keystore(
+        data_dir: String,
+        keystore: Box<dyn crate::FfiKeyProtector>,
+    ) -> Result<Self, MePassaFfiError> {
+        Self::start(data_dir, None, Some(Arc::new(keystore)))
+    }
+
+    /// Create the client from a backup made with `export_backup`, keeping
+    /// its peer id
+    pub fn restore_from_backup(
+        data_dir: String,
+        backup: Vec<u8>,
+        passphrase: String,
+    ) -> Result<Self, MePassaFfiError> {
+        if CLIENT_HANDLE.get().is_some() {
+            return Err(MePassaFfiError::Other {
+                details: "Client already running".to_string(),
+            });
+        }
+        // Opened here so a wrong passphrase is reported to the caller
+        let contents = BackupContents::open(&backup, &passphrase)?;
+        Self::start(data_dir, Some(contents), None)
+    }
+
+    fn start(
+        data_dir: String,
+        restore: Option<BackupContents>,
+        protector: Option<Arc<dyn KeyProtector>>,
+    ) -> Result<Self, MePassaFfiError> {
         // Initialize the client task if not already done
-        CLIENT_HANDLE.get_or_init(|| {
+        let _starting = STARTING.lock().unwrap_or_else(|e| e.into_inner());
+        if CLIENT_HANDLE.get().is_none() {
             let (sender, receiver) = mpsc::unbounded_channel();
+            // Reports whether the client could be built (e.g. wrong passphrase)
+            let (ready_tx, ready_rx) = std::sync::mpsc::channel();
             let data_dir_clone = data_dir.clone();
 
             // Spawn a dedicated thread with LocalSet for !Send Client
@@ -743,6 +927,12 @@ impl MePassaClient {
                 local.block_on(rt, async move {
                     let mut builder = ClientBuilder::new()
                         .data_dir(PathBuf::from(&data_dir_clone));
+                    if let Some(contents) = restore {
+                        builder = builder.restore_from_contents(contents);
+                    }
+                    if let Some(protector) = protector {
+                        builder = builder.key_protector(protector);
+                    }
 
                     // Add default bootstrap peers (IPFS public nodes)
                     let bootstrap_peers = vec![
@@ -761,7 +951,14 @@ impl MePassaClient {
                         }
                     }
 
-                    let client = std::sync::Arc::new(builder.build().await.expect("Failed to build client"));
+                    let client = match builder.build().await {
+                        Ok(client) => std::sync::Arc::new(client),
+                        Err(e) => {
+                            let _ = ready_tx.send(Err(MePassaFfiError::from(e)));
+                            return;
+                        }
+                    };
+                    let _ = ready_tx.send(Ok(()));
                     let client_for_network = std::sync::Arc::clone(&client);
 
                     // Spawn network event loop task using non-blocking polling
@@ -792,6 +989,18 @@ impl MePassaClient {
                         }
                     });
 
+                    // Retry queued outgoing messages (survives restarts via the outbox table)
+                    let client_for_outbox = std::sync::Arc::clone(&client);
+                    let outbox_handle = tokio::task::spawn_local(async move {
+                        client_for_outbox.run_outbox().await;
+                    });
+
+                    // Rotate the signed prekey and keep the directory stocked
+                    let client_for_prekeys = std::sync::Arc::clone(&client);
+                    let prekeys_handle = tokio::task::spawn_local(async move {
+                        client_for_prekeys.run_prekey_maintenance().await;
+                    });
+
                     // Run client command task (processes API commands)
                     // Note: We use Arc<Client> but run_client_task expects Client
                     // We need to keep client alive for the network task
@@ -802,12 +1011,21 @@ impl MePassaClient {
                         _ = network_handle => {
                             tracing::info!("Network event loop completed");
                         }
+                        _ = outbox_handle => {
+                            tracing::info!("Outbox task completed");
+                        }
+                        _ = prekeys_handle => {
+                            tracing::info!("Prekey maintenance task completed");
+                        }
                     }
                 });
             });
 
-            ClientHandle { sender }
-        });
+            ready_rx.recv().map_err(|_| MePassaFfiError::Other {
+                details: "Client task stopped during startup".to_string(),
+            })??;
+            let _ = CLIENT_HANDLE.set(ClientHandle { sender });
+        }
 
         Ok(Self { data_dir })
     }
@@ -870,6 +1088,57 @@ impl MePassaClient {
         })?
     }
 
+    /// Peer ID of our account (the primary device's)
+    pub fn account_peer_id(&self) -> Result<String, MePassaFfiError> {
+        let (tx, rx) = oneshot::channel();
+        self.handle()
+            .sender
+            .send(ClientCommand::AccountPeerId { response: tx })
+            .map_err(|_| MePassaFfiError::Other {
+                details: "Failed to send command".to_string(),
+            })?;
+
+        execute_future(rx).map_err(|_| MePassaFfiError::Other {
+            details: "Failed to receive response".to_string(),
+        })
+    }
+
+    /// Start linking this device; returns the QR payload for the primary device
+    pub async fn device_link_request(&self, device_name: String) -> Result<String, MePassaFfiError> {
+        let (tx, rx) = oneshot::channel();
+        self.handle()
+            .sender
+            .send(ClientCommand::DeviceLinkRequest {
+                device_name,
+                response: tx,
+            })
+            .map_err(|_| MePassaFfiError::Other {
+                details: "Failed to send command".to_string(),
+            })?;
+
+        rx.await.map_err(|_| MePassaFfiError::Other {
+            details: "Failed to receive response".to_string(),
+        })?
+    }
+
+    /// Link the device that showed `payload`; returns its Peer ID
+    pub async fn link_device(&self, payload: String) -> Result<String, MePassaFfiError> {
+        let (tx, rx) = oneshot::channel();
+        self.handle()
+            .sender
+            .send(ClientCommand::LinkDevice {
+                payload,
+                response: tx,
+            })
+            .map_err(|_| MePassaFfiError::Other {
+                details: "Failed to send command".to_string(),
+            })?;
+
+        rx.await.map_err(|_| MePassaFfiError::Other {
+            details: "Failed to receive response".to_string(),
+        })?
+    }
+
     /// Start listening on an address
     pub async fn listen_on(&self, multiaddr: String) -> Result<(), MePassaFfiError> {
         let addr: libp2p::Multiaddr = multiaddr.parse().map_err(|_| MePassaFfiError::Network {
@@ -1031,6 +1300,87 @@ impl MePassaClient {
         })?
     }
 
+    /// Mute or unmute the conversation with a peer (on all our devices)
+    pub fn set_conversation_muted(&self, peer_id: String, muted: bool) -> Result<(), MePassaFfiError> {
+        let (tx, rx) = oneshot::channel();
+        self.handle()
+            .sender
+            .send(ClientCommand::SetConversationMuted {
+                peer_id,
+                muted,
+                response: tx,
+            })
+            .map_err(|_| MePassaFfiError::Other {
+                details: "Failed to send command".to_string(),
+            })?;
+
+        execute_future(rx).map_err(|_| MePassaFfiError::Other {
+            details: "Failed to receive response".to_string(),
+        })?
+    }
+
+    /// Archive or unarchive the conversation with a peer (on all our devices)
+    pub fn set_conversation_archived(
+        &self,
+        peer_id: String,
+        archived: bool,
+    ) -> Result<(), MePassaFfiError> {
+        let (tx, rx) = oneshot::channel();
+        self.handle()
+            .sender
+            .send(ClientCommand::SetConversationArchived {
+                peer_id,
+                archived,
+                response: tx,
+            })
+            .map_err(|_| MePassaFfiError::Other {
+                details: "Failed to send command".to_string(),
+            })?;
+
+        execute_future(rx).map_err(|_| MePassaFfiError::Other {
+            details: "Failed to receive response".to_string(),
+        })?
+    }
+
+    /// Ask our other devices for the changes this one missed
+    pub fn request_device_sync(&self) -> Result<(), MePassaFfiError> {
+        let (tx, rx) = oneshot::channel();
+        self.handle()
+            .sender
+            .send(ClientCommand::RequestDeviceSync { response: tx })
+            .map_err(|_| MePassaFfiError::Other {
+                details: "Failed to send command".to_string(),
+            })?;
+
+        execute_future(rx).map_err(|_| MePassaFfiError::Other {
+            details: "Failed to receive response".to_string(),
+        })?
+    }
+
+    /// Export our identity, prekeys, contacts and (optionally) history,
+    /// sealed with `passphrase`
+    pub async fn export_backup(
+        &self,
+        passphrase: String,
+        include_history: bool,
+    ) -> Result<Vec<u8>, MePassaFfiError> {
+        let (tx, rx) = oneshot::channel();
+        self.handle()
+            .sender
+            .send(ClientCommand::ExportBackup {
+                passphrase,
+                include_history,
+                response: tx,
+            })
+            .map_err(|_| MePassaFfiError::Other {
+                details: "Failed to send command".to_string(),
+            })?;
+
+        rx.await.map_err(|_| MePassaFfiError::Other {
+            details: "Failed to receive response".to_string(),
+        })?
+    }
+
     /// Get connected peers count
     pub async fn connected_peers_count(&self) -> Result<u32, MePassaFfiError> {
         let (tx, rx) = oneshot::channel();
@@ -1046,6 +1396,31 @@ impl MePassaClient {
         })?
     }
 
+    /// Get connection state, type and ping RTT for a peer (e.g. to show a "relayed" indicator)
+    pub async fn get_connection_quality(
+        &self,
+        peer_id: String,
+    ) -> Result<FfiConnectionQuality, MePassaFfiError> {
+        let peer_id: libp2p::PeerId = peer_id.parse().map_err(|_| MePassaFfiError::Network {
+            details: "Invalid peer ID".to_string(),
+        })?;
+
+        let (tx, rx) = oneshot::channel();
+        self.handle()
+            .sender
+            .send(ClientCommand::GetConnectionQuality {
+                peer_id,
+                response: tx,
+            })
+            .map_err(|_| MePassaFfiError::Other {
+                details: "Failed to send command".to_string(),
+            })?;
+
+        rx.await.map_err(|_| MePassaFfiError::Other {
+            details: "Failed to receive response".to_string(),
+        })?
+    }
+
     /// Get current listening addresses
     pub async fn listening_addresses(&self) -> Result<Vec<String>, MePassaFfiError> {
         let (tx, rx) = oneshot::channel();
@@ -1544,6 +1919,29 @@ impl MePassaClient {
         })?
     }
 
+    /// Send a text message to a group
+    pub async fn send_group_message(
+        &self,
+        group_id: String,
+        content: String,
+    ) -> Result<String, MePassaFfiError> {
+        let (tx, rx) = oneshot::channel();
+        self.handle()
+            .sender
+            .send(ClientCommand::SendGroupMessage {
+                group_id,
+                content,
+                response: tx,
+            })
+            .map_err(|_| MePassaFfiError::Other {
+                details: "Failed to send command".to_string(),
+            })?;
+
+        rx.await.map_err(|_| MePassaFfiError::Other {
+            details: "Failed to receive response".to_string(),
+        })?
+    }
+
     // ═════════════════════════════════════════════════════════════════════
     // Media Methods (FASE 16 - Mídia & Polimento)
     // ═════════════════════════════════════════════════════════════════════
@@ -1680,6 +2078,35 @@ impl MePassaClient {
         })?
     }
 
+    /// Register a callback for media download progress
+    ///
+    /// Invoked on a background thread each time a download persists more
+    /// bytes, so the apps can draw progress bars for large files.
+    pub fn register_media_progress_callback(
+        &self,
+        callback: Box<dyn crate::FfiMediaProgressCallback>,
+    ) -> Result<(), MePassaFfiError> {
+        self.handle()
+            .sender
+            .send(ClientCommand::RegisterMediaProgressCallback { callback })
+            .map_err(|_| MePassaFfiError::Other {
+                details: "Failed to send command".to_string(),
+            })
+    }
+
+    /// Receive every client event (messages, peers, groups, calls), in order
+    pub fn add_event_listener(
+        &self,
+        listener: Box<dyn crate::MePassaEventListener>,
+    ) -> Result<(), MePassaFfiError> {
+        self.handle()
+            .sender
+            .send(ClientCommand::AddEventListener { listener })
+            .map_err(|_| MePassaFfiError::Other {
+                details: "Failed to send command".to_string(),
+            })
+    }
+
     /// Get media for a conversation
     pub fn get_conversation_media(
         &self,
@@ -20,6 +20,9 @@ pub enum MePassaFfiError {
     #[error("Protocol error: {details}")]
     Protocol { details: String },
 
+    #[error("Encryption required: {details}")]
+    EncryptionRequired { details: String },
+
     #[error("IO error: {details}")]
     Io { details: String },
 
@@ -27,6 +30,15 @@ pub enum MePassaFfiError {
     Other { details: String },
 }
 
+/// Errors thrown by app callbacks outside the declared ones
+impl From<uniffi::UnexpectedUniFFICallbackError> for MePassaFfiError {
+    fn from(err: uniffi::UnexpectedUniFFICallbackError) -> Self {
+        MePassaFfiError::Other {
+            details: format!("Callback failed: {}", err.reason),
+        }
+    }
+}
+
 impl From<crate::utils::error::MePassaError> for MePassaFfiError {
     fn from(err: crate::utils::error::MePassaError) -> Self {
         match err {
@@ -34,6 +46,10 @@ impl From<crate::utils::error::MePassaError> for MePassaFfiError {
                 MePassaFfiError::Identity { details: s }
             }
             crate::utils::error::MePassaError::Crypto(s) => MePassaFfiError::Crypto { details: s },
+            e @ (crate::utils::error::MePassaError::PreKeyMissing(_)
+            | crate::utils::error::MePassaError::SignedPreKeyMissing(_)) => {
+                MePassaFfiError::Crypto { details: e.to_string() }
+            }
             crate::utils::error::MePassaError::Network(s) => {
                 MePassaFfiError::Network { details: s }
             }
@@ -43,6 +59,9 @@ impl From<crate::utils::error::MePassaError> for MePassaFfiError {
             crate::utils::error::MePassaError::Protocol(s) => {
                 MePassaFfiError::Protocol { details: s }
             }
+            crate::utils::error::MePassaError::EncryptionRequired(s) => {
+                MePassaFfiError::EncryptionRequired { details: s }
+            }
             crate::utils::error::MePassaError::NotFound(s) => {
                 MePassaFfiError::Other { details: format!("Not found: {}", s) }
             }
@@ -550,6 +569,81 @@ impl From<FfiGroupRole> for InternalGroupRole {
     }
 }
 
+// ═══════════════════════════════════════════════════════════════════════════
+// Connection quality
+// ═══════════════════════════════════════════════════════════════════════════
+
+use crate::network::{
+    ConnectionQuality as InternalConnectionQuality, ConnectionState as InternalConnectionState,
+    ConnectionType as InternalConnectionType,
+};
+
+/// FFI-safe connection state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FfiConnectionState {
+    Disconnected,
+    AttemptingDirect,
+    AttemptingHolePunch,
+    AttemptingRelay,
+    Connected,
+}
+
+impl From<&InternalConnectionState> for FfiConnectionState {
+    fn from(state: &InternalConnectionState) -> Self {
+        match state {
+            InternalConnectionState::Disconnected => FfiConnectionState::Disconnected,
+            InternalConnectionState::AttemptingDirect { .. } => {
+                FfiConnectionState::AttemptingDirect
+            }
+            InternalConnectionState::AttemptingHolePunch { .. } => {
+                FfiConnectionState::AttemptingHolePunch
+            }
+            InternalConnectionState::AttemptingRelay { .. } => FfiConnectionState::AttemptingRelay,
+            InternalConnectionState::Connected(_) => FfiConnectionState::Connected,
+        }
+    }
+}
+
+/// FFI-safe connection type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FfiConnectionType {
+    Direct,
+    HolePunch,
+    Relayed,
+}
+
+impl From<InternalConnectionType> for FfiConnectionType {
+    fn from(connection_type: InternalConnectionType) -> Self {
+        match connection_type {
+            InternalConnectionType::Direct => FfiConnectionType::Direct,
+            InternalConnectionType::HolePunch => FfiConnectionType::HolePunch,
+            InternalConnectionType::Relayed => FfiConnectionType::Relayed,
+        }
+    }
+}
+
+/// FFI-safe connection quality for a peer
+#[derive(Debug, Clone)]
+pub struct FfiConnectionQuality {
+    pub state: FfiConnectionState,
+    pub connection_type: Option<FfiConnectionType>,
+    pub rtt_ms: Option<u32>,
+    pub open_connections: u32,
+    pub is_relayed: bool,
+}
+
+impl From<InternalConnectionQuality> for FfiConnectionQuality {
+    fn from(quality: InternalConnectionQuality) -> Self {
+        Self {
+            state: (&quality.state).into(),
+            connection_type: quality.connection_type.map(Into::into),
+            rtt_ms: quality.rtt.map(|rtt| rtt.as_millis().min(u32::MAX as u128) as u32),
+            open_connections: quality.open_connections as u32,
+            is_relayed: quality.is_relayed(),
+        }
+    }
+}
+
 // ═══════════════════════════════════════════════════════════════════════════
 // Media types (FASE 16 - Mídia & Polimento)
 // ═══════════════════════════════════════════════════════════════════════════
@@ -653,3 +747,342 @@ impl From<crate::storage::Reaction> for FfiReaction {
         }
     }
 }
+
+// ═══════════════════════════════════════════════════════════════════════════
+// Client events
+// ═══════════════════════════════════════════════════════════════════════════
+
+use crate::api::{ClientEvent, EventCallback};
+use crate::network::Reachability;
+use crate::protocol::pb::message::Payload;
+
+/// FFI-safe reachability
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FfiReachability {
+    Unknown,
+    Public,
+    Private,
+}
+
+/// FFI-safe client event (see `ClientEvent`)
+///
+/// Peer IDs are base58 strings. Message payloads other than text are not
+/// carried; fetch the message by ID instead.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FfiClientEvent {
+    MessageReceived {
+        message_id: String,
+        from_peer_id: String,
+        text: Option<String>,
+    },
+    MessageQueued {
+        message_id: String,
+        to_peer_id: String,
+    },
+    MessageSent {
+        message_id: String,
+        to_peer_id: String,
+    },
+    PlaintextFallback {
+        message_id: String,
+        to_peer_id: String,
+        reason: String,
+    },
+    MessageDelivered {
+        message_id: String,
+        to_peer_id: String,
+    },
+    MessageFailed {
+        message_id: String,
+        to_peer_id: String,
+        reason: String,
+    },
+    MessageRead {
+        message_id: String,
+        by_peer_id: String,
+        read_at: i64,
+    },
+    TypingStarted {
+        peer_id: String,
+    },
+    TypingStopped {
+        peer_id: String,
+    },
+    MediaProgress {
+        message_id: String,
+        media_hash: String,
+        bytes_received: u64,
+        total_bytes: u64,
+    },
+    PeerConnected {
+        peer_id: String,
+    },
+    PeerDisconnected {
+        peer_id: String,
+    },
+    PeerDiscovered {
+        peer_id: String,
+        addresses: Vec<String>,
+    },
+    NetworkOnline,
+    NetworkOffline,
+    ReachabilityChanged {
+        reachability: FfiReachability,
+        public_address: Option<String>,
+    },
+    GroupCreated {
+        group_id: String,
+        name: String,
+    },
+    GroupJoined {
+        group_id: String,
+    },
+    GroupLeft {
+        group_id: String,
+    },
+    GroupUpdated {
+        group_id: String,
+        name: String,
+    },
+    GroupMemberAdded {
+        group_id: String,
+        peer_id: String,
+    },
+    GroupMemberRemoved {
+        group_id: String,
+        peer_id: String,
+    },
+    GroupMessageReceived {
+        group_id: String,
+        message_id: String,
+        from_peer_id: String,
+        content: Vec<u8>,
+        timestamp: i64,
+    },
+    GroupMessageRejected {
+        group_id: String,
+        message_id: String,
+        from_peer_id: String,
+        reason: String,
+    },
+    DeviceLinked {
+        account_peer_id: String,
+        device_peer_id: String,
+    },
+    DeviceSynced {
+        device_peer_id: String,
+        messages: u32,
+        changes: u32,
+    },
+    IncomingCall {
+        call_id: String,
+        from_peer_id: String,
+    },
+    CallStateChanged {
+        call_id: String,
+        state: String,
+    },
+    CallEnded {
+        call_id: String,
+        reason: String,
+    },
+    Error {
+        error: String,
+    },
+}
+
+impl From<ClientEvent> for FfiClientEvent {
+    fn from(event: ClientEvent) -> Self {
+        match event {
+            ClientEvent::MessageReceived {
+                message_id,
+                from,
+                message,
+            } => FfiClientEvent::MessageReceived {
+                message_id,
+                from_peer_id: from.to_string(),
+                text: match message.payload {
+                    Some(Payload::Text(text)) => Some(text.content),
+                    _ => None,
+                },
+            },
+            ClientEvent::MessageQueued { message_id, to } => FfiClientEvent::MessageQueued {
+                message_id,
+                to_peer_id: to.to_string(),
+            },
+            ClientEvent::MessageSent { message_id, to } => FfiClientEvent::MessageSent {
+                message_id,
+                to_peer_id: to.to_string(),
+            },
+            ClientEvent::PlaintextFallback {
+                message_id,
+                to,
+                reason,
+            } => FfiClientEvent::PlaintextFallback {
+                message_id,
+                to_peer_id: to.to_string(),
+                reason,
+            },
+            ClientEvent::MessageDelivered { message_id, to } => FfiClientEvent::MessageDelivered {
+                message_id,
+                to_peer_id: to.to_string(),
+            },
+            ClientEvent::MessageFailed {
+                message_id,
+                to,
+                reason,
+            } => FfiClientEvent::MessageFailed {
+                message_id,
+                to_peer_id: to.to_string(),
+                reason,
+            },
+            ClientEvent::MessageRead {
+                message_id,
+                by,
+                read_at,
+            } => FfiClientEvent::MessageRead {
+                message_id,
+                by_peer_id: by.to_string(),
+                read_at,
+            },
+            ClientEvent::TypingStarted { peer_id } => FfiClientEvent::TypingStarted {
+                peer_id: peer_id.to_string(),
+            },
+            ClientEvent::TypingStopped { peer_id } => FfiClientEvent::TypingStopped {
+                peer_id: peer_id.to_string(),
+            },
+            ClientEvent::MediaProgress {
+                message_id,
+                media_hash,
+                bytes_received,
+                total_bytes,
+            } => FfiClientEvent::MediaProgress {
+                message_id,
+                media_hash,
+                bytes_received,
+                total_bytes,
+            },
+            ClientEvent::PeerConnected { peer_id } => FfiClientEvent::PeerConnected {
+                peer_id: peer_id.to_string(),
+            },
+            ClientEvent::PeerDisconnected { peer_id } => FfiClientEvent::PeerDisconnected {
+                peer_id: peer_id.to_string(),
+            },
+            ClientEvent::PeerDiscovered { peer_id, addresses } => FfiClientEvent::PeerDiscovered {
+                peer_id: peer_id.to_string(),
+                addresses,
+            },
+            ClientEvent::NetworkOnline => FfiClientEvent::NetworkOnline,
+            ClientEvent::NetworkOffline => FfiClientEvent::NetworkOffline,
+            ClientEvent::ReachabilityChanged { reachability } => match reachability {
+                Reachability::Unknown => FfiClientEvent::ReachabilityChanged {
+                    reachability: FfiReachability::Unknown,
+                    public_address: None,
+                },
+                Reachability::Public(addr) => FfiClientEvent::ReachabilityChanged {
+                    reachability: FfiReachability::Public,
+                    public_address: Some(addr.to_string()),
+                },
+                Reachability::Private => FfiClientEvent::ReachabilityChanged {
+                    reachability: FfiReachability::Private,
+                    public_address: None,
+                },
+            },
+            ClientEvent::GroupCreated { group_id, name } => {
+                FfiClientEvent::GroupCreated { group_id, name }
+            }
+            ClientEvent::GroupJoined { group_id } => FfiClientEvent::GroupJoined { group_id },
+            ClientEvent::GroupLeft { group_id } => FfiClientEvent::GroupLeft { group_id },
+            ClientEvent::GroupUpdated { group_id, name } => {
+                FfiClientEvent::GroupUpdated { group_id, name }
+            }
+            ClientEvent::GroupMemberAdded { group_id, peer_id } => {
+                FfiClientEvent::GroupMemberAdded {
+                    group_id,
+                    peer_id: peer_id.to_string(),
+                }
+            }
+            ClientEvent::GroupMemberRemoved { group_id, peer_id } => {
+                FfiClientEvent::GroupMemberRemoved {
+                    group_id,
+                    peer_id: peer_id.to_string(),
+                }
+            }
+            ClientEvent::GroupMessageReceived {
+                group_id,
+                message_id,
+                from,
+                content,
+                timestamp,
+            } => FfiClientEvent::GroupMessageReceived {
+                group_id,
+                message_id,
+                from_peer_id: from.to_string(),
+                content,
+                timestamp,
+            },
+            ClientEvent::GroupMessageRejected {
+                group_id,
+                message_id,
+                from,
+                reason,
+            } => FfiClientEvent::GroupMessageRejected {
+                group_id,
+                message_id,
+                from_peer_id: from.to_string(),
+                reason,
+            },
+            ClientEvent::DeviceLinked {
+                account_peer_id,
+                device_peer_id,
+            } => FfiClientEvent::DeviceLinked {
+                account_peer_id: account_peer_id.to_string(),
+                device_peer_id: device_peer_id.to_string(),
+            },
+            ClientEvent::DeviceSynced {
+                device_peer_id,
+                messages,
+                changes,
+            } => FfiClientEvent::DeviceSynced {
+                device_peer_id: device_peer_id.to_string(),
+                messages,
+                changes,
+            },
+            ClientEvent::IncomingCall { call_id, from } => FfiClientEvent::IncomingCall {
+                call_id,
+                from_peer_id: from.to_string(),
+            },
+            ClientEvent::CallStateChanged { call_id, state } => {
+                FfiClientEvent::CallStateChanged { call_id, state }
+            }
+            ClientEvent::CallEnded { call_id, reason } => {
+                FfiClientEvent::CallEnded { call_id, reason }
+            }
+            ClientEvent::Error { error } => FfiClientEvent::Error { error },
+        }
+    }
+}
+
+/// Deliver bus events to an app listener
+impl EventCallback for Box<dyn crate::MePassaEventListener> {
+    fn on_event(&self, event: ClientEvent) {
+        crate::MePassaEventListener::on_event(self.as_ref(), event.into());
+    }
+}
+
+/// Wrap identity secrets with the app's platform keystore
+impl crate::identity::KeyProtector for Box<dyn crate::FfiKeyProtector> {
+    fn kind(&self) -> &str {
+        "keystore"
+    }
+
+    fn wrap_key(&self, key: &[u8]) -> crate::utils::error::Result<Vec<u8>> {
+        crate::FfiKeyProtector::wrap_key(self.as_ref(), key.to_vec())
+            .map_err(|e| crate::utils::error::MePassaError::Crypto(format!("Keystore failed: {}", e)))
+    }
+
+    fn unwrap_key(&self, wrapped: &[u8]) -> crate::utils::error::Result<Vec<u8>> {
+        crate::FfiKeyProtector::unwrap_key(self.as_ref(), wrapped.to_vec())
+            .map_err(|e| crate::utils::error::MePassaError::Crypto(format!("Keystore failed: {}", e)))
+    }
+}
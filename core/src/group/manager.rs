@@ -2,13 +2,17 @@
 //!
 //! Manages group chat functionality using GossipSub.
 
+use super::sender_keys::{SenderKey, SenderKeyStore};
 use super::storage;
-use super::types::{Group, GroupEvent, GroupMessage, GroupRole};
-use crate::storage::Database;
+use super::types::{AdminAction, Group, GroupEvent, GroupMessage, GroupMessageType, GroupRole};
+use crate::crypto::encrypt_for_storage;
+use crate::identity::{Keypair, PublicKey};
+use crate::protocol::{GroupSnapshot, SenderKeyDistribution};
+use crate::storage::{Database, MessageStatus, NewMessage};
 use crate::utils::error::{MePassaError, Result};
 use libp2p::gossipsub::{self, TopicHash};
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use tokio::sync::{mpsc, RwLock};
 
 /// Group Manager
@@ -30,14 +34,36 @@ pub struct GroupManager {
     /// Event receiver (consumed by client)
     event_rx: Arc<RwLock<Option<mpsc::UnboundedReceiver<GroupEvent>>>>,
 
+    /// Additional event subscribers (e.g. the client event bus)
+    subscribers: Arc<Mutex<Vec<mpsc::UnboundedSender<GroupEvent>>>>,
+
     /// GossipSub topics we're subscribed to
     subscribed_topics: Arc<RwLock<HashMap<String, TopicHash>>>,
+
+    /// Identity keypair (signs outgoing group messages)
+    keypair: Keypair,
+
+    /// Storage encryption key (for persisted message content)
+    storage_key: [u8; 32],
+
+    /// Sender keys (ours and the ones distributed by other members)
+    sender_keys: Arc<RwLock<SenderKeyStore>>,
+
+    /// Sender key distributions waiting to be sent over pairwise E2E sessions
+    /// (recipient peer ID, distribution)
+    outbox: Arc<RwLock<Vec<(String, SenderKeyDistribution)>>>,
 }
 
 impl GroupManager {
     /// Create a new GroupManager
-    pub fn new(local_peer_id: String, db: Arc<Database>) -> Result<Self> {
+    pub fn new(
+        local_peer_id: String,
+        db: Arc<Database>,
+        keypair: Keypair,
+        storage_key: [u8; 32],
+    ) -> Result<Self> {
         let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let sender_keys = SenderKeyStore::with_storage((*db).clone(), storage_key)?;
 
         let manager = Self {
             local_peer_id,
@@ -45,7 +71,12 @@ impl GroupManager {
             groups: Arc::new(RwLock::new(HashMap::new())),
             event_tx,
             event_rx: Arc::new(RwLock::new(Some(event_rx))),
+            subscribers: Arc::new(Mutex::new(Vec::new())),
             subscribed_topics: Arc::new(RwLock::new(HashMap::new())),
+            keypair,
+            storage_key,
+            sender_keys: Arc::new(RwLock::new(sender_keys)),
+            outbox: Arc::new(RwLock::new(Vec::new())),
         };
 
         Ok(manager)
@@ -67,6 +98,7 @@ impl GroupManager {
                 .await
                 .insert(group.id.clone(), topic_hash);
 
+            self.ensure_own_sender_key(&group.id).await?;
             groups_map.insert(group.id.clone(), group);
         }
 
@@ -78,6 +110,16 @@ impl GroupManager {
         self.event_rx.write().await.take()
     }
 
+    /// Receive all future group events (alongside the taken receiver)
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<GroupEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.subscribers
+            .lock()
+            .expect("group subscribers lock poisoned")
+            .push(tx);
+        rx
+    }
+
     /// Create a new group
     pub async fn create_group(
         &self,
@@ -100,6 +142,7 @@ impl GroupManager {
 
         // Store in memory
         self.groups.write().await.insert(group_id.clone(), group.clone());
+        self.ensure_own_sender_key(&group_id).await?;
 
         // Get topic hash
         let topic_hash = group.topic_hash().hash();
@@ -117,7 +160,15 @@ impl GroupManager {
     }
 
     /// Join an existing group (invited by admin)
+    ///
+    /// If the admin's invitation already arrived, the group is known and only
+    /// its topic is returned. Otherwise a placeholder entry is created that the
+    /// invitation will fill in.
     pub async fn join_group(&self, group_id: String, group_name: String) -> Result<TopicHash> {
+        if let Some(group) = self.groups.read().await.get(&group_id) {
+            return Ok(group.topic_hash().hash());
+        }
+
         // Create group entry (we'll receive metadata via group messages)
         let group = Group::new(
             group_id.clone(),
@@ -131,6 +182,7 @@ impl GroupManager {
 
         // Store in memory
         self.groups.write().await.insert(group_id.clone(), group.clone());
+        self.ensure_own_sender_key(&group_id).await?;
 
         // Get topic hash
         let topic_hash = group.topic_hash().hash();
@@ -146,25 +198,31 @@ impl GroupManager {
     }
 
     /// Leave a group
-    pub async fn leave_group(&self, group_id: &str) -> Result<()> {
+    ///
+    /// Returns the topic to unsubscribe from (if we were subscribed).
+    pub async fn leave_group(&self, group_id: &str) -> Result<Option<TopicHash>> {
         // Mark as left in database
         storage::mark_group_left(&self.db, group_id)?;
 
         // Remove from memory
         self.groups.write().await.remove(group_id);
+        self.sender_keys.write().await.remove_group(group_id)?;
 
         // Unsubscribe from topic
-        self.subscribed_topics.write().await.remove(group_id);
+        let topic_hash = self.subscribed_topics.write().await.remove(group_id);
 
         // Emit event
         self.emit_event(GroupEvent::GroupLeft {
             group_id: group_id.to_string(),
         });
 
-        Ok(())
+        Ok(topic_hash)
     }
 
     /// Add a member to a group (admin only)
+    ///
+    /// The new member is sent our sender key together with a snapshot of the
+    /// group, and asked to answer with its own key.
     pub async fn add_member(&self, group_id: &str, peer_id: &str) -> Result<()> {
         let mut groups = self.groups.write().await;
         let group = groups
@@ -181,22 +239,34 @@ impl GroupManager {
             return Err(MePassaError::AlreadyExists("User is already a member".to_string()));
         }
 
+        let existing: Vec<String> = group.members.iter().cloned().collect();
+
         // Add to group
         group.members.insert(peer_id.to_string());
 
         // Save to database
         storage::add_member(&self.db, group_id, peer_id, GroupRole::Member)?;
 
+        let snapshot = Self::snapshot(group);
+        drop(groups);
+
         // Emit event
         self.emit_event(GroupEvent::MemberAdded {
             group_id: group_id.to_string(),
             peer_id: peer_id.to_string(),
         });
 
-        Ok(())
+        // The invitation, and the new member list for everyone else (over the
+        // pairwise sessions, so it arrives even before they subscribe)
+        self.queue_own_key(group_id, [peer_id.to_string()], true, Some(snapshot.clone()))
+            .await?;
+        self.queue_own_key(group_id, existing, false, Some(snapshot)).await
     }
 
     /// Remove a member from a group (admin only)
+    ///
+    /// Drops the member's sender key and rotates ours, so the removed member
+    /// can't read anything sent afterwards.
     pub async fn remove_member(&self, group_id: &str, peer_id: &str) -> Result<()> {
         let mut groups = self.groups.write().await;
         let group = groups
@@ -219,6 +289,9 @@ impl GroupManager {
 
         // Save to database
         storage::remove_member(&self.db, group_id, peer_id)?;
+        drop(groups);
+
+        self.sender_keys.write().await.remove_key(group_id, peer_id)?;
 
         // Emit event
         self.emit_event(GroupEvent::MemberRemoved {
@@ -226,7 +299,7 @@ impl GroupManager {
             peer_id: peer_id.to_string(),
         });
 
-        Ok(())
+        self.rotate_sender_key(group_id).await
     }
 
     /// Promote member to admin (admin only)
@@ -337,38 +410,638 @@ impl GroupManager {
         self.groups.read().await.values().cloned().collect()
     }
 
+    /// Topics of all groups we belong to
+    pub async fn topics(&self) -> Vec<TopicHash> {
+        self.subscribed_topics.read().await.values().cloned().collect()
+    }
+
+    /// Take the sender key distributions waiting to be sent
+    pub async fn take_pending_distributions(&self) -> Vec<(String, SenderKeyDistribution)> {
+        std::mem::take(&mut *self.outbox.write().await)
+    }
+
+    /// Our current sender key for a group, as a distribution message
+    pub async fn sender_key_distribution(
+        &self,
+        group_id: &str,
+        request_reply: bool,
+    ) -> Result<SenderKeyDistribution> {
+        self.ensure_own_sender_key(group_id).await?;
+        let sender_keys = self.sender_keys.read().await;
+        let key = sender_keys
+            .get_key(group_id, &self.local_peer_id)
+            .ok_or_else(|| MePassaError::Crypto("Sender key not available".to_string()))?;
+        Ok(key.to_distribution(request_reply))
+    }
+
+    /// Replace our sender key for a group and distribute the new one
+    pub async fn rotate_sender_key(&self, group_id: &str) -> Result<()> {
+        let recipients: Vec<String> = {
+            let groups = self.groups.read().await;
+            let group = groups
+                .get(group_id)
+                .ok_or_else(|| MePassaError::NotFound(format!("Group {} not found", group_id)))?;
+            group.members.iter().cloned().collect()
+        };
+
+        {
+            let mut sender_keys = self.sender_keys.write().await;
+            let rotated = match sender_keys.get_key(group_id, &self.local_peer_id) {
+                Some(key) => key.rotate()?,
+                None => SenderKey::generate(group_id.to_string(), self.local_peer_id.clone())?,
+            };
+            tracing::info!(
+                "🔄 Rotated sender key for group {} (generation {})",
+                group_id,
+                rotated.generation()
+            );
+            sender_keys.store_key(rotated)?;
+        }
+
+        self.queue_own_key(group_id, recipients, false, None).await
+    }
+
+    /// Handle a sender key received over the pairwise E2E session with `from_peer`
+    ///
+    /// `from_peer` must be the peer that session authenticated, never a sender
+    /// the message claims: the key is only accepted as `from_peer`'s own.
+    pub async fn handle_sender_key_distribution(
+        &self,
+        from_peer: &str,
+        distribution: SenderKeyDistribution,
+    ) -> Result<()> {
+        if distribution.sender_peer_id != from_peer {
+            return Err(MePassaError::Permission(format!(
+                "Sender key of {} distributed by {}",
+                distribution.sender_peer_id, from_peer
+            )));
+        }
+        if from_peer == self.local_peer_id {
+            return Err(MePassaError::Permission("Refusing to import our own sender key".to_string()));
+        }
+
+        let group_id = distribution.group_id.clone();
+        let key = SenderKey::from_distribution(&distribution)?;
+        {
+            let mut sender_keys = self.sender_keys.write().await;
+            // Only a later chain state replaces ours: replaying an old
+            // distribution would rewind the chain and reopen used message keys
+            let fresh = sender_keys
+                .get_key(&group_id, from_peer)
+                .is_none_or(|existing| {
+                    (key.generation(), key.iteration())
+                        > (existing.generation(), existing.iteration())
+                });
+            if fresh {
+                sender_keys.store_key(key)?;
+                tracing::info!("🔑 Received sender key of {} for group {}", from_peer, group_id);
+            } else {
+                tracing::debug!("Ignoring stale sender key of {} for {}", from_peer, group_id);
+            }
+        }
+
+        // An invitation from one of the group's admins, or the member list
+        // of a group we are in from an admin we know
+        let mut invited = false;
+        let mut announced = Vec::new();
+        if let Some(snapshot) = distribution.group.as_ref() {
+            if snapshot.admins.iter().any(|admin| admin == from_peer) {
+                invited = self.accept_invitation(&group_id, from_peer, snapshot).await?;
+            }
+            if !invited {
+                announced = self.add_announced_members(&group_id, from_peer, snapshot).await?;
+            }
+        }
+
+        let recipients: Vec<(String, bool)> = {
+            let groups = self.groups.read().await;
+            // Keys for groups we haven't joined yet are kept until we do
+            let Some(group) = groups.get(&group_id) else {
+                return Ok(());
+            };
+
+            // Membership only changes through admins; a key from anyone else
+            // waits until an admin adds them
+            if !group.is_member(from_peer) {
+                tracing::debug!(
+                    "Holding sender key of {} until it joins group {}",
+                    from_peer,
+                    group_id
+                );
+                return Ok(());
+            }
+
+            if invited {
+                // Introduce ourselves to every member and ask for their keys
+                group
+                    .members
+                    .iter()
+                    .map(|member| (member.clone(), member != from_peer))
+                    .collect()
+            } else {
+                let mut recipients = announced;
+                if distribution.request_reply {
+                    recipients.push((from_peer.to_string(), false));
+                }
+                recipients
+            }
+        };
+
+        for (peer_id, request_reply) in recipients {
+            self.queue_own_key(&group_id, [peer_id], request_reply, None).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Build a signed admin action message for a group
+    pub async fn create_admin_message(
+        &self,
+        group_id: &str,
+        action: AdminAction,
+    ) -> Result<(TopicHash, GroupMessage)> {
+        self.build_message(group_id, GroupMessageType::AdminAction { action }, Vec::new())
+            .await
+    }
+
+    /// Build a signed, sender-key encrypted text message for a group
+    pub async fn create_text_message(
+        &self,
+        group_id: &str,
+        content: &str,
+    ) -> Result<(TopicHash, GroupMessage)> {
+        self.ensure_own_sender_key(group_id).await?;
+        let local_peer_id = self.local_peer_id.clone();
+        let ciphertext = self
+            .sender_keys
+            .write()
+            .await
+            .encrypt(group_id, &local_peer_id, content.as_bytes())?;
+
+        self.build_message(group_id, GroupMessageType::Text, ciphertext)
+            .await
+    }
+
+    async fn build_message(
+        &self,
+        group_id: &str,
+        message_type: GroupMessageType,
+        content: Vec<u8>,
+    ) -> Result<(TopicHash, GroupMessage)> {
+        let topic_hash = {
+            let groups = self.groups.read().await;
+            let group = groups
+                .get(group_id)
+                .ok_or_else(|| MePassaError::NotFound(format!("Group {} not found", group_id)))?;
+
+            if !group.is_member(&self.local_peer_id) {
+                return Err(MePassaError::Permission("Not a member of this group".to_string()));
+            }
+
+            group.topic_hash().hash()
+        };
+
+        let mut message = GroupMessage {
+            message_id: uuid::Uuid::new_v4().to_string(),
+            group_id: group_id.to_string(),
+            sender_peer_id: self.local_peer_id.clone(),
+            message_type,
+            content,
+            timestamp: chrono::Utc::now().timestamp(),
+            signature: Vec::new(),
+        };
+        message.signature = self.keypair.sign(&message.signing_bytes()?).to_vec();
+
+        Ok((topic_hash, message))
+    }
+
+    /// Persist a group message (sent or received) with its decrypted content
+    pub fn store_message(
+        &self,
+        message: &GroupMessage,
+        content: &[u8],
+        status: MessageStatus,
+    ) -> Result<String> {
+        let group_name = storage::load_group(&self.db, &message.group_id)?.name;
+        let conversation_id = self
+            .db
+            .get_or_create_group_conversation(&message.group_id, &group_name)?;
+
+        self.db.ensure_contact_exists(&message.sender_peer_id)?;
+
+        let new_msg = NewMessage {
+            message_id: message.message_id.clone(),
+            conversation_id: conversation_id.clone(),
+            sender_peer_id: message.sender_peer_id.clone(),
+            recipient_peer_id: None,
+            message_type: "text".to_string(),
+            content_encrypted: Some(encrypt_for_storage(&self.storage_key, content)?),
+            content_plaintext: None,
+            status,
+            parent_message_id: None,
+        };
+        self.db.insert_message(&new_msg)?;
+        self.db
+            .update_conversation_last_message(&conversation_id, &message.message_id)?;
+
+        Ok(conversation_id)
+    }
+
     /// Handle incoming GossipSub message
+    ///
+    /// Messages that fail authentication or authorization are dropped and
+    /// reported as [`GroupEvent::MessageRejected`].
     pub async fn handle_gossipsub_message(
         &self,
         topic: &TopicHash,
         message: gossipsub::Message,
     ) -> Result<()> {
         // Deserialize message
-        let group_msg: GroupMessage = serde_json::from_slice(&message.data)
-            .map_err(|e| MePassaError::Protocol(format!("Invalid group message: {}", e)))?;
+        let group_msg = GroupMessage::from_bytes(&message.data)?;
 
-        // Verify message is from a group member
-        let groups = self.groups.read().await;
-        let group = groups.get(&group_msg.group_id);
+        match self.authenticate(topic, message.source, &group_msg).await {
+            Ok(true) => {}
+            Ok(false) => return Ok(()),
+            Err(e) => return Err(self.reject(&group_msg, e)),
+        }
+
+        match group_msg.message_type.clone() {
+            GroupMessageType::AdminAction { action } => {
+                if let Err(e) = self.authorize_admin(&group_msg).await {
+                    return Err(self.reject(&group_msg, e));
+                }
+                if let Err(e) = self.claim_admin_action(&group_msg) {
+                    return Err(self.reject(&group_msg, e));
+                }
+                self.handle_admin_action(&group_msg, action).await
+            }
+            _ => self.handle_content_message(group_msg).await,
+        }
+    }
+
+    /// Check who sent a message and where
+    ///
+    /// Returns `false` for messages of groups we don't know (ignored).
+    async fn authenticate(
+        &self,
+        topic: &TopicHash,
+        source: Option<libp2p::PeerId>,
+        group_msg: &GroupMessage,
+    ) -> Result<bool> {
+        // The GossipSub author (libp2p-signed) must be the claimed sender
+        let source = source.ok_or_else(|| {
+            MePassaError::Permission("Group message without GossipSub source".to_string())
+        })?;
+        if source.to_string() != group_msg.sender_peer_id {
+            return Err(MePassaError::Permission(format!(
+                "Group message sender {} does not match GossipSub source {}",
+                group_msg.sender_peer_id, source
+            )));
+        }
+
+        // Verify message is from a group member, on the group's own topic
+        {
+            let groups = self.groups.read().await;
+            let Some(group) = groups.get(&group_msg.group_id) else {
+                tracing::debug!("Ignoring message for unknown group {}", group_msg.group_id);
+                return Ok(false);
+            };
+
+            if group.topic_hash().hash() != *topic {
+                return Err(MePassaError::Protocol("Group message on the wrong topic".to_string()));
+            }
 
-        if let Some(group) = group {
             if !group.is_member(&group_msg.sender_peer_id) {
                 return Err(MePassaError::Permission("Sender is not a group member".to_string()));
             }
+        }
+
+        // The message must be signed by the sender's identity key
+        PublicKey::from_libp2p_peer_id(&source)?
+            .verify(&group_msg.signing_bytes()?, &group_msg.signature)?;
+
+        Ok(true)
+    }
+
+    /// Admin actions are only accepted from members we know as admins
+    async fn authorize_admin(&self, group_msg: &GroupMessage) -> Result<()> {
+        let groups = self.groups.read().await;
+        let is_admin = groups
+            .get(&group_msg.group_id)
+            .is_some_and(|group| group.is_admin(&group_msg.sender_peer_id));
+
+        if !is_admin {
+            return Err(MePassaError::Permission(format!(
+                "{} is not an admin of group {}",
+                group_msg.sender_peer_id, group_msg.group_id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Admin actions apply once, and never after a later one of the group
+    ///
+    /// A signed action stays valid forever, so without this an old
+    /// "add member" could be replayed after the member was removed.
+    fn claim_admin_action(&self, group_msg: &GroupMessage) -> Result<()> {
+        let group_id = group_msg.group_id.as_str();
+        if let Some(latest) = storage::latest_admin_action(&self.db, group_id)? {
+            if group_msg.timestamp < latest {
+                return Err(MePassaError::Permission(format!(
+                    "Admin action {} is older than the last one applied to group {}",
+                    group_msg.message_id, group_id
+                )));
+            }
+        }
+        if !storage::record_admin_action(&self.db, group_id, &group_msg.message_id, group_msg.timestamp)? {
+            return Err(MePassaError::Permission(format!(
+                "Admin action {} was already applied",
+                group_msg.message_id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Report a dropped message and hand the error back to the caller
+    fn reject(&self, group_msg: &GroupMessage, error: MePassaError) -> MePassaError {
+        tracing::warn!(
+            "🚫 Rejected group message {} from {}: {}",
+            group_msg.message_id,
+            group_msg.sender_peer_id,
+            error
+        );
+        self.emit_event(GroupEvent::MessageRejected {
+            group_id: group_msg.group_id.clone(),
+            message_id: group_msg.message_id.clone(),
+            sender_peer_id: group_msg.sender_peer_id.clone(),
+            reason: error.to_string(),
+        });
+        error
+    }
+
+    /// Decrypt, store and emit a message carrying sender-key encrypted content
+    async fn handle_content_message(&self, group_msg: GroupMessage) -> Result<()> {
+        let content = self.sender_keys.write().await.decrypt(
+            &group_msg.group_id,
+            &group_msg.sender_peer_id,
+            &group_msg.content,
+        )?;
+
+        self.store_message(&group_msg, &content, MessageStatus::Delivered)?;
+
+        tracing::info!(
+            "👥 Received group message {} from {} in {}",
+            group_msg.message_id,
+            group_msg.sender_peer_id,
+            group_msg.group_id
+        );
+
+        // Emit event
+        self.emit_event(GroupEvent::MessageReceived {
+            message: group_msg,
+            content,
+        });
+
+        Ok(())
+    }
+
+    /// Apply an admin action announced on the group topic (sender authorized, replay claimed)
+    async fn handle_admin_action(&self, group_msg: &GroupMessage, action: AdminAction) -> Result<()> {
+        let group_id = group_msg.group_id.as_str();
+        let mut groups = self.groups.write().await;
+        let group = groups
+            .get_mut(group_id)
+            .ok_or_else(|| MePassaError::NotFound(format!("Group {} not found", group_id)))?;
+
+        match action {
+            AdminAction::AddMember { peer_id } => {
+                if group.is_member(&peer_id) {
+                    return Ok(());
+                }
+                group.members.insert(peer_id.clone());
+                storage::add_member(&self.db, group_id, &peer_id, GroupRole::Member)?;
+                self.emit_event(GroupEvent::MemberAdded {
+                    group_id: group_id.to_string(),
+                    peer_id: peer_id.clone(),
+                });
+                drop(groups);
+
+                let request_reply = self.sender_keys.read().await.get_key(group_id, &peer_id).is_none();
+                return self.queue_own_key(group_id, [peer_id], request_reply, None).await;
+            }
+            AdminAction::RemoveMember { peer_id } => {
+                if peer_id == group.creator_peer_id {
+                    return Err(MePassaError::Permission("Can't remove group creator".to_string()));
+                }
+                drop(groups);
+
+                if peer_id == self.local_peer_id {
+                    tracing::info!("👥 Removed from group {} by {}", group_id, group_msg.sender_peer_id);
+                    self.leave_group(group_id).await?;
+                    return Ok(());
+                }
+
+                return self.apply_member_removal(group_id, &peer_id).await;
+            }
+            AdminAction::PromoteToAdmin { peer_id } => {
+                if group.is_member(&peer_id) {
+                    group.admins.insert(peer_id.clone());
+                    storage::update_member_role(&self.db, group_id, &peer_id, GroupRole::Admin)?;
+                }
+            }
+            AdminAction::DemoteToMember { peer_id } => {
+                if peer_id != group.creator_peer_id {
+                    group.admins.remove(&peer_id);
+                    storage::update_member_role(&self.db, group_id, &peer_id, GroupRole::Member)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Forget a removed member and rotate our sender key away from them
+    async fn apply_member_removal(&self, group_id: &str, peer_id: &str) -> Result<()> {
+        {
+            let mut groups = self.groups.write().await;
+            let Some(group) = groups.get_mut(group_id) else {
+                return Ok(());
+            };
+            if !group.members.remove(peer_id) {
+                return Ok(());
+            }
+            group.admins.remove(peer_id);
+            storage::remove_member(&self.db, group_id, peer_id)?;
+        }
+
+        self.sender_keys.write().await.remove_key(group_id, peer_id)?;
+
+        self.emit_event(GroupEvent::MemberRemoved {
+            group_id: group_id.to_string(),
+            peer_id: peer_id.to_string(),
+        });
+
+        self.rotate_sender_key(group_id).await
+    }
+
+    /// Add the members of an admin's snapshot of a group we are in
+    ///
+    /// Only additions are taken from it; removals are admin actions that
+    /// rotate sender keys. Returns the new members to send our key to, and
+    /// whether to ask for theirs (we may already hold it).
+    async fn add_announced_members(
+        &self,
+        group_id: &str,
+        admin: &str,
+        snapshot: &GroupSnapshot,
+    ) -> Result<Vec<(String, bool)>> {
+        let added: Vec<String> = {
+            let mut groups = self.groups.write().await;
+            let Some(group) = groups.get_mut(group_id) else {
+                return Ok(Vec::new());
+            };
+            if !group.is_admin(admin) {
+                return Ok(Vec::new());
+            }
 
-            // TODO: Verify signature
+            let mut added = Vec::new();
+            for peer_id in &snapshot.members {
+                if !group.is_member(peer_id) {
+                    group.members.insert(peer_id.clone());
+                    storage::add_member(&self.db, group_id, peer_id, GroupRole::Member)?;
+                    added.push(peer_id.clone());
+                }
+            }
+            added
+        };
 
-            // Emit event
-            self.emit_event(GroupEvent::MessageReceived {
-                message: group_msg,
+        let sender_keys = self.sender_keys.read().await;
+        let mut recipients = Vec::new();
+        for peer_id in added {
+            self.emit_event(GroupEvent::MemberAdded {
+                group_id: group_id.to_string(),
+                peer_id: peer_id.clone(),
             });
+            let request_reply = sender_keys.get_key(group_id, &peer_id).is_none();
+            recipients.push((peer_id, request_reply));
         }
+        Ok(recipients)
+    }
 
+    /// Create (or complete a placeholder of) a group `inviter` invited us to
+    ///
+    /// The inviter came over our pairwise E2E session, so it is the one admin
+    /// we know of; the admins in its snapshot are only its claim, and others
+    /// are recognized once a known admin promotes them. Members are those of
+    /// the invitation: sender keys that reached us earlier don't add anyone.
+    ///
+    /// Returns `true` if the invitation was accepted. Invitations for groups we
+    /// already know with other members are ignored; those updates arrive as
+    /// admin actions on the group topic.
+    async fn accept_invitation(
+        &self,
+        group_id: &str,
+        inviter: &str,
+        snapshot: &GroupSnapshot,
+    ) -> Result<bool> {
+        if !snapshot.members.contains(&self.local_peer_id) {
+            return Ok(false);
+        }
+
+        let group = {
+            let mut groups = self.groups.write().await;
+            if let Some(existing) = groups.get(group_id) {
+                let placeholder = existing.members.iter().all(|m| *m == self.local_peer_id);
+                if !placeholder {
+                    return Ok(false);
+                }
+            }
+
+            let group = Group {
+                id: group_id.to_string(),
+                name: snapshot.name.clone(),
+                description: (!snapshot.description.is_empty())
+                    .then(|| snapshot.description.clone()),
+                avatar_hash: None,
+                creator_peer_id: snapshot.creator_peer_id.clone(),
+                members: snapshot
+                    .members
+                    .iter()
+                    .cloned()
+                    .chain([inviter.to_string()])
+                    .collect(),
+                admins: [inviter.to_string()].into(),
+                created_at: chrono::Utc::now().timestamp(),
+                is_left: false,
+                topic: format!("/mepassa/group/{}", group_id),
+            };
+
+            storage::save_group(&self.db, &group)?;
+            groups.insert(group_id.to_string(), group.clone());
+            group
+        };
+
+        self.ensure_own_sender_key(group_id).await?;
+        self.subscribed_topics
+            .write()
+            .await
+            .insert(group_id.to_string(), group.topic_hash().hash());
+
+        tracing::info!("👥 Joined group {} ({})", group.name, group_id);
+        self.emit_event(GroupEvent::GroupJoined {
+            group_id: group_id.to_string(),
+        });
+
+        Ok(true)
+    }
+
+    /// Queue our sender key for the given members (never ourselves)
+    async fn queue_own_key(
+        &self,
+        group_id: &str,
+        recipients: impl IntoIterator<Item = String>,
+        request_reply: bool,
+        snapshot: Option<GroupSnapshot>,
+    ) -> Result<()> {
+        let mut distribution = self.sender_key_distribution(group_id, request_reply).await?;
+        distribution.group = snapshot;
+
+        let mut outbox = self.outbox.write().await;
+        for peer_id in recipients {
+            if peer_id != self.local_peer_id {
+                outbox.push((peer_id, distribution.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Group metadata sent along with an invitation
+    fn snapshot(group: &Group) -> GroupSnapshot {
+        GroupSnapshot {
+            name: group.name.clone(),
+            description: group.description.clone().unwrap_or_default(),
+            creator_peer_id: group.creator_peer_id.clone(),
+            members: group.members.iter().cloned().collect(),
+            admins: group.admins.iter().cloned().collect(),
+        }
+    }
+
+    /// Create our own sender key for a group if we don't have one yet
+    async fn ensure_own_sender_key(&self, group_id: &str) -> Result<()> {
+        let mut sender_keys = self.sender_keys.write().await;
+        if sender_keys.get_key(group_id, &self.local_peer_id).is_none() {
+            let key = SenderKey::generate(group_id.to_string(), self.local_peer_id.clone())?;
+            sender_keys.store_key(key)?;
+        }
         Ok(())
     }
 
     /// Emit a group event
     fn emit_event(&self, event: GroupEvent) {
+        self.subscribers
+            .lock()
+            .expect("group subscribers lock poisoned")
+            .retain(|tx| tx.send(event.clone()).is_ok());
         let _ = self.event_tx.send(event);
     }
 }
@@ -389,7 +1062,7 @@ mod tests {
             rusqlite::params!["peer-1", vec![0u8; 32]],
         ).unwrap();
 
-        let manager = GroupManager::new("peer-1".to_string(), db).unwrap();
+        let manager = GroupManager::new("peer-1".to_string(), db, Keypair::generate(), [7u8; 32]).unwrap();
         manager.init().await.unwrap();
 
         let (group, _topic) = manager
@@ -418,7 +1091,7 @@ mod tests {
             rusqlite::params!["peer-2", vec![1u8; 32]],
         ).unwrap();
 
-        let manager = GroupManager::new("peer-1".to_string(), db).unwrap();
+        let manager = GroupManager::new("peer-1".to_string(), db, Keypair::generate(), [7u8; 32]).unwrap();
         manager.init().await.unwrap();
 
         let (group, _) = manager
@@ -455,7 +1128,7 @@ mod tests {
             rusqlite::params!["peer-2", vec![1u8; 32]],
         ).unwrap();
 
-        let manager = GroupManager::new("peer-1".to_string(), db).unwrap();
+        let manager = GroupManager::new("peer-1".to_string(), db, Keypair::generate(), [7u8; 32]).unwrap();
         manager.init().await.unwrap();
 
         let (group, _) = manager
@@ -476,4 +1149,398 @@ mod tests {
         let updated = manager.get_group(&group.id).await.unwrap();
         assert!(!updated.is_admin("peer-2"));
     }
+
+    /// Manager backed by its own in-memory database, with a libp2p peer ID
+    fn test_manager() -> (GroupManager, libp2p::PeerId) {
+        let db = Arc::new(Database::in_memory().unwrap());
+        crate::storage::schema::init_schema(&db).unwrap();
+
+        let libp2p_keypair = libp2p::identity::Keypair::generate_ed25519();
+        let keypair = Keypair::from_libp2p_keypair(&libp2p_keypair).unwrap();
+        let peer_id = libp2p::PeerId::from(libp2p_keypair.public());
+        db.conn().execute(
+            "INSERT INTO contacts (peer_id, public_key) VALUES (?1, ?2)",
+            rusqlite::params![peer_id.to_string(), vec![0u8; 32]],
+        ).unwrap();
+
+        let manager = GroupManager::new(peer_id.to_string(), db, keypair, [7u8; 32]).unwrap();
+        (manager, peer_id)
+    }
+
+    /// Deliver queued sender key distributions between managers until none are left
+    async fn exchange_sender_keys(nodes: &[(&GroupManager, libp2p::PeerId)]) {
+        loop {
+            let mut delivered = false;
+            for (from, from_id) in nodes {
+                for (to_id, distribution) in from.take_pending_distributions().await {
+                    if let Some((to, _)) = nodes.iter().find(|(_, id)| id.to_string() == to_id) {
+                        to.handle_sender_key_distribution(&from_id.to_string(), distribution)
+                            .await
+                            .unwrap();
+                        delivered = true;
+                    }
+                }
+            }
+            if !delivered {
+                return;
+            }
+        }
+    }
+
+    fn gossipsub_message(source: libp2p::PeerId, topic: &TopicHash, message: &GroupMessage) -> gossipsub::Message {
+        gossipsub::Message {
+            source: Some(source),
+            data: message.to_bytes().unwrap(),
+            sequence_number: Some(1),
+            topic: topic.clone(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_group_message_roundtrip() {
+        let (alice, alice_id) = test_manager();
+        let (bob, bob_id) = test_manager();
+        let mut bob_events = bob.take_event_receiver().await.unwrap();
+
+        let (group, topic) = alice.create_group("Team".to_string(), None).await.unwrap();
+        alice.add_member(&group.id, &bob_id.to_string()).await.unwrap();
+
+        // The invitation carries the group and Alice's key; Bob answers with his
+        exchange_sender_keys(&[(&alice, alice_id), (&bob, bob_id)]).await;
+        let joined = bob.get_group(&group.id).await.unwrap();
+        assert_eq!(joined.name, "Team");
+        assert!(joined.is_admin(&alice_id.to_string()));
+        assert!(alice.sender_keys.read().await.get_key(&group.id, &bob_id.to_string()).is_some());
+        assert_eq!(bob.topics().await, vec![topic.clone()]);
+
+        let (publish_topic, message) = alice.create_text_message(&group.id, "hello team").await.unwrap();
+        assert_eq!(publish_topic, topic);
+        assert_ne!(message.content, b"hello team");
+        assert_eq!(message.signature.len(), 64);
+
+        bob.handle_gossipsub_message(&topic, gossipsub_message(alice_id, &topic, &message))
+            .await
+            .unwrap();
+
+        let content = loop {
+            match bob_events.recv().await.unwrap() {
+                GroupEvent::MessageReceived { message: received, content } => {
+                    assert_eq!(received.message_id, message.message_id);
+                    break content;
+                }
+                _ => continue,
+            }
+        };
+        assert_eq!(content, b"hello team");
+
+        let stored = bob.db.get_message(&message.message_id).unwrap();
+        assert_eq!(stored.conversation_id, format!("group:{}", group.id));
+    }
+
+    #[tokio::test]
+    async fn test_invitation_only_trusts_the_inviter() {
+        let (alice, alice_id) = test_manager();
+        let (bob, bob_id) = test_manager();
+        let (mallory, mallory_id) = test_manager();
+
+        let (group, _) = alice.create_group("Team".to_string(), None).await.unwrap();
+        alice.add_member(&group.id, &bob_id.to_string()).await.unwrap();
+
+        // Mallory's sender key reaches Bob before the invitation
+        mallory.join_group(group.id.clone(), group.name.clone()).await.unwrap();
+        let early = mallory.sender_key_distribution(&group.id, false).await.unwrap();
+        bob.handle_sender_key_distribution(&mallory_id.to_string(), early)
+            .await
+            .unwrap();
+
+        // The invitation claims Mallory is an admin too
+        let (_, mut invitation) = alice.take_pending_distributions().await.remove(0);
+        invitation.group.as_mut().unwrap().admins.push(mallory_id.to_string());
+        bob.handle_sender_key_distribution(&alice_id.to_string(), invitation)
+            .await
+            .unwrap();
+
+        let joined = bob.get_group(&group.id).await.unwrap();
+        assert!(joined.is_admin(&alice_id.to_string()));
+        assert!(!joined.is_admin(&mallory_id.to_string()));
+        assert!(!joined.is_member(&mallory_id.to_string()));
+        assert!(joined.is_member(&bob_id.to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_spoofed_sender() {
+        let (alice, alice_id) = test_manager();
+        let (bob, bob_id) = test_manager();
+
+        let (group, topic) = alice.create_group("Team".to_string(), None).await.unwrap();
+        alice.add_member(&group.id, &bob_id.to_string()).await.unwrap();
+        exchange_sender_keys(&[(&alice, alice_id), (&bob, bob_id)]).await;
+
+        let (_, mut message) = alice.create_text_message(&group.id, "hi").await.unwrap();
+        message.sender_peer_id = libp2p::PeerId::random().to_string();
+
+        let result = bob
+            .handle_gossipsub_message(&topic, gossipsub_message(alice_id, &topic, &message))
+            .await;
+        assert!(matches!(result, Err(MePassaError::Permission(_))));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_message_without_sender_key() {
+        let (alice, alice_id) = test_manager();
+        let (bob, _) = test_manager();
+
+        let (group, topic) = alice.create_group("Team".to_string(), None).await.unwrap();
+        bob.join_group(group.id.clone(), group.name.clone()).await.unwrap();
+        // Alice is a member but never shared her key with Bob
+        bob.groups.write().await.get_mut(&group.id).unwrap().members.insert(alice_id.to_string());
+
+        let (_, message) = alice.create_text_message(&group.id, "hi").await.unwrap();
+        let result = bob
+            .handle_gossipsub_message(&topic, gossipsub_message(alice_id, &topic, &message))
+            .await;
+        assert!(matches!(result, Err(MePassaError::Crypto(_))));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_sender_key_relayed_by_other_peer() {
+        let (alice, _) = test_manager();
+        let (bob, _) = test_manager();
+        let (group, _) = alice.create_group("Team".to_string(), None).await.unwrap();
+
+        let distribution = alice.sender_key_distribution(&group.id, false).await.unwrap();
+        let mallory = libp2p::PeerId::random().to_string();
+        let result = bob.handle_sender_key_distribution(&mallory, distribution).await;
+        assert!(matches!(result, Err(MePassaError::Permission(_))));
+    }
+
+    #[tokio::test]
+    async fn test_sender_key_does_not_grant_membership() {
+        let (alice, alice_id) = test_manager();
+        let (bob, bob_id) = test_manager();
+        let (mallory, mallory_id) = test_manager();
+
+        let (group, _) = alice.create_group("Team".to_string(), None).await.unwrap();
+        alice.add_member(&group.id, &bob_id.to_string()).await.unwrap();
+        exchange_sender_keys(&[(&alice, alice_id), (&bob, bob_id)]).await;
+
+        // Mallory knows the group ID and sends Bob her key, asking for his
+        mallory.join_group(group.id.clone(), group.name.clone()).await.unwrap();
+        let distribution = mallory.sender_key_distribution(&group.id, true).await.unwrap();
+        bob.handle_sender_key_distribution(&mallory_id.to_string(), distribution)
+            .await
+            .unwrap();
+
+        assert!(!bob.get_group(&group.id).await.unwrap().is_member(&mallory_id.to_string()));
+        assert!(bob.take_pending_distributions().await.is_empty());
+
+        // Once Alice adds her, everyone learns it from Alice
+        alice.add_member(&group.id, &mallory_id.to_string()).await.unwrap();
+        exchange_sender_keys(&[(&alice, alice_id), (&bob, bob_id), (&mallory, mallory_id)]).await;
+        assert!(bob.get_group(&group.id).await.unwrap().is_member(&mallory_id.to_string()));
+        assert!(mallory.sender_keys.read().await.get_key(&group.id, &bob_id.to_string()).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_ignores_stale_sender_key() {
+        let (alice, alice_id) = test_manager();
+        let (bob, bob_id) = test_manager();
+
+        let (group, topic) = alice.create_group("Team".to_string(), None).await.unwrap();
+        alice.add_member(&group.id, &bob_id.to_string()).await.unwrap();
+        let (_, invitation) = alice.take_pending_distributions().await.remove(0);
+        bob.handle_sender_key_distribution(&alice_id.to_string(), invitation.clone())
+            .await
+            .unwrap();
+
+        let (_, message) = alice.create_text_message(&group.id, "hi").await.unwrap();
+        bob.handle_gossipsub_message(&topic, gossipsub_message(alice_id, &topic, &message))
+            .await
+            .unwrap();
+
+        // Replaying the invitation must not rewind Alice's chain
+        bob.handle_sender_key_distribution(&alice_id.to_string(), invitation)
+            .await
+            .unwrap();
+        let bob_keys = bob.sender_keys.read().await;
+        let key = bob_keys.get_key(&group.id, &alice_id.to_string()).unwrap();
+        assert_eq!((key.generation(), key.iteration()), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn test_remove_member_rotates_sender_keys() {
+        let (alice, alice_id) = test_manager();
+        let (bob, bob_id) = test_manager();
+        let (carol, carol_id) = test_manager();
+        let nodes = [(&alice, alice_id), (&bob, bob_id), (&carol, carol_id)];
+
+        let (group, topic) = alice.create_group("Team".to_string(), None).await.unwrap();
+        alice.add_member(&group.id, &bob_id.to_string()).await.unwrap();
+        alice.add_member(&group.id, &carol_id.to_string()).await.unwrap();
+        exchange_sender_keys(&nodes).await;
+        for node in [&alice, &bob, &carol] {
+            assert_eq!(node.sender_keys.read().await.senders(&group.id).len(), 3);
+        }
+
+        // Alice removes Carol and tells the group
+        alice.remove_member(&group.id, &carol_id.to_string()).await.unwrap();
+        let (_, removal) = alice
+            .create_admin_message(&group.id, AdminAction::RemoveMember { peer_id: carol_id.to_string() })
+            .await
+            .unwrap();
+        for node in [&bob, &carol] {
+            node.handle_gossipsub_message(&topic, gossipsub_message(alice_id, &topic, &removal))
+                .await
+                .unwrap();
+        }
+        exchange_sender_keys(&nodes).await;
+
+        // Carol left; Bob rotated his key and has Alice's new one
+        assert!(carol.get_group(&group.id).await.is_none());
+        assert!(!bob.get_group(&group.id).await.unwrap().is_member(&carol_id.to_string()));
+        let bob_keys = bob.sender_keys.read().await;
+        assert_eq!(bob_keys.get_key(&group.id, &alice_id.to_string()).unwrap().generation(), 1);
+        assert_eq!(bob_keys.get_key(&group.id, &bob_id.to_string()).unwrap().generation(), 1);
+        assert!(bob_keys.get_key(&group.id, &carol_id.to_string()).is_none());
+        drop(bob_keys);
+
+        // Carol's old copy of Bob's key can't read what Bob sends now
+        let (_, message) = bob.create_text_message(&group.id, "without carol").await.unwrap();
+        let result = carol.sender_keys.write().await.decrypt(
+            &group.id,
+            &bob_id.to_string(),
+            &message.content,
+        );
+        assert!(result.is_err());
+        alice
+            .handle_gossipsub_message(&topic, gossipsub_message(bob_id, &topic, &message))
+            .await
+            .unwrap();
+    }
+
+    /// Next rejection reported by a manager
+    async fn next_rejection(events: &mut mpsc::UnboundedReceiver<GroupEvent>) -> String {
+        loop {
+            if let GroupEvent::MessageRejected { reason, .. } = events.recv().await.unwrap() {
+                return reason;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejects_tampered_message() {
+        let (alice, alice_id) = test_manager();
+        let (bob, bob_id) = test_manager();
+        let mut bob_events = bob.take_event_receiver().await.unwrap();
+
+        let (group, topic) = alice.create_group("Team".to_string(), None).await.unwrap();
+        alice.add_member(&group.id, &bob_id.to_string()).await.unwrap();
+        exchange_sender_keys(&[(&alice, alice_id), (&bob, bob_id)]).await;
+
+        let (_, mut message) = alice.create_text_message(&group.id, "hi").await.unwrap();
+        message.timestamp += 1;
+
+        let result = bob
+            .handle_gossipsub_message(&topic, gossipsub_message(alice_id, &topic, &message))
+            .await;
+        assert!(matches!(result, Err(MePassaError::Crypto(_))));
+        assert!(next_rejection(&mut bob_events).await.contains("Signature verification failed"));
+        assert!(bob.db.get_message(&message.message_id).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_admin_action_from_member() {
+        let (alice, alice_id) = test_manager();
+        let (bob, bob_id) = test_manager();
+        let (carol, carol_id) = test_manager();
+        let mut carol_events = carol.take_event_receiver().await.unwrap();
+
+        let (group, topic) = alice.create_group("Team".to_string(), None).await.unwrap();
+        alice.add_member(&group.id, &bob_id.to_string()).await.unwrap();
+        alice.add_member(&group.id, &carol_id.to_string()).await.unwrap();
+        exchange_sender_keys(&[(&alice, alice_id), (&bob, bob_id), (&carol, carol_id)]).await;
+
+        // Bob is a plain member, and his view of the group doesn't make him admin either
+        bob.groups.write().await.get_mut(&group.id).unwrap().admins.insert(bob_id.to_string());
+        let (_, message) = bob
+            .create_admin_message(&group.id, AdminAction::RemoveMember { peer_id: carol_id.to_string() })
+            .await
+            .unwrap();
+
+        let result = carol
+            .handle_gossipsub_message(&topic, gossipsub_message(bob_id, &topic, &message))
+            .await;
+        assert!(matches!(result, Err(MePassaError::Permission(_))));
+        assert!(next_rejection(&mut carol_events).await.contains("is not an admin"));
+        assert!(carol.get_group(&group.id).await.unwrap().is_member(&carol_id.to_string()));
+
+        // The same action signed by Alice is applied
+        let (_, message) = alice
+            .create_admin_message(&group.id, AdminAction::PromoteToAdmin { peer_id: bob_id.to_string() })
+            .await
+            .unwrap();
+        carol
+            .handle_gossipsub_message(&topic, gossipsub_message(alice_id, &topic, &message))
+            .await
+            .unwrap();
+        assert!(carol.get_group(&group.id).await.unwrap().is_admin(&bob_id.to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_replayed_admin_action() {
+        let (alice, alice_id) = test_manager();
+        let (bob, bob_id) = test_manager();
+        let (carol, carol_id) = test_manager();
+        let mut carol_events = carol.take_event_receiver().await.unwrap();
+
+        let (group, topic) = alice.create_group("Team".to_string(), None).await.unwrap();
+        alice.add_member(&group.id, &bob_id.to_string()).await.unwrap();
+        alice.add_member(&group.id, &carol_id.to_string()).await.unwrap();
+        exchange_sender_keys(&[(&alice, alice_id), (&bob, bob_id), (&carol, carol_id)]).await;
+
+        // Alice promotes Bob, then demotes him a second later
+        let (_, mut promotion) = alice
+            .create_admin_message(&group.id, AdminAction::PromoteToAdmin { peer_id: bob_id.to_string() })
+            .await
+            .unwrap();
+        promotion.timestamp -= 1;
+        promotion.signature = alice.keypair.sign(&promotion.signing_bytes().unwrap()).to_vec();
+        let (_, demotion) = alice
+            .create_admin_message(&group.id, AdminAction::DemoteToMember { peer_id: bob_id.to_string() })
+            .await
+            .unwrap();
+        for message in [&promotion, &demotion] {
+            carol
+                .handle_gossipsub_message(&topic, gossipsub_message(alice_id, &topic, message))
+                .await
+                .unwrap();
+        }
+        assert!(!carol.get_group(&group.id).await.unwrap().is_admin(&bob_id.to_string()));
+
+        // Replaying the promotion changes nothing, nor does the demotion again
+        let result = carol
+            .handle_gossipsub_message(&topic, gossipsub_message(alice_id, &topic, &promotion))
+            .await;
+        assert!(matches!(result, Err(MePassaError::Permission(_))));
+        assert!(next_rejection(&mut carol_events).await.contains("older than the last one"));
+        let result = carol
+            .handle_gossipsub_message(&topic, gossipsub_message(alice_id, &topic, &demotion))
+            .await;
+        assert!(matches!(result, Err(MePassaError::Permission(_))));
+        assert!(next_rejection(&mut carol_events).await.contains("already applied"));
+        assert!(!carol.get_group(&group.id).await.unwrap().is_admin(&bob_id.to_string()));
+
+        // Neither does an action signed before the last one but never seen
+        let (_, mut late) = alice
+            .create_admin_message(&group.id, AdminAction::PromoteToAdmin { peer_id: bob_id.to_string() })
+            .await
+            .unwrap();
+        late.timestamp = promotion.timestamp;
+        late.signature = alice.keypair.sign(&late.signing_bytes().unwrap()).to_vec();
+        let result = carol
+            .handle_gossipsub_message(&topic, gossipsub_message(alice_id, &topic, &late))
+            .await;
+        assert!(matches!(result, Err(MePassaError::Permission(_))));
+        assert!(!carol.get_group(&group.id).await.unwrap().is_admin(&bob_id.to_string()));
+    }
 }
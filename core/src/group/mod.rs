@@ -11,7 +11,8 @@
 //!
 //! Architecture:
 //! - GossipSub for message broadcasting (P2P pub/sub)
-//! - Sender Keys for group E2E encryption (one key per sender)
+//! - Sender Keys for group E2E encryption (one key per sender), distributed
+//!   to each member over the pairwise E2E session and rotated on removal
 //! - Admin-only operations via signed messages
 //! - Optimistic UI updates with eventual consistency
 
@@ -22,4 +23,4 @@ pub mod types;
 
 // Re-exports
 pub use manager::GroupManager;
-pub use types::{Group, GroupMember, GroupMessage, GroupRole, GroupEvent};
+pub use types::{AdminAction, Group, GroupMember, GroupMessage, GroupMessageType, GroupRole, GroupEvent};
@@ -11,6 +11,9 @@
 //! - https://signal.org/docs/specifications/doubleratchet/#sender-keys
 //! - https://signal.org/docs/specifications/sesame/
 
+use crate::crypto::{decrypt_for_storage, encrypt_for_storage, ratchet::MAX_SKIP};
+use crate::protocol::SenderKeyDistribution;
+use crate::storage::Database;
 use crate::utils::error::{MePassaError, Result};
 use aes_gcm::{
     aead::{Aead, KeyInit},
@@ -19,11 +22,15 @@ use aes_gcm::{
 use hkdf::Hkdf;
 use serde::{Deserialize, Serialize};
 use sha2::Sha256;
+use std::collections::HashMap;
 
 // HKDF info strings (as per Signal Protocol spec)
 const HKDF_INFO_CHAIN_KEY: &[u8] = b"MePassaSenderKeyChain";
 const HKDF_INFO_MESSAGE_KEY: &[u8] = b"MePassaSenderKeyMessage";
 
+/// Maximum number of skipped message keys kept per sender key
+const MAX_STORED_SKIPPED_KEYS: usize = 2000;
+
 /// Sender Key for group encryption
 ///
 /// Each group member has a sender key that they use to encrypt messages.
@@ -44,6 +51,14 @@ pub struct SenderKey {
 
     /// Public signing key (Ed25519)
     signing_key: Vec<u8>,
+
+    /// Key generation (incremented on every rotation)
+    #[serde(default)]
+    generation: u32,
+
+    /// Message keys of skipped iterations (out-of-order delivery)
+    #[serde(default)]
+    skipped_message_keys: Vec<(u32, Vec<u8>)>,
 }
 
 impl SenderKey {
@@ -61,6 +76,55 @@ impl SenderKey {
             chain_key,
             iteration: 0,
             signing_key,
+            generation: 0,
+            skipped_message_keys: Vec::new(),
+        })
+    }
+
+    /// Generate the replacement for this key (rotation)
+    pub fn rotate(&self) -> Result<Self> {
+        let mut key = Self::generate(self.group_id.clone(), self.sender_peer_id.clone())?;
+        key.generation = self.generation.wrapping_add(1);
+        Ok(key)
+    }
+
+    /// Key generation (incremented on every rotation)
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// Message key derivation counter (the next message's iteration)
+    pub fn iteration(&self) -> u32 {
+        self.iteration
+    }
+
+    /// Build a distribution message carrying the current chain state
+    pub fn to_distribution(&self, request_reply: bool) -> SenderKeyDistribution {
+        SenderKeyDistribution {
+            group_id: self.group_id.clone(),
+            sender_peer_id: self.sender_peer_id.clone(),
+            chain_key: self.chain_key.clone(),
+            iteration: self.iteration,
+            generation: self.generation,
+            request_reply,
+            group: None,
+        }
+    }
+
+    /// Rebuild a member's sender key from a distribution message
+    pub fn from_distribution(distribution: &SenderKeyDistribution) -> Result<Self> {
+        if distribution.chain_key.len() != 32 {
+            return Err(MePassaError::Crypto("Invalid sender key length".to_string()));
+        }
+
+        Ok(Self {
+            group_id: distribution.group_id.clone(),
+            sender_peer_id: distribution.sender_peer_id.clone(),
+            chain_key: distribution.chain_key.clone(),
+            iteration: distribution.iteration,
+            signing_key: vec![0u8; 32],
+            generation: distribution.generation,
+            skipped_message_keys: Vec::new(),
         })
     }
 
@@ -97,7 +161,17 @@ impl SenderKey {
     }
 
     /// Decrypt a message with this sender key
+    ///
+    /// The key is only advanced when decryption succeeds. Messages that
+    /// arrive out of order are decrypted with the skipped message keys.
     pub fn decrypt(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut next = self.clone();
+        let plaintext = next.decrypt_in_place(data)?;
+        *self = next;
+        Ok(plaintext)
+    }
+
+    fn decrypt_in_place(&mut self, data: &[u8]) -> Result<Vec<u8>> {
         // Extract iteration from first 4 bytes
         if data.len() < 4 {
             return Err(MePassaError::Crypto("Invalid ciphertext: too short".to_string()));
@@ -108,23 +182,45 @@ impl SenderKey {
         let message_iteration = u32::from_be_bytes(iteration_bytes);
         let ciphertext = &data[4..];
 
-        // Handle out-of-order messages by deriving the correct message key
-        // NOTE: In production, should cache previous message keys for out-of-order delivery
-        let message_key = if message_iteration == self.iteration {
-            // Current message
-            self.derive_message_key()?
-        } else if message_iteration > self.iteration {
-            // Future message - need to ratchet forward
-            let steps = message_iteration - self.iteration;
-            self.ratchet_n_times(steps)?;
-            self.derive_message_key()?
+        let message_key = if message_iteration < self.iteration {
+            // Past message - only decryptable if we skipped over it
+            let position = self
+                .skipped_message_keys
+                .iter()
+                .position(|(iteration, _)| *iteration == message_iteration)
+                .ok_or_else(|| {
+                    MePassaError::Crypto(format!(
+                        "No message key for iteration {} (already used or expired)",
+                        message_iteration
+                    ))
+                })?;
+            self.skipped_message_keys.remove(position).1
         } else {
-            // Past message - this is a simplified implementation
-            // Production should maintain a sliding window of message keys
-            return Err(MePassaError::Crypto(format!(
-                "Cannot decrypt past message (iteration {} < {}). Out-of-order delivery not fully supported.",
-                message_iteration, self.iteration
-            )));
+            if message_iteration - self.iteration > MAX_SKIP {
+                return Err(MePassaError::Crypto(format!(
+                    "Too many skipped messages ({} > {})",
+                    message_iteration - self.iteration,
+                    MAX_SKIP
+                )));
+            }
+
+            // Keep the keys of the iterations we jump over
+            while self.iteration < message_iteration {
+                let skipped_key = self.derive_message_key()?;
+                self.skipped_message_keys.push((self.iteration, skipped_key));
+                self.iteration += 1;
+                self.ratchet_forward()?;
+            }
+
+            if self.skipped_message_keys.len() > MAX_STORED_SKIPPED_KEYS {
+                let excess = self.skipped_message_keys.len() - MAX_STORED_SKIPPED_KEYS;
+                self.skipped_message_keys.drain(..excess);
+            }
+
+            let message_key = self.derive_message_key()?;
+            self.iteration += 1;
+            self.ratchet_forward()?;
+            message_key
         };
 
         // Decrypt with AES-256-GCM
@@ -136,17 +232,9 @@ impl SenderKey {
         let nonce = Nonce::from_slice(&nonce_bytes);
 
         // Decrypt
-        let plaintext = cipher
+        cipher
             .decrypt(nonce, ciphertext)
-            .map_err(|e| MePassaError::Crypto(format!("Decryption failed: {}", e)))?;
-
-        // Update iteration after successful decryption
-        if message_iteration >= self.iteration {
-            self.iteration = message_iteration + 1;
-            self.ratchet_forward()?;
-        }
-
-        Ok(plaintext)
+            .map_err(|e| MePassaError::Crypto(format!("Decryption failed: {}", e)))
     }
 
     /// Ratchet chain key forward using HKDF-SHA256
@@ -198,15 +286,6 @@ impl SenderKey {
         nonce
     }
 
-    /// Ratchet forward N times (for handling out-of-order messages)
-    fn ratchet_n_times(&mut self, n: u32) -> Result<()> {
-        for _ in 0..n {
-            self.iteration += 1;
-            self.ratchet_forward()?;
-        }
-        Ok(())
-    }
-
     /// Serialize sender key for transmission
     pub fn serialize(&self) -> Result<Vec<u8>> {
         serde_json::to_vec(self)
@@ -220,26 +299,98 @@ impl SenderKey {
     }
 }
 
+/// SQLite backing store for sender keys
+#[derive(Clone)]
+struct SenderKeyDb {
+    database: Database,
+    storage_key: [u8; 32],
+}
+
+impl std::fmt::Debug for SenderKeyDb {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SenderKeyDb").finish_non_exhaustive()
+    }
+}
+
+impl SenderKeyDb {
+    fn save(&self, key: &SenderKey) -> Result<()> {
+        let blob = encrypt_for_storage(&self.storage_key, &key.serialize()?)?;
+        self.database
+            .save_sender_key(&key.group_id, &key.sender_peer_id, &blob)?;
+        Ok(())
+    }
+
+    fn load_all(&self) -> Result<HashMap<(String, String), SenderKey>> {
+        let mut keys = HashMap::new();
+
+        for stored in self.database.list_sender_keys()? {
+            let key = decrypt_for_storage(&self.storage_key, &stored.key_data)
+                .and_then(|bytes| SenderKey::deserialize(&bytes));
+
+            match key {
+                Ok(key) => {
+                    keys.insert((stored.group_id, stored.sender_peer_id), key);
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "⚠️ Skipping unreadable sender key of {} in {}: {}",
+                        stored.sender_peer_id,
+                        stored.group_id,
+                        e
+                    );
+                }
+            }
+        }
+
+        Ok(keys)
+    }
+}
+
 /// Sender Key Store
 ///
-/// Stores sender keys for all group members.
+/// Stores sender keys for all group members (including our own). Optionally
+/// backed by the `sender_keys` table so keys survive restarts.
+#[derive(Debug)]
 pub struct SenderKeyStore {
     /// Keys indexed by (group_id, sender_peer_id)
-    keys: std::collections::HashMap<(String, String), SenderKey>,
+    keys: HashMap<(String, String), SenderKey>,
+
+    /// Persistent storage (None = in-memory only)
+    db: Option<SenderKeyDb>,
 }
 
 impl SenderKeyStore {
-    /// Create a new sender key store
+    /// Create a new in-memory sender key store
     pub fn new() -> Self {
         Self {
-            keys: std::collections::HashMap::new(),
+            keys: HashMap::new(),
+            db: None,
         }
     }
 
-    /// Store a sender key
-    pub fn store_key(&mut self, key: SenderKey) {
+    /// Create a store backed by the database, loading persisted keys
+    pub fn with_storage(database: Database, storage_key: [u8; 32]) -> Result<Self> {
+        let db = SenderKeyDb {
+            database,
+            storage_key,
+        };
+        let keys = db.load_all()?;
+
+        if !keys.is_empty() {
+            tracing::info!("🔑 Loaded {} group sender keys from storage", keys.len());
+        }
+
+        Ok(Self { keys, db: Some(db) })
+    }
+
+    /// Store a sender key (replaces any previous key of that member)
+    pub fn store_key(&mut self, key: SenderKey) -> Result<()> {
+        if let Some(db) = &self.db {
+            db.save(&key)?;
+        }
         let index = (key.group_id.clone(), key.sender_peer_id.clone());
         self.keys.insert(index, key);
+        Ok(())
     }
 
     /// Get a sender key
@@ -248,15 +399,65 @@ impl SenderKeyStore {
         self.keys.get(&index)
     }
 
-    /// Get a mutable sender key
-    pub fn get_key_mut(&mut self, group_id: &str, sender_peer_id: &str) -> Option<&mut SenderKey> {
-        let index = (group_id.to_string(), sender_peer_id.to_string());
-        self.keys.get_mut(&index)
+    /// Peers we hold sender keys from in a group
+    pub fn senders(&self, group_id: &str) -> Vec<String> {
+        self.keys
+            .keys()
+            .filter(|(gid, _)| gid == group_id)
+            .map(|(_, sender)| sender.clone())
+            .collect()
+    }
+
+    /// Encrypt with a stored sender key and persist the advanced chain
+    pub fn encrypt(&mut self, group_id: &str, sender_peer_id: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let key = self.key_mut(group_id, sender_peer_id)?;
+        let ciphertext = key.encrypt(plaintext)?;
+        let key = key.clone();
+        if let Some(db) = &self.db {
+            db.save(&key)?;
+        }
+        Ok(ciphertext)
+    }
+
+    /// Decrypt with a stored sender key and persist the advanced chain
+    pub fn decrypt(&mut self, group_id: &str, sender_peer_id: &str, data: &[u8]) -> Result<Vec<u8>> {
+        let key = self.key_mut(group_id, sender_peer_id)?;
+        let plaintext = key.decrypt(data)?;
+        let key = key.clone();
+        if let Some(db) = &self.db {
+            db.save(&key)?;
+        }
+        Ok(plaintext)
+    }
+
+    /// Remove the key of one member
+    pub fn remove_key(&mut self, group_id: &str, sender_peer_id: &str) -> Result<()> {
+        if let Some(db) = &self.db {
+            db.database.delete_sender_key(group_id, sender_peer_id)?;
+        }
+        self.keys
+            .remove(&(group_id.to_string(), sender_peer_id.to_string()));
+        Ok(())
     }
 
     /// Remove all keys for a group
-    pub fn remove_group(&mut self, group_id: &str) {
+    pub fn remove_group(&mut self, group_id: &str) -> Result<()> {
+        if let Some(db) = &self.db {
+            db.database.delete_group_sender_keys(group_id)?;
+        }
         self.keys.retain(|(gid, _), _| gid != group_id);
+        Ok(())
+    }
+
+    fn key_mut(&mut self, group_id: &str, sender_peer_id: &str) -> Result<&mut SenderKey> {
+        self.keys
+            .get_mut(&(group_id.to_string(), sender_peer_id.to_string()))
+            .ok_or_else(|| {
+                MePassaError::Crypto(format!(
+                    "No sender key from {} for group {}",
+                    sender_peer_id, group_id
+                ))
+            })
     }
 }
 
@@ -302,17 +503,100 @@ mod tests {
         let key1 = SenderKey::generate("group-1".to_string(), "peer-1".to_string()).unwrap();
         let key2 = SenderKey::generate("group-1".to_string(), "peer-2".to_string()).unwrap();
 
-        store.store_key(key1);
-        store.store_key(key2);
+        store.store_key(key1).unwrap();
+        store.store_key(key2).unwrap();
 
         assert!(store.get_key("group-1", "peer-1").is_some());
         assert!(store.get_key("group-1", "peer-2").is_some());
         assert!(store.get_key("group-2", "peer-1").is_none());
 
-        store.remove_group("group-1");
+        store.remove_group("group-1").unwrap();
         assert!(store.get_key("group-1", "peer-1").is_none());
     }
 
+    #[test]
+    fn test_out_of_order_messages() {
+        let mut sender_key = SenderKey::generate("group-1".to_string(), "peer-1".to_string()).unwrap();
+        let mut receiver_key = sender_key.clone();
+
+        let ciphertexts: Vec<Vec<u8>> = (0..4)
+            .map(|i| sender_key.encrypt(format!("Message {}", i).as_bytes()).unwrap())
+            .collect();
+
+        assert_eq!(receiver_key.decrypt(&ciphertexts[2]).unwrap(), b"Message 2");
+        assert_eq!(receiver_key.decrypt(&ciphertexts[0]).unwrap(), b"Message 0");
+        assert_eq!(receiver_key.decrypt(&ciphertexts[3]).unwrap(), b"Message 3");
+        assert_eq!(receiver_key.decrypt(&ciphertexts[1]).unwrap(), b"Message 1");
+
+        // Replays are rejected
+        assert!(receiver_key.decrypt(&ciphertexts[1]).is_err());
+    }
+
+    #[test]
+    fn test_failed_decrypt_does_not_advance_key() {
+        let mut sender_key = SenderKey::generate("group-1".to_string(), "peer-1".to_string()).unwrap();
+        let mut receiver_key = sender_key.clone();
+
+        let mut tampered = sender_key.encrypt(b"Message 1").unwrap();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 0xff;
+        let valid = sender_key.encrypt(b"Message 2").unwrap();
+
+        assert!(receiver_key.decrypt(&tampered).is_err());
+        assert_eq!(receiver_key.iteration, 0);
+        assert_eq!(receiver_key.decrypt(&valid).unwrap(), b"Message 2");
+    }
+
+    #[test]
+    fn test_distribution_roundtrip() {
+        let mut sender_key = SenderKey::generate("group-1".to_string(), "peer-1".to_string()).unwrap();
+        sender_key.encrypt(b"before distribution").unwrap();
+
+        let distribution = sender_key.to_distribution(true);
+        assert_eq!(distribution.iteration, 1);
+        assert!(distribution.request_reply);
+
+        let mut receiver_key = SenderKey::from_distribution(&distribution).unwrap();
+        let ciphertext = sender_key.encrypt(b"after distribution").unwrap();
+        assert_eq!(receiver_key.decrypt(&ciphertext).unwrap(), b"after distribution");
+    }
+
+    #[test]
+    fn test_rotation_changes_key_and_generation() {
+        let key = SenderKey::generate("group-1".to_string(), "peer-1".to_string()).unwrap();
+        let rotated = key.rotate().unwrap();
+
+        assert_eq!(rotated.generation(), key.generation() + 1);
+        assert_eq!(rotated.iteration, 0);
+        assert_ne!(rotated.chain_key, key.chain_key);
+    }
+
+    #[test]
+    fn test_persisted_store_survives_reload() {
+        let db = Database::in_memory().unwrap();
+        crate::storage::schema::init_schema(&db).unwrap();
+        let storage_key = [3u8; 32];
+
+        let mut sender_key = SenderKey::generate("group-1".to_string(), "peer-1".to_string()).unwrap();
+        let mut store = SenderKeyStore::with_storage(db.clone(), storage_key).unwrap();
+        store.store_key(sender_key.clone()).unwrap();
+
+        let first = sender_key.encrypt(b"first").unwrap();
+        let second = sender_key.encrypt(b"second").unwrap();
+        assert_eq!(store.decrypt("group-1", "peer-1", &first).unwrap(), b"first");
+
+        // Reload: the chain continues where it was persisted
+        let mut reloaded = SenderKeyStore::with_storage(db.clone(), storage_key).unwrap();
+        assert_eq!(reloaded.decrypt("group-1", "peer-1", &second).unwrap(), b"second");
+
+        // Blobs are encrypted at rest
+        let stored = db.list_sender_keys().unwrap();
+        assert!(!stored[0].key_data.windows(7).any(|w| w == b"group-1"));
+
+        reloaded.remove_key("group-1", "peer-1").unwrap();
+        assert!(db.list_sender_keys().unwrap().is_empty());
+    }
+
     #[test]
     fn test_ratcheting_forward_secrecy() {
         let mut key = SenderKey::generate("group-1".to_string(), "peer-1".to_string()).unwrap();
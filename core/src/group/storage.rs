@@ -9,6 +9,9 @@ use std::collections::HashSet;
 
 /// Save a group to database
 pub fn save_group(db: &Database, group: &Group) -> Result<()> {
+    // The creator may not be a contact yet (FOREIGN KEY constraint)
+    db.ensure_contact_exists(&group.creator_peer_id)?;
+
     db.conn().execute(
         r#"
         INSERT OR REPLACE INTO groups (id, group_name, group_description, avatar_hash, creator_peer_id, created_at, is_left)
@@ -40,6 +43,7 @@ pub fn save_group(db: &Database, group: &Group) -> Result<()> {
             GroupRole::Member
         };
 
+        db.ensure_contact_exists(peer_id)?;
         db.conn().execute(
             r#"
             INSERT INTO group_members (group_id, peer_id, role, joined_at)
@@ -165,6 +169,9 @@ pub fn load_all_groups(db: &Database) -> Result<Vec<Group>> {
 pub fn add_member(db: &Database, group_id: &str, peer_id: &str, role: GroupRole) -> Result<()> {
     let joined_at = chrono::Utc::now().timestamp();
 
+    // Members may not be contacts yet (FOREIGN KEY constraint)
+    db.ensure_contact_exists(peer_id)?;
+
     db.conn().execute(
         r#"
         INSERT OR REPLACE INTO group_members (group_id, peer_id, role, joined_at)
@@ -257,6 +264,30 @@ pub fn update_group_metadata(
     Ok(())
 }
 
+/// Record an admin action as applied; `false` if it already was
+pub fn record_admin_action(db: &Database, group_id: &str, message_id: &str, sent_at: i64) -> Result<bool> {
+    let inserted = db.conn().execute(
+        r#"
+        INSERT OR IGNORE INTO group_admin_actions (message_id, group_id, sent_at)
+        VALUES (?1, ?2, ?3)
+        "#,
+        rusqlite::params![message_id, group_id, sent_at],
+    )?;
+
+    Ok(inserted > 0)
+}
+
+/// Send time of the latest admin action applied to a group
+pub fn latest_admin_action(db: &Database, group_id: &str) -> Result<Option<i64>> {
+    let latest = db.conn().query_row(
+        "SELECT MAX(sent_at) FROM group_admin_actions WHERE group_id = ?1",
+        [group_id],
+        |row| row.get(0),
+    )?;
+
+    Ok(latest)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -2,6 +2,7 @@
 //!
 //! Data structures for group messaging.
 
+use crate::utils::error::{MePassaError, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 
@@ -173,6 +174,33 @@ pub struct GroupMessage {
     pub signature: Vec<u8>,
 }
 
+impl GroupMessage {
+    /// Bytes covered by the sender signature (every field except `signature`)
+    pub fn signing_bytes(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(&(
+            &self.message_id,
+            &self.group_id,
+            &self.sender_peer_id,
+            &self.message_type,
+            &self.content,
+            self.timestamp,
+        ))
+        .map_err(|e| MePassaError::Protocol(format!("Failed to encode group message: {}", e)))
+    }
+
+    /// Encode for publishing on the group topic
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(self)
+            .map_err(|e| MePassaError::Protocol(format!("Failed to encode group message: {}", e)))
+    }
+
+    /// Decode a message received on a group topic
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        serde_json::from_slice(data)
+            .map_err(|e| MePassaError::Protocol(format!("Invalid group message: {}", e)))
+    }
+}
+
 /// Group message type
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -249,8 +277,8 @@ pub enum GroupEvent {
     /// Left a group
     GroupLeft { group_id: String },
 
-    /// New message received
-    MessageReceived { message: GroupMessage },
+    /// New message received (`content` is the decrypted payload)
+    MessageReceived { message: GroupMessage, content: Vec<u8> },
 
     /// Member added
     MemberAdded { group_id: String, peer_id: String },
@@ -260,6 +288,14 @@ pub enum GroupEvent {
 
     /// Group metadata updated
     GroupUpdated { group: Group },
+
+    /// Incoming group message dropped (bad signature, missing authority, ...)
+    MessageRejected {
+        group_id: String,
+        message_id: String,
+        sender_peer_id: String,
+        reason: String,
+    },
 }
 
 #[cfg(test)]
@@ -294,4 +330,26 @@ mod tests {
         assert_eq!(GroupRole::from_str("admin"), Some(GroupRole::Admin));
         assert_eq!(GroupRole::from_str("invalid"), None);
     }
+
+    #[test]
+    fn test_group_message_roundtrip() {
+        let message = GroupMessage {
+            message_id: "msg-1".to_string(),
+            group_id: "group-1".to_string(),
+            sender_peer_id: "peer-1".to_string(),
+            message_type: GroupMessageType::Text,
+            content: vec![1, 2, 3],
+            timestamp: 1_700_000_000,
+            signature: vec![9; 64],
+        };
+
+        let decoded = GroupMessage::from_bytes(&message.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded.message_id, "msg-1");
+        assert_eq!(decoded.content, vec![1, 2, 3]);
+
+        // The signature is not part of the signed bytes
+        let mut unsigned = decoded.clone();
+        unsigned.signature.clear();
+        assert_eq!(unsigned.signing_bytes().unwrap(), message.signing_bytes().unwrap());
+    }
 }
@@ -0,0 +1,163 @@
+//! Identity backups
+//!
+//! A backup carries everything needed to bring an account back on a new
+//! install with the same peer id: the identity keypair, the prekey pool, the
+//! contacts and, optionally, the one-to-one message history. It is sealed
+//! with AES-256-GCM under a key derived from a recovery passphrase with
+//! Argon2id.
+//!
+//! The sealed form is `crypto::passphrase`'s, tagged "MPBK".
+
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::passphrase::{self, KdfParams};
+use crate::identity::PreKeyPoolState;
+use crate::utils::error::{MePassaError, Result};
+
+/// Marks a MePassa backup
+const MAGIC: &[u8; 4] = b"MPBK";
+
+/// Current backup format
+pub const BACKUP_VERSION: u8 = 1;
+
+/// A contact in a backup
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupContact {
+    pub peer_id: String,
+    pub username: Option<String>,
+    pub display_name: Option<String>,
+    pub public_key: Vec<u8>,
+    pub prekey_bundle_json: Option<String>,
+}
+
+/// A one-to-one message in a backup, with its content in the clear
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupMessage {
+    pub message_id: String,
+    pub conversation_id: String,
+    pub sender_peer_id: String,
+    pub recipient_peer_id: Option<String>,
+    pub message_type: String,
+    pub content: String,
+    pub created_at: i64,
+    pub status: String,
+    pub parent_message_id: Option<String>,
+}
+
+/// What a backup holds
+///
+/// ⚠️ **WARNING**: Holds the identity secret; only leaves memory sealed.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BackupContents {
+    /// Ed25519 secret key of the identity
+    pub keypair: [u8; 32],
+    /// Prekey pool, so peers holding our bundle can still start sessions
+    pub prekey_pool: Option<PreKeyPoolState>,
+    pub contacts: Vec<BackupContact>,
+    /// Empty unless history was included
+    #[serde(default)]
+    pub messages: Vec<BackupMessage>,
+    /// Unix timestamp of the export
+    pub created_at: i64,
+}
+
+impl std::fmt::Debug for BackupContents {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BackupContents")
+            .field("prekey_pool", &self.prekey_pool)
+            .field("contacts", &self.contacts.len())
+            .field("messages", &self.messages.len())
+            .field("created_at", &self.created_at)
+            .finish_non_exhaustive()
+    }
+}
+
+impl BackupContents {
+    /// Seal with a key derived from `passphrase` with the default cost
+    pub fn seal(&self, passphrase: &str) -> Result<Vec<u8>> {
+        self.seal_with_params(passphrase, KdfParams::default())
+    }
+
+    /// Seal with a key derived from `passphrase` with the given cost
+    pub fn seal_with_params(&self, passphrase: &str, params: KdfParams) -> Result<Vec<u8>> {
+        let plaintext = serde_json::to_vec(self)
+            .map_err(|e| MePassaError::Other(format!("Failed to serialize backup: {}", e)))?;
+        passphrase::seal(MAGIC, BACKUP_VERSION, &plaintext, passphrase, params)
+    }
+
+    /// Open a sealed backup
+    ///
+    /// # Errors
+    ///
+    /// Returns error if `bytes` isn't a backup of a known version, or if the
+    /// passphrase is wrong or the backup was altered
+    pub fn open(bytes: &[u8], passphrase: &str) -> Result<Self> {
+        match passphrase::sealed_version(MAGIC, bytes) {
+            None => return Err(MePassaError::Other("Not a MePassa backup".to_string())),
+            Some(version) if version != BACKUP_VERSION => {
+                return Err(MePassaError::Other(format!(
+                    "Unsupported backup version: {}",
+                    version
+                )));
+            }
+            Some(_) => {}
+        }
+        let plaintext = passphrase::open(MAGIC, BACKUP_VERSION, bytes, passphrase)?;
+
+        serde_json::from_slice(&plaintext)
+            .map_err(|e| MePassaError::Other(format!("Failed to parse backup: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::{Keypair, PreKeyPool};
+
+    /// Cheap parameters so the tests stay fast in debug builds
+    const TEST_PARAMS: KdfParams = KdfParams {
+        memory_kib: 64,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    fn contents() -> BackupContents {
+        let keypair = Keypair::generate();
+        BackupContents {
+            keypair: keypair.to_bytes(),
+            prekey_pool: Some(PreKeyPool::new(keypair, 3).export_state()),
+            contacts: vec![BackupContact {
+                peer_id: "bob".to_string(),
+                username: Some("bob".to_string()),
+                display_name: None,
+                public_key: vec![1, 2, 3],
+                prekey_bundle_json: None,
+            }],
+            messages: Vec::new(),
+            created_at: 1_700_000_000,
+        }
+    }
+
+    #[test]
+    fn test_seal_and_open() {
+        let contents = contents();
+        let sealed = contents.seal_with_params("correct horse", TEST_PARAMS).unwrap();
+
+        let opened = BackupContents::open(&sealed, "correct horse").unwrap();
+        assert_eq!(opened.keypair, contents.keypair);
+        assert_eq!(opened.contacts, contents.contacts);
+        assert_eq!(opened.prekey_pool.unwrap().one_time_prekeys.len(), 3);
+
+        // Salt and nonce are fresh for every export
+        let again = contents.seal_with_params("correct horse", TEST_PARAMS).unwrap();
+        assert_ne!(sealed, again);
+    }
+
+    #[test]
+    fn test_open_rejects_wrong_passphrase() {
+        let sealed = contents().seal_with_params("correct horse", TEST_PARAMS).unwrap();
+        assert!(BackupContents::open(&sealed, "wrong horse").is_err());
+        assert!(BackupContents::open(b"MPBK", "correct horse").is_err());
+        assert!(contents().seal_with_params("", TEST_PARAMS).is_err());
+    }
+}
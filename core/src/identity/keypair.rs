@@ -100,6 +100,12 @@ impl Keypair {
         Self::from_bytes(secret_bytes)
     }
 
+    /// Convert to a libp2p keypair (for the network layer)
+    pub fn to_libp2p_keypair(&self) -> Result<libp2p::identity::Keypair> {
+        libp2p::identity::Keypair::ed25519_from_bytes(self.to_bytes())
+            .map_err(|e| MePassaError::Identity(format!("Invalid Ed25519 key: {}", e)))
+    }
+
     /// Export the secret key as bytes (32 bytes)
     ///
     /// ⚠️ **WARNING**: Keep this secret! Never expose or transmit the secret key.
@@ -268,6 +274,33 @@ impl PublicKey {
 
         Self::from_bytes(&bytes)
     }
+
+    /// Extract the Ed25519 public key embedded in a libp2p peer ID
+    ///
+    /// Ed25519 peer IDs inline the public key (identity multihash), so the
+    /// key can be recovered without any lookup.
+    ///
+    /// # Errors
+    ///
+    /// Returns error if the peer ID doesn't embed an Ed25519 public key
+    pub fn from_libp2p_peer_id(peer_id: &libp2p::PeerId) -> Result<Self> {
+        const IDENTITY_MULTIHASH: u64 = 0x00;
+
+        let multihash = peer_id.as_ref();
+        if multihash.code() != IDENTITY_MULTIHASH {
+            return Err(MePassaError::Identity(format!(
+                "Peer ID {} does not embed its public key",
+                peer_id
+            )));
+        }
+
+        let public_key = libp2p::identity::PublicKey::try_decode_protobuf(multihash.digest())
+            .map_err(|e| MePassaError::Identity(format!("Invalid public key in peer ID: {}", e)))?
+            .try_into_ed25519()
+            .map_err(|_| MePassaError::Identity("Only Ed25519 keypairs are supported".to_string()))?;
+
+        Self::from_bytes(&public_key.to_bytes())
+    }
 }
 
 impl fmt::Display for PublicKey {
@@ -382,6 +415,16 @@ mod tests {
         assert_eq!(keypair.public_key_bytes(), public_key.to_bytes());
     }
 
+    #[test]
+    fn test_public_key_from_libp2p_peer_id() {
+        let libp2p_keypair = libp2p::identity::Keypair::generate_ed25519();
+        let keypair = Keypair::from_libp2p_keypair(&libp2p_keypair).unwrap();
+        let peer_id = libp2p::PeerId::from(libp2p_keypair.public());
+
+        let public_key = PublicKey::from_libp2p_peer_id(&peer_id).unwrap();
+        assert_eq!(public_key.to_bytes(), keypair.public_key_bytes());
+    }
+
     #[test]
     fn test_invalid_peer_id() {
         let result = PublicKey::from_peer_id("invalid_peer_id");
@@ -1,18 +1,23 @@
 //! Identity management module
 //!
-//! Handles Ed25519 keypairs, peer identity, and prekeys (X25519).
+//! Handles Ed25519 keypairs, peer identity, prekeys (X25519) and their
+//! storage, key protection at rest and passphrase-protected identity backups.
 //!
 //! # Examples
 //!
 //! ```no_run
-//! use mepassa_core::identity::{Identity, FileIdentityStorage, IdentityStorage};
+//! use std::sync::Arc;
+//! use mepassa_core::identity::{
+//!     Identity, FileIdentityStorage, IdentityStorage, PassphraseProtector,
+//! };
 //!
 //! // Generate a new identity
 //! let identity = Identity::generate(100);
 //! println!("Peer ID: {}", identity.peer_id());
 //!
-//! // Save to storage
-//! let storage = FileIdentityStorage::new("./data");
+//! // Save to storage, sealed under a passphrase
+//! let protector = Arc::new(PassphraseProtector::new("correct horse battery staple"));
+//! let storage = FileIdentityStorage::with_protector("./data", protector);
 //! storage.save_identity(&identity).unwrap();
 //!
 //! // Load from storage
@@ -20,10 +25,21 @@
 //! assert_eq!(identity.peer_id(), loaded.peer_id());
 //! ```
 
+pub mod backup;
 pub mod keypair;
+pub mod prekey_store;
 pub mod prekeys;
+pub mod protector;
 pub mod storage;
 
 pub use keypair::{Keypair, PublicKey};
-pub use prekeys::{PreKey, PreKeyBundle, PreKeyPool, OneTimePreKey};
+pub use backup::{BackupContact, BackupContents, BackupMessage};
+pub use prekey_store::{
+    PreKeyDirectory, PreKeyPoolStore, DIRECTORY_REFILL_THRESHOLD, PREKEY_POOL_SIZE,
+};
+pub use prekeys::{
+    OneTimePreKey, PreKey, PreKeyBundle, PreKeyPool, PreKeyPoolState, SignedPreKeyPolicy,
+    MAX_HANDED_OUT_PREKEYS,
+};
+pub use protector::{KeyProtector, NoProtection, PassphraseProtector};
 pub use storage::{Identity, IdentityStorage, FileIdentityStorage, MemoryIdentityStorage};
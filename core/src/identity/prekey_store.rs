@@ -0,0 +1,126 @@
+//! Prekey persistence and publication
+//!
+//! The prekey pool is kept in the `prekey_pool` table, encrypted with the
+//! database key, so bundles contacts fetched before a restart still work.
+//! A consumed one-time prekey is simply gone from the stored pool; its ID is
+//! never handed out again because the pool only counts upwards.
+//!
+//! When the pool runs low it is refilled and the new public prekeys go to a
+//! [`PreKeyDirectory`] (the identity server), so lookups keep getting fresh
+//! one-time prekeys. The server hands each one out only once, so it is also
+//! topped up when its own count drops below [`DIRECTORY_REFILL_THRESHOLD`].
+
+use async_trait::async_trait;
+use zeroize::Zeroizing;
+
+use crate::crypto::{decrypt_for_storage, encrypt_for_storage};
+use crate::identity::{Keypair, OneTimePreKey, PreKeyBundle, PreKeyPool, PreKeyPoolState};
+use crate::storage::Database;
+use crate::utils::error::{MePassaError, Result};
+
+/// One-time prekeys kept in the pool after a refill
+pub const PREKEY_POOL_SIZE: usize = 100;
+
+/// Directory count under which it gets new one-time prekeys, back up to
+/// `PREKEY_POOL_SIZE`
+pub const DIRECTORY_REFILL_THRESHOLD: usize = 20;
+
+/// Server handing out our prekeys to initiators (the identity server)
+#[async_trait]
+pub trait PreKeyDirectory: Send + Sync {
+    /// Publish our current bundle along with new one-time prekeys
+    async fn publish_prekeys(
+        &self,
+        bundle: &PreKeyBundle,
+        one_time_prekeys: &[OneTimePreKey],
+    ) -> Result<()>;
+
+    /// One-time prekeys the directory has left to hand out, or None if it
+    /// doesn't hand them out one by one
+    async fn one_time_prekey_count(&self) -> Result<Option<usize>> {
+        Ok(None)
+    }
+}
+
+/// SQLite backing store for the prekey pool
+#[derive(Clone)]
+pub struct PreKeyPoolStore {
+    database: Database,
+    storage_key: [u8; 32],
+}
+
+impl std::fmt::Debug for PreKeyPoolStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PreKeyPoolStore").finish_non_exhaustive()
+    }
+}
+
+impl PreKeyPoolStore {
+    /// Store the pool in `database`, encrypted with `storage_key`
+    pub fn new(database: Database, storage_key: [u8; 32]) -> Self {
+        Self {
+            database,
+            storage_key,
+        }
+    }
+
+    /// Write the pool
+    ///
+    /// As JSON, so pools saved before a field was added still load.
+    pub fn save(&self, pool: &PreKeyPool) -> Result<()> {
+        let bytes = Zeroizing::new(
+            serde_json::to_vec(&pool.export_state())
+                .map_err(|e| MePassaError::Crypto(format!("Prekey pool serialize failed: {}", e)))?,
+        );
+        let blob = encrypt_for_storage(&self.storage_key, &bytes)?;
+        self.database.save_prekey_pool(&blob)?;
+        Ok(())
+    }
+
+    /// Read the pool of `identity_keypair`, if one was saved
+    pub fn load(&self, identity_keypair: Keypair) -> Result<Option<PreKeyPool>> {
+        let Some(blob) = self.database.load_prekey_pool()? else {
+            return Ok(None);
+        };
+        let bytes = Zeroizing::new(decrypt_for_storage(&self.storage_key, &blob)?);
+        let state: PreKeyPoolState = serde_json::from_slice(&bytes)
+            .map_err(|e| MePassaError::Crypto(format!("Prekey pool deserialize failed: {}", e)))?;
+
+        PreKeyPool::from_state(identity_keypair, &state).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::schema::init_schema;
+
+    #[test]
+    fn test_save_and_load_pool() {
+        let db = Database::in_memory().unwrap();
+        init_schema(&db).unwrap();
+        let keypair = Keypair::generate();
+        let store = PreKeyPoolStore::new(db.clone(), [7u8; 32]);
+
+        assert!(store.load(keypair.clone()).unwrap().is_none());
+
+        let mut pool = PreKeyPool::new(keypair.clone(), 5);
+        let used = pool.reserve_bundle().one_time_prekey.unwrap().id;
+        pool.remove_prekey(used);
+        store.save(&pool).unwrap();
+
+        let loaded = store.load(keypair.clone()).unwrap().unwrap();
+        assert_eq!(loaded.prekey_count(), 4);
+        assert!(loaded.get_prekey(used).is_none());
+        assert_eq!(
+            loaded.signed_prekey().public_bytes(),
+            pool.signed_prekey().public_bytes()
+        );
+
+        // The pool is stored encrypted, and only opens with the right key
+        let blob = db.load_prekey_pool().unwrap().unwrap();
+        let secret = pool.signed_prekey().secret_bytes();
+        assert!(!blob.windows(32).any(|window| window == secret));
+        assert!(PreKeyPoolStore::new(db, [8u8; 32]).load(keypair).is_err());
+    }
+}
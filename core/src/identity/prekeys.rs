@@ -6,13 +6,22 @@
 
 use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
 use x25519_dalek::{PublicKey as X25519PublicKey, StaticSecret};
 
 use crate::utils::error::{Result, MePassaError};
 
+/// Most one-time prekeys kept once given out but not yet used
+///
+/// Initiators that look us up often never start a session, so handed-out
+/// keys would otherwise pile up forever. Past this many, the oldest are
+/// dropped; an initiator still holding one gets its message answered without
+/// the one-time prekey and retries with signed-prekey-only X3DH.
+pub const MAX_HANDED_OUT_PREKEYS: usize = 300;
+
 // Custom serialization for [u8; 64] arrays
-mod serde_bytes_64 {
+pub(crate) mod serde_bytes_64 {
     use serde::{Deserialize, Deserializer, Serializer};
 
     pub fn serialize<S>(bytes: &[u8; 64], serializer: S) -> Result<S::Ok, S::Error>
@@ -151,6 +160,34 @@ pub struct OneTimePreKey {
     pub public_key: [u8; 32],
 }
 
+/// When the signed prekey is rotated, and how long replaced ones are kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignedPreKeyPolicy {
+    /// Age at which the signed prekey is replaced
+    pub rotation_interval: Duration,
+    /// How long a replaced signed prekey still answers initiators that
+    /// fetched our bundle before the rotation
+    pub grace_period: Duration,
+}
+
+impl Default for SignedPreKeyPolicy {
+    /// Weekly rotation, old keys kept for two weeks
+    fn default() -> Self {
+        Self {
+            rotation_interval: Duration::from_secs(7 * 24 * 60 * 60),
+            grace_period: Duration::from_secs(14 * 24 * 60 * 60),
+        }
+    }
+}
+
+/// A replaced signed prekey, kept for the grace period
+#[derive(Clone)]
+struct RetiredPreKey {
+    prekey: PreKey,
+    /// Unix timestamp of the rotation that replaced it
+    retired_at: i64,
+}
+
 /// Pool of prekeys for key agreement
 ///
 /// Signal Protocol recommends maintaining:
@@ -164,8 +201,15 @@ pub struct PreKeyPool {
     signed_prekey: PreKey,
     /// Signature over signed prekey
     signed_prekey_signature: [u8; 64],
+    /// Unix timestamp the current signed prekey was generated at
+    signed_prekey_created_at: i64,
+    /// Replaced signed prekeys still within their grace period
+    retired_signed_prekeys: Vec<RetiredPreKey>,
     /// Pool of one-time prekeys
     one_time_prekeys: HashMap<u32, PreKey>,
+    /// One-time prekeys already given out (in a bundle or to a directory),
+    /// which must not go to anyone else, oldest first
+    handed_out: VecDeque<u32>,
     /// Next prekey ID to assign
     next_prekey_id: u32,
 }
@@ -182,7 +226,10 @@ impl PreKeyPool {
             identity_keypair: identity_keypair.clone(),
             signed_prekey: PreKey::generate(1),
             signed_prekey_signature: [0u8; 64],
+            signed_prekey_created_at: chrono::Utc::now().timestamp(),
+            retired_signed_prekeys: Vec::new(),
             one_time_prekeys: HashMap::new(),
+            handed_out: VecDeque::new(),
             next_prekey_id: 2,
         };
 
@@ -205,27 +252,63 @@ impl PreKeyPool {
         self.signed_prekey_signature
     }
 
+    /// Get the signed prekey with `id`: the current one, or a replaced one
+    /// still within its grace period
+    ///
+    /// Used for processing incoming X3DH messages
+    pub fn signed_prekey_by_id(&self, id: u32) -> Option<&PreKey> {
+        if self.signed_prekey.id == id {
+            return Some(&self.signed_prekey);
+        }
+        self.retired_signed_prekeys
+            .iter()
+            .map(|retired| &retired.prekey)
+            .find(|prekey| prekey.id == id)
+    }
+
     /// Replenish one-time prekeys to reach target count
     ///
     /// # Arguments
     ///
     /// * `target_count` - Desired number of prekeys in the pool
-    pub fn replenish_prekeys(&mut self, target_count: usize) {
+    ///
+    /// # Returns
+    ///
+    /// Public halves of the new prekeys, for publishing
+    pub fn replenish_prekeys(&mut self, target_count: usize) -> Vec<OneTimePreKey> {
         let current_count = self.one_time_prekeys.len();
 
         if current_count >= target_count {
-            return;
+            return Vec::new();
         }
 
-        let to_generate = target_count - current_count;
+        self.generate_one_time_prekeys(target_count - current_count)
+    }
+
+    /// Add `count` new one-time prekeys, whatever the pool holds
+    ///
+    /// Used when a server handing out our prekeys runs low: the keys it gave
+    /// away stay in the pool until an initiator actually uses them.
+    ///
+    /// # Returns
+    ///
+    /// Public halves of the new prekeys, for publishing
+    pub fn generate_one_time_prekeys(&mut self, count: usize) -> Vec<OneTimePreKey> {
+        let mut generated = Vec::with_capacity(count);
 
-        for _ in 0..to_generate {
+        for _ in 0..count {
             let id = self.next_prekey_id;
             self.next_prekey_id += 1;
 
             let prekey = PreKey::generate(id);
+            generated.push(OneTimePreKey {
+                id,
+                public_key: prekey.public_bytes(),
+            });
             self.one_time_prekeys.insert(id, prekey);
         }
+
+        generated
     }
 
     /// Get a prekey bundle for key exchange
@@ -250,6 +333,63 @@ impl PreKeyPool {
         }
     }
 
+    /// Get a prekey bundle without a one-time prekey
+    ///
+    /// Used where the same bundle may reach several initiators (a directory,
+    /// linked devices): they fall back to signed-prekey-only X3DH.
+    pub fn export_bundle(&self) -> PreKeyBundle {
+        PreKeyBundle {
+            identity_key: self.identity_keypair.public_key_bytes(),
+            signed_prekey_id: self.signed_prekey.id,
+            signed_prekey: self.signed_prekey.public_bytes(),
+            signed_prekey_signature: self.signed_prekey_signature,
+            one_time_prekey: None,
+        }
+    }
+
+    /// Get a prekey bundle with a one-time prekey nobody else was given
+    ///
+    /// Used when the bundle is shared directly with one contact instead of
+    /// being handed out by a server. The key stays in the pool until the
+    /// initiator actually uses it (see [`Self::remove_prekey`]); a new one is
+    /// generated if every key was already given out.
+    pub fn reserve_bundle(&mut self) -> PreKeyBundle {
+        let available = self
+            .one_time_prekeys
+            .keys()
+            .filter(|id| !self.handed_out.contains(id))
+            .min()
+            .copied();
+        let id = match available {
+            Some(id) => id,
+            None => self.generate_one_time_prekeys(1)[0].id,
+        };
+        self.handed_out.push_back(id);
+        self.expire_handed_out();
+
+        let mut bundle = self.export_bundle();
+        bundle.one_time_prekey = self.one_time_prekeys.get(&id).map(|pk| OneTimePreKey {
+            id: pk.id,
+            public_key: pk.public_bytes(),
+        });
+        bundle
+    }
+
+    /// Record one-time prekeys given to a directory, so no bundle offers them
+    pub fn mark_handed_out(&mut self, prekeys: &[OneTimePreKey]) {
+        self.handed_out.extend(prekeys.iter().map(|prekey| prekey.id));
+        self.expire_handed_out();
+    }
+
+    /// Drop the oldest handed-out prekeys past [`MAX_HANDED_OUT_PREKEYS`]
+    fn expire_handed_out(&mut self) {
+        while self.handed_out.len() > MAX_HANDED_OUT_PREKEYS {
+            if let Some(id) = self.handed_out.pop_front() {
+                self.one_time_prekeys.remove(&id);
+            }
+        }
+    }
+
     /// Consume and remove one one-time prekey from the pool
     ///
     /// Returns None if pool is empty (should trigger replenishment)
@@ -260,7 +400,7 @@ impl PreKeyPool {
 
         // Get any prekey (doesn't matter which one)
         let id = *self.one_time_prekeys.keys().next()?;
-        self.one_time_prekeys.remove(&id)
+        self.remove_prekey(id)
     }
 
     /// Get a specific one-time prekey by ID (without consuming)
@@ -272,22 +412,53 @@ impl PreKeyPool {
 
     /// Remove a specific one-time prekey after use
     pub fn remove_prekey(&mut self, id: u32) -> Option<PreKey> {
+        self.handed_out.retain(|handed_out| *handed_out != id);
         self.one_time_prekeys.remove(&id)
     }
 
     /// Rotate the signed prekey
     ///
-    /// Should be called periodically (e.g., every 7 days) for forward secrecy
+    /// Should be called periodically (e.g., every 7 days) for forward secrecy.
+    /// The replaced key is kept (see [`Self::signed_prekey_by_id`]) until
+    /// [`Self::rotate_signed_prekey_if_due`] finds its grace period over.
     pub fn rotate_signed_prekey(&mut self) {
+        self.rotate_signed_prekey_at(chrono::Utc::now().timestamp());
+    }
+
+    fn rotate_signed_prekey_at(&mut self, now: i64) {
         let new_id = self.next_prekey_id;
         self.next_prekey_id += 1;
 
-        self.signed_prekey = PreKey::generate(new_id);
+        let replaced = std::mem::replace(&mut self.signed_prekey, PreKey::generate(new_id));
+        self.retired_signed_prekeys.push(RetiredPreKey {
+            prekey: replaced,
+            retired_at: now,
+        });
+        self.signed_prekey_created_at = now;
         self.signed_prekey_signature = self
             .identity_keypair
             .sign(&self.signed_prekey.public_bytes());
     }
 
+    /// Drop replaced signed prekeys past their grace period, and rotate the
+    /// signed prekey if it is older than the rotation interval
+    ///
+    /// # Returns
+    ///
+    /// true if the signed prekey was rotated (the bundle changed)
+    pub fn rotate_signed_prekey_if_due(&mut self, now: i64, policy: &SignedPreKeyPolicy) -> bool {
+        let grace = policy.grace_period.as_secs() as i64;
+        self.retired_signed_prekeys
+            .retain(|retired| now < retired.retired_at.saturating_add(grace));
+
+        let age = now.saturating_sub(self.signed_prekey_created_at);
+        if age < policy.rotation_interval.as_secs() as i64 {
+            return false;
+        }
+        self.rotate_signed_prekey_at(now);
+        true
+    }
+
     /// Get count of remaining one-time prekeys
     pub fn prekey_count(&self) -> usize {
         self.one_time_prekeys.len()
@@ -299,12 +470,131 @@ impl PreKeyPool {
     pub fn needs_replenishment(&self) -> bool {
         self.prekey_count() < 20
     }
+
+    /// Export the pool's secret state
+    pub fn export_state(&self) -> PreKeyPoolState {
+        let mut one_time_prekeys: Vec<(u32, [u8; 32])> = self
+            .one_time_prekeys
+            .values()
+            .map(|prekey| (prekey.id, prekey.secret_bytes()))
+            .collect();
+        one_time_prekeys.sort_by_key(|(id, _)| *id);
+
+        PreKeyPoolState {
+            signed_prekey_id: self.signed_prekey.id,
+            signed_prekey: self.signed_prekey.secret_bytes(),
+            signed_prekey_signature: self.signed_prekey_signature,
+            signed_prekey_created_at: self.signed_prekey_created_at,
+            retired_signed_prekeys: self
+                .retired_signed_prekeys
+                .iter()
+                .map(|retired| {
+                    (
+                        retired.prekey.id,
+                        retired.prekey.secret_bytes(),
+                        retired.retired_at,
+                    )
+                })
+                .collect(),
+            one_time_prekeys,
+            handed_out: self.handed_out.iter().copied().collect(),
+            next_prekey_id: self.next_prekey_id,
+        }
+    }
+
+    /// Rebuild a pool from exported state
+    ///
+    /// # Errors
+    ///
+    /// Returns error if the signed prekey wasn't signed by `identity_keypair`
+    pub fn from_state(identity_keypair: crate::identity::Keypair, state: &PreKeyPoolState) -> Result<Self> {
+        let signed_prekey = PreKey::from_bytes(state.signed_prekey_id, &state.signed_prekey)?;
+        identity_keypair
+            .verify(&signed_prekey.public_bytes(), &state.signed_prekey_signature)
+            .map_err(|_| {
+                MePassaError::Identity("Signed prekey belongs to another identity".to_string())
+            })?;
+
+        let one_time_prekeys = state
+            .one_time_prekeys
+            .iter()
+            .map(|(id, secret)| PreKey::from_bytes(*id, secret).map(|prekey| (*id, prekey)))
+            .collect::<Result<HashMap<_, _>>>()?;
+        let retired_signed_prekeys = state
+            .retired_signed_prekeys
+            .iter()
+            .map(|(id, secret, retired_at)| {
+                PreKey::from_bytes(*id, secret).map(|prekey| RetiredPreKey {
+                    prekey,
+                    retired_at: *retired_at,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            identity_keypair,
+            signed_prekey,
+            signed_prekey_signature: state.signed_prekey_signature,
+            signed_prekey_created_at: state.signed_prekey_created_at,
+            retired_signed_prekeys,
+            one_time_prekeys,
+            handed_out: state.handed_out.iter().copied().collect(),
+            next_prekey_id: state.next_prekey_id,
+        })
+    }
+}
+
+/// Secret state of a prekey pool, for backups
+///
+/// ⚠️ **WARNING**: Holds the prekey secrets; only store it encrypted.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PreKeyPoolState {
+    /// Signed prekey ID
+    pub signed_prekey_id: u32,
+    /// Signed prekey secret bytes
+    pub signed_prekey: [u8; 32],
+    /// Signature over signed prekey
+    #[serde(with = "serde_bytes_64")]
+    pub signed_prekey_signature: [u8; 64],
+    /// Unix timestamp the signed prekey was generated at (0 if unknown, which
+    /// makes it due for rotation)
+    #[serde(default)]
+    pub signed_prekey_created_at: i64,
+    /// Replaced signed prekeys in their grace period: ID, secret bytes and
+    /// unix timestamp of the rotation
+    #[serde(default)]
+    pub retired_signed_prekeys: Vec<(u32, [u8; 32], i64)>,
+    /// One-time prekey IDs and secret bytes
+    pub one_time_prekeys: Vec<(u32, [u8; 32])>,
+    /// IDs of the one-time prekeys already given out, oldest first
+    #[serde(default)]
+    pub handed_out: Vec<u32>,
+    /// Next prekey ID to assign
+    pub next_prekey_id: u32,
+}
+
+impl std::fmt::Debug for PreKeyPoolState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PreKeyPoolState")
+            .field("signed_prekey_id", &self.signed_prekey_id)
+            .field(
+                "retired_signed_prekey_count",
+                &self.retired_signed_prekeys.len(),
+            )
+            .field("one_time_prekey_count", &self.one_time_prekeys.len())
+            .field("next_prekey_id", &self.next_prekey_id)
+            .finish_non_exhaustive()
+    }
 }
 
 impl std::fmt::Debug for PreKeyPool {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("PreKeyPool")
             .field("signed_prekey_id", &self.signed_prekey.id)
+            .field(
+                "retired_signed_prekey_count",
+                &self.retired_signed_prekeys.len(),
+            )
             .field("one_time_prekey_count", &self.one_time_prekeys.len())
             .field("next_prekey_id", &self.next_prekey_id)
             .finish_non_exhaustive()
@@ -317,6 +607,7 @@ pub type PreKeyStore = PreKeyPool;
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashSet;
 
     #[test]
     fn test_prekey_generation() {
@@ -381,6 +672,67 @@ mod tests {
         assert_eq!(pool.prekey_count(), 3);
     }
 
+    #[test]
+    fn test_reserve_bundle_keeps_prekey() {
+        let identity = crate::identity::Keypair::generate();
+        let mut pool = PreKeyPool::new(identity, 5);
+        assert!(pool.export_bundle().one_time_prekey.is_none());
+
+        let bundle = pool.reserve_bundle();
+        let otpk = bundle.one_time_prekey.unwrap();
+
+        assert_eq!(pool.prekey_count(), 5);
+        assert!(pool.get_prekey(otpk.id).is_some());
+    }
+
+    #[test]
+    fn test_reserved_bundles_get_distinct_prekeys() {
+        let identity = crate::identity::Keypair::generate();
+        let mut pool = PreKeyPool::new(identity, 2);
+        // Keys given to a directory aren't offered again
+        let published = pool.generate_one_time_prekeys(1);
+        pool.mark_handed_out(&published);
+
+        let ids: Vec<u32> = (0..4)
+            .map(|_| pool.reserve_bundle().one_time_prekey.unwrap().id)
+            .collect();
+        let unique: HashSet<u32> = ids.iter().copied().collect();
+        assert_eq!(unique.len(), 4);
+        assert!(!unique.contains(&published[0].id));
+        // Two came from the pool, two were generated once it ran out
+        assert_eq!(pool.prekey_count(), 5);
+    }
+
+    #[test]
+    fn test_handed_out_prekeys_expire() {
+        let identity = crate::identity::Keypair::generate();
+        let mut pool = PreKeyPool::new(identity, 10);
+
+        // A directory refilled over and over, its keys never used
+        let mut published = Vec::new();
+        for _ in 0..10 {
+            let fresh = pool.generate_one_time_prekeys(100);
+            pool.mark_handed_out(&fresh);
+            published.extend(fresh);
+        }
+        assert_eq!(pool.prekey_count(), 10 + MAX_HANDED_OUT_PREKEYS);
+
+        // The newest handed-out keys are kept, the oldest are gone
+        let (expired, kept) = published.split_at(published.len() - MAX_HANDED_OUT_PREKEYS);
+        assert!(expired.iter().all(|prekey| pool.get_prekey(prekey.id).is_none()));
+        assert!(kept.iter().all(|prekey| pool.get_prekey(prekey.id).is_some()));
+
+        // Keys never given out stay; reserving one expires the oldest handed
+        // out, not the reserved key with its lower ID
+        let otpk = pool.reserve_bundle().one_time_prekey.unwrap();
+        assert!(otpk.id < published[0].id);
+        assert!(pool.get_prekey(otpk.id).is_some());
+        assert!(pool.get_prekey(kept[0].id).is_none());
+        assert_eq!(pool.prekey_count(), 9 + MAX_HANDED_OUT_PREKEYS);
+        let restored = PreKeyPool::from_state(pool.identity_keypair.clone(), &pool.export_state()).unwrap();
+        assert_eq!(restored.prekey_count(), pool.prekey_count());
+    }
+
     #[test]
     fn test_prekey_replenishment() {
         let identity = crate::identity::Keypair::generate();
@@ -394,8 +746,11 @@ mod tests {
         assert_eq!(pool.prekey_count(), 0);
 
         // Replenish
-        pool.replenish_prekeys(100);
+        let fresh = pool.replenish_prekeys(100);
         assert_eq!(pool.prekey_count(), 100);
+        assert_eq!(fresh.len(), 100);
+        // New prekeys never reuse the IDs of consumed ones
+        assert!(fresh.iter().all(|prekey| prekey.id > 10 && pool.get_prekey(prekey.id).is_some()));
     }
 
     #[test]
@@ -428,6 +783,51 @@ mod tests {
 
         assert_ne!(old_id, new_id);
         assert_ne!(old_public, new_public);
+
+        // The replaced key still answers initiators that have it
+        assert_eq!(
+            pool.signed_prekey_by_id(old_id).unwrap().public_bytes(),
+            old_public
+        );
+        assert_eq!(
+            pool.signed_prekey_by_id(new_id).unwrap().public_bytes(),
+            new_public
+        );
+    }
+
+    #[test]
+    fn test_rotate_signed_prekey_if_due() {
+        let identity = crate::identity::Keypair::generate();
+        let mut pool = PreKeyPool::new(identity.clone(), 10);
+        let policy = SignedPreKeyPolicy {
+            rotation_interval: Duration::from_secs(100),
+            grace_period: Duration::from_secs(50),
+        };
+        let start = pool.signed_prekey_created_at;
+        let first = pool.signed_prekey().id;
+
+        assert!(!pool.rotate_signed_prekey_if_due(start + 99, &policy));
+        assert_eq!(pool.signed_prekey().id, first);
+
+        assert!(pool.rotate_signed_prekey_if_due(start + 100, &policy));
+        let second = pool.signed_prekey().id;
+        assert_ne!(second, first);
+        identity
+            .verify(
+                &pool.signed_prekey().public_bytes(),
+                &pool.signed_prekey_signature(),
+            )
+            .unwrap();
+
+        // The replaced key is kept for the grace period, and survives a restart
+        assert!(!pool.rotate_signed_prekey_if_due(start + 149, &policy));
+        let restored = PreKeyPool::from_state(identity, &pool.export_state()).unwrap();
+        assert!(restored.signed_prekey_by_id(first).is_some());
+        assert!(pool.signed_prekey_by_id(first).is_some());
+
+        assert!(!pool.rotate_signed_prekey_if_due(start + 150, &policy));
+        assert!(pool.signed_prekey_by_id(first).is_none());
+        assert!(pool.signed_prekey_by_id(second).is_some());
     }
 
     #[test]
@@ -446,4 +846,32 @@ mod tests {
         assert_eq!(bundle.identity_key, deserialized.identity_key);
         assert_eq!(bundle.signed_prekey_id, deserialized.signed_prekey_id);
     }
+
+    #[test]
+    fn test_pool_state_roundtrip() {
+        let identity = crate::identity::Keypair::generate();
+        let mut pool = PreKeyPool::new(identity.clone(), 5);
+        pool.remove_prekey(3);
+        let reserved = pool.reserve_bundle().one_time_prekey.unwrap().id;
+
+        let state = pool.export_state();
+        let json = serde_json::to_string(&state).unwrap();
+        let state: PreKeyPoolState = serde_json::from_str(&json).unwrap();
+        let restored = PreKeyPool::from_state(identity, &state).unwrap();
+
+        assert_eq!(restored.prekey_count(), 4);
+        assert!(restored.get_prekey(3).is_none());
+        assert_eq!(
+            restored.get_prekey(4).unwrap().public_bytes(),
+            pool.get_prekey(4).unwrap().public_bytes()
+        );
+        assert_eq!(restored.export_bundle().signed_prekey, pool.export_bundle().signed_prekey);
+        // The reserved key isn't offered again
+        let mut restored = restored;
+        assert_ne!(restored.reserve_bundle().one_time_prekey.unwrap().id, reserved);
+
+        // Another identity didn't sign the signed prekey
+        let other = crate::identity::Keypair::generate();
+        assert!(PreKeyPool::from_state(other, &state).is_err());
+    }
 }
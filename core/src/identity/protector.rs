@@ -0,0 +1,113 @@
+//! Key protection at rest
+//!
+//! A `KeyProtector` wraps the identity secret and the database key before
+//! `FileIdentityStorage` writes them to disk. `PassphraseProtector` derives
+//! the wrapping key from a passphrase; platform keystores (Android Keystore,
+//! iOS Keychain) plug in through the FFI as their own implementation.
+
+use zeroize::Zeroizing;
+
+use crate::crypto::passphrase::{self, KdfParams};
+use crate::utils::error::Result;
+
+/// Wraps secrets before they are written to disk
+pub trait KeyProtector: Send + Sync {
+    /// Name stored with the wrapped secrets, to tell which protector opens them
+    fn kind(&self) -> &str;
+
+    /// Wrap `key` for storage
+    fn wrap_key(&self, key: &[u8]) -> Result<Vec<u8>>;
+
+    /// Unwrap what `wrap_key` returned
+    fn unwrap_key(&self, wrapped: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Stores secrets as they are
+///
+/// ⚠️ **WARNING**: Anyone who can read the data directory gets the identity
+/// and the messages. Only for development and tests.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoProtection;
+
+impl KeyProtector for NoProtection {
+    fn kind(&self) -> &str {
+        "none"
+    }
+
+    fn wrap_key(&self, key: &[u8]) -> Result<Vec<u8>> {
+        Ok(key.to_vec())
+    }
+
+    fn unwrap_key(&self, wrapped: &[u8]) -> Result<Vec<u8>> {
+        Ok(wrapped.to_vec())
+    }
+}
+
+/// Wraps secrets under a key derived from a passphrase with Argon2id
+pub struct PassphraseProtector {
+    passphrase: Zeroizing<String>,
+    params: KdfParams,
+}
+
+/// Marks secrets wrapped by a passphrase
+const MAGIC: &[u8; 4] = b"MPKP";
+const VERSION: u8 = 1;
+
+impl PassphraseProtector {
+    /// Protector with the default Argon2id cost
+    pub fn new(passphrase: impl Into<String>) -> Self {
+        Self::with_params(passphrase, KdfParams::default())
+    }
+
+    /// Protector with the given Argon2id cost (used when wrapping only;
+    /// unwrapping uses the cost stored with the secrets)
+    pub fn with_params(passphrase: impl Into<String>, params: KdfParams) -> Self {
+        Self {
+            passphrase: Zeroizing::new(passphrase.into()),
+            params,
+        }
+    }
+}
+
+impl std::fmt::Debug for PassphraseProtector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PassphraseProtector")
+            .field("params", &self.params)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KeyProtector for PassphraseProtector {
+    fn kind(&self) -> &str {
+        "passphrase"
+    }
+
+    fn wrap_key(&self, key: &[u8]) -> Result<Vec<u8>> {
+        passphrase::seal(MAGIC, VERSION, key, &self.passphrase, self.params)
+    }
+
+    fn unwrap_key(&self, wrapped: &[u8]) -> Result<Vec<u8>> {
+        passphrase::open(MAGIC, VERSION, wrapped, &self.passphrase)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passphrase_protector() {
+        let params = KdfParams {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let protector = PassphraseProtector::with_params("correct horse", params);
+        let wrapped = protector.wrap_key(&[7u8; 64]).unwrap();
+        assert!(!wrapped.windows(64).any(|window| window == [7u8; 64]));
+        assert_eq!(protector.unwrap_key(&wrapped).unwrap(), vec![7u8; 64]);
+
+        let wrong = PassphraseProtector::with_params("wrong horse", params);
+        assert!(wrong.unwrap_key(&wrapped).is_err());
+    }
+}
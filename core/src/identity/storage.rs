@@ -4,13 +4,18 @@
 //! platform-specific secure storage (Keychain on iOS/macOS, Keystore on Android).
 //!
 //! **Note**: This is the Rust interface. Platform-specific implementations
-//! are provided via FFI from the host application.
+//! are provided via FFI from the host application, as a `KeyProtector`
+//! that wraps the secrets `FileIdentityStorage` writes.
 
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use hkdf::Hkdf;
 use sha2::Sha256;
+use zeroize::Zeroizing;
 
+use crate::identity::protector::{KeyProtector, NoProtection};
 use crate::identity::{Keypair, PreKeyPool};
 use crate::utils::error::{Result, MePassaError};
 
@@ -23,6 +28,8 @@ pub struct Identity {
     peer_id: String,
     /// PreKey pool for Signal Protocol
     prekey_pool: Option<PreKeyPool>,
+    /// Key encrypting the database contents
+    storage_key: [u8; 32],
 }
 
 impl Identity {
@@ -49,20 +56,37 @@ impl Identity {
             keypair,
             peer_id,
             prekey_pool,
+            storage_key: random_storage_key(),
         }
     }
 
-    /// Create identity from existing keypair
+    /// Create identity from existing keypair, with a new database key
     pub fn from_keypair(keypair: Keypair) -> Self {
+        Self::with_storage_key(keypair, random_storage_key())
+    }
+
+    /// Create identity from existing keypair and database key
+    pub(crate) fn with_storage_key(keypair: Keypair, storage_key: [u8; 32]) -> Self {
         let peer_id = keypair.peer_id();
 
         Self {
             keypair,
             peer_id,
             prekey_pool: None,
+            storage_key,
         }
     }
 
+    /// Identity of a data directory from before database keys were random:
+    /// its database key was derived from the identity secret
+    pub(crate) fn from_legacy_keypair(keypair: Keypair) -> Result<Self> {
+        let hkdf = Hkdf::<Sha256>::new(Some(b"mepassa-storage-v1"), &keypair.to_bytes());
+        let mut storage_key = [0u8; 32];
+        hkdf.expand(b"storage-key", &mut storage_key)
+            .map_err(|e| MePassaError::Crypto(format!("Storage key derivation failed: {}", e)))?;
+        Ok(Self::with_storage_key(keypair, storage_key))
+    }
+
     /// Get peer ID
     pub fn peer_id(&self) -> &str {
         &self.peer_id
@@ -90,6 +114,11 @@ impl Identity {
         }
     }
 
+    /// Replace the prekey pool (e.g. with one restored from a backup)
+    pub fn set_prekey_pool(&mut self, pool: PreKeyPool) {
+        self.prekey_pool = Some(pool);
+    }
+
     /// Sign a message with this identity
     pub fn sign(&self, message: &[u8]) -> [u8; 64] {
         self.keypair.sign(message)
@@ -100,17 +129,21 @@ impl Identity {
         self.keypair.verify(message, signature)
     }
 
-    /// Derive a stable storage encryption key from the identity keypair
-    pub fn storage_key(&self) -> Result<[u8; 32]> {
-        let key_bytes = self.keypair.to_bytes();
-        let hkdf = Hkdf::<Sha256>::new(Some(b"mepassa-storage-v1"), &key_bytes);
-        let mut out = [0u8; 32];
-        hkdf.expand(b"storage-key", &mut out)
-            .map_err(|e| MePassaError::Crypto(format!("Storage key derivation failed: {}", e)))?;
-        Ok(out)
+    /// Key encrypting the database contents
+    ///
+    /// Random and independent of the identity secret; `IdentityStorage`
+    /// keeps it next to the keypair.
+    pub fn storage_key(&self) -> [u8; 32] {
+        self.storage_key
     }
 }
 
+fn random_storage_key() -> [u8; 32] {
+    let mut key = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut key);
+    key
+}
+
 impl std::fmt::Debug for Identity {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Identity")
@@ -123,8 +156,16 @@ impl std::fmt::Debug for Identity {
 /// Serializable identity data for persistence
 #[derive(Serialize, Deserialize)]
 struct IdentityData {
-    keypair_bytes: Vec<u8>,
     peer_id: String,
+    /// Kind of the protector that wrapped `sealed_keys`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    protector: Option<String>,
+    /// Identity secret followed by the database key, wrapped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sealed_keys: Option<Vec<u8>>,
+    /// Identity secret in the clear (files written before key protection)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    keypair_bytes: Option<Vec<u8>>,
     // Prekey pool is persisted separately due to size
 }
 
@@ -149,23 +190,36 @@ pub trait IdentityStorage: Send + Sync {
     fn has_identity(&self) -> Result<bool>;
 }
 
-/// File-based identity storage (for development/testing)
+/// File-based identity storage
+///
+/// The identity secret and the database key are wrapped by a `KeyProtector`
+/// before they are written.
 ///
-/// ⚠️ **WARNING**: This stores keys in plaintext. DO NOT use in production!
-/// Use platform-specific secure storage instead.
+/// ⚠️ **WARNING**: `new` stores keys in plaintext. DO NOT use in production!
+/// Use `with_protector` with a passphrase or platform keystore instead.
 pub struct FileIdentityStorage {
     data_dir: PathBuf,
+    protector: Arc<dyn KeyProtector>,
 }
 
 impl FileIdentityStorage {
-    /// Create a new file-based storage
+    /// Create a new file-based storage without key protection
     ///
     /// # Arguments
     ///
     /// * `data_dir` - Directory for storing identity file
     pub fn new<P: AsRef<Path>>(data_dir: P) -> Self {
+        Self::with_protector(data_dir, Arc::new(NoProtection))
+    }
+
+    /// Create a new file-based storage wrapping keys with `protector`
+    ///
+    /// An unprotected identity found on load is re-saved with `protector`;
+    /// one wrapped by another kind of protector fails to load.
+    pub fn with_protector<P: AsRef<Path>>(data_dir: P, protector: Arc<dyn KeyProtector>) -> Self {
         Self {
             data_dir: data_dir.as_ref().to_path_buf(),
+            protector,
         }
     }
 
@@ -181,15 +235,25 @@ impl IdentityStorage for FileIdentityStorage {
             MePassaError::Storage(format!("Failed to create data directory: {}", e))
         })?;
 
+        let mut secrets = Zeroizing::new(Vec::with_capacity(64));
+        secrets.extend_from_slice(&identity.keypair.to_bytes());
+        secrets.extend_from_slice(&identity.storage_key);
+
         let data = IdentityData {
-            keypair_bytes: identity.keypair.to_bytes().to_vec(),
             peer_id: identity.peer_id.clone(),
+            protector: Some(self.protector.kind().to_string()),
+            sealed_keys: Some(self.protector.wrap_key(&secrets)?),
+            keypair_bytes: None,
         };
 
         let json = serde_json::to_string_pretty(&data)
             .map_err(|e| MePassaError::Storage(format!("Failed to serialize identity: {}", e)))?;
 
-        std::fs::write(self.identity_path(), json)
+        // Written aside and renamed, so a crash never leaves half an identity
+        let path = self.identity_path();
+        let temp_path = path.with_extension("json.tmp");
+        std::fs::write(&temp_path, json)
+            .and_then(|()| std::fs::rename(&temp_path, &path))
             .map_err(|e| MePassaError::Storage(format!("Failed to write identity: {}", e)))?;
 
         Ok(())
@@ -208,8 +272,49 @@ impl IdentityStorage for FileIdentityStorage {
         let data: IdentityData = serde_json::from_str(&json)
             .map_err(|e| MePassaError::Storage(format!("Failed to deserialize identity: {}", e)))?;
 
-        let keypair = Keypair::from_bytes(&data.keypair_bytes)?;
-        let identity = Identity::from_keypair(keypair);
+        let (identity, resave) = match (data.sealed_keys, data.keypair_bytes) {
+            (Some(sealed), _) => {
+                let kind = data.protector.unwrap_or_else(|| NoProtection.kind().to_string());
+                let secrets = Zeroizing::new(if kind == self.protector.kind() {
+                    self.protector.unwrap_key(&sealed)?
+                } else if kind == NoProtection.kind() {
+                    NoProtection.unwrap_key(&sealed)?
+                } else {
+                    return Err(MePassaError::Identity(format!(
+                        "Identity is protected by {}, not {}",
+                        kind,
+                        self.protector.kind()
+                    )));
+                });
+                if secrets.len() != 64 {
+                    return Err(MePassaError::Identity(format!(
+                        "Invalid protected keys length: expected 64 bytes, got {}",
+                        secrets.len()
+                    )));
+                }
+                let mut storage_key = [0u8; 32];
+                storage_key.copy_from_slice(&secrets[32..]);
+                let identity =
+                    Identity::with_storage_key(Keypair::from_bytes(&secrets[..32])?, storage_key);
+                (identity, kind != self.protector.kind())
+            }
+            (None, Some(keypair_bytes)) => {
+                (Identity::from_legacy_keypair(Keypair::from_bytes(&keypair_bytes)?)?, true)
+            }
+            (None, None) => {
+                return Err(MePassaError::Storage("Identity file holds no keys".to_string()));
+            }
+        };
+
+        if identity.peer_id != data.peer_id {
+            return Err(MePassaError::Identity(
+                "Identity file doesn't match its peer ID".to_string(),
+            ));
+        }
+        if resave {
+            self.save_identity(&identity)?;
+            tracing::info!("Identity re-saved with {} key protection", self.protector.kind());
+        }
 
         Ok(Some(identity))
     }
@@ -380,6 +485,76 @@ mod tests {
         let _ = std::fs::remove_dir_all(&temp_dir);
     }
 
+    fn passphrase(passphrase: &str) -> Arc<dyn KeyProtector> {
+        let params = crate::crypto::KdfParams {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        };
+        Arc::new(crate::identity::PassphraseProtector::with_params(passphrase, params))
+    }
+
+    #[test]
+    fn test_file_storage_with_protector() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let storage = FileIdentityStorage::with_protector(temp_dir.path(), passphrase("hunter22"));
+
+        let identity = Identity::generate(10);
+        storage.save_identity(&identity).unwrap();
+        let loaded = storage.load_identity().unwrap().unwrap();
+        assert_eq!(loaded.peer_id(), identity.peer_id());
+        assert_eq!(loaded.storage_key(), identity.storage_key());
+
+        // Neither secret is on disk in the clear
+        let file = std::fs::read_to_string(temp_dir.path().join("identity.json")).unwrap();
+        let data: IdentityData = serde_json::from_str(&file).unwrap();
+        assert!(data.keypair_bytes.is_none());
+        let sealed = data.sealed_keys.unwrap();
+        for secret in [identity.keypair().to_bytes(), identity.storage_key()] {
+            assert!(!sealed.windows(32).any(|window| window == secret));
+        }
+
+        let wrong = FileIdentityStorage::with_protector(temp_dir.path(), passphrase("hunter2"));
+        assert!(wrong.load_identity().is_err());
+        assert!(FileIdentityStorage::new(temp_dir.path()).load_identity().is_err());
+    }
+
+    #[test]
+    fn test_unprotected_files_are_upgraded() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let keypair = Keypair::generate();
+        let legacy = serde_json::json!({
+            "keypair_bytes": keypair.to_bytes().to_vec(),
+            "peer_id": keypair.peer_id(),
+        });
+        std::fs::write(temp_dir.path().join("identity.json"), legacy.to_string()).unwrap();
+
+        // Files from before key protection keep their derived database key
+        let storage = FileIdentityStorage::with_protector(temp_dir.path(), passphrase("hunter22"));
+        let loaded = storage.load_identity().unwrap().unwrap();
+        let expected = Identity::from_legacy_keypair(keypair.clone()).unwrap();
+        assert_eq!(loaded.peer_id(), keypair.peer_id());
+        assert_eq!(loaded.storage_key(), expected.storage_key());
+
+        // ...and are re-saved protected
+        let file = std::fs::read_to_string(temp_dir.path().join("identity.json")).unwrap();
+        let data: IdentityData = serde_json::from_str(&file).unwrap();
+        assert!(data.keypair_bytes.is_none());
+        assert_eq!(data.protector.as_deref(), Some("passphrase"));
+        let reloaded = storage.load_identity().unwrap().unwrap();
+        assert_eq!(reloaded.storage_key(), expected.storage_key());
+
+        // An unprotected identity moves to the protector it is opened with
+        let other_dir = tempfile::TempDir::new().unwrap();
+        FileIdentityStorage::new(other_dir.path()).save_identity(&loaded).unwrap();
+        let protected = FileIdentityStorage::with_protector(other_dir.path(), passphrase("hunter22"));
+        assert_eq!(
+            protected.load_identity().unwrap().unwrap().storage_key(),
+            expected.storage_key()
+        );
+        assert!(FileIdentityStorage::new(other_dir.path()).load_identity().is_err());
+    }
+
     #[test]
     fn test_identity_sign_verify() {
         let identity = Identity::generate(10);
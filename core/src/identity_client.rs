@@ -3,12 +3,22 @@
 //! HTTP client for communicating with the Identity Server to register and lookup @usernames.
 
 use anyhow::{anyhow, Result};
+use async_trait::async_trait;
 use base64::{engine::general_purpose, Engine as _};
 use chrono::{DateTime, Utc};
+use libp2p::PeerId;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::time::Duration;
 
-use crate::identity::{Identity, PreKeyBundle as CorePreKeyBundle};
+use crate::identity::{
+    Identity, OneTimePreKey as CoreOneTimePreKey, PreKeyBundle as CorePreKeyBundle,
+    PreKeyDirectory,
+};
+use crate::sync::device::{
+    DeviceCertificate as CoreDeviceCertificate, DeviceDirectory, LinkedDevice as CoreLinkedDevice,
+};
+use crate::utils::error::MePassaError;
 
 /// Prekey Bundle for Identity Server API
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -113,14 +123,216 @@ pub struct LookupResponse {
 struct UpdatePrekeysRequest {
     peer_id: String,
     prekey_bundle: PreKeyBundle,
+    /// Additional one-time prekeys for the server to hand out
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    one_time_prekeys: Vec<OneTimePreKey>,
+    nonce: String,
     signature: String,
     timestamp: i64,
 }
 
+impl UpdatePrekeysRequest {
+    /// Unsigned request for `bundle` and `one_time_prekeys`, with a fresh nonce
+    fn new(
+        peer_id: String,
+        bundle: &CorePreKeyBundle,
+        one_time_prekeys: &[CoreOneTimePreKey],
+    ) -> Self {
+        Self {
+            peer_id,
+            prekey_bundle: PreKeyBundle::from_core(bundle),
+            one_time_prekeys: one_time_prekeys
+                .iter()
+                .map(|prekey| OneTimePreKey {
+                    id: prekey.id as i32,
+                    public_key: general_purpose::STANDARD.encode(prekey.public_key),
+                })
+                .collect(),
+            nonce: uuid::Uuid::new_v4().simple().to_string(),
+            signature: String::new(),
+            timestamp: Utc::now().timestamp(),
+        }
+    }
+
+    /// Message the peer signs, covering the uploaded keys
+    ///
+    /// Format: "update_prekeys:{peer_id}:{timestamp}:{nonce}:{prekeys_digest}",
+    /// where the digest is the hex SHA-256 of one line per field: identity
+    /// key, signed prekey id, signed prekey, its signature, the bundle's
+    /// one-time prekey ("{id}:{public_key}" or "-"), then each uploaded
+    /// one-time prekey
+    fn challenge(&self) -> String {
+        let bundle = &self.prekey_bundle;
+        let prekey_line =
+            |prekey: &OneTimePreKey| format!("{}:{}\n", prekey.id, prekey.public_key);
+
+        let mut hasher = Sha256::new();
+        hasher.update(format!(
+            "{}\n{}\n{}\n{}\n",
+            bundle.identity_key,
+            bundle.signed_prekey_id,
+            bundle.signed_prekey,
+            bundle.signed_prekey_signature
+        ));
+        match &bundle.one_time_prekey {
+            Some(prekey) => hasher.update(prekey_line(prekey)),
+            None => hasher.update("-\n"),
+        }
+        for prekey in &self.one_time_prekeys {
+            hasher.update(prekey_line(prekey));
+        }
+
+        format!(
+            "update_prekeys:{}:{}:{}:{:x}",
+            self.peer_id,
+            self.timestamp,
+            self.nonce,
+            hasher.finalize()
+        )
+    }
+}
+
 /// Update prekeys response
 #[derive(Debug, Deserialize)]
 pub struct UpdatePrekeysResponse {
     pub updated_at: DateTime<Utc>,
+    /// One-time prekeys the server has left after the upload
+    #[serde(default)]
+    pub one_time_prekey_count: i64,
+}
+
+/// Prekey count response
+#[derive(Debug, Deserialize)]
+pub struct PrekeyCountResponse {
+    pub peer_id: String,
+    pub one_time_prekey_count: i64,
+}
+
+/// Device certificate in API format
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceCertificate {
+    pub account_peer_id: String,
+    pub device_peer_id: String,
+    pub device_name: String,
+    pub requested_at: i64,
+    pub device_signature: String,
+    pub issued_at: i64,
+    pub signature: String,
+}
+
+impl DeviceCertificate {
+    /// Convert from a core certificate to API format
+    pub fn from_core(certificate: &CoreDeviceCertificate) -> Self {
+        Self {
+            account_peer_id: certificate.account_peer_id.to_string(),
+            device_peer_id: certificate.device_peer_id.to_string(),
+            device_name: certificate.device_name.clone(),
+            requested_at: certificate.requested_at,
+            device_signature: general_purpose::STANDARD.encode(&certificate.device_signature),
+            issued_at: certificate.issued_at,
+            signature: general_purpose::STANDARD.encode(&certificate.signature),
+        }
+    }
+
+    /// Convert to a core certificate, checking its signatures
+    pub fn to_core(&self) -> Result<CoreDeviceCertificate> {
+        let certificate = CoreDeviceCertificate {
+            account_peer_id: self.account_peer_id.parse()?,
+            device_peer_id: self.device_peer_id.parse()?,
+            device_name: self.device_name.clone(),
+            requested_at: self.requested_at,
+            device_signature: general_purpose::STANDARD.decode(&self.device_signature)?,
+            issued_at: self.issued_at,
+            signature: general_purpose::STANDARD.decode(&self.signature)?,
+        };
+        certificate.verify()?;
+        Ok(certificate)
+    }
+}
+
+/// Publish device request
+#[derive(Debug, Serialize)]
+struct PublishDeviceRequest {
+    signer_peer_id: String,
+    certificate: DeviceCertificate,
+    prekey_bundle: PreKeyBundle,
+    nonce: String,
+    signature: String,
+    timestamp: i64,
+}
+
+impl PublishDeviceRequest {
+    /// Unsigned request publishing `device`, with a fresh nonce
+    fn new(signer_peer_id: String, device: &CoreLinkedDevice) -> Self {
+        Self {
+            signer_peer_id,
+            certificate: DeviceCertificate::from_core(&device.certificate),
+            prekey_bundle: PreKeyBundle::from_core(&device.prekey_bundle),
+            nonce: uuid::Uuid::new_v4().simple().to_string(),
+            signature: String::new(),
+            timestamp: Utc::now().timestamp(),
+        }
+    }
+
+    /// Message the signer signs, covering the certificate and bundle
+    ///
+    /// Format: "devices:{signer}:{device}:{timestamp}:{nonce}:{publish_digest}",
+    /// where the digest is the hex SHA-256 of one line per field: the
+    /// certificate's account, device, device name, requested_at, device
+    /// signature, issued_at and signature, then the bundle's identity key,
+    /// signed prekey id, signed prekey, its signature and one-time prekey
+    /// ("{id}:{public_key}" or "-")
+    fn challenge(&self) -> String {
+        let certificate = &self.certificate;
+        let bundle = &self.prekey_bundle;
+
+        let mut hasher = Sha256::new();
+        hasher.update(format!(
+            "{}\n{}\n{}\n{}\n{}\n{}\n{}\n",
+            certificate.account_peer_id,
+            certificate.device_peer_id,
+            certificate.device_name,
+            certificate.requested_at,
+            certificate.device_signature,
+            certificate.issued_at,
+            certificate.signature
+        ));
+        hasher.update(format!(
+            "{}\n{}\n{}\n{}\n",
+            bundle.identity_key,
+            bundle.signed_prekey_id,
+            bundle.signed_prekey,
+            bundle.signed_prekey_signature
+        ));
+        match &bundle.one_time_prekey {
+            Some(prekey) => hasher.update(format!("{}:{}\n", prekey.id, prekey.public_key)),
+            None => hasher.update("-\n"),
+        }
+
+        format!(
+            "devices:{}:{}:{}:{}:{:x}",
+            self.signer_peer_id,
+            certificate.device_peer_id,
+            self.timestamp,
+            self.nonce,
+            hasher.finalize()
+        )
+    }
+}
+
+/// A device of an account, as listed by the server
+#[derive(Debug, Deserialize)]
+pub struct LinkedDeviceResponse {
+    pub certificate: DeviceCertificate,
+    pub prekey_bundle: PreKeyBundle,
+    pub last_updated: DateTime<Utc>,
+}
+
+/// Device list response
+#[derive(Debug, Deserialize)]
+pub struct DevicesResponse {
+    pub account_peer_id: String,
+    pub devices: Vec<LinkedDeviceResponse>,
 }
 
 /// Error response from Identity Server
@@ -174,12 +386,12 @@ impl IdentityClient {
         username: &str,
         peer_id: &str,
     ) -> Result<RegisterResponse> {
-        // Get prekey bundle
-        let mut identity_mut = identity.clone();
-        let prekey_bundle = identity_mut
-            .prekey_pool_mut()
+        // Get prekey bundle (one-time prekeys are uploaded in batches, see
+        // `IdentityServerDirectory`, so none is taken from the pool here)
+        let prekey_bundle = identity
+            .prekey_pool()
             .ok_or_else(|| anyhow!("No prekey pool"))?
-            .get_bundle();
+            .export_bundle();
 
         // Create signature
         let timestamp = Utc::now().timestamp();
@@ -250,25 +462,16 @@ impl IdentityClient {
         identity: &Identity,
         peer_id: &str,
     ) -> Result<UpdatePrekeysResponse> {
-        // Get new prekey bundle
-        let mut identity_mut = identity.clone();
-        let prekey_bundle = identity_mut
-            .prekey_pool_mut()
+        // Get new prekey bundle (without a one-time prekey, as on registration)
+        let prekey_bundle = identity
+            .prekey_pool()
             .ok_or_else(|| anyhow!("No prekey pool"))?
-            .get_bundle();
-
-        // Create signature
-        let timestamp = Utc::now().timestamp();
-        let message = format!("update_prekeys:{}:{}", peer_id, timestamp);
-        let signature = identity.keypair().sign(message.as_bytes());
+            .export_bundle();
 
-        // Build request
-        let request = UpdatePrekeysRequest {
-            peer_id: peer_id.to_string(),
-            prekey_bundle: PreKeyBundle::from_core(&prekey_bundle),
-            signature: general_purpose::STANDARD.encode(signature),
-            timestamp,
-        };
+        // Build and sign request
+        let mut request = UpdatePrekeysRequest::new(peer_id.to_string(), &prekey_bundle, &[]);
+        let signature = identity.keypair().sign(request.challenge().as_bytes());
+        request.signature = general_purpose::STANDARD.encode(signature);
 
         // Send request
         let url = format!("{}/api/v1/prekeys", self.base_url);
@@ -283,6 +486,85 @@ impl IdentityClient {
         }
     }
 
+    /// Publish a bundle and new one-time prekeys, signed by `keypair`
+    ///
+    /// Same request as `update_prekeys`, for callers holding the libp2p
+    /// keypair rather than the `Identity`.
+    pub async fn publish_prekeys(
+        &self,
+        keypair: &libp2p::identity::Keypair,
+        bundle: &CorePreKeyBundle,
+        one_time_prekeys: &[CoreOneTimePreKey],
+    ) -> Result<UpdatePrekeysResponse> {
+        let peer_id = keypair.public().to_peer_id().to_string();
+
+        let mut request = UpdatePrekeysRequest::new(peer_id, bundle, one_time_prekeys);
+        let signature = keypair.sign(request.challenge().as_bytes())?;
+        request.signature = general_purpose::STANDARD.encode(signature);
+
+        let url = format!("{}/api/v1/prekeys", self.base_url);
+        let response = self.client.put(&url).json(&request).send().await?;
+
+        if response.status().is_success() {
+            Ok(response.json().await?)
+        } else {
+            let error: ErrorResponse = response.json().await?;
+            Err(anyhow!("{}: {}", error.error, error.message))
+        }
+    }
+
+    /// One-time prekeys the server has left to hand out for a peer
+    ///
+    /// Each lookup takes one; upload more with `publish_prekeys` when low.
+    pub async fn prekey_count(&self, peer_id: &str) -> Result<PrekeyCountResponse> {
+        let url = format!("{}/api/v1/prekeys/count?peer_id={}", self.base_url, peer_id);
+        let response = self.client.get(&url).send().await?;
+
+        if response.status().is_success() {
+            Ok(response.json().await?)
+        } else {
+            let error: ErrorResponse = response.json().await?;
+            Err(anyhow!("{}: {}", error.error, error.message))
+        }
+    }
+
+    /// Publish a device linked to an account
+    ///
+    /// Signed by `keypair`, which must be the account's or the device's.
+    pub async fn publish_device(
+        &self,
+        keypair: &libp2p::identity::Keypair,
+        device: &CoreLinkedDevice,
+    ) -> Result<()> {
+        let mut request =
+            PublishDeviceRequest::new(keypair.public().to_peer_id().to_string(), device);
+        let signature = keypair.sign(request.challenge().as_bytes())?;
+        request.signature = general_purpose::STANDARD.encode(signature);
+
+        let url = format!("{}/api/v1/devices", self.base_url);
+        let response = self.client.put(&url).json(&request).send().await?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            let error: ErrorResponse = response.json().await?;
+            Err(anyhow!("{}: {}", error.error, error.message))
+        }
+    }
+
+    /// Devices linked to an account
+    pub async fn list_devices(&self, account_peer_id: &str) -> Result<DevicesResponse> {
+        let url = format!("{}/api/v1/devices?peer_id={}", self.base_url, account_peer_id);
+        let response = self.client.get(&url).send().await?;
+
+        if response.status().is_success() {
+            Ok(response.json().await?)
+        } else {
+            let error: ErrorResponse = response.json().await?;
+            Err(anyhow!("{}: {}", error.error, error.message))
+        }
+    }
+
     /// Check Identity Server health
     pub async fn health_check(&self) -> Result<serde_json::Value> {
         let url = format!("{}/health", self.base_url);
@@ -296,6 +578,79 @@ impl IdentityClient {
     }
 }
 
+/// Device and prekey directory backed by the Identity Server
+pub struct IdentityServerDirectory {
+    client: IdentityClient,
+    keypair: libp2p::identity::Keypair,
+}
+
+impl IdentityServerDirectory {
+    /// Publish and fetch devices and prekeys at `base_url`, signing with `keypair`
+    pub fn new(base_url: impl Into<String>, keypair: libp2p::identity::Keypair) -> Result<Self> {
+        Ok(Self {
+            client: IdentityClient::new(base_url)?,
+            keypair,
+        })
+    }
+}
+
+#[async_trait]
+impl DeviceDirectory for IdentityServerDirectory {
+    async fn publish_device(&self, device: &CoreLinkedDevice) -> crate::utils::error::Result<()> {
+        self.client
+            .publish_device(&self.keypair, device)
+            .await
+            .map_err(|e| MePassaError::Network(format!("Failed to publish device: {}", e)))
+    }
+
+    async fn fetch_devices(&self, account: &PeerId) -> crate::utils::error::Result<Vec<CoreLinkedDevice>> {
+        let response = self
+            .client
+            .list_devices(&account.to_string())
+            .await
+            .map_err(|e| MePassaError::Network(format!("Failed to fetch devices: {}", e)))?;
+
+        // Devices that don't verify are skipped, not trusted
+        Ok(response
+            .devices
+            .into_iter()
+            .filter_map(|device| {
+                let device = CoreLinkedDevice {
+                    certificate: device.certificate.to_core().ok()?,
+                    prekey_bundle: device.prekey_bundle.to_core().ok()?,
+                };
+                (device.certificate.account_peer_id == *account && device.verify().is_ok())
+                    .then_some(device)
+            })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl PreKeyDirectory for IdentityServerDirectory {
+    async fn publish_prekeys(
+        &self,
+        bundle: &CorePreKeyBundle,
+        one_time_prekeys: &[CoreOneTimePreKey],
+    ) -> crate::utils::error::Result<()> {
+        self.client
+            .publish_prekeys(&self.keypair, bundle, one_time_prekeys)
+            .await
+            .map(|_| ())
+            .map_err(|e| MePassaError::Network(format!("Failed to publish prekeys: {}", e)))
+    }
+
+    async fn one_time_prekey_count(&self) -> crate::utils::error::Result<Option<usize>> {
+        let peer_id = self.keypair.public().to_peer_id().to_string();
+        let response = self
+            .client
+            .prekey_count(&peer_id)
+            .await
+            .map_err(|e| MePassaError::Network(format!("Failed to count prekeys: {}", e)))?;
+        Ok(Some(response.one_time_prekey_count.max(0) as usize))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -323,6 +678,38 @@ mod tests {
         assert_eq!(core_bundle.signed_prekey, converted_bundle.signed_prekey);
     }
 
+    #[test]
+    fn test_device_certificate_conversion() {
+        let account = libp2p::identity::Keypair::generate_ed25519();
+        let account_keypair = crate::identity::Keypair::from_libp2p_keypair(&account).unwrap();
+        let device = libp2p::identity::Keypair::generate_ed25519();
+        let mut device_identity =
+            Identity::from_keypair(crate::identity::Keypair::from_libp2p_keypair(&device).unwrap());
+        device_identity.init_prekey_pool(1);
+        let request = crate::sync::device::LinkRequest::new(
+            device_identity.keypair(),
+            device.public().to_peer_id(),
+            "Laptop",
+            device_identity.prekey_pool().unwrap().export_bundle(),
+            Vec::new(),
+            1_000,
+        );
+        let certificate = CoreDeviceCertificate::issue(
+            &account_keypair,
+            account.public().to_peer_id(),
+            &request,
+            1_000,
+        )
+        .unwrap();
+
+        let api_certificate = DeviceCertificate::from_core(&certificate);
+        assert_eq!(api_certificate.to_core().unwrap(), certificate);
+
+        let mut renamed = api_certificate;
+        renamed.device_name = "Phone".to_string();
+        assert!(renamed.to_core().is_err());
+    }
+
     #[test]
     fn test_client_creation() {
         let client = IdentityClient::new("http://localhost:8080").unwrap();
@@ -352,10 +739,20 @@ mod tests {
     async fn test_update_prekeys_signature() {
         let identity = Identity::generate(1);
         let peer_id = "12D3KooWTest";
-        let timestamp = Utc::now().timestamp();
+        let bundle = identity.prekey_pool().unwrap().export_bundle();
+        let one_time_prekeys = [CoreOneTimePreKey {
+            id: 7,
+            public_key: [7u8; 32],
+        }];
+
+        let request = UpdatePrekeysRequest::new(peer_id.to_string(), &bundle, &one_time_prekeys);
+        let message = request.challenge();
+        assert!(message.starts_with(&format!(
+            "update_prekeys:{}:{}:{}:",
+            peer_id, request.timestamp, request.nonce
+        )));
 
         // Create signature
-        let message = format!("update_prekeys:{}:{}", peer_id, timestamp);
         let signature = identity.keypair().sign(message.as_bytes());
 
         // Verify signature
@@ -363,6 +760,81 @@ mod tests {
             .keypair()
             .verify(message.as_bytes(), &signature)
             .is_ok());
+
+        // Every request gets its own nonce
+        let again = UpdatePrekeysRequest::new(peer_id.to_string(), &bundle, &one_time_prekeys);
+        assert_ne!(again.nonce, request.nonce);
+
+        // The challenge covers the uploaded keys
+        let mut swapped = UpdatePrekeysRequest {
+            nonce: request.nonce.clone(),
+            timestamp: request.timestamp,
+            ..again
+        };
+        assert_eq!(swapped.challenge(), message);
+        swapped.one_time_prekeys[0].public_key = general_purpose::STANDARD.encode([9u8; 32]);
+        assert_ne!(swapped.challenge(), message);
+    }
+
+    #[test]
+    fn test_publish_device_signature() {
+        use crate::identity::Keypair as CoreKeypair;
+        use crate::sync::device::LinkRequest;
+
+        let identity = |libp2p_keypair: &libp2p::identity::Keypair| {
+            let keypair = CoreKeypair::from_libp2p_keypair(libp2p_keypair).unwrap();
+            let mut identity = Identity::from_keypair(keypair);
+            identity.init_prekey_pool(1);
+            identity
+        };
+        let account_keypair = libp2p::identity::Keypair::generate_ed25519();
+        let device_keypair = libp2p::identity::Keypair::generate_ed25519();
+        let account = identity(&account_keypair);
+        let device_identity = identity(&device_keypair);
+        let bundle = device_identity.prekey_pool().unwrap().export_bundle();
+        let request = LinkRequest::new(
+            device_identity.keypair(),
+            device_keypair.public().to_peer_id(),
+            "Laptop",
+            bundle.clone(),
+            Vec::new(),
+            1_000,
+        );
+        let device = CoreLinkedDevice {
+            certificate: CoreDeviceCertificate::issue(
+                account.keypair(),
+                account_keypair.public().to_peer_id(),
+                &request,
+                1_060,
+            )
+            .unwrap(),
+            prekey_bundle: bundle,
+        };
+
+        let signer = account_keypair.public().to_peer_id().to_string();
+        let request = PublishDeviceRequest::new(signer.clone(), &device);
+        let message = request.challenge();
+        assert!(message.starts_with(&format!(
+            "devices:{}:{}:{}:{}:",
+            signer, device.certificate.device_peer_id, request.timestamp, request.nonce
+        )));
+
+        // Every request gets its own nonce
+        let again = PublishDeviceRequest::new(signer, &device);
+        assert_ne!(again.nonce, request.nonce);
+
+        // The challenge covers the certificate and bundle
+        let mut swapped = PublishDeviceRequest {
+            nonce: request.nonce.clone(),
+            timestamp: request.timestamp,
+            ..again
+        };
+        assert_eq!(swapped.challenge(), message);
+        swapped.prekey_bundle.signed_prekey = general_purpose::STANDARD.encode([9u8; 32]);
+        assert_ne!(swapped.challenge(), message);
+        swapped.prekey_bundle = request.prekey_bundle.clone();
+        swapped.certificate.device_name = "Phone".to_string();
+        assert_ne!(swapped.challenge(), message);
     }
 
     // Integration tests (require Identity Server running)
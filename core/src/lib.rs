@@ -41,8 +41,9 @@ pub use identity::{Identity, Keypair, PublicKey};
 
 // Re-export FFI types (required by UniFFI scaffolding)
 pub use ffi::{
-    FfiConversation, FfiGroup, FfiMedia, FfiMediaType, FfiMessage, FfiReaction, MePassaClient,
-    MePassaFfiError, MessageStatus,
+    FfiClientEvent, FfiConnectionQuality, FfiConnectionState, FfiConnectionType,
+    FfiConversation, FfiGroup, FfiMedia, FfiMediaType, FfiMessage, FfiReachability, FfiReaction,
+    MePassaClient, MePassaFfiError, MessageStatus,
 };
 
 // Re-export VoIP FFI types (always available - stubs when voip feature is disabled)
@@ -63,6 +64,7 @@ pub mod media;
 pub mod network;
 pub mod protocol;
 pub mod storage;
+pub mod store_client;
 pub mod sync;
 #[cfg(any(feature = "voip", feature = "video"))]
 pub mod voip;
@@ -77,6 +79,28 @@ pub trait FfiVideoFrameCallback: Send + Sync {
     fn on_video_frame(&self, call_id: String, frame_data: Vec<u8>, width: u32, height: u32);
 }
 
+// Forward declaration for the media progress callback interface
+pub trait FfiMediaProgressCallback: Send + Sync {
+    fn on_media_progress(
+        &self,
+        message_id: String,
+        media_hash: String,
+        bytes_received: u64,
+        total_bytes: u64,
+    );
+}
+
+// Forward declaration for the client event listener callback interface
+pub trait MePassaEventListener: Send + Sync {
+    fn on_event(&self, event: FfiClientEvent);
+}
+
+// Forward declaration for the platform keystore callback interface
+pub trait FfiKeyProtector: Send + Sync {
+    fn wrap_key(&self, key: Vec<u8>) -> Result<Vec<u8>, MePassaFfiError>;
+    fn unwrap_key(&self, wrapped: Vec<u8>) -> Result<Vec<u8>, MePassaFfiError>;
+}
+
 // Include UniFFI scaffolding (after all module declarations)
 uniffi::include_scaffolding!("mepassa");
 
@@ -0,0 +1,37 @@
+//! AutoNAT reachability probes
+//!
+//! The client side of AutoNAT (see `mepassa-autonat`): the node asks servers
+//! (the bootstrap nodes) to dial it back, and `nat_detection` turns their
+//! answers into a reachability status. Clients only send probes; answering
+//! them is left to the servers, so a client never dials anyone on behalf of
+//! another peer.
+
+use std::time::Duration;
+
+pub use mepassa_autonat::{
+    client_behaviour, AutoNatCodec, DialRequest, DialResponse, ResponseStatus, AUTONAT_PROTOCOL,
+};
+
+/// Probe scheduling
+#[derive(Debug, Clone)]
+pub struct AutoNatConfig {
+    /// Delay before the first probe
+    pub boot_delay: Duration,
+    /// Interval between probes while the status is unknown or unconfirmed
+    pub retry_interval: Duration,
+    /// Interval between probes once the status is confirmed
+    pub refresh_interval: Duration,
+    /// Agreeing probes needed to confirm a status (and to overturn it)
+    pub confidence_max: usize,
+}
+
+impl Default for AutoNatConfig {
+    fn default() -> Self {
+        Self {
+            boot_delay: Duration::from_secs(15),
+            retry_interval: Duration::from_secs(90),
+            refresh_interval: Duration::from_secs(15 * 60),
+            confidence_max: 3,
+        }
+    }
+}
@@ -7,6 +7,8 @@
 //! - Ping (keep-alive)
 //! - GossipSub (will be used for group messaging)
 //! - VoIP Signaling (WebRTC signaling over P2P)
+//! - Relay client (reservations and circuits on a relay server)
+//! - AutoNAT (reachability probes and dial-backs)
 
 use libp2p::{
     dcutr, gossipsub, identify, kad, mdns, ping, relay, request_response, PeerId, StreamProtocol,
@@ -14,6 +16,8 @@ use libp2p::{
 use libp2p::swarm::NetworkBehaviour;
 use std::time::Duration;
 
+use super::autonat::{self, AutoNatCodec};
+use super::framing::{FrameLimits, FramedBehaviour};
 use super::messaging::MePassaCodec;
 use crate::utils::error::MePassaError;
 #[cfg(any(feature = "voip", feature = "video"))]
@@ -33,20 +37,43 @@ pub struct MePassaBehaviour {
     /// GossipSub for pub/sub messaging (groups)
     pub gossipsub: gossipsub::Behaviour,
     /// Request/Response for direct messaging
-    pub request_response: request_response::Behaviour<MePassaCodec>,
+    pub request_response: FramedBehaviour<MePassaCodec>,
     /// Request/Response for VoIP signaling (WebRTC)
     #[cfg(any(feature = "voip", feature = "video"))]
-    pub voip_signaling: request_response::Behaviour<SignalingCodec>,
+    pub voip_signaling: FramedBehaviour<SignalingCodec>,
+    /// Relay client (paired with the relay transport built alongside it)
+    pub relay_client: relay::client::Behaviour,
     /// DCUtR for hole punching (requires relay transport)
     pub dcutr: dcutr::Behaviour,
+    /// AutoNAT probes (client only: dial-backs are the servers' job)
+    pub autonat: request_response::Behaviour<AutoNatCodec>,
 }
 
 impl MePassaBehaviour {
     /// Create a new MePassa network behaviour
-    pub fn new(local_peer_id: PeerId, keypair: &libp2p::identity::Keypair) -> crate::utils::error::Result<Self> {
+    ///
+    /// `relay_client` is the behaviour half of `relay::client::new`; its
+    /// transport half must be part of the swarm's transport.
+    pub fn new(
+        local_peer_id: PeerId,
+        keypair: &libp2p::identity::Keypair,
+        relay_client: relay::client::Behaviour,
+    ) -> crate::utils::error::Result<Self> {
+        Self::with_frame_limits(local_peer_id, keypair, relay_client, FrameLimits::default())
+    }
+
+    /// Create a new MePassa network behaviour with custom codec frame limits
+    pub fn with_frame_limits(
+        local_peer_id: PeerId,
+        keypair: &libp2p::identity::Keypair,
+        relay_client: relay::client::Behaviour,
+        frame_limits: FrameLimits,
+    ) -> crate::utils::error::Result<Self> {
         // Kademlia DHT configuration
         let mut kad_config = kad::Config::default();
         kad_config.set_query_timeout(Duration::from_secs(60));
+        // Records are verified before they are stored (see `peer_record`)
+        kad_config.set_record_filtering(kad::StoreInserts::FilterBoth);
 
         let store = kad::store::MemoryStore::new(local_peer_id);
         let kademlia = kad::Behaviour::with_config(local_peer_id, store, kad_config);
@@ -92,11 +119,11 @@ impl MePassaBehaviour {
             StreamProtocol::new("/mepassa/message/1.0.0"),
             request_response::ProtocolSupport::Full,
         ));
-        let request_response = request_response::Behaviour::with_codec(
-            MePassaCodec,
+        let request_response = FramedBehaviour::new(request_response::Behaviour::with_codec(
+            MePassaCodec::new(frame_limits.message),
             protocols,
             request_response::Config::default(),
-        );
+        ));
 
         // Request/Response for VoIP signaling (WebRTC)
         #[cfg(any(feature = "voip", feature = "video"))]
@@ -105,14 +132,13 @@ impl MePassaBehaviour {
             request_response::ProtocolSupport::Full,
         ));
         #[cfg(any(feature = "voip", feature = "video"))]
-        let voip_signaling = request_response::Behaviour::with_codec(
-            SignalingCodec,
+        let voip_signaling = FramedBehaviour::new(request_response::Behaviour::with_codec(
+            SignalingCodec::new(frame_limits.signaling),
             voip_protocols,
             request_response::Config::default(),
-        );
+        ));
 
-        // DCUtR for hole punching
-        // Note: Relay functionality is integrated at transport level in libp2p 0.53
+        // DCUtR for hole punching over relayed connections
         let dcutr = dcutr::Behaviour::new(local_peer_id);
 
         Ok(Self {
@@ -124,7 +150,9 @@ impl MePassaBehaviour {
             request_response,
             #[cfg(any(feature = "voip", feature = "video"))]
             voip_signaling,
+            relay_client,
             dcutr,
+            autonat: autonat::client_behaviour(),
         })
     }
 }
@@ -139,7 +167,8 @@ mod tests {
         let keypair = identity::Keypair::generate_ed25519();
         let local_peer_id = PeerId::from(keypair.public());
 
-        let behaviour = MePassaBehaviour::new(local_peer_id, &keypair);
+        let (_, relay_client) = relay::client::new(local_peer_id);
+        let behaviour = MePassaBehaviour::new(local_peer_id, &keypair, relay_client);
 
         assert!(behaviour.is_ok());
     }
@@ -152,8 +181,8 @@ mod tests {
         let peer1 = PeerId::from(keypair1.public());
         let peer2 = PeerId::from(keypair2.public());
 
-        let behaviour1 = MePassaBehaviour::new(peer1, &keypair1);
-        let behaviour2 = MePassaBehaviour::new(peer2, &keypair2);
+        let behaviour1 = MePassaBehaviour::new(peer1, &keypair1, relay::client::new(peer1).1);
+        let behaviour2 = MePassaBehaviour::new(peer2, &keypair2, relay::client::new(peer2).1);
 
         assert!(behaviour1.is_ok());
         assert!(behaviour2.is_ok());
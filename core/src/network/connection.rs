@@ -2,7 +2,7 @@
 //!
 //! Manages connection attempts with automatic fallback from direct → hole punch → relay.
 
-use libp2p::{Multiaddr, PeerId};
+use libp2p::{swarm::ConnectionId, Multiaddr, PeerId};
 use std::collections::HashMap;
 use std::time::{Duration, Instant};
 
@@ -15,6 +15,8 @@ pub struct ConnectionManager {
     strategies: HashMap<PeerId, ConnectionStrategy>,
     /// Default retry policy
     retry_policy: RetryPolicy,
+    /// Open connections per peer
+    connections: HashMap<PeerId, HashMap<ConnectionId, OpenConnection>>,
 }
 
 impl ConnectionManager {
@@ -23,6 +25,7 @@ impl ConnectionManager {
         Self {
             strategies: HashMap::new(),
             retry_policy,
+            connections: HashMap::new(),
         }
     }
 
@@ -71,6 +74,140 @@ impl ConnectionManager {
             .map(|s| s.state.clone())
             .unwrap_or(ConnectionState::Disconnected)
     }
+
+    /// Record a new connection to a peer
+    pub fn connection_established(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        connection_type: ConnectionType,
+    ) {
+        let connections = self.connections.entry(peer_id).or_default();
+        if connections.contains_key(&connection_id) {
+            // Already reported by DCUtR
+            return;
+        }
+        connections.insert(connection_id, OpenConnection::new(connection_type));
+
+        self.get_or_create_strategy(peer_id)
+            .record_attempt(connection_type, true);
+        self.refresh_state(peer_id);
+    }
+
+    /// Record a connection upgraded from a relayed one by DCUtR
+    pub fn hole_punch_succeeded(&mut self, peer_id: PeerId, connection_id: ConnectionId) {
+        self.connections
+            .entry(peer_id)
+            .or_default()
+            .entry(connection_id)
+            .or_insert_with(|| OpenConnection::new(ConnectionType::HolePunch))
+            .connection_type = ConnectionType::HolePunch;
+
+        self.get_or_create_strategy(peer_id)
+            .record_attempt(ConnectionType::HolePunch, true);
+        self.refresh_state(peer_id);
+    }
+
+    /// Record a failed DCUtR upgrade (the relayed connection stays up)
+    pub fn hole_punch_failed(&mut self, peer_id: PeerId) {
+        self.get_or_create_strategy(peer_id)
+            .record_attempt(ConnectionType::HolePunch, false);
+    }
+
+    /// Record a closed connection to a peer
+    pub fn connection_closed(&mut self, peer_id: PeerId, connection_id: ConnectionId) {
+        if let Some(connections) = self.connections.get_mut(&peer_id) {
+            connections.remove(&connection_id);
+            if connections.is_empty() {
+                self.connections.remove(&peer_id);
+            }
+        }
+        self.refresh_state(peer_id);
+    }
+
+    /// Record a ping round-trip time on a connection
+    pub fn record_rtt(&mut self, peer_id: &PeerId, connection_id: ConnectionId, rtt: Duration) {
+        if let Some(connection) = self
+            .connections
+            .get_mut(peer_id)
+            .and_then(|connections| connections.get_mut(&connection_id))
+        {
+            connection.rtt = Some(rtt);
+        }
+    }
+
+    /// State, type and latency of the connection to a peer
+    ///
+    /// With several connections open, the best one (direct over hole
+    /// punched over relayed) is reported.
+    pub fn quality(&self, peer_id: &PeerId) -> ConnectionQuality {
+        let best = self.best_connection(peer_id);
+        ConnectionQuality {
+            state: self.get_state(peer_id),
+            connection_type: best.map(|c| c.connection_type),
+            rtt: best.and_then(|c| c.rtt),
+            open_connections: self.connections.get(peer_id).map_or(0, HashMap::len),
+        }
+    }
+
+    fn best_connection(&self, peer_id: &PeerId) -> Option<&OpenConnection> {
+        self.connections
+            .get(peer_id)?
+            .values()
+            .min_by_key(|c| (c.connection_type.rank(), c.rtt.is_none()))
+    }
+
+    /// Derive the peer's state from its open connections
+    fn refresh_state(&mut self, peer_id: PeerId) {
+        let best = self.best_connection(&peer_id).map(|c| c.connection_type);
+        let Some(strategy) = self.strategies.get_mut(&peer_id) else {
+            return;
+        };
+        match best {
+            Some(connection_type) => strategy.state = ConnectionState::Connected(connection_type),
+            None if matches!(strategy.state, ConnectionState::Connected(_)) => {
+                strategy.state = ConnectionState::Disconnected
+            }
+            None => {}
+        }
+    }
+}
+
+/// An open connection to a peer
+#[derive(Debug, Clone, Copy)]
+struct OpenConnection {
+    connection_type: ConnectionType,
+    /// Latest ping round-trip time
+    rtt: Option<Duration>,
+}
+
+impl OpenConnection {
+    fn new(connection_type: ConnectionType) -> Self {
+        Self {
+            connection_type,
+            rtt: None,
+        }
+    }
+}
+
+/// Connection quality for a peer, as shown to the user
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionQuality {
+    /// Current connection state
+    pub state: ConnectionState,
+    /// Type of the best open connection
+    pub connection_type: Option<ConnectionType>,
+    /// Latest ping round-trip time on that connection
+    pub rtt: Option<Duration>,
+    /// Number of open connections
+    pub open_connections: usize,
+}
+
+impl ConnectionQuality {
+    /// Whether traffic to the peer goes through a relay
+    pub fn is_relayed(&self) -> bool {
+        self.connection_type == Some(ConnectionType::Relayed)
+    }
 }
 
 /// Connection strategy for a single peer
@@ -153,15 +290,18 @@ impl ConnectionStrategy {
 
     /// Record a connection success
     pub fn record_success(&mut self, connection_type: ConnectionType) {
-        let attempt = ConnectionAttempt {
+        self.record_attempt(connection_type, true);
+        self.state = ConnectionState::Connected(connection_type);
+    }
+
+    /// Add an attempt to the history without changing the state
+    pub fn record_attempt(&mut self, connection_type: ConnectionType, success: bool) {
+        self.attempts.push(ConnectionAttempt {
             started_at: Instant::now(),
             duration: Duration::from_secs(0),
-            success: true,
-            connection_type: connection_type.clone(),
-        };
-
-        self.attempts.push(attempt);
-        self.state = ConnectionState::Connected(connection_type);
+            success,
+            connection_type,
+        });
     }
 
     /// Check if we should try relay
@@ -220,7 +360,7 @@ pub enum ConnectionState {
 }
 
 /// Type of connection
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ConnectionType {
     /// Direct P2P connection
     Direct,
@@ -232,6 +372,17 @@ pub enum ConnectionType {
     Relayed,
 }
 
+impl ConnectionType {
+    /// Preference order when several connections are open (lower is better)
+    fn rank(self) -> u8 {
+        match self {
+            ConnectionType::Direct => 0,
+            ConnectionType::HolePunch => 1,
+            ConnectionType::Relayed => 2,
+        }
+    }
+}
+
 /// Connection attempt record
 #[derive(Debug, Clone)]
 pub struct ConnectionAttempt {
@@ -305,4 +456,68 @@ mod tests {
         assert_eq!(strategy.success_rate(&ConnectionType::Direct), 0.5);
         assert_eq!(strategy.success_rate(&ConnectionType::Relayed), 0.0);
     }
+
+    #[test]
+    fn test_hole_punch_upgrades_relayed_connection() {
+        let mut manager = ConnectionManager::new(RetryPolicy::default());
+        let peer_id = PeerId::random();
+        let relayed = ConnectionId::new_unchecked(1);
+        let punched = ConnectionId::new_unchecked(2);
+
+        manager.connection_established(peer_id, relayed, ConnectionType::Relayed);
+        manager.record_rtt(&peer_id, relayed, Duration::from_millis(180));
+        let quality = manager.quality(&peer_id);
+        assert_eq!(quality.state, ConnectionState::Connected(ConnectionType::Relayed));
+        assert!(quality.is_relayed());
+        assert_eq!(quality.rtt, Some(Duration::from_millis(180)));
+
+        // DCUtR dials the direct connection, then reports it
+        manager.connection_established(peer_id, punched, ConnectionType::Direct);
+        manager.hole_punch_succeeded(peer_id, punched);
+        manager.record_rtt(&peer_id, punched, Duration::from_millis(40));
+
+        let quality = manager.quality(&peer_id);
+        assert_eq!(quality.state, ConnectionState::Connected(ConnectionType::HolePunch));
+        assert_eq!(quality.connection_type, Some(ConnectionType::HolePunch));
+        assert_eq!(quality.rtt, Some(Duration::from_millis(40)));
+        assert_eq!(quality.open_connections, 2);
+
+        // The relay connection is dropped once the direct one is up
+        manager.connection_closed(peer_id, relayed);
+        assert_eq!(manager.quality(&peer_id).open_connections, 1);
+        assert!(!manager.quality(&peer_id).is_relayed());
+
+        let strategy = manager.get_strategy(&peer_id).unwrap();
+        assert_eq!(strategy.success_rate(&ConnectionType::HolePunch), 1.0);
+    }
+
+    #[test]
+    fn test_failed_hole_punch_keeps_relayed_connection() {
+        let mut manager = ConnectionManager::new(RetryPolicy::default());
+        let peer_id = PeerId::random();
+
+        manager.connection_established(peer_id, ConnectionId::new_unchecked(1), ConnectionType::Relayed);
+        manager.hole_punch_failed(peer_id);
+
+        assert!(manager.quality(&peer_id).is_relayed());
+        let strategy = manager.get_strategy(&peer_id).unwrap();
+        assert_eq!(strategy.success_rate(&ConnectionType::HolePunch), 0.0);
+        assert_eq!(strategy.attempts.len(), 2);
+    }
+
+    #[test]
+    fn test_last_connection_closed_disconnects() {
+        let mut manager = ConnectionManager::new(RetryPolicy::default());
+        let peer_id = PeerId::random();
+        let connection_id = ConnectionId::new_unchecked(1);
+
+        manager.connection_established(peer_id, connection_id, ConnectionType::Direct);
+        manager.connection_closed(peer_id, connection_id);
+
+        let quality = manager.quality(&peer_id);
+        assert_eq!(quality.state, ConnectionState::Disconnected);
+        assert_eq!(quality.connection_type, None);
+        assert_eq!(quality.rtt, None);
+        assert_eq!(quality.open_connections, 0);
+    }
 }
@@ -0,0 +1,336 @@
+//! Length-Prefixed Framing
+//!
+//! Shared by the request-response codecs: a frame is a 4-byte big-endian
+//! length followed by the payload. The length is checked against the
+//! protocol's limit before anything is allocated, and peers that keep sending
+//! oversized or undecodable frames are disconnected.
+
+use futures::prelude::*;
+use libp2p::core::Endpoint;
+use libp2p::request_response::{self, Codec, InboundFailure};
+use libp2p::swarm::{
+    ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler, THandlerInEvent,
+    THandlerOutEvent, ToSwarm,
+};
+use libp2p::{Multiaddr, PeerId};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::task::{Context, Poll};
+use thiserror::Error;
+
+/// Default frame limit for direct messages (media chunks included)
+pub const MAX_MESSAGE_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Default frame limit for VoIP signaling (SDP offers/answers, ICE candidates)
+pub const MAX_SIGNALING_FRAME_SIZE: usize = 64 * 1024;
+
+/// Malformed frames tolerated from a peer before it is disconnected
+pub const MAX_MALFORMED_FRAMES: u32 = 3;
+
+/// Maximum frame size accepted per request-response protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameLimits {
+    /// `/mepassa/message/1.0.0`
+    pub message: usize,
+    /// `/mepassa/voip/1.0.0`
+    pub signaling: usize,
+}
+
+impl Default for FrameLimits {
+    fn default() -> Self {
+        Self {
+            message: MAX_MESSAGE_FRAME_SIZE,
+            signaling: MAX_SIGNALING_FRAME_SIZE,
+        }
+    }
+}
+
+/// Why a frame was rejected
+#[derive(Error, Debug, Clone)]
+pub enum FrameError {
+    #[error("Frame of {len} bytes exceeds the {max} byte limit")]
+    TooLarge { len: usize, max: usize },
+
+    #[error("Malformed frame: {0}")]
+    Malformed(String),
+}
+
+impl FrameError {
+    /// The frame error carried by an I/O error returned from a codec, if any
+    pub fn from_io(err: &io::Error) -> Option<&FrameError> {
+        err.get_ref().and_then(|inner| inner.downcast_ref::<FrameError>())
+    }
+
+    /// The frame error of a frame the remote peer sent us, if any
+    ///
+    /// Frames we refused to send ourselves are not the peer's fault and are
+    /// reported with `InvalidInput` instead.
+    pub fn received(err: &io::Error) -> Option<&FrameError> {
+        if err.kind() != io::ErrorKind::InvalidData {
+            return None;
+        }
+        Self::from_io(err)
+    }
+}
+
+impl From<FrameError> for io::Error {
+    fn from(err: FrameError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// Read one frame, refusing lengths above `max_len`
+pub async fn read_frame<T>(io: &mut T, max_len: usize) -> io::Result<Vec<u8>>
+where
+    T: AsyncRead + Unpin + Send,
+{
+    let mut len_buf = [0u8; 4];
+    io.read_exact(&mut len_buf).await?;
+    let len = u32::from_be_bytes(len_buf) as usize;
+    if len > max_len {
+        return Err(FrameError::TooLarge { len, max: max_len }.into());
+    }
+
+    let mut data = vec![0u8; len];
+    io.read_exact(&mut data).await?;
+    Ok(data)
+}
+
+/// Write one frame; oversized payloads are refused here rather than by the peer
+pub async fn write_frame<T>(io: &mut T, data: &[u8], max_len: usize) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+{
+    if data.len() > max_len {
+        let err = FrameError::TooLarge {
+            len: data.len(),
+            max: max_len,
+        };
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, err));
+    }
+
+    io.write_all(&(data.len() as u32).to_be_bytes()).await?;
+    io.write_all(data).await
+}
+
+/// Per-peer count of rejected frames
+#[derive(Debug)]
+pub struct MalformedFrameTracker {
+    counts: HashMap<PeerId, u32>,
+    limit: u32,
+}
+
+impl MalformedFrameTracker {
+    pub fn new(limit: u32) -> Self {
+        Self {
+            counts: HashMap::new(),
+            limit,
+        }
+    }
+
+    /// Count a rejected frame; returns true once the peer should be disconnected
+    pub fn record(&mut self, peer: PeerId) -> bool {
+        let count = self.counts.entry(peer).or_insert(0);
+        *count += 1;
+        if *count >= self.limit {
+            self.counts.remove(&peer);
+            return true;
+        }
+        false
+    }
+
+    /// Rejected frames counted for a peer
+    pub fn count(&self, peer: &PeerId) -> u32 {
+        self.counts.get(peer).copied().unwrap_or(0)
+    }
+
+    /// Forget a peer (e.g. once it disconnected)
+    pub fn forget(&mut self, peer: &PeerId) {
+        self.counts.remove(peer);
+    }
+}
+
+impl Default for MalformedFrameTracker {
+    fn default() -> Self {
+        Self::new(MAX_MALFORMED_FRAMES)
+    }
+}
+
+/// Request-response behaviour that reports requests rejected by the codec
+///
+/// libp2p 0.53 only logs a failed `read_request` at debug level, so an
+/// oversized or undecodable request would never reach the swarm. This wrapper
+/// turns such failures back into `InboundFailure::Io` events carrying the
+/// `FrameError`, which is what the per-peer accounting needs.
+pub struct FramedBehaviour<C: Codec + Clone + Send + 'static> {
+    inner: request_response::Behaviour<C>,
+    rejected: VecDeque<request_response::Event<C::Request, C::Response>>,
+}
+
+type HandlerEvent<C> = THandlerOutEvent<request_response::Behaviour<C>>;
+
+impl<C: Codec + Clone + Send + 'static> FramedBehaviour<C> {
+    pub fn new(inner: request_response::Behaviour<C>) -> Self {
+        Self {
+            inner,
+            rejected: VecDeque::new(),
+        }
+    }
+}
+
+impl<C: Codec + Clone + Send + 'static> Deref for FramedBehaviour<C> {
+    type Target = request_response::Behaviour<C>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<C: Codec + Clone + Send + 'static> DerefMut for FramedBehaviour<C> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+impl<C: Codec + Clone + Send + 'static> NetworkBehaviour for FramedBehaviour<C> {
+    type ConnectionHandler = THandler<request_response::Behaviour<C>>;
+    type ToSwarm = request_response::Event<C::Request, C::Response>;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        self.inner
+            .handle_pending_inbound_connection(connection_id, local_addr, remote_addr)
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.inner
+            .handle_established_inbound_connection(connection_id, peer, local_addr, remote_addr)
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        addresses: &[Multiaddr],
+        effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        self.inner.handle_pending_outbound_connection(
+            connection_id,
+            maybe_peer,
+            addresses,
+            effective_role,
+        )
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        role_override: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.inner
+            .handle_established_outbound_connection(connection_id, peer, addr, role_override)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        self.inner.on_swarm_event(event)
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        if let HandlerEvent::<C>::InboundStreamFailed { request_id, error } = &event {
+            if let Some(frame_error) = FrameError::received(error) {
+                self.rejected.push_back(request_response::Event::InboundFailure {
+                    peer: peer_id,
+                    request_id: *request_id,
+                    error: InboundFailure::Io(frame_error.clone().into()),
+                });
+            }
+        }
+        self.inner
+            .on_connection_handler_event(peer_id, connection_id, event)
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        if let Some(event) = self.rejected.pop_front() {
+            return Poll::Ready(ToSwarm::GenerateEvent(event));
+        }
+        self.inner.poll(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::io::Cursor;
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let mut buf = Cursor::new(Vec::new());
+        block_on(write_frame(&mut buf, b"payload", 16)).unwrap();
+
+        let mut reader = Cursor::new(buf.into_inner());
+        assert_eq!(block_on(read_frame(&mut reader, 16)).unwrap(), b"payload");
+    }
+
+    #[test]
+    fn test_oversized_frame_rejected_before_reading() {
+        // Only the length prefix is there: a 4 GiB claim must not be allocated
+        let mut reader = Cursor::new(u32::MAX.to_be_bytes().to_vec());
+        let err = block_on(read_frame(&mut reader, 1024)).unwrap_err();
+
+        assert!(matches!(
+            FrameError::received(&err),
+            Some(FrameError::TooLarge { max: 1024, .. })
+        ));
+    }
+
+    #[test]
+    fn test_oversized_frame_not_written() {
+        let mut buf = Cursor::new(Vec::new());
+        let err = block_on(write_frame(&mut buf, &[0u8; 32], 16)).unwrap_err();
+
+        assert!(FrameError::from_io(&err).is_some());
+        assert!(FrameError::received(&err).is_none());
+        assert!(buf.into_inner().is_empty());
+    }
+
+    #[test]
+    fn test_tracker_disconnects_after_limit() {
+        let mut tracker = MalformedFrameTracker::new(3);
+        let peer = PeerId::random();
+        let other = PeerId::random();
+
+        assert!(!tracker.record(peer));
+        assert!(!tracker.record(other));
+        assert!(!tracker.record(peer));
+        assert_eq!(tracker.count(&peer), 2);
+        assert!(tracker.record(peer));
+
+        // The count starts over once the peer was dealt with
+        assert_eq!(tracker.count(&peer), 0);
+        assert_eq!(tracker.count(&other), 1);
+        tracker.forget(&other);
+        assert_eq!(tracker.count(&other), 0);
+    }
+}
@@ -8,27 +8,146 @@
 //! 5. Sends acknowledgment back to sender
 
 use libp2p::PeerId;
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
 
 use crate::{
     crypto::{
-        decrypt_for_storage, encrypt_for_storage,
-        session::SessionManager,
+        decrypt_for_storage, decrypt_media_chunk, encrypt_for_storage, encrypt_media_chunk,
+        media::{media_key_from_bytes, MEDIA_CHUNK_SIZE},
+        ratchet::{RatchetHeader, RatchetMessage},
+        session::{PreKeyHeader, Session, SessionManager},
         signal::{EncryptedMessage as CryptoEncryptedMessage, X3DH},
     },
+    group::GroupManager,
     media::MediaEnvelope,
     protocol::{
-        pb::message::Payload, AckMessage, AckStatus, EncryptedMessage as ProtoEncryptedMessage,
-        MediaChunk, MediaOffer, MediaRequest, Message, MessageType, ReadReceipt, TextMessage,
-        TypingIndicator,
+        pb::{self, message::Payload}, AckMessage, AckStatus, EncryptedMessage as ProtoEncryptedMessage,
+        MediaChunk, MediaOffer, MediaRequest, Message, MessageType, ReadReceipt,
+        SenderKeyDistribution, TextMessage, TypingIndicator,
+    },
+    storage::{
+        Database, MediaType, MessageStatus, NewMedia, NewMessage, UpdateContact, UpdateMessage,
+    },
+    sync::{
+        device::{
+            account_of, is_own_device, save_certificate, verify_bundle, DeviceCertificate,
+            DeviceDirectory, LinkedDevice,
+        },
+        protocol as sync_protocol, MergeSummary,
     },
-    storage::{Database, MediaType, MessageStatus, NewMedia, NewMessage, UpdateMessage},
     utils::error::{MePassaError, Result},
 };
-use tokio::sync::RwLock;
-use crate::identity::Identity;
+use tokio::sync::{watch, RwLock};
+use crate::identity::{
+    Identity, PreKeyBundle, PreKeyDirectory, PreKeyPoolStore, SignedPreKeyPolicy,
+    DIRECTORY_REFILL_THRESHOLD, PREKEY_POOL_SIZE,
+};
+use prost::Message as _;
 use sha2::{Digest, Sha256};
 
+/// Chunks a media request asks for when it doesn't say
+pub const MEDIA_WINDOW: u32 = 8;
+
+/// Most chunks served for a single media request
+pub const MAX_MEDIA_WINDOW: u32 = 32;
+
+/// Largest chunk size a peer may ask for
+pub const MAX_MEDIA_CHUNK_SIZE: usize = 256 * 1024;
+
+/// Encrypt a payload for a peer over its pairwise E2E session
+///
+/// Starts a session from the contact's prekey bundle (or the bundle of a
+/// linked device) when there is none yet, provided the bundle is signed by
+/// the key behind `peer_id`. Returns `None` if neither a session nor a
+/// bundle is available.
+pub(crate) fn encrypt_for_peer(
+    database: &Database,
+    session_manager: &SessionManager,
+    peer_id: &str,
+    plaintext: &[u8],
+) -> Result<Option<ProtoEncryptedMessage>> {
+    // An established (possibly persisted) session doesn't need the bundle
+    if !session_manager.has_session(peer_id)? {
+        let bundle_json = database
+            .get_contact_by_peer_id(peer_id)
+            .ok()
+            .and_then(|contact| contact.prekey_bundle_json)
+            .or_else(|| {
+                database
+                    .get_device(peer_id)
+                    .ok()
+                    .flatten()
+                    .and_then(|device| device.prekey_bundle_json)
+            });
+        let Some(bundle_json) = bundle_json else {
+            return Ok(None);
+        };
+
+        let bundle: crate::identity::PreKeyBundle = serde_json::from_str(&bundle_json)
+            .map_err(|e| MePassaError::Crypto(format!("Invalid prekey bundle: {}", e)))?;
+        // Our session is only as authentic as the bundle it starts from
+        let peer: PeerId = peer_id
+            .parse()
+            .map_err(|e| MePassaError::Crypto(format!("Invalid peer id {}: {}", peer_id, e)))?;
+        verify_bundle(&peer, &bundle)
+            .map_err(|e| MePassaError::Crypto(format!("Prekey bundle of {}: {}", peer_id, e)))?;
+
+        session_manager.initiate_session(peer_id.to_string(), &bundle)?;
+    }
+
+    let (ratchet_message, prekey) = session_manager.encrypt_for(peer_id, plaintext)?;
+    let header = ratchet_message.header;
+
+    Ok(Some(ProtoEncryptedMessage {
+        ciphertext: ratchet_message.encrypted.ciphertext,
+        nonce: ratchet_message.encrypted.nonce.to_vec(),
+        ephemeral_public: prekey
+            .map(|p| p.ephemeral_public.to_vec())
+            .unwrap_or_default(),
+        signed_prekey_id: prekey.map(|p| p.signed_prekey_id).unwrap_or(0),
+        one_time_prekey_id: prekey.map(|p| p.one_time_prekey_id).unwrap_or(0),
+        ratchet_public: header.ratchet_public.to_vec(),
+        previous_counter: header.previous_counter,
+        counter: header.counter,
+        ephemeral_signature: prekey.map(|p| p.signature.to_vec()).unwrap_or_default(),
+    }))
+}
+
+/// Queue a message E2E encrypted for `to`, for `run_outbox` to deliver
+///
+/// Returns false, queuing nothing, when there is neither a session nor a
+/// prekey bundle for `to`.
+pub(crate) fn queue_encrypted(
+    database: &Database,
+    session_manager: &SessionManager,
+    storage_key: &[u8; 32],
+    from: &str,
+    to: &str,
+    message_type: MessageType,
+    plaintext: &[u8],
+) -> Result<bool> {
+    let Some(encrypted) = encrypt_for_peer(database, session_manager, to, plaintext)? else {
+        return Ok(false);
+    };
+
+    let message_id = uuid::Uuid::new_v4().to_string();
+    let message = Message {
+        id: message_id.clone(),
+        sender_peer_id: from.to_string(),
+        recipient_peer_id: to.to_string(),
+        timestamp: chrono::Utc::now().timestamp_millis(),
+        r#type: message_type as i32,
+        payload: Some(Payload::Encrypted(encrypted)),
+    };
+    let queued = encrypt_for_storage(storage_key, &message.encode_to_vec())?;
+    database.enqueue_outbox(&message_id, to, &queued)?;
+    Ok(true)
+}
+
 /// Message handler
 ///
 /// Processes incoming messages and coordinates between network, storage, and crypto layers.
@@ -53,6 +172,31 @@ pub struct MessageHandler {
 
     /// Event callback for notifying UI
     event_tx: Option<tokio::sync::mpsc::UnboundedSender<MessageEvent>>,
+
+    /// Group manager (receives sender key distributions)
+    group_manager: Option<Arc<GroupManager>>,
+
+    /// Where the prekey pool is saved after one-time prekeys are used
+    prekey_store: Option<PreKeyPoolStore>,
+
+    /// Where refilled one-time prekeys and rotated bundles are published
+    prekey_directory: Option<Arc<dyn PreKeyDirectory>>,
+
+    /// Where the current bundles of linked devices are fetched from
+    device_directory: Option<Arc<dyn DeviceDirectory>>,
+
+    /// When our signed prekey is rotated
+    signed_prekey_policy: SignedPreKeyPolicy,
+
+    /// Bumped whenever a download makes progress or completes
+    media_progress: watch::Sender<u64>,
+
+    /// Chunks written past the contiguous prefix of each download (offset -> end)
+    media_ranges: Mutex<HashMap<String, BTreeMap<u64, u64>>>,
+
+    /// Until when (unix seconds) a device list adding us to an account is
+    /// accepted, set while our link request is shown
+    device_link_deadline: Mutex<Option<i64>>,
 }
 
 impl MessageHandler {
@@ -74,7 +218,172 @@ impl MessageHandler {
             session_manager,
             storage_key,
             event_tx,
+            group_manager: None,
+            prekey_store: None,
+            prekey_directory: None,
+            device_directory: None,
+            signed_prekey_policy: SignedPreKeyPolicy::default(),
+            media_progress: watch::channel(0).0,
+            media_ranges: Mutex::new(HashMap::new()),
+            device_link_deadline: Mutex::new(None),
+        }
+    }
+
+    /// Accept being linked to an account until `deadline` (unix seconds)
+    pub fn await_device_link(&self, deadline: i64) {
+        *self
+            .device_link_deadline
+            .lock()
+            .expect("device link lock poisoned") = Some(deadline);
+    }
+
+    /// Wake-ups for whoever is waiting on a download
+    pub fn watch_media_progress(&self) -> watch::Receiver<u64> {
+        self.media_progress.subscribe()
+    }
+
+    /// Deliver sender key distributions to the group manager
+    pub fn with_group_manager(mut self, group_manager: Arc<GroupManager>) -> Self {
+        self.group_manager = Some(group_manager);
+        self
+    }
+
+    /// Save the prekey pool in `store` whenever a one-time prekey is used,
+    /// and publish refills to `directory`
+    pub fn with_prekey_store(
+        mut self,
+        store: PreKeyPoolStore,
+        directory: Option<Arc<dyn PreKeyDirectory>>,
+    ) -> Self {
+        self.prekey_store = Some(store);
+        self.prekey_directory = directory;
+        self
+    }
+
+    /// Fetch the bundles of linked devices from `directory` when ours is stale
+    pub fn with_device_directory(mut self, directory: Option<Arc<dyn DeviceDirectory>>) -> Self {
+        self.device_directory = directory;
+        self
+    }
+
+    /// Rotate our signed prekey according to `policy` (defaults to weekly)
+    pub fn with_signed_prekey_policy(mut self, policy: SignedPreKeyPolicy) -> Self {
+        self.signed_prekey_policy = policy;
+        self
+    }
+
+    /// Rotate the signed prekey if it is due, then save the pool and publish
+    /// the new bundle
+    ///
+    /// Replaced signed prekeys past their grace period are dropped on the way.
+    /// Returns whether the signed prekey was rotated.
+    pub async fn rotate_signed_prekey_if_due(&self) -> Result<bool> {
+        let now = chrono::Utc::now().timestamp();
+        let (rotated, bundle) = {
+            let mut identity = self.identity.write().await;
+            let Some(pool) = identity.prekey_pool_mut() else {
+                return Ok(false);
+            };
+            let rotated = pool.rotate_signed_prekey_if_due(now, &self.signed_prekey_policy);
+            // Saved either way: expired keys may have been dropped
+            if let Some(store) = &self.prekey_store {
+                store.save(pool)?;
+            }
+            (rotated, pool.export_bundle())
+        };
+
+        if rotated {
+            tracing::info!("🔑 Rotated signed prekey to {}", bundle.signed_prekey_id);
+            if let Some(directory) = &self.prekey_directory {
+                directory.publish_prekeys(&bundle, &[]).await?;
+            }
         }
+        Ok(rotated)
+    }
+
+    /// Prekey bundle to share directly with one contact
+    ///
+    /// Its one-time prekey goes to nobody else; the pool is saved so that
+    /// holds across restarts.
+    pub async fn reserve_prekey_bundle(&self) -> Result<PreKeyBundle> {
+        let mut identity = self.identity.write().await;
+        identity.init_prekey_pool(PREKEY_POOL_SIZE);
+        let pool = identity
+            .prekey_pool_mut()
+            .ok_or_else(|| MePassaError::Identity("Prekey pool not initialized".to_string()))?;
+        let bundle = pool.reserve_bundle();
+        if let Some(store) = &self.prekey_store {
+            store.save(pool)?;
+        }
+        Ok(bundle)
+    }
+
+    /// Upload new one-time prekeys if the prekey directory is running out
+    ///
+    /// The directory hands out each key once, to whoever looks us up, so its
+    /// count drops even when no session follows. Returns how many were
+    /// uploaded.
+    pub async fn replenish_published_prekeys(&self) -> Result<usize> {
+        let Some(directory) = &self.prekey_directory else {
+            return Ok(0);
+        };
+        let remaining = match directory.one_time_prekey_count().await? {
+            Some(remaining) if remaining < DIRECTORY_REFILL_THRESHOLD => remaining,
+            _ => return Ok(0),
+        };
+
+        let (bundle, fresh) = {
+            let mut identity = self.identity.write().await;
+            let Some(pool) = identity.prekey_pool_mut() else {
+                return Ok(0);
+            };
+            let fresh = pool.generate_one_time_prekeys(PREKEY_POOL_SIZE - remaining);
+            pool.mark_handed_out(&fresh);
+            if let Some(store) = &self.prekey_store {
+                store.save(pool)?;
+            }
+            (pool.export_bundle(), fresh)
+        };
+
+        tracing::info!(
+            "🔑 Directory has {} one-time prekeys left, uploading {}",
+            remaining,
+            fresh.len()
+        );
+        directory.publish_prekeys(&bundle, &fresh).await?;
+        Ok(fresh.len())
+    }
+
+    /// Wrap a sender key distribution for a group member
+    ///
+    /// Sender keys are only ever sent over the pairwise E2E session; without
+    /// one this fails with `EncryptionRequired`.
+    pub fn build_sender_key_message(
+        &self,
+        to: &str,
+        distribution: &SenderKeyDistribution,
+    ) -> Result<Message> {
+        let encrypted = encrypt_for_peer(
+            &self.database,
+            &self.session_manager,
+            to,
+            &distribution.encode_to_vec(),
+        )?
+        .ok_or_else(|| {
+            MePassaError::EncryptionRequired(format!(
+                "no E2E session or prekey bundle for {}, can't send sender key",
+                to
+            ))
+        })?;
+
+        Ok(Message {
+            id: uuid::Uuid::new_v4().to_string(),
+            sender_peer_id: self.local_peer_id.clone(),
+            recipient_peer_id: to.to_string(),
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            r#type: MessageType::SenderKeyDistribution as i32,
+            payload: Some(Payload::Encrypted(encrypted)),
+        })
     }
 
     /// Handle an incoming message request
@@ -98,13 +407,40 @@ impl MessageHandler {
             return Ok(self.create_ack(&message.id, AckStatus::Error, Some(e.to_string())));
         }
 
+        // Only the connection (or the store server's signature check) proves
+        // who sent a message; the sender it names must match
+        if message.sender_peer_id != from_peer.to_string() {
+            tracing::warn!(
+                "Rejecting message {}: sent by {} on behalf of {}",
+                message.id,
+                from_peer,
+                message.sender_peer_id
+            );
+            return Ok(self.create_ack(
+                &message.id,
+                AckStatus::Error,
+                Some("sender does not match the connection".to_string()),
+            ));
+        }
+
+        // A retransmission whose ACK got lost: acknowledge again, don't process twice
+        // (messages from linked devices are stored under their account)
+        if let Ok(existing) = self.database.get_message(&message.id) {
+            if existing.sender_peer_id == from_peer.to_string()
+                || message.r#type == MessageType::DeviceMessage as i32
+            {
+                tracing::debug!("Duplicate message {} from {}, re-sending ACK", message.id, from_peer);
+                return Ok(self.create_ack(&message.id, AckStatus::Received, None));
+            }
+        }
+
         // Process based on message type
         let result = match message.payload {
             Some(Payload::Text(ref text_msg)) => {
                 self.handle_text_message(&message, text_msg).await
             }
             Some(Payload::Ack(ref ack_msg)) => {
-                self.handle_ack_message(&message, ack_msg).await
+                self.handle_ack_message(&from_peer, ack_msg).await
             }
             Some(Payload::Typing(ref typing_msg)) => {
                 self.handle_typing_indicator(&message, typing_msg).await
@@ -113,11 +449,11 @@ impl MessageHandler {
                 self.handle_read_receipt(&message, read_msg).await
             }
             Some(Payload::Encrypted(ref enc_msg)) => {
-                self.handle_encrypted_message(&message, enc_msg).await
-            }
-            Some(Payload::MediaOffer(ref offer)) => {
-                self.handle_media_offer(&message, offer).await
+                self.handle_encrypted_message(&from_peer, &message, enc_msg).await
             }
+            Some(Payload::MediaOffer(_)) => Err(MePassaError::EncryptionRequired(
+                "media offers must be end-to-end encrypted".to_string(),
+            )),
             Some(Payload::MediaChunk(ref chunk)) => {
                 self.handle_media_chunk(&message, chunk).await
             }
@@ -135,6 +471,18 @@ impl MessageHandler {
 
         match result {
             Ok(_) => Ok(self.create_ack(&message.id, AckStatus::Received, None)),
+            // Someone else used the one-time prekey of the bundle the sender
+            // has; it can start over with the signed prekey alone
+            Err(e @ MePassaError::PreKeyMissing(_)) => {
+                tracing::warn!("Failed to process message {}: {}", message.id, e);
+                Ok(self.create_ack(&message.id, AckStatus::PrekeyMissing, Some(e.to_string())))
+            }
+            // The sender's copy of our bundle predates a rotation (and its
+            // grace period); it has to fetch the current one
+            Err(e @ MePassaError::SignedPreKeyMissing(_)) => {
+                tracing::warn!("Failed to process message {}: {}", message.id, e);
+                Ok(self.create_ack(&message.id, AckStatus::StaleBundle, Some(e.to_string())))
+            }
             Err(e) => {
                 tracing::error!("Failed to process message {}: {}", message.id, e);
                 Ok(self.create_ack(&message.id, AckStatus::Error, Some(e.to_string())))
@@ -142,31 +490,95 @@ impl MessageHandler {
         }
     }
 
-    /// Handle acknowledgment for an outgoing message
-    pub async fn handle_outgoing_ack(&self, ack: AckMessage) -> Result<()> {
+    /// Handle acknowledgment for an outgoing message, sent by `from_peer_id`
+    ///
+    /// Each device of the recipient acknowledges its own copy. Copies sent to
+    /// our other devices don't change the message status. ACKs from peers the
+    /// message wasn't sent to are ignored.
+    pub async fn handle_outgoing_ack(&self, from_peer_id: &str, ack: AckMessage) -> Result<()> {
         tracing::info!(
-            "✅ Received ACK for message {} - status: {:?}",
+            "✅ Received ACK for message {} from {} - status: {:?}",
             ack.message_id,
+            from_peer_id,
             AckStatus::try_from(ack.status).unwrap_or(AckStatus::Unspecified)
         );
 
-        // Update message status in database
+        let queued = self
+            .database
+            .get_outbox_entry(&ack.message_id, from_peer_id)?
+            .is_some();
+        let recipient = self
+            .database
+            .get_message(&ack.message_id)
+            .ok()
+            .and_then(|message| message.recipient_peer_id);
+        if !queued && recipient.as_deref() != Some(from_peer_id) {
+            tracing::warn!(
+                "Ignoring ACK for message {} from {}: not one of its recipients",
+                ack.message_id,
+                from_peer_id
+            );
+            return Ok(());
+        }
+
         let status = match AckStatus::try_from(ack.status) {
             Ok(AckStatus::Received) => MessageStatus::Delivered,
+            Ok(AckStatus::PrekeyMissing) => {
+                match self.requeue_without_one_time_prekey(from_peer_id, &ack.message_id) {
+                    Ok(true) => return Ok(()),
+                    Ok(false) => {}
+                    Err(e) => tracing::warn!(
+                        "Failed to requeue message {} for {}: {}",
+                        ack.message_id,
+                        from_peer_id,
+                        e
+                    ),
+                }
+                MessageStatus::Failed
+            }
+            Ok(AckStatus::StaleBundle) => {
+                match self.requeue_with_fresh_bundle(from_peer_id, &ack.message_id).await {
+                    Ok(true) => return Ok(()),
+                    Ok(false) => {}
+                    Err(e) => tracing::warn!(
+                        "Failed to requeue message {} for {}: {}",
+                        ack.message_id,
+                        from_peer_id,
+                        e
+                    ),
+                }
+                MessageStatus::Failed
+            }
             Ok(AckStatus::Error) => MessageStatus::Failed,
             _ => return Ok(()), // Ignore other statuses
         };
 
-        {
-            let update = UpdateMessage {
-                status: Some(status),
-                ..Default::default()
-            };
-            if let Err(e) = self.database.update_message(&ack.message_id, &update) {
-                tracing::warn!("Failed to update message status: {}", e);
+        // Either way the device answered, so stop retrying
+        if let Err(e) = self.database.remove_outbox_entry(&ack.message_id, from_peer_id) {
+            tracing::warn!("Failed to remove message from outbox: {}", e);
+        }
+        if is_own_device(&self.database, &self.local_peer_id, from_peer_id) {
+            return Ok(());
+        }
+
+        // Another device of the recipient already has the message
+        if status == MessageStatus::Failed {
+            if let Ok(message) = self.database.get_message(&ack.message_id) {
+                if matches!(message.status, MessageStatus::Delivered | MessageStatus::Read) {
+                    return Ok(());
+                }
             }
         }
 
+        // Update message status in database
+        let update = UpdateMessage {
+            status: Some(status),
+            ..Default::default()
+        };
+        if let Err(e) = self.database.update_message(&ack.message_id, &update) {
+            tracing::warn!("Failed to update message status: {}", e);
+        }
+
         // Emit event (include recipient when available)
         let to_peer_id = self
             .database
@@ -183,6 +595,149 @@ impl MessageHandler {
         Ok(())
     }
 
+    /// Start over with `device` without the one-time prekey it no longer
+    /// has, and queue the message encrypted for the new session
+    ///
+    /// Only text messages are rebuilt; returns false for anything else, or
+    /// when the message is no longer queued for `device`.
+    fn requeue_without_one_time_prekey(&self, device: &str, message_id: &str) -> Result<bool> {
+        // Our session was built on the missing key until the peer replies
+        let pending = self
+            .session_manager
+            .get_session(device)
+            .ok()
+            .and_then(|session| session.pending_prekey);
+        if pending.is_some_and(|header| header.one_time_prekey_id != 0) {
+            self.session_manager.remove_session(device)?;
+        }
+        // Don't use the key again for the next session either
+        if let Ok(contact) = self.database.get_contact_by_peer_id(device) {
+            if let Some(bundle_json) = contact.prekey_bundle_json {
+                let mut bundle: PreKeyBundle = serde_json::from_str(&bundle_json)
+                    .map_err(|e| MePassaError::Crypto(format!("Invalid prekey bundle: {}", e)))?;
+                if bundle.one_time_prekey.take().is_some() {
+                    let bundle_json = serde_json::to_string(&bundle).map_err(|e| {
+                        MePassaError::Crypto(format!("Failed to serialize prekey bundle: {}", e))
+                    })?;
+                    let update = UpdateContact {
+                        prekey_bundle_json: Some(Some(bundle_json)),
+                        ..Default::default()
+                    };
+                    self.database.update_contact(device, &update)?;
+                }
+            }
+        }
+
+        let requeued = self.reencrypt_queued(device, message_id)?;
+        if requeued {
+            tracing::info!(
+                "🔑 {} no longer had our one-time prekey, requeued message {} without it",
+                device,
+                message_id
+            );
+        }
+        Ok(requeued)
+    }
+
+    /// Start over with `device` from a fresh copy of its prekey bundle, after
+    /// it rotated out the signed prekey of the one we started from
+    ///
+    /// Linked devices publish their bundle to the device directory, so theirs
+    /// is fetched again there. Returns false when no fresh bundle is found or
+    /// the message can't be rebuilt (see `requeue_without_one_time_prekey`).
+    async fn requeue_with_fresh_bundle(&self, device: &str, message_id: &str) -> Result<bool> {
+        // Only a session the peer never answered is built on the stale bundle
+        let pending = self
+            .session_manager
+            .get_session(device)
+            .ok()
+            .is_some_and(|session| session.pending_prekey.is_some());
+        if pending {
+            self.session_manager.remove_session(device)?;
+        }
+
+        let (Some(directory), Some(stored)) =
+            (&self.device_directory, self.database.get_device(device)?)
+        else {
+            return Ok(false);
+        };
+        let account: PeerId = stored
+            .account_peer_id
+            .parse()
+            .map_err(|e| MePassaError::Protocol(format!("Invalid account peer id: {}", e)))?;
+        let Some(fresh) = directory
+            .fetch_devices(&account)
+            .await?
+            .into_iter()
+            .find(|listed| listed.certificate.device_peer_id.to_string() == device)
+        else {
+            return Ok(false);
+        };
+        if fresh.certificate.account_peer_id != account || fresh.verify().is_err() {
+            return Err(MePassaError::Protocol(format!(
+                "directory entry of {} doesn't verify",
+                device
+            )));
+        }
+        fresh.save(&self.database)?;
+
+        let requeued = self.reencrypt_queued(device, message_id)?;
+        if requeued {
+            tracing::info!(
+                "🔑 Fetched the current bundle of {}, requeued message {}",
+                device,
+                message_id
+            );
+        }
+        Ok(requeued)
+    }
+
+    /// Encrypt the queued copy of a text message for `device` again, for the
+    /// session (or bundle) we now have with it
+    fn reencrypt_queued(&self, device: &str, message_id: &str) -> Result<bool> {
+        let Some(entry) = self.database.get_outbox_entry(message_id, device)? else {
+            return Ok(false);
+        };
+        let mut queued = Message::decode(
+            decrypt_for_storage(&self.storage_key, &entry.payload)?.as_slice(),
+        )
+        .map_err(|e| MePassaError::Protocol(format!("Invalid queued message: {}", e)))?;
+        let stored = self.database.get_message(message_id)?;
+        let (Some(content), "text") = (stored.content_encrypted, stored.message_type.as_str())
+        else {
+            return Ok(false);
+        };
+        let content = self.decrypt_for_storage(&content)?;
+
+        // The same E2E plaintext the message was first sent with
+        let plaintext = if queued.r#type == MessageType::Encrypted as i32 {
+            content.into_bytes()
+        } else if queued.r#type == MessageType::DeviceMessage as i32 {
+            let sender_device = match self.database.get_device(&self.local_peer_id)? {
+                Some(own) => Some(DeviceCertificate::from_bytes(&own.certificate)?.to_proto()),
+                None => None,
+            };
+            pb::DeviceMessage {
+                sender_device,
+                recipient_account_peer_id: stored.recipient_peer_id.unwrap_or_default(),
+                content,
+            }
+            .encode_to_vec()
+        } else {
+            return Ok(false);
+        };
+
+        let Some(encrypted) =
+            encrypt_for_peer(&self.database, &self.session_manager, device, &plaintext)?
+        else {
+            return Ok(false);
+        };
+        queued.payload = Some(Payload::Encrypted(encrypted));
+        let queued = self.encrypt_for_storage(&queued.encode_to_vec())?;
+        self.database.replace_outbox_payload(message_id, device, &queued)?;
+        Ok(true)
+    }
+
     /// Validate message format
     fn validate_message(&self, message: &Message) -> Result<()> {
         // Check message ID
@@ -265,73 +820,88 @@ impl MessageHandler {
     }
 
     /// Handle acknowledgment message
-    async fn handle_ack_message(&self, _message: &Message, ack: &AckMessage) -> Result<()> {
-        // This is an ACK for one of our messages
-        self.handle_outgoing_ack(ack.clone()).await
+    async fn handle_ack_message(&self, from_peer: &PeerId, ack: &AckMessage) -> Result<()> {
+        // This is an ACK for one of our messages, by whoever we're connected to
+        self.handle_outgoing_ack(&from_peer.to_string(), ack.clone())
+            .await
     }
 
+    /// Decrypt a message over the E2E session with `from_peer`
+    ///
+    /// A new session is only built from an X3DH header `from_peer` signed.
     async fn handle_encrypted_message(
         &self,
+        from_peer: &PeerId,
         message: &Message,
         encrypted: &ProtoEncryptedMessage,
     ) -> Result<()> {
-        let peer_id = message.sender_peer_id.clone();
+        let peer_id = from_peer.to_string();
+        let ratchet_message = Self::ratchet_message_from_proto(encrypted)?;
 
-        if !self.session_manager.has_session(&peer_id)? {
-            if encrypted.ephemeral_public.is_empty() {
+        let plaintext = if encrypted.ephemeral_public.is_empty() {
+            if !self.session_manager.has_session(&peer_id)? {
                 return Err(MePassaError::Crypto("No session and no ephemeral key".to_string()));
             }
-
-            let (signed_prekey_secret, one_time_secret_opt) = {
-                let mut identity = self.identity.write().await;
-                identity.init_prekey_pool(100);
-                let pool = identity
-                    .prekey_pool_mut()
-                    .ok_or_else(|| MePassaError::Crypto("Prekey pool not initialized".to_string()))?;
-
-                let signed_prekey_secret = pool.signed_prekey().secret_bytes();
-                let one_time_secret_opt: Option<[u8; 32]> = if encrypted.one_time_prekey_id != 0 {
-                    pool.get_prekey(encrypted.one_time_prekey_id)
-                        .map(|pk| pk.secret_bytes())
-                } else {
-                    None
-                };
-                (signed_prekey_secret, one_time_secret_opt)
-            };
-
+            self.session_manager.decrypt_from(&peer_id, &ratchet_message)?
+        } else {
             let ephemeral_public: [u8; 32] = encrypted
                 .ephemeral_public
                 .as_slice()
                 .try_into()
                 .map_err(|_| MePassaError::Crypto("Invalid ephemeral public key".to_string()))?;
 
-            let shared_secret = X3DH::respond(
-                &signed_prekey_secret,
-                one_time_secret_opt.as_ref(),
-                &ephemeral_public,
-            )?;
-
-            self.session_manager
-                .create_session(peer_id.clone(), shared_secret)?;
-
-            if encrypted.one_time_prekey_id != 0 {
-                let mut identity = self.identity.write().await;
-                if let Some(pool) = identity.prekey_pool_mut() {
-                    pool.remove_prekey(encrypted.one_time_prekey_id);
+            match self.session_manager.get_session(&peer_id).ok() {
+                // Follow-up message of the X3DH exchange we already accepted
+                Some(session) if session.base_ephemeral == ephemeral_public => {
+                    self.session_manager.decrypt_from(&peer_id, &ratchet_message)?
+                }
+                // Both sides initiated at the same time: the higher peer id keeps
+                // its own session, the message is read with a throwaway session
+                Some(session)
+                    if session.pending_prekey.is_some() && self.local_peer_id > peer_id =>
+                {
+                    let mut session = self
+                        .respond_to_prekey_message(from_peer, encrypted, ephemeral_public)
+                        .await?;
+                    let plaintext = session.decrypt(&ratchet_message)?;
+                    self.consume_one_time_prekey(encrypted.one_time_prekey_id).await;
+                    plaintext
+                }
+                // New session (or peer re-initiated): only replace when the
+                // peer signed the exchange and the message decrypts
+                _ => {
+                    let mut session = self
+                        .respond_to_prekey_message(from_peer, encrypted, ephemeral_public)
+                        .await?;
+                    let plaintext = session.decrypt(&ratchet_message)?;
+                    self.session_manager.update_session(session)?;
+                    self.consume_one_time_prekey(encrypted.one_time_prekey_id).await;
+                    plaintext
                 }
             }
-        }
-
-        let crypto_msg = CryptoEncryptedMessage {
-            nonce: encrypted
-                .nonce
-                .as_slice()
-                .try_into()
-                .map_err(|_| MePassaError::Crypto("Invalid nonce".to_string()))?,
-            ciphertext: encrypted.ciphertext.clone(),
         };
 
-        let plaintext = self.session_manager.decrypt_from(&peer_id, &crypto_msg)?;
+        if message.r#type == MessageType::SenderKeyDistribution as i32 {
+            return self.handle_sender_key_distribution(from_peer, &plaintext).await;
+        }
+        if message.r#type == MessageType::DeviceList as i32 {
+            return self.handle_device_list(&peer_id, &plaintext);
+        }
+        if message.r#type == MessageType::DeviceMessage as i32 {
+            return self.handle_device_message(&peer_id, message, &plaintext);
+        }
+        if message.r#type == MessageType::SyncRequest as i32 {
+            return self.handle_sync_request(&peer_id, &plaintext);
+        }
+        if message.r#type == MessageType::SyncBatch as i32 {
+            return self.handle_sync_batch(&peer_id, &plaintext);
+        }
+        if message.r#type == MessageType::MediaOffer as i32 {
+            let offer = MediaOffer::decode(plaintext.as_slice())
+                .map_err(|e| MePassaError::Protocol(format!("Invalid media offer: {}", e)))?;
+            return self.handle_media_offer(message, &offer).await;
+        }
+
         let text = String::from_utf8(plaintext)
             .map_err(|_| MePassaError::Protocol("Invalid UTF-8 content".to_string()))?;
 
@@ -374,6 +944,422 @@ impl MessageHandler {
         Ok(())
     }
 
+    /// Store the devices of an account, as sent by its primary device
+    ///
+    /// `from_peer_id` is the peer the E2E session authenticated, never the
+    /// sender a message names. Only lists of our own account are accepted:
+    /// from our primary once we are linked, or the first one naming us while
+    /// a link request is shown.
+    fn handle_device_list(&self, from_peer_id: &str, plaintext: &[u8]) -> Result<()> {
+        let list = pb::DeviceList::decode(plaintext)
+            .map_err(|e| MePassaError::Protocol(format!("Invalid device list: {}", e)))?;
+        if list.account_peer_id != from_peer_id {
+            return Err(MePassaError::Protocol(
+                "device list not sent by its account".to_string(),
+            ));
+        }
+
+        let devices = list
+            .devices
+            .iter()
+            .map(LinkedDevice::from_proto)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        if devices
+            .iter()
+            .any(|device| device.certificate.account_peer_id.to_string() != list.account_peer_id)
+        {
+            return Err(MePassaError::Protocol(
+                "device list names devices of another account".to_string(),
+            ));
+        }
+
+        if account_of(&self.database, &self.local_peer_id) != list.account_peer_id {
+            let names_us = devices
+                .iter()
+                .any(|device| device.certificate.device_peer_id.to_string() == self.local_peer_id);
+            let mut deadline = self
+                .device_link_deadline
+                .lock()
+                .expect("device link lock poisoned");
+            let now = chrono::Utc::now().timestamp();
+            if !names_us || !deadline.is_some_and(|deadline| now <= deadline) {
+                return Err(MePassaError::Protocol(format!(
+                    "unexpected device list of {}",
+                    list.account_peer_id
+                )));
+            }
+            *deadline = None;
+            tracing::info!("🔗 Linked to account {}", list.account_peer_id);
+        }
+
+        let newly_linked = self.database.get_device(&self.local_peer_id)?.is_none();
+        for device in devices {
+            let device_peer_id = device.certificate.device_peer_id.to_string();
+            let is_new = self.database.get_device(&device_peer_id)?.is_none();
+            if !device.save(&self.database)? {
+                tracing::warn!(
+                    "⚠️ Ignoring device {} of {}: linked to another account",
+                    device_peer_id,
+                    list.account_peer_id
+                );
+                continue;
+            }
+            if is_new {
+                self.emit_event(MessageEvent::DeviceLinked {
+                    account_peer_id: list.account_peer_id.clone(),
+                    device_peer_id,
+                });
+            }
+        }
+
+        // A new device starts from the account's recent history and state
+        if newly_linked {
+            let request = sync_protocol::sync_request(&self.database, true)?;
+            if let Err(e) = self.queue_for_device(
+                &list.account_peer_id,
+                MessageType::SyncRequest,
+                &request.encode_to_vec(),
+            ) {
+                tracing::warn!("⚠️ Failed to request history from {}: {}", list.account_peer_id, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Answer another device of our account with the changes (and history)
+    /// it asked for
+    ///
+    /// `from_peer_id` is the peer the E2E session authenticated.
+    fn handle_sync_request(&self, from_peer_id: &str, plaintext: &[u8]) -> Result<()> {
+        self.check_own_device(from_peer_id)?;
+        let request = pb::SyncRequest::decode(plaintext)
+            .map_err(|e| MePassaError::Protocol(format!("Invalid sync request: {}", e)))?;
+
+        let batches = sync_protocol::answer_request(
+            &self.database,
+            &self.storage_key,
+            &self.local_peer_id,
+            &request,
+        )?;
+        tracing::info!("🔄 Sending {} sync batches to {}", batches.len(), from_peer_id);
+        for batch in batches {
+            self.queue_for_device(from_peer_id, MessageType::SyncBatch, &batch.encode_to_vec())?;
+        }
+        Ok(())
+    }
+
+    /// Merge history and changes from another device of our account
+    ///
+    /// `from_peer_id` is the peer the E2E session authenticated.
+    fn handle_sync_batch(&self, from_peer_id: &str, plaintext: &[u8]) -> Result<()> {
+        self.check_own_device(from_peer_id)?;
+        let batch = pb::SyncBatch::decode(plaintext)
+            .map_err(|e| MePassaError::Protocol(format!("Invalid sync batch: {}", e)))?;
+
+        let summary =
+            sync_protocol::merge_batch(
+                &self.database,
+                &self.storage_key,
+                &self.local_peer_id,
+                from_peer_id,
+                batch,
+            )?;
+        if summary != MergeSummary::default() {
+            self.emit_event(MessageEvent::DeviceSynced {
+                device_peer_id: from_peer_id.to_string(),
+                messages: summary.messages,
+                changes: summary.changes,
+            });
+        }
+        Ok(())
+    }
+
+    /// Sync messages are only accepted from the other devices of our account
+    ///
+    /// `peer_id` must come from the session the message was decrypted with:
+    /// sessions are bound to the peer's key (a signed X3DH header, or a
+    /// bundle `verify_bundle` accepted), and the device certificate binds
+    /// that key to our account.
+    fn check_own_device(&self, peer_id: &str) -> Result<()> {
+        if peer_id == self.local_peer_id
+            || !is_own_device(&self.database, &self.local_peer_id, peer_id)
+        {
+            return Err(MePassaError::Protocol(format!(
+                "sync message from {}, not a device of our account",
+                peer_id
+            )));
+        }
+        Ok(())
+    }
+
+    fn queue_for_device(&self, to: &str, message_type: MessageType, plaintext: &[u8]) -> Result<()> {
+        let queued = queue_encrypted(
+            &self.database,
+            &self.session_manager,
+            &self.storage_key,
+            &self.local_peer_id,
+            to,
+            message_type,
+            plaintext,
+        )?;
+        if !queued {
+            return Err(MePassaError::EncryptionRequired(format!(
+                "no E2E session with device {}",
+                to
+            )));
+        }
+        Ok(())
+    }
+
+    /// A direct message sent to every device of an account, by `from_peer_id`
+    /// (the peer the E2E session authenticated)
+    ///
+    /// Either another account's message to ours, or a copy of a message one
+    /// of our devices sent, which is stored as sent by us.
+    fn handle_device_message(
+        &self,
+        from_peer_id: &str,
+        message: &Message,
+        plaintext: &[u8],
+    ) -> Result<()> {
+        let envelope = pb::DeviceMessage::decode(plaintext)
+            .map_err(|e| MePassaError::Protocol(format!("Invalid device message: {}", e)))?;
+
+        // A secondary device proves which account it belongs to, unless we
+        // already know it as a device of another one
+        let sender_account = match &envelope.sender_device {
+            Some(certificate) => {
+                let certificate = DeviceCertificate::from_proto(certificate)?;
+                if certificate.device_peer_id.to_string() != from_peer_id
+                    || from_peer_id == self.local_peer_id
+                {
+                    return Err(MePassaError::Protocol(
+                        "device certificate of another device".to_string(),
+                    ));
+                }
+                if !save_certificate(&self.database, &certificate, None)? {
+                    return Err(MePassaError::Protocol(format!(
+                        "device {} is linked to another account",
+                        from_peer_id
+                    )));
+                }
+                certificate.account_peer_id.to_string()
+            }
+            None => from_peer_id.to_string(),
+        };
+
+        let own_account = account_of(&self.database, &self.local_peer_id);
+        let (conversation_peer_id, sender_peer_id, status) = if sender_account == own_account {
+            if envelope.recipient_account_peer_id.is_empty()
+                || envelope.recipient_account_peer_id == own_account
+            {
+                return Err(MePassaError::Protocol("invalid device message recipient".to_string()));
+            }
+            (
+                envelope.recipient_account_peer_id.clone(),
+                self.local_peer_id.clone(),
+                MessageStatus::Sent,
+            )
+        } else {
+            if envelope.recipient_account_peer_id != own_account {
+                return Err(MePassaError::Protocol(
+                    "device message for another account".to_string(),
+                ));
+            }
+            (sender_account.clone(), sender_account, MessageStatus::Delivered)
+        };
+
+        let text = envelope.content;
+
+        let conversation_id = self.database.get_or_create_conversation(&conversation_peer_id)?;
+        let new_msg = NewMessage {
+            message_id: message.id.clone(),
+            conversation_id: conversation_id.clone(),
+            sender_peer_id: sender_peer_id.clone(),
+            recipient_peer_id: Some(envelope.recipient_account_peer_id.clone()),
+            message_type: "text".to_string(),
+            content_encrypted: self.encrypt_for_storage(text.as_bytes()).ok(),
+            content_plaintext: None,
+            status,
+            parent_message_id: None,
+        };
+        self.database.insert_message(&new_msg)?;
+        self.database.update_conversation_last_message(&conversation_id, &message.id)?;
+
+        let mut display_message = message.clone();
+        display_message.sender_peer_id = sender_peer_id.clone();
+        display_message.recipient_peer_id = envelope.recipient_account_peer_id;
+        display_message.payload = Some(Payload::Text(TextMessage {
+            content: text.clone(),
+            reply_to_id: String::new(),
+            metadata: std::collections::HashMap::new(),
+        }));
+        display_message.r#type = MessageType::Text as i32;
+
+        self.emit_event(MessageEvent::MessageReceived {
+            message_id: message.id.clone(),
+            from_peer_id: sender_peer_id,
+            conversation_id,
+            content: text,
+            message: display_message,
+        });
+
+        Ok(())
+    }
+
+    /// Hand a group sender key received over the E2E session to the group manager
+    ///
+    /// `from_peer` is the peer the session authenticated, so the key is only
+    /// accepted as that peer's own, whatever sender the message names.
+    async fn handle_sender_key_distribution(
+        &self,
+        from_peer: &PeerId,
+        plaintext: &[u8],
+    ) -> Result<()> {
+        let distribution = SenderKeyDistribution::decode(plaintext)
+            .map_err(|e| MePassaError::Protocol(format!("Invalid sender key distribution: {}", e)))?;
+
+        let group_manager = self
+            .group_manager
+            .as_ref()
+            .ok_or_else(|| MePassaError::Other("Group manager not available".to_string()))?;
+
+        group_manager
+            .handle_sender_key_distribution(&from_peer.to_string(), distribution)
+            .await
+    }
+
+    /// Drop a one-time prekey an initiator used (0 means none)
+    ///
+    /// The pool is saved without it, and refilled and published when it runs
+    /// low. Failures are logged: the message itself was already accepted.
+    async fn consume_one_time_prekey(&self, id: u32) {
+        if id == 0 {
+            return;
+        }
+
+        let refill = {
+            let mut identity = self.identity.write().await;
+            let Some(pool) = identity.prekey_pool_mut() else {
+                return;
+            };
+            pool.remove_prekey(id);
+            let fresh = if pool.needs_replenishment() {
+                pool.replenish_prekeys(PREKEY_POOL_SIZE)
+            } else {
+                Vec::new()
+            };
+            if self.prekey_directory.is_some() {
+                pool.mark_handed_out(&fresh);
+            }
+
+            if let Some(store) = &self.prekey_store {
+                if let Err(e) = store.save(pool) {
+                    tracing::warn!("⚠️ Failed to save prekey pool: {}", e);
+                }
+            }
+            (!fresh.is_empty()).then(|| (pool.export_bundle(), fresh))
+        };
+
+        if let (Some((bundle, fresh)), Some(directory)) = (refill, &self.prekey_directory) {
+            tracing::info!("🔑 Publishing {} new one-time prekeys", fresh.len());
+            let directory = Arc::clone(directory);
+            tokio::spawn(async move {
+                if let Err(e) = directory.publish_prekeys(&bundle, &fresh).await {
+                    tracing::warn!("⚠️ Failed to publish prekeys: {}", e);
+                }
+            });
+        }
+    }
+
+    /// Build the responder side of a session from an X3DH prekey message
+    ///
+    /// The X3DH header must be signed by the key behind `peer`.
+    async fn respond_to_prekey_message(
+        &self,
+        peer: &PeerId,
+        encrypted: &ProtoEncryptedMessage,
+        ephemeral_public: [u8; 32],
+    ) -> Result<Session> {
+        let header = PreKeyHeader {
+            ephemeral_public,
+            signed_prekey_id: encrypted.signed_prekey_id,
+            one_time_prekey_id: encrypted.one_time_prekey_id,
+            signature: encrypted
+                .ephemeral_signature
+                .as_slice()
+                .try_into()
+                .map_err(|_| MePassaError::Crypto("Unsigned X3DH header".to_string()))?,
+        };
+        let initiator = crate::identity::PublicKey::from_libp2p_peer_id(peer)
+            .map_err(|e| MePassaError::Crypto(format!("Can't authenticate {}: {}", peer, e)))?;
+
+        let (signed_prekey_secret, one_time_secret_opt) = {
+            let mut identity = self.identity.write().await;
+            header
+                .verify(&initiator, &identity.keypair().public_key_bytes())
+                .map_err(|_| {
+                    MePassaError::Crypto(format!("X3DH header not signed by {}", peer))
+                })?;
+            identity.init_prekey_pool(100);
+            let pool = identity
+                .prekey_pool_mut()
+                .ok_or_else(|| MePassaError::Crypto("Prekey pool not initialized".to_string()))?;
+
+            // The initiator may hold our bundle from before a rotation
+            let signed_prekey_secret = pool
+                .signed_prekey_by_id(encrypted.signed_prekey_id)
+                .ok_or(MePassaError::SignedPreKeyMissing(encrypted.signed_prekey_id))?
+                .secret_bytes();
+            let one_time_secret_opt: Option<[u8; 32]> = if encrypted.one_time_prekey_id != 0 {
+                let prekey = pool
+                    .get_prekey(encrypted.one_time_prekey_id)
+                    .ok_or(MePassaError::PreKeyMissing(encrypted.one_time_prekey_id))?;
+                Some(prekey.secret_bytes())
+            } else {
+                None
+            };
+            (signed_prekey_secret, one_time_secret_opt)
+        };
+
+        let shared_secret = X3DH::respond(
+            &signed_prekey_secret,
+            one_time_secret_opt.as_ref(),
+            &ephemeral_public,
+        )?;
+
+        Ok(Session::respond(
+            peer.to_string(),
+            shared_secret,
+            ephemeral_public,
+            signed_prekey_secret,
+        ))
+    }
+
+    /// Convert a protobuf EncryptedMessage into a Double Ratchet message
+    fn ratchet_message_from_proto(encrypted: &ProtoEncryptedMessage) -> Result<RatchetMessage> {
+        Ok(RatchetMessage {
+            header: RatchetHeader {
+                ratchet_public: encrypted
+                    .ratchet_public
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| MePassaError::Crypto("Invalid ratchet public key".to_string()))?,
+                previous_counter: encrypted.previous_counter,
+                counter: encrypted.counter,
+            },
+            encrypted: CryptoEncryptedMessage {
+                nonce: encrypted
+                    .nonce
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| MePassaError::Crypto("Invalid nonce".to_string()))?,
+                ciphertext: encrypted.ciphertext.clone(),
+            },
+        })
+    }
+
     /// Handle typing indicator
     async fn handle_typing_indicator(
         &self,
@@ -426,6 +1412,14 @@ impl MessageHandler {
     }
 
     async fn handle_media_offer(&self, message: &Message, offer: &MediaOffer) -> Result<()> {
+        let media_key = media_key_from_bytes(&offer.media_key)?;
+        let digest = media_key_from_bytes(&offer.digest)?;
+        self.database.save_media_key(
+            &offer.media_hash,
+            &self.encrypt_for_storage(&media_key)?,
+            &digest,
+        )?;
+
         let media_type = MediaType::from_str(&offer.media_type);
         let placeholder = match media_type {
             MediaType::Image => format!("[Image: {}]", offer.file_name),
@@ -471,12 +1465,20 @@ impl MessageHandler {
         };
         let _ = self.database.insert_media(&new_media);
 
+        // Hand the application the offer, without the file key
+        let mut display_message = message.clone();
+        display_message.payload = Some(Payload::MediaOffer(MediaOffer {
+            media_key: Vec::new(),
+            digest: Vec::new(),
+            ..offer.clone()
+        }));
+
         self.emit_event(MessageEvent::MessageReceived {
             message_id: message.id.clone(),
             from_peer_id: message.sender_peer_id.clone(),
             conversation_id,
             content: placeholder,
-            message: message.clone(),
+            message: display_message,
         });
 
         Ok(())
@@ -485,54 +1487,154 @@ impl MessageHandler {
     async fn handle_media_chunk(&self, _message: &Message, chunk: &MediaChunk) -> Result<()> {
         use std::io::{Seek, SeekFrom, Write};
 
+        let media = self
+            .database
+            .get_media_by_hash(&chunk.media_hash)?
+            .ok_or_else(|| MePassaError::NotFound("Media record not found".to_string()))?;
+        let (media_key, digest) = self.media_key(&chunk.media_hash)?;
+        let total = media.file_size.unwrap_or(0).max(0) as u64;
+
+        let offset = chunk.offset.max(0) as u64;
+        let plaintext = decrypt_media_chunk(
+            &media_key,
+            &chunk.media_hash,
+            chunk.index,
+            offset,
+            chunk.is_last,
+            &chunk.data,
+        )?;
+        let end = offset + plaintext.len() as u64;
+        if end > total || (chunk.is_last && end != total) {
+            return Err(MePassaError::Protocol(format!(
+                "Media chunk {} doesn't fit a {} byte file",
+                chunk.index, total
+            )));
+        }
+
         let tmp_dir = self.data_dir.join("media").join("tmp");
         std::fs::create_dir_all(&tmp_dir)
             .map_err(|e| MePassaError::Storage(format!("Failed to create tmp dir: {}", e)))?;
         let tmp_path = tmp_dir.join(format!("{}.part", chunk.media_hash));
         let mut file = std::fs::OpenOptions::new()
             .create(true)
+            .truncate(false)
+            .read(true)
             .write(true)
             .open(&tmp_path)
             .map_err(|e| MePassaError::Storage(format!("Failed to open temp file: {}", e)))?;
-        file.seek(SeekFrom::Start(chunk.offset as u64))
+
+        // Persisted progress only counts if the partial file is still there
+        let mut received = self.database.get_media_received_bytes(&chunk.media_hash)?;
+        let on_disk = file
+            .metadata()
+            .map_err(|e| MePassaError::Storage(format!("Failed to stat temp file: {}", e)))?
+            .len();
+        if on_disk < received {
+            received = 0;
+        }
+        if end <= received {
+            // Already have it (retransmission after a resume)
+            return Ok(());
+        }
+
+        file.seek(SeekFrom::Start(offset))
             .map_err(|e| MePassaError::Storage(format!("Failed to seek temp file: {}", e)))?;
-        file.write_all(&chunk.data)
+        file.write_all(&plaintext)
             .map_err(|e| MePassaError::Storage(format!("Failed to write chunk: {}", e)))?;
 
-        if chunk.is_last {
-            let media = self
-                .database
-                .get_media_by_hash(&chunk.media_hash)?
-                .ok_or_else(|| MePassaError::NotFound("Media record not found".to_string()))?;
-
-            let extension = media
-                .file_name
-                .as_ref()
-                .and_then(|name| std::path::Path::new(name).extension())
-                .and_then(|ext| ext.to_str());
-            let file_name = match extension {
-                Some(ext) => format!("{}.{}", chunk.media_hash, ext),
-                None => chunk.media_hash.clone(),
-            };
-            let final_path = self.data_dir.join("media").join(file_name);
-            std::fs::create_dir_all(self.data_dir.join("media"))
-                .map_err(|e| MePassaError::Storage(format!("Failed to create media dir: {}", e)))?;
-            std::fs::rename(&tmp_path, &final_path)
-                .map_err(|e| MePassaError::Storage(format!("Failed to finalize media file: {}", e)))?;
-
+        let contiguous = self.record_media_range(&chunk.media_hash, received, offset, end);
+        if contiguous > received {
             self.database
-                .update_media_local_path(media.id, &final_path.to_string_lossy())
-                .map_err(|e| MePassaError::Storage(e.to_string()))?;
+                .set_media_received_bytes(&chunk.media_hash, contiguous)?;
+            self.emit_event(MessageEvent::MediaProgress {
+                message_id: media.message_id.clone(),
+                media_hash: chunk.media_hash.clone(),
+                bytes_received: contiguous,
+                total_bytes: total,
+            });
+            self.media_progress.send_modify(|n| *n += 1);
+        }
+        if contiguous < total {
+            return Ok(());
+        }
+
+        self.media_ranges.lock().unwrap().remove(&chunk.media_hash);
+        self.database.delete_media_transfer(&chunk.media_hash)?;
+
+        let mut hasher = Sha256::new();
+        file.set_len(total)
+            .and_then(|_| file.seek(SeekFrom::Start(0)))
+            .and_then(|_| std::io::copy(&mut file, &mut hasher))
+            .map_err(|e| MePassaError::Storage(format!("Failed to read temp file: {}", e)))?;
+        drop(file);
+
+        if hasher.finalize().as_slice() != digest {
+            let _ = std::fs::remove_file(&tmp_path);
+            self.media_progress.send_modify(|n| *n += 1);
+            return Err(MePassaError::Crypto(format!(
+                "Digest mismatch for media {}, discarding download",
+                chunk.media_hash
+            )));
         }
 
+        let extension = media
+            .file_name
+            .as_ref()
+            .and_then(|name| std::path::Path::new(name).extension())
+            .and_then(|ext| ext.to_str());
+        let file_name = match extension {
+            Some(ext) => format!("{}.{}", chunk.media_hash, ext),
+            None => chunk.media_hash.clone(),
+        };
+        let final_path = self.data_dir.join("media").join(file_name);
+        std::fs::create_dir_all(self.data_dir.join("media"))
+            .map_err(|e| MePassaError::Storage(format!("Failed to create media dir: {}", e)))?;
+        std::fs::rename(&tmp_path, &final_path)
+            .map_err(|e| MePassaError::Storage(format!("Failed to finalize media file: {}", e)))?;
+
+        self.database
+            .update_media_local_path(media.id, &final_path.to_string_lossy())
+            .map_err(|e| MePassaError::Storage(e.to_string()))?;
+        self.media_progress.send_modify(|n| *n += 1);
+
         Ok(())
     }
 
+    /// Track a written chunk and return the end of the contiguous prefix
+    fn record_media_range(&self, media_hash: &str, received: u64, offset: u64, end: u64) -> u64 {
+        let mut ranges = self.media_ranges.lock().unwrap();
+        let ranges = ranges.entry(media_hash.to_string()).or_default();
+        ranges.insert(offset, end);
+
+        let mut contiguous = received;
+        while let Some((&start, &stop)) = ranges.range(..=contiguous).next() {
+            ranges.remove(&start);
+            contiguous = contiguous.max(stop);
+        }
+        contiguous
+    }
+
+    /// Key and plaintext digest of an end-to-end encrypted file
+    fn media_key(&self, media_hash: &str) -> Result<([u8; 32], [u8; 32])> {
+        let stored = self
+            .database
+            .get_media_key(media_hash)?
+            .ok_or_else(|| MePassaError::Crypto(format!("No key for media {}", media_hash)))?;
+        let key = media_key_from_bytes(&decrypt_for_storage(&self.storage_key, &stored.key_data)?)?;
+        let digest = media_key_from_bytes(&stored.digest)?;
+        Ok((key, digest))
+    }
+
+    /// Read the window of chunks a media request asks for
+    ///
+    /// Only the requested window is read from disk and held in memory.
     pub async fn build_media_chunks(
         &self,
         from_peer: PeerId,
         request: &MediaRequest,
     ) -> Result<Vec<Message>> {
+        use std::io::{Read, Seek, SeekFrom};
+
         let media = self
             .database
             .get_media_by_hash(&request.media_hash)?
@@ -540,41 +1642,56 @@ impl MessageHandler {
         let local_path = media
             .local_path
             .ok_or_else(|| MePassaError::NotFound("Media file missing".to_string()))?;
-        let data = std::fs::read(&local_path)?;
+        let (media_key, _) = self.media_key(&request.media_hash)?;
 
         let chunk_size = if request.chunk_size > 0 {
-            request.chunk_size as usize
+            (request.chunk_size as usize).min(MAX_MEDIA_CHUNK_SIZE)
         } else {
-            64 * 1024
+            MEDIA_CHUNK_SIZE
+        };
+        let window = match request.window {
+            0 => MEDIA_WINDOW,
+            window => window.min(MAX_MEDIA_WINDOW),
         };
 
+        let mut file = std::fs::File::open(&local_path)?;
+        let total = file.metadata()?.len();
+        let mut offset = (request.offset.max(0) as u64).min(total);
+        file.seek(SeekFrom::Start(offset))?;
+
+        let mut index = (offset / chunk_size as u64) as u32;
         let mut chunks = Vec::new();
-        let mut offset: usize = if request.offset > 0 {
-            std::cmp::min(request.offset as usize, data.len())
-        } else {
-            0
-        };
-        while offset < data.len() {
-            let end = std::cmp::min(offset + chunk_size, data.len());
-            let chunk_data = data[offset..end].to_vec();
-            let is_last = end >= data.len();
+        while offset < total && chunks.len() < window as usize {
+            let len = (total - offset).min(chunk_size as u64) as usize;
+            let mut plaintext = vec![0u8; len];
+            file.read_exact(&mut plaintext)?;
+
+            let is_last = offset + len as u64 >= total;
             let chunk = MediaChunk {
                 message_id: request.message_id.clone(),
                 media_hash: request.media_hash.clone(),
                 offset: offset as i64,
-                data: chunk_data,
+                data: encrypt_media_chunk(
+                    &media_key,
+                    &request.media_hash,
+                    index,
+                    offset,
+                    is_last,
+                    &plaintext,
+                )?,
                 is_last,
+                index,
             };
-            let msg = Message {
+            chunks.push(Message {
                 id: uuid::Uuid::new_v4().to_string(),
                 sender_peer_id: self.local_peer_id.clone(),
                 recipient_peer_id: from_peer.to_string(),
                 timestamp: chrono::Utc::now().timestamp_millis(),
                 r#type: MessageType::MediaChunk as i32,
                 payload: Some(Payload::MediaChunk(chunk)),
-            };
-            chunks.push(msg);
-            offset = end;
+            });
+            offset += len as u64;
+            index += 1;
         }
 
         Ok(chunks)
@@ -775,14 +1892,44 @@ pub enum MessageEvent {
         from_peer_id: String,
         is_typing: bool,
     },
+
+    /// A media download made progress
+    MediaProgress {
+        message_id: String,
+        media_hash: String,
+        bytes_received: u64,
+        total_bytes: u64,
+    },
+
+    /// A device was linked to an account (ours included)
+    DeviceLinked {
+        account_peer_id: String,
+        device_peer_id: String,
+    },
+
+    /// Another device of our account sent history or state changes
+    DeviceSynced {
+        device_peer_id: String,
+        messages: usize,
+        changes: usize,
+    },
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crypto::media_digest;
+    use crate::identity::MAX_HANDED_OUT_PREKEYS;
     use crate::storage::{contacts::NewContact, schema::init_schema};
     use libp2p::PeerId;
 
+    /// A keypair and the peer id it signs for
+    fn peer_keypair() -> (crate::identity::Keypair, PeerId) {
+        let keypair = crate::identity::Keypair::generate();
+        let peer = PeerId::from(keypair.to_libp2p_keypair().unwrap().public());
+        (keypair, peer)
+    }
+
     #[tokio::test]
     async fn test_handle_text_message() {
         let db = Database::in_memory().unwrap();
@@ -808,7 +1955,7 @@ mod tests {
         let (event_tx, mut event_rx) = tokio::sync::mpsc::unbounded_channel();
 
         let identity = Arc::new(RwLock::new(crate::identity::Identity::generate(0)));
-        let storage_key = identity.read().await.storage_key().unwrap();
+        let storage_key = identity.read().await.storage_key();
         let session_manager = SessionManager::new();
         let handler = MessageHandler::new(
             local_peer_id.clone(),
@@ -836,7 +1983,7 @@ mod tests {
 
         // Handle message
         let ack = handler
-            .handle_incoming_message(sender_peer, message)
+            .handle_incoming_message(sender_peer, message.clone())
             .await
             .unwrap();
 
@@ -844,6 +1991,13 @@ mod tests {
         assert_eq!(ack.message_id, "msg-123");
         assert_eq!(ack.status, AckStatus::Received as i32);
 
+        // A retransmission (lost ACK) is acknowledged again but not delivered twice
+        let ack = handler
+            .handle_incoming_message(sender_peer, message)
+            .await
+            .unwrap();
+        assert_eq!(ack.status, AckStatus::Received as i32);
+
         // Verify event emitted
         let event = event_rx.recv().await.unwrap();
         match event {
@@ -859,6 +2013,507 @@ mod tests {
             }
             _ => panic!("Expected MessageReceived event"),
         }
+        assert!(event_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_handle_encrypted_message_ratchet() {
+        let db = Database::in_memory().unwrap();
+        init_schema(&db).unwrap();
+
+        let (alice, alice_peer) = peer_keypair();
+        let alice_peer_id = alice_peer.to_string();
+        let bob_peer_id = "bob-peer".to_string();
+
+        db.insert_contact(&NewContact {
+            peer_id: alice_peer_id.clone(),
+            username: None,
+            display_name: Some("Alice".to_string()),
+            public_key: vec![1, 2, 3],
+            prekey_bundle_json: None,
+        })
+        .unwrap();
+
+        let (event_tx, mut event_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let bob_identity = Arc::new(RwLock::new(crate::identity::Identity::generate(0)));
+        let bob_bundle = bob_identity
+            .write()
+            .await
+            .prekey_pool_mut()
+            .unwrap()
+            .get_bundle();
+        let storage_key = bob_identity.read().await.storage_key();
+        let bob_sessions = SessionManager::new();
+        let handler = MessageHandler::new(
+            bob_peer_id.clone(),
+            Arc::new(db),
+            std::env::temp_dir().join("mepassa_test_media"),
+            bob_identity,
+            bob_sessions.clone(),
+            storage_key,
+            Some(event_tx),
+        );
+
+        // Alice starts a session from Bob's bundle and sends two messages
+        let alice_sessions = SessionManager::new().with_identity(alice);
+        alice_sessions
+            .initiate_session(bob_peer_id.clone(), &bob_bundle)
+            .unwrap();
+
+        let to_proto = |id: &str, text: &str| {
+            let (ratchet_message, prekey) =
+                alice_sessions.encrypt_for(&bob_peer_id, text.as_bytes()).unwrap();
+            let prekey = prekey.unwrap();
+            Message {
+                id: id.to_string(),
+                sender_peer_id: alice_peer_id.clone(),
+                recipient_peer_id: bob_peer_id.clone(),
+                timestamp: chrono::Utc::now().timestamp_millis(),
+                r#type: MessageType::Encrypted as i32,
+                payload: Some(Payload::Encrypted(ProtoEncryptedMessage {
+                    ciphertext: ratchet_message.encrypted.ciphertext,
+                    nonce: ratchet_message.encrypted.nonce.to_vec(),
+                    ephemeral_public: prekey.ephemeral_public.to_vec(),
+                    signed_prekey_id: prekey.signed_prekey_id,
+                    one_time_prekey_id: prekey.one_time_prekey_id,
+                    ratchet_public: ratchet_message.header.ratchet_public.to_vec(),
+                    previous_counter: ratchet_message.header.previous_counter,
+                    counter: ratchet_message.header.counter,
+                    ephemeral_signature: prekey.signature.to_vec(),
+                })),
+            }
+        };
+
+        let first = to_proto("msg-1", "first");
+        let second = to_proto("msg-2", "second");
+
+        // Deliver out of order: the second prekey message creates the session
+        for message in [second, first] {
+            handler
+                .handle_incoming_message(alice_peer, message)
+                .await
+                .unwrap();
+        }
+
+        let mut received = Vec::new();
+        for _ in 0..2 {
+            if let MessageEvent::MessageReceived { content, .. } = event_rx.recv().await.unwrap() {
+                received.push(content);
+            }
+        }
+        assert_eq!(received, vec!["second".to_string(), "first".to_string()]);
+
+        // Bob can reply on the ratchet and Alice decrypts it
+        let (reply, prekey) = bob_sessions.encrypt_for(&alice_peer_id, b"reply").unwrap();
+        assert!(prekey.is_none());
+        assert_eq!(
+            alice_sessions.decrypt_from(&bob_peer_id, &reply).unwrap(),
+            b"reply"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_prekey_message_must_come_from_its_signer() {
+        let db = Database::in_memory().unwrap();
+        init_schema(&db).unwrap();
+
+        let (alice, alice_peer) = peer_keypair();
+        let (mallory, mallory_peer) = peer_keypair();
+        let bob_peer_id = "bob-peer".to_string();
+        for peer in [alice_peer, mallory_peer] {
+            db.insert_contact(&NewContact {
+                peer_id: peer.to_string(),
+                username: None,
+                display_name: None,
+                public_key: vec![1, 2, 3],
+                prekey_bundle_json: None,
+            })
+            .unwrap();
+        }
+
+        let bob_identity = crate::identity::Identity::generate(0);
+        let bob_bundle = bob_identity.prekey_pool().unwrap().export_bundle();
+        let storage_key = bob_identity.storage_key();
+        let bob_sessions = SessionManager::new();
+        let handler = MessageHandler::new(
+            bob_peer_id.clone(),
+            Arc::new(db),
+            std::env::temp_dir().join("mepassa_test_media"),
+            Arc::new(RwLock::new(bob_identity)),
+            bob_sessions.clone(),
+            storage_key,
+            None,
+        );
+
+        // Bob and Alice have a session
+        let alice_sessions = SessionManager::new().with_identity(alice);
+        alice_sessions
+            .initiate_session(bob_peer_id.clone(), &bob_bundle)
+            .unwrap();
+        let hello = prekey_message(
+            &alice_sessions,
+            &alice_peer.to_string(),
+            &bob_peer_id,
+            "msg-1",
+            "hello",
+        );
+        let ack = handler.handle_incoming_message(alice_peer, hello).await.unwrap();
+        assert_eq!(ack.status, AckStatus::Received as i32);
+        let established = bob_sessions.get_session(&alice_peer.to_string()).unwrap();
+
+        // Mallory starts her own session, but sends it as Alice
+        let mallory_sessions = SessionManager::new().with_identity(mallory);
+        mallory_sessions
+            .initiate_session(bob_peer_id.clone(), &bob_bundle)
+            .unwrap();
+        let spoofed = prekey_message(
+            &mallory_sessions,
+            &alice_peer.to_string(),
+            &bob_peer_id,
+            "msg-2",
+            "it's me, Alice",
+        );
+
+        // over her own connection
+        let ack = handler
+            .handle_incoming_message(mallory_peer, spoofed.clone())
+            .await
+            .unwrap();
+        assert_eq!(ack.status, AckStatus::Error as i32);
+
+        // or through a path that vouches for Alice: the header isn't hers
+        let ack = handler.handle_incoming_message(alice_peer, spoofed).await.unwrap();
+        assert_eq!(ack.status, AckStatus::Error as i32);
+
+        // Either way Bob keeps the session he had with Alice
+        let kept = bob_sessions.get_session(&alice_peer.to_string()).unwrap();
+        assert_eq!(kept.base_ephemeral, established.base_ephemeral);
+        assert!(!bob_sessions.has_session(&mallory_peer.to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_sessions_only_start_from_the_peers_own_bundle() {
+        let db = Database::in_memory().unwrap();
+        init_schema(&db).unwrap();
+
+        let (alice, alice_peer) = peer_keypair();
+        let mallory = crate::identity::Identity::generate(0);
+        db.insert_contact(&NewContact {
+            peer_id: alice_peer.to_string(),
+            username: None,
+            display_name: Some("Alice".to_string()),
+            public_key: vec![1, 2, 3],
+            // A bundle someone swapped in for Alice's
+            prekey_bundle_json: Some(
+                serde_json::to_string(&mallory.prekey_pool().unwrap().export_bundle()).unwrap(),
+            ),
+        })
+        .unwrap();
+
+        let sessions = SessionManager::new().with_identity(crate::identity::Keypair::generate());
+        assert!(encrypt_for_peer(&db, &sessions, &alice_peer.to_string(), b"hi").is_err());
+        assert!(!sessions.has_session(&alice_peer.to_string()).unwrap());
+
+        // Alice's own bundle works
+        let mut alice = crate::identity::Identity::from_keypair(alice);
+        alice.init_prekey_pool(1);
+        let update = UpdateContact {
+            prekey_bundle_json: Some(Some(
+                serde_json::to_string(&alice.prekey_pool().unwrap().export_bundle()).unwrap(),
+            )),
+            ..Default::default()
+        };
+        db.update_contact(&alice_peer.to_string(), &update).unwrap();
+        assert!(encrypt_for_peer(&db, &sessions, &alice_peer.to_string(), b"hi")
+            .unwrap()
+            .is_some());
+    }
+
+    /// Directory recording what was published
+    #[derive(Default)]
+    struct RecordingDirectory {
+        /// Signed prekey ID of each published bundle
+        bundles: std::sync::Mutex<Vec<u32>>,
+        published: std::sync::Mutex<Vec<u32>>,
+        /// What `one_time_prekey_count` reports
+        remaining: std::sync::Mutex<Option<usize>>,
+    }
+
+    #[async_trait::async_trait]
+    impl PreKeyDirectory for RecordingDirectory {
+        async fn publish_prekeys(
+            &self,
+            bundle: &crate::identity::PreKeyBundle,
+            one_time_prekeys: &[crate::identity::OneTimePreKey],
+        ) -> Result<()> {
+            self.bundles.lock().unwrap().push(bundle.signed_prekey_id);
+            self.published
+                .lock()
+                .unwrap()
+                .extend(one_time_prekeys.iter().map(|prekey| prekey.id));
+            Ok(())
+        }
+
+        async fn one_time_prekey_count(&self) -> Result<Option<usize>> {
+            Ok(*self.remaining.lock().unwrap())
+        }
+    }
+
+    /// First message of a session `sessions` started with `to`
+    fn prekey_message(
+        sessions: &SessionManager,
+        from: &str,
+        to: &str,
+        id: &str,
+        text: &str,
+    ) -> Message {
+        let (ratchet_message, prekey) = sessions.encrypt_for(to, text.as_bytes()).unwrap();
+        let prekey = prekey.unwrap();
+        Message {
+            id: id.to_string(),
+            sender_peer_id: from.to_string(),
+            recipient_peer_id: to.to_string(),
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            r#type: MessageType::Encrypted as i32,
+            payload: Some(Payload::Encrypted(ProtoEncryptedMessage {
+                ciphertext: ratchet_message.encrypted.ciphertext,
+                nonce: ratchet_message.encrypted.nonce.to_vec(),
+                ephemeral_public: prekey.ephemeral_public.to_vec(),
+                signed_prekey_id: prekey.signed_prekey_id,
+                one_time_prekey_id: prekey.one_time_prekey_id,
+                ratchet_public: ratchet_message.header.ratchet_public.to_vec(),
+                previous_counter: ratchet_message.header.previous_counter,
+                counter: ratchet_message.header.counter,
+                ephemeral_signature: prekey.signature.to_vec(),
+            })),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prekey_message_consumes_one_time_prekey() {
+        let db = Database::in_memory().unwrap();
+        init_schema(&db).unwrap();
+
+        let (alice, alice_peer) = peer_keypair();
+        let alice_peer_id = alice_peer.to_string();
+        let bob_peer_id = "bob-peer".to_string();
+        db.insert_contact(&NewContact {
+            peer_id: alice_peer_id.clone(),
+            username: None,
+            display_name: Some("Alice".to_string()),
+            public_key: vec![1, 2, 3],
+            prekey_bundle_json: None,
+        })
+        .unwrap();
+
+        // Bob's pool is one prekey away from needing a refill
+        let mut bob_identity = crate::identity::Identity::generate(20);
+        let bob_keypair = bob_identity.keypair().clone();
+        let bob_bundle = bob_identity.prekey_pool_mut().unwrap().reserve_bundle();
+        let used = bob_bundle.one_time_prekey.as_ref().unwrap().id;
+        let storage_key = bob_identity.storage_key();
+        let store = PreKeyPoolStore::new(db.clone(), storage_key);
+        let directory = Arc::new(RecordingDirectory::default());
+        let bob_identity = Arc::new(RwLock::new(bob_identity));
+        let handler = MessageHandler::new(
+            bob_peer_id.clone(),
+            Arc::new(db),
+            std::env::temp_dir().join("mepassa_test_media"),
+            Arc::clone(&bob_identity),
+            SessionManager::new(),
+            storage_key,
+            None,
+        )
+        .with_prekey_store(store.clone(), Some(directory.clone()));
+
+        let alice_sessions = SessionManager::new().with_identity(alice);
+        alice_sessions
+            .initiate_session(bob_peer_id.clone(), &bob_bundle)
+            .unwrap();
+        let message = prekey_message(&alice_sessions, &alice_peer_id, &bob_peer_id, "msg-1", "hi");
+        let Some(Payload::Encrypted(encrypted)) = &message.payload else {
+            unreachable!()
+        };
+        assert_eq!(encrypted.one_time_prekey_id, used);
+        handler
+            .handle_incoming_message(alice_peer, message)
+            .await
+            .unwrap();
+
+        // The used prekey is gone, in memory and on disk, and the pool was refilled
+        {
+            let identity = bob_identity.read().await;
+            let pool = identity.prekey_pool().unwrap();
+            assert!(pool.get_prekey(used).is_none());
+            assert_eq!(pool.prekey_count(), PREKEY_POOL_SIZE);
+        }
+        let saved = store.load(bob_keypair).unwrap().unwrap();
+        assert!(saved.get_prekey(used).is_none());
+        assert_eq!(saved.prekey_count(), PREKEY_POOL_SIZE);
+
+        // Only the new prekeys are published
+        for _ in 0..100 {
+            if !directory.published.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let published = directory.published.lock().unwrap().clone();
+        assert_eq!(published.len(), PREKEY_POOL_SIZE - 19);
+        assert!(published.iter().all(|id| *id > 21));
+    }
+
+    #[tokio::test]
+    async fn test_replenish_published_prekeys() {
+        let db = Database::in_memory().unwrap();
+        init_schema(&db).unwrap();
+
+        let bob_identity = crate::identity::Identity::generate(PREKEY_POOL_SIZE);
+        let bob_keypair = bob_identity.keypair().clone();
+        let storage_key = bob_identity.storage_key();
+        let store = PreKeyPoolStore::new(db.clone(), storage_key);
+        let directory = Arc::new(RecordingDirectory::default());
+        let bob_identity = Arc::new(RwLock::new(bob_identity));
+        let handler = MessageHandler::new(
+            "bob-peer".to_string(),
+            Arc::new(db),
+            std::env::temp_dir().join("mepassa_test_media"),
+            Arc::clone(&bob_identity),
+            SessionManager::new(),
+            storage_key,
+            None,
+        )
+        .with_prekey_store(store.clone(), Some(directory.clone()));
+
+        // Nothing to do while the directory doesn't count, or has enough
+        assert_eq!(handler.replenish_published_prekeys().await.unwrap(), 0);
+        *directory.remaining.lock().unwrap() = Some(DIRECTORY_REFILL_THRESHOLD);
+        assert_eq!(handler.replenish_published_prekeys().await.unwrap(), 0);
+        assert!(directory.published.lock().unwrap().is_empty());
+
+        // Lookups took most of them: the directory is topped back up with new
+        // keys, while the ones it handed out stay usable
+        *directory.remaining.lock().unwrap() = Some(5);
+        let uploaded = handler.replenish_published_prekeys().await.unwrap();
+        assert_eq!(uploaded, PREKEY_POOL_SIZE - 5);
+        let published = directory.published.lock().unwrap().clone();
+        assert_eq!(published.len(), uploaded);
+        assert!(published.iter().all(|id| *id > PREKEY_POOL_SIZE as u32 + 1));
+        let saved = store.load(bob_keypair.clone()).unwrap().unwrap();
+        assert_eq!(saved.prekey_count(), 2 * PREKEY_POOL_SIZE - 5);
+        assert!(published.iter().all(|id| saved.get_prekey(*id).is_some()));
+
+        // However often the directory is drained, the pool stays bounded
+        for _ in 0..10 {
+            handler.replenish_published_prekeys().await.unwrap();
+        }
+        let saved = store.load(bob_keypair).unwrap().unwrap();
+        assert_eq!(saved.prekey_count(), PREKEY_POOL_SIZE + MAX_HANDED_OUT_PREKEYS);
+    }
+
+    #[tokio::test]
+    async fn test_prekey_message_to_previous_signed_prekey() {
+        let db = Database::in_memory().unwrap();
+        init_schema(&db).unwrap();
+
+        let bob_peer_id = "bob-peer".to_string();
+        let (keypairs, initiators): (Vec<_>, Vec<PeerId>) = (0..2).map(|_| peer_keypair()).unzip();
+        for peer in &initiators {
+            db.insert_contact(&NewContact {
+                peer_id: peer.to_string(),
+                username: None,
+                display_name: None,
+                public_key: vec![1, 2, 3],
+                prekey_bundle_json: None,
+            })
+            .unwrap();
+        }
+
+        let bob_identity = crate::identity::Identity::generate(5);
+        let old_bundle = bob_identity.prekey_pool().unwrap().export_bundle();
+        let storage_key = bob_identity.storage_key();
+        let directory = Arc::new(RecordingDirectory::default());
+        let bob_identity = Arc::new(RwLock::new(bob_identity));
+        let handler = MessageHandler::new(
+            bob_peer_id.clone(),
+            Arc::new(db.clone()),
+            std::env::temp_dir().join("mepassa_test_media"),
+            Arc::clone(&bob_identity),
+            SessionManager::new(),
+            storage_key,
+            None,
+        )
+        .with_prekey_store(
+            PreKeyPoolStore::new(db, storage_key),
+            Some(directory.clone()),
+        )
+        .with_signed_prekey_policy(SignedPreKeyPolicy {
+            rotation_interval: std::time::Duration::ZERO,
+            grace_period: std::time::Duration::from_secs(3600),
+        });
+
+        // Both initiators fetched the bundle before Bob rotated
+        let sessions: Vec<SessionManager> = keypairs
+            .into_iter()
+            .map(|keypair| {
+                let sessions = SessionManager::new().with_identity(keypair);
+                sessions
+                    .initiate_session(bob_peer_id.clone(), &old_bundle)
+                    .unwrap();
+                sessions
+            })
+            .collect();
+        assert!(handler.rotate_signed_prekey_if_due().await.unwrap());
+        let new_id = bob_identity
+            .read()
+            .await
+            .prekey_pool()
+            .unwrap()
+            .signed_prekey()
+            .id;
+        assert_ne!(new_id, old_bundle.signed_prekey_id);
+        assert_eq!(*directory.bundles.lock().unwrap(), vec![new_id]);
+
+        // Within the grace period the old signed prekey still answers
+        let late = prekey_message(
+            &sessions[0],
+            &initiators[0].to_string(),
+            &bob_peer_id,
+            "msg-1",
+            "late",
+        );
+        let ack = handler
+            .handle_incoming_message(initiators[0], late)
+            .await
+            .unwrap();
+        assert_eq!(ack.status, AckStatus::Received as i32);
+
+        // After it, the old signed prekey is gone
+        bob_identity
+            .write()
+            .await
+            .prekey_pool_mut()
+            .unwrap()
+            .rotate_signed_prekey_if_due(
+                chrono::Utc::now().timestamp() + 3600,
+                &SignedPreKeyPolicy {
+                    rotation_interval: std::time::Duration::from_secs(u32::MAX as u64),
+                    grace_period: std::time::Duration::from_secs(3600),
+                },
+            );
+        let too_late = prekey_message(
+            &sessions[1],
+            &initiators[1].to_string(),
+            &bob_peer_id,
+            "msg-2",
+            "too late",
+        );
+        let ack = handler
+            .handle_incoming_message(initiators[1], too_late)
+            .await
+            .unwrap();
+        assert_eq!(ack.status, AckStatus::StaleBundle as i32);
     }
 
     #[tokio::test]
@@ -898,7 +2553,7 @@ mod tests {
             message_id: "msg-456".to_string(),
             conversation_id,
             sender_peer_id: local_peer_id.clone(),
-            recipient_peer_id: Some(remote_peer_id),
+            recipient_peer_id: Some(remote_peer_id.clone()),
             message_type: "text".to_string(),
             content_encrypted: None,
             content_plaintext: Some("Test".to_string()),
@@ -910,7 +2565,7 @@ mod tests {
         let db_arc = Arc::new(db);
 
         let identity = Arc::new(RwLock::new(crate::identity::Identity::generate(0)));
-        let storage_key = identity.read().await.storage_key().unwrap();
+        let storage_key = identity.read().await.storage_key();
         let session_manager = SessionManager::new();
         let handler = MessageHandler::new(
             local_peer_id,
@@ -922,6 +2577,18 @@ mod tests {
             None,
         );
 
+        // Someone the message wasn't sent to can't acknowledge it
+        let stranger_ack = AckMessage {
+            message_id: "msg-456".to_string(),
+            status: AckStatus::Error as i32,
+            error: String::new(),
+        };
+        handler
+            .handle_outgoing_ack(&PeerId::random().to_string(), stranger_ack)
+            .await
+            .unwrap();
+        assert_eq!(db_arc.get_message("msg-456").unwrap().status, MessageStatus::Sent);
+
         // Create ACK message
         let ack = AckMessage {
             message_id: "msg-456".to_string(),
@@ -930,7 +2597,7 @@ mod tests {
         };
 
         // Handle ACK
-        handler.handle_outgoing_ack(ack).await.unwrap();
+        handler.handle_outgoing_ack(&remote_peer_id, ack).await.unwrap();
 
         // Verify message status updated
         {
@@ -938,4 +2605,201 @@ mod tests {
             assert_eq!(message.status, MessageStatus::Delivered);
         }
     }
+
+    /// Handler with a temporary media directory and a known sender contact
+    async fn media_handler(data_dir: &std::path::Path) -> (MessageHandler, Arc<Database>, PeerId) {
+        let db = Database::in_memory().unwrap();
+        init_schema(&db).unwrap();
+
+        let sender = PeerId::random();
+        db.insert_contact(&NewContact {
+            peer_id: sender.to_string(),
+            username: None,
+            display_name: Some("Sender".to_string()),
+            public_key: vec![1, 2, 3],
+            prekey_bundle_json: None,
+        })
+        .unwrap();
+        let db = Arc::new(db);
+
+        let identity = Arc::new(RwLock::new(crate::identity::Identity::generate(0)));
+        let storage_key = identity.read().await.storage_key();
+        let handler = MessageHandler::new(
+            "local-peer".to_string(),
+            Arc::clone(&db),
+            data_dir.to_path_buf(),
+            identity,
+            SessionManager::new(),
+            storage_key,
+            None,
+        );
+        (handler, db, sender)
+    }
+
+    /// Offer a file to `handler`, returning the media key
+    async fn offer_media(
+        handler: &MessageHandler,
+        sender: PeerId,
+        message_id: &str,
+        file_size: usize,
+        digest: [u8; 32],
+    ) -> [u8; 32] {
+        let key = crate::crypto::generate_media_key();
+        let offer = MediaOffer {
+            message_id: message_id.to_string(),
+            media_hash: format!("hash-{}", message_id),
+            media_type: "document".to_string(),
+            file_name: "notes.txt".to_string(),
+            mime_type: "text/plain".to_string(),
+            file_size: file_size as i64,
+            media_key: key.to_vec(),
+            digest: digest.to_vec(),
+            ..Default::default()
+        };
+        let offer_message = Message {
+            id: message_id.to_string(),
+            sender_peer_id: sender.to_string(),
+            recipient_peer_id: "local-peer".to_string(),
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            r#type: MessageType::MediaOffer as i32,
+            payload: None,
+        };
+        handler.handle_media_offer(&offer_message, &offer).await.unwrap();
+        key
+    }
+
+    /// Deliver the given 4-byte chunks of `data`, in the given order
+    async fn deliver_chunks(
+        handler: &MessageHandler,
+        message_id: &str,
+        key: &[u8; 32],
+        data: &[u8],
+        order: &[usize],
+    ) -> Result<()> {
+        let media_hash = format!("hash-{}", message_id);
+        let chunks: Vec<&[u8]> = data.chunks(4).collect();
+        for &index in order {
+            let offset = (index * 4) as u64;
+            let is_last = index == chunks.len() - 1;
+            let chunk = MediaChunk {
+                message_id: message_id.to_string(),
+                media_hash: media_hash.clone(),
+                offset: offset as i64,
+                data: encrypt_media_chunk(key, &media_hash, index as u32, offset, is_last, chunks[index])?,
+                is_last,
+                index: index as u32,
+            };
+            handler.handle_media_chunk(&Message::default(), &chunk).await?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_encrypted_media_chunks() {
+        let data_dir = tempfile::TempDir::new().unwrap();
+        let (handler, db, sender) = media_handler(data_dir.path()).await;
+
+        // Chunks may arrive out of order
+        let data = b"attachment contents";
+        let key = offer_media(&handler, sender, "media-ok", data.len(), media_digest(data)).await;
+        deliver_chunks(&handler, "media-ok", &key, data, &[4, 1, 0, 3, 2])
+            .await
+            .unwrap();
+        let media = db.get_media_by_hash("hash-media-ok").unwrap().unwrap();
+        assert_eq!(std::fs::read(media.local_path.unwrap()).unwrap(), data);
+
+        // A file that doesn't match the announced digest is discarded
+        let key = offer_media(&handler, sender, "media-bad", data.len(), media_digest(b"other")).await;
+        let err = deliver_chunks(&handler, "media-bad", &key, data, &[0, 1, 2, 3, 4])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, MePassaError::Crypto(_)));
+        let media = db.get_media_by_hash("hash-media-bad").unwrap().unwrap();
+        assert!(media.local_path.is_none());
+
+        // A cleartext offer is refused
+        let cleartext = Message {
+            id: "media-clear".to_string(),
+            sender_peer_id: sender.to_string(),
+            recipient_peer_id: "local-peer".to_string(),
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            r#type: MessageType::MediaOffer as i32,
+            payload: Some(Payload::MediaOffer(MediaOffer::default())),
+        };
+        let ack = handler
+            .handle_incoming_message(sender, cleartext)
+            .await
+            .unwrap();
+        assert_eq!(ack.status, AckStatus::Error as i32);
+        assert!(db.get_media_by_hash("").unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_media_download_resumes_from_persisted_offset() {
+        let data_dir = tempfile::TempDir::new().unwrap();
+        let (handler, db, sender) = media_handler(data_dir.path()).await;
+        let mut progress = handler.watch_media_progress();
+
+        let data = b"attachment contents";
+        let key = offer_media(&handler, sender, "media-1", data.len(), media_digest(data)).await;
+
+        // Only the contiguous prefix counts as received
+        deliver_chunks(&handler, "media-1", &key, data, &[0, 1, 3]).await.unwrap();
+        assert_eq!(db.get_media_received_bytes("hash-media-1").unwrap(), 8);
+        assert!(progress.has_changed().unwrap());
+
+        // After a restart the chunks past the prefix are requested again
+        let restarted = MessageHandler::new(
+            "local-peer".to_string(),
+            Arc::clone(&db),
+            data_dir.path().to_path_buf(),
+            Arc::clone(&handler.identity),
+            SessionManager::new(),
+            handler.storage_key,
+            None,
+        );
+        deliver_chunks(&restarted, "media-1", &key, data, &[1, 2, 3, 4])
+            .await
+            .unwrap();
+
+        let media = db.get_media_by_hash("hash-media-1").unwrap().unwrap();
+        assert_eq!(std::fs::read(media.local_path.unwrap()).unwrap(), data);
+        assert_eq!(db.get_media_received_bytes("hash-media-1").unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_build_media_chunks_reads_one_window() {
+        let data_dir = tempfile::TempDir::new().unwrap();
+        let (handler, _db, sender) = media_handler(data_dir.path()).await;
+
+        let data = b"attachment contents";
+        let key = offer_media(&handler, sender, "media-1", data.len(), media_digest(data)).await;
+        deliver_chunks(&handler, "media-1", &key, data, &[0, 1, 2, 3, 4])
+            .await
+            .unwrap();
+
+        let request = MediaRequest {
+            message_id: "media-1".to_string(),
+            media_hash: "hash-media-1".to_string(),
+            offset: 8,
+            chunk_size: 4,
+            window: 2,
+        };
+        let chunks = handler.build_media_chunks(sender, &request).await.unwrap();
+
+        let chunks: Vec<MediaChunk> = chunks
+            .into_iter()
+            .map(|message| match message.payload {
+                Some(Payload::MediaChunk(chunk)) => chunk,
+                _ => panic!("Expected a media chunk"),
+            })
+            .collect();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!((chunks[0].index, chunks[0].offset), (2, 8));
+        assert_eq!((chunks[1].index, chunks[1].offset), (3, 12));
+        assert!(!chunks[1].is_last);
+
+        let plaintext = decrypt_media_chunk(&key, "hash-media-1", 3, 12, false, &chunks[1].data).unwrap();
+        assert_eq!(plaintext, b"onte");
+    }
 }
@@ -6,11 +6,44 @@ use futures::prelude::*;
 use libp2p::{request_response, StreamProtocol};
 use std::io;
 
+use super::framing::{read_frame, write_frame, FrameError, MAX_MESSAGE_FRAME_SIZE};
 use crate::protocol::{codec, Message};
 
 /// Codec for encoding/decoding messages over the wire
-#[derive(Clone, Debug, Default)]
-pub struct MePassaCodec;
+#[derive(Clone, Debug)]
+pub struct MePassaCodec {
+    /// Largest frame accepted (or sent) in either direction
+    max_frame_size: usize,
+}
+
+impl MePassaCodec {
+    pub fn new(max_frame_size: usize) -> Self {
+        Self { max_frame_size }
+    }
+
+    async fn read_message<T>(&self, io: &mut T) -> io::Result<Message>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let data = read_frame(io, self.max_frame_size).await?;
+        codec::decode(&data).map_err(|e| FrameError::Malformed(e.to_string()).into())
+    }
+
+    async fn write_message<T>(&self, io: &mut T, message: &Message) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let data = codec::encode(message).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        write_frame(io, &data, self.max_frame_size).await?;
+        io.close().await
+    }
+}
+
+impl Default for MePassaCodec {
+    fn default() -> Self {
+        Self::new(MAX_MESSAGE_FRAME_SIZE)
+    }
+}
 
 #[async_trait::async_trait]
 impl request_response::Codec for MePassaCodec {
@@ -26,16 +59,7 @@ impl request_response::Codec for MePassaCodec {
     where
         T: AsyncRead + Unpin + Send,
     {
-        // Read length prefix (4 bytes)
-        let mut len_buf = [0u8; 4];
-        io.read_exact(&mut len_buf).await?;
-        let len = u32::from_be_bytes(len_buf) as usize;
-
-        // Read message data
-        let mut data = vec![0u8; len];
-        io.read_exact(&mut data).await?;
-
-        codec::decode(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        self.read_message(io).await
     }
 
     async fn read_response<T>(
@@ -46,16 +70,7 @@ impl request_response::Codec for MePassaCodec {
     where
         T: AsyncRead + Unpin + Send,
     {
-        // Read length prefix (4 bytes)
-        let mut len_buf = [0u8; 4];
-        io.read_exact(&mut len_buf).await?;
-        let len = u32::from_be_bytes(len_buf) as usize;
-
-        // Read message data
-        let mut data = vec![0u8; len];
-        io.read_exact(&mut data).await?;
-
-        codec::decode(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        self.read_message(io).await
     }
 
     async fn write_request<T>(
@@ -67,15 +82,7 @@ impl request_response::Codec for MePassaCodec {
     where
         T: AsyncWrite + Unpin + Send,
     {
-        let data = codec::encode(&req).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-
-        // Write length prefix (4 bytes)
-        let len = data.len() as u32;
-        io.write_all(&len.to_be_bytes()).await?;
-
-        // Write message data
-        io.write_all(&data).await?;
-        io.close().await
+        self.write_message(io, &req).await
     }
 
     async fn write_response<T>(
@@ -87,14 +94,100 @@ impl request_response::Codec for MePassaCodec {
     where
         T: AsyncWrite + Unpin + Send,
     {
-        let data = codec::encode(&res).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.write_message(io, &res).await
+    }
+}
 
-        // Write length prefix (4 bytes)
-        let len = data.len() as u32;
-        io.write_all(&len.to_be_bytes()).await?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::pb::{message::Payload, MessageType, TextMessage};
+    use futures::executor::block_on;
+    use futures::io::Cursor;
+    use proptest::prelude::*;
+    use request_response::Codec;
+
+    fn protocol() -> StreamProtocol {
+        StreamProtocol::new("/mepassa/message/1.0.0")
+    }
 
-        // Write message data
-        io.write_all(&data).await?;
-        io.close().await
+    fn text_message(id: String, content: String) -> Message {
+        Message {
+            id,
+            sender_peer_id: "sender".to_string(),
+            recipient_peer_id: "recipient".to_string(),
+            timestamp: 1234567890,
+            r#type: MessageType::Text as i32,
+            payload: Some(Payload::Text(TextMessage {
+                content,
+                reply_to_id: String::new(),
+                metadata: std::collections::HashMap::new(),
+            })),
+        }
+    }
+
+    fn read(codec: &mut MePassaCodec, bytes: Vec<u8>) -> io::Result<Message> {
+        block_on(codec.read_request(&protocol(), &mut Cursor::new(bytes)))
+    }
+
+    #[test]
+    fn test_rejects_oversized_frame() {
+        let mut codec = MePassaCodec::new(1024);
+        let mut bytes = 2048u32.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&[0u8; 2048]);
+
+        let err = read(&mut codec, bytes).unwrap_err();
+        assert!(matches!(
+            FrameError::from_io(&err),
+            Some(FrameError::TooLarge { len: 2048, max: 1024 })
+        ));
+    }
+
+    #[test]
+    fn test_refuses_to_send_oversized_message() {
+        let mut codec = MePassaCodec::new(64);
+        let message = text_message("id".to_string(), "x".repeat(128));
+
+        let mut buf = Cursor::new(Vec::new());
+        let err = block_on(codec.write_request(&protocol(), &mut buf, message)).unwrap_err();
+        assert!(matches!(FrameError::from_io(&err), Some(FrameError::TooLarge { .. })));
+        // Our own refusal is not held against the peer
+        assert!(FrameError::received(&err).is_none());
+    }
+
+    proptest! {
+        #[test]
+        fn prop_roundtrip(id in ".{0,64}", content in ".{0,2048}") {
+            let mut codec = MePassaCodec::default();
+            let message = text_message(id, content);
+
+            let mut buf = Cursor::new(Vec::new());
+            block_on(codec.write_request(&protocol(), &mut buf, message.clone())).unwrap();
+            prop_assert_eq!(read(&mut codec, buf.into_inner()).unwrap(), message);
+        }
+
+        #[test]
+        fn prop_arbitrary_input_never_panics(bytes in prop::collection::vec(any::<u8>(), 0..512)) {
+            let mut codec = MePassaCodec::new(256);
+            if let Err(err) = read(&mut codec, bytes.clone()) {
+                // Either the stream ended early or the frame is typed as rejected
+                if err.kind() != io::ErrorKind::UnexpectedEof {
+                    prop_assert!(FrameError::from_io(&err).is_some());
+                }
+            } else {
+                prop_assert!(bytes.len() >= 4);
+            }
+        }
+
+        #[test]
+        fn prop_declared_length_above_limit_rejected(len in 257u32.., tail in prop::collection::vec(any::<u8>(), 0..64)) {
+            let mut codec = MePassaCodec::new(256);
+            let mut bytes = len.to_be_bytes().to_vec();
+            bytes.extend(tail);
+
+            let err = read(&mut codec, bytes).unwrap_err();
+            let too_large = matches!(FrameError::from_io(&err), Some(FrameError::TooLarge { .. }));
+            prop_assert!(too_large);
+        }
     }
 }
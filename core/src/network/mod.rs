@@ -2,11 +2,14 @@
 //!
 //! Implements P2P networking using libp2p (Kademlia DHT, GossipSub, Relay).
 
+pub mod autonat;
 pub mod behaviour;
 pub mod connection;
+pub mod framing;
 pub mod message_handler;
 pub mod messaging;
 pub mod nat_detection;
+pub mod peer_record;
 pub mod relay;
 pub mod retry;
 pub mod swarm;
@@ -15,13 +18,20 @@ pub mod transport;
 // pub mod gossip;
 
 pub use behaviour::MePassaBehaviour;
-pub use connection::{ConnectionManager, ConnectionState, ConnectionStrategy, ConnectionType};
+pub use connection::{
+    ConnectionManager, ConnectionQuality, ConnectionState, ConnectionStrategy, ConnectionType,
+};
+pub use framing::{FrameError, FrameLimits};
 pub use message_handler::{MessageEvent, MessageHandler};
 pub use messaging::MePassaCodec;
-pub use nat_detection::{ConnectionStrategy as NatConnectionStrategy, NatDetector, NatType};
+pub use autonat::AutoNatConfig;
+pub use nat_detection::{
+    ConnectionStrategy as NatConnectionStrategy, NatDetector, ProbeResult, Reachability,
+};
+pub use peer_record::{AddressRecord, RecordError};
 pub use relay::{RelayManager, ReservationStatus};
 pub use retry::RetryPolicy;
-pub use swarm::NetworkManager;
+pub use swarm::{NetworkEvent, NetworkManager};
 
 use thiserror::Error;
 
@@ -1,49 +1,105 @@
-//! NAT type detection helper
+//! NAT reachability detection
 //!
-//! Provides utilities for detecting the type of NAT a peer is behind,
-//! which helps determine the best connection strategy.
+//! Tracks whether this node is reachable from the internet, as confirmed by
+//! AutoNAT dial-backs (see `autonat`), which determines the best connection
+//! strategy. A status is only overturned after `confidence_max` probes
+//! disagree with it, so a single flaky server can't flip it.
 
-use libp2p::Multiaddr;
-use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use std::time::Instant;
 
-/// Types of NAT
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum NatType {
-    /// Full Cone NAT: External IP:Port same for all destinations
-    /// Direct connection likely works
-    FullCone,
+use super::autonat::{AutoNatConfig, DialResponse, ResponseStatus};
 
-    /// Restricted NAT: External IP same, port varies by destination
-    /// Medium difficulty, hole punching might work
-    Restricted,
+/// Reachability of this node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reachability {
+    /// Not probed yet (or no conclusive answer so far)
+    Unknown,
+    /// Servers reached us directly at this address
+    Public(Multiaddr),
+    /// Servers failed to dial us back: behind a NAT or firewall
+    Private,
+}
 
-    /// Port Restricted NAT: Both IP and port vary by destination
-    /// Hard, DCUtR needed
-    PortRestricted,
+impl Reachability {
+    fn same_kind(&self, other: &Reachability) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
 
-    /// Symmetric NAT: Different external IP:Port per destination
-    /// Hardest, relay required
-    Symmetric,
+/// Outcome of one AutoNAT probe
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProbeResult {
+    /// The server dialed us back at this address
+    Reachable(Multiaddr),
+    /// The server tried and failed to dial us back
+    Unreachable,
+    /// No verdict (refused, bad request, server unreachable...)
+    Inconclusive,
+}
 
-    /// Unknown NAT type
-    Unknown,
+impl From<DialResponse> for ProbeResult {
+    fn from(response: DialResponse) -> Self {
+        match response {
+            DialResponse::Reachable(addr) => ProbeResult::Reachable(addr),
+            DialResponse::Failed {
+                status: ResponseStatus::DialError,
+                ..
+            } => ProbeResult::Unreachable,
+            DialResponse::Failed { .. } => ProbeResult::Inconclusive,
+        }
+    }
 }
 
 /// NAT detector
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub struct NatDetector {
-    /// Observed external addresses from different peers
+    config: AutoNatConfig,
+    /// Observed external addresses from different peers (probe candidates)
     observed_addrs: Vec<Multiaddr>,
+    reachability: Reachability,
+    /// Probes agreeing with the current status, up to `confidence_max`
+    confidence: usize,
+    /// Peers that answer AutoNAT probes
+    servers: Vec<PeerId>,
+    /// Server asked last (the next probe prefers another one)
+    last_server: Option<PeerId>,
+    /// Probe in flight, and since when
+    probe_started: Option<Instant>,
+    next_probe: Instant,
+}
+
+impl Default for NatDetector {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl NatDetector {
     /// Create a new NAT detector
     pub fn new() -> Self {
+        Self::with_config(AutoNatConfig::default())
+    }
+
+    /// Create a NAT detector with custom probe settings
+    pub fn with_config(config: AutoNatConfig) -> Self {
         Self {
+            next_probe: Instant::now() + config.boot_delay,
+            config,
             observed_addrs: Vec::new(),
+            reachability: Reachability::Unknown,
+            confidence: 0,
+            servers: Vec::new(),
+            last_server: None,
+            probe_started: None,
         }
     }
 
+    /// Probe settings
+    pub fn config(&self) -> &AutoNatConfig {
+        &self.config
+    }
+
     /// Add an observed external address
     pub fn add_observed_address(&mut self, addr: Multiaddr) {
         if !self.observed_addrs.contains(&addr) {
@@ -51,200 +107,258 @@ impl NatDetector {
         }
     }
 
-    /// Guess NAT type based on observed addresses
-    ///
-    /// Simple heuristic:
-    /// - If all observed addresses match → FullCone
-    /// - If same IP but different ports → PortRestricted
-    /// - If different IPs → Symmetric
-    pub fn guess_nat_type(&self) -> NatType {
-        if self.observed_addrs.is_empty() {
-            return NatType::Unknown;
-        }
-
-        if self.observed_addrs.len() == 1 {
-            // Only one observation, assume FullCone
-            return NatType::FullCone;
-        }
-
-        let mut first_ip: Option<String> = None;
-        let mut first_port: Option<u16> = None;
-        let mut ip_changed = false;
-        let mut port_changed = false;
+    /// Get all observed addresses
+    pub fn observed_addresses(&self) -> &[Multiaddr] {
+        &self.observed_addrs
+    }
 
-        for addr in &self.observed_addrs {
-            let (ip, port) = match Self::extract_ip_port(addr) {
-                Some(value) => value,
-                None => continue,
-            };
+    /// Clear observed addresses
+    pub fn clear(&mut self) {
+        self.observed_addrs.clear();
+    }
 
-            if first_ip.is_none() {
-                first_ip = Some(ip);
-                first_port = Some(port);
-                continue;
-            }
+    /// Current reachability
+    pub fn reachability(&self) -> &Reachability {
+        &self.reachability
+    }
 
-            if let Some(ref first_ip_val) = first_ip {
-                if *first_ip_val != ip {
-                    ip_changed = true;
-                }
-            }
-            if let Some(first_port_val) = first_port {
-                if first_port_val != port {
-                    port_changed = true;
-                }
-            }
-        }
+    /// How many probes confirmed the current reachability
+    pub fn confidence(&self) -> usize {
+        self.confidence
+    }
 
-        if ip_changed {
-            NatType::Symmetric
-        } else if port_changed {
-            NatType::PortRestricted
-        } else {
-            NatType::FullCone
+    /// Add a peer that supports AutoNAT
+    pub fn add_server(&mut self, peer_id: PeerId) {
+        if !self.servers.contains(&peer_id) {
+            self.servers.push(peer_id);
         }
     }
 
-    /// Determine if relay should be used based on NAT type
-    pub fn should_use_relay(&self) -> bool {
-        matches!(
-            self.guess_nat_type(),
-            NatType::Symmetric | NatType::PortRestricted
-        )
+    /// Remove a server (e.g. when disconnected)
+    pub fn remove_server(&mut self, peer_id: &PeerId) {
+        self.servers.retain(|p| p != peer_id);
     }
 
-    /// Get all observed addresses
-    pub fn observed_addresses(&self) -> &[Multiaddr] {
-        &self.observed_addrs
+    /// Pick the server for the next probe, if one is due
+    pub fn next_probe_server(&self, now: Instant) -> Option<PeerId> {
+        if self.probe_started.is_some() || now < self.next_probe {
+            return None;
+        }
+        self.select_server()
     }
 
-    /// Clear observed addresses
-    pub fn clear(&mut self) {
-        self.observed_addrs.clear();
+    /// Pick a server, preferring one not asked last time
+    pub fn select_server(&self) -> Option<PeerId> {
+        self.servers
+            .iter()
+            .find(|p| Some(**p) != self.last_server)
+            .or_else(|| self.servers.first())
+            .copied()
     }
 
-    /// Get recommendation for connection strategy
-    pub fn connection_recommendation(&self) -> ConnectionStrategy {
-        match self.guess_nat_type() {
-            NatType::FullCone => ConnectionStrategy::DirectFirst,
-            NatType::Restricted => ConnectionStrategy::DirectFirst,
-            NatType::PortRestricted => ConnectionStrategy::HolePunchFirst,
-            NatType::Symmetric => ConnectionStrategy::RelayFirst,
-            NatType::Unknown => ConnectionStrategy::DirectFirst, // Optimistic default
-        }
+    /// Record that a probe was sent to `server`
+    pub fn probe_started(&mut self, server: PeerId, now: Instant) {
+        self.last_server = Some(server);
+        self.probe_started = Some(now);
     }
 
-    fn extract_ip_port(addr: &Multiaddr) -> Option<(String, u16)> {
-        let mut ip: Option<String> = None;
-        let mut port: Option<u16> = None;
+    /// Record the outcome of a probe; returns the new reachability if it changed
+    pub fn record_probe(&mut self, result: ProbeResult, now: Instant) -> Option<Reachability> {
+        self.probe_started = None;
 
-        for protocol in addr.iter() {
-            match protocol {
-                Protocol::Ip4(v4) => ip = Some(v4.to_string()),
-                Protocol::Ip6(v6) => ip = Some(v6.to_string()),
-                Protocol::Tcp(p) => port = Some(p),
-                Protocol::Udp(p) => port = Some(p),
-                _ => {}
+        let observed = match result {
+            ProbeResult::Reachable(addr) => Reachability::Public(addr),
+            ProbeResult::Unreachable => Reachability::Private,
+            ProbeResult::Inconclusive => {
+                self.next_probe = now + self.config.retry_interval;
+                return None;
             }
-        }
+        };
+
+        let changed = if self.reachability.same_kind(&observed) {
+            self.confidence = (self.confidence + 1).min(self.config.confidence_max);
+            // Keep the latest confirmed address
+            self.reachability = observed;
+            None
+        } else if self.confidence > 0 {
+            self.confidence -= 1;
+            None
+        } else {
+            self.reachability = observed;
+            Some(self.reachability.clone())
+        };
+
+        self.next_probe = now
+            + if self.confidence >= self.config.confidence_max {
+                self.config.refresh_interval
+            } else {
+                self.config.retry_interval
+            };
+        changed
+    }
+
+    /// Probe as soon as possible (e.g. after our addresses changed)
+    pub fn probe_now(&mut self, now: Instant) {
+        self.next_probe = now;
+    }
+
+    /// Determine if relay should be used based on reachability
+    pub fn should_use_relay(&self) -> bool {
+        self.reachability == Reachability::Private
+    }
 
-        match (ip, port) {
-            (Some(ip_val), Some(port_val)) => Some((ip_val, port_val)),
-            _ => None,
+    /// Get recommendation for connection strategy
+    pub fn connection_recommendation(&self) -> ConnectionStrategy {
+        match self.reachability {
+            Reachability::Public(_) => ConnectionStrategy::DirectFirst,
+            Reachability::Private => ConnectionStrategy::RelayFirst,
+            Reachability::Unknown => ConnectionStrategy::DirectFirst, // Optimistic default
         }
     }
 }
 
-/// Recommended connection strategy based on NAT type
+/// Recommended connection strategy based on reachability
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ConnectionStrategy {
-    /// Try direct connection first (best for FullCone/Restricted NAT)
+    /// Try direct connection first (publicly reachable)
     DirectFirst,
-    /// Try hole punching first (best for PortRestricted NAT)
+    /// Try hole punching first
     HolePunchFirst,
-    /// Use relay immediately (best for Symmetric NAT)
+    /// Connect through the relay, DCUtR upgrades the connection when it can
+    /// (not reachable from outside)
     RelayFirst,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::Duration;
+
+    fn public_addr() -> Multiaddr {
+        "/ip4/1.2.3.4/tcp/1234".parse().unwrap()
+    }
 
     #[test]
     fn test_empty_detector() {
         let detector = NatDetector::new();
-        assert_eq!(detector.guess_nat_type(), NatType::Unknown);
+        assert_eq!(*detector.reachability(), Reachability::Unknown);
         assert!(!detector.should_use_relay());
+        assert_eq!(
+            detector.connection_recommendation(),
+            ConnectionStrategy::DirectFirst
+        );
     }
 
     #[test]
-    fn test_single_observation() {
+    fn test_first_probe_sets_status() {
         let mut detector = NatDetector::new();
-        let addr: Multiaddr = "/ip4/1.2.3.4/tcp/1234".parse().unwrap();
-        detector.add_observed_address(addr);
+        let now = Instant::now();
 
-        assert_eq!(detector.guess_nat_type(), NatType::FullCone);
-        assert!(!detector.should_use_relay());
+        let changed = detector.record_probe(ProbeResult::Unreachable, now);
+        assert_eq!(changed, Some(Reachability::Private));
+        assert!(detector.should_use_relay());
+        assert_eq!(
+            detector.connection_recommendation(),
+            ConnectionStrategy::RelayFirst
+        );
     }
 
     #[test]
-    fn test_full_cone_detection() {
-        let mut detector = NatDetector::new();
-        let addr: Multiaddr = "/ip4/1.2.3.4/tcp/1234".parse().unwrap();
-
-        // Add same address multiple times
-        detector.add_observed_address(addr.clone());
-        detector.add_observed_address(addr.clone());
-        detector.add_observed_address(addr);
+    fn test_confident_status_needs_several_probes_to_flip() {
+        let mut detector = NatDetector::with_config(AutoNatConfig {
+            confidence_max: 2,
+            ..Default::default()
+        });
+        let now = Instant::now();
+
+        detector.record_probe(ProbeResult::Reachable(public_addr()), now);
+        detector.record_probe(ProbeResult::Reachable(public_addr()), now);
+        detector.record_probe(ProbeResult::Reachable(public_addr()), now);
+        assert_eq!(detector.confidence(), 2);
+
+        // Two disagreeing probes only erode the confidence
+        assert_eq!(detector.record_probe(ProbeResult::Unreachable, now), None);
+        assert_eq!(detector.record_probe(ProbeResult::Unreachable, now), None);
+        assert_eq!(*detector.reachability(), Reachability::Public(public_addr()));
 
-        assert_eq!(detector.guess_nat_type(), NatType::FullCone);
-        assert!(!detector.should_use_relay());
+        assert_eq!(
+            detector.record_probe(ProbeResult::Unreachable, now),
+            Some(Reachability::Private)
+        );
     }
 
     #[test]
-    fn test_symmetric_detection() {
+    fn test_inconclusive_probe_keeps_status() {
         let mut detector = NatDetector::new();
+        let now = Instant::now();
 
-        let addr1: Multiaddr = "/ip4/1.2.3.4/tcp/1234".parse().unwrap();
-        let addr2: Multiaddr = "/ip4/1.2.3.4/tcp/5678".parse().unwrap();
-
-        detector.add_observed_address(addr1);
-        detector.add_observed_address(addr2);
+        assert_eq!(detector.record_probe(ProbeResult::Inconclusive, now), None);
+        assert_eq!(*detector.reachability(), Reachability::Unknown);
+        assert_eq!(
+            ProbeResult::from(DialResponse::failed(ResponseStatus::DialRefused, "busy")),
+            ProbeResult::Inconclusive
+        );
+        assert_eq!(
+            ProbeResult::from(DialResponse::failed(ResponseStatus::DialError, "timeout")),
+            ProbeResult::Unreachable
+        );
+    }
 
-        assert_eq!(detector.guess_nat_type(), NatType::Symmetric);
-        assert!(detector.should_use_relay());
+    #[test]
+    fn test_probe_scheduling() {
+        let config = AutoNatConfig {
+            boot_delay: Duration::from_secs(10),
+            retry_interval: Duration::from_secs(60),
+            refresh_interval: Duration::from_secs(600),
+            confidence_max: 1,
+        };
+        let mut detector = NatDetector::with_config(config);
+        let start = Instant::now();
+        let server = PeerId::random();
+
+        // Nothing to ask, then too early
+        assert_eq!(detector.next_probe_server(start + Duration::from_secs(20)), None);
+        detector.add_server(server);
+        assert_eq!(detector.next_probe_server(start), None);
+
+        let now = start + Duration::from_secs(11);
+        assert_eq!(detector.next_probe_server(now), Some(server));
+        detector.probe_started(server, now);
+        // One probe at a time
+        assert_eq!(detector.next_probe_server(now), None);
+
+        // Unconfirmed status: retry soon
+        detector.record_probe(ProbeResult::Reachable(public_addr()), now);
+        assert_eq!(detector.next_probe_server(now + Duration::from_secs(61)), Some(server));
+
+        // Confirmed: refresh rarely
+        detector.record_probe(ProbeResult::Reachable(public_addr()), now);
+        assert_eq!(detector.next_probe_server(now + Duration::from_secs(61)), None);
+        assert_eq!(detector.next_probe_server(now + Duration::from_secs(601)), Some(server));
     }
 
     #[test]
-    fn test_connection_strategy() {
+    fn test_rotates_servers() {
         let mut detector = NatDetector::new();
-        assert_eq!(
-            detector.connection_recommendation(),
-            ConnectionStrategy::DirectFirst
-        );
+        let (a, b) = (PeerId::random(), PeerId::random());
+        detector.add_server(a);
+        detector.add_server(b);
 
-        let addr1: Multiaddr = "/ip4/1.2.3.4/tcp/1234".parse().unwrap();
-        let addr2: Multiaddr = "/ip4/1.2.3.4/tcp/5678".parse().unwrap();
+        detector.probe_started(a, Instant::now());
+        assert_eq!(detector.select_server(), Some(b));
 
-        detector.add_observed_address(addr1);
-        detector.add_observed_address(addr2);
-
-        assert_eq!(
-            detector.connection_recommendation(),
-            ConnectionStrategy::RelayFirst
-        );
+        detector.remove_server(&b);
+        assert_eq!(detector.select_server(), Some(a));
     }
 
     #[test]
     fn test_clear() {
         let mut detector = NatDetector::new();
-        let addr: Multiaddr = "/ip4/1.2.3.4/tcp/1234".parse().unwrap();
 
-        detector.add_observed_address(addr);
+        detector.add_observed_address(public_addr());
         assert_eq!(detector.observed_addresses().len(), 1);
 
         detector.clear();
         assert_eq!(detector.observed_addresses().len(), 0);
-        assert_eq!(detector.guess_nat_type(), NatType::Unknown);
     }
 }
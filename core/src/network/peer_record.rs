@@ -0,0 +1,268 @@
+//! Signed Peer Address Records
+//!
+//! Peers publish where they can be reached under `mepassa:addr:<peer id>` in
+//! the DHT. The value is a libp2p signed envelope (RFC 0002) wrapping the
+//! addresses, a sequence number and an expiry, signed with the peer's
+//! identity key. Lookups, and DHT nodes asked to store a record, reject
+//! anything that isn't signed by the peer the key names, has expired, or is
+//! older than the record they already hold.
+
+use libp2p::{core::SignedEnvelope, identity::Keypair, kad::RecordKey, Multiaddr, PeerId};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// Domain separation string of the envelope signature
+pub const PEER_RECORD_DOMAIN: &str = "mepassa-peer-record";
+
+/// Envelope payload type
+pub const PEER_RECORD_PAYLOAD_TYPE: &[u8] = b"/mepassa/peer-record/1";
+
+/// How long a published record stays valid
+pub const PEER_RECORD_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Republish this long before our record expires
+pub const REPUBLISH_MARGIN: Duration = Duration::from_secs(15 * 60);
+
+/// Addresses kept per record
+pub const MAX_RECORD_ADDRESSES: usize = 16;
+
+const RECORD_KEY_PREFIX: &str = "mepassa:addr:";
+
+/// Why a record was rejected
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum RecordError {
+    #[error("Malformed record: {0}")]
+    Malformed(String),
+
+    #[error("Invalid record signature: {0}")]
+    InvalidSignature(String),
+
+    #[error("Record signed by {0}, not the peer it names")]
+    WrongSigner(PeerId),
+
+    #[error("Record expired")]
+    Expired,
+
+    #[error("Record has no addresses")]
+    NoAddresses,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct AddressRecordPayload {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    addrs: Vec<Vec<u8>>,
+    #[prost(uint64, tag = "2")]
+    seq: u64,
+    /// Unix seconds
+    #[prost(uint64, tag = "3")]
+    expires_at: u64,
+}
+
+/// Where a peer can be reached, as signed by that peer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressRecord {
+    pub peer_id: PeerId,
+    /// Direct addresses first, relay circuits last
+    pub addresses: Vec<Multiaddr>,
+    /// Higher sequence numbers replace lower ones
+    pub seq: u64,
+    /// Unix seconds
+    pub expires_at: u64,
+}
+
+impl AddressRecord {
+    /// Create a record valid for `ttl` from `now`
+    ///
+    /// Circuit addresses are moved after the direct ones and the list is
+    /// capped at `MAX_RECORD_ADDRESSES`.
+    pub fn new(
+        peer_id: PeerId,
+        addresses: &[Multiaddr],
+        seq: u64,
+        now: SystemTime,
+        ttl: Duration,
+    ) -> Self {
+        let (mut addresses, circuits): (Vec<_>, Vec<_>) =
+            addresses.iter().cloned().partition(|addr| !is_circuit(addr));
+        addresses.extend(circuits);
+        addresses.truncate(MAX_RECORD_ADDRESSES);
+
+        Self {
+            peer_id,
+            addresses,
+            seq,
+            expires_at: unix_secs(now) + ttl.as_secs(),
+        }
+    }
+
+    /// DHT key of a peer's address record
+    pub fn key(peer_id: &PeerId) -> RecordKey {
+        RecordKey::new(&format!("{}{}", RECORD_KEY_PREFIX, peer_id))
+    }
+
+    /// Peer named by an address record key, if `key` is one
+    pub fn peer_of_key(key: &RecordKey) -> Option<PeerId> {
+        std::str::from_utf8(key.as_ref())
+            .ok()?
+            .strip_prefix(RECORD_KEY_PREFIX)?
+            .parse()
+            .ok()
+    }
+
+    /// Whether the record is no longer valid at `now`
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        unix_secs(now) >= self.expires_at
+    }
+
+    /// Time left before expiry (zero once expired)
+    pub fn remaining(&self, now: SystemTime) -> Duration {
+        Duration::from_secs(self.expires_at.saturating_sub(unix_secs(now)))
+    }
+
+    /// Sign the record into envelope bytes (the DHT record value)
+    pub fn sign(&self, keypair: &Keypair) -> Result<Vec<u8>, RecordError> {
+        if PeerId::from(keypair.public()) != self.peer_id {
+            return Err(RecordError::WrongSigner(PeerId::from(keypair.public())));
+        }
+
+        let payload = AddressRecordPayload {
+            addrs: self.addresses.iter().map(|addr| addr.to_vec()).collect(),
+            seq: self.seq,
+            expires_at: self.expires_at,
+        };
+        let envelope = SignedEnvelope::new(
+            keypair,
+            PEER_RECORD_DOMAIN.to_string(),
+            PEER_RECORD_PAYLOAD_TYPE.to_vec(),
+            prost::Message::encode_to_vec(&payload),
+        )
+        .map_err(|e| RecordError::InvalidSignature(e.to_string()))?;
+        Ok(envelope.into_protobuf_encoding())
+    }
+
+    /// Decode a record of `peer_id`, checking signature, signer and expiry
+    pub fn verify(peer_id: &PeerId, bytes: &[u8], now: SystemTime) -> Result<Self, RecordError> {
+        let envelope = SignedEnvelope::from_protobuf_encoding(bytes)
+            .map_err(|e| RecordError::Malformed(e.to_string()))?;
+        let (payload, key) = envelope
+            .payload_and_signing_key(PEER_RECORD_DOMAIN.to_string(), PEER_RECORD_PAYLOAD_TYPE)
+            .map_err(|e| RecordError::InvalidSignature(e.to_string()))?;
+
+        let signer = key.to_peer_id();
+        if signer != *peer_id {
+            return Err(RecordError::WrongSigner(signer));
+        }
+
+        let payload: AddressRecordPayload = prost::Message::decode(payload)
+            .map_err(|e| RecordError::Malformed(e.to_string()))?;
+        let addresses = payload
+            .addrs
+            .into_iter()
+            .take(MAX_RECORD_ADDRESSES)
+            .map(Multiaddr::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| RecordError::Malformed(e.to_string()))?;
+        if addresses.is_empty() {
+            return Err(RecordError::NoAddresses);
+        }
+
+        let record = Self {
+            peer_id: *peer_id,
+            addresses,
+            seq: payload.seq,
+            expires_at: payload.expires_at,
+        };
+        if record.is_expired(now) {
+            return Err(RecordError::Expired);
+        }
+        Ok(record)
+    }
+}
+
+fn is_circuit(addr: &Multiaddr) -> bool {
+    addr.iter()
+        .any(|p| p == libp2p::multiaddr::Protocol::P2pCircuit)
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs() -> Vec<Multiaddr> {
+        vec![
+            "/ip4/1.2.3.4/tcp/4001/p2p/12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN/p2p-circuit"
+                .parse()
+                .unwrap(),
+            "/ip4/5.6.7.8/tcp/4001".parse().unwrap(),
+        ]
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let keypair = Keypair::generate_ed25519();
+        let peer_id = PeerId::from(keypair.public());
+        let now = SystemTime::now();
+
+        let record = AddressRecord::new(peer_id, &addrs(), 7, now, PEER_RECORD_TTL);
+        // Direct addresses first
+        assert_eq!(record.addresses[0], addrs()[1]);
+
+        let bytes = record.sign(&keypair).unwrap();
+        assert_eq!(AddressRecord::verify(&peer_id, &bytes, now).unwrap(), record);
+    }
+
+    #[test]
+    fn test_rejects_record_signed_by_another_peer() {
+        let victim = PeerId::from(Keypair::generate_ed25519().public());
+        let attacker = Keypair::generate_ed25519();
+        let now = SystemTime::now();
+
+        // An attacker can only sign records naming itself...
+        let forged = AddressRecord::new(victim, &addrs(), 1, now, PEER_RECORD_TTL);
+        assert!(forged.sign(&attacker).is_err());
+
+        // ...which don't verify under someone else's key
+        let own = AddressRecord::new(PeerId::from(attacker.public()), &addrs(), 1, now, PEER_RECORD_TTL);
+        let bytes = own.sign(&attacker).unwrap();
+        assert!(matches!(
+            AddressRecord::verify(&victim, &bytes, now),
+            Err(RecordError::WrongSigner(_))
+        ));
+
+        // Tampering breaks the signature
+        let mut tampered = bytes.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(AddressRecord::verify(&PeerId::from(attacker.public()), &tampered, now).is_err());
+
+        // A raw multiaddr (the old format) is not a record
+        assert!(AddressRecord::verify(&victim, b"/ip4/6.6.6.6/tcp/1", now).is_err());
+    }
+
+    #[test]
+    fn test_expiry() {
+        let keypair = Keypair::generate_ed25519();
+        let peer_id = PeerId::from(keypair.public());
+        let now = SystemTime::now();
+
+        let record = AddressRecord::new(peer_id, &addrs(), 1, now, Duration::from_secs(60));
+        assert_eq!(record.remaining(now), Duration::from_secs(60));
+        let bytes = record.sign(&keypair).unwrap();
+
+        let later = now + Duration::from_secs(61);
+        assert!(record.is_expired(later));
+        assert_eq!(AddressRecord::verify(&peer_id, &bytes, later), Err(RecordError::Expired));
+    }
+
+    #[test]
+    fn test_record_key() {
+        let peer_id = PeerId::random();
+        let key = AddressRecord::key(&peer_id);
+        assert_eq!(AddressRecord::peer_of_key(&key), Some(peer_id));
+        assert_eq!(AddressRecord::peer_of_key(&RecordKey::new(&"other")), None);
+    }
+}
@@ -1,9 +1,26 @@
 //! Relay client utilities
 //!
 //! Provides helpers for managing relay reservations and relay-based connections.
+//!
+//! The status is driven by the relay client's events. libp2p renews an
+//! accepted reservation on the same connection by itself; if that stalls or
+//! the connection to the relay is lost, `needs_reservation` asks for a new
+//! one before the current reservation expires.
+
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+use std::time::{Duration, Instant};
+
+/// Reservation lifetime granted by circuit relay v2 servers (libp2p default)
+pub const RESERVATION_TTL: Duration = Duration::from_secs(3600);
 
-use libp2p::{Multiaddr, PeerId};
-use std::time::Instant;
+/// Request a new reservation this long before the current one expires
+pub const RESERVATION_RENEWAL_MARGIN: Duration = Duration::from_secs(300);
+
+/// Give up on an unanswered reservation request after this long
+pub const RESERVATION_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Wait this long after a failed reservation before trying again
+pub const RESERVATION_RETRY_INTERVAL: Duration = Duration::from_secs(30);
 
 /// Relay manager handles relay reservations and relay-based connections
 #[derive(Debug, Clone)]
@@ -14,6 +31,8 @@ pub struct RelayManager {
     pub relay_addr: Option<Multiaddr>,
     /// Reservation status
     pub reservation_status: ReservationStatus,
+    /// When a reservation was last requested
+    last_request_at: Option<Instant>,
 }
 
 /// Reservation status
@@ -36,6 +55,50 @@ impl RelayManager {
             bootstrap_relay_peer,
             relay_addr,
             reservation_status: ReservationStatus::NotReserved,
+            last_request_at: None,
+        }
+    }
+
+    /// Configure the relay (drops any reservation on the previous one)
+    pub fn set_relay(&mut self, relay_peer: PeerId, relay_addr: Multiaddr) {
+        self.bootstrap_relay_peer = Some(relay_peer);
+        self.relay_addr = Some(relay_addr);
+        self.reservation_status = ReservationStatus::NotReserved;
+        self.last_request_at = None;
+    }
+
+    /// Address to listen on to obtain a reservation
+    ///
+    /// Format: /ip4/relay-ip/tcp/relay-port/p2p/relay-peer-id/p2p-circuit
+    pub fn reservation_addr(&self) -> Option<Multiaddr> {
+        let (relay_addr, relay_peer) = (self.relay_addr.as_ref()?, self.bootstrap_relay_peer?);
+        let mut addr = relay_addr.clone();
+        if !matches!(addr.iter().last(), Some(Protocol::P2p(_))) {
+            addr.push(Protocol::P2p(relay_peer));
+        }
+        Some(addr.with(Protocol::P2pCircuit))
+    }
+
+    /// Whether a (new) reservation should be requested now
+    ///
+    /// Only once one was asked for: when the request went unanswered, failed
+    /// a while ago, or the reservation is about to expire.
+    pub fn needs_reservation(&self) -> bool {
+        if self.bootstrap_relay_peer.is_none() || self.relay_addr.is_none() {
+            return false;
+        }
+
+        match &self.reservation_status {
+            ReservationStatus::NotReserved => false,
+            ReservationStatus::Pending { requested_at } => {
+                requested_at.elapsed() > RESERVATION_REQUEST_TIMEOUT
+            }
+            ReservationStatus::Reserved { expires_at } => {
+                Instant::now() + RESERVATION_RENEWAL_MARGIN >= *expires_at
+            }
+            ReservationStatus::Failed { .. } => self
+                .last_request_at
+                .is_none_or(|at| at.elapsed() > RESERVATION_RETRY_INTERVAL),
         }
     }
 
@@ -49,9 +112,9 @@ impl RelayManager {
 
     /// Mark reservation as pending
     pub fn mark_reservation_pending(&mut self) {
-        self.reservation_status = ReservationStatus::Pending {
-            requested_at: Instant::now(),
-        };
+        let now = Instant::now();
+        self.reservation_status = ReservationStatus::Pending { requested_at: now };
+        self.last_request_at = Some(now);
     }
 
     /// Mark reservation as reserved
@@ -65,6 +128,11 @@ impl RelayManager {
         self.reservation_status = ReservationStatus::Failed { error };
     }
 
+    /// Drop the reservation (e.g. when no longer needed)
+    pub fn release_reservation(&mut self) {
+        self.reservation_status = ReservationStatus::NotReserved;
+    }
+
     /// Get relay address for listening
     pub fn listen_addr(&self) -> Option<Multiaddr> {
         if self.has_reservation() {
@@ -78,20 +146,9 @@ impl RelayManager {
     ///
     /// Format: /ip4/relay-ip/tcp/relay-port/p2p/relay-peer-id/p2p-circuit/p2p/target-peer-id
     pub fn circuit_addr(&self, target_peer_id: &PeerId) -> Option<Multiaddr> {
-        if let (Some(relay_addr), Some(relay_peer)) =
-            (&self.relay_addr, &self.bootstrap_relay_peer)
-        {
-            // Build relay circuit address
-            let circuit = relay_addr
-                .clone()
-                .with(libp2p::multiaddr::Protocol::P2p(*relay_peer))
-                .with(libp2p::multiaddr::Protocol::P2pCircuit)
-                .with(libp2p::multiaddr::Protocol::P2p(*target_peer_id));
-
-            Some(circuit)
-        } else {
-            None
-        }
+        // Build relay circuit address
+        self.reservation_addr()
+            .map(|addr| addr.with(Protocol::P2p(*target_peer_id)))
     }
 
     /// Check if reservation has expired
@@ -175,6 +232,54 @@ mod tests {
         assert!(circuit_str.contains("p2p-circuit"));
     }
 
+    #[test]
+    fn test_reservation_addr() {
+        let relay_peer = PeerId::random();
+        let relay_addr: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+        let manager = RelayManager::new(Some(relay_peer), Some(relay_addr.clone()));
+
+        let expected = relay_addr
+            .clone()
+            .with(Protocol::P2p(relay_peer))
+            .with(Protocol::P2pCircuit);
+        assert_eq!(manager.reservation_addr(), Some(expected.clone()));
+
+        // A relay address that already names the relay is not extended twice
+        let manager = RelayManager::new(
+            Some(relay_peer),
+            Some(relay_addr.with(Protocol::P2p(relay_peer))),
+        );
+        assert_eq!(manager.reservation_addr(), Some(expected));
+
+        assert_eq!(RelayManager::new(None, None).reservation_addr(), None);
+    }
+
+    #[test]
+    fn test_needs_reservation() {
+        let relay_addr: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+        let mut manager = RelayManager::new(None, None);
+
+        // Nothing to renew without a relay, or before one was requested
+        assert!(!manager.needs_reservation());
+        manager.set_relay(PeerId::random(), relay_addr);
+        assert!(!manager.needs_reservation());
+
+        manager.mark_reservation_pending();
+        assert!(!manager.needs_reservation());
+
+        // Renewed ahead of expiry, not only once expired
+        manager.mark_reservation_reserved(RESERVATION_TTL.as_secs());
+        assert!(!manager.needs_reservation());
+        manager.mark_reservation_reserved(RESERVATION_RENEWAL_MARGIN.as_secs() - 1);
+        assert!(!manager.is_reservation_expired());
+        assert!(manager.needs_reservation());
+
+        // A fresh failure is retried later, not immediately
+        manager.mark_reservation_pending();
+        manager.mark_reservation_failed("denied".to_string());
+        assert!(!manager.needs_reservation());
+    }
+
     #[test]
     fn test_reservation_expiry() {
         let mut manager = RelayManager::new(None, None);
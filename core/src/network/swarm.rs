@@ -3,43 +3,86 @@
 //! Manages the libp2p Swarm for P2P networking.
 
 use libp2p::{
+    dcutr,
+    gossipsub::{self, IdentTopic, TopicHash},
     identity::Keypair,
-    kad::{self, Quorum, QueryId, Record, RecordKey},
-    swarm::{Config as SwarmConfig, Swarm, SwarmEvent},
+    kad::{self, store::RecordStore, Quorum, QueryId, Record},
+    multiaddr::Protocol,
+    relay, request_response,
+    core::transport::ListenerId,
+    swarm::{dial_opts::DialOpts, Config as SwarmConfig, DialError, Swarm, SwarmEvent},
     Multiaddr, PeerId,
 };
 use futures::stream::StreamExt;
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 use tokio::select;
-use tokio::sync::oneshot;
+use tokio::sync::{mpsc, oneshot};
 use uuid::Uuid;
 use chrono::Utc;
 
 use super::{
+    autonat::{AutoNatConfig, DialRequest, AUTONAT_PROTOCOL},
     behaviour::MePassaBehaviour,
-    connection::{ConnectionManager, ConnectionType},
+    connection::{ConnectionManager, ConnectionQuality, ConnectionType},
+    framing::{FrameError, FrameLimits, MalformedFrameTracker, MAX_MALFORMED_FRAMES},
     message_handler::MessageHandler,
-    relay::RelayManager,
-    nat_detection::NatDetector,
+    relay::{RelayManager, ReservationStatus, RESERVATION_TTL},
+    nat_detection::{NatDetector, ProbeResult, Reachability},
+    peer_record::{AddressRecord, PEER_RECORD_TTL, REPUBLISH_MARGIN},
     retry::RetryPolicy,
     transport::build_transport,
 };
 use crate::{
+    group::GroupManager,
     protocol::{pb::message::Payload, Message, MessageType},
     utils::error::{MePassaError, Result},
 };
 
+/// Network-level events reported to the application
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetworkEvent {
+    /// AutoNAT settled on a new reachability status
+    ReachabilityChanged { reachability: Reachability },
+    /// First connection to a peer established
+    PeerConnected { peer_id: PeerId },
+    /// Last connection to a peer closed
+    PeerDisconnected { peer_id: PeerId },
+    /// A peer was announced on the local network (mDNS)
+    PeerDiscovered {
+        peer_id: PeerId,
+        addresses: Vec<Multiaddr>,
+    },
+    /// Connected to the first peer
+    Online,
+    /// Disconnected from the last peer
+    Offline,
+}
+
 /// P2P Network Manager
 pub struct NetworkManager {
     swarm: Swarm<MePassaBehaviour>,
     local_peer_id: PeerId,
+    /// Signs our DHT address records
+    keypair: Keypair,
     connection_manager: ConnectionManager,
     relay_manager: RelayManager,
+    /// Circuit listener holding our relay reservation
+    relay_listener: Option<ListenerId>,
     message_handler: Option<std::sync::Arc<MessageHandler>>,
-    pending_kad_get: HashMap<QueryId, oneshot::Sender<Option<Multiaddr>>>,
-    last_published_addr: Option<Multiaddr>,
+    group_manager: Option<Arc<GroupManager>>,
+    pending_kad_get: HashMap<QueryId, (PeerId, oneshot::Sender<Vec<Multiaddr>>)>,
+    /// Addresses we advertise in our DHT record
+    advertised_addrs: Vec<Multiaddr>,
+    /// Our last published DHT record
+    published_record: Option<AddressRecord>,
     nat_detector: NatDetector,
     prefer_relay: bool,
+    malformed_frames: MalformedFrameTracker,
+    event_tx: Option<mpsc::UnboundedSender<NetworkEvent>>,
 }
 
 impl NetworkManager {
@@ -53,14 +96,30 @@ impl NetworkManager {
         keypair: Keypair,
         bootstrap_relay_peer: Option<PeerId>,
         relay_addr: Option<Multiaddr>,
+    ) -> Result<Self> {
+        Self::with_config(keypair, bootstrap_relay_peer, relay_addr, FrameLimits::default())
+    }
+
+    /// Create a new network manager with custom codec frame limits
+    pub fn with_frame_limits(keypair: Keypair, frame_limits: FrameLimits) -> Result<Self> {
+        Self::with_config(keypair, None, None, frame_limits)
+    }
+
+    fn with_config(
+        keypair: Keypair,
+        bootstrap_relay_peer: Option<PeerId>,
+        relay_addr: Option<Multiaddr>,
+        frame_limits: FrameLimits,
     ) -> Result<Self> {
         let local_peer_id = PeerId::from(keypair.public());
 
-        // Build transport
-        let transport = build_transport(&keypair)?;
+        // Build transport (the relay client has a transport and a behaviour half)
+        let (relay_transport, relay_client) = relay::client::new(local_peer_id);
+        let transport = build_transport(&keypair, relay_transport)?;
 
         // Create behaviour
-        let behaviour = MePassaBehaviour::new(local_peer_id, &keypair)?;
+        let behaviour =
+            MePassaBehaviour::with_frame_limits(local_peer_id, &keypair, relay_client, frame_limits)?;
 
         // Create swarm
         let swarm = Swarm::new(
@@ -80,13 +139,19 @@ impl NetworkManager {
         Ok(Self {
             swarm,
             local_peer_id,
+            keypair,
             connection_manager,
             relay_manager,
+            relay_listener: None,
             message_handler: None,
+            group_manager: None,
             pending_kad_get: HashMap::new(),
-            last_published_addr: None,
+            advertised_addrs: Vec::new(),
+            published_record: None,
             nat_detector: NatDetector::new(),
             prefer_relay: false,
+            malformed_frames: MalformedFrameTracker::default(),
+            event_tx: None,
         })
     }
 
@@ -95,11 +160,56 @@ impl NetworkManager {
         &self.local_peer_id
     }
 
+    /// Set the channel network events are reported on
+    pub fn set_event_sender(&mut self, event_tx: mpsc::UnboundedSender<NetworkEvent>) {
+        self.event_tx = Some(event_tx);
+    }
+
     /// Set message handler for processing incoming messages
     pub fn set_message_handler(&mut self, handler: std::sync::Arc<MessageHandler>) {
         self.message_handler = Some(handler);
     }
 
+    /// Message handler processing incoming messages, if configured
+    pub fn message_handler(&self) -> Option<std::sync::Arc<MessageHandler>> {
+        self.message_handler.clone()
+    }
+
+    /// Set group manager for processing incoming group messages
+    pub fn set_group_manager(&mut self, group_manager: Arc<GroupManager>) {
+        self.group_manager = Some(group_manager);
+    }
+
+    /// Subscribe to a GossipSub topic
+    pub fn subscribe_topic(&mut self, topic: &TopicHash) -> Result<()> {
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .subscribe(&IdentTopic::new(topic.as_str()))
+            .map_err(|e| MePassaError::Network(format!("Failed to subscribe to {}: {}", topic, e)))?;
+        Ok(())
+    }
+
+    /// Unsubscribe from a GossipSub topic
+    pub fn unsubscribe_topic(&mut self, topic: &TopicHash) -> Result<()> {
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .unsubscribe(&IdentTopic::new(topic.as_str()))
+            .map_err(|e| MePassaError::Network(format!("Failed to unsubscribe from {}: {}", topic, e)))?;
+        Ok(())
+    }
+
+    /// Publish data on a GossipSub topic
+    pub fn publish(&mut self, topic: &TopicHash, data: Vec<u8>) -> Result<()> {
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .publish(topic.clone(), data)
+            .map_err(|e| MePassaError::Network(format!("Failed to publish to {}: {}", topic, e)))?;
+        Ok(())
+    }
+
     /// Start listening on a multiaddr
     pub fn listen_on(&mut self, addr: Multiaddr) -> Result<()> {
         self.swarm
@@ -119,8 +229,29 @@ impl NetworkManager {
         self.swarm.is_connected(peer_id)
     }
 
+    /// Check if a peer is currently announced on the local network (mDNS)
+    pub fn is_lan_peer(&self, peer_id: &PeerId) -> bool {
+        self.swarm
+            .behaviour()
+            .mdns
+            .discovered_nodes()
+            .any(|peer| peer == peer_id)
+    }
+
     /// Dial a peer with automatic relay fallback
     pub fn dial(&mut self, peer_id: PeerId, addr: Multiaddr) -> Result<()> {
+        tracing::debug!("📞 Dial target for {}: {}", peer_id, addr);
+        self.dial_with_fallback(peer_id, DialOpts::from(addr))
+    }
+
+    /// Dial a peer at any of several addresses (e.g. from its DHT record)
+    pub fn dial_addresses(&mut self, peer_id: PeerId, addrs: Vec<Multiaddr>) -> Result<()> {
+        tracing::debug!("📞 Dial targets for {}: {:?}", peer_id, addrs);
+        let opts = DialOpts::peer_id(peer_id).addresses(addrs).build();
+        self.dial_with_fallback(peer_id, opts)
+    }
+
+    fn dial_with_fallback(&mut self, peer_id: PeerId, opts: DialOpts) -> Result<()> {
         if self.prefer_relay {
             tracing::info!("🔁 NAT suggests relay-first, attempting relay to {}", peer_id);
             if let Ok(()) = self.dial_via_relay(peer_id) {
@@ -135,12 +266,14 @@ impl NetworkManager {
         }
 
         // Try direct connection first
-        tracing::debug!("📞 Attempting direct connection to {} at {}", peer_id, addr);
-        match self.swarm.dial(addr.clone()) {
+        tracing::debug!("📞 Attempting direct connection to {}", peer_id);
+        match self.swarm.dial(opts) {
             Ok(_) => {
                 // Connection initiated, will track result in events
                 Ok(())
             }
+            // Already connected or being dialed
+            Err(DialError::DialPeerConditionFalse(_)) => Ok(()),
             Err(e) => {
                 tracing::warn!("⚠️ Direct dial failed to {}: {}", peer_id, e);
                 self.connection_manager.record_failure(peer_id);
@@ -179,29 +312,84 @@ impl NetworkManager {
             .add_address(&peer_id, addr);
     }
 
-    /// Publish our reachable address in the DHT
+    /// Advertise an address in our DHT record (republished if it is new)
     pub fn publish_own_address(&mut self, addr: Multiaddr) {
-        if self.last_published_addr.as_ref() == Some(&addr) {
+        if self.advertised_addrs.contains(&addr) {
             return;
         }
+        self.advertised_addrs.push(addr);
+        self.publish_address_record();
+    }
 
-        let key = Self::addr_record_key(&self.local_peer_id);
-        let record = Record {
-            key,
-            value: addr.to_string().into_bytes(),
-            publisher: Some(self.local_peer_id),
-            expires: None,
+    /// Stop advertising an address (republished if it was advertised)
+    pub fn withdraw_own_address(&mut self, addr: &Multiaddr) {
+        let before = self.advertised_addrs.len();
+        self.advertised_addrs.retain(|a| a != addr);
+        if self.advertised_addrs.len() != before && !self.advertised_addrs.is_empty() {
+            self.publish_address_record();
+        }
+    }
+
+    /// Addresses advertised in our DHT record
+    pub fn advertised_addresses(&self) -> &[Multiaddr] {
+        &self.advertised_addrs
+    }
+
+    /// Our last published DHT record
+    pub fn published_address_record(&self) -> Option<&AddressRecord> {
+        self.published_record.as_ref()
+    }
+
+    /// Sign and publish a fresh record of our advertised addresses
+    ///
+    /// The sequence number is the publication time in milliseconds, so
+    /// records published after a restart still replace older ones.
+    pub fn publish_address_record(&mut self) {
+        if self.advertised_addrs.is_empty() {
+            return;
+        }
+
+        let now = SystemTime::now();
+        let now_millis = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        let seq = match &self.published_record {
+            Some(previous) => now_millis.max(previous.seq + 1),
+            None => now_millis,
+        };
+        let record = AddressRecord::new(
+            self.local_peer_id,
+            &self.advertised_addrs,
+            seq,
+            now,
+            PEER_RECORD_TTL,
+        );
+        let value = match record.sign(&self.keypair) {
+            Ok(value) => value,
+            Err(e) => {
+                tracing::warn!("⚠️ Failed to sign address record: {}", e);
+                return;
+            }
         };
 
+        let kad_record = Record {
+            key: AddressRecord::key(&self.local_peer_id),
+            value,
+            publisher: Some(self.local_peer_id),
+            expires: Some(Instant::now() + record.remaining(now)),
+        };
         match self
             .swarm
             .behaviour_mut()
             .kademlia
-            .put_record(record, Quorum::One)
+            .put_record(kad_record, Quorum::One)
         {
             Ok(query_id) => {
-                tracing::info!("📌 Published address in DHT (query_id: {:?})", query_id);
-                self.last_published_addr = Some(addr);
+                tracing::info!(
+                    "📌 Published {} addresses in DHT (seq {}, query_id: {:?})",
+                    record.addresses.len(),
+                    record.seq,
+                    query_id
+                );
+                self.published_record = Some(record);
             }
             Err(e) => {
                 tracing::warn!("⚠️ Failed to publish address in DHT: {:?}", e);
@@ -209,18 +397,91 @@ impl NetworkManager {
         }
     }
 
-    /// Resolve a peer address via DHT
-    pub fn resolve_peer_address(&mut self, peer_id: PeerId) -> oneshot::Receiver<Option<Multiaddr>> {
-        let key = Self::addr_record_key(&peer_id);
+    /// Republish our record before it expires
+    pub fn maintain_address_record(&mut self) {
+        let due = self
+            .published_record
+            .as_ref()
+            .is_some_and(|record| record.remaining(SystemTime::now()) <= REPUBLISH_MARGIN);
+        if due {
+            tracing::info!("🔄 Republishing address record before expiry");
+            self.publish_address_record();
+        }
+    }
+
+    /// Resolve a peer's addresses via its signed DHT record
+    ///
+    /// Records that fail verification are skipped; the lookup yields an empty
+    /// list if no valid record is found.
+    pub fn resolve_peer_addresses(&mut self, peer_id: PeerId) -> oneshot::Receiver<Vec<Multiaddr>> {
+        let key = AddressRecord::key(&peer_id);
         let query_id = self.swarm.behaviour_mut().kademlia.get_record(key);
         let (tx, rx) = oneshot::channel();
-        self.pending_kad_get.insert(query_id, tx);
+        self.pending_kad_get.insert(query_id, (peer_id, tx));
         rx
     }
 
-    fn addr_record_key(peer_id: &PeerId) -> RecordKey {
-        let key = format!("mepassa:addr:{}", peer_id);
-        RecordKey::new(&key)
+    /// Handle a step of an address record lookup
+    fn handle_record_lookup(&mut self, id: QueryId, result: kad::GetRecordResult, last: bool) {
+        let Some((peer_id, tx)) = self.pending_kad_get.remove(&id) else {
+            return;
+        };
+
+        if let Ok(kad::GetRecordOk::FoundRecord(found)) = result {
+            match AddressRecord::verify(&peer_id, &found.record.value, SystemTime::now()) {
+                Ok(record) => {
+                    let _ = tx.send(record.addresses);
+                    if let Some(mut query) = self.swarm.behaviour_mut().kademlia.query_mut(&id) {
+                        query.finish();
+                    }
+                    return;
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "Ignoring address record of {} from {:?}: {}",
+                        peer_id,
+                        found.peer,
+                        e
+                    );
+                }
+            }
+        }
+
+        if last {
+            let _ = tx.send(Vec::new());
+        } else {
+            self.pending_kad_get.insert(id, (peer_id, tx));
+        }
+    }
+
+    /// Store a record another peer asked us to hold, if it checks out
+    ///
+    /// Address records must verify and replace an older one; other records
+    /// are stored as they come.
+    fn store_inbound_record(&mut self, source: PeerId, record: Record) {
+        let store = self.swarm.behaviour_mut().kademlia.store_mut();
+        if let Some(peer_id) = AddressRecord::peer_of_key(&record.key) {
+            let now = SystemTime::now();
+            let incoming = match AddressRecord::verify(&peer_id, &record.value, now) {
+                Ok(incoming) => incoming,
+                Err(e) => {
+                    tracing::warn!("Rejected address record of {} from {}: {}", peer_id, source, e);
+                    return;
+                }
+            };
+            let stale = store.get(&record.key).is_some_and(|existing| {
+                AddressRecord::verify(&peer_id, &existing.value, now)
+                    .is_ok_and(|existing| existing.seq >= incoming.seq)
+            });
+            if stale {
+                tracing::debug!("Ignoring stale address record of {} from {}", peer_id, source);
+                return;
+            }
+        }
+
+        if let Err(e) = store.put(record) {
+            tracing::warn!("Record from {} not stored: {:?}", source, e);
+        }
     }
 
     fn is_routable_addr(addr: &Multiaddr) -> bool {
@@ -255,32 +516,185 @@ impl NetworkManager {
         self.connection_manager.get_state(peer_id)
     }
 
+    /// Get connection state, type and ping RTT for a peer
+    pub fn connection_quality(&self, peer_id: &PeerId) -> ConnectionQuality {
+        self.connection_manager.quality(peer_id)
+    }
+
     /// Check if relay is available
     pub fn has_relay(&self) -> bool {
         self.relay_manager.has_reservation()
     }
 
+    /// Configure the relay server used for reservations and circuits
+    pub fn set_relay(&mut self, relay_peer: PeerId, relay_addr: Multiaddr) {
+        if let Some(listener) = self.relay_listener.take() {
+            self.swarm.remove_listener(listener);
+        }
+        self.relay_manager.set_relay(relay_peer, relay_addr);
+    }
+
+    /// Current relay reservation status
+    pub fn relay_reservation_status(&self) -> &ReservationStatus {
+        &self.relay_manager.reservation_status
+    }
+
+    /// Circuit address through our relay at which `peer_id` can be dialed
+    pub fn relay_circuit_addr(&self, peer_id: &PeerId) -> Option<Multiaddr> {
+        self.relay_manager.circuit_addr(peer_id)
+    }
+
     /// Attempt to reserve relay slot
+    ///
+    /// Listens on the relay's `/p2p-circuit` address; the relay client dials
+    /// the relay and requests the reservation. The outcome arrives as relay
+    /// client and listener events.
     pub fn reserve_relay_slot(&mut self) -> Result<()> {
-        if let Some(relay_peer) = self.relay_manager.bootstrap_relay_peer {
-            if let Some(relay_addr) = &self.relay_manager.relay_addr {
-                tracing::info!("🔗 Requesting relay reservation from {}", relay_peer);
+        let relay_peer = self
+            .relay_manager
+            .bootstrap_relay_peer
+            .ok_or_else(|| MePassaError::Network("No relay peer configured".to_string()))?;
+        let reservation_addr = self
+            .relay_manager
+            .reservation_addr()
+            .ok_or_else(|| MePassaError::Network("No relay address configured".to_string()))?;
+
+        tracing::info!("🔗 Requesting relay reservation from {}", relay_peer);
+        if let Some(relay_addr) = self.relay_manager.relay_addr.clone() {
+            self.add_peer_to_dht(relay_peer, relay_addr);
+        }
+
+        // A new listener replaces the old reservation
+        if let Some(listener) = self.relay_listener.take() {
+            self.swarm.remove_listener(listener);
+        }
+        match self.swarm.listen_on(reservation_addr) {
+            Ok(listener) => {
+                self.relay_listener = Some(listener);
+                self.relay_manager.mark_reservation_pending();
+                Ok(())
+            }
+            Err(e) => {
+                self.relay_manager.mark_reservation_failed(e.to_string());
+                Err(MePassaError::Network(format!("Failed to request relay reservation: {}", e)))
+            }
+        }
+    }
 
-                // Connect to relay first if not connected
-                self.add_peer_to_dht(relay_peer, relay_addr.clone());
+    /// Request a new reservation when the current one is about to expire,
+    /// was lost, or its request went unanswered
+    pub fn maintain_relay_reservation(&mut self) {
+        if self.relay_manager.needs_reservation() {
+            tracing::info!("🔄 Renewing relay reservation");
+            if let Err(e) = self.reserve_relay_slot() {
+                tracing::warn!("⚠️ {}", e);
+            }
+        }
+    }
+
+    /// Replace the AutoNAT settings (call before connecting to peers)
+    pub fn set_autonat_config(&mut self, config: AutoNatConfig) {
+        self.nat_detector = NatDetector::with_config(config);
+    }
 
-                // Mark reservation as pending
-                // Note: In libp2p 0.53, relay reservation is handled at transport level
-                // We're marking it here for state tracking
-                // TODO: Integrate with actual relay transport API
-                tracing::warn!("⚠️ Relay reservation requires transport-level integration (libp2p 0.53)");
+    /// Reachability as confirmed by AutoNAT
+    pub fn reachability(&self) -> &Reachability {
+        self.nat_detector.reachability()
+    }
 
-                Ok(())
-            } else {
-                Err(MePassaError::Network("No relay address configured".to_string()))
+    /// Ask an AutoNAT server to dial us back now
+    pub fn probe_reachability(&mut self) -> Result<()> {
+        let server = self
+            .nat_detector
+            .select_server()
+            .ok_or_else(|| MePassaError::Network("No AutoNAT server connected".to_string()))?;
+        self.send_probe(server);
+        Ok(())
+    }
+
+    /// Send an AutoNAT probe when one is due
+    pub fn maintain_reachability(&mut self) {
+        if let Some(server) = self.nat_detector.next_probe_server(Instant::now()) {
+            self.send_probe(server);
+        }
+    }
+
+    fn send_probe(&mut self, server: PeerId) {
+        // Servers dial the IP they see us from, so listen addresses supply the ports
+        let mut addrs: Vec<Multiaddr> = Vec::new();
+        let candidates = self
+            .nat_detector
+            .observed_addresses()
+            .iter()
+            .chain(self.swarm.external_addresses())
+            .chain(self.swarm.listeners())
+            .filter(|addr| !addr.iter().any(|p| p == Protocol::P2pCircuit))
+            .cloned()
+            .collect::<Vec<_>>();
+        for addr in candidates {
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+
+        tracing::debug!("🧪 AutoNAT probe via {} ({} addresses)", server, addrs.len());
+        let request = DialRequest {
+            peer_id: self.local_peer_id,
+            addrs,
+        };
+        self.swarm.behaviour_mut().autonat.send_request(&server, request);
+        self.nat_detector.probe_started(server, Instant::now());
+    }
+
+    fn record_probe(&mut self, result: ProbeResult) {
+        if let Some(reachability) = self.nat_detector.record_probe(result, Instant::now()) {
+            self.apply_reachability(reachability);
+        }
+    }
+
+    /// Adapt dialing and the relay reservation to a new reachability status
+    fn apply_reachability(&mut self, reachability: Reachability) {
+        tracing::info!("🌐 Reachability changed: {:?}", reachability);
+        self.prefer_relay = self.nat_detector.should_use_relay();
+
+        match &reachability {
+            Reachability::Public(addr) => {
+                self.swarm.add_external_address(addr.clone());
+                self.publish_own_address(addr.clone());
+                // Reachable directly: no need to hold a relay slot
+                if let Some(listener) = self.relay_listener.take() {
+                    self.swarm.remove_listener(listener);
+                }
+                self.relay_manager.release_reservation();
+            }
+            Reachability::Private => {
+                // Addresses we confirmed earlier no longer reach us
+                let stale: Vec<Multiaddr> = self.swarm.external_addresses().cloned().collect();
+                for addr in stale {
+                    self.swarm.remove_external_address(&addr);
+                    self.withdraw_own_address(&addr);
+                }
+                let reserving = matches!(
+                    self.relay_manager.reservation_status,
+                    ReservationStatus::Pending { .. } | ReservationStatus::Reserved { .. }
+                );
+                if self.relay_manager.bootstrap_relay_peer.is_some() && !reserving {
+                    if let Err(e) = self.reserve_relay_slot() {
+                        tracing::warn!("⚠️ {}", e);
+                    }
+                }
+            }
+            Reachability::Unknown => {}
+        }
+
+        self.emit_event(NetworkEvent::ReachabilityChanged { reachability });
+    }
+
+    fn emit_event(&self, event: NetworkEvent) {
+        if let Some(ref tx) = self.event_tx {
+            if let Err(e) = tx.send(event) {
+                tracing::warn!("Failed to emit network event: {}", e);
             }
-        } else {
-            Err(MePassaError::Network("No relay peer configured".to_string()))
         }
     }
 
@@ -346,11 +760,18 @@ impl NetworkManager {
 
     /// Run the event loop (blocking)
     pub async fn run(&mut self) -> Result<()> {
+        let mut group_tick = tokio::time::interval(Duration::from_millis(100));
         loop {
             select! {
                 event = self.swarm.select_next_some() => {
                     self.handle_event(event).await?;
                 }
+                _ = group_tick.tick() => {
+                    self.sync_groups().await;
+                    self.maintain_relay_reservation();
+                    self.maintain_reachability();
+                    self.maintain_address_record();
+                }
             }
         }
     }
@@ -361,6 +782,11 @@ impl NetworkManager {
         use futures::future::poll_fn;
         use std::task::Poll;
 
+        self.sync_groups().await;
+        self.maintain_relay_reservation();
+        self.maintain_reachability();
+        self.maintain_address_record();
+
         let event = poll_fn(|cx| {
             match self.swarm.poll_next_unpin(cx) {
                 Poll::Ready(Some(event)) => Poll::Ready(Some(event)),
@@ -377,6 +803,65 @@ impl NetworkManager {
         }
     }
 
+    /// Send queued sender key distributions and follow the group manager's topics
+    async fn sync_groups(&mut self) {
+        let Some(group_manager) = self.group_manager.clone() else {
+            return;
+        };
+
+        let topics = group_manager.topics().await;
+        let subscribed: Vec<TopicHash> = self.swarm.behaviour().gossipsub.topics().cloned().collect();
+        for topic in topics.iter().filter(|topic| !subscribed.contains(topic)) {
+            if let Err(e) = self.subscribe_topic(topic) {
+                tracing::warn!("⚠️  {}", e);
+            }
+        }
+        let stale = subscribed
+            .into_iter()
+            .filter(|topic| topic.as_str().starts_with("/mepassa/group/") && !topics.contains(topic));
+        for topic in stale {
+            if let Err(e) = self.unsubscribe_topic(&topic) {
+                tracing::warn!("⚠️  {}", e);
+            }
+        }
+
+        let distributions = group_manager.take_pending_distributions().await;
+        if distributions.is_empty() {
+            return;
+        }
+        let Some(handler) = self.message_handler.clone() else {
+            tracing::warn!("Dropping {} sender key distributions: no message handler", distributions.len());
+            return;
+        };
+
+        for (peer_id, distribution) in distributions {
+            let target = match peer_id.parse::<PeerId>() {
+                Ok(target) => target,
+                Err(e) => {
+                    tracing::warn!("Invalid group member peer ID {}: {}", peer_id, e);
+                    continue;
+                }
+            };
+
+            match handler.build_sender_key_message(&peer_id, &distribution) {
+                Ok(message) => {
+                    tracing::info!(
+                        "🔑 Sending sender key for group {} to {}",
+                        distribution.group_id,
+                        peer_id
+                    );
+                    let _ = self.send_message(target, message);
+                }
+                Err(e) => tracing::warn!(
+                    "⚠️  Could not send sender key for group {} to {}: {}",
+                    distribution.group_id,
+                    peer_id,
+                    e
+                ),
+            }
+        }
+    }
+
     /// Handle swarm events
     async fn handle_event(&mut self, event: SwarmEvent<MePassaBehaviourEvent>) -> Result<()> {
         match event {
@@ -387,26 +872,49 @@ impl NetworkManager {
                 }
             }
             SwarmEvent::ConnectionEstablished {
-                peer_id, endpoint, ..
+                peer_id,
+                connection_id,
+                endpoint,
+                num_established,
+                ..
             } => {
                 let addr = endpoint.get_remote_address();
                 tracing::info!("✅ Connected to {} at {}", peer_id, addr);
 
-                // Determine connection type and record success
-                let connection_type = if addr.to_string().contains("p2p-circuit") {
+                // Circuit connections are relayed; hole punched ones are
+                // reclassified when DCUtR reports the upgrade
+                let connection_type = if endpoint.is_relayed() {
                     ConnectionType::Relayed
                 } else {
-                    // TODO: Detect if connection was upgraded via DCUtR
                     ConnectionType::Direct
                 };
 
                 self.connection_manager
-                    .record_success(peer_id, connection_type);
+                    .connection_established(peer_id, connection_id, connection_type);
+                if num_established.get() == 1 {
+                    if self.swarm.connected_peers().count() == 1 {
+                        self.emit_event(NetworkEvent::Online);
+                    }
+                    self.emit_event(NetworkEvent::PeerConnected { peer_id });
+                }
             }
             SwarmEvent::ConnectionClosed {
-                peer_id, cause, ..
+                peer_id,
+                connection_id,
+                cause,
+                num_established,
+                ..
             } => {
                 tracing::info!("Disconnected from {}: {:?}", peer_id, cause);
+                self.connection_manager.connection_closed(peer_id, connection_id);
+                if num_established == 0 {
+                    self.malformed_frames.forget(&peer_id);
+                    self.nat_detector.remove_server(&peer_id);
+                    self.emit_event(NetworkEvent::PeerDisconnected { peer_id });
+                    if self.swarm.connected_peers().next().is_none() {
+                        self.emit_event(NetworkEvent::Offline);
+                    }
+                }
             }
             SwarmEvent::Behaviour(event) => {
                 self.handle_behaviour_event(event).await?;
@@ -419,10 +927,40 @@ impl NetworkManager {
             }
             SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
                 tracing::warn!("⚠️ Outgoing connection error to {:?}: {:?}", peer_id, error);
+                if let Some(peer_id) = peer_id {
+                    if !self.swarm.is_connected(&peer_id) {
+                        self.connection_manager.record_failure(peer_id);
+                    }
+                }
             }
             SwarmEvent::Dialing { peer_id, .. } => {
                 tracing::info!("📞 Dialing peer: {:?}", peer_id);
             }
+            SwarmEvent::ExpiredListenAddr { address, .. } => {
+                self.withdraw_own_address(&address);
+            }
+            SwarmEvent::ListenerClosed {
+                listener_id,
+                addresses,
+                reason,
+            } if self.relay_listener == Some(listener_id) => {
+                for address in &addresses {
+                    self.withdraw_own_address(address);
+                }
+                // The reservation was denied, or the connection to the relay was lost
+                let error = match reason {
+                    Ok(()) => "relay listener closed".to_string(),
+                    Err(e) => e.to_string(),
+                };
+                tracing::warn!("⚠️ Relay reservation lost: {}", error);
+                self.relay_listener = None;
+                self.relay_manager.mark_reservation_failed(error);
+            }
+            SwarmEvent::ListenerClosed { addresses, .. } => {
+                for address in &addresses {
+                    self.withdraw_own_address(address);
+                }
+            }
             _ => {
                 tracing::trace!("Other swarm event received");
             }
@@ -431,28 +969,52 @@ impl NetworkManager {
         Ok(())
     }
 
+    /// Count a frame a peer's codec rejected; disconnect repeat offenders
+    fn check_frame_error(&mut self, peer: PeerId, error: &std::io::Error) {
+        let Some(frame_error) = FrameError::received(error) else {
+            return;
+        };
+
+        tracing::warn!("Rejected frame from {}: {}", peer, frame_error);
+        if self.malformed_frames.record(peer) {
+            tracing::warn!(
+                "Disconnecting {} after {} malformed frames",
+                peer,
+                MAX_MALFORMED_FRAMES
+            );
+            let _ = self.swarm.disconnect_peer_id(peer);
+        }
+    }
+
     /// Handle behaviour-specific events
     async fn handle_behaviour_event(&mut self, event: MePassaBehaviourEvent) -> Result<()> {
         match event {
             MePassaBehaviourEvent::Kademlia(kad_event) => {
                 match kad_event {
-                    kad::Event::OutboundQueryProgressed { id, result, .. } => {
-                        if let Some(tx) = self.pending_kad_get.remove(&id) {
-                            let addr_opt = match result {
-                                kad::QueryResult::GetRecord(Ok(kad::GetRecordOk::FoundRecord(record))) => {
-                                    let value = record.record.value;
-                                    match std::str::from_utf8(&value) {
-                                        Ok(addr_str) => addr_str.parse::<Multiaddr>().ok(),
-                                        Err(_) => None,
-                                    }
-                                }
-                                kad::QueryResult::GetRecord(Ok(kad::GetRecordOk::FinishedWithNoAdditionalRecord { .. })) => None,
-                                kad::QueryResult::GetRecord(Err(_)) => None,
-                                _ => None,
-                            };
-                            let _ = tx.send(addr_opt);
-                        } else {
-                            tracing::debug!("Kademlia event: {:?}", result);
+                    kad::Event::OutboundQueryProgressed {
+                        id,
+                        result: kad::QueryResult::GetRecord(result),
+                        step,
+                        ..
+                    } if self.pending_kad_get.contains_key(&id) => {
+                        self.handle_record_lookup(id, result, step.last);
+                    }
+                    kad::Event::InboundRequest {
+                        request: kad::InboundRequest::PutRecord {
+                            source,
+                            record: Some(record),
+                            ..
+                        },
+                    } => {
+                        self.store_inbound_record(source, record);
+                    }
+                    kad::Event::InboundRequest {
+                        request: kad::InboundRequest::AddProvider {
+                            record: Some(provider),
+                        },
+                    } => {
+                        if let Err(e) = self.swarm.behaviour_mut().kademlia.store_mut().add_provider(provider) {
+                            tracing::warn!("Provider record not stored: {:?}", e);
                         }
                     }
                     _ => {
@@ -463,9 +1025,17 @@ impl NetworkManager {
             MePassaBehaviourEvent::Mdns(mdns_event) => {
                 match mdns_event {
                     libp2p::mdns::Event::Discovered(peers) => {
+                        let mut discovered: Vec<(PeerId, Vec<Multiaddr>)> = Vec::new();
                         for (peer_id, addr) in peers {
                             tracing::info!("mDNS discovered peer: {} at {}", peer_id, addr);
-                            self.add_peer_to_dht(peer_id, addr);
+                            self.add_peer_to_dht(peer_id, addr.clone());
+                            match discovered.iter_mut().find(|(p, _)| *p == peer_id) {
+                                Some((_, addresses)) => addresses.push(addr),
+                                None => discovered.push((peer_id, vec![addr])),
+                            }
+                        }
+                        for (peer_id, addresses) in discovered {
+                            self.emit_event(NetworkEvent::PeerDiscovered { peer_id, addresses });
                         }
                     }
                     libp2p::mdns::Event::Expired(peers) => {
@@ -477,19 +1047,20 @@ impl NetworkManager {
             }
             MePassaBehaviourEvent::Identify(identify_event) => {
                 match identify_event {
-                    libp2p::identify::Event::Received { info, .. } => {
+                    libp2p::identify::Event::Received { peer_id, info, .. } => {
+                        // Peers we reach directly can confirm our reachability
+                        if info.protocols.contains(&AUTONAT_PROTOCOL)
+                            && self.connection_manager.quality(&peer_id).connection_type
+                                == Some(ConnectionType::Direct)
+                        {
+                            self.nat_detector.add_server(peer_id);
+                        }
+
                         let observed_addr = info.observed_addr;
                         tracing::info!("🧭 Observed external address: {}", observed_addr);
                         if Self::is_routable_addr(&observed_addr) {
-                            self.swarm.add_external_address(observed_addr.clone());
-                            self.nat_detector.add_observed_address(observed_addr.clone());
-                            self.publish_own_address(observed_addr);
-
-                            if self.nat_detector.should_use_relay() && !self.prefer_relay {
-                                tracing::info!("🌐 NAT suggests relay-first strategy");
-                                self.prefer_relay = true;
-                                let _ = self.reserve_relay_slot();
-                            }
+                            // A candidate until AutoNAT confirms it
+                            self.nat_detector.add_observed_address(observed_addr);
                         }
                     }
                     _ => {
@@ -499,10 +1070,33 @@ impl NetworkManager {
             }
             MePassaBehaviourEvent::Ping(ping_event) => {
                 tracing::trace!("Ping event: {:?}", ping_event);
+                if let Ok(rtt) = ping_event.result {
+                    self.connection_manager
+                        .record_rtt(&ping_event.peer, ping_event.connection, rtt);
+                }
             }
-            MePassaBehaviourEvent::Gossipsub(gossipsub_event) => {
-                tracing::debug!("GossipSub event: {:?}", gossipsub_event);
-            }
+            MePassaBehaviourEvent::Gossipsub(gossipsub_event) => match gossipsub_event {
+                gossipsub::Event::Message {
+                    propagation_source,
+                    message,
+                    ..
+                } => {
+                    let topic = message.topic.clone();
+                    if let Some(group_manager) = self.group_manager.clone() {
+                        if let Err(e) = group_manager.handle_gossipsub_message(&topic, message).await {
+                            tracing::warn!(
+                                "Rejected group message on {} (via {}): {}",
+                                topic,
+                                propagation_source,
+                                e
+                            );
+                        }
+                    }
+                }
+                other => {
+                    tracing::debug!("GossipSub event: {:?}", other);
+                }
+            },
             MePassaBehaviourEvent::RequestResponse(rr_event) => {
                 match rr_event {
                     libp2p::request_response::Event::Message { peer, message } => {
@@ -585,7 +1179,7 @@ impl NetworkManager {
                                     if let Some(crate::protocol::pb::message::Payload::Ack(ack)) = response.payload {
                                         let handler = Arc::clone(handler);
                                         tokio::spawn(async move {
-                                            if let Err(e) = handler.handle_outgoing_ack(ack).await {
+                                            if let Err(e) = handler.handle_outgoing_ack(&peer.to_string(), ack).await {
                                                 tracing::error!("❌ Failed to process ACK: {}", e);
                                             }
                                         });
@@ -609,6 +1203,9 @@ impl NetworkManager {
                             error,
                             request_id
                         );
+                        if let request_response::OutboundFailure::Io(ref e) = error {
+                            self.check_frame_error(peer, e);
+                        }
                     }
                     libp2p::request_response::Event::InboundFailure {
                         peer,
@@ -621,6 +1218,9 @@ impl NetworkManager {
                             error,
                             request_id
                         );
+                        if let request_response::InboundFailure::Io(ref e) = error {
+                            self.check_frame_error(peer, e);
+                        }
                     }
                     libp2p::request_response::Event::ResponseSent { peer, request_id } => {
                         tracing::debug!("Response sent to {} (request_id: {:?})", peer, request_id);
@@ -675,6 +1275,9 @@ impl NetworkManager {
                             error,
                             request_id
                         );
+                        if let request_response::OutboundFailure::Io(ref e) = error {
+                            self.check_frame_error(peer, e);
+                        }
                         // TODO: Notify CallManager of failure (FASE 12)
                     }
                     libp2p::request_response::Event::InboundFailure {
@@ -688,24 +1291,68 @@ impl NetworkManager {
                             error,
                             request_id
                         );
+                        if let request_response::InboundFailure::Io(ref e) = error {
+                            self.check_frame_error(peer, e);
+                        }
                     }
                     libp2p::request_response::Event::ResponseSent { peer, request_id } => {
                         tracing::debug!("📞 VoIP response sent to {} (request_id: {:?})", peer, request_id);
                     }
                 }
             }
-            MePassaBehaviourEvent::Dcutr(dcutr_event) => {
-                // DCUtR hole punching events
-                // Note: Event structure varies in libp2p 0.53, using debug for now
-                // TODO: Pattern match specific events when API is stable:
-                //   - RemoteInitiatedDirectConnectionUpgrade
-                //   - DirectConnectionUpgradeSucceeded
-                //   - DirectConnectionUpgradeFailed
-                tracing::debug!("🎯 DCUtR event: {:?}", dcutr_event);
-
-                // If this is a successful upgrade event, we'd update connection type to HolePunch
-                // self.connection_manager.record_success(peer_id, ConnectionType::HolePunch);
-            }
+            MePassaBehaviourEvent::RelayClient(relay_event) => match relay_event {
+                relay::client::Event::ReservationReqAccepted {
+                    relay_peer_id,
+                    renewal,
+                    ..
+                } => {
+                    if Some(relay_peer_id) == self.relay_manager.bootstrap_relay_peer {
+                        tracing::info!(
+                            "🌉 Relay reservation {} by {}",
+                            if renewal { "renewed" } else { "accepted" },
+                            relay_peer_id
+                        );
+                        self.relay_manager
+                            .mark_reservation_reserved(RESERVATION_TTL.as_secs());
+                    }
+                }
+                relay::client::Event::OutboundCircuitEstablished { relay_peer_id, .. } => {
+                    tracing::info!("🌉 Outbound circuit established via {}", relay_peer_id);
+                }
+                relay::client::Event::InboundCircuitEstablished { src_peer_id, .. } => {
+                    tracing::info!("🌉 Inbound circuit established from {}", src_peer_id);
+                }
+            },
+            MePassaBehaviourEvent::Dcutr(dcutr::Event {
+                remote_peer_id,
+                result,
+            }) => match result {
+                Ok(connection_id) => {
+                    tracing::info!("🎯 Hole punch to {} succeeded", remote_peer_id);
+                    self.connection_manager
+                        .hole_punch_succeeded(remote_peer_id, connection_id);
+                }
+                Err(e) => {
+                    tracing::debug!("🎯 Hole punch to {} failed: {}", remote_peer_id, e);
+                    self.connection_manager.hole_punch_failed(remote_peer_id);
+                }
+            },
+            MePassaBehaviourEvent::Autonat(autonat_event) => match autonat_event {
+                request_response::Event::Message {
+                    peer,
+                    message: request_response::Message::Response { response, .. },
+                } => {
+                    tracing::debug!("🧪 AutoNAT response from {}: {:?}", peer, response);
+                    self.record_probe(response.into());
+                }
+                request_response::Event::OutboundFailure { peer, error, .. } => {
+                    tracing::debug!("🧪 AutoNAT probe via {} failed: {}", peer, error);
+                    self.record_probe(ProbeResult::Inconclusive);
+                }
+                other => {
+                    tracing::trace!("AutoNAT event: {:?}", other);
+                }
+            },
             #[cfg(any(feature = "voip", feature = "video"))]
             MePassaBehaviourEvent::VoipSignaling(voip_event) => {
                 // VoIP signaling events (WebRTC SDP/ICE)
@@ -745,6 +1392,14 @@ mod tests {
         assert_eq!(*manager.local_peer_id(), expected_peer_id);
     }
 
+    #[tokio::test]
+    async fn test_unknown_peer_is_not_lan_peer() {
+        let keypair = identity::Keypair::generate_ed25519();
+        let manager = NetworkManager::new(keypair).unwrap();
+
+        assert!(!manager.is_lan_peer(&PeerId::random()));
+    }
+
     #[tokio::test]
     async fn test_listen_on() {
         let keypair = identity::Keypair::generate_ed25519();
@@ -1,11 +1,12 @@
 //! Transport Layer
 //!
-//! Manages libp2p transport with TCP, QUIC, Noise encryption, and Yamux multiplexing.
+//! Manages libp2p transport with TCP, QUIC, Noise encryption, and Yamux multiplexing,
+//! plus relayed (`/p2p-circuit`) connections through a circuit relay v2 server.
 
 use libp2p::{
     core::{muxing::StreamMuxerBox, transport::Boxed, upgrade},
     identity::Keypair,
-    noise, quic, tcp, yamux, PeerId, Transport,
+    noise, quic, relay, tcp, yamux, PeerId, Transport,
 };
 use std::time::Duration;
 
@@ -13,15 +14,26 @@ use crate::utils::error::{MePassaError, Result};
 
 /// Build a libp2p transport with:
 /// - TCP + QUIC (dual-stack)
+/// - Relay circuits (the transport half of `relay::client::new`)
 /// - Noise encryption
 /// - Yamux multiplexing
-pub fn build_transport(keypair: &Keypair) -> Result<Boxed<(PeerId, StreamMuxerBox)>> {
+pub fn build_transport(
+    keypair: &Keypair,
+    relay_transport: relay::client::Transport,
+) -> Result<Boxed<(PeerId, StreamMuxerBox)>> {
     // TCP transport with Noise + Yamux (using tokio runtime)
     let tcp_transport = tcp::tokio::Transport::new(tcp::Config::default().nodelay(true))
         .upgrade(upgrade::Version::V1Lazy)
-        .authenticate(noise::Config::new(keypair).map_err(|e| {
-            MePassaError::Network(format!("Failed to create Noise config: {}", e))
-        })?)
+        .authenticate(noise_config(keypair)?)
+        .multiplex(yamux::Config::default())
+        .timeout(Duration::from_secs(20))
+        .boxed();
+
+    // Relayed connections are authenticated and multiplexed end-to-end,
+    // the relay only sees encrypted bytes
+    let relay_transport = relay_transport
+        .upgrade(upgrade::Version::V1Lazy)
+        .authenticate(noise_config(keypair)?)
         .multiplex(yamux::Config::default())
         .timeout(Duration::from_secs(20))
         .boxed();
@@ -31,15 +43,25 @@ pub fn build_transport(keypair: &Keypair) -> Result<Boxed<(PeerId, StreamMuxerBo
         .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
         .boxed();
 
-    // Combine transports (try QUIC first, fallback to TCP)
-    let transport = quic_transport
+    // Combine transports (try QUIC first, fallback to TCP); circuit
+    // addresses are only understood by the relay transport
+    let direct_transport = quic_transport
         .or_transport(tcp_transport)
         .map(|either, _| either.into_inner())
         .boxed();
+    let transport = relay_transport
+        .or_transport(direct_transport)
+        .map(|either, _| either.into_inner())
+        .boxed();
 
     Ok(transport)
 }
 
+fn noise_config(keypair: &Keypair) -> Result<noise::Config> {
+    noise::Config::new(keypair)
+        .map_err(|e| MePassaError::Network(format!("Failed to create Noise config: {}", e)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -48,7 +70,8 @@ mod tests {
     #[test]
     fn test_build_transport() {
         let keypair = identity::Keypair::generate_ed25519();
-        let transport = build_transport(&keypair);
+        let (relay_transport, _) = relay::client::new(keypair.public().to_peer_id());
+        let transport = build_transport(&keypair, relay_transport);
 
         assert!(transport.is_ok());
     }
@@ -58,8 +81,10 @@ mod tests {
         let keypair1 = identity::Keypair::generate_ed25519();
         let keypair2 = identity::Keypair::generate_ed25519();
 
-        let transport1 = build_transport(&keypair1);
-        let transport2 = build_transport(&keypair2);
+        let (relay1, _) = relay::client::new(keypair1.public().to_peer_id());
+        let (relay2, _) = relay::client::new(keypair2.public().to_peer_id());
+        let transport1 = build_transport(&keypair1, relay1);
+        let transport2 = build_transport(&keypair2, relay2);
 
         assert!(transport1.is_ok());
         assert!(transport2.is_ok());
@@ -115,8 +115,25 @@ pub struct EncryptedMessage {
     /// One-time prekey id used for X3DH (0 if not used)
     #[prost(uint32, tag = "5")]
     pub one_time_prekey_id: u32,
+    /// Double Ratchet header: sender's current ratchet public key (X25519)
+    #[prost(bytes = "vec", tag = "6")]
+    pub ratchet_public: ::prost::alloc::vec::Vec<u8>,
+    /// Double Ratchet header: length of the sender's previous sending chain
+    #[prost(uint32, tag = "7")]
+    pub previous_counter: u32,
+    /// Double Ratchet header: message number in the current sending chain
+    #[prost(uint32, tag = "8")]
+    pub counter: u32,
+    /// Initiator's Ed25519 signature (peer id key) over the X3DH fields above,
+    /// sent along with them; the responder rejects unsigned prekey messages
+    #[prost(bytes = "vec", tag = "9")]
+    pub ephemeral_signature: ::prost::alloc::vec::Vec<u8>,
 }
 /// Media offer (metadata only, no bytes)
+///
+/// Never sent in the clear: it is serialized and carried as the plaintext of
+/// an EncryptedMessage (type MESSAGE_TYPE_MEDIA_OFFER), so the file key and
+/// digest only reach the recipient.
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct MediaOffer {
@@ -138,8 +155,18 @@ pub struct MediaOffer {
     pub height: i32,
     #[prost(int32, tag = "9")]
     pub duration_seconds: i32,
+    /// Random per-file AES-256-GCM key the chunks are encrypted with
+    #[prost(bytes = "vec", tag = "10")]
+    pub media_key: ::prost::alloc::vec::Vec<u8>,
+    /// SHA-256 of the whole plaintext file
+    #[prost(bytes = "vec", tag = "11")]
+    pub digest: ::prost::alloc::vec::Vec<u8>,
 }
 /// Media request (asks peer to send chunks)
+///
+/// The requester drives the transfer: it asks for at most `window` chunks
+/// starting at `offset` (the end of what it has already persisted) and asks
+/// for the next window as the current one arrives.
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct MediaRequest {
@@ -151,8 +178,15 @@ pub struct MediaRequest {
     pub offset: i64,
     #[prost(int32, tag = "4")]
     pub chunk_size: i32,
+    /// Maximum number of chunks to send for this request (0 = sender default)
+    #[prost(uint32, tag = "5")]
+    pub window: u32,
 }
 /// Media chunk (binary data)
+///
+/// `data` is nonce || AES-256-GCM ciphertext of the plaintext bytes at
+/// `offset`, under the offer's media key. The media hash, chunk index, offset
+/// and last-chunk flag are authenticated as associated data.
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct MediaChunk {
@@ -166,6 +200,252 @@ pub struct MediaChunk {
     pub data: ::prost::alloc::vec::Vec<u8>,
     #[prost(bool, tag = "5")]
     pub is_last: bool,
+    #[prost(uint32, tag = "6")]
+    pub index: u32,
+}
+/// Sender key distribution (group E2E)
+///
+/// Never sent in the clear: it is serialized and carried as the plaintext of
+/// an EncryptedMessage (type MESSAGE_TYPE_SENDER_KEY_DISTRIBUTION) over the
+/// pairwise Double Ratchet session with each group member.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SenderKeyDistribution {
+    /// Group the key belongs to
+    #[prost(string, tag = "1")]
+    pub group_id: ::prost::alloc::string::String,
+    /// Owner of the sender key (must match the pairwise session peer)
+    #[prost(string, tag = "2")]
+    pub sender_peer_id: ::prost::alloc::string::String,
+    /// Current chain key (32 bytes)
+    #[prost(bytes = "vec", tag = "3")]
+    pub chain_key: ::prost::alloc::vec::Vec<u8>,
+    /// Iteration of the chain key
+    #[prost(uint32, tag = "4")]
+    pub iteration: u32,
+    /// Key generation (incremented on every rotation)
+    #[prost(uint32, tag = "5")]
+    pub generation: u32,
+    /// Ask the recipient to answer with its own sender key
+    #[prost(bool, tag = "6")]
+    pub request_reply: bool,
+    /// Group state, sent by an admin when inviting a new member
+    #[prost(message, optional, tag = "7")]
+    pub group: ::core::option::Option<GroupSnapshot>,
+}
+/// Group metadata and membership snapshot
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GroupSnapshot {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub description: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub creator_peer_id: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "4")]
+    pub members: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(string, repeated, tag = "5")]
+    pub admins: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// Certificate binding a linked device to an account
+///
+/// The account is the peer id of the primary device; the certificate is
+/// signed with its identity key and carries the device's signature over its
+/// link request (see `sync::device::DeviceCertificate`).
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DeviceCertificate {
+    #[prost(string, tag = "1")]
+    pub account_peer_id: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub device_peer_id: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub device_name: ::prost::alloc::string::String,
+    /// Unix timestamp (seconds)
+    #[prost(int64, tag = "4")]
+    pub issued_at: i64,
+    /// Ed25519 signature by the account key
+    #[prost(bytes = "vec", tag = "5")]
+    pub signature: ::prost::alloc::vec::Vec<u8>,
+    /// Unix timestamp (seconds) of the device's link request
+    #[prost(int64, tag = "6")]
+    pub requested_at: i64,
+    /// Ed25519 signature by the device over its link request
+    #[prost(bytes = "vec", tag = "7")]
+    pub device_signature: ::prost::alloc::vec::Vec<u8>,
+}
+/// A linked device and the prekey bundle to start a session with it
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LinkedDevice {
+    #[prost(message, optional, tag = "1")]
+    pub certificate: ::core::option::Option<DeviceCertificate>,
+    /// PreKeyBundle (JSON)
+    #[prost(string, tag = "2")]
+    pub prekey_bundle_json: ::prost::alloc::string::String,
+}
+/// Linked devices of an account, sent by its primary device to all of them
+///
+/// Never sent in the clear: it is serialized and carried as the plaintext of
+/// an EncryptedMessage (type MESSAGE_TYPE_DEVICE_LIST).
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DeviceList {
+    #[prost(string, tag = "1")]
+    pub account_peer_id: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "2")]
+    pub devices: ::prost::alloc::vec::Vec<LinkedDevice>,
+}
+/// Direct message fanned out to one device
+///
+/// Used for copies to a recipient's linked devices, copies to the sender's
+/// own devices and anything sent by a linked device. Never sent in the
+/// clear: it is serialized and carried as the plaintext of an
+/// EncryptedMessage (type MESSAGE_TYPE_DEVICE_MESSAGE).
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DeviceMessage {
+    /// Certificate of the sending device (unset when sent by a primary device)
+    #[prost(message, optional, tag = "1")]
+    pub sender_device: ::core::option::Option<DeviceCertificate>,
+    /// Account the message is addressed to
+    #[prost(string, tag = "2")]
+    pub recipient_account_peer_id: ::prost::alloc::string::String,
+    /// Message text
+    #[prost(string, tag = "3")]
+    pub content: ::prost::alloc::string::String,
+}
+/// A change to state shared by the devices of an account
+///
+/// Changes to the same register (conversation flag, reaction, ...) are
+/// ordered by (lamport, origin_device_peer_id); the greatest wins on every
+/// device (see `sync::crdt`).
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SyncOp {
+    /// Device that made the change
+    #[prost(string, tag = "1")]
+    pub origin_device_peer_id: ::prost::alloc::string::String,
+    /// Per-origin sequence number (1, 2, ...)
+    #[prost(uint64, tag = "2")]
+    pub seq: u64,
+    /// Lamport timestamp
+    #[prost(uint64, tag = "3")]
+    pub lamport: u64,
+    #[prost(oneof = "sync_op::Change", tags = "4, 5, 6, 7, 8")]
+    pub change: ::core::option::Option<sync_op::Change>,
+}
+/// Nested message and enum types in `SyncOp`.
+pub mod sync_op {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum Change {
+        #[prost(message, tag = "4")]
+        ConversationRead(super::ConversationReadChange),
+        #[prost(message, tag = "5")]
+        ConversationMuted(super::ConversationFlagChange),
+        #[prost(message, tag = "6")]
+        ConversationArchived(super::ConversationFlagChange),
+        #[prost(message, tag = "7")]
+        Reaction(super::ReactionChange),
+        #[prost(message, tag = "8")]
+        MessageDeleted(super::MessageDeletedChange),
+    }
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ConversationReadChange {
+    #[prost(string, tag = "1")]
+    pub conversation_id: ::prost::alloc::string::String,
+    /// Unix timestamp (seconds) the conversation was read at
+    #[prost(int64, tag = "2")]
+    pub read_at: i64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ConversationFlagChange {
+    #[prost(string, tag = "1")]
+    pub conversation_id: ::prost::alloc::string::String,
+    #[prost(bool, tag = "2")]
+    pub value: bool,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ReactionChange {
+    #[prost(string, tag = "1")]
+    pub message_id: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub peer_id: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub emoji: ::prost::alloc::string::String,
+    /// Whether the reaction was added (or removed)
+    #[prost(bool, tag = "4")]
+    pub present: bool,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MessageDeletedChange {
+    #[prost(string, tag = "1")]
+    pub message_id: ::prost::alloc::string::String,
+}
+/// Highest contiguous sequence number seen from a device
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SyncCursor {
+    #[prost(string, tag = "1")]
+    pub origin_device_peer_id: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "2")]
+    pub seq: u64,
+}
+/// Ask another device of the account for the changes we are missing
+///
+/// Never sent in the clear: it is serialized and carried as the plaintext of
+/// an EncryptedMessage (type MESSAGE_TYPE_SYNC_REQUEST).
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SyncRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub cursors: ::prost::alloc::vec::Vec<SyncCursor>,
+    /// Also send recent message history (a newly linked device)
+    #[prost(bool, tag = "2")]
+    pub history: bool,
+}
+/// A message of the history sent to a newly linked device
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct HistoryMessage {
+    #[prost(string, tag = "1")]
+    pub message_id: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub conversation_id: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub sender_peer_id: ::prost::alloc::string::String,
+    #[prost(string, tag = "4")]
+    pub recipient_peer_id: ::prost::alloc::string::String,
+    #[prost(string, tag = "5")]
+    pub message_type: ::prost::alloc::string::String,
+    #[prost(string, tag = "6")]
+    pub content: ::prost::alloc::string::String,
+    /// Unix timestamp (seconds)
+    #[prost(int64, tag = "7")]
+    pub created_at: i64,
+    #[prost(string, tag = "8")]
+    pub status: ::prost::alloc::string::String,
+    #[prost(string, tag = "9")]
+    pub parent_message_id: ::prost::alloc::string::String,
+}
+/// Changes (and history) for another device of the account
+///
+/// Never sent in the clear: it is serialized and carried as the plaintext of
+/// an EncryptedMessage (type MESSAGE_TYPE_SYNC_BATCH).
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SyncBatch {
+    #[prost(message, repeated, tag = "1")]
+    pub history: ::prost::alloc::vec::Vec<HistoryMessage>,
+    #[prost(message, repeated, tag = "2")]
+    pub ops: ::prost::alloc::vec::Vec<SyncOp>,
 }
 /// Message type enum
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
@@ -180,6 +460,11 @@ pub enum MessageType {
     MediaOffer = 6,
     MediaRequest = 7,
     MediaChunk = 8,
+    SenderKeyDistribution = 9,
+    DeviceList = 10,
+    DeviceMessage = 11,
+    SyncRequest = 12,
+    SyncBatch = 13,
 }
 impl MessageType {
     /// String value of the enum field names used in the ProtoBuf definition.
@@ -197,6 +482,11 @@ impl MessageType {
             MessageType::MediaOffer => "MESSAGE_TYPE_MEDIA_OFFER",
             MessageType::MediaRequest => "MESSAGE_TYPE_MEDIA_REQUEST",
             MessageType::MediaChunk => "MESSAGE_TYPE_MEDIA_CHUNK",
+            MessageType::SenderKeyDistribution => "MESSAGE_TYPE_SENDER_KEY_DISTRIBUTION",
+            MessageType::DeviceList => "MESSAGE_TYPE_DEVICE_LIST",
+            MessageType::DeviceMessage => "MESSAGE_TYPE_DEVICE_MESSAGE",
+            MessageType::SyncRequest => "MESSAGE_TYPE_SYNC_REQUEST",
+            MessageType::SyncBatch => "MESSAGE_TYPE_SYNC_BATCH",
         }
     }
     /// Creates an enum from field names used in the ProtoBuf definition.
@@ -211,6 +501,11 @@ impl MessageType {
             "MESSAGE_TYPE_MEDIA_OFFER" => Some(Self::MediaOffer),
             "MESSAGE_TYPE_MEDIA_REQUEST" => Some(Self::MediaRequest),
             "MESSAGE_TYPE_MEDIA_CHUNK" => Some(Self::MediaChunk),
+            "MESSAGE_TYPE_SENDER_KEY_DISTRIBUTION" => Some(Self::SenderKeyDistribution),
+            "MESSAGE_TYPE_DEVICE_LIST" => Some(Self::DeviceList),
+            "MESSAGE_TYPE_DEVICE_MESSAGE" => Some(Self::DeviceMessage),
+            "MESSAGE_TYPE_SYNC_REQUEST" => Some(Self::SyncRequest),
+            "MESSAGE_TYPE_SYNC_BATCH" => Some(Self::SyncBatch),
             _ => None,
         }
     }
@@ -226,6 +521,10 @@ pub enum AckStatus {
     Delivered = 2,
     /// Error processing message
     Error = 3,
+    /// One-time prekey already used: retry without it
+    PrekeyMissing = 4,
+    /// Signed prekey rotated out: fetch the bundle again
+    StaleBundle = 5,
 }
 impl AckStatus {
     /// String value of the enum field names used in the ProtoBuf definition.
@@ -238,6 +537,8 @@ impl AckStatus {
             AckStatus::Received => "ACK_STATUS_RECEIVED",
             AckStatus::Delivered => "ACK_STATUS_DELIVERED",
             AckStatus::Error => "ACK_STATUS_ERROR",
+            AckStatus::PrekeyMissing => "ACK_STATUS_PREKEY_MISSING",
+            AckStatus::StaleBundle => "ACK_STATUS_STALE_BUNDLE",
         }
     }
     /// Creates an enum from field names used in the ProtoBuf definition.
@@ -247,6 +548,8 @@ impl AckStatus {
             "ACK_STATUS_RECEIVED" => Some(Self::Received),
             "ACK_STATUS_DELIVERED" => Some(Self::Delivered),
             "ACK_STATUS_ERROR" => Some(Self::Error),
+            "ACK_STATUS_PREKEY_MISSING" => Some(Self::PrekeyMissing),
+            "ACK_STATUS_STALE_BUNDLE" => Some(Self::StaleBundle),
             _ => None,
         }
     }
@@ -0,0 +1,155 @@
+//! Linked Devices Storage
+//!
+//! Devices linked to accounts, ours and our contacts'. The certificate is the
+//! encoded `DeviceCertificate` protobuf and is verified by the caller before
+//! it is stored (`sync::device`).
+
+use rusqlite::{params, OptionalExtension, Row};
+
+use super::{Database, Result};
+
+/// Stored linked device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredDevice {
+    pub device_peer_id: String,
+    pub account_peer_id: String,
+    pub device_name: String,
+    /// Encoded `DeviceCertificate`
+    pub certificate: Vec<u8>,
+    /// Prekey bundle of the device, once known
+    pub prekey_bundle_json: Option<String>,
+    pub linked_at: i64,
+}
+
+impl StoredDevice {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            device_peer_id: row.get(0)?,
+            account_peer_id: row.get(1)?,
+            device_name: row.get(2)?,
+            certificate: row.get(3)?,
+            prekey_bundle_json: row.get(4)?,
+            linked_at: row.get(5)?,
+        })
+    }
+}
+
+const DEVICE_COLUMNS: &str =
+    "device_peer_id, account_peer_id, device_name, certificate, prekey_bundle_json, linked_at";
+
+impl Database {
+    /// Insert a linked device, or refresh it
+    ///
+    /// A missing prekey bundle keeps the one already stored. A device stays
+    /// linked to the account it was first stored for: returns `false`, and
+    /// leaves the row alone, if it belongs to another account.
+    pub fn save_device(
+        &self,
+        device_peer_id: &str,
+        account_peer_id: &str,
+        device_name: &str,
+        certificate: &[u8],
+        prekey_bundle_json: Option<&str>,
+    ) -> Result<bool> {
+        let saved = self.conn().execute(
+            r#"
+            INSERT INTO devices (device_peer_id, account_peer_id, device_name, certificate, prekey_bundle_json)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT(device_peer_id) DO UPDATE SET
+                device_name = excluded.device_name,
+                certificate = excluded.certificate,
+                prekey_bundle_json = COALESCE(excluded.prekey_bundle_json, devices.prekey_bundle_json)
+            WHERE devices.account_peer_id = excluded.account_peer_id
+            "#,
+            params![
+                device_peer_id,
+                account_peer_id,
+                device_name,
+                certificate,
+                prekey_bundle_json
+            ],
+        )?;
+
+        Ok(saved > 0)
+    }
+
+    /// Get a linked device
+    pub fn get_device(&self, device_peer_id: &str) -> Result<Option<StoredDevice>> {
+        let conn = self.conn();
+        let device = conn
+            .query_row(
+                &format!("SELECT {} FROM devices WHERE device_peer_id = ?1", DEVICE_COLUMNS),
+                [device_peer_id],
+                StoredDevice::from_row,
+            )
+            .optional()?;
+
+        Ok(device)
+    }
+
+    /// Devices linked to an account (oldest first, the primary not included)
+    pub fn list_devices(&self, account_peer_id: &str) -> Result<Vec<StoredDevice>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM devices WHERE account_peer_id = ?1 ORDER BY linked_at ASC, rowid ASC",
+            DEVICE_COLUMNS
+        ))?;
+
+        let devices = stmt
+            .query_map([account_peer_id], StoredDevice::from_row)?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        Ok(devices)
+    }
+
+    /// Unlink a device
+    pub fn delete_device(&self, device_peer_id: &str) -> Result<bool> {
+        let removed = self
+            .conn()
+            .execute("DELETE FROM devices WHERE device_peer_id = ?1", [device_peer_id])?;
+
+        Ok(removed > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::schema::init_schema;
+
+    #[test]
+    fn test_save_and_list_devices() {
+        let db = Database::in_memory().unwrap();
+        init_schema(&db).unwrap();
+
+        db.save_device("laptop", "alice", "Laptop", b"cert-1", Some("{}"))
+            .unwrap();
+        db.save_device("tablet", "alice", "Tablet", b"cert-2", None)
+            .unwrap();
+        db.save_device("phone", "bob", "Phone", b"cert-3", None).unwrap();
+
+        let devices = db.list_devices("alice").unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].device_peer_id, "laptop");
+        assert_eq!(devices[0].prekey_bundle_json.as_deref(), Some("{}"));
+
+        // Re-linking without a bundle keeps the known one
+        db.save_device("laptop", "alice", "Work laptop", b"cert-4", None)
+            .unwrap();
+        let laptop = db.get_device("laptop").unwrap().unwrap();
+        assert_eq!(laptop.device_name, "Work laptop");
+        assert_eq!(laptop.certificate, b"cert-4");
+        assert_eq!(laptop.prekey_bundle_json.as_deref(), Some("{}"));
+
+        // Another account can't take the device over
+        assert!(!db.save_device("laptop", "mallory", "Laptop", b"cert-5", Some("[]")).unwrap());
+        let laptop = db.get_device("laptop").unwrap().unwrap();
+        assert_eq!(laptop.account_peer_id, "alice");
+        assert_eq!(laptop.certificate, b"cert-4");
+        assert_eq!(laptop.prekey_bundle_json.as_deref(), Some("{}"));
+
+        assert!(db.delete_device("laptop").unwrap());
+        assert!(db.get_device("laptop").unwrap().is_none());
+        assert_eq!(db.list_devices("alice").unwrap().len(), 1);
+    }
+}
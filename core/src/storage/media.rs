@@ -40,6 +40,14 @@ pub struct NewMedia {
     pub duration_seconds: Option<i32>,
 }
 
+/// Stored media key record (key blob encrypted by the caller)
+#[derive(Debug, Clone)]
+pub struct StoredMediaKey {
+    pub media_hash: String,
+    pub key_data: Vec<u8>,
+    pub digest: Vec<u8>,
+}
+
 /// Media type enumeration
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MediaType {
@@ -258,6 +266,79 @@ impl Database {
         Ok(())
     }
 
+    /// Insert or replace the encryption key and plaintext digest of a file
+    pub fn save_media_key(&self, media_hash: &str, key_data: &[u8], digest: &[u8]) -> Result<()> {
+        self.conn().execute(
+            r#"
+            INSERT INTO media_keys (media_hash, key_data, digest)
+            VALUES (?1, ?2, ?3)
+            ON CONFLICT(media_hash) DO UPDATE SET
+                key_data = excluded.key_data,
+                digest = excluded.digest
+            "#,
+            params![media_hash, key_data, digest],
+        )?;
+        Ok(())
+    }
+
+    /// Get the encryption key and plaintext digest of a file
+    pub fn get_media_key(&self, media_hash: &str) -> Result<Option<StoredMediaKey>> {
+        let conn = self.conn();
+        match conn.query_row(
+            "SELECT media_hash, key_data, digest FROM media_keys WHERE media_hash = ?1",
+            params![media_hash],
+            |row| {
+                Ok(StoredMediaKey {
+                    media_hash: row.get(0)?,
+                    key_data: row.get(1)?,
+                    digest: row.get(2)?,
+                })
+            },
+        ) {
+            Ok(key) => Ok(Some(key)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Bytes of a download already persisted contiguously from the start
+    pub fn get_media_received_bytes(&self, media_hash: &str) -> Result<u64> {
+        let conn = self.conn();
+        match conn.query_row(
+            "SELECT received_bytes FROM media_transfers WHERE media_hash = ?1",
+            params![media_hash],
+            |row| row.get::<_, i64>(0),
+        ) {
+            Ok(bytes) => Ok(bytes.max(0) as u64),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Record how far a download has progressed
+    pub fn set_media_received_bytes(&self, media_hash: &str, received_bytes: u64) -> Result<()> {
+        self.conn().execute(
+            r#"
+            INSERT INTO media_transfers (media_hash, received_bytes)
+            VALUES (?1, ?2)
+            ON CONFLICT(media_hash) DO UPDATE SET
+                received_bytes = excluded.received_bytes,
+                updated_at = unixepoch()
+            "#,
+            params![media_hash, received_bytes as i64],
+        )?;
+        Ok(())
+    }
+
+    /// Forget the progress of a finished or discarded download
+    pub fn delete_media_transfer(&self, media_hash: &str) -> Result<()> {
+        self.conn().execute(
+            "DELETE FROM media_transfers WHERE media_hash = ?1",
+            params![media_hash],
+        )?;
+        Ok(())
+    }
+
     /// Helper: Parse media from row
     fn media_from_row(&self, row: &Row) -> rusqlite::Result<Media> {
         Ok(Media {
@@ -408,4 +489,32 @@ mod tests {
             MediaType::Document
         );
     }
+
+    #[test]
+    fn test_save_and_get_media_key() {
+        let db = setup_test_db();
+
+        assert!(db.get_media_key("hash123").unwrap().is_none());
+
+        db.save_media_key("hash123", b"key", b"digest").unwrap();
+        db.save_media_key("hash123", b"key2", b"digest2").unwrap();
+
+        let key = db.get_media_key("hash123").unwrap().unwrap();
+        assert_eq!(key.key_data, b"key2");
+        assert_eq!(key.digest, b"digest2");
+    }
+
+    #[test]
+    fn test_media_transfer_progress() {
+        let db = setup_test_db();
+
+        assert_eq!(db.get_media_received_bytes("hash123").unwrap(), 0);
+
+        db.set_media_received_bytes("hash123", 4096).unwrap();
+        db.set_media_received_bytes("hash123", 8192).unwrap();
+        assert_eq!(db.get_media_received_bytes("hash123").unwrap(), 8192);
+
+        db.delete_media_transfer("hash123").unwrap();
+        assert_eq!(db.get_media_received_bytes("hash123").unwrap(), 0);
+    }
 }
@@ -261,8 +261,23 @@ impl Database {
         Ok(conversation_id)
     }
 
+    /// Get or create conversation for a group chat
+    pub fn get_or_create_group_conversation(&self, group_id: &str, display_name: &str) -> Result<String> {
+        let conversation_id = format!("group:{}", group_id);
+
+        self.conn().execute(
+            r#"
+            INSERT OR IGNORE INTO conversations (id, conversation_type, group_id, display_name)
+            VALUES (?1, 'group', ?2, ?3)
+            "#,
+            params![&conversation_id, group_id, display_name],
+        )?;
+
+        Ok(conversation_id)
+    }
+
     /// Ensure a contact exists in the database (create placeholder if not)
-    fn ensure_contact_exists(&self, peer_id: &str) -> Result<()> {
+    pub(crate) fn ensure_contact_exists(&self, peer_id: &str) -> Result<()> {
         // Check if contact already exists
         let exists: bool = self
             .conn()
@@ -338,16 +353,118 @@ impl Database {
         Ok(())
     }
 
-    /// Mark conversation as read
-    pub fn mark_conversation_read(&self, conversation_id: &str) -> Result<()> {
+    /// Mark the messages of a conversation up to `read_at` (unix seconds) as
+    /// read
+    ///
+    /// Messages that arrived later stay unread, and are counted in
+    /// `unread_count`.
+    pub fn mark_conversation_read(&self, conversation_id: &str, read_at: i64) -> Result<()> {
         let conn = self.conn();
         conn.execute(
-            "UPDATE conversations SET unread_count = 0 WHERE id = ?1",
+            r#"
+            UPDATE messages SET read_at = ?2
+            WHERE conversation_id = ?1 AND read_at IS NULL AND created_at <= ?2
+            "#,
+            params![conversation_id, read_at],
+        )?;
+        conn.execute(
+            r#"
+            UPDATE conversations SET unread_count = (
+                SELECT COUNT(*) FROM messages
+                WHERE messages.conversation_id = conversations.id
+                  AND messages.sender_peer_id = conversations.peer_id
+                  AND messages.read_at IS NULL
+                  AND messages.is_deleted = 0
+            )
+            WHERE id = ?1
+            "#,
             params![conversation_id],
         )?;
         Ok(())
     }
 
+    /// Mute or unmute a conversation
+    pub fn set_conversation_muted(&self, conversation_id: &str, muted: bool) -> Result<()> {
+        self.conn().execute(
+            "UPDATE conversations SET is_muted = ?1 WHERE id = ?2",
+            params![muted as i32, conversation_id],
+        )?;
+        Ok(())
+    }
+
+    /// Archive or unarchive a conversation
+    pub fn set_conversation_archived(&self, conversation_id: &str, archived: bool) -> Result<()> {
+        self.conn().execute(
+            "UPDATE conversations SET is_archived = ?1 WHERE id = ?2",
+            params![archived as i32, conversation_id],
+        )?;
+        Ok(())
+    }
+
+    /// Most recent text messages of 1:1 conversations, newest first
+    pub fn recent_messages(&self, limit: usize) -> Result<Vec<Message>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT id, message_id, conversation_id, sender_peer_id, recipient_peer_id,
+                   message_type, content_encrypted, content_plaintext, created_at,
+                   sent_at, received_at, read_at, status, is_deleted, parent_message_id
+            FROM messages
+            WHERE conversation_id LIKE '1:1:%' AND message_type = 'text' AND is_deleted = 0
+            ORDER BY created_at DESC, id DESC
+            LIMIT ?1
+            "#,
+        )?;
+
+        let messages = stmt
+            .query_map(params![limit], |row| self.message_from_row(row))?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        Ok(messages)
+    }
+
+    /// Insert a message received from another device, keeping its creation
+    /// time; false if it was already stored
+    ///
+    /// The conversation's last message only moves forward in time.
+    pub fn import_message(&self, message: &NewMessage, created_at: i64) -> Result<bool> {
+        self.ensure_contact_exists(&message.sender_peer_id)?;
+        let inserted = self.conn().execute(
+            r#"
+            INSERT OR IGNORE INTO messages (
+                message_id, conversation_id, sender_peer_id, recipient_peer_id,
+                message_type, content_encrypted, content_plaintext, status,
+                parent_message_id, created_at
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+            "#,
+            params![
+                message.message_id,
+                message.conversation_id,
+                message.sender_peer_id,
+                message.recipient_peer_id,
+                message.message_type,
+                message.content_encrypted,
+                message.content_plaintext,
+                message.status.as_str(),
+                message.parent_message_id,
+                created_at,
+            ],
+        )?;
+        if inserted == 0 {
+            return Ok(false);
+        }
+
+        self.conn().execute(
+            r#"
+            UPDATE conversations
+            SET last_message_id = ?1, last_message_at = ?2
+            WHERE id = ?3 AND (last_message_at IS NULL OR last_message_at <= ?2)
+            "#,
+            params![message.message_id, created_at, message.conversation_id],
+        )?;
+        Ok(true)
+    }
+
     /// Search messages using FTS5
     pub fn search_messages(&self, query: &str, limit: Option<usize>) -> Result<Vec<Message>> {
         let conn = self.conn();
@@ -534,4 +651,50 @@ mod tests {
         let conv_id2 = db.get_or_create_conversation("peer1").unwrap();
         assert_eq!(conv_id, conv_id2);
     }
+
+    #[test]
+    fn test_get_or_create_group_conversation() {
+        let db = setup_test_db();
+
+        let conv_id = db.get_or_create_group_conversation("group-1", "Team").unwrap();
+        assert_eq!(conv_id, "group:group-1");
+
+        let conv_id2 = db.get_or_create_group_conversation("group-1", "Team").unwrap();
+        assert_eq!(conv_id, conv_id2);
+
+        let conversation = db.get_conversation(&conv_id).unwrap();
+        assert_eq!(conversation.group_id.as_deref(), Some("group-1"));
+        assert_eq!(conversation.display_name.as_deref(), Some("Team"));
+    }
+
+    #[test]
+    fn test_import_message() {
+        let db = setup_test_db();
+        let conv_id = db.get_or_create_conversation("peer1").unwrap();
+
+        let message = |message_id: &str| NewMessage {
+            message_id: message_id.to_string(),
+            conversation_id: conv_id.clone(),
+            sender_peer_id: "peer3".to_string(),
+            recipient_peer_id: Some("peer1".to_string()),
+            message_type: "text".to_string(),
+            content_encrypted: None,
+            content_plaintext: Some("Earlier".to_string()),
+            status: MessageStatus::Delivered,
+            parent_message_id: None,
+        };
+        assert!(db.import_message(&message("new"), 2_000).unwrap());
+        assert!(db.import_message(&message("old"), 1_000).unwrap());
+        assert!(!db.import_message(&message("new"), 2_000).unwrap());
+
+        // Creation times are kept and only the newest is the last message
+        assert_eq!(db.get_message("old").unwrap().created_at, 1_000);
+        let conversation = db.get_conversation(&conv_id).unwrap();
+        assert_eq!(conversation.last_message_id.as_deref(), Some("new"));
+        assert_eq!(conversation.last_message_at, Some(2_000));
+
+        let recent = db.recent_messages(1).unwrap();
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].message_id, "new");
+    }
 }
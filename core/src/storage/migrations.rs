@@ -29,6 +29,56 @@ const MIGRATIONS: &[Migration] = &[
         description: "Add message_reactions table for emoji reactions",
         up: migrate_to_v3,
     },
+    Migration {
+        version: 4,
+        description: "Add sessions table for persisted E2E sessions",
+        up: migrate_to_v4,
+    },
+    Migration {
+        version: 5,
+        description: "Add sender_keys table for persisted group sender keys",
+        up: migrate_to_v5,
+    },
+    Migration {
+        version: 6,
+        description: "Add media_keys table for end-to-end encrypted media",
+        up: migrate_to_v6,
+    },
+    Migration {
+        version: 7,
+        description: "Add media_transfers table for resumable downloads",
+        up: migrate_to_v7,
+    },
+    Migration {
+        version: 8,
+        description: "Add outbox table for retried message delivery",
+        up: migrate_to_v8,
+    },
+    Migration {
+        version: 9,
+        description: "Add devices table and per-device outbox entries",
+        up: migrate_to_v9,
+    },
+    Migration {
+        version: 10,
+        description: "Add sync_ops table for multi-device state sync",
+        up: migrate_to_v10,
+    },
+    Migration {
+        version: 11,
+        description: "Add prekey_pool table for persisted prekeys",
+        up: migrate_to_v11,
+    },
+    Migration {
+        version: 12,
+        description: "Add plaintext flag to outbox for LAN-only messages",
+        up: migrate_to_v12,
+    },
+    Migration {
+        version: 13,
+        description: "Add group_admin_actions table against replayed admin actions",
+        up: migrate_to_v13,
+    },
 ];
 
 /// Migrate database to latest version
@@ -147,6 +197,195 @@ fn migrate_to_v3(db: &Database) -> Result<()> {
     Ok(())
 }
 
+/// Migration to version 4: Add sessions table
+fn migrate_to_v4(db: &Database) -> Result<()> {
+    db.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS sessions (
+            peer_id TEXT PRIMARY KEY,
+            session_data BLOB NOT NULL,
+            created_at INTEGER NOT NULL DEFAULT (unixepoch()),
+            updated_at INTEGER NOT NULL DEFAULT (unixepoch())
+        );
+        "#,
+    )?;
+
+    Ok(())
+}
+
+/// Migration to version 5: Add sender_keys table
+fn migrate_to_v5(db: &Database) -> Result<()> {
+    db.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS sender_keys (
+            group_id TEXT NOT NULL,
+            sender_peer_id TEXT NOT NULL,
+            key_data BLOB NOT NULL,
+            updated_at INTEGER NOT NULL DEFAULT (unixepoch()),
+            PRIMARY KEY (group_id, sender_peer_id)
+        );
+        "#,
+    )?;
+
+    Ok(())
+}
+
+fn migrate_to_v6(db: &Database) -> Result<()> {
+    db.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS media_keys (
+            media_hash TEXT PRIMARY KEY,
+            key_data BLOB NOT NULL,
+            digest BLOB NOT NULL,
+            created_at INTEGER NOT NULL DEFAULT (unixepoch())
+        );
+        "#,
+    )?;
+
+    Ok(())
+}
+
+fn migrate_to_v7(db: &Database) -> Result<()> {
+    db.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS media_transfers (
+            media_hash TEXT PRIMARY KEY,
+            received_bytes INTEGER NOT NULL DEFAULT 0,
+            updated_at INTEGER NOT NULL DEFAULT (unixepoch())
+        );
+        "#,
+    )?;
+
+    Ok(())
+}
+
+fn migrate_to_v8(db: &Database) -> Result<()> {
+    db.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS outbox (
+            message_id TEXT PRIMARY KEY,
+            recipient_peer_id TEXT NOT NULL,
+            payload BLOB NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            next_attempt_at INTEGER NOT NULL,
+            last_error TEXT,
+            created_at INTEGER NOT NULL DEFAULT (unixepoch())
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_outbox_next_attempt ON outbox(next_attempt_at);
+        "#,
+    )?;
+
+    Ok(())
+}
+
+fn migrate_to_v9(db: &Database) -> Result<()> {
+    db.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS devices (
+            device_peer_id TEXT PRIMARY KEY,
+            account_peer_id TEXT NOT NULL,
+            device_name TEXT NOT NULL,
+            certificate BLOB NOT NULL,
+            prekey_bundle_json TEXT,
+            linked_at INTEGER NOT NULL DEFAULT (unixepoch())
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_devices_account ON devices(account_peer_id);
+
+        -- Outbox entries are now keyed by (message, recipient device)
+        CREATE TABLE outbox_v9 (
+            message_id TEXT NOT NULL,
+            recipient_peer_id TEXT NOT NULL,
+            payload BLOB NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            next_attempt_at INTEGER NOT NULL,
+            last_error TEXT,
+            created_at INTEGER NOT NULL DEFAULT (unixepoch()),
+            PRIMARY KEY (message_id, recipient_peer_id)
+        );
+
+        INSERT INTO outbox_v9
+        SELECT message_id, recipient_peer_id, payload, attempts, next_attempt_at, last_error, created_at
+        FROM outbox;
+
+        DROP TABLE outbox;
+        ALTER TABLE outbox_v9 RENAME TO outbox;
+        CREATE INDEX IF NOT EXISTS idx_outbox_next_attempt ON outbox(next_attempt_at);
+        "#,
+    )?;
+
+    Ok(())
+}
+
+/// Migration to version 10: Replicated state changes between linked devices
+fn migrate_to_v10(db: &Database) -> Result<()> {
+    db.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS sync_ops (
+            origin_device TEXT NOT NULL,
+            seq INTEGER NOT NULL,
+            lamport INTEGER NOT NULL,
+            op_key TEXT NOT NULL,
+            op BLOB NOT NULL,
+            received_at INTEGER NOT NULL DEFAULT (unixepoch()),
+            PRIMARY KEY (origin_device, seq)
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_sync_ops_key ON sync_ops(op_key, lamport DESC);
+        "#,
+    )?;
+
+    Ok(())
+}
+
+/// Migration to version 11: Prekeys survive restarts
+fn migrate_to_v11(db: &Database) -> Result<()> {
+    db.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS prekey_pool (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            pool_data BLOB NOT NULL,
+            updated_at INTEGER NOT NULL DEFAULT (unixepoch())
+        );
+        "#,
+    )?;
+
+    Ok(())
+}
+
+/// Migration to version 12: Plaintext copies never leave the LAN
+fn migrate_to_v12(db: &Database) -> Result<()> {
+    let has_column: bool = db.conn().query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('outbox') WHERE name = 'plaintext'",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_column {
+        db.execute_batch("ALTER TABLE outbox ADD COLUMN plaintext INTEGER NOT NULL DEFAULT 0;")?;
+    }
+
+    Ok(())
+}
+
+/// Migration to version 13: Admin actions are applied once, in order
+fn migrate_to_v13(db: &Database) -> Result<()> {
+    db.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS group_admin_actions (
+            message_id TEXT PRIMARY KEY,
+            group_id TEXT NOT NULL,
+            sent_at INTEGER NOT NULL,
+            applied_at INTEGER NOT NULL DEFAULT (unixepoch())
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_group_admin_actions_group ON group_admin_actions(group_id, sent_at);
+        "#,
+    )?;
+
+    Ok(())
+}
+
 /// Check if database needs migration
 pub fn needs_migration(db: &Database) -> Result<bool> {
     let current_version = db.get_version()?;
@@ -212,4 +451,183 @@ mod tests {
 
         assert_eq!(username, Some("alice".to_string()));
     }
+
+    #[test]
+    fn test_migration_from_v3_adds_sessions_table() {
+        let db = Database::in_memory().unwrap();
+        migrate(&db).unwrap();
+
+        // Simulate a database created before sessions were persisted
+        db.execute_batch("DROP TABLE sessions;").unwrap();
+        db.set_version(3).unwrap();
+
+        migrate(&db).unwrap();
+
+        assert!(db.table_exists("sessions").unwrap());
+        assert_eq!(db.get_version().unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_migration_from_v4_adds_sender_keys_table() {
+        let db = Database::in_memory().unwrap();
+        migrate(&db).unwrap();
+
+        db.execute_batch("DROP TABLE sender_keys;").unwrap();
+        db.set_version(4).unwrap();
+
+        migrate(&db).unwrap();
+
+        assert!(db.table_exists("sender_keys").unwrap());
+        assert_eq!(db.get_version().unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_migration_from_v5_adds_media_keys_table() {
+        let db = Database::in_memory().unwrap();
+        migrate(&db).unwrap();
+
+        db.execute_batch("DROP TABLE media_keys;").unwrap();
+        db.set_version(5).unwrap();
+
+        migrate(&db).unwrap();
+
+        assert!(db.table_exists("media_keys").unwrap());
+        assert_eq!(db.get_version().unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_migration_from_v6_adds_media_transfers_table() {
+        let db = Database::in_memory().unwrap();
+        migrate(&db).unwrap();
+
+        db.execute_batch("DROP TABLE media_transfers;").unwrap();
+        db.set_version(6).unwrap();
+
+        migrate(&db).unwrap();
+
+        assert!(db.table_exists("media_transfers").unwrap());
+        assert_eq!(db.get_version().unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_migration_from_v7_adds_outbox_table() {
+        let db = Database::in_memory().unwrap();
+        migrate(&db).unwrap();
+
+        db.execute_batch("DROP TABLE outbox;").unwrap();
+        db.set_version(7).unwrap();
+
+        migrate(&db).unwrap();
+
+        assert!(db.table_exists("outbox").unwrap());
+        assert_eq!(db.get_version().unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_migration_from_v8_keeps_queued_messages() {
+        let db = Database::in_memory().unwrap();
+        migrate(&db).unwrap();
+
+        // A v8 database: one outbox row per message, no devices
+        db.execute_batch(
+            r#"
+            DROP TABLE outbox;
+            DROP TABLE devices;
+            CREATE TABLE outbox (
+                message_id TEXT PRIMARY KEY,
+                recipient_peer_id TEXT NOT NULL,
+                payload BLOB NOT NULL,
+                attempts INTEGER NOT NULL DEFAULT 0,
+                next_attempt_at INTEGER NOT NULL,
+                last_error TEXT,
+                created_at INTEGER NOT NULL DEFAULT (unixepoch())
+            );
+            INSERT INTO outbox (message_id, recipient_peer_id, payload, next_attempt_at)
+            VALUES ('msg-1', 'peer-a', x'01', 0);
+            "#,
+        )
+        .unwrap();
+        db.set_version(8).unwrap();
+
+        migrate(&db).unwrap();
+
+        assert!(db.table_exists("devices").unwrap());
+        assert!(db.get_outbox_entry("msg-1", "peer-a").unwrap().is_some());
+        db.enqueue_outbox("msg-1", "peer-b", b"copy").unwrap();
+        assert_eq!(db.get_version().unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_migration_from_v9_adds_sync_ops_table() {
+        let db = Database::in_memory().unwrap();
+        migrate(&db).unwrap();
+
+        db.execute_batch("DROP TABLE sync_ops;").unwrap();
+        db.set_version(9).unwrap();
+
+        migrate(&db).unwrap();
+
+        assert!(db.table_exists("sync_ops").unwrap());
+        assert_eq!(db.get_version().unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_migration_from_v10_adds_prekey_pool_table() {
+        let db = Database::in_memory().unwrap();
+        migrate(&db).unwrap();
+
+        db.execute_batch("DROP TABLE prekey_pool;").unwrap();
+        db.set_version(10).unwrap();
+
+        migrate(&db).unwrap();
+
+        assert!(db.table_exists("prekey_pool").unwrap());
+        assert_eq!(db.get_version().unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_migration_from_v11_keeps_queued_messages_encrypted() {
+        let db = Database::in_memory().unwrap();
+        migrate(&db).unwrap();
+
+        db.execute_batch(
+            r#"
+            DROP TABLE outbox;
+            CREATE TABLE outbox (
+                message_id TEXT NOT NULL,
+                recipient_peer_id TEXT NOT NULL,
+                payload BLOB NOT NULL,
+                attempts INTEGER NOT NULL DEFAULT 0,
+                next_attempt_at INTEGER NOT NULL,
+                last_error TEXT,
+                created_at INTEGER NOT NULL DEFAULT (unixepoch()),
+                PRIMARY KEY (message_id, recipient_peer_id)
+            );
+            INSERT INTO outbox (message_id, recipient_peer_id, payload, next_attempt_at)
+            VALUES ('msg-1', 'peer-a', x'01', 0);
+            "#,
+        )
+        .unwrap();
+        db.set_version(11).unwrap();
+
+        migrate(&db).unwrap();
+
+        let entry = db.get_outbox_entry("msg-1", "peer-a").unwrap().unwrap();
+        assert!(!entry.plaintext);
+        assert_eq!(db.get_version().unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_migration_from_v12_adds_group_admin_actions_table() {
+        let db = Database::in_memory().unwrap();
+        migrate(&db).unwrap();
+
+        db.execute_batch("DROP TABLE group_admin_actions;").unwrap();
+        db.set_version(12).unwrap();
+
+        migrate(&db).unwrap();
+
+        assert!(db.table_exists("group_admin_actions").unwrap());
+        assert_eq!(db.get_version().unwrap(), SCHEMA_VERSION);
+    }
 }
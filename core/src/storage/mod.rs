@@ -4,21 +4,32 @@
 
 pub mod contacts;
 pub mod database;
+pub mod devices;
 pub mod groups;
 pub mod media;
 pub mod messages;
 pub mod migrations;
+pub mod outbox;
+pub mod prekeys;
 pub mod reactions;
 pub mod schema;
+pub mod sender_keys;
+pub mod sessions;
+pub mod sync_ops;
 
 pub use contacts::{Contact, NewContact, UpdateContact};
 pub use database::Database;
+pub use devices::StoredDevice;
 pub use groups::{Group, GroupMember, MemberRole, NewGroup, NewGroupMember};
-pub use media::{Media, MediaType, NewMedia};
+pub use media::{Media, MediaType, NewMedia, StoredMediaKey};
 pub use messages::{Conversation, Message, MessageStatus, NewMessage, UpdateMessage};
 pub use migrations::{migrate, needs_migration};
+pub use outbox::OutboxEntry;
 pub use reactions::{NewReaction, Reaction};
 pub use schema::{init_fts, init_schema, SCHEMA_VERSION};
+pub use sender_keys::StoredSenderKey;
+pub use sessions::StoredSession;
+pub use sync_ops::StoredSyncOp;
 
 use thiserror::Error;
 
@@ -0,0 +1,278 @@
+//! Outbox Storage
+//!
+//! Outgoing direct messages waiting for an ACK. Each entry holds the encoded
+//! protocol message (encrypted with the storage key by the caller), so it can
+//! be retransmitted unchanged after a restart. A message sent to an account
+//! with linked devices has one entry per device. Plaintext copies (allowed
+//! only to LAN peers) are flagged so they are never handed off.
+
+use rusqlite::{params, OptionalExtension, Row};
+
+use super::{Database, Result};
+
+/// Queued outgoing message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboxEntry {
+    pub message_id: String,
+    pub recipient_peer_id: String,
+    /// Encoded protocol message (storage-encrypted)
+    pub payload: Vec<u8>,
+    /// Delivery attempts made so far
+    pub attempts: u32,
+    /// Unix timestamp (ms) of the next attempt
+    pub next_attempt_at: i64,
+    pub last_error: Option<String>,
+    pub created_at: i64,
+    /// Not end-to-end encrypted: retried only while the peer is on the LAN
+    pub plaintext: bool,
+}
+
+impl OutboxEntry {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            message_id: row.get(0)?,
+            recipient_peer_id: row.get(1)?,
+            payload: row.get(2)?,
+            attempts: row.get(3)?,
+            next_attempt_at: row.get(4)?,
+            last_error: row.get(5)?,
+            created_at: row.get(6)?,
+            plaintext: row.get(7)?,
+        })
+    }
+}
+
+const OUTBOX_COLUMNS: &str = "message_id, recipient_peer_id, payload, attempts, next_attempt_at, \
+     last_error, created_at, plaintext";
+
+impl Database {
+    /// Queue an outgoing message, due immediately
+    pub fn enqueue_outbox(&self, message_id: &str, recipient_peer_id: &str, payload: &[u8]) -> Result<()> {
+        self.insert_outbox(message_id, recipient_peer_id, payload, false)
+    }
+
+    /// Queue a message that is not end-to-end encrypted, due immediately
+    pub fn enqueue_plaintext_outbox(
+        &self,
+        message_id: &str,
+        recipient_peer_id: &str,
+        payload: &[u8],
+    ) -> Result<()> {
+        self.insert_outbox(message_id, recipient_peer_id, payload, true)
+    }
+
+    fn insert_outbox(
+        &self,
+        message_id: &str,
+        recipient_peer_id: &str,
+        payload: &[u8],
+        plaintext: bool,
+    ) -> Result<()> {
+        self.conn().execute(
+            r#"
+            INSERT INTO outbox (message_id, recipient_peer_id, payload, next_attempt_at, plaintext)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            "#,
+            params![
+                message_id,
+                recipient_peer_id,
+                payload,
+                chrono::Utc::now().timestamp_millis(),
+                plaintext
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Get the entry of a message queued for one recipient device
+    pub fn get_outbox_entry(
+        &self,
+        message_id: &str,
+        recipient_peer_id: &str,
+    ) -> Result<Option<OutboxEntry>> {
+        let conn = self.conn();
+        let entry = conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM outbox WHERE message_id = ?1 AND recipient_peer_id = ?2",
+                    OUTBOX_COLUMNS
+                ),
+                [message_id, recipient_peer_id],
+                OutboxEntry::from_row,
+            )
+            .optional()?;
+
+        Ok(entry)
+    }
+
+    /// Devices a message is still queued for
+    pub fn outbox_recipients(&self, message_id: &str) -> Result<Vec<String>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT recipient_peer_id FROM outbox WHERE message_id = ?1 ORDER BY rowid ASC",
+        )?;
+
+        let recipients = stmt
+            .query_map([message_id], |row| row.get(0))?
+            .collect::<std::result::Result<Vec<String>, _>>()?;
+
+        Ok(recipients)
+    }
+
+    /// Queued messages whose next attempt is due at `now` (oldest first)
+    pub fn due_outbox_entries(&self, now: i64) -> Result<Vec<OutboxEntry>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM outbox WHERE next_attempt_at <= ?1 ORDER BY created_at ASC, rowid ASC",
+            OUTBOX_COLUMNS
+        ))?;
+
+        let entries = stmt
+            .query_map([now], OutboxEntry::from_row)?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        Ok(entries)
+    }
+
+    /// All queued messages (oldest first)
+    pub fn list_outbox(&self) -> Result<Vec<OutboxEntry>> {
+        self.due_outbox_entries(i64::MAX)
+    }
+
+    /// Record a delivery attempt and schedule the next one
+    pub fn reschedule_outbox(
+        &self,
+        message_id: &str,
+        recipient_peer_id: &str,
+        attempts: u32,
+        next_attempt_at: i64,
+        last_error: Option<&str>,
+    ) -> Result<()> {
+        self.conn().execute(
+            r#"
+            UPDATE outbox
+            SET attempts = ?3, next_attempt_at = ?4, last_error = ?5
+            WHERE message_id = ?1 AND recipient_peer_id = ?2
+            "#,
+            params![message_id, recipient_peer_id, attempts, next_attempt_at, last_error],
+        )?;
+
+        Ok(())
+    }
+
+    /// Replace the queued copy of a message (encrypted for a new session),
+    /// due immediately with a fresh attempt count; the copy is no longer plaintext
+    pub fn replace_outbox_payload(
+        &self,
+        message_id: &str,
+        recipient_peer_id: &str,
+        payload: &[u8],
+    ) -> Result<bool> {
+        let updated = self.conn().execute(
+            r#"
+            UPDATE outbox
+            SET payload = ?3, attempts = 0, next_attempt_at = ?4, last_error = NULL, plaintext = 0
+            WHERE message_id = ?1 AND recipient_peer_id = ?2
+            "#,
+            params![
+                message_id,
+                recipient_peer_id,
+                payload,
+                chrono::Utc::now().timestamp_millis()
+            ],
+        )?;
+
+        Ok(updated > 0)
+    }
+
+    /// Remove a message from the outbox (delivered, handed off or given up)
+    pub fn remove_outbox_entry(&self, message_id: &str, recipient_peer_id: &str) -> Result<bool> {
+        let removed = self.conn().execute(
+            "DELETE FROM outbox WHERE message_id = ?1 AND recipient_peer_id = ?2",
+            [message_id, recipient_peer_id],
+        )?;
+
+        Ok(removed > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::schema::init_schema;
+
+    #[test]
+    fn test_outbox_lifecycle() {
+        let db = Database::in_memory().unwrap();
+        init_schema(&db).unwrap();
+
+        db.enqueue_outbox("msg-1", "peer-a", b"payload-1").unwrap();
+        db.enqueue_outbox("msg-2", "peer-b", b"payload-2").unwrap();
+
+        let now = chrono::Utc::now().timestamp_millis();
+        let due = db.due_outbox_entries(now).unwrap();
+        assert_eq!(due.len(), 2);
+        assert_eq!(due[0].message_id, "msg-1");
+        assert_eq!(due[0].payload, b"payload-1");
+        assert_eq!(due[0].attempts, 0);
+
+        // A rescheduled entry is not due until its next attempt
+        db.reschedule_outbox("msg-1", "peer-a", 1, now + 60_000, Some("peer offline"))
+            .unwrap();
+        let due = db.due_outbox_entries(now).unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].message_id, "msg-2");
+
+        let entry = db.get_outbox_entry("msg-1", "peer-a").unwrap().unwrap();
+        assert_eq!(entry.attempts, 1);
+        assert_eq!(entry.last_error.as_deref(), Some("peer offline"));
+        assert_eq!(db.due_outbox_entries(now + 60_000).unwrap().len(), 2);
+
+        // A replaced copy is due again, attempts start over
+        assert!(db.replace_outbox_payload("msg-1", "peer-a", b"payload-3").unwrap());
+        let entry = db.get_outbox_entry("msg-1", "peer-a").unwrap().unwrap();
+        assert_eq!(entry.payload, b"payload-3");
+        assert_eq!(entry.attempts, 0);
+        assert!(entry.last_error.is_none());
+        assert!(entry.next_attempt_at <= chrono::Utc::now().timestamp_millis());
+
+        assert!(db.remove_outbox_entry("msg-1", "peer-a").unwrap());
+        assert!(!db.remove_outbox_entry("msg-1", "peer-a").unwrap());
+        assert!(db.get_outbox_entry("msg-1", "peer-a").unwrap().is_none());
+        assert_eq!(db.list_outbox().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_one_entry_per_device() {
+        let db = Database::in_memory().unwrap();
+        init_schema(&db).unwrap();
+
+        db.enqueue_outbox("msg-1", "phone", b"for-phone").unwrap();
+        db.enqueue_outbox("msg-1", "laptop", b"for-laptop").unwrap();
+        assert!(db.enqueue_outbox("msg-1", "phone", b"again").is_err());
+
+        let entry = db.get_outbox_entry("msg-1", "laptop").unwrap().unwrap();
+        assert_eq!(entry.payload, b"for-laptop");
+
+        assert_eq!(db.outbox_recipients("msg-1").unwrap(), vec!["phone", "laptop"]);
+        assert!(db.remove_outbox_entry("msg-1", "phone").unwrap());
+        assert_eq!(db.outbox_recipients("msg-1").unwrap(), vec!["laptop"]);
+        assert!(db.remove_outbox_entry("msg-1", "laptop").unwrap());
+        assert!(db.outbox_recipients("msg-1").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_plaintext_flag_cleared_on_reencryption() {
+        let db = Database::in_memory().unwrap();
+        init_schema(&db).unwrap();
+
+        db.enqueue_plaintext_outbox("msg-1", "peer-a", b"clear").unwrap();
+        db.enqueue_outbox("msg-2", "peer-a", b"sealed").unwrap();
+        assert!(db.get_outbox_entry("msg-1", "peer-a").unwrap().unwrap().plaintext);
+        assert!(!db.get_outbox_entry("msg-2", "peer-a").unwrap().unwrap().plaintext);
+
+        assert!(db.replace_outbox_payload("msg-1", "peer-a", b"sealed").unwrap());
+        assert!(!db.get_outbox_entry("msg-1", "peer-a").unwrap().unwrap().plaintext);
+    }
+}
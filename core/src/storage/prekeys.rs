@@ -0,0 +1,56 @@
+//! Prekey Pool Storage
+//!
+//! Persists our prekey pool as a single row. The blob is encrypted by the
+//! caller (`identity::PreKeyPoolStore`) before it reaches this table.
+
+use rusqlite::OptionalExtension;
+
+use super::{Database, Result};
+
+impl Database {
+    /// Insert or replace the prekey pool
+    pub fn save_prekey_pool(&self, pool_data: &[u8]) -> Result<()> {
+        self.conn().execute(
+            r#"
+            INSERT INTO prekey_pool (id, pool_data)
+            VALUES (1, ?1)
+            ON CONFLICT(id) DO UPDATE SET
+                pool_data = excluded.pool_data,
+                updated_at = unixepoch()
+            "#,
+            [pool_data],
+        )?;
+
+        Ok(())
+    }
+
+    /// Get the stored prekey pool, if any
+    pub fn load_prekey_pool(&self) -> Result<Option<Vec<u8>>> {
+        let pool_data = self
+            .conn()
+            .query_row("SELECT pool_data FROM prekey_pool WHERE id = 1", [], |row| {
+                row.get(0)
+            })
+            .optional()?;
+
+        Ok(pool_data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::schema::init_schema;
+
+    #[test]
+    fn test_save_and_load_prekey_pool() {
+        let db = Database::in_memory().unwrap();
+        init_schema(&db).unwrap();
+
+        assert_eq!(db.load_prekey_pool().unwrap(), None);
+
+        db.save_prekey_pool(b"old").unwrap();
+        db.save_prekey_pool(b"new").unwrap();
+        assert_eq!(db.load_prekey_pool().unwrap(), Some(b"new".to_vec()));
+    }
+}
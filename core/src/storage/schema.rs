@@ -5,7 +5,7 @@
 use super::{Database, Result};
 
 /// Current schema version
-pub const SCHEMA_VERSION: i32 = 3;
+pub const SCHEMA_VERSION: i32 = 13;
 
 /// Initialize database schema (version 1)
 pub fn init_schema(db: &Database) -> Result<()> {
@@ -178,6 +178,96 @@ pub fn init_schema(db: &Database) -> Result<()> {
 
         CREATE INDEX IF NOT EXISTS idx_reactions_message ON message_reactions(message_id);
         CREATE INDEX IF NOT EXISTS idx_reactions_peer ON message_reactions(peer_id);
+
+        -- E2E sessions table: Double Ratchet state, encrypted with the identity storage key
+        CREATE TABLE IF NOT EXISTS sessions (
+            peer_id TEXT PRIMARY KEY,
+            session_data BLOB NOT NULL,
+            created_at INTEGER NOT NULL DEFAULT (unixepoch()),
+            updated_at INTEGER NOT NULL DEFAULT (unixepoch())
+        );
+
+        -- Group sender keys (ours and other members'), encrypted with the identity storage key
+        CREATE TABLE IF NOT EXISTS sender_keys (
+            group_id TEXT NOT NULL,
+            sender_peer_id TEXT NOT NULL,
+            key_data BLOB NOT NULL,
+            updated_at INTEGER NOT NULL DEFAULT (unixepoch()),
+            PRIMARY KEY (group_id, sender_peer_id)
+        );
+
+        -- Per-file media keys, encrypted with the identity storage key
+        CREATE TABLE IF NOT EXISTS media_keys (
+            media_hash TEXT PRIMARY KEY,
+            key_data BLOB NOT NULL,
+            digest BLOB NOT NULL,
+            created_at INTEGER NOT NULL DEFAULT (unixepoch())
+        );
+
+        -- Progress of incoming media downloads (contiguous bytes on disk)
+        CREATE TABLE IF NOT EXISTS media_transfers (
+            media_hash TEXT PRIMARY KEY,
+            received_bytes INTEGER NOT NULL DEFAULT 0,
+            updated_at INTEGER NOT NULL DEFAULT (unixepoch())
+        );
+
+        -- Outgoing messages waiting for an ACK, one row per recipient device
+        -- (payload encrypted with the storage key)
+        CREATE TABLE IF NOT EXISTS outbox (
+            message_id TEXT NOT NULL,
+            recipient_peer_id TEXT NOT NULL,
+            payload BLOB NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            next_attempt_at INTEGER NOT NULL,
+            last_error TEXT,
+            created_at INTEGER NOT NULL DEFAULT (unixepoch()),
+            plaintext INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (message_id, recipient_peer_id)
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_outbox_next_attempt ON outbox(next_attempt_at);
+
+        -- Devices linked to accounts (ours and our contacts')
+        CREATE TABLE IF NOT EXISTS devices (
+            device_peer_id TEXT PRIMARY KEY,
+            account_peer_id TEXT NOT NULL,
+            device_name TEXT NOT NULL,
+            certificate BLOB NOT NULL,
+            prekey_bundle_json TEXT,
+            linked_at INTEGER NOT NULL DEFAULT (unixepoch())
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_devices_account ON devices(account_peer_id);
+
+        -- Replicated state changes of our account's devices (see sync::crdt)
+        CREATE TABLE IF NOT EXISTS sync_ops (
+            origin_device TEXT NOT NULL,
+            seq INTEGER NOT NULL,
+            lamport INTEGER NOT NULL,
+            op_key TEXT NOT NULL,
+            op BLOB NOT NULL,
+            received_at INTEGER NOT NULL DEFAULT (unixepoch()),
+            PRIMARY KEY (origin_device, seq)
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_sync_ops_key ON sync_ops(op_key, lamport DESC);
+
+        -- Our prekey pool (single row), encrypted with the identity storage key
+        CREATE TABLE IF NOT EXISTS prekey_pool (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            pool_data BLOB NOT NULL,
+            updated_at INTEGER NOT NULL DEFAULT (unixepoch())
+        );
+
+        -- Admin actions applied to each group (replays are rejected)
+        CREATE TABLE IF NOT EXISTS group_admin_actions (
+            message_id TEXT PRIMARY KEY,
+            group_id TEXT NOT NULL,
+            sent_at INTEGER NOT NULL,
+            applied_at INTEGER NOT NULL DEFAULT (unixepoch())
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_group_admin_actions_group ON group_admin_actions(group_id, sent_at);
         "#,
     )?;
 
@@ -225,6 +315,14 @@ pub fn drop_all_tables(db: &Database) -> Result<()> {
         DROP TABLE IF EXISTS messages_fts;
         DROP TABLE IF EXISTS settings;
         DROP TABLE IF EXISTS crypto_sessions;
+        DROP TABLE IF EXISTS sessions;
+        DROP TABLE IF EXISTS sender_keys;
+        DROP TABLE IF EXISTS media_keys;
+        DROP TABLE IF EXISTS media_transfers;
+        DROP TABLE IF EXISTS outbox;
+        DROP TABLE IF EXISTS devices;
+        DROP TABLE IF EXISTS sync_ops;
+        DROP TABLE IF EXISTS prekey_pool;
         DROP TABLE IF EXISTS media;
         DROP TABLE IF EXISTS group_members;
         DROP TABLE IF EXISTS groups;
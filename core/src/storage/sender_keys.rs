@@ -0,0 +1,112 @@
+//! Group Sender Keys Storage
+//!
+//! Persists serialized group sender keys. Key blobs are encrypted by the
+//! caller (`group::sender_keys::SenderKeyStore`) before they reach this table.
+
+use super::{Database, Result};
+
+/// Stored sender key record (encrypted blob)
+#[derive(Debug, Clone)]
+pub struct StoredSenderKey {
+    pub group_id: String,
+    pub sender_peer_id: String,
+    pub key_data: Vec<u8>,
+    pub updated_at: i64,
+}
+
+impl Database {
+    /// Insert or replace the sender key of a group member
+    pub fn save_sender_key(&self, group_id: &str, sender_peer_id: &str, key_data: &[u8]) -> Result<()> {
+        self.conn().execute(
+            r#"
+            INSERT INTO sender_keys (group_id, sender_peer_id, key_data)
+            VALUES (?1, ?2, ?3)
+            ON CONFLICT(group_id, sender_peer_id) DO UPDATE SET
+                key_data = excluded.key_data,
+                updated_at = unixepoch()
+            "#,
+            rusqlite::params![group_id, sender_peer_id, key_data],
+        )?;
+
+        Ok(())
+    }
+
+    /// Get all stored sender keys
+    pub fn list_sender_keys(&self) -> Result<Vec<StoredSenderKey>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT group_id, sender_peer_id, key_data, updated_at FROM sender_keys",
+        )?;
+
+        let keys = stmt
+            .query_map([], |row| {
+                Ok(StoredSenderKey {
+                    group_id: row.get(0)?,
+                    sender_peer_id: row.get(1)?,
+                    key_data: row.get(2)?,
+                    updated_at: row.get(3)?,
+                })
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        Ok(keys)
+    }
+
+    /// Delete the sender key of a group member
+    pub fn delete_sender_key(&self, group_id: &str, sender_peer_id: &str) -> Result<()> {
+        self.conn().execute(
+            "DELETE FROM sender_keys WHERE group_id = ?1 AND sender_peer_id = ?2",
+            [group_id, sender_peer_id],
+        )?;
+
+        Ok(())
+    }
+
+    /// Delete all sender keys of a group
+    pub fn delete_group_sender_keys(&self, group_id: &str) -> Result<()> {
+        self.conn()
+            .execute("DELETE FROM sender_keys WHERE group_id = ?1", [group_id])?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::schema::init_schema;
+
+    #[test]
+    fn test_save_and_list_sender_keys() {
+        let db = Database::in_memory().unwrap();
+        init_schema(&db).unwrap();
+
+        db.save_sender_key("group-1", "peer-1", b"key-1").unwrap();
+        db.save_sender_key("group-1", "peer-2", b"key-2").unwrap();
+        db.save_sender_key("group-1", "peer-1", b"key-1b").unwrap();
+
+        let mut keys = db.list_sender_keys().unwrap();
+        keys.sort_by(|a, b| a.sender_peer_id.cmp(&b.sender_peer_id));
+
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].key_data, b"key-1b");
+    }
+
+    #[test]
+    fn test_delete_sender_keys() {
+        let db = Database::in_memory().unwrap();
+        init_schema(&db).unwrap();
+
+        db.save_sender_key("group-1", "peer-1", b"key").unwrap();
+        db.save_sender_key("group-1", "peer-2", b"key").unwrap();
+        db.save_sender_key("group-2", "peer-1", b"key").unwrap();
+
+        db.delete_sender_key("group-1", "peer-1").unwrap();
+        assert_eq!(db.list_sender_keys().unwrap().len(), 2);
+
+        db.delete_group_sender_keys("group-1").unwrap();
+        let keys = db.list_sender_keys().unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].group_id, "group-2");
+    }
+}
@@ -0,0 +1,108 @@
+//! E2E Sessions Storage
+//!
+//! Persists serialized Double Ratchet sessions. Session blobs are encrypted by
+//! the caller (`crypto::session::SessionManager`) before they reach this table.
+
+use super::{Database, Result};
+
+/// Stored session record (encrypted blob)
+#[derive(Debug, Clone)]
+pub struct StoredSession {
+    pub peer_id: String,
+    pub session_data: Vec<u8>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+impl Database {
+    /// Insert or replace the session for a peer
+    pub fn save_session(&self, peer_id: &str, session_data: &[u8]) -> Result<()> {
+        self.conn().execute(
+            r#"
+            INSERT INTO sessions (peer_id, session_data)
+            VALUES (?1, ?2)
+            ON CONFLICT(peer_id) DO UPDATE SET
+                session_data = excluded.session_data,
+                updated_at = unixepoch()
+            "#,
+            rusqlite::params![peer_id, session_data],
+        )?;
+
+        Ok(())
+    }
+
+    /// Get all stored sessions
+    pub fn list_sessions(&self) -> Result<Vec<StoredSession>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT peer_id, session_data, created_at, updated_at FROM sessions",
+        )?;
+
+        let sessions = stmt
+            .query_map([], |row| {
+                Ok(StoredSession {
+                    peer_id: row.get(0)?,
+                    session_data: row.get(1)?,
+                    created_at: row.get(2)?,
+                    updated_at: row.get(3)?,
+                })
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        Ok(sessions)
+    }
+
+    /// Delete the session for a peer
+    pub fn delete_session(&self, peer_id: &str) -> Result<()> {
+        self.conn()
+            .execute("DELETE FROM sessions WHERE peer_id = ?1", [peer_id])?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::schema::init_schema;
+
+    #[test]
+    fn test_save_and_list_sessions() {
+        let db = Database::in_memory().unwrap();
+        init_schema(&db).unwrap();
+
+        db.save_session("peer_1", b"blob-1").unwrap();
+        db.save_session("peer_2", b"blob-2").unwrap();
+
+        let mut sessions = db.list_sessions().unwrap();
+        sessions.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].peer_id, "peer_1");
+        assert_eq!(sessions[0].session_data, b"blob-1");
+    }
+
+    #[test]
+    fn test_save_session_replaces_existing() {
+        let db = Database::in_memory().unwrap();
+        init_schema(&db).unwrap();
+
+        db.save_session("peer_1", b"old").unwrap();
+        db.save_session("peer_1", b"new").unwrap();
+
+        let sessions = db.list_sessions().unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].session_data, b"new");
+    }
+
+    #[test]
+    fn test_delete_session() {
+        let db = Database::in_memory().unwrap();
+        init_schema(&db).unwrap();
+
+        db.save_session("peer_1", b"blob").unwrap();
+        db.delete_session("peer_1").unwrap();
+
+        assert!(db.list_sessions().unwrap().is_empty());
+    }
+}
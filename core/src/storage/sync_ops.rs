@@ -0,0 +1,179 @@
+//! Sync Operations Storage
+//!
+//! Log of the state changes replicated between the devices of our account.
+//! `op` is the encoded `SyncOp` protobuf; `op_key` names the register it
+//! writes, so the winning change of a register is a single indexed lookup
+//! (`sync::crdt` decides what the keys are and applies the changes).
+
+use rusqlite::{params, OptionalExtension};
+
+use super::{Database, Result};
+
+/// Stored sync operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredSyncOp {
+    pub origin_device: String,
+    pub seq: u64,
+    pub lamport: u64,
+    pub op_key: String,
+    /// Encoded `SyncOp`
+    pub op: Vec<u8>,
+}
+
+impl Database {
+    /// Store an operation; false if it was already stored
+    pub fn insert_sync_op(&self, op: &StoredSyncOp) -> Result<bool> {
+        let inserted = self.conn().execute(
+            r#"
+            INSERT OR IGNORE INTO sync_ops (origin_device, seq, lamport, op_key, op)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            "#,
+            params![
+                op.origin_device,
+                op.seq as i64,
+                op.lamport as i64,
+                op.op_key,
+                op.op
+            ],
+        )?;
+
+        Ok(inserted > 0)
+    }
+
+    /// Winning (greatest `(lamport, origin_device)`) operation on a register
+    pub fn latest_sync_op(&self, op_key: &str) -> Result<Option<(u64, String)>> {
+        let latest = self
+            .conn()
+            .query_row(
+                r#"
+                SELECT lamport, origin_device FROM sync_ops
+                WHERE op_key = ?1
+                ORDER BY lamport DESC, origin_device DESC
+                LIMIT 1
+                "#,
+                [op_key],
+                |row| Ok((row.get::<_, i64>(0)? as u64, row.get(1)?)),
+            )
+            .optional()?;
+
+        Ok(latest)
+    }
+
+    /// Winning operation of every register whose key starts with `prefix`
+    pub fn winning_sync_ops(&self, prefix: &str) -> Result<Vec<StoredSyncOp>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT origin_device, seq, lamport, op_key, op FROM sync_ops o
+            WHERE substr(op_key, 1, length(?1)) = ?1
+              AND NOT EXISTS (
+                  SELECT 1 FROM sync_ops w
+                  WHERE w.op_key = o.op_key
+                    AND (w.lamport > o.lamport
+                         OR (w.lamport = o.lamport AND w.origin_device > o.origin_device))
+              )
+            "#,
+        )?;
+
+        let ops = stmt
+            .query_map([prefix], Self::sync_op_from_row)?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        Ok(ops)
+    }
+
+    /// Highest sequence number stored for a device (0 if none)
+    pub fn max_sync_seq(&self, origin_device: &str) -> Result<u64> {
+        let seq: i64 = self.conn().query_row(
+            "SELECT COALESCE(MAX(seq), 0) FROM sync_ops WHERE origin_device = ?1",
+            [origin_device],
+            |row| row.get(0),
+        )?;
+
+        Ok(seq as u64)
+    }
+
+    /// Highest Lamport timestamp stored (0 if none)
+    pub fn max_sync_lamport(&self) -> Result<u64> {
+        let lamport: i64 = self.conn().query_row(
+            "SELECT COALESCE(MAX(lamport), 0) FROM sync_ops",
+            [],
+            |row| row.get(0),
+        )?;
+
+        Ok(lamport as u64)
+    }
+
+    /// All stored operations, by origin and sequence number
+    pub fn list_sync_ops(&self) -> Result<Vec<StoredSyncOp>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT origin_device, seq, lamport, op_key, op FROM sync_ops
+            ORDER BY origin_device, seq
+            "#,
+        )?;
+
+        let ops = stmt
+            .query_map([], Self::sync_op_from_row)?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        Ok(ops)
+    }
+
+    /// Helper: Parse sync operation from row
+    fn sync_op_from_row(row: &rusqlite::Row) -> rusqlite::Result<StoredSyncOp> {
+        Ok(StoredSyncOp {
+            origin_device: row.get(0)?,
+            seq: row.get::<_, i64>(1)? as u64,
+            lamport: row.get::<_, i64>(2)? as u64,
+            op_key: row.get(3)?,
+            op: row.get(4)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::schema::init_schema;
+
+    fn op(origin: &str, seq: u64, lamport: u64, key: &str) -> StoredSyncOp {
+        StoredSyncOp {
+            origin_device: origin.to_string(),
+            seq,
+            lamport,
+            op_key: key.to_string(),
+            op: vec![seq as u8],
+        }
+    }
+
+    #[test]
+    fn test_sync_op_log() {
+        let db = Database::in_memory().unwrap();
+        init_schema(&db).unwrap();
+        assert_eq!(db.max_sync_lamport().unwrap(), 0);
+        assert_eq!(db.latest_sync_op("muted:c1").unwrap(), None);
+
+        assert!(db.insert_sync_op(&op("phone", 1, 1, "muted:c1")).unwrap());
+        assert!(db.insert_sync_op(&op("laptop", 1, 3, "muted:c1")).unwrap());
+        assert!(db.insert_sync_op(&op("phone", 2, 3, "muted:c1")).unwrap());
+        // Replays are ignored
+        assert!(!db.insert_sync_op(&op("phone", 1, 1, "muted:c1")).unwrap());
+
+        // Equal Lamport timestamps are ordered by origin
+        assert_eq!(
+            db.latest_sync_op("muted:c1").unwrap(),
+            Some((3, "phone".to_string()))
+        );
+        assert_eq!(db.max_sync_seq("phone").unwrap(), 2);
+        assert_eq!(db.max_sync_seq("tablet").unwrap(), 0);
+        assert_eq!(db.max_sync_lamport().unwrap(), 3);
+        assert_eq!(db.list_sync_ops().unwrap().len(), 3);
+
+        assert!(db.insert_sync_op(&op("laptop", 2, 2, "muted:c2")).unwrap());
+        let mut winners = db.winning_sync_ops("muted:").unwrap();
+        winners.sort_by(|a, b| a.op_key.cmp(&b.op_key));
+        assert_eq!(winners, vec![op("phone", 2, 3, "muted:c1"), op("laptop", 2, 2, "muted:c2")]);
+    }
+}
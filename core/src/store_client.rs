@@ -0,0 +1,266 @@
+//! Store-and-Forward Client
+//!
+//! HTTP client for the Message Store (`server/store`), which keeps messages
+//! for peers that are offline. Payloads are the encoded protocol `Message`s,
+//! whose content is already end-to-end encrypted by the sender.
+//!
+//! Every request is signed with the peer's Ed25519 key: the sender signs what
+//! it stores, the recipient signs its retrieve and delete requests. The server
+//! takes the public key from the peer id and accepts each signature once,
+//! within a few minutes of its timestamp. A fresh nonce goes into every
+//! signature, so repeating a request within a second is not a replay.
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine as _};
+use libp2p::{identity::Keypair, PeerId};
+use prost::Message as _;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::api::{OfflineMessage, OfflineStore};
+use crate::protocol::{Message, MessageType};
+use crate::utils::error::{MePassaError, Result};
+
+/// Messages fetched per request
+const FETCH_LIMIT: i32 = 100;
+
+/// Store message request
+#[derive(Debug, Serialize)]
+struct StoreMessageRequest {
+    recipient_peer_id: String,
+    sender_peer_id: String,
+    encrypted_payload: String,
+    message_type: Option<String>,
+    message_id: String,
+    timestamp: i64,
+    nonce: String,
+    signature: String,
+}
+
+/// Pending message as returned by the server
+#[derive(Debug, Deserialize)]
+struct OfflineMessageDto {
+    sender_peer_id: String,
+    encrypted_payload: String,
+    message_id: String,
+}
+
+/// Retrieve messages response
+#[derive(Debug, Deserialize)]
+struct RetrieveMessagesResponse {
+    messages: Vec<OfflineMessageDto>,
+}
+
+/// Delete messages request
+#[derive(Debug, Serialize)]
+struct DeleteMessagesRequest {
+    peer_id: String,
+    message_ids: Vec<String>,
+    timestamp: i64,
+    nonce: String,
+    signature: String,
+}
+
+/// Fields that authenticate a request
+struct SignedRequest {
+    timestamp: i64,
+    nonce: String,
+    signature: String,
+}
+
+/// Message Store client
+#[derive(Clone)]
+pub struct StoreForwardClient {
+    base_url: String,
+    client: reqwest::Client,
+    keypair: Keypair,
+}
+
+impl StoreForwardClient {
+    /// Create a new Message Store client signing requests with `keypair`
+    pub fn new(base_url: impl Into<String>, keypair: Keypair) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()
+            .map_err(|e| MePassaError::Network(format!("Failed to create HTTP client: {}", e)))?;
+
+        Ok(Self {
+            base_url: base_url.into(),
+            client,
+            keypair,
+        })
+    }
+
+    /// Our peer id, which the server checks signatures against
+    pub fn peer_id(&self) -> PeerId {
+        self.keypair.public().to_peer_id()
+    }
+
+    /// Sign a request; returns the timestamp (seconds), nonce and base64 signature
+    fn sign(&self, challenge: impl FnOnce(i64, &str) -> String) -> Result<SignedRequest> {
+        let timestamp = chrono::Utc::now().timestamp();
+        let nonce = uuid::Uuid::new_v4().simple().to_string();
+        let signature = self
+            .keypair
+            .sign(challenge(timestamp, &nonce).as_bytes())
+            .map_err(|e| MePassaError::Crypto(format!("Failed to sign store request: {}", e)))?;
+        Ok(SignedRequest {
+            timestamp,
+            nonce,
+            signature: general_purpose::STANDARD.encode(signature),
+        })
+    }
+
+    fn url(&self) -> String {
+        format!("{}/api/store", self.base_url)
+    }
+
+    async fn check(response: reqwest::Response) -> Result<reqwest::Response> {
+        if response.status().is_success() {
+            return Ok(response);
+        }
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        Err(MePassaError::Network(format!("Message store returned {}: {}", status, body)))
+    }
+}
+
+fn http_error(e: reqwest::Error) -> MePassaError {
+    MePassaError::Network(format!("Message store request failed: {}", e))
+}
+
+#[async_trait]
+impl OfflineStore for StoreForwardClient {
+    /// Upload a message for `recipient`
+    ///
+    /// POST /api/store
+    async fn store(&self, recipient: &PeerId, message: &Message) -> Result<()> {
+        let message_type = MessageType::try_from(message.r#type)
+            .map(|t| t.as_str_name().to_lowercase())
+            .ok();
+        let sender_peer_id = self.peer_id().to_string();
+        let recipient_peer_id = recipient.to_string();
+        let encrypted_payload = general_purpose::STANDARD.encode(message.encode_to_vec());
+        // Format: "store:{sender}:{recipient}:{message_id}:{timestamp}:{nonce}:{encrypted_payload}"
+        let signed = self.sign(|timestamp, nonce| {
+            format!(
+                "store:{}:{}:{}:{}:{}:{}",
+                sender_peer_id, recipient_peer_id, message.id, timestamp, nonce, encrypted_payload
+            )
+        })?;
+        let request = StoreMessageRequest {
+            recipient_peer_id,
+            sender_peer_id,
+            encrypted_payload,
+            message_type,
+            message_id: message.id.clone(),
+            timestamp: signed.timestamp,
+            nonce: signed.nonce,
+            signature: signed.signature,
+        };
+
+        let response = self
+            .client
+            .post(self.url())
+            .json(&request)
+            .send()
+            .await
+            .map_err(http_error)?;
+        Self::check(response).await?;
+        Ok(())
+    }
+
+    /// Pending messages for `recipient` (which must be us)
+    ///
+    /// GET /api/store?peer_id={peer_id}&limit={limit}&timestamp={timestamp}&nonce={nonce}&signature={signature}
+    async fn fetch(&self, recipient: &PeerId) -> Result<Vec<OfflineMessage>> {
+        let peer_id = recipient.to_string();
+        // Format: "retrieve:{peer_id}:{timestamp}:{nonce}"
+        let signed = self.sign(|timestamp, nonce| {
+            format!("retrieve:{}:{}:{}", peer_id, timestamp, nonce)
+        })?;
+
+        let response = self
+            .client
+            .get(self.url())
+            .query(&[
+                ("peer_id", peer_id),
+                ("limit", FETCH_LIMIT.to_string()),
+                ("timestamp", signed.timestamp.to_string()),
+                ("nonce", signed.nonce),
+                ("signature", signed.signature),
+            ])
+            .send()
+            .await
+            .map_err(http_error)?;
+        let body: RetrieveMessagesResponse = Self::check(response)
+            .await?
+            .json()
+            .await
+            .map_err(http_error)?;
+
+        body.messages
+            .into_iter()
+            .map(|dto| {
+                let payload = general_purpose::STANDARD
+                    .decode(&dto.encrypted_payload)
+                    .map_err(|e| {
+                        MePassaError::Protocol(format!(
+                            "Invalid payload for stored message {}: {}",
+                            dto.message_id, e
+                        ))
+                    })?;
+                Ok(OfflineMessage {
+                    message_id: dto.message_id,
+                    sender_peer_id: dto.sender_peer_id,
+                    payload,
+                })
+            })
+            .collect()
+    }
+
+    /// Mark messages as delivered so the server drops them
+    ///
+    /// DELETE /api/store
+    async fn acknowledge(&self, message_ids: &[String]) -> Result<()> {
+        if message_ids.is_empty() {
+            return Ok(());
+        }
+
+        let peer_id = self.peer_id().to_string();
+        // Format: "delete:{peer_id}:{timestamp}:{nonce}:{message_id},{message_id},..."
+        let signed = self.sign(|timestamp, nonce| {
+            format!("delete:{}:{}:{}:{}", peer_id, timestamp, nonce, message_ids.join(","))
+        })?;
+        let request = DeleteMessagesRequest {
+            peer_id,
+            message_ids: message_ids.to_vec(),
+            timestamp: signed.timestamp,
+            nonce: signed.nonce,
+            signature: signed.signature,
+        };
+        let response = self
+            .client
+            .delete(self.url())
+            .json(&request)
+            .send()
+            .await
+            .map_err(http_error)?;
+        Self::check(response).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_creation() {
+        let keypair = Keypair::generate_ed25519();
+        let peer_id = keypair.public().to_peer_id();
+        let client = StoreForwardClient::new("http://localhost:8080", keypair).unwrap();
+        assert_eq!(client.url(), "http://localhost:8080/api/store");
+        assert_eq!(client.peer_id(), peer_id);
+    }
+}
@@ -0,0 +1,537 @@
+//! Replicated Account State
+//!
+//! Every change a device makes to replicated state (read markers, mute and
+//! archive flags, reactions, deletions) is an operation in a log, numbered
+//! per origin device (`seq`) and stamped with a Lamport clock. Each piece of
+//! state is a last-writer-wins register named by the operation's key: the
+//! operation with the greatest `(lamport, origin_device)` wins, so devices
+//! that have seen the same operations agree, whatever order they came in.
+
+use std::collections::HashMap;
+
+use prost::Message as _;
+
+use super::device::is_own_device;
+use super::{Result, SyncError};
+use crate::protocol::pb::{self, sync_op};
+use crate::storage::{Database, NewReaction, StoredSyncOp};
+
+/// A change to replicated state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// Everything in the conversation up to `read_at` was read
+    ConversationRead { conversation_id: String, read_at: i64 },
+    ConversationMuted { conversation_id: String, muted: bool },
+    ConversationArchived { conversation_id: String, archived: bool },
+    /// `peer_id` reacted (or took back its reaction) with `emoji`
+    Reaction {
+        message_id: String,
+        peer_id: String,
+        emoji: String,
+        present: bool,
+    },
+    MessageDeleted { message_id: String },
+}
+
+impl Change {
+    /// Register the change writes
+    ///
+    /// Registers of a message start with `message_key_prefix`.
+    pub fn key(&self) -> String {
+        match self {
+            Change::ConversationRead { conversation_id, .. } => {
+                format!("conversation:{}:read", conversation_id)
+            }
+            Change::ConversationMuted { conversation_id, .. } => {
+                format!("conversation:{}:muted", conversation_id)
+            }
+            Change::ConversationArchived { conversation_id, .. } => {
+                format!("conversation:{}:archived", conversation_id)
+            }
+            Change::Reaction {
+                message_id,
+                peer_id,
+                emoji,
+                ..
+            } => format!("{}reaction:{}:{}", message_key_prefix(message_id), peer_id, emoji),
+            Change::MessageDeleted { message_id } => {
+                format!("{}deleted", message_key_prefix(message_id))
+            }
+        }
+    }
+
+    fn to_proto(&self) -> sync_op::Change {
+        match self.clone() {
+            Change::ConversationRead {
+                conversation_id,
+                read_at,
+            } => sync_op::Change::ConversationRead(pb::ConversationReadChange {
+                conversation_id,
+                read_at,
+            }),
+            Change::ConversationMuted {
+                conversation_id,
+                muted,
+            } => sync_op::Change::ConversationMuted(pb::ConversationFlagChange {
+                conversation_id,
+                value: muted,
+            }),
+            Change::ConversationArchived {
+                conversation_id,
+                archived,
+            } => sync_op::Change::ConversationArchived(pb::ConversationFlagChange {
+                conversation_id,
+                value: archived,
+            }),
+            Change::Reaction {
+                message_id,
+                peer_id,
+                emoji,
+                present,
+            } => sync_op::Change::Reaction(pb::ReactionChange {
+                message_id,
+                peer_id,
+                emoji,
+                present,
+            }),
+            Change::MessageDeleted { message_id } => {
+                sync_op::Change::MessageDeleted(pb::MessageDeletedChange { message_id })
+            }
+        }
+    }
+
+    fn from_proto(change: sync_op::Change) -> Self {
+        match change {
+            sync_op::Change::ConversationRead(change) => Change::ConversationRead {
+                conversation_id: change.conversation_id,
+                read_at: change.read_at,
+            },
+            sync_op::Change::ConversationMuted(change) => Change::ConversationMuted {
+                conversation_id: change.conversation_id,
+                muted: change.value,
+            },
+            sync_op::Change::ConversationArchived(change) => Change::ConversationArchived {
+                conversation_id: change.conversation_id,
+                archived: change.value,
+            },
+            sync_op::Change::Reaction(change) => Change::Reaction {
+                message_id: change.message_id,
+                peer_id: change.peer_id,
+                emoji: change.emoji,
+                present: change.present,
+            },
+            sync_op::Change::MessageDeleted(change) => Change::MessageDeleted {
+                message_id: change.message_id,
+            },
+        }
+    }
+}
+
+/// Prefix of the keys of every register of a message
+pub fn message_key_prefix(message_id: &str) -> String {
+    format!("message:{}:", message_id)
+}
+
+/// A change, as made by one device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncOp {
+    /// Peer ID of the device that made the change
+    pub origin_device: String,
+    /// Position in the origin device's log (from 1)
+    pub seq: u64,
+    pub lamport: u64,
+    pub change: Change,
+}
+
+impl SyncOp {
+    pub fn to_proto(&self) -> pb::SyncOp {
+        pb::SyncOp {
+            origin_device_peer_id: self.origin_device.clone(),
+            seq: self.seq,
+            lamport: self.lamport,
+            change: Some(self.change.to_proto()),
+        }
+    }
+
+    pub fn from_proto(op: pb::SyncOp) -> Result<Self> {
+        if op.origin_device_peer_id.is_empty() || op.seq == 0 {
+            return Err(SyncError::InvalidOperation("missing origin".to_string()));
+        }
+        let change = op
+            .change
+            .ok_or_else(|| SyncError::InvalidOperation("missing change".to_string()))?;
+        Ok(Self {
+            origin_device: op.origin_device_peer_id,
+            seq: op.seq,
+            lamport: op.lamport,
+            change: Change::from_proto(change),
+        })
+    }
+
+    fn to_stored(&self) -> StoredSyncOp {
+        StoredSyncOp {
+            origin_device: self.origin_device.clone(),
+            seq: self.seq,
+            lamport: self.lamport,
+            op_key: self.change.key(),
+            op: self.to_proto().encode_to_vec(),
+        }
+    }
+
+    fn from_stored(stored: &StoredSyncOp) -> Result<Self> {
+        let op = pb::SyncOp::decode(stored.op.as_slice())
+            .map_err(|e| SyncError::InvalidOperation(e.to_string()))?;
+        Self::from_proto(op)
+    }
+
+    /// Whether this operation wins over the one stamped `(lamport, origin)`
+    fn wins_over(&self, lamport: u64, origin_device: &str) -> bool {
+        (self.lamport, self.origin_device.as_str()) > (lamport, origin_device)
+    }
+}
+
+/// Make a change on this device: log it after everything seen so far and
+/// apply it. The returned operation is for the other devices.
+pub fn record_change(database: &Database, local_device: &str, change: Change) -> Result<SyncOp> {
+    let op = SyncOp {
+        origin_device: local_device.to_string(),
+        seq: database.max_sync_seq(local_device)? + 1,
+        lamport: database.max_sync_lamport()? + 1,
+        change,
+    };
+    database.insert_sync_op(&op.to_stored())?;
+    apply(database, &op.change)?;
+    Ok(op)
+}
+
+/// Merge an operation `from_device` sent
+///
+/// It is applied if it wins its register. Returns false if it was already
+/// known, or wasn't made by `from_device` or another (verified) device of its
+/// account.
+pub fn merge_op(database: &Database, from_device: &str, op: &SyncOp) -> Result<bool> {
+    if !is_own_device(database, from_device, &op.origin_device) {
+        tracing::warn!(
+            "⚠️ Ignoring operation of {} sent by {}: not a device of its account",
+            op.origin_device,
+            from_device
+        );
+        return Ok(false);
+    }
+
+    let winner = database.latest_sync_op(&op.change.key())?;
+    if !database.insert_sync_op(&op.to_stored())? {
+        return Ok(false);
+    }
+    if winner.is_none_or(|(lamport, origin)| op.wins_over(lamport, &origin)) {
+        apply(database, &op.change)?;
+    }
+    Ok(true)
+}
+
+/// Apply the winning state of a message that arrived after its operations
+pub fn reapply_message_state(database: &Database, message_id: &str) -> Result<()> {
+    for stored in database.winning_sync_ops(&message_key_prefix(message_id))? {
+        apply(database, &SyncOp::from_stored(&stored)?.change)?;
+    }
+    Ok(())
+}
+
+/// Write a change to the conversations, messages and reactions tables
+///
+/// Changes to messages not stored (yet) are skipped; the log keeps them for
+/// `reapply_message_state`.
+fn apply(database: &Database, change: &Change) -> Result<()> {
+    match change {
+        Change::ConversationRead {
+            conversation_id,
+            read_at,
+        } => {
+            database.mark_conversation_read(conversation_id, *read_at)?;
+        }
+        Change::ConversationMuted {
+            conversation_id,
+            muted,
+        } => {
+            ensure_conversation(database, conversation_id)?;
+            database.set_conversation_muted(conversation_id, *muted)?;
+        }
+        Change::ConversationArchived {
+            conversation_id,
+            archived,
+        } => {
+            ensure_conversation(database, conversation_id)?;
+            database.set_conversation_archived(conversation_id, *archived)?;
+        }
+        Change::Reaction {
+            message_id,
+            peer_id,
+            emoji,
+            present,
+        } => {
+            if database.get_message(message_id).is_err() {
+                return Ok(());
+            }
+            if *present {
+                database.ensure_contact_exists(peer_id)?;
+                database.add_reaction(&NewReaction {
+                    reaction_id: uuid::Uuid::new_v4().to_string(),
+                    message_id: message_id.clone(),
+                    peer_id: peer_id.clone(),
+                    emoji: emoji.clone(),
+                })?;
+            } else {
+                database.remove_reaction(message_id, peer_id, emoji)?;
+            }
+        }
+        Change::MessageDeleted { message_id } => {
+            database.delete_message(message_id)?;
+        }
+    }
+    Ok(())
+}
+
+/// Create a 1:1 conversation a flag was set on before its first message
+fn ensure_conversation(database: &Database, conversation_id: &str) -> Result<()> {
+    if let Some(peer_id) = conversation_id.strip_prefix("1:1:") {
+        database.get_or_create_conversation(peer_id)?;
+    }
+    Ok(())
+}
+
+/// How far the log of each origin device is known without gaps
+pub fn cursors(database: &Database) -> Result<HashMap<String, u64>> {
+    let mut cursors: HashMap<String, u64> = HashMap::new();
+    for op in database.list_sync_ops()? {
+        let cursor = cursors.entry(op.origin_device).or_default();
+        if op.seq == *cursor + 1 {
+            *cursor = op.seq;
+        }
+    }
+    Ok(cursors)
+}
+
+/// Operations past the given cursors, by origin and sequence number
+pub fn ops_after(database: &Database, cursors: &HashMap<String, u64>) -> Result<Vec<SyncOp>> {
+    database
+        .list_sync_ops()?
+        .iter()
+        .filter(|op| op.seq > cursors.get(&op.origin_device).copied().unwrap_or(0))
+        .map(SyncOp::from_stored)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{init_schema, MessageStatus, NewMessage};
+
+    fn setup_test_db() -> Database {
+        let db = Database::in_memory().unwrap();
+        init_schema(&db).unwrap();
+        let conversation_id = db.get_or_create_conversation("bob").unwrap();
+        db.insert_message(&NewMessage {
+            message_id: "m1".to_string(),
+            conversation_id,
+            sender_peer_id: "bob".to_string(),
+            recipient_peer_id: Some("alice".to_string()),
+            message_type: "text".to_string(),
+            content_encrypted: None,
+            content_plaintext: Some("Hi".to_string()),
+            status: MessageStatus::Delivered,
+            parent_message_id: None,
+        })
+        .unwrap();
+        db
+    }
+
+    fn muted(value: bool) -> Change {
+        Change::ConversationMuted {
+            conversation_id: "1:1:bob".to_string(),
+            muted: value,
+        }
+    }
+
+    fn reaction(present: bool) -> Change {
+        Change::Reaction {
+            message_id: "m1".to_string(),
+            peer_id: "alice".to_string(),
+            emoji: "👍".to_string(),
+            present,
+        }
+    }
+
+    #[test]
+    fn test_concurrent_changes_converge() {
+        let phone = setup_test_db();
+        let laptop = setup_test_db();
+
+        // Both change the same registers without having seen each other
+        let from_phone = vec![
+            record_change(&phone, "phone", muted(true)).unwrap(),
+            record_change(&phone, "phone", reaction(true)).unwrap(),
+        ];
+        let from_laptop = vec![
+            record_change(&laptop, "laptop", muted(false)).unwrap(),
+            record_change(&laptop, "laptop", reaction(false)).unwrap(),
+            record_change(
+                &laptop,
+                "laptop",
+                Change::MessageDeleted {
+                    message_id: "m1".to_string(),
+                },
+            )
+            .unwrap(),
+        ];
+
+        for op in from_laptop.iter().rev() {
+            assert!(merge_op(&phone, "laptop", op).unwrap());
+        }
+        for op in &from_phone {
+            assert!(merge_op(&laptop, "phone", op).unwrap());
+            assert!(!merge_op(&laptop, "phone", op).unwrap());
+        }
+
+        // Equal clocks: the phone's changes win on both
+        for db in [&phone, &laptop] {
+            assert!(db.get_conversation("1:1:bob").unwrap().is_muted);
+            assert!(db.has_reaction("m1", "alice", "👍").unwrap());
+            assert!(db.get_message("m1").unwrap().is_deleted);
+        }
+    }
+
+    #[test]
+    fn test_later_change_wins() {
+        let phone = setup_test_db();
+        let laptop = setup_test_db();
+
+        let first = record_change(&phone, "phone", muted(true)).unwrap();
+        merge_op(&laptop, "phone", &first).unwrap();
+        // The laptop has seen the phone's change, so its own comes after
+        let second = record_change(&laptop, "laptop", muted(false)).unwrap();
+        assert!(second.lamport > first.lamport);
+        merge_op(&phone, "laptop", &second).unwrap();
+
+        assert!(!phone.get_conversation("1:1:bob").unwrap().is_muted);
+        assert!(!laptop.get_conversation("1:1:bob").unwrap().is_muted);
+    }
+
+    #[test]
+    fn test_changes_wait_for_their_message() {
+        let phone = setup_test_db();
+        let laptop = Database::in_memory().unwrap();
+        init_schema(&laptop).unwrap();
+
+        let op = record_change(&phone, "phone", reaction(true)).unwrap();
+        merge_op(&laptop, "phone", &op).unwrap();
+        assert!(laptop.get_message("m1").is_err());
+
+        let message = phone.get_message("m1").unwrap();
+        let conversation_id = laptop.get_or_create_conversation("bob").unwrap();
+        laptop
+            .import_message(
+                &NewMessage {
+                    message_id: message.message_id,
+                    conversation_id,
+                    sender_peer_id: message.sender_peer_id,
+                    recipient_peer_id: message.recipient_peer_id,
+                    message_type: message.message_type,
+                    content_encrypted: None,
+                    content_plaintext: message.content_plaintext,
+                    status: message.status,
+                    parent_message_id: None,
+                },
+                message.created_at,
+            )
+            .unwrap();
+        reapply_message_state(&laptop, "m1").unwrap();
+        assert!(laptop.has_reaction("m1", "alice", "👍").unwrap());
+    }
+
+    #[test]
+    fn test_read_marker_stops_at_its_time() {
+        let phone = setup_test_db();
+        let laptop = setup_test_db();
+        let read_at = laptop.get_message("m1").unwrap().created_at;
+        // Bob's next message reaches the laptop after the phone read m1
+        let conversation_id = laptop.get_or_create_conversation("bob").unwrap();
+        laptop
+            .import_message(
+                &NewMessage {
+                    message_id: "m2".to_string(),
+                    conversation_id,
+                    sender_peer_id: "bob".to_string(),
+                    recipient_peer_id: Some("alice".to_string()),
+                    message_type: "text".to_string(),
+                    content_encrypted: None,
+                    content_plaintext: Some("Still there?".to_string()),
+                    status: MessageStatus::Delivered,
+                    parent_message_id: None,
+                },
+                read_at + 60,
+            )
+            .unwrap();
+
+        let op = record_change(
+            &phone,
+            "phone",
+            Change::ConversationRead {
+                conversation_id: "1:1:bob".to_string(),
+                read_at,
+            },
+        )
+        .unwrap();
+        merge_op(&laptop, "phone", &op).unwrap();
+
+        assert_eq!(laptop.get_message("m1").unwrap().read_at, Some(read_at));
+        assert_eq!(laptop.get_message("m2").unwrap().read_at, None);
+        assert_eq!(laptop.get_conversation("1:1:bob").unwrap().unread_count, 1);
+    }
+
+    #[test]
+    fn test_ignores_ops_from_other_accounts() {
+        let tablet = setup_test_db();
+        tablet.save_device("laptop", "phone", "Laptop", &[], None).unwrap();
+        tablet.save_device("tablet", "phone", "Tablet", &[], None).unwrap();
+        let phone = setup_test_db();
+        let mallory = setup_test_db();
+
+        // The laptop passes on the phone's changes
+        let from_phone = record_change(&phone, "phone", muted(true)).unwrap();
+        assert!(merge_op(&tablet, "laptop", &from_phone).unwrap());
+        assert!(tablet.get_conversation("1:1:bob").unwrap().is_muted);
+
+        // but nobody speaks for a device outside the account
+        let from_mallory = record_change(&mallory, "mallory", muted(false)).unwrap();
+        let forged = SyncOp {
+            lamport: from_phone.lamport + 1,
+            ..from_mallory
+        };
+        assert!(!merge_op(&tablet, "laptop", &forged).unwrap());
+        let forged = SyncOp {
+            origin_device: "phone".to_string(),
+            seq: 2,
+            ..forged
+        };
+        assert!(!merge_op(&tablet, "mallory", &forged).unwrap());
+        assert!(tablet.get_conversation("1:1:bob").unwrap().is_muted);
+        assert_eq!(cursors(&tablet).unwrap().get("mallory"), None);
+    }
+
+    #[test]
+    fn test_cursors_stop_at_gaps() {
+        let phone = setup_test_db();
+        let laptop = setup_test_db();
+
+        let ops: Vec<SyncOp> = [true, false, true]
+            .into_iter()
+            .map(|value| record_change(&phone, "phone", muted(value)).unwrap())
+            .collect();
+        merge_op(&laptop, "phone", &ops[0]).unwrap();
+        merge_op(&laptop, "phone", &ops[2]).unwrap();
+
+        let cursors = cursors(&laptop).unwrap();
+        assert_eq!(cursors.get("phone"), Some(&1));
+        assert_eq!(ops_after(&phone, &cursors).unwrap(), ops[1..].to_vec());
+    }
+}
@@ -0,0 +1,565 @@
+//! Linked Devices
+//!
+//! An account is the identity of its primary device. Further devices run
+//! their own identity (peer id, prekeys, sessions) and are linked to the
+//! account by a `DeviceCertificate` signed with the account's identity key.
+//!
+//! Linking: the new device shows a `LinkRequest` as a QR code (its peer id,
+//! name, addresses and prekey bundle, signed with its identity key), the
+//! primary scans it, issues the certificate and sends the account's
+//! `DeviceList` to every device over their E2E sessions. The certificate
+//! carries the device's signature over its request, so no account can claim
+//! a device that didn't ask to be linked. Senders encrypt direct messages separately for every
+//! device of the recipient and for their own other devices.
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine as _};
+use libp2p::{Multiaddr, PeerId};
+use prost::Message as _;
+use serde::{Deserialize, Serialize};
+
+use super::{Result, SyncError};
+use crate::identity::{Keypair, PreKeyBundle, PublicKey};
+use crate::protocol::pb;
+use crate::storage::{Database, StoredDevice};
+
+/// Prefix of the link request QR payload
+pub const LINK_PAYLOAD_PREFIX: &str = "mepassa-link:";
+
+/// How long a link request can be scanned (seconds)
+pub const LINK_REQUEST_TTL_SECS: i64 = 10 * 60;
+
+/// Longest accepted device name
+pub const MAX_DEVICE_NAME_LEN: usize = 64;
+
+/// Certificate binding a device to an account
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceCertificate {
+    /// Peer id of the account (its primary device)
+    pub account_peer_id: PeerId,
+    pub device_peer_id: PeerId,
+    pub device_name: String,
+    /// Unix timestamp (seconds) of the device's link request
+    pub requested_at: i64,
+    /// Ed25519 signature by the device over its link request
+    /// (`LinkRequest::challenge`)
+    pub device_signature: Vec<u8>,
+    /// Unix timestamp (seconds)
+    pub issued_at: i64,
+    /// Ed25519 signature by the account key over `challenge`
+    pub signature: Vec<u8>,
+}
+
+impl DeviceCertificate {
+    /// Signed bytes
+    ///
+    /// Format: "device:{account}:{device}:{issued_at}:{requested_at}:{device_name}"
+    pub fn challenge(
+        account_peer_id: &PeerId,
+        device_peer_id: &PeerId,
+        device_name: &str,
+        requested_at: i64,
+        issued_at: i64,
+    ) -> String {
+        format!(
+            "device:{}:{}:{}:{}:{}",
+            account_peer_id, device_peer_id, issued_at, requested_at, device_name
+        )
+    }
+
+    /// Sign a certificate for the device that made `request` with the
+    /// account key
+    pub fn issue(
+        account_keypair: &Keypair,
+        account_peer_id: PeerId,
+        request: &LinkRequest,
+        issued_at: i64,
+    ) -> Result<Self> {
+        let device_peer_id = request.peer_id()?;
+        let account_key = PublicKey::from_libp2p_peer_id(&account_peer_id)
+            .map_err(|e| SyncError::InvalidCertificate(e.to_string()))?;
+        if account_key.to_bytes() != account_keypair.public_key_bytes() {
+            return Err(SyncError::InvalidCertificate(
+                "keypair does not belong to the account".to_string(),
+            ));
+        }
+        if account_peer_id == device_peer_id {
+            return Err(SyncError::InvalidCertificate(
+                "an account can't link itself".to_string(),
+            ));
+        }
+
+        let challenge = Self::challenge(
+            &account_peer_id,
+            &device_peer_id,
+            &request.device_name,
+            request.created_at,
+            issued_at,
+        );
+        let certificate = Self {
+            account_peer_id,
+            device_peer_id,
+            device_name: request.device_name.clone(),
+            requested_at: request.created_at,
+            device_signature: request.signature.clone(),
+            issued_at,
+            signature: account_keypair.sign(challenge.as_bytes()).to_vec(),
+        };
+        certificate.verify()?;
+        Ok(certificate)
+    }
+
+    /// Check the device's consent and the account's signature
+    pub fn verify(&self) -> Result<()> {
+        if !(0..=LINK_REQUEST_TTL_SECS).contains(&(self.issued_at - self.requested_at)) {
+            return Err(SyncError::InvalidCertificate(
+                "issued outside the link request's lifetime".to_string(),
+            ));
+        }
+        let device_key = PublicKey::from_libp2p_peer_id(&self.device_peer_id)
+            .map_err(|e| SyncError::InvalidCertificate(e.to_string()))?;
+        let request =
+            LinkRequest::challenge(&self.device_peer_id, &self.device_name, self.requested_at);
+        device_key
+            .verify(request.as_bytes(), &self.device_signature)
+            .map_err(|_| {
+                SyncError::InvalidCertificate("device did not ask to be linked".to_string())
+            })?;
+
+        let account_key = PublicKey::from_libp2p_peer_id(&self.account_peer_id)
+            .map_err(|e| SyncError::InvalidCertificate(e.to_string()))?;
+        let challenge = Self::challenge(
+            &self.account_peer_id,
+            &self.device_peer_id,
+            &self.device_name,
+            self.requested_at,
+            self.issued_at,
+        );
+        account_key
+            .verify(challenge.as_bytes(), &self.signature)
+            .map_err(|_| SyncError::InvalidCertificate("bad signature".to_string()))
+    }
+
+    /// Wire format
+    pub fn to_proto(&self) -> pb::DeviceCertificate {
+        pb::DeviceCertificate {
+            account_peer_id: self.account_peer_id.to_string(),
+            device_peer_id: self.device_peer_id.to_string(),
+            device_name: self.device_name.clone(),
+            requested_at: self.requested_at,
+            device_signature: self.device_signature.clone(),
+            issued_at: self.issued_at,
+            signature: self.signature.clone(),
+        }
+    }
+
+    /// Parse and verify a certificate received from the network
+    pub fn from_proto(certificate: &pb::DeviceCertificate) -> Result<Self> {
+        let parse = |peer_id: &str| {
+            peer_id
+                .parse::<PeerId>()
+                .map_err(|e| SyncError::InvalidCertificate(format!("bad peer id: {}", e)))
+        };
+        let certificate = Self {
+            account_peer_id: parse(&certificate.account_peer_id)?,
+            device_peer_id: parse(&certificate.device_peer_id)?,
+            device_name: certificate.device_name.clone(),
+            requested_at: certificate.requested_at,
+            device_signature: certificate.device_signature.clone(),
+            issued_at: certificate.issued_at,
+            signature: certificate.signature.clone(),
+        };
+        certificate.verify()?;
+        Ok(certificate)
+    }
+
+    /// Encoded protobuf (as stored in the `devices` table)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_proto().encode_to_vec()
+    }
+
+    /// Decode and verify an encoded certificate
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let certificate = pb::DeviceCertificate::decode(bytes)
+            .map_err(|e| SyncError::InvalidCertificate(e.to_string()))?;
+        Self::from_proto(&certificate)
+    }
+}
+
+/// A device of an account and how to start a session with it
+#[derive(Debug, Clone)]
+pub struct LinkedDevice {
+    pub certificate: DeviceCertificate,
+    pub prekey_bundle: PreKeyBundle,
+}
+
+impl LinkedDevice {
+    /// Check the certificate, and that the bundle is the device's own
+    pub fn verify(&self) -> Result<()> {
+        self.certificate.verify()?;
+        verify_bundle(&self.certificate.device_peer_id, &self.prekey_bundle)
+    }
+
+    /// Wire format
+    pub fn to_proto(&self) -> pb::LinkedDevice {
+        pb::LinkedDevice {
+            certificate: Some(self.certificate.to_proto()),
+            prekey_bundle_json: serde_json::to_string(&self.prekey_bundle)
+                .expect("prekey bundle serializes"),
+        }
+    }
+
+    /// Parse and verify a device received from the network
+    pub fn from_proto(device: &pb::LinkedDevice) -> Result<Self> {
+        let certificate = device
+            .certificate
+            .as_ref()
+            .ok_or_else(|| SyncError::InvalidCertificate("missing certificate".to_string()))?;
+        let prekey_bundle = serde_json::from_str(&device.prekey_bundle_json)
+            .map_err(|e| SyncError::InvalidCertificate(format!("bad prekey bundle: {}", e)))?;
+        let device = Self {
+            certificate: DeviceCertificate::from_proto(certificate)?,
+            prekey_bundle,
+        };
+        verify_bundle(&device.certificate.device_peer_id, &device.prekey_bundle)?;
+        Ok(device)
+    }
+
+    /// A stored device, if its prekey bundle is known
+    pub fn from_stored(stored: &StoredDevice) -> Result<Option<Self>> {
+        let Some(bundle_json) = &stored.prekey_bundle_json else {
+            return Ok(None);
+        };
+        let prekey_bundle = serde_json::from_str(bundle_json)
+            .map_err(|e| SyncError::InvalidCertificate(format!("bad prekey bundle: {}", e)))?;
+        Ok(Some(Self {
+            certificate: DeviceCertificate::from_bytes(&stored.certificate)?,
+            prekey_bundle,
+        }))
+    }
+
+    /// Store (or refresh) the device; `false` if it belongs to another
+    /// account (see `save_certificate`)
+    pub fn save(&self, database: &Database) -> crate::storage::Result<bool> {
+        let bundle_json =
+            serde_json::to_string(&self.prekey_bundle).expect("prekey bundle serializes");
+        save_certificate(database, &self.certificate, Some(&bundle_json))
+    }
+}
+
+/// Store a verified certificate (keeping a known prekey bundle when `None`)
+///
+/// A stored device is never moved to another account, even with a valid
+/// certificate: returns `false` and keeps the stored one.
+pub fn save_certificate(
+    database: &Database,
+    certificate: &DeviceCertificate,
+    prekey_bundle_json: Option<&str>,
+) -> crate::storage::Result<bool> {
+    database.save_device(
+        &certificate.device_peer_id.to_string(),
+        &certificate.account_peer_id.to_string(),
+        &certificate.device_name,
+        &certificate.to_bytes(),
+        prekey_bundle_json,
+    )
+}
+
+/// Account a device belongs to (a device that isn't linked is an account)
+pub fn account_of(database: &Database, device_peer_id: &str) -> String {
+    match database.get_device(device_peer_id) {
+        Ok(Some(device)) => device.account_peer_id,
+        _ => device_peer_id.to_string(),
+    }
+}
+
+/// Whether `peer_id` is one of the devices of our account (including us)
+pub fn is_own_device(database: &Database, local_peer_id: &str, peer_id: &str) -> bool {
+    account_of(database, peer_id) == account_of(database, local_peer_id)
+}
+
+/// QR payload a new device shows to be linked
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkRequest {
+    pub device_peer_id: String,
+    pub device_name: String,
+    pub prekey_bundle: PreKeyBundle,
+    /// Where the primary can dial the new device
+    pub addresses: Vec<String>,
+    /// Unix timestamp (seconds)
+    pub created_at: i64,
+    /// Ed25519 signature by the device over `challenge`
+    pub signature: Vec<u8>,
+}
+
+impl LinkRequest {
+    /// Signed bytes, the device's consent to be linked (which the
+    /// certificate carries)
+    ///
+    /// Format: "link:{device}:{created_at}:{device_name}"
+    pub fn challenge(device_peer_id: &PeerId, device_name: &str, created_at: i64) -> String {
+        format!("link:{}:{}:{}", device_peer_id, created_at, device_name)
+    }
+
+    /// Sign a link request with the device's identity key
+    pub fn new(
+        device_keypair: &Keypair,
+        device_peer_id: PeerId,
+        device_name: &str,
+        prekey_bundle: PreKeyBundle,
+        addresses: Vec<String>,
+        created_at: i64,
+    ) -> Self {
+        let challenge = Self::challenge(&device_peer_id, device_name, created_at);
+        Self {
+            device_peer_id: device_peer_id.to_string(),
+            device_name: device_name.to_string(),
+            prekey_bundle,
+            addresses,
+            created_at,
+            signature: device_keypair.sign(challenge.as_bytes()).to_vec(),
+        }
+    }
+
+    /// Encode as `mepassa-link:<base64url JSON>`
+    pub fn to_payload(&self) -> String {
+        let json = serde_json::to_vec(self).expect("link request serializes");
+        format!(
+            "{}{}",
+            LINK_PAYLOAD_PREFIX,
+            general_purpose::URL_SAFE_NO_PAD.encode(json)
+        )
+    }
+
+    /// Decode a scanned payload, checking its age, the device's signature
+    /// and that the prekey bundle belongs to the device
+    pub fn from_payload(payload: &str, now: i64) -> Result<Self> {
+        let encoded = payload
+            .trim()
+            .strip_prefix(LINK_PAYLOAD_PREFIX)
+            .ok_or_else(|| SyncError::InvalidLinkRequest("not a link request".to_string()))?;
+        let json = general_purpose::URL_SAFE_NO_PAD
+            .decode(encoded)
+            .map_err(|e| SyncError::InvalidLinkRequest(e.to_string()))?;
+        let request: Self = serde_json::from_slice(&json)
+            .map_err(|e| SyncError::InvalidLinkRequest(e.to_string()))?;
+
+        if now - request.created_at > LINK_REQUEST_TTL_SECS || request.created_at - now > 60 {
+            return Err(SyncError::InvalidLinkRequest("link request expired".to_string()));
+        }
+        if request.device_name.is_empty() || request.device_name.len() > MAX_DEVICE_NAME_LEN {
+            return Err(SyncError::InvalidLinkRequest("invalid device name".to_string()));
+        }
+        let device_peer_id = request.peer_id()?;
+        PublicKey::from_libp2p_peer_id(&device_peer_id)
+            .map_err(|e| SyncError::InvalidLinkRequest(e.to_string()))?
+            .verify(
+                Self::challenge(&device_peer_id, &request.device_name, request.created_at)
+                    .as_bytes(),
+                &request.signature,
+            )
+            .map_err(|_| SyncError::InvalidLinkRequest("bad signature".to_string()))?;
+        verify_bundle(&device_peer_id, &request.prekey_bundle)?;
+
+        Ok(request)
+    }
+
+    /// Peer id of the new device
+    pub fn peer_id(&self) -> Result<PeerId> {
+        self.device_peer_id
+            .parse()
+            .map_err(|e| SyncError::InvalidLinkRequest(format!("bad peer id: {}", e)))
+    }
+
+    /// Dialable addresses (unparsable ones are skipped)
+    pub fn multiaddrs(&self) -> Vec<Multiaddr> {
+        self.addresses
+            .iter()
+            .filter_map(|addr| addr.parse().ok())
+            .collect()
+    }
+}
+
+/// Check that a prekey bundle carries `device`'s identity key and a valid
+/// signed prekey
+pub fn verify_bundle(device: &PeerId, bundle: &PreKeyBundle) -> Result<()> {
+    let device_key = PublicKey::from_libp2p_peer_id(device)
+        .map_err(|e| SyncError::InvalidCertificate(e.to_string()))?;
+    if device_key.to_bytes() != bundle.identity_key {
+        return Err(SyncError::InvalidCertificate(
+            "prekey bundle belongs to another identity".to_string(),
+        ));
+    }
+    device_key
+        .verify(&bundle.signed_prekey, &bundle.signed_prekey_signature)
+        .map_err(|_| SyncError::InvalidCertificate("bad signed prekey signature".to_string()))
+}
+
+/// Server publishing the devices linked to each account (the identity server)
+#[async_trait]
+pub trait DeviceDirectory: Send + Sync {
+    /// Publish (or refresh) a device of our account
+    async fn publish_device(&self, device: &LinkedDevice) -> crate::utils::error::Result<()>;
+
+    /// Devices linked to `account`
+    async fn fetch_devices(&self, account: &PeerId) -> crate::utils::error::Result<Vec<LinkedDevice>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::Identity;
+
+    fn identity() -> (Identity, PeerId) {
+        let libp2p_keypair = libp2p::identity::Keypair::generate_ed25519();
+        let keypair = Keypair::from_libp2p_keypair(&libp2p_keypair).unwrap();
+        let mut identity = Identity::from_keypair(keypair);
+        identity.init_prekey_pool(1);
+        (identity, PeerId::from(libp2p_keypair.public()))
+    }
+
+    fn link_request(device: &Identity, device_id: PeerId, name: &str, created_at: i64) -> LinkRequest {
+        LinkRequest::new(
+            device.keypair(),
+            device_id,
+            name,
+            device.prekey_pool().unwrap().export_bundle(),
+            Vec::new(),
+            created_at,
+        )
+    }
+
+    #[test]
+    fn test_certificate_roundtrip() {
+        let (account, account_id) = identity();
+        let (device, device_id) = identity();
+        let request = link_request(&device, device_id, "Tablet", 1_700_000_000);
+
+        let certificate =
+            DeviceCertificate::issue(account.keypair(), account_id, &request, 1_700_000_060).unwrap();
+        certificate.verify().unwrap();
+        assert_eq!(certificate.device_peer_id, device_id);
+        assert_eq!(
+            DeviceCertificate::from_proto(&certificate.to_proto()).unwrap(),
+            certificate
+        );
+    }
+
+    #[test]
+    fn test_rejects_forged_certificates() {
+        let (account, account_id) = identity();
+        let (mallory, mallory_id) = identity();
+        let (device, device_id) = identity();
+        let request = link_request(&device, device_id, "Laptop", 1);
+
+        // Only the account key can issue certificates for the account
+        assert!(DeviceCertificate::issue(mallory.keypair(), account_id, &request, 1).is_err());
+        let mut forged = DeviceCertificate::issue(mallory.keypair(), mallory_id, &request, 1).unwrap();
+        forged.account_peer_id = account_id;
+        assert!(forged.verify().is_err());
+
+        // Any change to a signed field breaks the signature
+        let mut renamed = DeviceCertificate::issue(account.keypair(), account_id, &request, 1).unwrap();
+        renamed.device_name = "Phone".to_string();
+        assert!(renamed.verify().is_err());
+    }
+
+    #[test]
+    fn test_rejects_foreign_account_claiming_device() {
+        let (mallory, mallory_id) = identity();
+        let (device, device_id) = identity();
+
+        // The device never asked Mallory (or anyone) to link it, so she can
+        // only sign its consent with her own key
+        let mut request = link_request(&mallory, mallory_id, "Laptop", 1);
+        request.device_peer_id = device_id.to_string();
+        assert!(DeviceCertificate::issue(mallory.keypair(), mallory_id, &request, 1).is_err());
+
+        // A certificate without the device's signature
+        let mut request = link_request(&device, device_id, "Laptop", 1);
+        request.signature = vec![0; 64];
+        assert!(DeviceCertificate::issue(mallory.keypair(), mallory_id, &request, 1).is_err());
+
+        // Or issued for a request that expired long ago
+        let request = link_request(&device, device_id, "Laptop", 1);
+        assert!(DeviceCertificate::issue(
+            mallory.keypair(),
+            mallory_id,
+            &request,
+            1 + LINK_REQUEST_TTL_SECS + 1
+        )
+        .is_err());
+
+        // Once the device is linked, no certificate moves it to another
+        // account, even one issued for a request Mallory got hold of
+        let db = Database::in_memory().unwrap();
+        crate::storage::init_schema(&db).unwrap();
+        let (owner, owner_id) = identity();
+        let own = DeviceCertificate::issue(owner.keypair(), owner_id, &request, 2).unwrap();
+        assert!(save_certificate(&db, &own, None).unwrap());
+        let claimed = DeviceCertificate::issue(mallory.keypair(), mallory_id, &request, 3).unwrap();
+        assert!(!save_certificate(&db, &claimed, None).unwrap());
+        assert_eq!(account_of(&db, &device_id.to_string()), owner_id.to_string());
+    }
+
+    #[test]
+    fn test_account_of_linked_devices() {
+        let (account, account_id) = identity();
+        let (device, device_id) = identity();
+        let db = Database::in_memory().unwrap();
+        crate::storage::init_schema(&db).unwrap();
+
+        let request = link_request(&device, device_id, "Tablet", 1);
+        let certificate = DeviceCertificate::issue(account.keypair(), account_id, &request, 1).unwrap();
+        assert!(save_certificate(&db, &certificate, None).unwrap());
+
+        let (account_id, device_id) = (account_id.to_string(), device_id.to_string());
+        assert_eq!(account_of(&db, &device_id), account_id);
+        assert_eq!(account_of(&db, &account_id), account_id);
+        assert!(is_own_device(&db, &device_id, &account_id));
+        assert!(!is_own_device(&db, &device_id, &PeerId::random().to_string()));
+        assert_eq!(
+            DeviceCertificate::from_bytes(&db.get_device(&device_id).unwrap().unwrap().certificate)
+                .unwrap(),
+            certificate
+        );
+    }
+
+    #[test]
+    fn test_link_request_payload() {
+        let (device, device_id) = identity();
+        let request = LinkRequest::new(
+            device.keypair(),
+            device_id,
+            "Tablet",
+            device.prekey_pool().unwrap().export_bundle(),
+            vec!["/ip4/192.168.1.20/tcp/4001".to_string(), "junk".to_string()],
+            1_000,
+        );
+
+        let payload = request.to_payload();
+        assert!(payload.starts_with(LINK_PAYLOAD_PREFIX));
+        let decoded = LinkRequest::from_payload(&payload, 1_060).unwrap();
+        assert_eq!(decoded.peer_id().unwrap(), device_id);
+        assert_eq!(decoded.multiaddrs().len(), 1);
+
+        // Stale
+        assert!(LinkRequest::from_payload(&payload, 1_000 + LINK_REQUEST_TTL_SECS + 1).is_err());
+
+        // Renamed after signing
+        let renamed = LinkRequest {
+            device_name: "Phone".to_string(),
+            ..request.clone()
+        };
+        assert!(LinkRequest::from_payload(&renamed.to_payload(), 1_000).is_err());
+
+        // Someone else's prekeys
+        let (other, _) = identity();
+        let swapped = LinkRequest {
+            prekey_bundle: other.prekey_pool().unwrap().export_bundle(),
+            ..request
+        };
+        assert!(LinkRequest::from_payload(&swapped.to_payload(), 1_000).is_err());
+        assert!(LinkRequest::from_payload("hello", 1_000).is_err());
+    }
+}
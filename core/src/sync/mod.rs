@@ -1,13 +1,20 @@
 //! Synchronization module
 //!
-//! Multi-device sync using CRDTs (Automerge).
+//! Multi-device sync: linked devices, and the state they replicate through
+//! an operation log of last-writer-wins registers.
 
-// pub mod crdt;
-// pub mod device;
-// pub mod protocol;
+pub mod crdt;
+pub mod device;
+pub mod protocol;
+
+pub use crdt::{Change, SyncOp};
+pub use device::{DeviceCertificate, DeviceDirectory, LinkRequest, LinkedDevice};
+pub use protocol::MergeSummary;
 
 use thiserror::Error;
 
+use crate::storage::StorageError;
+
 #[derive(Error, Debug)]
 pub enum SyncError {
     #[error("Sync failed: {0}")]
@@ -18,6 +25,18 @@ pub enum SyncError {
 
     #[error("Conflict resolution failed")]
     ConflictResolutionFailed,
+
+    #[error("Invalid link request: {0}")]
+    InvalidLinkRequest(String),
+
+    #[error("Invalid device certificate: {0}")]
+    InvalidCertificate(String),
+
+    #[error("Invalid sync operation: {0}")]
+    InvalidOperation(String),
+
+    #[error("Storage error: {0}")]
+    Storage(#[from] StorageError),
 }
 
 pub type Result<T> = std::result::Result<T, SyncError>;
@@ -0,0 +1,292 @@
+//! Device Sync Protocol
+//!
+//! The devices of an account exchange `SyncBatch`es over their E2E
+//! sessions. A change is pushed to the other devices as it is made; a device
+//! that may have missed some sends a `SyncRequest` with its cursors and gets
+//! the operations past them. A newly linked device also asks for history and
+//! gets the last `BACKFILL_MESSAGE_LIMIT` one-to-one text messages (group
+//! history stays with the group, which the new device isn't a member of).
+
+use std::collections::HashMap;
+
+use super::crdt::{self, SyncOp};
+use super::device::{account_of, is_own_device};
+use crate::crypto::{decrypt_for_storage, encrypt_for_storage};
+use crate::protocol::pb;
+use crate::storage::{Database, Message, MessageStatus, NewMessage};
+use crate::utils::error::Result;
+
+/// Most recent messages sent to a newly linked device
+pub const BACKFILL_MESSAGE_LIMIT: usize = 500;
+
+/// Messages per batch (each batch is one E2E message)
+pub const BATCH_MESSAGES: usize = 50;
+
+/// Operations per batch
+pub const BATCH_OPS: usize = 200;
+
+/// What a batch added on this device
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MergeSummary {
+    /// History messages that were new
+    pub messages: usize,
+    /// Operations that were new
+    pub changes: usize,
+}
+
+/// Request for the operations we lack (and history, on a new device)
+pub fn sync_request(database: &Database, history: bool) -> Result<pb::SyncRequest> {
+    let cursors = crdt::cursors(database)?
+        .into_iter()
+        .map(|(origin_device_peer_id, seq)| pb::SyncCursor {
+            origin_device_peer_id,
+            seq,
+        })
+        .collect();
+    Ok(pb::SyncRequest { cursors, history })
+}
+
+/// Batches answering another device's request
+pub fn answer_request(
+    database: &Database,
+    storage_key: &[u8; 32],
+    local_peer_id: &str,
+    request: &pb::SyncRequest,
+) -> Result<Vec<pb::SyncBatch>> {
+    let mut batches = Vec::new();
+    if request.history {
+        let history = export_history(database, storage_key, local_peer_id)?;
+        for chunk in history.chunks(BATCH_MESSAGES) {
+            batches.push(pb::SyncBatch {
+                history: chunk.to_vec(),
+                ops: Vec::new(),
+            });
+        }
+    }
+
+    let cursors: HashMap<String, u64> = request
+        .cursors
+        .iter()
+        .map(|cursor| (cursor.origin_device_peer_id.clone(), cursor.seq))
+        .collect();
+    let ops = crdt::ops_after(database, &cursors)?;
+    for chunk in ops.chunks(BATCH_OPS) {
+        batches.push(ops_batch(chunk));
+    }
+    Ok(batches)
+}
+
+/// Batch pushing operations to the other devices
+pub fn ops_batch(ops: &[SyncOp]) -> pb::SyncBatch {
+    pb::SyncBatch {
+        history: Vec::new(),
+        ops: ops.iter().map(SyncOp::to_proto).collect(),
+    }
+}
+
+/// Merge a batch from another device (`from_peer_id`): history first, so
+/// the operations find their messages
+pub fn merge_batch(
+    database: &Database,
+    storage_key: &[u8; 32],
+    local_peer_id: &str,
+    from_peer_id: &str,
+    batch: pb::SyncBatch,
+) -> Result<MergeSummary> {
+    let mut summary = MergeSummary::default();
+    for message in &batch.history {
+        if import_history_message(database, storage_key, local_peer_id, message)? {
+            summary.messages += 1;
+        }
+    }
+    for op in batch.ops {
+        if crdt::merge_op(database, from_peer_id, &SyncOp::from_proto(op)?)? {
+            summary.changes += 1;
+        }
+    }
+    Ok(summary)
+}
+
+/// Recent history, oldest first, with our devices named by the account
+fn export_history(
+    database: &Database,
+    storage_key: &[u8; 32],
+    local_peer_id: &str,
+) -> Result<Vec<pb::HistoryMessage>> {
+    let account = account_of(database, local_peer_id);
+    let by_account = |peer_id: &str| {
+        if is_own_device(database, local_peer_id, peer_id) {
+            account.clone()
+        } else {
+            peer_id.to_string()
+        }
+    };
+
+    let mut history = Vec::new();
+    for message in database.recent_messages(BACKFILL_MESSAGE_LIMIT)?.into_iter().rev() {
+        let Some(content) = message_content(storage_key, &message) else {
+            tracing::warn!("⚠️ Not syncing message {}: unreadable content", message.message_id);
+            continue;
+        };
+        history.push(pb::HistoryMessage {
+            sender_peer_id: by_account(&message.sender_peer_id),
+            recipient_peer_id: message
+                .recipient_peer_id
+                .as_deref()
+                .map(by_account)
+                .unwrap_or_default(),
+            message_id: message.message_id,
+            conversation_id: message.conversation_id,
+            message_type: message.message_type,
+            content,
+            created_at: message.created_at,
+            status: message.status.as_str().to_string(),
+            parent_message_id: message.parent_message_id.unwrap_or_default(),
+        });
+    }
+    Ok(history)
+}
+
+fn message_content(storage_key: &[u8; 32], message: &Message) -> Option<String> {
+    if let Some(plaintext) = &message.content_plaintext {
+        return Some(plaintext.clone());
+    }
+    let bytes = decrypt_for_storage(storage_key, message.content_encrypted.as_ref()?).ok()?;
+    String::from_utf8(bytes).ok()
+}
+
+/// Store a history message between our account and one peer; false if it
+/// was known (or isn't such a message)
+fn import_history_message(
+    database: &Database,
+    storage_key: &[u8; 32],
+    local_peer_id: &str,
+    message: &pb::HistoryMessage,
+) -> Result<bool> {
+    let account = account_of(database, local_peer_id);
+    let peer = match message.conversation_id.strip_prefix("1:1:") {
+        Some(peer) if !is_own_device(database, local_peer_id, peer) => peer,
+        _ => {
+            tracing::warn!(
+                "⚠️ Ignoring synced message {} of conversation {}",
+                message.message_id,
+                message.conversation_id
+            );
+            return Ok(false);
+        }
+    };
+    // Messages we sent are stored as sent by this device
+    let sender_peer_id = if message.sender_peer_id == account {
+        local_peer_id
+    } else if message.sender_peer_id == peer {
+        peer
+    } else {
+        tracing::warn!("⚠️ Ignoring synced message {} of another sender", message.message_id);
+        return Ok(false);
+    };
+
+    let conversation_id = database.get_or_create_conversation(peer)?;
+    let new_message = NewMessage {
+        message_id: message.message_id.clone(),
+        conversation_id,
+        sender_peer_id: sender_peer_id.to_string(),
+        recipient_peer_id: Some(message.recipient_peer_id.clone())
+            .filter(|recipient| !recipient.is_empty()),
+        message_type: message.message_type.clone(),
+        content_encrypted: Some(encrypt_for_storage(storage_key, message.content.as_bytes())?),
+        content_plaintext: None,
+        status: MessageStatus::from_str(&message.status),
+        parent_message_id: Some(message.parent_message_id.clone())
+            .filter(|parent| !parent.is_empty()),
+    };
+    if !database.import_message(&new_message, message.created_at)? {
+        return Ok(false);
+    }
+    crdt::reapply_message_state(database, &message.message_id)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::init_schema;
+    use crate::sync::crdt::Change;
+
+    fn setup_test_db() -> Database {
+        let db = Database::in_memory().unwrap();
+        init_schema(&db).unwrap();
+        db
+    }
+
+    fn insert_text(db: &Database, key: &[u8; 32], id: &str, sender: &str, recipient: &str, peer: &str) {
+        let conversation_id = db.get_or_create_conversation(peer).unwrap();
+        db.ensure_contact_exists(sender).unwrap();
+        db.insert_message(&NewMessage {
+            message_id: id.to_string(),
+            conversation_id,
+            sender_peer_id: sender.to_string(),
+            recipient_peer_id: Some(recipient.to_string()),
+            message_type: "text".to_string(),
+            content_encrypted: Some(encrypt_for_storage(key, id.as_bytes()).unwrap()),
+            content_plaintext: None,
+            status: MessageStatus::Delivered,
+            parent_message_id: None,
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_backfill_new_device() {
+        let (phone_key, laptop_key) = ([1u8; 32], [2u8; 32]);
+        let phone = setup_test_db();
+        let laptop = setup_test_db();
+        laptop.save_device("laptop", "alice", "Laptop", &[], None).unwrap();
+        insert_text(&phone, &phone_key, "in", "bob", "alice", "bob");
+        insert_text(&phone, &phone_key, "out", "alice", "bob", "bob");
+        crdt::record_change(
+            &phone,
+            "alice",
+            Change::MessageDeleted {
+                message_id: "in".to_string(),
+            },
+        )
+        .unwrap();
+        let kept = crdt::record_change(
+            &phone,
+            "alice",
+            Change::ConversationMuted {
+                conversation_id: "1:1:bob".to_string(),
+                muted: true,
+            },
+        )
+        .unwrap();
+
+        // The laptop already has the second change
+        crdt::merge_op(&laptop, "alice", &kept).unwrap();
+        let request = sync_request(&laptop, true).unwrap();
+        let batches = answer_request(&phone, &phone_key, "alice", &request).unwrap();
+        assert_eq!(batches.len(), 2);
+
+        let mut total = MergeSummary::default();
+        for batch in batches {
+            let summary = merge_batch(&laptop, &laptop_key, "laptop", "alice", batch).unwrap();
+            total.messages += summary.messages;
+            total.changes += summary.changes;
+        }
+        // Deleted messages aren't sent; the outgoing one is the laptop's
+        assert_eq!(total, MergeSummary { messages: 1, changes: 1 });
+        let out = laptop.get_message("out").unwrap();
+        assert_eq!(out.sender_peer_id, "laptop");
+        assert_eq!(
+            message_content(&laptop_key, &out).as_deref(),
+            Some("out")
+        );
+        assert!(laptop.get_conversation("1:1:bob").unwrap().is_muted);
+
+        // Nothing is missing any more
+        let request = sync_request(&laptop, false).unwrap();
+        assert!(answer_request(&phone, &phone_key, "alice", &request)
+            .unwrap()
+            .is_empty());
+    }
+}
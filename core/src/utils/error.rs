@@ -15,6 +15,12 @@ pub enum MePassaError {
     #[error("Crypto error: {0}")]
     Crypto(String),
 
+    #[error("One-time prekey {0} not found")]
+    PreKeyMissing(u32),
+
+    #[error("Signed prekey {0} not found")]
+    SignedPreKeyMissing(u32),
+
     #[error("Network error: {0}")]
     Network(String),
 
@@ -24,6 +30,9 @@ pub enum MePassaError {
     #[error("Protocol error: {0}")]
     Protocol(String),
 
+    #[error("Encryption required: {0}")]
+    EncryptionRequired(String),
+
     #[error("Not found: {0}")]
     NotFound(String),
 
@@ -46,6 +55,12 @@ impl From<StorageError> for MePassaError {
     }
 }
 
+impl From<crate::sync::SyncError> for MePassaError {
+    fn from(err: crate::sync::SyncError) -> Self {
+        MePassaError::Identity(err.to_string())
+    }
+}
+
 impl From<rusqlite::Error> for MePassaError {
     fn from(err: rusqlite::Error) -> Self {
         MePassaError::Storage(err.to_string())
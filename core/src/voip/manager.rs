@@ -106,8 +106,11 @@ pub struct CallManager {
     /// Event sender
     event_tx: mpsc::UnboundedSender<CallEvent>,
 
-    /// Event receiver
-    event_rx: Arc<RwLock<mpsc::UnboundedReceiver<CallEvent>>>,
+    /// Event receiver (fanned out to subscribers once the first one subscribes)
+    event_rx: Arc<RwLock<Option<mpsc::UnboundedReceiver<CallEvent>>>>,
+
+    /// Event subscribers
+    subscribers: Arc<std::sync::Mutex<Vec<mpsc::UnboundedSender<CallEvent>>>>,
 
     /// TURN credentials (cached)
     turn_credentials: Arc<RwLock<Option<TurnCredentials>>>,
@@ -123,7 +126,8 @@ impl CallManager {
             peers: Arc::new(RwLock::new(HashMap::new())),
             video_enabled: Arc::new(RwLock::new(HashMap::new())),
             event_tx,
-            event_rx: Arc::new(RwLock::new(event_rx)),
+            event_rx: Arc::new(RwLock::new(Some(event_rx))),
+            subscribers: Arc::new(std::sync::Mutex::new(Vec::new())),
             turn_credentials: Arc::new(RwLock::new(None)),
         }
     }
@@ -568,13 +572,24 @@ impl CallManager {
 
     /// Subscribe to call events
     pub async fn subscribe_events(&self) -> mpsc::UnboundedReceiver<CallEvent> {
-        // Create a new channel for this subscriber
-        // Note: This is a simplified implementation
-        // In production, we'd use a broadcast channel
         let (tx, rx) = mpsc::unbounded_channel();
-
-        // TODO: Implement proper event broadcasting
-        // For now, this is a placeholder
+        self.subscribers
+            .lock()
+            .expect("call subscribers lock poisoned")
+            .push(tx);
+
+        // The first subscriber starts forwarding events to all of them
+        if let Some(mut events) = self.event_rx.write().await.take() {
+            let subscribers = Arc::clone(&self.subscribers);
+            tokio::spawn(async move {
+                while let Some(event) = events.recv().await {
+                    subscribers
+                        .lock()
+                        .expect("call subscribers lock poisoned")
+                        .retain(|tx| tx.send(event.clone()).is_ok());
+                }
+            });
+        }
 
         rx
     }
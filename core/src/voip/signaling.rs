@@ -6,6 +6,8 @@
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
+use crate::network::framing::{read_frame, write_frame, FrameError, MAX_SIGNALING_FRAME_SIZE};
+
 /// Call signaling message types
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -106,8 +108,47 @@ impl fmt::Display for SignalingMessage {
 }
 
 /// Codec for signaling messages over libp2p request-response
-#[derive(Debug, Clone, Default)]
-pub struct SignalingCodec;
+#[derive(Debug, Clone)]
+pub struct SignalingCodec {
+    /// Largest frame accepted (or sent) in either direction
+    max_frame_size: usize,
+}
+
+impl SignalingCodec {
+    pub fn new(max_frame_size: usize) -> Self {
+        Self { max_frame_size }
+    }
+
+    async fn read_message<T>(&self, io: &mut T) -> std::io::Result<SignalingMessage>
+    where
+        T: futures::AsyncRead + Unpin + Send,
+    {
+        let data = read_frame(io, self.max_frame_size).await?;
+
+        // Decode JSON
+        serde_json::from_slice(&data).map_err(|e| FrameError::Malformed(e.to_string()).into())
+    }
+
+    async fn write_message<T>(&self, io: &mut T, message: &SignalingMessage) -> std::io::Result<()>
+    where
+        T: futures::AsyncWrite + Unpin + Send,
+    {
+        use futures::AsyncWriteExt;
+
+        // Encode to JSON
+        let data = serde_json::to_vec(message)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
+        write_frame(io, &data, self.max_frame_size).await?;
+        io.close().await
+    }
+}
+
+impl Default for SignalingCodec {
+    fn default() -> Self {
+        Self::new(MAX_SIGNALING_FRAME_SIZE)
+    }
+}
 
 #[async_trait::async_trait]
 impl libp2p::request_response::Codec for SignalingCodec {
@@ -123,20 +164,7 @@ impl libp2p::request_response::Codec for SignalingCodec {
     where
         T: futures::AsyncRead + Unpin + Send,
     {
-        use futures::AsyncReadExt;
-
-        // Read length prefix (4 bytes)
-        let mut len_buf = [0u8; 4];
-        io.read_exact(&mut len_buf).await?;
-        let len = u32::from_be_bytes(len_buf) as usize;
-
-        // Read message data
-        let mut data = vec![0u8; len];
-        io.read_exact(&mut data).await?;
-
-        // Decode JSON
-        serde_json::from_slice(&data)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+        self.read_message(io).await
     }
 
     async fn read_response<T>(
@@ -147,20 +175,7 @@ impl libp2p::request_response::Codec for SignalingCodec {
     where
         T: futures::AsyncRead + Unpin + Send,
     {
-        use futures::AsyncReadExt;
-
-        // Read length prefix (4 bytes)
-        let mut len_buf = [0u8; 4];
-        io.read_exact(&mut len_buf).await?;
-        let len = u32::from_be_bytes(len_buf) as usize;
-
-        // Read message data
-        let mut data = vec![0u8; len];
-        io.read_exact(&mut data).await?;
-
-        // Decode JSON
-        serde_json::from_slice(&data)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+        self.read_message(io).await
     }
 
     async fn write_request<T>(
@@ -172,19 +187,7 @@ impl libp2p::request_response::Codec for SignalingCodec {
     where
         T: futures::AsyncWrite + Unpin + Send,
     {
-        use futures::AsyncWriteExt;
-
-        // Encode to JSON
-        let data = serde_json::to_vec(&req)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-
-        // Write length prefix (4 bytes)
-        let len = data.len() as u32;
-        io.write_all(&len.to_be_bytes()).await?;
-
-        // Write message data
-        io.write_all(&data).await?;
-        io.close().await
+        self.write_message(io, &req).await
     }
 
     async fn write_response<T>(
@@ -196,25 +199,17 @@ impl libp2p::request_response::Codec for SignalingCodec {
     where
         T: futures::AsyncWrite + Unpin + Send,
     {
-        use futures::AsyncWriteExt;
-
-        // Encode to JSON
-        let data = serde_json::to_vec(&res)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-
-        // Write length prefix (4 bytes)
-        let len = data.len() as u32;
-        io.write_all(&len.to_be_bytes()).await?;
-
-        // Write message data
-        io.write_all(&data).await?;
-        io.close().await
+        self.write_message(io, &res).await
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use futures::executor::block_on;
+    use futures::io::Cursor;
+    use libp2p::request_response::Codec;
+    use proptest::prelude::*;
 
     #[test]
     fn test_call_offer_message() {
@@ -241,11 +236,67 @@ mod tests {
         assert!(msg.is_ice_candidate());
     }
 
+    fn protocol() -> libp2p::StreamProtocol {
+        libp2p::StreamProtocol::new("/mepassa/voip/1.0.0")
+    }
+
+    fn read(codec: &mut SignalingCodec, bytes: Vec<u8>) -> std::io::Result<SignalingMessage> {
+        block_on(codec.read_request(&protocol(), &mut Cursor::new(bytes)))
+    }
+
+    #[test]
+    fn test_codec_rejects_oversized_frame() {
+        let mut codec = SignalingCodec::new(1024);
+        let mut bytes = 4096u32.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&[b' '; 4096]);
+
+        let err = read(&mut codec, bytes).unwrap_err();
+        assert!(matches!(
+            FrameError::from_io(&err),
+            Some(FrameError::TooLarge { len: 4096, max: 1024 })
+        ));
+    }
+
     #[test]
-    fn test_codec_creation() {
-        let _codec = SignalingCodec::default();
-        // SignalingCodec implements libp2p::request_response::Codec
-        // Full codec testing requires async I/O streams
+    fn test_codec_rejects_malformed_json() {
+        let mut codec = SignalingCodec::default();
+        let mut bytes = 8u32.to_be_bytes().to_vec();
+        bytes.extend_from_slice(b"not json");
+
+        let err = read(&mut codec, bytes).unwrap_err();
+        assert!(matches!(FrameError::from_io(&err), Some(FrameError::Malformed(_))));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_codec_roundtrip(call_id in ".{0,64}", sdp in ".{0,4096}") {
+            let mut codec = SignalingCodec::default();
+            let message = SignalingMessage::CallOffer { call_id, sdp };
+
+            let mut buf = Cursor::new(Vec::new());
+            block_on(codec.write_request(&protocol(), &mut buf, message.clone())).unwrap();
+            prop_assert_eq!(read(&mut codec, buf.into_inner()).unwrap(), message);
+        }
+
+        #[test]
+        fn prop_codec_arbitrary_input_never_panics(bytes in prop::collection::vec(any::<u8>(), 0..512)) {
+            let mut codec = SignalingCodec::new(256);
+            if let Err(err) = read(&mut codec, bytes) {
+                // Either the stream ended early or the frame is typed as rejected
+                if err.kind() != std::io::ErrorKind::UnexpectedEof {
+                    prop_assert!(FrameError::from_io(&err).is_some());
+                }
+            }
+        }
+
+        #[test]
+        fn prop_codec_declared_length_above_limit_rejected(len in 257u32..) {
+            let mut codec = SignalingCodec::new(256);
+
+            let err = read(&mut codec, len.to_be_bytes().to_vec()).unwrap_err();
+            let too_large = matches!(FrameError::from_io(&err), Some(FrameError::TooLarge { .. }));
+            prop_assert!(too_large);
+        }
     }
 
     #[test]
@@ -0,0 +1,113 @@
+//! AutoNAT Integration Test
+//!
+//! A local peer dials an AutoNAT server (a stand-in for the bootstrap node),
+//! learns that it serves dial-backs and asks to be dialed back. The
+//! dial-back reaches its listener, so it is reported as publicly reachable.
+//! Peers only probe: another client never dials back.
+
+mod common;
+
+use common::autonat_server::AutoNatServer;
+use libp2p::{identity::Keypair, Multiaddr};
+use mepassa_core::network::{AutoNatConfig, NetworkEvent, NetworkManager, Reachability};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+
+fn local_config() -> AutoNatConfig {
+    AutoNatConfig {
+        boot_delay: Duration::ZERO,
+        ..Default::default()
+    }
+}
+
+async fn listening_peer(config: AutoNatConfig) -> (NetworkManager, Multiaddr) {
+    let mut peer = NetworkManager::new(Keypair::generate_ed25519()).unwrap();
+    peer.set_autonat_config(config);
+    peer.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+    for _ in 0..200 {
+        if let Some(addr) = peer.listening_addresses().into_iter().next() {
+            return (peer, addr);
+        }
+        if !peer.poll_once().await.unwrap_or(false) {
+            sleep(Duration::from_millis(5)).await;
+        }
+    }
+    panic!("Peer never started listening");
+}
+
+/// Poll the peers until `done` holds (or give up after ~10 seconds)
+async fn drive(peers: &mut [&mut NetworkManager], done: impl Fn(&[&mut NetworkManager]) -> bool) -> bool {
+    for _ in 0..2000 {
+        if done(peers) {
+            return true;
+        }
+        let mut progressed = false;
+        for peer in peers.iter_mut() {
+            progressed |= peer.poll_once().await.unwrap_or(false);
+        }
+        if !progressed {
+            sleep(Duration::from_millis(5)).await;
+        }
+    }
+    done(peers)
+}
+
+#[tokio::test]
+async fn test_dial_back_confirms_public_reachability() {
+    let server = AutoNatServer::start().await;
+    let (mut client, client_addr) = listening_peer(local_config()).await;
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+    client.set_event_sender(event_tx);
+
+    assert_eq!(*client.reachability(), Reachability::Unknown);
+    client.dial(server.peer_id, server.addr.clone()).unwrap();
+
+    let public = drive(&mut [&mut client], |peers| {
+        matches!(peers[0].reachability(), Reachability::Public(_))
+    })
+    .await;
+    assert!(public, "Reachability: {:?}", client.reachability());
+
+    // The server reached the client's listener
+    let Reachability::Public(addr) = client.reachability().clone() else {
+        unreachable!()
+    };
+    assert_eq!(addr, client_addr);
+    // (after the connection events)
+    let mut events = Vec::new();
+    while let Ok(event) = event_rx.try_recv() {
+        events.push(event);
+    }
+    assert_eq!(events[0], NetworkEvent::Online);
+    assert_eq!(
+        events.last(),
+        Some(&NetworkEvent::ReachabilityChanged {
+            reachability: Reachability::Public(client_addr)
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_probe_without_server_fails() {
+    let (mut client, _) = listening_peer(local_config()).await;
+    assert!(client.probe_reachability().is_err());
+}
+
+#[tokio::test]
+async fn test_clients_do_not_serve_dial_backs() {
+    let (mut alice, alice_addr) = listening_peer(local_config()).await;
+    let (mut bob, _) = listening_peer(local_config()).await;
+    bob.dial(*alice.local_peer_id(), alice_addr).unwrap();
+
+    // Connected and identified, but Alice is no AutoNAT server for Bob
+    let connected = drive(&mut [&mut alice, &mut bob], |peers| peers[1].connected_peers() > 0).await;
+    assert!(connected);
+    for _ in 0..100 {
+        alice.poll_once().await.ok();
+        bob.poll_once().await.ok();
+        sleep(Duration::from_millis(5)).await;
+    }
+    assert!(bob.probe_reachability().is_err());
+    assert_eq!(*bob.reachability(), Reachability::Unknown);
+}
@@ -0,0 +1,131 @@
+//! Backup and Restore Integration Test
+//!
+//! Alice exports a backup after talking to Bob, then restores it in a new
+//! data directory: she keeps her peer id, contacts and history, and Carol,
+//! who got her prekey bundle before the backup, can still start a session
+//! with the restored install.
+
+mod common;
+
+use common::{connect, TestNode};
+use mepassa_core::api::ClientBuilder;
+use tempfile::TempDir;
+use tokio::task::LocalSet;
+
+const PASSPHRASE: &str = "correct horse battery staple";
+
+#[tokio::test]
+async fn test_restore_keeps_identity_contacts_and_history() {
+    LocalSet::new().run_until(run_restore_scenario()).await;
+}
+
+async fn run_restore_scenario() {
+    let dirs: Vec<TempDir> = (0..4).map(|_| TempDir::new().unwrap()).collect();
+    let alice = TestNode::start(dirs[0].path()).await;
+    let bob = TestNode::start(dirs[1].path()).await;
+    let alice_id = alice.peer_id();
+    let bob_id = bob.peer_id().to_string();
+
+    let alice_bundle = alice.client.get_prekey_bundle_json().await.unwrap();
+    bob.client
+        .set_contact_prekey_bundle(alice_id.to_string(), alice_bundle.clone())
+        .unwrap();
+    connect(&bob, &alice).await;
+    bob.client
+        .send_text_message(alice_id, "before the backup".to_string())
+        .await
+        .unwrap();
+    alice.wait_for_message("before the backup").await;
+    // Every bundle gets its own one-time prekey
+    let carol_bundle = alice.client.get_prekey_bundle_json().await.unwrap();
+    assert_ne!(carol_bundle, alice_bundle);
+
+    let backup = alice.client.export_backup(PASSPHRASE, true).await.unwrap();
+    let without_history = alice.client.export_backup(PASSPHRASE, false).await.unwrap();
+    alice.shutdown();
+
+    // A wrong passphrase, or a directory holding another identity, fails
+    let wrong = ClientBuilder::new()
+        .data_dir(dirs[2].path().to_path_buf())
+        .restore_from_backup(backup.clone(), "wrong horse")
+        .build()
+        .await;
+    assert!(wrong.is_err());
+    let occupied = ClientBuilder::new()
+        .data_dir(dirs[1].path().to_path_buf())
+        .restore_from_backup(backup.clone(), PASSPHRASE)
+        .build()
+        .await;
+    assert!(occupied.is_err());
+
+    let restored = TestNode::start_with(
+        ClientBuilder::new()
+            .data_dir(dirs[2].path().to_path_buf())
+            .restore_from_backup(backup, PASSPHRASE),
+    )
+    .await;
+    assert_eq!(restored.peer_id(), alice_id);
+    // Same signed prekey, and Carol's one-time prekey stays hers
+    let restored_bundle = restored.client.get_prekey_bundle_json().await.unwrap();
+    assert_eq!(
+        bundle_field(&restored_bundle, "signed_prekey_id"),
+        bundle_field(&carol_bundle, "signed_prekey_id")
+    );
+    assert_ne!(
+        bundle_field(&restored_bundle, "one_time_prekey"),
+        bundle_field(&carol_bundle, "one_time_prekey")
+    );
+    let bob_contact = restored.client.database().get_contact_by_peer_id(&bob_id).unwrap();
+    assert_eq!(bob_contact.peer_id, bob_id);
+    let history = restored
+        .client
+        .get_conversation_messages(&bob_id, None, None)
+        .unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].sender_peer_id, bob_id);
+    assert_eq!(history[0].content_plaintext.as_deref(), Some("before the backup"));
+
+    // Carol got the bundle before the backup; the restored prekeys answer her
+    let carol = TestNode::start(dirs[3].path()).await;
+    carol
+        .client
+        .set_contact_prekey_bundle(alice_id.to_string(), carol_bundle)
+        .unwrap();
+    connect(&carol, &restored).await;
+    carol
+        .client
+        .send_text_message(alice_id, "hi restored Alice".to_string())
+        .await
+        .unwrap();
+    restored.wait_for_message("hi restored Alice").await;
+    restored.shutdown();
+    carol.shutdown();
+
+    // The identity stays with the directory after a restart
+    let restarted = ClientBuilder::new()
+        .data_dir(dirs[2].path().to_path_buf())
+        .build()
+        .await
+        .unwrap();
+    assert_eq!(restarted.local_peer_id(), alice_id);
+
+    // History is only there when asked for
+    let fresh = TempDir::new().unwrap();
+    let contacts_only = ClientBuilder::new()
+        .data_dir(fresh.path().to_path_buf())
+        .restore_from_backup(without_history, PASSPHRASE)
+        .build()
+        .await
+        .unwrap();
+    assert_eq!(contacts_only.local_peer_id(), alice_id);
+    assert!(contacts_only.database().get_contact_by_peer_id(&bob_id).is_ok());
+    assert!(contacts_only
+        .get_conversation_messages(&bob_id, None, None)
+        .unwrap()
+        .is_empty());
+}
+
+fn bundle_field(bundle_json: &str, field: &str) -> serde_json::Value {
+    let bundle: serde_json::Value = serde_json::from_str(bundle_json).unwrap();
+    bundle[field].clone()
+}
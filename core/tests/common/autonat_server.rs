@@ -0,0 +1,115 @@
+//! Stand-in for the bootstrap node's AutoNAT server
+//!
+//! Clients only send AutoNAT probes, so tests that need a confirmed
+//! reachability run this server (the bootstrap node's dial-back logic on a
+//! bare swarm) and connect to it.
+
+use futures::StreamExt;
+use libp2p::{
+    identify, noise,
+    request_response::{self, Message},
+    swarm::{NetworkBehaviour, SwarmEvent},
+    tcp, yamux, Multiaddr, PeerId, SwarmBuilder,
+};
+use mepassa_autonat::{AutoNatCodec, DialBackServer, DialResponse, ResponseStatus};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+#[derive(NetworkBehaviour)]
+struct ServerBehaviour {
+    identify: identify::Behaviour,
+    autonat: request_response::Behaviour<AutoNatCodec>,
+}
+
+/// Running dial-back server
+pub struct AutoNatServer {
+    pub peer_id: PeerId,
+    pub addr: Multiaddr,
+    task: JoinHandle<()>,
+}
+
+impl AutoNatServer {
+    /// Listen on localhost and answer probes (local addresses included)
+    pub async fn start() -> Self {
+        let mut swarm = SwarmBuilder::with_new_identity()
+            .with_tokio()
+            .with_tcp(tcp::Config::default(), noise::Config::new, yamux::Config::default)
+            .unwrap()
+            .with_behaviour(|key| ServerBehaviour {
+                identify: identify::Behaviour::new(identify::Config::new(
+                    "/mepassa/1.0.0".to_string(),
+                    key.public(),
+                )),
+                autonat: mepassa_autonat::server_behaviour(),
+            })
+            .unwrap()
+            .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
+            .build();
+        let peer_id = *swarm.local_peer_id();
+        swarm.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+
+        let (addr_tx, addr_rx) = oneshot::channel();
+        let task = tokio::spawn(async move {
+            let mut addr_tx = Some(addr_tx);
+            let mut dial_back = DialBackServer::default();
+            loop {
+                match swarm.select_next_some().await {
+                    SwarmEvent::NewListenAddr { address, .. } => {
+                        if let Some(tx) = addr_tx.take() {
+                            let _ = tx.send(address);
+                        }
+                    }
+                    SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint, .. } => {
+                        dial_back.connection_established(peer_id, endpoint.get_remote_address());
+                        if let Some(channel) = dial_back.dial_finished(&connection_id) {
+                            let reached = mepassa_autonat::reached_addr(endpoint.get_remote_address());
+                            let _ = swarm
+                                .behaviour_mut()
+                                .autonat
+                                .send_response(channel, DialResponse::Reachable(reached));
+                            swarm.close_connection(connection_id);
+                        }
+                    }
+                    SwarmEvent::ConnectionClosed { peer_id, num_established: 0, .. } => {
+                        dial_back.peer_disconnected(&peer_id);
+                    }
+                    SwarmEvent::OutgoingConnectionError { connection_id, error, .. } => {
+                        if let Some(channel) = dial_back.dial_finished(&connection_id) {
+                            let response = DialResponse::failed(ResponseStatus::DialError, error.to_string());
+                            let _ = swarm.behaviour_mut().autonat.send_response(channel, response);
+                        }
+                    }
+                    SwarmEvent::Behaviour(ServerBehaviourEvent::Autonat(request_response::Event::Message {
+                        peer,
+                        message: Message::Request { request, channel, .. },
+                    })) => match dial_back.dial_back_opts(peer, &request, false) {
+                        Ok(opts) => {
+                            let connection_id = opts.connection_id();
+                            match swarm.dial(opts) {
+                                Ok(()) => dial_back.dial_started(connection_id, peer, channel),
+                                Err(e) => {
+                                    let response = DialResponse::failed(ResponseStatus::DialError, e.to_string());
+                                    let _ = swarm.behaviour_mut().autonat.send_response(channel, response);
+                                }
+                            }
+                        }
+                        Err(response) => {
+                            let _ = swarm.behaviour_mut().autonat.send_response(channel, response);
+                        }
+                    },
+                    _ => {}
+                }
+            }
+        });
+
+        let addr = addr_rx.await.expect("AutoNAT server never started listening");
+        Self { peer_id, addr, task }
+    }
+}
+
+impl Drop for AutoNatServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
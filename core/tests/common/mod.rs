@@ -0,0 +1,156 @@
+//! Fixtures shared by the client integration tests
+//!
+//! Each test binary uses a different part of this module.
+#![allow(dead_code)]
+
+pub mod autonat_server;
+
+use libp2p::{Multiaddr, PeerId};
+use mepassa_core::api::{Client, ClientBuilder, ClientEvent, FunctionCallback};
+use mepassa_core::protocol::pb::message::Payload;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::task::JoinHandle;
+
+/// Running client, the events it emitted and the tasks driving it
+pub struct TestNode {
+    pub client: Rc<Client>,
+    events: Arc<Mutex<Vec<ClientEvent>>>,
+    tasks: Vec<JoinHandle<()>>,
+    _data_dir: Option<TempDir>,
+}
+
+impl TestNode {
+    pub async fn start(data_dir: &Path) -> Self {
+        Self::start_with(ClientBuilder::new().data_dir(data_dir.to_path_buf())).await
+    }
+
+    /// Node in a temporary data directory that lives as long as the node
+    pub async fn start_temporary() -> Self {
+        let data_dir = TempDir::new().unwrap();
+        let mut node = Self::start(data_dir.path()).await;
+        node._data_dir = Some(data_dir);
+        node
+    }
+
+    pub async fn start_with(builder: ClientBuilder) -> Self {
+        let client = Rc::new(builder.build().await.expect("Failed to build client"));
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_cb = Arc::clone(&events);
+        client
+            .register_callback(FunctionCallback::new(move |event| {
+                events_cb.lock().unwrap().push(event);
+            }))
+            .await;
+
+        client
+            .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .await
+            .expect("Failed to listen");
+
+        let client_for_network = Rc::clone(&client);
+        // The network future is not Send, so drive it on the local set (like the FFI does)
+        let network_task = tokio::task::spawn_local(async move {
+            loop {
+                match client_for_network.poll_network_once().await {
+                    Ok(true) => {}
+                    _ => tokio::time::sleep(Duration::from_millis(10)).await,
+                }
+            }
+        });
+
+        Self {
+            client,
+            events,
+            tasks: vec![network_task],
+            _data_dir: None,
+        }
+    }
+
+    /// Also drive the outbox, like the FFI does once the client is up
+    pub fn run_outbox(&mut self) {
+        let client_for_outbox = Rc::clone(&self.client);
+        self.tasks.push(tokio::task::spawn_local(async move {
+            client_for_outbox.run_outbox().await;
+        }));
+    }
+
+    pub fn peer_id(&self) -> PeerId {
+        self.client.local_peer_id()
+    }
+
+    pub async fn listen_addr(&self) -> Multiaddr {
+        for _ in 0..100 {
+            if let Some(addr) = self.client.listening_addresses().await.first() {
+                return addr.parse().unwrap();
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("Client never reported a listening address");
+    }
+
+    /// Every event emitted so far, in order
+    pub fn events(&self) -> Vec<ClientEvent> {
+        self.events.lock().unwrap().clone()
+    }
+
+    /// Contents of the text messages received so far
+    pub fn received_texts(&self) -> Vec<String> {
+        self.events()
+            .into_iter()
+            .filter_map(|event| match event {
+                ClientEvent::MessageReceived { message, .. } => match message.payload {
+                    Some(Payload::Text(text)) => Some(text.content),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub async fn wait_for_message(&self, expected: &str) {
+        for _ in 0..250 {
+            if self.received_texts().iter().any(|m| m == expected) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("Message {:?} was not received", expected);
+    }
+
+    pub async fn wait_until(&self, what: &str, done: impl Fn(&Client) -> bool) {
+        for _ in 0..500 {
+            if done(&self.client) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("{} never happened on {}", what, self.peer_id());
+    }
+
+    pub fn shutdown(self) {
+        for task in self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Dial `to` and wait until the connection is up
+pub async fn connect(from: &TestNode, to: &TestNode) {
+    from.client
+        .connect_to_peer(to.peer_id(), to.listen_addr().await)
+        .await
+        .expect("Failed to dial");
+
+    for _ in 0..250 {
+        if from.client.connection_quality(&to.peer_id()).await.open_connections > 0 {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("Peers never connected");
+}
@@ -0,0 +1,254 @@
+//! Event Listener Integration Test
+//!
+//! Every `ClientEvent` emitted on the bus reaches an FFI
+//! `MePassaEventListener`, a Rust callback and an event stream, converted
+//! and in emission order.
+
+use futures::StreamExt;
+use libp2p::PeerId;
+use mepassa_core::api::{ClientEvent, EventBus, FunctionCallback};
+use mepassa_core::network::Reachability;
+use mepassa_core::protocol::pb::message::Payload;
+use mepassa_core::protocol::{Message, MessageType, TextMessage};
+use mepassa_core::{FfiClientEvent, FfiReachability, MePassaEventListener};
+use std::sync::{Arc, Mutex};
+
+/// Variant name; the match is exhaustive, so new events must be added below
+fn name(event: &ClientEvent) -> &'static str {
+    match event {
+        ClientEvent::MessageReceived { .. } => "MessageReceived",
+        ClientEvent::MessageQueued { .. } => "MessageQueued",
+        ClientEvent::MessageSent { .. } => "MessageSent",
+        ClientEvent::PlaintextFallback { .. } => "PlaintextFallback",
+        ClientEvent::MessageDelivered { .. } => "MessageDelivered",
+        ClientEvent::MessageFailed { .. } => "MessageFailed",
+        ClientEvent::MessageRead { .. } => "MessageRead",
+        ClientEvent::TypingStarted { .. } => "TypingStarted",
+        ClientEvent::TypingStopped { .. } => "TypingStopped",
+        ClientEvent::MediaProgress { .. } => "MediaProgress",
+        ClientEvent::PeerConnected { .. } => "PeerConnected",
+        ClientEvent::PeerDisconnected { .. } => "PeerDisconnected",
+        ClientEvent::PeerDiscovered { .. } => "PeerDiscovered",
+        ClientEvent::NetworkOnline => "NetworkOnline",
+        ClientEvent::NetworkOffline => "NetworkOffline",
+        ClientEvent::ReachabilityChanged { .. } => "ReachabilityChanged",
+        ClientEvent::GroupCreated { .. } => "GroupCreated",
+        ClientEvent::GroupJoined { .. } => "GroupJoined",
+        ClientEvent::GroupLeft { .. } => "GroupLeft",
+        ClientEvent::GroupUpdated { .. } => "GroupUpdated",
+        ClientEvent::GroupMemberAdded { .. } => "GroupMemberAdded",
+        ClientEvent::GroupMemberRemoved { .. } => "GroupMemberRemoved",
+        ClientEvent::GroupMessageReceived { .. } => "GroupMessageReceived",
+        ClientEvent::GroupMessageRejected { .. } => "GroupMessageRejected",
+        ClientEvent::DeviceLinked { .. } => "DeviceLinked",
+        ClientEvent::DeviceSynced { .. } => "DeviceSynced",
+        ClientEvent::IncomingCall { .. } => "IncomingCall",
+        ClientEvent::CallStateChanged { .. } => "CallStateChanged",
+        ClientEvent::CallEnded { .. } => "CallEnded",
+        ClientEvent::Error { .. } => "Error",
+    }
+}
+
+fn every_event(peer: PeerId) -> Vec<ClientEvent> {
+    let id = || "m1".to_string();
+    let group = || "g1".to_string();
+    vec![
+        ClientEvent::MessageReceived {
+            message_id: id(),
+            from: peer,
+            message: Message {
+                id: id(),
+                sender_peer_id: peer.to_string(),
+                recipient_peer_id: String::new(),
+                timestamp: 1,
+                r#type: MessageType::Text as i32,
+                payload: Some(Payload::Text(TextMessage {
+                    content: "hi".to_string(),
+                    reply_to_id: String::new(),
+                    metadata: Default::default(),
+                })),
+            },
+        },
+        ClientEvent::MessageQueued {
+            message_id: id(),
+            to: peer,
+        },
+        ClientEvent::MessageSent {
+            message_id: id(),
+            to: peer,
+        },
+        ClientEvent::PlaintextFallback {
+            message_id: id(),
+            to: peer,
+            reason: "no session".to_string(),
+        },
+        ClientEvent::MessageDelivered {
+            message_id: id(),
+            to: peer,
+        },
+        ClientEvent::MessageFailed {
+            message_id: id(),
+            to: peer,
+            reason: "expired".to_string(),
+        },
+        ClientEvent::MessageRead {
+            message_id: id(),
+            by: peer,
+            read_at: 2,
+        },
+        ClientEvent::TypingStarted { peer_id: peer },
+        ClientEvent::TypingStopped { peer_id: peer },
+        ClientEvent::MediaProgress {
+            message_id: id(),
+            media_hash: "h".to_string(),
+            bytes_received: 1,
+            total_bytes: 2,
+        },
+        ClientEvent::PeerConnected { peer_id: peer },
+        ClientEvent::PeerDisconnected { peer_id: peer },
+        ClientEvent::PeerDiscovered {
+            peer_id: peer,
+            addresses: vec!["/ip4/10.0.0.2/tcp/4001".to_string()],
+        },
+        ClientEvent::NetworkOnline,
+        ClientEvent::NetworkOffline,
+        ClientEvent::ReachabilityChanged {
+            reachability: Reachability::Public("/ip4/1.2.3.4/tcp/4001".parse().unwrap()),
+        },
+        ClientEvent::GroupCreated {
+            group_id: group(),
+            name: "Family".to_string(),
+        },
+        ClientEvent::GroupJoined { group_id: group() },
+        ClientEvent::GroupLeft { group_id: group() },
+        ClientEvent::GroupUpdated {
+            group_id: group(),
+            name: "Friends".to_string(),
+        },
+        ClientEvent::GroupMemberAdded {
+            group_id: group(),
+            peer_id: peer,
+        },
+        ClientEvent::GroupMemberRemoved {
+            group_id: group(),
+            peer_id: peer,
+        },
+        ClientEvent::GroupMessageReceived {
+            group_id: group(),
+            message_id: id(),
+            from: peer,
+            content: b"hello group".to_vec(),
+            timestamp: 3,
+        },
+        ClientEvent::GroupMessageRejected {
+            group_id: group(),
+            message_id: id(),
+            from: peer,
+            reason: "not a member".to_string(),
+        },
+        ClientEvent::DeviceLinked {
+            account_peer_id: peer,
+            device_peer_id: peer,
+        },
+        ClientEvent::DeviceSynced {
+            device_peer_id: peer,
+            messages: 2,
+            changes: 1,
+        },
+        ClientEvent::IncomingCall {
+            call_id: "c1".to_string(),
+            from: peer,
+        },
+        ClientEvent::CallStateChanged {
+            call_id: "c1".to_string(),
+            state: "Active".to_string(),
+        },
+        ClientEvent::CallEnded {
+            call_id: "c1".to_string(),
+            reason: "Hangup".to_string(),
+        },
+        ClientEvent::Error {
+            error: "boom".to_string(),
+        },
+    ]
+}
+
+struct RecordingListener {
+    events: Arc<Mutex<Vec<FfiClientEvent>>>,
+}
+
+impl MePassaEventListener for RecordingListener {
+    fn on_event(&self, event: FfiClientEvent) {
+        self.events.lock().unwrap().push(event);
+    }
+}
+
+#[tokio::test]
+async fn test_every_event_reaches_every_consumer_in_order() {
+    let peer = PeerId::random();
+    let events = every_event(peer);
+    let names: Vec<&str> = events.iter().map(name).collect();
+    // One of each variant
+    let mut unique = names.clone();
+    unique.sort();
+    unique.dedup();
+    assert_eq!(unique.len(), names.len());
+
+    let bus = EventBus::new();
+    let ffi_events = Arc::new(Mutex::new(Vec::new()));
+    let listener: Box<dyn MePassaEventListener> = Box::new(RecordingListener {
+        events: Arc::clone(&ffi_events),
+    });
+    bus.add_callback(Arc::new(listener));
+    let rust_names = Arc::new(Mutex::new(Vec::new()));
+    let rust_names_cb = Arc::clone(&rust_names);
+    bus.add_callback(Arc::new(FunctionCallback::new(move |event| {
+        rust_names_cb.lock().unwrap().push(name(&event));
+    })));
+    let mut stream = bus.subscribe();
+
+    for event in events {
+        bus.emit(event);
+    }
+
+    assert_eq!(*rust_names.lock().unwrap(), names);
+    let mut streamed = Vec::new();
+    for _ in 0..names.len() {
+        streamed.push(name(&stream.next().await.unwrap()));
+    }
+    assert_eq!(streamed, names);
+
+    let ffi_events = ffi_events.lock().unwrap();
+    assert_eq!(ffi_events.len(), names.len());
+    assert_eq!(
+        ffi_events[0],
+        FfiClientEvent::MessageReceived {
+            message_id: "m1".to_string(),
+            from_peer_id: peer.to_string(),
+            text: Some("hi".to_string()),
+        }
+    );
+    assert_eq!(
+        ffi_events[15],
+        FfiClientEvent::ReachabilityChanged {
+            reachability: FfiReachability::Public,
+            public_address: Some("/ip4/1.2.3.4/tcp/4001".to_string()),
+        }
+    );
+    assert_eq!(
+        ffi_events[22],
+        FfiClientEvent::GroupMessageReceived {
+            group_id: "g1".to_string(),
+            message_id: "m1".to_string(),
+            from_peer_id: peer.to_string(),
+            content: b"hello group".to_vec(),
+            timestamp: 3,
+        }
+    );
+    assert_eq!(
+        *ffi_events.last().unwrap(),
+        FfiClientEvent::Error {
+            error: "boom".to_string()
+        }
+    );
+}
@@ -0,0 +1,85 @@
+//! Frame Limit Integration Test
+//!
+//! A peer keeps sending messages larger than the receiver's frame limit. Each
+//! one is rejected before it is read, and after the third the receiver drops
+//! the connection.
+
+use libp2p::{identity::Keypair, Multiaddr};
+use mepassa_core::network::{FrameLimits, NetworkManager};
+use mepassa_core::protocol::{pb::message::Payload, Message, MessageType, TextMessage};
+use std::time::Duration;
+use tokio::time::sleep;
+
+fn text_message(from: &NetworkManager, content: String) -> Message {
+    Message {
+        id: uuid::Uuid::new_v4().to_string(),
+        sender_peer_id: from.local_peer_id().to_string(),
+        recipient_peer_id: String::new(),
+        timestamp: chrono::Utc::now().timestamp_millis(),
+        r#type: MessageType::Text as i32,
+        payload: Some(Payload::Text(TextMessage {
+            content,
+            reply_to_id: String::new(),
+            metadata: std::collections::HashMap::new(),
+        })),
+    }
+}
+
+/// Poll both peers until `done` holds (or give up after ~5 seconds)
+async fn drive(
+    a: &mut NetworkManager,
+    b: &mut NetworkManager,
+    done: impl Fn(&NetworkManager, &NetworkManager) -> bool,
+) -> bool {
+    for _ in 0..1000 {
+        if done(a, b) {
+            return true;
+        }
+        let progressed_a = a.poll_once().await.unwrap_or(false);
+        let progressed_b = b.poll_once().await.unwrap_or(false);
+        if !progressed_a && !progressed_b {
+            sleep(Duration::from_millis(5)).await;
+        }
+    }
+    done(a, b)
+}
+
+#[tokio::test]
+async fn test_peer_sending_oversized_frames_is_disconnected() {
+    let _ = tracing_subscriber::fmt().with_max_level(tracing::Level::INFO).try_init();
+
+    let mut sender = NetworkManager::new(Keypair::generate_ed25519()).unwrap();
+    let mut receiver = NetworkManager::with_frame_limits(
+        Keypair::generate_ed25519(),
+        FrameLimits {
+            message: 256,
+            ..FrameLimits::default()
+        },
+    )
+    .unwrap();
+    let receiver_id = *receiver.local_peer_id();
+
+    receiver
+        .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    assert!(drive(&mut sender, &mut receiver, |_, r| !r.listening_addresses().is_empty()).await);
+    let addr: Multiaddr = receiver.listening_addresses()[0].clone();
+
+    sender.dial(receiver_id, addr).unwrap();
+    assert!(drive(&mut sender, &mut receiver, |s, _| s.is_connected(&receiver_id)).await);
+
+    // Small messages still go through
+    sender
+        .send_message(receiver_id, text_message(&sender, "hi".to_string()))
+        .unwrap();
+
+    for _ in 0..3 {
+        let oversized = text_message(&sender, "x".repeat(1024));
+        sender.send_message(receiver_id, oversized).unwrap();
+    }
+
+    assert!(
+        drive(&mut sender, &mut receiver, |s, _| !s.is_connected(&receiver_id)).await,
+        "Receiver kept the connection to a peer sending oversized frames"
+    );
+}
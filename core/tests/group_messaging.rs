@@ -0,0 +1,217 @@
+//! Group Messaging Integration Test
+//!
+//! Three clients join a group and exchange sender-key encrypted messages
+//! over GossipSub. Sender keys are distributed automatically over the
+//! pairwise E2E sessions, and rotated when a member is removed.
+
+mod common;
+
+use common::{connect, TestNode};
+use mepassa_core::group::GroupEvent;
+use std::ops::Deref;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::task::LocalSet;
+
+/// Test node that also watches its group events
+struct GroupNode {
+    node: TestNode,
+    group_events: UnboundedReceiver<GroupEvent>,
+}
+
+impl Deref for GroupNode {
+    type Target = TestNode;
+
+    fn deref(&self) -> &TestNode {
+        &self.node
+    }
+}
+
+impl GroupNode {
+    async fn start() -> Self {
+        let node = TestNode::start_temporary().await;
+        let group_events = node
+            .client
+            .take_group_event_receiver()
+            .await
+            .expect("Group event receiver already taken");
+        Self { node, group_events }
+    }
+
+    fn peer_id(&self) -> String {
+        self.node.peer_id().to_string()
+    }
+
+    /// Publish, retrying while GossipSub has no subscribed peers for the topic yet
+    async fn send_group_message(&self, group_id: &str, content: &str) -> String {
+        for _ in 0..100 {
+            match self
+                .client
+                .send_group_message(group_id.to_string(), content.to_string())
+                .await
+            {
+                Ok(message_id) => return message_id,
+                Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+            }
+        }
+        panic!("Could not publish {:?} to group {}", content, group_id);
+    }
+
+    /// Wait until we hold the sender keys of `count` members (ourselves included)
+    async fn wait_for_sender_keys(&self, group_id: &str, count: usize) {
+        for _ in 0..250 {
+            let keys = self.client.database().list_sender_keys().unwrap();
+            if keys.iter().filter(|k| k.group_id == group_id).count() >= count {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("Sender keys for group {} were not distributed", group_id);
+    }
+
+    async fn wait_for_group_event(&mut self, matches: impl Fn(&GroupEvent) -> bool) {
+        tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                match self.group_events.recv().await {
+                    Some(event) if matches(&event) => return,
+                    Some(_) => {}
+                    None => panic!("Group event channel closed"),
+                }
+            }
+        })
+        .await
+        .expect("Group event was not emitted");
+    }
+
+    async fn wait_for_group_message(&mut self, expected: &str) -> String {
+        self.try_wait_for_group_message(expected, Duration::from_secs(10))
+            .await
+            .unwrap_or_else(|| panic!("Group message {:?} was not received", expected))
+    }
+
+    /// Sender of the message with the expected content, if it arrives in time
+    async fn try_wait_for_group_message(&mut self, expected: &str, wait: Duration) -> Option<String> {
+        let received = tokio::time::timeout(wait, async {
+            loop {
+                match self.group_events.recv().await {
+                    Some(GroupEvent::MessageReceived { message, content }) => {
+                        if content == expected.as_bytes() {
+                            return message;
+                        }
+                    }
+                    Some(_) => {}
+                    None => panic!("Group event channel closed"),
+                }
+            }
+        })
+        .await
+        .ok()?;
+
+        Some(received.sender_peer_id)
+    }
+
+    fn shutdown(self) {
+        self.node.shutdown();
+    }
+}
+
+#[tokio::test]
+async fn test_group_messages_over_gossipsub() {
+    LocalSet::new().run_until(run_group_scenario()).await;
+}
+
+async fn run_group_scenario() {
+    let _ = tracing_subscriber::fmt().with_max_level(tracing::Level::INFO).try_init();
+
+    let mut alice = GroupNode::start().await;
+    let mut bob = GroupNode::start().await;
+    let mut carol = GroupNode::start().await;
+
+    // Exchange prekey bundles out of band so every pair can open an E2E session;
+    // each export carries its own one-time prekey
+    let nodes = [&alice, &bob, &carol];
+    for owner in nodes {
+        for other in nodes {
+            if other.peer_id() != owner.peer_id() {
+                let bundle = owner.client.get_prekey_bundle_json().await.unwrap();
+                other
+                    .client
+                    .set_contact_prekey_bundle(owner.peer_id(), bundle)
+                    .unwrap();
+            }
+        }
+    }
+
+    connect(&bob, &alice).await;
+    connect(&carol, &alice).await;
+    connect(&carol, &bob).await;
+
+    // Alice creates the group and invites Bob and Carol; the invitations carry
+    // her sender key and the members answer with theirs
+    let group = alice
+        .client
+        .create_group("Team".to_string(), None)
+        .await
+        .unwrap();
+    for member in [&bob, &carol] {
+        alice
+            .client
+            .add_group_member(group.id.clone(), member.peer_id())
+            .await
+            .unwrap();
+    }
+    for node in nodes {
+        node.wait_for_sender_keys(&group.id, 3).await;
+    }
+    let joined = carol.client.get_groups().await.unwrap();
+    assert!(joined.iter().any(|g| g.id == group.id));
+
+    let message_id = alice.send_group_message(&group.id, "hello group").await;
+    assert_eq!(bob.wait_for_group_message("hello group").await, alice.peer_id());
+    assert_eq!(carol.wait_for_group_message("hello group").await, alice.peer_id());
+
+    bob.send_group_message(&group.id, "hi from bob").await;
+    assert_eq!(alice.wait_for_group_message("hi from bob").await, bob.peer_id());
+    assert_eq!(carol.wait_for_group_message("hi from bob").await, bob.peer_id());
+
+    // Received messages are persisted in the group conversation
+    let conversation_id = format!("group:{}", group.id);
+    let stored = carol
+        .client
+        .database()
+        .get_conversation_messages(&conversation_id, None, None)
+        .unwrap();
+    assert_eq!(stored.len(), 2);
+    assert!(stored.iter().any(|m| m.message_id == message_id));
+
+    // Removing Carol rotates the sender keys of everyone who stays; Bob's
+    // messages become readable by Alice once his new key reached her
+    alice
+        .client
+        .remove_group_member(group.id.clone(), carol.peer_id())
+        .await
+        .unwrap();
+    carol.wait_for_group_event(|e| matches!(e, GroupEvent::GroupLeft { .. })).await;
+    bob.wait_for_group_event(|e| matches!(e, GroupEvent::MemberRemoved { .. })).await;
+    for node in [&alice, &bob] {
+        node.wait_for_sender_keys(&group.id, 2).await;
+    }
+
+    // Messages encrypted before Bob's new key reached Alice are dropped, so retry
+    let mut sender = None;
+    for attempt in 0..20 {
+        let content = format!("after carol #{}", attempt);
+        bob.send_group_message(&group.id, &content).await;
+        sender = alice
+            .try_wait_for_group_message(&content, Duration::from_millis(500))
+            .await;
+        if sender.is_some() {
+            break;
+        }
+    }
+    assert_eq!(sender, Some(bob.peer_id()));
+
+    alice.shutdown();
+    bob.shutdown();
+    carol.shutdown();
+}
@@ -361,7 +361,7 @@ async fn test_ack_handling() {
 
     // Process ACK
     handler
-        .handle_outgoing_ack(ack)
+        .handle_outgoing_ack(&remote_peer_id, ack)
         .await
         .expect("Failed to handle ACK");
 
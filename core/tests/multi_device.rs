@@ -0,0 +1,426 @@
+//! Multi-Device Integration Tests
+//!
+//! Alice links a second device by scanning its link request. Bob finds it
+//! in the device directory and his message reaches both of Alice's devices;
+//! Alice's reply from the linked device reaches Bob as sent by her account,
+//! with a copy on her primary device. Another account that gets hold of the
+//! link request can't take the device over.
+//!
+//! A device linked after a conversation started gets its recent history and
+//! state, and later changes on either device reach the other.
+//!
+//! A device that rotated its signed prekey republishes its bundle, and a
+//! sender holding the old one fetches it again.
+
+mod common;
+
+use async_trait::async_trait;
+use common::{connect, TestNode};
+use libp2p::PeerId;
+use mepassa_core::api::{Client, ClientBuilder, ClientEvent};
+use mepassa_core::identity::SignedPreKeyPolicy;
+use mepassa_core::network::retry::RetryPolicy;
+use mepassa_core::storage::MessageStatus;
+use mepassa_core::sync::{DeviceDirectory, LinkedDevice};
+use mepassa_core::utils::error::Result;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::task::LocalSet;
+
+/// In-memory stand-in for the identity server's device endpoints
+#[derive(Default)]
+struct MemoryDirectory {
+    devices: Mutex<HashMap<PeerId, Vec<LinkedDevice>>>,
+}
+
+#[async_trait]
+impl DeviceDirectory for MemoryDirectory {
+    async fn publish_device(&self, device: &LinkedDevice) -> Result<()> {
+        let mut devices = self.devices.lock().unwrap();
+        let listed = devices.entry(device.certificate.account_peer_id).or_default();
+        listed.retain(|known| known.certificate.device_peer_id != device.certificate.device_peer_id);
+        listed.push(device.clone());
+        Ok(())
+    }
+
+    async fn fetch_devices(&self, account: &PeerId) -> Result<Vec<LinkedDevice>> {
+        Ok(self
+            .devices
+            .lock()
+            .unwrap()
+            .get(account)
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+async fn start(data_dir: &Path, directory: Arc<MemoryDirectory>) -> TestNode {
+    start_with(data_dir, directory, ClientBuilder::new()).await
+}
+
+async fn start_with(
+    data_dir: &Path,
+    directory: Arc<MemoryDirectory>,
+    builder: ClientBuilder,
+) -> TestNode {
+    let mut node = TestNode::start_with(
+        builder
+            .data_dir(data_dir.to_path_buf())
+            .retry_policy(RetryPolicy::new(
+                10,
+                Duration::from_millis(50),
+                Duration::from_millis(200),
+            ))
+            .device_directory(directory),
+    )
+    .await;
+    node.run_outbox();
+    node
+}
+
+/// (account, device) of every DeviceLinked event so far
+fn linked(node: &TestNode) -> Vec<(PeerId, PeerId)> {
+    node.events()
+        .into_iter()
+        .filter_map(|event| match event {
+            ClientEvent::DeviceLinked {
+                account_peer_id,
+                device_peer_id,
+            } => Some((account_peer_id, device_peer_id)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// (device, messages, changes) of every DeviceSynced event so far
+fn synced(node: &TestNode) -> Vec<(PeerId, u32, u32)> {
+    node.events()
+        .into_iter()
+        .filter_map(|event| match event {
+            ClientEvent::DeviceSynced {
+                device_peer_id,
+                messages,
+                changes,
+            } => Some((device_peer_id, messages, changes)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn has_message(client: &Client, message_id: &str) -> bool {
+    client.database().get_message(message_id).is_ok()
+}
+
+/// Link `device` to the account of `primary`
+async fn link(primary: &TestNode, device: &TestNode, name: &str) {
+    // Wait for the device to listen so the request carries its addresses
+    while device.client.listening_addresses().await.is_empty() {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let payload = device.client.device_link_request(name).await.unwrap();
+    primary.client.link_device(&payload).await.unwrap();
+    let account = primary.client.account_peer_id();
+    device
+        .wait_until("linking", |client| client.account_peer_id() == account)
+        .await;
+}
+
+#[tokio::test]
+async fn test_linked_device_sends_and_receives() {
+    LocalSet::new()
+        .run_until(async {
+            let directory = Arc::new(MemoryDirectory::default());
+            let dirs: Vec<TempDir> = (0..3).map(|_| TempDir::new().unwrap()).collect();
+            let phone = start(dirs[0].path(), directory.clone()).await;
+            let laptop = start(dirs[1].path(), directory.clone()).await;
+            let bob = start(dirs[2].path(), directory.clone()).await;
+            let alice = phone.peer_id();
+
+            // Wait for the laptop to listen so the request carries its addresses
+            while laptop.client.listening_addresses().await.is_empty() {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+
+            // The laptop shows a QR code, the phone scans it
+            let payload = laptop.client.device_link_request("Laptop").await.unwrap();
+            let laptop_id = phone.client.link_device(&payload).await.unwrap();
+            assert_eq!(laptop_id, laptop.peer_id());
+
+            laptop
+                .wait_until("linking", |client| client.account_peer_id() == alice)
+                .await;
+            assert_eq!(phone.client.account_peer_id(), alice);
+            assert_eq!(linked(&phone), vec![(alice, laptop_id)]);
+            assert_eq!(linked(&laptop), vec![(alice, laptop_id)]);
+            assert_eq!(directory.fetch_devices(&alice).await.unwrap().len(), 1);
+            // A linked device can't link others
+            let other = laptop.client.device_link_request("Tablet").await.unwrap();
+            assert!(laptop.client.link_device(&other).await.is_err());
+
+            // Bob knows Alice's account; her devices come from the directory
+            bob.client
+                .set_contact_prekey_bundle(
+                    alice.to_string(),
+                    phone.client.get_prekey_bundle_json().await.unwrap(),
+                )
+                .unwrap();
+            let bob_bundle = bob.client.get_prekey_bundle_json().await.unwrap();
+            for device in [&phone, &laptop] {
+                device
+                    .client
+                    .set_contact_prekey_bundle(bob.peer_id().to_string(), bob_bundle.clone())
+                    .unwrap();
+                connect(&bob, device).await;
+            }
+
+            let to_alice = bob
+                .client
+                .send_text_message(alice, "hi Alice".to_string())
+                .await
+                .unwrap();
+            for device in [&phone, &laptop] {
+                device
+                    .wait_until("Bob's message", |client| has_message(client, &to_alice))
+                    .await;
+                let received = device.client.database().get_message(&to_alice).unwrap();
+                assert_eq!(received.sender_peer_id, bob.peer_id().to_string());
+            }
+            // Both devices acknowledged their copy
+            bob.wait_until("the acknowledgments", |client| {
+                client.database().outbox_recipients(&to_alice).unwrap().is_empty()
+            })
+            .await;
+            assert_eq!(
+                bob.client.database().get_message(&to_alice).unwrap().status,
+                MessageStatus::Delivered
+            );
+
+            // The reply from the laptop is Alice's, and the phone keeps a copy
+            let to_bob = laptop
+                .client
+                .send_text_message(bob.peer_id(), "hi Bob".to_string())
+                .await
+                .unwrap();
+            bob.wait_until("Alice's reply", |client| has_message(client, &to_bob))
+                .await;
+            let received = bob.client.database().get_message(&to_bob).unwrap();
+            assert_eq!(received.sender_peer_id, alice.to_string());
+
+            phone
+                .wait_until("the copy", |client| has_message(client, &to_bob))
+                .await;
+            let copy = phone.client.database().get_message(&to_bob).unwrap();
+            assert_eq!(copy.sender_peer_id, alice.to_string());
+            assert_eq!(copy.recipient_peer_id, Some(bob.peer_id().to_string()));
+
+            laptop
+                .wait_until("the delivery", |client| {
+                    client.database().get_message(&to_bob).unwrap().status
+                        == MessageStatus::Delivered
+                })
+                .await;
+
+            phone.shutdown();
+            laptop.shutdown();
+            bob.shutdown();
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn test_foreign_account_cannot_claim_device() {
+    LocalSet::new()
+        .run_until(async {
+            let directory = Arc::new(MemoryDirectory::default());
+            let dirs: Vec<TempDir> = (0..4).map(|_| TempDir::new().unwrap()).collect();
+            let phone = start(dirs[0].path(), directory.clone()).await;
+            let laptop = start(dirs[1].path(), directory.clone()).await;
+            let mallory = start(dirs[2].path(), directory.clone()).await;
+            let bob = start(dirs[3].path(), directory.clone()).await;
+            let alice = phone.peer_id();
+
+            while laptop.client.listening_addresses().await.is_empty() {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            let payload = laptop.client.device_link_request("Laptop").await.unwrap();
+            let laptop_id = phone.client.link_device(&payload).await.unwrap();
+            laptop
+                .wait_until("linking", |client| client.account_peer_id() == alice)
+                .await;
+
+            // Mallory scanned the same QR code: the laptop consented to be
+            // linked, but it already belongs to Alice and ignores her list
+            mallory.client.link_device(&payload).await.unwrap();
+            mallory
+                .wait_until("the laptop's answer", |client| {
+                    client.database().list_outbox().unwrap().is_empty()
+                })
+                .await;
+            assert_eq!(laptop.client.account_peer_id(), alice);
+            assert_eq!(linked(&laptop), vec![(alice, laptop_id)]);
+
+            // Bob keeps the laptop as Alice's whatever the directory lists
+            assert_eq!(bob.client.refresh_devices(&alice).await.unwrap(), 1);
+            assert_eq!(bob.client.refresh_devices(&mallory.peer_id()).await.unwrap(), 0);
+            assert_eq!(bob.client.linked_devices(&alice).unwrap().len(), 1);
+            assert!(bob.client.linked_devices(&mallory.peer_id()).unwrap().is_empty());
+
+            phone.shutdown();
+            laptop.shutdown();
+            mallory.shutdown();
+            bob.shutdown();
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn test_linked_device_syncs_history_and_state() {
+    LocalSet::new()
+        .run_until(async {
+            let directory = Arc::new(MemoryDirectory::default());
+            let dirs: Vec<TempDir> = (0..3).map(|_| TempDir::new().unwrap()).collect();
+            let phone = start(dirs[0].path(), directory.clone()).await;
+            let laptop = start(dirs[1].path(), directory.clone()).await;
+            let bob = start(dirs[2].path(), directory.clone()).await;
+            let alice = phone.peer_id();
+            let bob_id = bob.peer_id().to_string();
+
+            // Alice and Bob talk before the laptop is linked
+            bob.client
+                .set_contact_prekey_bundle(
+                    alice.to_string(),
+                    phone.client.get_prekey_bundle_json().await.unwrap(),
+                )
+                .unwrap();
+            connect(&bob, &phone).await;
+            let earlier = bob
+                .client
+                .send_text_message(alice, "before the laptop".to_string())
+                .await
+                .unwrap();
+            phone
+                .wait_until("Bob's message", |client| has_message(client, &earlier))
+                .await;
+            phone.client.add_reaction(&earlier, "👍").unwrap();
+            phone.client.set_conversation_muted(&bob_id, true).unwrap();
+
+            // The new device gets the conversation with its state
+            link(&phone, &laptop, "Laptop").await;
+            laptop
+                .wait_until("the history", |client| {
+                    client.database().has_reaction(&earlier, &alice.to_string(), "👍").unwrap_or(false)
+                        && client
+                            .database()
+                            .get_conversation(&format!("1:1:{}", bob_id))
+                            .is_ok_and(|conversation| conversation.is_muted)
+                })
+                .await;
+            let history = laptop.client.get_conversation_messages(&bob_id, None, None).unwrap();
+            assert_eq!(history.len(), 1);
+            assert_eq!(history[0].sender_peer_id, bob_id);
+            assert_eq!(history[0].content_plaintext.as_deref(), Some("before the laptop"));
+            let synced = synced(&laptop);
+            assert!(synced.iter().all(|(from, _, _)| *from == alice));
+            assert_eq!(synced.iter().map(|(_, messages, _)| messages).sum::<u32>(), 1);
+            assert_eq!(synced.iter().map(|(_, _, changes)| changes).sum::<u32>(), 2);
+
+            // Changes made on either device reach the other
+            laptop.client.remove_reaction(&earlier, "👍").unwrap();
+            laptop.client.delete_message(&earlier).unwrap();
+            phone.client.set_conversation_archived(&bob_id, true).unwrap();
+            phone
+                .wait_until("the laptop's changes", |client| {
+                    client.database().get_message(&earlier).unwrap().is_deleted
+                        && client.get_message_reactions(&earlier).unwrap().is_empty()
+                })
+                .await;
+            laptop
+                .wait_until("the phone's change", |client| {
+                    client.list_conversations().unwrap().is_empty()
+                })
+                .await;
+
+            phone.shutdown();
+            laptop.shutdown();
+            bob.shutdown();
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn test_rotated_device_bundle_fetched_again() {
+    LocalSet::new()
+        .run_until(async {
+            let directory = Arc::new(MemoryDirectory::default());
+            let dirs: Vec<TempDir> = (0..3).map(|_| TempDir::new().unwrap()).collect();
+            let phone = start(dirs[0].path(), directory.clone()).await;
+            // Every check rotates; replaced signed prekeys go at the next one
+            let laptop = start_with(
+                dirs[1].path(),
+                directory.clone(),
+                ClientBuilder::new().signed_prekey_policy(SignedPreKeyPolicy {
+                    rotation_interval: Duration::ZERO,
+                    grace_period: Duration::ZERO,
+                }),
+            )
+            .await;
+            let bob = start(dirs[2].path(), directory.clone()).await;
+            let alice = phone.peer_id();
+            link(&phone, &laptop, "Laptop").await;
+
+            let published_bundle = |directory: &MemoryDirectory| {
+                directory.devices.lock().unwrap()[&alice][0]
+                    .prekey_bundle
+                    .signed_prekey_id
+            };
+
+            // Bob looks the laptop up, then it rotates past his bundle
+            bob.client
+                .set_contact_prekey_bundle(
+                    alice.to_string(),
+                    phone.client.get_prekey_bundle_json().await.unwrap(),
+                )
+                .unwrap();
+            assert_eq!(bob.client.refresh_devices(&alice).await.unwrap(), 1);
+            let stale = published_bundle(&directory);
+            for _ in 0..2 {
+                assert!(laptop.client.rotate_signed_prekey_if_due().await.unwrap());
+            }
+            assert_ne!(published_bundle(&directory), stale);
+
+            let bob_bundle = bob.client.get_prekey_bundle_json().await.unwrap();
+            for device in [&phone, &laptop] {
+                device
+                    .client
+                    .set_contact_prekey_bundle(bob.peer_id().to_string(), bob_bundle.clone())
+                    .unwrap();
+                connect(&bob, device).await;
+            }
+
+            // The laptop can't read the first copy; Bob fetches its bundle again
+            let to_alice = bob
+                .client
+                .send_text_message(alice, "after the rotation".to_string())
+                .await
+                .unwrap();
+            laptop
+                .wait_until("Bob's message", |client| has_message(client, &to_alice))
+                .await;
+            bob.wait_until("the acknowledgments", |client| {
+                client.database().outbox_recipients(&to_alice).unwrap().is_empty()
+            })
+            .await;
+            assert_eq!(
+                bob.client.database().get_message(&to_alice).unwrap().status,
+                MessageStatus::Delivered
+            );
+
+            phone.shutdown();
+            laptop.shutdown();
+            bob.shutdown();
+        })
+        .await;
+}
@@ -0,0 +1,324 @@
+//! Outbox Integration Test
+//!
+//! Messages to unreachable peers stay queued in the outbox and are retried
+//! with backoff. Once the attempts are used up they go to the
+//! store-and-forward server (or fail), and queued messages survive a restart
+//! of the sending client.
+
+mod common;
+
+use async_trait::async_trait;
+use common::{connect, TestNode};
+use libp2p::PeerId;
+use mepassa_core::api::{ClientBuilder, ClientEvent, EncryptionPolicy, OfflineMessage, OfflineStore};
+use mepassa_core::network::retry::RetryPolicy;
+use mepassa_core::protocol::Message;
+use mepassa_core::storage::MessageStatus;
+use mepassa_core::utils::error::{MePassaError, Result};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::task::LocalSet;
+
+/// Store-and-forward double that records what it was given
+#[derive(Default)]
+struct RecordingStore {
+    stored: Mutex<Vec<(PeerId, String)>>,
+    fail: bool,
+}
+
+#[async_trait]
+impl OfflineStore for RecordingStore {
+    async fn store(&self, recipient: &PeerId, message: &Message) -> Result<()> {
+        if self.fail {
+            return Err(MePassaError::Network("store unavailable".to_string()));
+        }
+        self.stored.lock().unwrap().push((*recipient, message.id.clone()));
+        Ok(())
+    }
+
+    async fn fetch(&self, _recipient: &PeerId) -> Result<Vec<OfflineMessage>> {
+        Ok(Vec::new())
+    }
+
+    async fn acknowledge(&self, _message_ids: &[String]) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Short backoff so tests don't wait on the default policy
+fn fast_policy(max_attempts: u32) -> RetryPolicy {
+    RetryPolicy::new(max_attempts, Duration::from_millis(20), Duration::from_millis(100))
+}
+
+async fn start(data_dir: &Path, builder: ClientBuilder, run_outbox: bool) -> TestNode {
+    let mut node = TestNode::start_with(builder.data_dir(data_dir.to_path_buf())).await;
+    if run_outbox {
+        node.run_outbox();
+    }
+    node
+}
+
+fn status(node: &TestNode, message_id: &str) -> MessageStatus {
+    node.client.database().get_message(message_id).unwrap().status
+}
+
+async fn wait_for_status(node: &TestNode, message_id: &str, expected: MessageStatus) {
+    for _ in 0..500 {
+        if status(node, message_id) == expected {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!(
+        "Message {} stayed {:?}, expected {:?}",
+        message_id,
+        status(node, message_id),
+        expected
+    );
+}
+
+/// Message lifecycle events emitted so far
+fn message_events(node: &TestNode) -> Vec<&'static str> {
+    node.events()
+        .iter()
+        .filter_map(|event| match event {
+            ClientEvent::MessageQueued { .. } => Some("queued"),
+            ClientEvent::MessageSent { .. } => Some("sent"),
+            ClientEvent::MessageDelivered { .. } => Some("delivered"),
+            ClientEvent::MessageFailed { .. } => Some("failed"),
+            ClientEvent::MessageReceived { .. } => Some("received"),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Prekey bundle of a node that never goes online
+async fn offline_peer_bundle() -> (PeerId, String, TempDir) {
+    let dir = TempDir::new().unwrap();
+    let client = ClientBuilder::new()
+        .data_dir(dir.path().to_path_buf())
+        .build()
+        .await
+        .unwrap();
+    let bundle = client.get_prekey_bundle_json().await.unwrap();
+    (client.local_peer_id(), bundle, dir)
+}
+
+#[tokio::test]
+async fn test_unreachable_peer_handed_to_store() {
+    LocalSet::new()
+        .run_until(async {
+            let (bob_id, bob_bundle, _bob_dir) = offline_peer_bundle().await;
+            let store = Arc::new(RecordingStore::default());
+
+            let dir = TempDir::new().unwrap();
+            let alice = start(
+                dir.path(),
+                ClientBuilder::new()
+                    .retry_policy(fast_policy(2))
+                    .offline_store(store.clone()),
+                true,
+            )
+            .await;
+            alice
+                .client
+                .set_contact_prekey_bundle(bob_id.to_string(), bob_bundle)
+                .unwrap();
+
+            let message_id = alice
+                .client
+                .send_text_message(bob_id, "are you there?".to_string())
+                .await
+                .unwrap();
+            assert_eq!(status(&alice, &message_id), MessageStatus::Pending);
+
+            wait_for_status(&alice, &message_id, MessageStatus::Sent).await;
+            assert_eq!(*store.stored.lock().unwrap(), vec![(bob_id, message_id.clone())]);
+            assert!(alice.client.database().list_outbox().unwrap().is_empty());
+            assert_eq!(message_events(&alice), vec!["queued", "sent"]);
+
+            alice.shutdown();
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn test_unreachable_peer_fails_without_store() {
+    LocalSet::new()
+        .run_until(async {
+            let (bob_id, bob_bundle, _bob_dir) = offline_peer_bundle().await;
+            let store = Arc::new(RecordingStore {
+                fail: true,
+                ..Default::default()
+            });
+
+            let dir = TempDir::new().unwrap();
+            let alice = start(
+                dir.path(),
+                ClientBuilder::new()
+                    .retry_policy(fast_policy(2))
+                    .offline_store(store),
+                true,
+            )
+            .await;
+            alice
+                .client
+                .set_contact_prekey_bundle(bob_id.to_string(), bob_bundle)
+                .unwrap();
+
+            let message_id = alice
+                .client
+                .send_text_message(bob_id, "hello?".to_string())
+                .await
+                .unwrap();
+
+            wait_for_status(&alice, &message_id, MessageStatus::Failed).await;
+            assert!(alice.client.database().list_outbox().unwrap().is_empty());
+            assert_eq!(message_events(&alice), vec!["queued", "failed"]);
+
+            alice.shutdown();
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn test_plaintext_never_handed_to_store() {
+    LocalSet::new()
+        .run_until(async {
+            // No bundle for Bob: the message can only go out in the clear
+            let (bob_id, _bob_bundle, _bob_dir) = offline_peer_bundle().await;
+            let store = Arc::new(RecordingStore::default());
+
+            let dir = TempDir::new().unwrap();
+            let alice = start(
+                dir.path(),
+                ClientBuilder::new()
+                    .retry_policy(fast_policy(2))
+                    .encryption_policy(EncryptionPolicy::AllowPlaintext)
+                    .offline_store(store.clone()),
+                true,
+            )
+            .await;
+
+            let message_id = alice
+                .client
+                .send_text_message(bob_id, "in the clear".to_string())
+                .await
+                .unwrap();
+
+            wait_for_status(&alice, &message_id, MessageStatus::Failed).await;
+            assert!(store.stored.lock().unwrap().is_empty());
+            assert!(alice.client.database().list_outbox().unwrap().is_empty());
+
+            alice.shutdown();
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn test_queued_plaintext_encrypted_once_bundle_known() {
+    LocalSet::new()
+        .run_until(async {
+            let (bob_id, bob_bundle, _bob_dir) = offline_peer_bundle().await;
+
+            let dir = TempDir::new().unwrap();
+            let alice = start(
+                dir.path(),
+                ClientBuilder::new()
+                    .retry_policy(fast_policy(10))
+                    .encryption_policy(EncryptionPolicy::AllowPlaintext),
+                false,
+            )
+            .await;
+
+            let message_id = alice
+                .client
+                .send_text_message(bob_id, "sealed later".to_string())
+                .await
+                .unwrap();
+            let database = alice.client.database();
+            let entry = database
+                .get_outbox_entry(&message_id, &bob_id.to_string())
+                .unwrap()
+                .unwrap();
+            assert!(entry.plaintext);
+
+            alice
+                .client
+                .set_contact_prekey_bundle(bob_id.to_string(), bob_bundle)
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            alice.client.process_outbox().await.unwrap();
+
+            let entry = database
+                .get_outbox_entry(&message_id, &bob_id.to_string())
+                .unwrap()
+                .unwrap();
+            assert!(!entry.plaintext);
+
+            alice.shutdown();
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn test_outbox_survives_restart() {
+    LocalSet::new()
+        .run_until(async {
+            let alice_dir = TempDir::new().unwrap();
+            let bob_dir = TempDir::new().unwrap();
+            let bob = start(bob_dir.path(), ClientBuilder::new(), false).await;
+            let bob_id = bob.client.local_peer_id();
+
+            // Queue a message while Bob can't be reached, without the outbox task
+            let alice = start(alice_dir.path(), ClientBuilder::new(), false).await;
+            let alice_id = alice.client.local_peer_id();
+            let bob_bundle = bob.client.get_prekey_bundle_json().await.unwrap();
+            let alice_bundle = alice.client.get_prekey_bundle_json().await.unwrap();
+            alice
+                .client
+                .set_contact_prekey_bundle(bob_id.to_string(), bob_bundle)
+                .unwrap();
+            bob.client
+                .set_contact_prekey_bundle(alice_id.to_string(), alice_bundle)
+                .unwrap();
+
+            let message_id = alice
+                .client
+                .send_text_message(bob_id, "sent before restart".to_string())
+                .await
+                .unwrap();
+            assert_eq!(status(&alice, &message_id), MessageStatus::Pending);
+            alice.shutdown();
+
+            // Restart Alice; the queued message is still there and goes out once
+            // Bob is reachable
+            let mut alice = start(
+                alice_dir.path(),
+                ClientBuilder::new().retry_policy(fast_policy(10)),
+                false,
+            )
+            .await;
+            assert_eq!(alice.client.local_peer_id(), alice_id);
+            let queued = alice.client.database().list_outbox().unwrap();
+            assert_eq!(queued.len(), 1);
+            assert_eq!(queued[0].message_id, message_id);
+
+            connect(&alice, &bob).await;
+            alice.run_outbox();
+
+            wait_for_status(&alice, &message_id, MessageStatus::Delivered).await;
+            assert!(alice.client.database().list_outbox().unwrap().is_empty());
+            assert_eq!(message_events(&alice), vec!["sent", "delivered"]);
+
+            let received = bob.client.database().get_message(&message_id).unwrap();
+            assert_eq!(received.sender_peer_id, alice_id.to_string());
+            assert_eq!(message_events(&bob), vec!["received"]);
+
+            alice.shutdown();
+            bob.shutdown();
+        })
+        .await;
+}
@@ -0,0 +1,222 @@
+//! Signed Peer Record Integration Test
+//!
+//! Local peers sharing a DHT: Bob publishes a signed record of his
+//! addresses to Alice (a DHT server once AutoNAT confirms she is reachable)
+//! and Carol resolves it through her. A record for Bob signed by someone
+//! else is refused by Alice and never reaches Carol.
+
+mod common;
+
+use common::autonat_server::AutoNatServer;
+use futures::StreamExt;
+use libp2p::{
+    identity::Keypair,
+    kad::{self, store::MemoryStore, Quorum, Record},
+    noise, tcp, yamux, Multiaddr, PeerId, Swarm, SwarmBuilder,
+};
+use mepassa_core::network::{
+    AddressRecord, AutoNatConfig, NetworkManager, Reachability,
+};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::oneshot;
+use tokio::time::sleep;
+
+fn local_config() -> AutoNatConfig {
+    AutoNatConfig {
+        boot_delay: Duration::ZERO,
+        ..Default::default()
+    }
+}
+
+async fn listening_peer() -> (NetworkManager, Multiaddr) {
+    let mut peer = NetworkManager::new(Keypair::generate_ed25519()).unwrap();
+    peer.set_autonat_config(local_config());
+    peer.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+    for _ in 0..200 {
+        if let Some(addr) = peer.listening_addresses().into_iter().next() {
+            return (peer, addr);
+        }
+        if !peer.poll_once().await.unwrap_or(false) {
+            sleep(Duration::from_millis(5)).await;
+        }
+    }
+    panic!("Peer never started listening");
+}
+
+/// Poll all peers until `done` holds (or give up after ~10 seconds)
+async fn drive(
+    peers: &mut [&mut NetworkManager],
+    mut done: impl FnMut(&[&mut NetworkManager]) -> bool,
+) -> bool {
+    for _ in 0..2000 {
+        if done(peers) {
+            return true;
+        }
+        let mut progressed = false;
+        for peer in peers.iter_mut() {
+            progressed |= peer.poll_once().await.unwrap_or(false);
+        }
+        if !progressed {
+            sleep(Duration::from_millis(5)).await;
+        }
+    }
+    done(peers)
+}
+
+/// Alice: connected to Bob, and a DHT server because AutoNAT found her public
+async fn dht_server_and_publisher() -> (NetworkManager, Multiaddr, NetworkManager, AutoNatServer) {
+    let server = AutoNatServer::start().await;
+    let (mut alice, alice_addr) = listening_peer().await;
+    let (mut bob, _) = listening_peer().await;
+    let alice_id = *alice.local_peer_id();
+
+    bob.add_peer_to_dht(alice_id, alice_addr.clone());
+    alice.dial(server.peer_id, server.addr.clone()).unwrap();
+    alice.dial(*bob.local_peer_id(), bob.listening_addresses()[0].clone()).unwrap();
+    let public = drive(&mut [&mut alice, &mut bob], |peers| {
+        matches!(peers[0].reachability(), Reachability::Public(_))
+    })
+    .await;
+    assert!(public, "Alice never became public");
+
+    (alice, alice_addr, bob, server)
+}
+
+/// Resolve `peer` from `resolver`, polling the others meanwhile
+async fn resolve(
+    resolver: &mut NetworkManager,
+    others: &mut [&mut NetworkManager],
+    peer: PeerId,
+) -> Vec<Multiaddr> {
+    let mut rx: oneshot::Receiver<Vec<Multiaddr>> = resolver.resolve_peer_addresses(peer);
+    for _ in 0..2000 {
+        if let Ok(addrs) = rx.try_recv() {
+            return addrs;
+        }
+        let mut progressed = resolver.poll_once().await.unwrap_or(false);
+        for other in others.iter_mut() {
+            progressed |= other.poll_once().await.unwrap_or(false);
+        }
+        if !progressed {
+            sleep(Duration::from_millis(5)).await;
+        }
+    }
+    panic!("Lookup of {} never finished", peer);
+}
+
+#[tokio::test]
+async fn test_signed_record_resolves_through_dht() {
+    let (mut alice, alice_addr, mut bob, _server) = dht_server_and_publisher().await;
+    let bob_id = *bob.local_peer_id();
+
+    let relayed: Multiaddr = format!(
+        "/ip4/203.0.113.9/tcp/4001/p2p/{}/p2p-circuit",
+        alice.local_peer_id()
+    )
+    .parse()
+    .unwrap();
+    let direct: Multiaddr = "/ip4/198.51.100.7/tcp/4001".parse().unwrap();
+    bob.publish_own_address(relayed.clone());
+    bob.publish_own_address(direct.clone());
+    let record = bob.published_address_record().unwrap().clone();
+    assert!(record.addresses.contains(&direct));
+    assert_eq!(record.addresses.last(), Some(&relayed));
+
+    // Carol only knows Alice
+    let mut carol = NetworkManager::new(Keypair::generate_ed25519()).unwrap();
+    carol.add_peer_to_dht(*alice.local_peer_id(), alice_addr);
+    let mut addrs = Vec::new();
+    for _ in 0..20 {
+        addrs = resolve(&mut carol, &mut [&mut alice, &mut bob], bob_id).await;
+        if !addrs.is_empty() {
+            break;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    assert!(addrs.contains(&direct));
+    assert_eq!(addrs.last(), Some(&relayed));
+
+    // Withdrawing an address republishes with a higher sequence number
+    bob.withdraw_own_address(&direct);
+    let republished = bob.published_address_record().unwrap();
+    assert!(republished.seq > record.seq);
+    assert!(!republished.addresses.contains(&direct));
+}
+
+#[derive(libp2p::swarm::NetworkBehaviour)]
+struct AttackerBehaviour {
+    kademlia: kad::Behaviour<MemoryStore>,
+}
+
+#[tokio::test]
+async fn test_forged_record_is_not_stored() {
+    let (mut alice, alice_addr, bob, _server) = dht_server_and_publisher().await;
+    let alice_id = *alice.local_peer_id();
+    let bob_id = *bob.local_peer_id();
+
+    // Mallory puts a record under Bob's key, signed with her own key
+    let mallory_key = Keypair::generate_ed25519();
+    let mallory_id = PeerId::from(mallory_key.public());
+    let hijack: Multiaddr = "/ip4/192.0.2.66/tcp/4001".parse().unwrap();
+    let forged = AddressRecord::new(mallory_id, &[hijack], 1, SystemTime::now(), Duration::from_secs(600))
+        .sign(&mallory_key)
+        .unwrap();
+
+    let mut mallory: Swarm<AttackerBehaviour> = SwarmBuilder::with_existing_identity(mallory_key)
+        .with_tokio()
+        .with_tcp(tcp::Config::default(), noise::Config::new, yamux::Config::default)
+        .unwrap()
+        .with_behaviour(|key| AttackerBehaviour {
+            kademlia: kad::Behaviour::new(
+                key.public().to_peer_id(),
+                MemoryStore::new(key.public().to_peer_id()),
+            ),
+        })
+        .unwrap()
+        .build();
+    mallory.behaviour_mut().kademlia.add_address(&alice_id, alice_addr);
+    mallory
+        .behaviour_mut()
+        .kademlia
+        .put_record(
+            Record {
+                key: AddressRecord::key(&bob_id),
+                value: forged,
+                publisher: None,
+                expires: Some(Instant::now() + Duration::from_secs(600)),
+            },
+            Quorum::One,
+        )
+        .unwrap();
+    let (done_tx, done_rx) = oneshot::channel();
+    tokio::spawn(async move {
+        let mut done_tx = Some(done_tx);
+        loop {
+            if let libp2p::swarm::SwarmEvent::Behaviour(AttackerBehaviourEvent::Kademlia(
+                kad::Event::OutboundQueryProgressed {
+                    result: kad::QueryResult::PutRecord(result),
+                    ..
+                },
+            )) = mallory.select_next_some().await
+            {
+                if let Some(tx) = done_tx.take() {
+                    let _ = tx.send(result.is_ok());
+                }
+            }
+        }
+    });
+
+    // Alice answers the put (she always does) but drops the record
+    let mut done_rx = done_rx;
+    let mut put_answered = false;
+    drive(&mut [&mut alice], |_| {
+        put_answered = put_answered || done_rx.try_recv().is_ok();
+        put_answered
+    })
+    .await;
+    assert!(put_answered);
+
+    let mut carol = NetworkManager::new(Keypair::generate_ed25519()).unwrap();
+    carol.add_peer_to_dht(alice_id, alice.listening_addresses()[0].clone());
+    assert!(resolve(&mut carol, &mut [&mut alice], bob_id).await.is_empty());
+}
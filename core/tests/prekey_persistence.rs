@@ -0,0 +1,139 @@
+//! Prekey Persistence Integration Test
+//!
+//! Alice hands out her prekey bundle and restarts before Bob uses it: the
+//! restarted client still answers Bob's X3DH, and the one-time prekey he
+//! used stays consumed across the next restart, so Carol, holding a copy of
+//! the same bundle, falls back to signed-prekey-only X3DH. A signed prekey
+//! replaced by rotation keeps answering bundles handed out before it.
+
+mod common;
+
+use common::{connect, TestNode};
+use mepassa_core::api::ClientBuilder;
+use mepassa_core::identity::SignedPreKeyPolicy;
+use mepassa_core::storage::MessageStatus;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::task::LocalSet;
+
+#[tokio::test]
+async fn test_prekeys_survive_restarts() {
+    LocalSet::new().run_until(run_restart_scenario()).await;
+}
+
+async fn run_restart_scenario() {
+    let alice_dir = TempDir::new().unwrap();
+    let bob_dir = TempDir::new().unwrap();
+    let carol_dir = TempDir::new().unwrap();
+
+    let alice = TestNode::start(alice_dir.path()).await;
+    let alice_id = alice.peer_id();
+    let bundle = alice.client.get_prekey_bundle_json().await.unwrap();
+    alice.shutdown();
+
+    // Same signed prekey after a restart, and the one-time prekey handed out
+    // before it isn't offered again
+    let alice = TestNode::start(alice_dir.path()).await;
+    assert_eq!(alice.peer_id(), alice_id);
+    let next = alice.client.get_prekey_bundle_json().await.unwrap();
+    assert_eq!(signed_prekey_id(&next), signed_prekey_id(&bundle));
+    assert_ne!(one_time_prekey_id(&next), one_time_prekey_id(&bundle));
+
+    // Bob uses the bundle Alice handed out before the restart
+    let bob = TestNode::start(bob_dir.path()).await;
+    bob.client
+        .set_contact_prekey_bundle(alice_id.to_string(), bundle.clone())
+        .unwrap();
+    connect(&bob, &alice).await;
+    bob.client
+        .send_text_message(alice_id, "hi Alice".to_string())
+        .await
+        .unwrap();
+    alice.wait_for_message("hi Alice").await;
+    alice.shutdown();
+    bob.shutdown();
+
+    // His one-time prekey stays used up after the next restart: Carol, who
+    // got a copy of his bundle, starts over with the signed prekey alone
+    let alice = TestNode::start(alice_dir.path()).await;
+    let mut carol = TestNode::start(carol_dir.path()).await;
+    carol.run_outbox();
+    carol
+        .client
+        .set_contact_prekey_bundle(alice_id.to_string(), bundle.clone())
+        .unwrap();
+    connect(&carol, &alice).await;
+    let message_id = carol
+        .client
+        .send_text_message(alice_id, "hi from Carol".to_string())
+        .await
+        .unwrap();
+    alice.wait_for_message("hi from Carol").await;
+    carol
+        .wait_until("the delivery", |client| {
+            client.database().get_message(&message_id).unwrap().status == MessageStatus::Delivered
+        })
+        .await;
+    let contact = carol
+        .client
+        .database()
+        .get_contact_by_peer_id(&alice_id.to_string())
+        .unwrap();
+    assert_eq!(one_time_prekey_id(&contact.prekey_bundle_json.unwrap()), None);
+    alice.shutdown();
+    carol.shutdown();
+}
+
+#[tokio::test]
+async fn test_rotated_signed_prekey_answers_old_bundles() {
+    LocalSet::new().run_until(run_rotation_scenario()).await;
+}
+
+async fn run_rotation_scenario() {
+    let alice_dir = TempDir::new().unwrap();
+    let bob_dir = TempDir::new().unwrap();
+    // Every check rotates; replaced keys are kept for a day
+    let alice_builder = || {
+        ClientBuilder::new()
+            .data_dir(alice_dir.path().to_path_buf())
+            .signed_prekey_policy(SignedPreKeyPolicy {
+                rotation_interval: Duration::ZERO,
+                grace_period: Duration::from_secs(24 * 60 * 60),
+            })
+    };
+
+    let alice = TestNode::start_with(alice_builder()).await;
+    let alice_id = alice.peer_id();
+    let bundle = alice.client.get_prekey_bundle_json().await.unwrap();
+    assert!(alice.client.rotate_signed_prekey_if_due().await.unwrap());
+    let rotated = alice.client.get_prekey_bundle_json().await.unwrap();
+    assert_ne!(signed_prekey_id(&rotated), signed_prekey_id(&bundle));
+    alice.shutdown();
+
+    // Bob still has the bundle from before the rotation
+    let alice = TestNode::start_with(alice_builder()).await;
+    let after_restart = alice.client.get_prekey_bundle_json().await.unwrap();
+    assert_eq!(signed_prekey_id(&after_restart), signed_prekey_id(&rotated));
+    let bob = TestNode::start(bob_dir.path()).await;
+    bob.client
+        .set_contact_prekey_bundle(alice_id.to_string(), bundle)
+        .unwrap();
+    connect(&bob, &alice).await;
+    bob.client
+        .send_text_message(alice_id, "hi rotated Alice".to_string())
+        .await
+        .unwrap();
+    alice.wait_for_message("hi rotated Alice").await;
+    alice.shutdown();
+    bob.shutdown();
+}
+
+fn signed_prekey_id(bundle_json: &str) -> u64 {
+    let bundle: serde_json::Value = serde_json::from_str(bundle_json).unwrap();
+    bundle["signed_prekey_id"].as_u64().unwrap()
+}
+
+fn one_time_prekey_id(bundle_json: &str) -> Option<u64> {
+    let bundle: serde_json::Value = serde_json::from_str(bundle_json).unwrap();
+    bundle["one_time_prekey"]["id"].as_u64()
+}
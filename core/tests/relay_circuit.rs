@@ -0,0 +1,146 @@
+//! Relay Circuit Integration Test
+//!
+//! Three local swarms: a circuit relay v2 server (like the bootstrap node),
+//! a peer that is only reachable through it, and a peer dialing the first
+//! one over a `/p2p-circuit` address. The reservation status follows the
+//! relay client's events.
+
+use futures::StreamExt;
+use libp2p::{
+    identify, identity::Keypair, noise, ping, relay, swarm::NetworkBehaviour, tcp, yamux,
+    Multiaddr, PeerId, Swarm, SwarmBuilder,
+};
+use mepassa_core::network::{
+    ConnectionState, ConnectionType, NetworkManager, ReservationStatus,
+};
+use std::time::Duration;
+use tokio::time::sleep;
+
+#[derive(NetworkBehaviour)]
+struct RelayServerBehaviour {
+    relay: relay::Behaviour,
+    identify: identify::Behaviour,
+    ping: ping::Behaviour,
+}
+
+/// Start a relay server on a local port; returns its peer id and address
+async fn start_relay_server() -> (PeerId, Multiaddr) {
+    let mut swarm: Swarm<RelayServerBehaviour> = SwarmBuilder::with_new_identity()
+        .with_tokio()
+        .with_tcp(tcp::Config::default(), noise::Config::new, yamux::Config::default)
+        .unwrap()
+        .with_behaviour(|key| RelayServerBehaviour {
+            relay: relay::Behaviour::new(key.public().to_peer_id(), relay::Config::default()),
+            identify: identify::Behaviour::new(identify::Config::new(
+                "/mepassa/1.0.0".to_string(),
+                key.public(),
+            )),
+            ping: ping::Behaviour::default(),
+        })
+        .unwrap()
+        .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
+        .build();
+
+    swarm.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+    let addr = loop {
+        if let libp2p::swarm::SwarmEvent::NewListenAddr { address, .. } =
+            swarm.select_next_some().await
+        {
+            break address;
+        }
+    };
+    // Reservations carry the relay's external addresses
+    swarm.add_external_address(addr.clone());
+
+    let peer_id = *swarm.local_peer_id();
+    tokio::spawn(async move {
+        loop {
+            swarm.select_next_some().await;
+        }
+    });
+    (peer_id, addr)
+}
+
+/// Poll both peers until `done` holds (or give up after ~10 seconds)
+async fn drive(
+    a: &mut NetworkManager,
+    b: &mut NetworkManager,
+    done: impl Fn(&NetworkManager, &NetworkManager) -> bool,
+) -> bool {
+    for _ in 0..2000 {
+        if done(a, b) {
+            return true;
+        }
+        let progressed_a = a.poll_once().await.unwrap_or(false);
+        let progressed_b = b.poll_once().await.unwrap_or(false);
+        if !progressed_a && !progressed_b {
+            sleep(Duration::from_millis(5)).await;
+        }
+    }
+    done(a, b)
+}
+
+#[tokio::test]
+async fn test_peer_reachable_through_relay_circuit() {
+    let _ = tracing_subscriber::fmt().with_max_level(tracing::Level::INFO).try_init();
+
+    let (relay_id, relay_addr) = start_relay_server().await;
+
+    // Bob does not listen anywhere but on the relay
+    let mut bob = NetworkManager::new(Keypair::generate_ed25519()).unwrap();
+    bob.set_relay(relay_id, relay_addr.clone());
+    let bob_id = *bob.local_peer_id();
+
+    let mut alice = NetworkManager::new(Keypair::generate_ed25519()).unwrap();
+    alice.set_relay(relay_id, relay_addr);
+
+    assert_eq!(*bob.relay_reservation_status(), ReservationStatus::NotReserved);
+    bob.reserve_relay_slot().unwrap();
+    assert!(matches!(
+        bob.relay_reservation_status(),
+        ReservationStatus::Pending { .. }
+    ));
+
+    let reserved = drive(&mut alice, &mut bob, |_, b| b.has_relay()).await;
+    assert!(reserved, "Relay reservation was not accepted: {:?}", bob.relay_reservation_status());
+    assert!(matches!(
+        bob.relay_reservation_status(),
+        ReservationStatus::Reserved { .. }
+    ));
+
+    // Alice reaches Bob through the relay
+    let circuit = alice.relay_circuit_addr(&bob_id).unwrap();
+    alice.dial(bob_id, circuit).unwrap();
+    let connected = drive(&mut alice, &mut bob, |a, b| {
+        a.is_connected(&bob_id) && b.is_connected(a.local_peer_id())
+    })
+    .await;
+    assert!(connected, "Peers never connected through the relay");
+    assert_eq!(
+        alice.connection_state(&bob_id),
+        ConnectionState::Connected(ConnectionType::Relayed)
+    );
+
+    // Both ends show the connection as relayed
+    let quality = alice.connection_quality(&bob_id);
+    assert!(quality.is_relayed());
+    assert_eq!(quality.open_connections, 1);
+    assert!(bob.connection_quality(alice.local_peer_id()).is_relayed());
+}
+
+#[tokio::test]
+async fn test_reservation_on_unreachable_relay_fails() {
+    // Nothing listens on this port
+    let relay_addr: Multiaddr = "/ip4/127.0.0.1/tcp/1".parse().unwrap();
+    let mut bob = NetworkManager::new(Keypair::generate_ed25519()).unwrap();
+    let mut alice = NetworkManager::new(Keypair::generate_ed25519()).unwrap();
+    bob.set_relay(PeerId::random(), relay_addr);
+
+    bob.reserve_relay_slot().unwrap();
+    let failed = drive(&mut alice, &mut bob, |_, b| {
+        matches!(b.relay_reservation_status(), ReservationStatus::Failed { .. })
+    })
+    .await;
+    assert!(failed, "Reservation status: {:?}", bob.relay_reservation_status());
+    assert!(!bob.has_relay());
+}
@@ -6,12 +6,12 @@
 use libp2p::{identity, Multiaddr, PeerId};
 use mepassa_core::network::{
     connection::{ConnectionManager, ConnectionState, ConnectionStrategy, ConnectionType},
-    nat_detection::{ConnectionStrategy as NatStrategy, NatDetector, NatType},
+    nat_detection::{ConnectionStrategy as NatStrategy, NatDetector, ProbeResult, Reachability},
     relay::{RelayManager, ReservationStatus},
     retry::RetryPolicy,
     NetworkManager,
 };
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 /// Test NetworkManager creation with relay configuration
 #[tokio::test]
@@ -113,65 +113,56 @@ fn test_retry_policy_max_delay() {
     assert_eq!(delay, Duration::from_secs(30));
 }
 
-/// Test NAT detection type inference
+/// Test reachability tracking from AutoNAT probe results
 #[test]
 fn test_nat_detection() {
     let mut detector = NatDetector::new();
+    let now = Instant::now();
 
     // Initially unknown
-    assert_eq!(detector.guess_nat_type(), NatType::Unknown);
+    assert_eq!(*detector.reachability(), Reachability::Unknown);
 
-    // Add same observed address multiple times → FullCone
-    let addr1: Multiaddr = "/ip4/203.0.113.1/tcp/12345".parse().unwrap();
-    detector.add_observed_address(addr1.clone());
-    detector.add_observed_address(addr1.clone());
-
-    let nat_type = detector.guess_nat_type();
-    assert_eq!(nat_type, NatType::FullCone);
+    // A dial-back confirms we are reachable
+    let addr: Multiaddr = "/ip4/203.0.113.1/tcp/12345".parse().unwrap();
+    let changed = detector.record_probe(ProbeResult::Reachable(addr.clone()), now);
+    assert_eq!(changed, Some(Reachability::Public(addr)));
 
-    // Different addresses → Symmetric
+    // A failed dial-back means we are behind a NAT
     let mut detector2 = NatDetector::new();
-    let addr2: Multiaddr = "/ip4/203.0.113.1/tcp/12345".parse().unwrap();
-    let addr3: Multiaddr = "/ip4/203.0.113.2/tcp/54321".parse().unwrap();
-    detector2.add_observed_address(addr2);
-    detector2.add_observed_address(addr3);
-
-    let nat_type2 = detector2.guess_nat_type();
-    assert_eq!(nat_type2, NatType::Symmetric);
+    detector2.record_probe(ProbeResult::Unreachable, now);
+    assert_eq!(*detector2.reachability(), Reachability::Private);
 }
 
-/// Test NAT-based connection strategy recommendation
+/// Test reachability-based connection strategy recommendation
 #[test]
 fn test_nat_connection_strategy() {
     let mut detector = NatDetector::new();
+    let now = Instant::now();
 
-    // Unknown NAT → try direct first
+    // Unknown → try direct first
     assert_eq!(
         detector.connection_recommendation(),
         NatStrategy::DirectFirst
     );
 
-    // FullCone NAT → try direct
+    // Publicly reachable → try direct
     let addr: Multiaddr = "/ip4/203.0.113.1/tcp/12345".parse().unwrap();
-    detector.add_observed_address(addr.clone());
-    detector.add_observed_address(addr);
+    detector.record_probe(ProbeResult::Reachable(addr), now);
     assert_eq!(
         detector.connection_recommendation(),
         NatStrategy::DirectFirst
     );
 
-    // Symmetric NAT → should use relay
+    // Not reachable → should use relay
     let mut detector2 = NatDetector::new();
-    let addr1: Multiaddr = "/ip4/203.0.113.1/tcp/12345".parse().unwrap();
-    let addr2: Multiaddr = "/ip4/203.0.113.2/tcp/54321".parse().unwrap();
-    detector2.add_observed_address(addr1);
-    detector2.add_observed_address(addr2);
+    detector2.record_probe(ProbeResult::Unreachable, now);
 
     let strategy = detector2.connection_recommendation();
     assert!(
         matches!(strategy, NatStrategy::RelayFirst)
             || matches!(strategy, NatStrategy::HolePunchFirst)
     );
+    assert!(detector2.should_use_relay());
 }
 
 /// Test relay manager reservation lifecycle
@@ -0,0 +1,87 @@
+//! E2E Session Persistence Integration Test
+//!
+//! Restarts a `Client` in the middle of an encrypted conversation and checks
+//! that the Double Ratchet sessions loaded from SQLite keep working.
+
+mod common;
+
+use common::{connect, TestNode};
+use std::rc::Rc;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::task::LocalSet;
+
+#[tokio::test]
+async fn test_encrypted_conversation_survives_restart() {
+    LocalSet::new().run_until(run_restart_scenario()).await;
+}
+
+async fn run_restart_scenario() {
+    let _ = tracing_subscriber::fmt().with_max_level(tracing::Level::INFO).try_init();
+    let alice_dir = TempDir::new().unwrap();
+    let bob_dir = TempDir::new().unwrap();
+
+    let alice = TestNode::start(alice_dir.path()).await;
+    let mut bob = TestNode::start(bob_dir.path()).await;
+
+    let alice_id = alice.client.local_peer_id();
+    let bob_id = bob.client.local_peer_id();
+
+    // Exchange prekey bundles out of band
+    let alice_bundle = alice.client.get_prekey_bundle_json().await.unwrap();
+    let bob_bundle = bob.client.get_prekey_bundle_json().await.unwrap();
+    alice
+        .client
+        .set_contact_prekey_bundle(bob_id.to_string(), bob_bundle)
+        .unwrap();
+    bob.client
+        .set_contact_prekey_bundle(alice_id.to_string(), alice_bundle)
+        .unwrap();
+
+    connect(&alice, &bob).await;
+
+    // Establish the session in both directions
+    alice
+        .client
+        .send_text_message(bob_id, "hello before restart".to_string())
+        .await
+        .unwrap();
+    bob.wait_for_message("hello before restart").await;
+
+    bob.client
+        .send_text_message(alice_id, "reply before restart".to_string())
+        .await
+        .unwrap();
+    alice.wait_for_message("reply before restart").await;
+
+    // Restart Bob from the same data directory
+    let bob_client = Rc::downgrade(&bob.client);
+    bob.shutdown();
+    for _ in 0..100 {
+        if bob_client.strong_count() == 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    bob = TestNode::start(bob_dir.path()).await;
+    assert_eq!(bob.client.local_peer_id(), bob_id);
+
+    connect(&alice, &bob).await;
+
+    // Alice keeps using her ratchet; Bob decrypts with the restored session
+    alice
+        .client
+        .send_text_message(bob_id, "hello after restart".to_string())
+        .await
+        .unwrap();
+    bob.wait_for_message("hello after restart").await;
+
+    bob.client
+        .send_text_message(alice_id, "reply after restart".to_string())
+        .await
+        .unwrap();
+    alice.wait_for_message("reply after restart").await;
+
+    alice.shutdown();
+    bob.shutdown();
+}
@@ -0,0 +1,351 @@
+//! Store-and-Forward Integration Test
+//!
+//! Runs an in-process stand-in for the Message Store API (`server/store`)
+//! and checks that a message to an offline peer is uploaded by the sender's
+//! outbox, then fetched, decrypted and deleted by the recipient. Like the
+//! real server, the stand-in checks that requests are signed by the key
+//! behind the sender's or recipient's peer id, and accepts each signature
+//! only once.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    routing::post,
+    Json, Router,
+};
+use base64::{engine::general_purpose, Engine as _};
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::PeerId;
+use mepassa_core::api::{Client, ClientBuilder, ClientEvent, FunctionCallback, OfflineStore};
+use mepassa_core::network::retry::RetryPolicy;
+use mepassa_core::protocol::pb::message::Payload;
+use mepassa_core::protocol::{Message, MessageType, TextMessage};
+use mepassa_core::storage::MessageStatus;
+use mepassa_core::store_client::StoreForwardClient;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::task::LocalSet;
+
+/// Stored message as kept by the stand-in server
+#[derive(Clone)]
+struct StoredMessage {
+    recipient_peer_id: String,
+    sender_peer_id: String,
+    encrypted_payload: String,
+    message_id: String,
+}
+
+type Store = Arc<Mutex<Vec<StoredMessage>>>;
+
+/// Stand-in server state: the stored messages and the signatures already used
+#[derive(Clone, Default)]
+struct ServerState {
+    store: Store,
+    used_signatures: Arc<Mutex<HashSet<String>>>,
+}
+
+#[derive(Deserialize)]
+struct RetrieveQuery {
+    peer_id: String,
+    limit: Option<usize>,
+    timestamp: i64,
+    nonce: String,
+    signature: String,
+}
+
+/// Check a request signature against the key embedded in the peer id
+fn verify(peer_id: &str, challenge: &str, signature: &str) -> bool {
+    let Ok(peer_id) = peer_id.parse::<PeerId>() else {
+        return false;
+    };
+    let Ok(public_key) = PublicKey::try_decode_protobuf(peer_id.as_ref().digest()) else {
+        return false;
+    };
+    let Ok(signature) = general_purpose::STANDARD.decode(signature) else {
+        return false;
+    };
+    public_key.verify(challenge.as_bytes(), &signature)
+}
+
+/// Verify a request and claim its signature, like the real server's replay cache
+fn authenticate(state: &ServerState, peer_id: &str, challenge: &str, signature: &str) -> bool {
+    verify(peer_id, challenge, signature)
+        && state.used_signatures.lock().unwrap().insert(signature.to_string())
+}
+
+fn unauthorized() -> (StatusCode, Json<Value>) {
+    (StatusCode::UNAUTHORIZED, Json(json!({ "error": "Invalid signature" })))
+}
+
+async fn store_message(State(state): State<ServerState>, Json(req): Json<Value>) -> (StatusCode, Json<Value>) {
+    let field = |name: &str| req[name].as_str().unwrap_or_default().to_string();
+    let challenge = format!(
+        "store:{}:{}:{}:{}:{}:{}",
+        field("sender_peer_id"),
+        field("recipient_peer_id"),
+        field("message_id"),
+        req["timestamp"],
+        field("nonce"),
+        field("encrypted_payload")
+    );
+    if !authenticate(&state, &field("sender_peer_id"), &challenge, &field("signature")) {
+        return unauthorized();
+    }
+
+    state.store.lock().unwrap().push(StoredMessage {
+        recipient_peer_id: field("recipient_peer_id"),
+        sender_peer_id: field("sender_peer_id"),
+        encrypted_payload: field("encrypted_payload"),
+        message_id: field("message_id"),
+    });
+    (StatusCode::CREATED, Json(json!({ "message_id": field("message_id") })))
+}
+
+async fn retrieve_messages(
+    State(state): State<ServerState>,
+    Query(query): Query<RetrieveQuery>,
+) -> (StatusCode, Json<Value>) {
+    let challenge = format!("retrieve:{}:{}:{}", query.peer_id, query.timestamp, query.nonce);
+    if !authenticate(&state, &query.peer_id, &challenge, &query.signature) {
+        return unauthorized();
+    }
+
+    let messages: Vec<Value> = state
+        .store
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|m| m.recipient_peer_id == query.peer_id)
+        .take(query.limit.unwrap_or(100))
+        .map(|m| {
+            json!({
+                "id": uuid::Uuid::new_v4(),
+                "sender_peer_id": m.sender_peer_id,
+                "encrypted_payload": m.encrypted_payload,
+                "message_type": "encrypted",
+                "message_id": m.message_id,
+                "created_at": chrono::Utc::now(),
+            })
+        })
+        .collect();
+    let total = messages.len();
+    (StatusCode::OK, Json(json!({ "messages": messages, "total": total })))
+}
+
+async fn delete_messages(State(state): State<ServerState>, Json(req): Json<Value>) -> (StatusCode, Json<Value>) {
+    let peer_id = req["peer_id"].as_str().unwrap_or_default();
+    let ids: Vec<String> = serde_json::from_value(req["message_ids"].clone()).unwrap();
+    let challenge = format!(
+        "delete:{}:{}:{}:{}",
+        peer_id,
+        req["timestamp"],
+        req["nonce"].as_str().unwrap_or_default(),
+        ids.join(",")
+    );
+    if !authenticate(&state, peer_id, &challenge, req["signature"].as_str().unwrap_or_default()) {
+        return unauthorized();
+    }
+
+    // Only the recipient's own messages
+    let mut store = state.store.lock().unwrap();
+    let before = store.len();
+    store.retain(|m| m.recipient_peer_id != peer_id || !ids.contains(&m.message_id));
+    (StatusCode::OK, Json(json!({ "deleted_count": before - store.len() })))
+}
+
+/// Serve the stand-in store API on a random local port
+async fn start_store_server() -> (String, Store) {
+    let state = ServerState::default();
+    let store = Arc::clone(&state.store);
+    let app = Router::new()
+        .route(
+            "/api/store",
+            post(store_message).get(retrieve_messages).delete(delete_messages),
+        )
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    (url, store)
+}
+
+fn text_message(from: PeerId, to: PeerId, id: &str) -> Message {
+    Message {
+        id: id.to_string(),
+        sender_peer_id: from.to_string(),
+        recipient_peer_id: to.to_string(),
+        timestamp: chrono::Utc::now().timestamp_millis(),
+        r#type: MessageType::Text as i32,
+        payload: Some(Payload::Text(TextMessage {
+            content: "hi".to_string(),
+            reply_to_id: String::new(),
+            metadata: std::collections::HashMap::new(),
+        })),
+    }
+}
+
+#[tokio::test]
+async fn test_store_fetch_acknowledge() {
+    let (url, server) = start_store_server().await;
+    let alice_client = StoreForwardClient::new(url.clone(), Keypair::generate_ed25519()).unwrap();
+    let bob_client = StoreForwardClient::new(url, Keypair::generate_ed25519()).unwrap();
+    let alice = alice_client.peer_id();
+    let bob = bob_client.peer_id();
+
+    let message = text_message(alice, bob, "msg-1");
+    alice_client.store(&bob, &message).await.unwrap();
+    assert_eq!(server.lock().unwrap().len(), 1);
+
+    assert!(alice_client.fetch(&alice).await.unwrap().is_empty());
+    let pending = bob_client.fetch(&bob).await.unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].message_id, "msg-1");
+    assert_eq!(pending[0].sender_peer_id, alice.to_string());
+    assert_eq!(pending[0].payload, prost::Message::encode_to_vec(&message));
+
+    // Alice can neither read nor delete Bob's messages
+    assert!(alice_client.fetch(&bob).await.is_err());
+    alice_client.acknowledge(&["msg-1".to_string()]).await.unwrap();
+    assert_eq!(server.lock().unwrap().len(), 1);
+
+    bob_client.acknowledge(&["msg-1".to_string()]).await.unwrap();
+    assert!(bob_client.fetch(&bob).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_repeated_fetch_is_not_a_replay() {
+    let (url, _server) = start_store_server().await;
+    let keypair = Keypair::generate_ed25519();
+    let client = StoreForwardClient::new(url.clone(), keypair.clone()).unwrap();
+    let peer_id = client.peer_id();
+
+    // Same peer, same second: each request still carries its own signature
+    for _ in 0..3 {
+        assert!(client.fetch(&peer_id).await.unwrap().is_empty());
+    }
+
+    // Sending the very same signed request again is refused
+    let timestamp = chrono::Utc::now().timestamp().to_string();
+    let challenge = format!("retrieve:{}:{}:n1", peer_id, timestamp);
+    let signature = general_purpose::STANDARD.encode(keypair.sign(challenge.as_bytes()).unwrap());
+    let peer_id = peer_id.to_string();
+    let query = [
+        ("peer_id", peer_id.as_str()),
+        ("timestamp", timestamp.as_str()),
+        ("nonce", "n1"),
+        ("signature", signature.as_str()),
+    ];
+    let http = reqwest::Client::new();
+    let first = http.get(format!("{}/api/store", url)).query(&query).send().await.unwrap();
+    assert_eq!(first.status(), reqwest::StatusCode::OK);
+    let replayed = http.get(format!("{}/api/store", url)).query(&query).send().await.unwrap();
+    assert_eq!(replayed.status(), reqwest::StatusCode::UNAUTHORIZED);
+}
+
+async fn build_client(dir: &TempDir, url: &str) -> Rc<Client> {
+    Rc::new(
+        ClientBuilder::new()
+            .data_dir(dir.path().to_path_buf())
+            .retry_policy(RetryPolicy::new(1, Duration::from_millis(20), Duration::from_millis(50)))
+            .store_server(url)
+            .build()
+            .await
+            .expect("Failed to build client"),
+    )
+}
+
+#[tokio::test]
+async fn test_offline_recipient_receives_stored_message() {
+    LocalSet::new()
+        .run_until(async {
+            let (url, server) = start_store_server().await;
+            let alice_dir = TempDir::new().unwrap();
+            let bob_dir = TempDir::new().unwrap();
+            let alice = build_client(&alice_dir, &url).await;
+            let bob = build_client(&bob_dir, &url).await;
+            let alice_id = alice.local_peer_id();
+            let bob_id = bob.local_peer_id();
+
+            // Bundles were exchanged earlier; Bob is offline from now on
+            alice
+                .set_contact_prekey_bundle(bob_id.to_string(), bob.get_prekey_bundle_json().await.unwrap())
+                .unwrap();
+            bob.set_contact_prekey_bundle(alice_id.to_string(), alice.get_prekey_bundle_json().await.unwrap())
+                .unwrap();
+
+            let received = Arc::new(Mutex::new(Vec::new()));
+            let received_cb = Arc::clone(&received);
+            bob.register_callback(FunctionCallback::new(move |event| {
+                if let ClientEvent::MessageReceived { message_id, from, .. } = event {
+                    received_cb.lock().unwrap().push((message_id, from));
+                }
+            }))
+            .await;
+
+            let alice_network = Rc::clone(&alice);
+            let network_task = tokio::task::spawn_local(async move {
+                loop {
+                    match alice_network.poll_network_once().await {
+                        Ok(true) => {}
+                        _ => tokio::time::sleep(Duration::from_millis(10)).await,
+                    }
+                }
+            });
+            let alice_outbox = Rc::clone(&alice);
+            let outbox_task = tokio::task::spawn_local(async move {
+                alice_outbox.run_outbox().await;
+            });
+
+            let message_id = alice
+                .send_text_message(bob_id, "see you later".to_string())
+                .await
+                .unwrap();
+
+            // The outbox gives up on direct delivery and uploads the message
+            for _ in 0..250 {
+                let status = alice.database().get_message(&message_id).unwrap().status;
+                if status != MessageStatus::Pending {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            assert_eq!(server.lock().unwrap().len(), 1);
+            assert_eq!(
+                alice.database().get_message(&message_id).unwrap().status,
+                MessageStatus::Sent
+            );
+
+            // Bob comes online: his inbox task fetches, decrypts and deletes it
+            let bob_inbox = Rc::clone(&bob);
+            let inbox_task = tokio::task::spawn_local(async move {
+                bob_inbox.run_offline_inbox().await;
+            });
+            for _ in 0..250 {
+                if !received.lock().unwrap().is_empty() && server.lock().unwrap().is_empty() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            assert_eq!(*received.lock().unwrap(), vec![(message_id.clone(), alice_id)]);
+            let stored = bob.database().get_message(&message_id).unwrap();
+            assert_eq!(stored.sender_peer_id, alice_id.to_string());
+            assert!(server.lock().unwrap().is_empty());
+
+            // Nothing left for a notification-triggered fetch
+            bob.notify_offline_messages();
+            assert_eq!(bob.fetch_offline_messages().await.unwrap(), 0);
+
+            inbox_task.abort();
+            outbox_task.abort();
+            network_task.abort();
+        })
+        .await;
+}
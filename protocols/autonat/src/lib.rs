@@ -0,0 +1,633 @@
+//! MePassa AutoNAT protocol
+//!
+//! The libp2p AutoNAT v1 protocol (`/libp2p/autonat/1.0.0`) on top of
+//! request-response, shared by the client (`mepassa-core`) and the bootstrap
+//! node. A client sends its candidate addresses to a server, which dials it
+//! back on a new connection: a successful dial-back confirms the client is
+//! publicly reachable, a failed one that it sits behind a NAT or firewall.
+//!
+//! The server only dials the IP it observes the request coming from, so it
+//! can't be used to make connections to third parties.
+
+use futures::prelude::*;
+use libp2p::{
+    multiaddr::Protocol,
+    request_response::{self, ResponseChannel},
+    swarm::{
+        dial_opts::{DialOpts, PeerCondition},
+        ConnectionId,
+    },
+    Multiaddr, PeerId, StreamProtocol,
+};
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+
+/// AutoNAT v1 protocol name
+pub const AUTONAT_PROTOCOL: StreamProtocol = StreamProtocol::new("/libp2p/autonat/1.0.0");
+
+/// Largest AutoNAT message accepted (same limit as libp2p)
+pub const MAX_AUTONAT_MESSAGE_SIZE: usize = 1024;
+
+/// Ask a server to dial us back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DialRequest {
+    pub peer_id: PeerId,
+    pub addrs: Vec<Multiaddr>,
+}
+
+/// Server answer to a `DialRequest`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DialResponse {
+    /// The server reached us at this address
+    Reachable(Multiaddr),
+    /// The server did not reach us (or refused to try)
+    Failed { status: ResponseStatus, text: String },
+}
+
+impl DialResponse {
+    pub fn failed(status: ResponseStatus, text: impl Into<String>) -> Self {
+        Self::Failed {
+            status,
+            text: text.into(),
+        }
+    }
+}
+
+/// AutoNAT v1 response status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum ResponseStatus {
+    Ok = 0,
+    /// The dial-back failed: the peer is not reachable on its addresses
+    DialError = 100,
+    /// The server refused to dial (policy, no usable address, busy)
+    DialRefused = 101,
+    BadRequest = 200,
+    InternalError = 300,
+}
+
+// Wire format (autonat v1 `structs.proto`, proto2)
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+enum WireMessageType {
+    Dial = 0,
+    DialResponse = 1,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct WireMessage {
+    #[prost(enumeration = "WireMessageType", optional, tag = "1")]
+    r#type: Option<i32>,
+    #[prost(message, optional, tag = "2")]
+    dial: Option<WireDial>,
+    #[prost(message, optional, tag = "3")]
+    dial_response: Option<WireDialResponse>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct WirePeerInfo {
+    #[prost(bytes = "vec", optional, tag = "1")]
+    id: Option<Vec<u8>>,
+    #[prost(bytes = "vec", repeated, tag = "2")]
+    addrs: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct WireDial {
+    #[prost(message, optional, tag = "1")]
+    peer: Option<WirePeerInfo>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct WireDialResponse {
+    #[prost(enumeration = "ResponseStatus", optional, tag = "1")]
+    status: Option<i32>,
+    #[prost(string, optional, tag = "2")]
+    status_text: Option<String>,
+    #[prost(bytes = "vec", optional, tag = "3")]
+    addr: Option<Vec<u8>>,
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
+
+impl From<DialRequest> for WireMessage {
+    fn from(request: DialRequest) -> Self {
+        Self {
+            r#type: Some(WireMessageType::Dial as i32),
+            dial: Some(WireDial {
+                peer: Some(WirePeerInfo {
+                    id: Some(request.peer_id.to_bytes()),
+                    addrs: request.addrs.into_iter().map(|a| a.to_vec()).collect(),
+                }),
+            }),
+            dial_response: None,
+        }
+    }
+}
+
+impl TryFrom<WireMessage> for DialRequest {
+    type Error = io::Error;
+
+    fn try_from(message: WireMessage) -> io::Result<Self> {
+        if message.r#type != Some(WireMessageType::Dial as i32) {
+            return Err(invalid("expected a DIAL message"));
+        }
+        let peer = message
+            .dial
+            .and_then(|dial| dial.peer)
+            .ok_or_else(|| invalid("DIAL without peer info"))?;
+        let peer_id = peer
+            .id
+            .and_then(|id| PeerId::from_bytes(&id).ok())
+            .ok_or_else(|| invalid("DIAL with an invalid peer id"))?;
+        // Unparseable addresses are skipped, as libp2p does
+        let addrs = peer
+            .addrs
+            .into_iter()
+            .filter_map(|bytes| Multiaddr::try_from(bytes).ok())
+            .collect();
+
+        Ok(Self { peer_id, addrs })
+    }
+}
+
+impl From<DialResponse> for WireMessage {
+    fn from(response: DialResponse) -> Self {
+        let dial_response = match response {
+            DialResponse::Reachable(addr) => WireDialResponse {
+                status: Some(ResponseStatus::Ok as i32),
+                status_text: None,
+                addr: Some(addr.to_vec()),
+            },
+            DialResponse::Failed { status, text } => WireDialResponse {
+                status: Some(status as i32),
+                status_text: Some(text),
+                addr: None,
+            },
+        };
+        Self {
+            r#type: Some(WireMessageType::DialResponse as i32),
+            dial: None,
+            dial_response: Some(dial_response),
+        }
+    }
+}
+
+impl TryFrom<WireMessage> for DialResponse {
+    type Error = io::Error;
+
+    fn try_from(message: WireMessage) -> io::Result<Self> {
+        if message.r#type != Some(WireMessageType::DialResponse as i32) {
+            return Err(invalid("expected a DIAL_RESPONSE message"));
+        }
+        let response = message
+            .dial_response
+            .ok_or_else(|| invalid("DIAL_RESPONSE without body"))?;
+        let status = response
+            .status
+            .and_then(|s| ResponseStatus::try_from(s).ok())
+            .ok_or_else(|| invalid("DIAL_RESPONSE with an unknown status"))?;
+
+        if status == ResponseStatus::Ok {
+            let addr = response
+                .addr
+                .and_then(|bytes| Multiaddr::try_from(bytes).ok())
+                .ok_or_else(|| invalid("OK response without a valid address"))?;
+            Ok(Self::Reachable(addr))
+        } else {
+            Ok(Self::Failed {
+                status,
+                text: response.status_text.unwrap_or_default(),
+            })
+        }
+    }
+}
+
+/// Codec for AutoNAT messages (unsigned-varint length prefixed protobuf)
+#[derive(Clone, Debug, Default)]
+pub struct AutoNatCodec;
+
+impl AutoNatCodec {
+    async fn read_message<T>(io: &mut T) -> io::Result<WireMessage>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let len = read_varint(io).await?;
+        if len > MAX_AUTONAT_MESSAGE_SIZE {
+            return Err(invalid("AutoNAT message too large"));
+        }
+        let mut buf = vec![0u8; len];
+        io.read_exact(&mut buf).await?;
+        prost::Message::decode(buf.as_slice()).map_err(|e| invalid(&e.to_string()))
+    }
+
+    async fn write_message<T>(io: &mut T, message: WireMessage) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        io.write_all(&prost::Message::encode_length_delimited_to_vec(&message))
+            .await?;
+        io.close().await
+    }
+}
+
+/// Read an unsigned varint (at most `usize`-sized)
+async fn read_varint<T>(io: &mut T) -> io::Result<usize>
+where
+    T: AsyncRead + Unpin + Send,
+{
+    let mut value = 0usize;
+    for shift in (0..).step_by(7).take(10) {
+        let mut byte = [0u8; 1];
+        io.read_exact(&mut byte).await?;
+        value |= ((byte[0] & 0x7f) as usize)
+            .checked_shl(shift)
+            .ok_or_else(|| invalid("varint overflow"))?;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("varint too long"))
+}
+
+#[async_trait::async_trait]
+impl request_response::Codec for AutoNatCodec {
+    type Protocol = StreamProtocol;
+    type Request = DialRequest;
+    type Response = DialResponse;
+
+    async fn read_request<T>(
+        &mut self,
+        _protocol: &Self::Protocol,
+        io: &mut T,
+    ) -> io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        Self::read_message(io).await?.try_into()
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _protocol: &Self::Protocol,
+        io: &mut T,
+    ) -> io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        Self::read_message(io).await?.try_into()
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _protocol: &Self::Protocol,
+        io: &mut T,
+        req: Self::Request,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        Self::write_message(io, req.into()).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _protocol: &Self::Protocol,
+        io: &mut T,
+        res: Self::Response,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        Self::write_message(io, res.into()).await
+    }
+}
+
+/// AutoNAT behaviour of a client: sends probes, never answers them
+pub fn client_behaviour() -> request_response::Behaviour<AutoNatCodec> {
+    behaviour(request_response::ProtocolSupport::Outbound)
+}
+
+/// AutoNAT behaviour of a server: answers probes by dialing back
+pub fn server_behaviour() -> request_response::Behaviour<AutoNatCodec> {
+    behaviour(request_response::ProtocolSupport::Inbound)
+}
+
+fn behaviour(
+    support: request_response::ProtocolSupport,
+) -> request_response::Behaviour<AutoNatCodec> {
+    request_response::Behaviour::with_codec(
+        AutoNatCodec,
+        std::iter::once((AUTONAT_PROTOCOL, support)),
+        request_response::Config::default(),
+    )
+}
+
+fn ip_of(addr: &Multiaddr) -> Option<IpAddr> {
+    addr.iter().find_map(|p| match p {
+        Protocol::Ip4(ip) => Some(IpAddr::V4(ip)),
+        Protocol::Ip6(ip) => Some(IpAddr::V6(ip)),
+        _ => None,
+    })
+}
+
+/// Whether an address has an IP reachable from the internet
+pub fn is_global_addr(addr: &Multiaddr) -> bool {
+    ip_of(addr).is_some_and(|ip| is_global_ip(&ip))
+}
+
+/// Whether an IP is reachable from the internet
+pub fn is_global_ip(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                // 100.64.0.0/10 (carrier-grade NAT)
+                || (ip.octets()[0] == 100 && (ip.octets()[1] & 0xc0) == 64))
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                // fc00::/7 (unique local) and fe80::/10 (link local)
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// Addresses a server dials to answer a `DialRequest` from `peer`
+///
+/// The IP of each requested address is replaced with the one the request
+/// was observed from; relayed addresses and addresses of other peers are
+/// dropped.
+pub fn dial_back_addrs(
+    peer: &PeerId,
+    requested: &[Multiaddr],
+    observed: &Multiaddr,
+) -> Vec<Multiaddr> {
+    let Some(observed_ip) = ip_of(observed) else {
+        return Vec::new();
+    };
+    let observed_ip = match observed_ip {
+        IpAddr::V4(ip) => Protocol::Ip4(ip),
+        IpAddr::V6(ip) => Protocol::Ip6(ip),
+    };
+
+    let mut addrs: Vec<Multiaddr> = Vec::new();
+    for addr in requested {
+        let Some(ip_index) = addr
+            .iter()
+            .position(|p| matches!(p, Protocol::Ip4(_) | Protocol::Ip6(_)))
+        else {
+            continue;
+        };
+        let Some(mut addr) = addr.replace(ip_index, |_| Some(observed_ip.clone())) else {
+            continue;
+        };
+
+        let valid = addr.iter().all(|p| match p {
+            Protocol::P2pCircuit => false,
+            Protocol::P2p(id) => id == *peer,
+            _ => true,
+        });
+        if !valid {
+            continue;
+        }
+        if !addr.iter().any(|p| matches!(p, Protocol::P2p(_))) {
+            addr.push(Protocol::P2p(*peer));
+        }
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+    addrs
+}
+
+/// Address a dial-back connection reached, as reported to the client
+pub fn reached_addr(remote_addr: &Multiaddr) -> Multiaddr {
+    let mut reached = remote_addr.clone();
+    if matches!(reached.iter().last(), Some(Protocol::P2p(_))) {
+        reached.pop();
+    }
+    reached
+}
+
+/// Server side: addresses requests come from, and dial-backs in flight
+#[derive(Debug, Default)]
+pub struct DialBackServer {
+    /// Remote address of each peer's direct connection
+    observed: HashMap<PeerId, Multiaddr>,
+    /// Dial-back connection -> requester and the channel awaiting the answer
+    pending: HashMap<ConnectionId, (PeerId, ResponseChannel<DialResponse>)>,
+}
+
+impl DialBackServer {
+    /// Remember where a peer connected from (direct connections only)
+    pub fn connection_established(&mut self, peer: PeerId, remote_addr: &Multiaddr) {
+        if !remote_addr.iter().any(|p| p == Protocol::P2pCircuit) {
+            self.observed.insert(peer, remote_addr.clone());
+        }
+    }
+
+    /// Forget a peer once its last connection is closed
+    pub fn peer_disconnected(&mut self, peer: &PeerId) {
+        self.observed.remove(peer);
+    }
+
+    /// Address a peer's requests are observed from
+    pub fn observed_addr(&self, peer: &PeerId) -> Option<&Multiaddr> {
+        self.observed.get(peer)
+    }
+
+    /// Whether a dial-back to `peer` is already in flight
+    pub fn is_dialing(&self, peer: &PeerId) -> bool {
+        self.pending.values().any(|(p, _)| p == peer)
+    }
+
+    /// How to dial `peer` back for its request, or why not to
+    ///
+    /// The dial asks for a new connection even though the peer is connected
+    /// already; pass its connection id to `dial_started`.
+    pub fn dial_back_opts(
+        &self,
+        peer: PeerId,
+        request: &DialRequest,
+        only_global_ips: bool,
+    ) -> Result<DialOpts, DialResponse> {
+        if request.peer_id != peer {
+            return Err(DialResponse::failed(
+                ResponseStatus::BadRequest,
+                "peer id does not match the connection",
+            ));
+        }
+        let observed = self
+            .observed_addr(&peer)
+            .ok_or_else(|| DialResponse::failed(ResponseStatus::DialRefused, "no direct connection"))?;
+        if only_global_ips && !is_global_addr(observed) {
+            return Err(DialResponse::failed(ResponseStatus::DialRefused, "non-global address"));
+        }
+        if self.is_dialing(&peer) {
+            return Err(DialResponse::failed(
+                ResponseStatus::DialRefused,
+                "dial-back already in progress",
+            ));
+        }
+
+        let addrs = dial_back_addrs(&peer, &request.addrs, observed);
+        if addrs.is_empty() {
+            return Err(DialResponse::failed(ResponseStatus::DialRefused, "no dialable addresses"));
+        }
+
+        Ok(DialOpts::peer_id(peer)
+            .condition(PeerCondition::Always)
+            .addresses(addrs)
+            .build())
+    }
+
+    /// Track a dial-back until its connection is established or fails
+    pub fn dial_started(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        channel: ResponseChannel<DialResponse>,
+    ) {
+        self.pending.insert(connection_id, (peer, channel));
+    }
+
+    /// Channel of the request a dial-back connection answers
+    pub fn dial_finished(
+        &mut self,
+        connection_id: &ConnectionId,
+    ) -> Option<ResponseChannel<DialResponse>> {
+        self.pending.remove(connection_id).map(|(_, channel)| channel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::io::Cursor;
+    use request_response::Codec;
+
+    #[test]
+    fn test_request_roundtrip() {
+        let request = DialRequest {
+            peer_id: PeerId::random(),
+            addrs: vec!["/ip4/1.2.3.4/tcp/4001".parse().unwrap()],
+        };
+
+        let mut buf = Cursor::new(Vec::new());
+        block_on(AutoNatCodec.write_request(&AUTONAT_PROTOCOL, &mut buf, request.clone())).unwrap();
+        let mut read = Cursor::new(buf.into_inner());
+        let decoded = block_on(AutoNatCodec.read_request(&AUTONAT_PROTOCOL, &mut read)).unwrap();
+        assert_eq!(decoded, request);
+    }
+
+    #[test]
+    fn test_response_roundtrip() {
+        for response in [
+            DialResponse::Reachable("/ip4/1.2.3.4/tcp/4001".parse().unwrap()),
+            DialResponse::failed(ResponseStatus::DialError, "dial failed"),
+        ] {
+            let mut buf = Cursor::new(Vec::new());
+            block_on(AutoNatCodec.write_response(&AUTONAT_PROTOCOL, &mut buf, response.clone()))
+                .unwrap();
+            let mut read = Cursor::new(buf.into_inner());
+            let decoded =
+                block_on(AutoNatCodec.read_response(&AUTONAT_PROTOCOL, &mut read)).unwrap();
+            assert_eq!(decoded, response);
+        }
+    }
+
+    #[test]
+    fn test_rejects_oversized_message() {
+        let mut prefix = Vec::new();
+        prost::encoding::encode_varint((MAX_AUTONAT_MESSAGE_SIZE + 1) as u64, &mut prefix);
+        prefix.extend(vec![0u8; MAX_AUTONAT_MESSAGE_SIZE + 1]);
+
+        let err = block_on(AutoNatCodec.read_request(&AUTONAT_PROTOCOL, &mut Cursor::new(prefix)))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_dial_back_uses_observed_ip() {
+        let peer = PeerId::random();
+        let observed: Multiaddr = "/ip4/203.0.113.7/tcp/51234".parse().unwrap();
+        let requested: Vec<Multiaddr> = vec![
+            "/ip4/192.168.1.10/tcp/4001".parse().unwrap(),
+            "/ip4/198.51.100.1/tcp/4001".parse().unwrap(),
+            "/ip4/1.2.3.4/tcp/4001/p2p/12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN/p2p-circuit"
+                .parse()
+                .unwrap(),
+            "/dns4/example.com/tcp/4001".parse().unwrap(),
+        ];
+
+        let addrs = dial_back_addrs(&peer, &requested, &observed);
+        let expected: Multiaddr = format!("/ip4/203.0.113.7/tcp/4001/p2p/{}", peer)
+            .parse()
+            .unwrap();
+        // Both IPv4 candidates collapse into one; relayed and DNS ones are dropped
+        assert_eq!(addrs, vec![expected]);
+    }
+
+    #[test]
+    fn test_dial_back_rejects_other_peer() {
+        let peer = PeerId::random();
+        let other = PeerId::random();
+        let observed: Multiaddr = "/ip4/203.0.113.7/tcp/51234".parse().unwrap();
+        let requested: Multiaddr = format!("/ip4/203.0.113.7/tcp/4001/p2p/{}", other)
+            .parse()
+            .unwrap();
+
+        assert!(dial_back_addrs(&peer, &[requested], &observed).is_empty());
+    }
+
+    #[test]
+    fn test_dial_back_policy() {
+        let peer = PeerId::random();
+        let request = DialRequest {
+            peer_id: peer,
+            addrs: vec!["/ip4/192.168.1.10/tcp/4001".parse().unwrap()],
+        };
+        let mut server = DialBackServer::default();
+
+        // Only peers connected directly, from a public IP unless allowed otherwise
+        assert!(server.dial_back_opts(peer, &request, false).is_err());
+        server.connection_established(peer, &"/ip4/10.0.0.2/tcp/51234".parse().unwrap());
+        assert!(server.dial_back_opts(peer, &request, true).is_err());
+        assert!(server.dial_back_opts(peer, &request, false).is_ok());
+
+        // Not on behalf of another peer
+        let spoofed = DialRequest {
+            peer_id: PeerId::random(),
+            ..request
+        };
+        assert_eq!(
+            server.dial_back_opts(peer, &spoofed, false).unwrap_err(),
+            DialResponse::failed(ResponseStatus::BadRequest, "peer id does not match the connection")
+        );
+    }
+
+    #[test]
+    fn test_global_ips() {
+        assert!(is_global_ip(&"8.8.8.8".parse().unwrap()));
+        assert!(!is_global_ip(&"203.0.113.7".parse().unwrap()));
+        assert!(!is_global_ip(&"10.0.0.1".parse().unwrap()));
+        assert!(!is_global_ip(&"127.0.0.1".parse().unwrap()));
+        assert!(!is_global_ip(&"100.64.1.1".parse().unwrap()));
+        assert!(is_global_ip(&"2001:4860::8888".parse().unwrap()));
+        assert!(!is_global_ip(&"fd00::1".parse().unwrap()));
+        assert!(!is_global_ip(&"fe80::1".parse().unwrap()));
+    }
+}
@@ -0,0 +1,136 @@
+//! MePassa peer authentication
+//!
+//! The servers (identity, store) authenticate requests by a signature with
+//! the Ed25519 key behind the caller's peer id. Ed25519 peer ids embed the
+//! public key, so no key registration is needed. Each server defines the
+//! challenges its requests sign; this crate checks the signatures and the
+//! accepted clock skew.
+
+use base64::{engine::general_purpose, Engine as _};
+use libp2p_identity::{PeerId, PublicKey};
+use thiserror::Error;
+
+/// Accepted distance between a request's timestamp and the server clock
+pub const MAX_CLOCK_SKEW_SECS: i64 = 300;
+
+/// Multihash code of peer ids that inline their public key
+const IDENTITY_MULTIHASH: u64 = 0x00;
+
+/// Why a request was not authenticated
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum AuthError {
+    #[error("Invalid peer id")]
+    InvalidPeerId,
+
+    #[error("Peer id does not embed an Ed25519 public key")]
+    UnsupportedKey,
+
+    #[error("Invalid signature")]
+    InvalidSignature,
+
+    #[error("Timestamp too old or in future")]
+    Expired,
+
+    #[error("Signature already used")]
+    Replayed,
+}
+
+/// Public key embedded in an (Ed25519) peer id
+pub fn public_key_of(peer_id: &str) -> Result<PublicKey, AuthError> {
+    let peer_id: PeerId = peer_id.parse().map_err(|_| AuthError::InvalidPeerId)?;
+    let multihash = peer_id.as_ref();
+    if multihash.code() != IDENTITY_MULTIHASH {
+        return Err(AuthError::UnsupportedKey);
+    }
+
+    let public_key =
+        PublicKey::try_decode_protobuf(multihash.digest()).map_err(|_| AuthError::UnsupportedKey)?;
+    public_key
+        .clone()
+        .try_into_ed25519()
+        .map_err(|_| AuthError::UnsupportedKey)?;
+    Ok(public_key)
+}
+
+/// Check a base64 signature over `message` by the key embedded in `peer_id`
+pub fn verify_signature(peer_id: &str, message: &str, signature_b64: &str) -> Result<(), AuthError> {
+    let public_key = public_key_of(peer_id)?;
+    let signature = general_purpose::STANDARD
+        .decode(signature_b64)
+        .map_err(|_| AuthError::InvalidSignature)?;
+
+    if !public_key.verify(message.as_bytes(), &signature) {
+        return Err(AuthError::InvalidSignature);
+    }
+
+    Ok(())
+}
+
+/// Check that a request's `timestamp` is within `MAX_CLOCK_SKEW_SECS` of `now`
+pub fn check_timestamp(timestamp: i64, now: i64) -> Result<(), AuthError> {
+    if (now - timestamp).abs() > MAX_CLOCK_SKEW_SECS {
+        return Err(AuthError::Expired);
+    }
+    Ok(())
+}
+
+/// Check that `peer_id` signed `challenge` at `timestamp` (seconds)
+pub fn verify(
+    peer_id: &str,
+    challenge: &str,
+    signature_b64: &str,
+    timestamp: i64,
+    now: i64,
+) -> Result<(), AuthError> {
+    check_timestamp(timestamp, now)?;
+    verify_signature(peer_id, challenge, signature_b64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p_identity::Keypair;
+
+    fn sign(keypair: &Keypair, message: &str) -> String {
+        general_purpose::STANDARD.encode(keypair.sign(message.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn test_public_key_of() {
+        let keypair = Keypair::generate_ed25519();
+        let peer_id = keypair.public().to_peer_id().to_string();
+        assert_eq!(public_key_of(&peer_id), Ok(keypair.public()));
+
+        assert_eq!(public_key_of("not a peer id"), Err(AuthError::InvalidPeerId));
+        // Hashed peer ids don't carry their key
+        let hashed = "QmYyQSo1c1Ym7orWxLYvCrM2EmxFTANf8wXmmE7DWjhx5N";
+        assert_eq!(public_key_of(hashed), Err(AuthError::UnsupportedKey));
+    }
+
+    #[test]
+    fn test_verify() {
+        let keypair = Keypair::generate_ed25519();
+        let peer_id = keypair.public().to_peer_id().to_string();
+        let signature = sign(&keypair, "hello");
+
+        assert_eq!(verify(&peer_id, "hello", &signature, 1_000, 1_100), Ok(()));
+        assert_eq!(
+            verify(&peer_id, "hello!", &signature, 1_000, 1_100),
+            Err(AuthError::InvalidSignature)
+        );
+        assert_eq!(
+            verify(&peer_id, "hello", "not base64", 1_000, 1_100),
+            Err(AuthError::InvalidSignature)
+        );
+        assert_eq!(
+            verify(&peer_id, "hello", &signature, 1_000, 1_000 + MAX_CLOCK_SKEW_SECS + 1),
+            Err(AuthError::Expired)
+        );
+
+        let other = Keypair::generate_ed25519().public().to_peer_id().to_string();
+        assert_eq!(
+            verify(&other, "hello", &signature, 1_000, 1_000),
+            Err(AuthError::InvalidSignature)
+        );
+    }
+}
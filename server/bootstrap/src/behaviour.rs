@@ -1,16 +1,17 @@
 use libp2p::{
-    dcutr, identify, kad, ping, relay,
+    dcutr, identify, kad, ping, relay, request_response,
     swarm::NetworkBehaviour,
     PeerId,
 };
 use std::time::Duration;
 
+use mepassa_autonat::AutoNatCodec;
 use crate::config::Config;
 
 /// Custom NetworkBehaviour for the Bootstrap Node
 ///
 /// Combines Kademlia DHT for peer discovery, Circuit Relay v2 for NAT traversal,
-/// DCUtR for hole punching, Identify and Ping protocols.
+/// DCUtR for hole punching, AutoNAT dial-backs, Identify and Ping protocols.
 #[derive(NetworkBehaviour)]
 pub struct BootstrapBehaviour {
     /// Kademlia DHT for peer discovery
@@ -27,6 +28,9 @@ pub struct BootstrapBehaviour {
 
     /// DCUtR for direct connection upgrade through relay
     pub dcutr: dcutr::Behaviour,
+
+    /// AutoNAT server: dials peers back to confirm their reachability
+    pub autonat: request_response::Behaviour<AutoNatCodec>,
 }
 
 impl BootstrapBehaviour {
@@ -91,6 +95,7 @@ impl BootstrapBehaviour {
             ping,
             relay,
             dcutr,
+            autonat: mepassa_autonat::server_behaviour(),
         }
     }
 }
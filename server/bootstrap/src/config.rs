@@ -30,6 +30,9 @@ pub struct Config {
 
     /// Maximum bytes per second per circuit
     pub relay_max_bytes_per_second: u64,
+
+    /// Only answer AutoNAT probes from peers with public IPs
+    pub autonat_only_global_ips: bool,
 }
 
 impl Config {
@@ -71,6 +74,10 @@ impl Config {
             relay_max_bytes_per_second: std::env::var("RELAY_MAX_BYTES_PER_SEC")
                 .unwrap_or_else(|_| "1000000".to_string())
                 .parse()?,
+
+            autonat_only_global_ips: std::env::var("AUTONAT_ONLY_GLOBAL_IPS")
+                .unwrap_or_else(|_| "true".to_string())
+                .parse()?,
         })
     }
 
@@ -3,7 +3,7 @@ use futures::stream::StreamExt;
 use libp2p::{
     core::upgrade,
     identity::Keypair,
-    noise, tcp, yamux,
+    noise, request_response, tcp, yamux,
     swarm::{Config as SwarmConfig, Swarm, SwarmEvent},
     Multiaddr, PeerId, Transport,
 };
@@ -15,6 +15,7 @@ mod behaviour;
 mod storage;
 mod health;
 
+use mepassa_autonat::{DialBackServer, DialResponse, ResponseStatus};
 use config::Config;
 use behaviour::BootstrapBehaviour;
 use storage::DhtStorage;
@@ -84,6 +85,9 @@ async fn main() -> Result<()> {
     let health_server = health::start_server(config.health_port, peer_count.clone());
     tokio::spawn(health_server);
 
+    // AutoNAT dial-backs in flight
+    let mut dial_back = DialBackServer::default();
+
     info!("✅ Bootstrap node ready!");
 
     // 9. Event loop
@@ -94,15 +98,35 @@ async fn main() -> Result<()> {
             }
 
             SwarmEvent::Behaviour(event) => {
-                handle_behaviour_event(event, &mut swarm, &storage, &peer_count).await;
+                handle_behaviour_event(
+                    event,
+                    &mut swarm,
+                    &storage,
+                    &peer_count,
+                    &mut dial_back,
+                    &config,
+                )
+                .await;
             }
 
             SwarmEvent::IncomingConnection { local_addr, send_back_addr, connection_id } => {
                 info!("📥 Incoming connection from {} to {} (id: {:?})", send_back_addr, local_addr, connection_id);
             }
 
-            SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
+            SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint, .. } => {
                 info!("✅ Connection established with {}", peer_id);
+                dial_back.connection_established(peer_id, endpoint.get_remote_address());
+
+                // One of our AutoNAT dial-backs got through
+                if let Some(channel) = dial_back.dial_finished(&connection_id) {
+                    let reached = mepassa_autonat::reached_addr(endpoint.get_remote_address());
+                    info!("🧪 Dial-back to {} succeeded at {}", peer_id, reached);
+                    let _ = swarm
+                        .behaviour_mut()
+                        .autonat
+                        .send_response(channel, DialResponse::Reachable(reached));
+                    swarm.close_connection(connection_id);
+                }
 
                 // Add to DHT
                 let addr = endpoint.get_remote_address();
@@ -119,12 +143,21 @@ async fn main() -> Result<()> {
                 peer_count.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
             }
 
-            SwarmEvent::ConnectionClosed { peer_id, cause, .. } => {
+            SwarmEvent::ConnectionClosed { peer_id, cause, num_established, .. } => {
                 warn!("❌ Connection closed with {}: {:?}", peer_id, cause);
+                if num_established == 0 {
+                    dial_back.peer_disconnected(&peer_id);
+                }
                 peer_count.fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
             }
 
-            SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
+            SwarmEvent::OutgoingConnectionError { connection_id, peer_id, error } => {
+                if let Some(channel) = dial_back.dial_finished(&connection_id) {
+                    let response =
+                        DialResponse::failed(ResponseStatus::DialError, error.to_string());
+                    let _ = swarm.behaviour_mut().autonat.send_response(channel, response);
+                }
+
                 if let Some(peer) = peer_id {
                     warn!("❌ Outgoing connection error to {}: {}", peer, error);
                 } else {
@@ -147,6 +180,8 @@ async fn handle_behaviour_event(
     swarm: &mut libp2p::Swarm<BootstrapBehaviour>,
     storage: &DhtStorage,
     _peer_count: &std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    dial_back: &mut DialBackServer,
+    config: &Config,
 ) {
     match event {
         // Kademlia events
@@ -249,6 +284,34 @@ async fn handle_behaviour_event(
             // DCUtR events are logged at debug level
             tracing::debug!("🎯 DCUtR event: {:?}", dcutr_event);
         }
+
+        // AutoNAT probes
+        behaviour::BootstrapBehaviourEvent::Autonat(autonat_event) => {
+            if let request_response::Event::Message {
+                peer,
+                message: request_response::Message::Request { request, channel, .. },
+            } = autonat_event
+            {
+                let dial = dial_back
+                    .dial_back_opts(peer, &request, config.autonat_only_global_ips)
+                    .and_then(|opts| {
+                        let connection_id = opts.connection_id();
+                        swarm
+                            .dial(opts)
+                            .map(|()| connection_id)
+                            .map_err(|e| DialResponse::failed(ResponseStatus::DialError, e.to_string()))
+                    });
+                match dial {
+                    Ok(connection_id) => dial_back.dial_started(connection_id, peer, channel),
+                    Err(response) => {
+                        tracing::debug!("🧪 Not dialing back {}: {:?}", peer, response);
+                        let _ = swarm.behaviour_mut().autonat.send_response(channel, response);
+                    }
+                }
+            } else {
+                tracing::debug!("🧪 AutoNAT event: {:?}", autonat_event);
+            }
+        }
     }
 }
 
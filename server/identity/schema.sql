@@ -29,3 +29,41 @@ CREATE TRIGGER trigger_update_last_updated
 BEFORE UPDATE ON usernames
 FOR EACH ROW
 EXECUTE FUNCTION update_last_updated();
+
+-- Devices linked to an account (identified by its primary's Peer ID)
+CREATE TABLE IF NOT EXISTS devices (
+    device_peer_id TEXT PRIMARY KEY,
+    account_peer_id TEXT NOT NULL,
+    device_name TEXT NOT NULL,
+    requested_at BIGINT NOT NULL,
+    device_signature TEXT NOT NULL,
+    issued_at BIGINT NOT NULL,
+    certificate_signature TEXT NOT NULL,
+    prekey_bundle JSONB NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    last_updated TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_devices_account ON devices(account_peer_id);
+
+-- One-time prekeys uploaded by a peer; each is handed out to one initiator
+CREATE TABLE IF NOT EXISTS one_time_prekeys (
+    peer_id TEXT NOT NULL,
+    key_id INTEGER NOT NULL,
+    public_key TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (peer_id, key_id)
+);
+
+-- Move one-time prekeys embedded in stored bundles into the table
+INSERT INTO one_time_prekeys (peer_id, key_id, public_key)
+SELECT peer_id,
+       (prekey_bundle->'one_time_prekey'->>'id')::INTEGER,
+       prekey_bundle->'one_time_prekey'->>'public_key'
+FROM usernames
+WHERE jsonb_typeof(prekey_bundle->'one_time_prekey') = 'object'
+ON CONFLICT (peer_id, key_id) DO NOTHING;
+
+UPDATE usernames
+SET prekey_bundle = prekey_bundle - 'one_time_prekey'
+WHERE prekey_bundle ? 'one_time_prekey';
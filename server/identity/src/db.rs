@@ -1,6 +1,6 @@
 //! Database operations for Identity Server
 
-use sqlx::{PgPool, Row, postgres::PgPoolOptions};
+use sqlx::{PgConnection, PgPool, Row, postgres::PgPoolOptions};
 use crate::{error::Result, models::*};
 
 /// Initialize database connection pool
@@ -26,6 +26,47 @@ pub fn validate_username(username: &str) -> Result<()> {
     Ok(())
 }
 
+/// Bundle as stored: its one-time prekey goes to `one_time_prekeys`
+fn stored_bundle_json(prekey_bundle: &PreKeyBundle) -> Result<serde_json::Value> {
+    let bundle = PreKeyBundle {
+        one_time_prekey: None,
+        ..prekey_bundle.clone()
+    };
+    serde_json::to_value(&bundle).map_err(|e| crate::error::AppError::Internal(e.into()))
+}
+
+/// Add one-time prekeys of a peer (IDs it already has are left alone)
+async fn insert_one_time_prekeys(
+    conn: &mut PgConnection,
+    peer_id: &str,
+    one_time_prekeys: &[OneTimePreKey],
+) -> Result<()> {
+    if one_time_prekeys.is_empty() {
+        return Ok(());
+    }
+    let ids: Vec<i32> = one_time_prekeys.iter().map(|prekey| prekey.id).collect();
+    let public_keys: Vec<&str> = one_time_prekeys
+        .iter()
+        .map(|prekey| prekey.public_key.as_str())
+        .collect();
+
+    sqlx::query(
+        r#"
+        INSERT INTO one_time_prekeys (peer_id, key_id, public_key)
+        SELECT $1, key_id, public_key
+        FROM UNNEST($2::INTEGER[], $3::TEXT[]) AS uploaded(key_id, public_key)
+        ON CONFLICT (peer_id, key_id) DO NOTHING
+        "#,
+    )
+    .bind(peer_id)
+    .bind(&ids)
+    .bind(&public_keys)
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
 /// Register a new username
 pub async fn register_username(
     pool: &PgPool,
@@ -36,9 +77,9 @@ pub async fn register_username(
 ) -> Result<RegisterResponse> {
     validate_username(username)?;
 
-    let prekey_bundle_json = serde_json::to_value(prekey_bundle)
-        .map_err(|e| crate::error::AppError::Internal(e.into()))?;
+    let prekey_bundle_json = stored_bundle_json(prekey_bundle)?;
 
+    let mut tx = pool.begin().await?;
     let result = sqlx::query(
         r#"
         INSERT INTO usernames (username, peer_id, public_key, prekey_bundle)
@@ -50,12 +91,16 @@ pub async fn register_username(
     .bind(peer_id)
     .bind(public_key)
     .bind(prekey_bundle_json)
-    .fetch_one(pool)
+    .fetch_one(&mut *tx)
     .await;
 
     match result {
         Ok(row) => {
             let created_at: chrono::NaiveDateTime = row.try_get("created_at")?;
+            let one_time_prekeys: Vec<OneTimePreKey> =
+                prekey_bundle.one_time_prekey.iter().cloned().collect();
+            insert_one_time_prekeys(&mut tx, peer_id, &one_time_prekeys).await?;
+            tx.commit().await?;
             Ok(RegisterResponse {
                 username: username.to_string(),
                 peer_id: peer_id.to_string(),
@@ -70,6 +115,9 @@ pub async fn register_username(
 }
 
 /// Lookup username
+///
+/// The bundle carries one of the peer's one-time prekeys, which is removed
+/// so no other lookup gets it (none once they run out).
 pub async fn lookup_username(pool: &PgPool, username: &str) -> Result<LookupResponse> {
     let row = sqlx::query_as::<_, UsernameRow>(
         r#"
@@ -83,22 +131,70 @@ pub async fn lookup_username(pool: &PgPool, username: &str) -> Result<LookupResp
     .await?;
 
     match row {
-        Some(row) => row
-            .to_lookup_response()
-            .map_err(|e| crate::error::AppError::Internal(e.into())),
+        Some(row) => {
+            let mut response = row
+                .to_lookup_response()
+                .map_err(|e| crate::error::AppError::Internal(e.into()))?;
+            response.prekey_bundle.one_time_prekey =
+                pop_one_time_prekey(pool, &response.peer_id).await?;
+            Ok(response)
+        }
         None => Err(crate::error::AppError::UsernameNotFound(username.to_string())),
     }
 }
 
-/// Update prekeys for a username
+/// Remove and return the peer's lowest one-time prekey
+///
+/// A single statement, so concurrent lookups never get the same key.
+pub async fn pop_one_time_prekey(pool: &PgPool, peer_id: &str) -> Result<Option<OneTimePreKey>> {
+    let row = sqlx::query(
+        r#"
+        DELETE FROM one_time_prekeys
+        WHERE (peer_id, key_id) = (
+            SELECT peer_id, key_id
+            FROM one_time_prekeys
+            WHERE peer_id = $1
+            ORDER BY key_id
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING key_id, public_key
+        "#,
+    )
+    .bind(peer_id)
+    .fetch_optional(pool)
+    .await?;
+
+    row.map(|row| {
+        Ok(OneTimePreKey {
+            id: row.try_get("key_id")?,
+            public_key: row.try_get("public_key")?,
+        })
+    })
+    .transpose()
+}
+
+/// One-time prekeys a peer has left
+pub async fn count_one_time_prekeys(pool: &PgPool, peer_id: &str) -> Result<i64> {
+    let row = sqlx::query("SELECT COUNT(*) AS count FROM one_time_prekeys WHERE peer_id = $1")
+        .bind(peer_id)
+        .fetch_one(pool)
+        .await?;
+
+    Ok(row.try_get("count")?)
+}
+
+/// Update prekeys for a username, adding `one_time_prekeys` to the ones
+/// lookups hand out
 pub async fn update_prekeys(
     pool: &PgPool,
     peer_id: &str,
     prekey_bundle: &PreKeyBundle,
+    one_time_prekeys: &[OneTimePreKey],
 ) -> Result<UpdatePrekeysResponse> {
-    let prekey_bundle_json = serde_json::to_value(prekey_bundle)
-        .map_err(|e| crate::error::AppError::Internal(e.into()))?;
+    let prekey_bundle_json = stored_bundle_json(prekey_bundle)?;
 
+    let mut tx = pool.begin().await?;
     let result = sqlx::query(
         r#"
         UPDATE usernames
@@ -109,20 +205,100 @@ pub async fn update_prekeys(
     )
     .bind(prekey_bundle_json)
     .bind(peer_id)
-    .fetch_optional(pool)
+    .fetch_optional(&mut *tx)
     .await?;
 
     match result {
         Some(row) => {
             let last_updated: chrono::NaiveDateTime = row.try_get("last_updated")?;
+            insert_one_time_prekeys(&mut tx, peer_id, one_time_prekeys).await?;
+            tx.commit().await?;
+
             Ok(UpdatePrekeysResponse {
                 updated_at: last_updated.and_utc(),
+                one_time_prekey_count: count_one_time_prekeys(pool, peer_id).await?,
             })
         }
         None => Err(crate::error::AppError::UsernameNotFound(peer_id.to_string())),
     }
 }
 
+/// Insert or refresh a linked device
+///
+/// A device stays linked to the account that first published it.
+pub async fn publish_device(
+    pool: &PgPool,
+    certificate: &DeviceCertificate,
+    prekey_bundle: &PreKeyBundle,
+) -> Result<PublishDeviceResponse> {
+    let prekey_bundle_json = serde_json::to_value(prekey_bundle)
+        .map_err(|e| crate::error::AppError::Internal(e.into()))?;
+
+    let row = sqlx::query(
+        r#"
+        INSERT INTO devices (
+            device_peer_id, account_peer_id, device_name, requested_at,
+            device_signature, issued_at, certificate_signature, prekey_bundle
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        ON CONFLICT (device_peer_id) DO UPDATE SET
+            device_name = EXCLUDED.device_name,
+            requested_at = EXCLUDED.requested_at,
+            device_signature = EXCLUDED.device_signature,
+            issued_at = EXCLUDED.issued_at,
+            certificate_signature = EXCLUDED.certificate_signature,
+            prekey_bundle = EXCLUDED.prekey_bundle,
+            last_updated = NOW()
+        WHERE devices.account_peer_id = EXCLUDED.account_peer_id
+        RETURNING last_updated
+        "#,
+    )
+    .bind(&certificate.device_peer_id)
+    .bind(&certificate.account_peer_id)
+    .bind(&certificate.device_name)
+    .bind(certificate.requested_at)
+    .bind(&certificate.device_signature)
+    .bind(certificate.issued_at)
+    .bind(&certificate.signature)
+    .bind(prekey_bundle_json)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| crate::error::AppError::DeviceLinked(certificate.device_peer_id.clone()))?;
+
+    let last_updated: chrono::NaiveDateTime = row.try_get("last_updated")?;
+    Ok(PublishDeviceResponse {
+        updated_at: last_updated.and_utc(),
+    })
+}
+
+/// Devices linked to an account (oldest first)
+pub async fn list_devices(pool: &PgPool, account_peer_id: &str) -> Result<DevicesResponse> {
+    let rows = sqlx::query_as::<_, DeviceRow>(
+        r#"
+        SELECT device_peer_id, account_peer_id, device_name, requested_at,
+               device_signature, issued_at, certificate_signature, prekey_bundle,
+               last_updated
+        FROM devices
+        WHERE account_peer_id = $1
+        ORDER BY created_at ASC
+        "#,
+    )
+    .bind(account_peer_id)
+    .fetch_all(pool)
+    .await?;
+
+    let devices = rows
+        .into_iter()
+        .map(DeviceRow::to_linked_device)
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| crate::error::AppError::Internal(e.into()))?;
+
+    Ok(DevicesResponse {
+        account_peer_id: account_peer_id.to_string(),
+        devices,
+    })
+}
+
 /// Check database health
 pub async fn check_health(pool: &PgPool) -> Result<f64> {
     let start = std::time::Instant::now();
@@ -0,0 +1,359 @@
+//! Linked device verification
+//!
+//! An account is identified by the Peer ID of its primary device; further
+//! devices carry a certificate signed by the account key, which embeds the
+//! device's own signed link request, so an account can't claim a device
+//! that didn't ask to be linked. Ed25519 peer ids embed the public key, so
+//! certificates and requests are checked against the peer ids themselves
+//! (see `mepassa_peer_auth`) and no key registration is needed. Publish
+//! requests are signed over a digest of what they publish and a random
+//! nonce, and each signature is only accepted once, like prekey uploads.
+
+use base64::{engine::general_purpose, Engine as _};
+use mepassa_peer_auth::{public_key_of, verify_signature};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::{AppError, Result},
+    models::{DeviceCertificate, PreKeyBundle, PublishDeviceRequest},
+};
+
+/// Longest accepted device name
+pub const MAX_DEVICE_NAME_LEN: usize = 64;
+
+/// How long after its link request a device can be linked (seconds)
+pub const LINK_REQUEST_TTL_SECS: i64 = 10 * 60;
+
+/// Signed by the device when it asks to be linked
+///
+/// Format: "link:{device}:{requested_at}:{device_name}"
+pub fn link_challenge(device_peer_id: &str, device_name: &str, requested_at: i64) -> String {
+    format!("link:{}:{}:{}", device_peer_id, requested_at, device_name)
+}
+
+/// Signed by the account when it links a device
+///
+/// Format: "device:{account}:{device}:{issued_at}:{requested_at}:{device_name}"
+pub fn certificate_challenge(certificate: &DeviceCertificate) -> String {
+    format!(
+        "device:{}:{}:{}:{}:{}",
+        certificate.account_peer_id,
+        certificate.device_peer_id,
+        certificate.issued_at,
+        certificate.requested_at,
+        certificate.device_name
+    )
+}
+
+/// Digest of the certificate and bundle a publish request carries
+///
+/// Format: hex SHA-256 of one line per field: the certificate's account,
+/// device, device name, requested_at, device signature, issued_at and
+/// signature, then the bundle's identity key, signed prekey id, signed
+/// prekey and its signature, and its one-time prekey ("{id}:{public_key}",
+/// or "-" without one)
+pub fn publish_digest(certificate: &DeviceCertificate, bundle: &PreKeyBundle) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!(
+        "{}\n{}\n{}\n{}\n{}\n{}\n{}\n",
+        certificate.account_peer_id,
+        certificate.device_peer_id,
+        certificate.device_name,
+        certificate.requested_at,
+        certificate.device_signature,
+        certificate.issued_at,
+        certificate.signature
+    ));
+    hasher.update(format!(
+        "{}\n{}\n{}\n{}\n",
+        bundle.identity_key,
+        bundle.signed_prekey_id,
+        bundle.signed_prekey,
+        bundle.signed_prekey_signature
+    ));
+    match &bundle.one_time_prekey {
+        Some(prekey) => hasher.update(format!("{}:{}\n", prekey.id, prekey.public_key)),
+        None => hasher.update("-\n"),
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Signed by the account or the device when publishing the device
+///
+/// Format: "devices:{signer}:{device}:{timestamp}:{nonce}:{publish_digest}"
+pub fn publish_challenge(
+    signer_peer_id: &str,
+    device_peer_id: &str,
+    timestamp: i64,
+    nonce: &str,
+    publish_digest: &str,
+) -> String {
+    format!(
+        "devices:{}:{}:{}:{}:{}",
+        signer_peer_id, device_peer_id, timestamp, nonce, publish_digest
+    )
+}
+
+/// Check the account's signature over a device certificate and the
+/// device's signature over the link request it was issued for
+pub fn verify_certificate(certificate: &DeviceCertificate) -> Result<()> {
+    if certificate.account_peer_id == certificate.device_peer_id {
+        return Err(AppError::InvalidCertificate(
+            "an account can't link itself".to_string(),
+        ));
+    }
+    if certificate.device_name.is_empty() || certificate.device_name.len() > MAX_DEVICE_NAME_LEN {
+        return Err(AppError::InvalidCertificate("invalid device name".to_string()));
+    }
+    let age = certificate.issued_at - certificate.requested_at;
+    if !(0..=LINK_REQUEST_TTL_SECS).contains(&age) {
+        return Err(AppError::InvalidCertificate(
+            "issued outside the link request's lifetime".to_string(),
+        ));
+    }
+
+    verify_signature(
+        &certificate.device_peer_id,
+        &link_challenge(
+            &certificate.device_peer_id,
+            &certificate.device_name,
+            certificate.requested_at,
+        ),
+        &certificate.device_signature,
+    )
+    .map_err(|_| AppError::InvalidCertificate("device did not ask to be linked".to_string()))?;
+
+    verify_signature(
+        &certificate.account_peer_id,
+        &certificate_challenge(certificate),
+        &certificate.signature,
+    )
+    .map_err(|_| AppError::InvalidCertificate("bad certificate signature".to_string()))
+}
+
+/// Check that a prekey bundle carries the device's identity key and a
+/// signed prekey signed by it
+pub fn verify_bundle(device_peer_id: &str, bundle: &PreKeyBundle) -> Result<()> {
+    let device_key = public_key_of(device_peer_id)
+        .map_err(|e| AppError::InvalidCertificate(e.to_string()))?
+        .try_into_ed25519()
+        .map_err(|_| AppError::InvalidCertificate("unsupported device key".to_string()))?;
+    let identity_key = general_purpose::STANDARD
+        .decode(&bundle.identity_key)
+        .map_err(|_| AppError::InvalidCertificate("invalid identity key".to_string()))?;
+    if identity_key != device_key.to_bytes() {
+        return Err(AppError::InvalidCertificate(
+            "prekey bundle belongs to another identity".to_string(),
+        ));
+    }
+
+    let signed_prekey = general_purpose::STANDARD
+        .decode(&bundle.signed_prekey)
+        .map_err(|_| AppError::InvalidCertificate("invalid signed prekey".to_string()))?;
+    let signature = general_purpose::STANDARD
+        .decode(&bundle.signed_prekey_signature)
+        .map_err(|_| AppError::InvalidCertificate("invalid signed prekey".to_string()))?;
+    if !device_key.verify(&signed_prekey, &signature) {
+        return Err(AppError::InvalidCertificate(
+            "bad signed prekey signature".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Check a publish request: signed by the account or the device itself over
+/// what it publishes, within `mepassa_peer_auth::MAX_CLOCK_SKEW_SECS` of `now`
+pub fn verify_publish_request(req: &PublishDeviceRequest, now: i64) -> Result<()> {
+    let certificate = &req.certificate;
+    if req.signer_peer_id != certificate.account_peer_id
+        && req.signer_peer_id != certificate.device_peer_id
+    {
+        return Err(AppError::InvalidSignature);
+    }
+    if req.nonce.is_empty() {
+        return Err(AppError::InvalidSignature);
+    }
+
+    let digest = publish_digest(certificate, &req.prekey_bundle);
+    mepassa_peer_auth::verify(
+        &req.signer_peer_id,
+        &publish_challenge(
+            &req.signer_peer_id,
+            &certificate.device_peer_id,
+            req.timestamp,
+            &req.nonce,
+            &digest,
+        ),
+        &req.signature,
+        req.timestamp,
+        now,
+    )
+    .map_err(|_| AppError::InvalidSignature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p_identity::Keypair;
+
+    fn sign(keypair: &Keypair, message: &str) -> String {
+        general_purpose::STANDARD.encode(keypair.sign(message.as_bytes()).unwrap())
+    }
+
+    fn peer_id(keypair: &Keypair) -> String {
+        keypair.public().to_peer_id().to_string()
+    }
+
+    fn certificate(account: &Keypair, device: &Keypair) -> DeviceCertificate {
+        let device_id = peer_id(device);
+        let mut certificate = DeviceCertificate {
+            account_peer_id: peer_id(account),
+            device_signature: sign(device, &link_challenge(&device_id, "Laptop", 900)),
+            device_peer_id: device_id,
+            device_name: "Laptop".to_string(),
+            requested_at: 900,
+            issued_at: 1_000,
+            signature: String::new(),
+        };
+        certificate.signature = sign(account, &certificate_challenge(&certificate));
+        certificate
+    }
+
+    fn bundle(device: &Keypair) -> PreKeyBundle {
+        let signed_prekey = [7u8; 32];
+        let identity_key = device.public().try_into_ed25519().unwrap().to_bytes();
+        PreKeyBundle {
+            identity_key: general_purpose::STANDARD.encode(identity_key),
+            signed_prekey_id: 1,
+            signed_prekey: general_purpose::STANDARD.encode(signed_prekey),
+            signed_prekey_signature: general_purpose::STANDARD
+                .encode(device.sign(&signed_prekey).unwrap()),
+            one_time_prekey: None,
+        }
+    }
+
+    #[test]
+    fn test_verify_certificate() {
+        let account = Keypair::generate_ed25519();
+        let device = Keypair::generate_ed25519();
+        let certificate = certificate(&account, &device);
+        assert!(verify_certificate(&certificate).is_ok());
+
+        // Signed by someone other than the account
+        let mallory = Keypair::generate_ed25519();
+        let mut forged = certificate.clone();
+        forged.signature = sign(&mallory, &certificate_challenge(&forged));
+        assert!(verify_certificate(&forged).is_err());
+
+        // Renamed after signing
+        let mut renamed = certificate;
+        renamed.device_name = "Phone".to_string();
+        assert!(verify_certificate(&renamed).is_err());
+    }
+
+    #[test]
+    fn test_rejects_foreign_account_claiming_device() {
+        let account = Keypair::generate_ed25519();
+        let device = Keypair::generate_ed25519();
+        let mallory = Keypair::generate_ed25519();
+
+        // Mallory signs a certificate for a device that never asked her
+        let mut claimed = certificate(&mallory, &device);
+        claimed.device_signature = sign(
+            &mallory,
+            &link_challenge(&claimed.device_peer_id, "Laptop", claimed.requested_at),
+        );
+        claimed.signature = sign(&mallory, &certificate_challenge(&claimed));
+        assert!(verify_certificate(&claimed).is_err());
+
+        // Without the consent at all
+        let mut unsigned = certificate(&mallory, &device);
+        unsigned.device_signature = String::new();
+        assert!(verify_certificate(&unsigned).is_err());
+
+        // Or long after the device asked the account to link it
+        let mut late = certificate(&account, &device);
+        late.issued_at = late.requested_at + LINK_REQUEST_TTL_SECS + 1;
+        late.signature = sign(&account, &certificate_challenge(&late));
+        assert!(verify_certificate(&late).is_err());
+    }
+
+    #[test]
+    fn test_verify_bundle_owner() {
+        let device = Keypair::generate_ed25519();
+        let other = Keypair::generate_ed25519();
+        assert!(verify_bundle(&peer_id(&device), &bundle(&device)).is_ok());
+        assert!(verify_bundle(&peer_id(&device), &bundle(&other)).is_err());
+    }
+
+    fn publish_request(
+        signer: &Keypair,
+        device: &Keypair,
+        certificate: &DeviceCertificate,
+        nonce: &str,
+    ) -> PublishDeviceRequest {
+        let mut req = PublishDeviceRequest {
+            signer_peer_id: peer_id(signer),
+            certificate: certificate.clone(),
+            prekey_bundle: bundle(device),
+            nonce: nonce.to_string(),
+            signature: String::new(),
+            timestamp: 1_000,
+        };
+        let digest = publish_digest(&req.certificate, &req.prekey_bundle);
+        let challenge = publish_challenge(
+            &req.signer_peer_id,
+            &certificate.device_peer_id,
+            req.timestamp,
+            &req.nonce,
+            &digest,
+        );
+        req.signature = sign(signer, &challenge);
+        req
+    }
+
+    #[test]
+    fn test_verify_publish_request() {
+        let account = Keypair::generate_ed25519();
+        let device = Keypair::generate_ed25519();
+        let certificate = certificate(&account, &device);
+
+        for signer in [&account, &device] {
+            let req = publish_request(signer, &device, &certificate, "n1");
+            assert!(verify_publish_request(&req, 1_100).is_ok());
+            // Stale
+            assert!(verify_publish_request(&req, 2_000).is_err());
+        }
+
+        // A third party can't publish someone else's device
+        let mallory = Keypair::generate_ed25519();
+        let req = publish_request(&mallory, &device, &certificate, "n1");
+        assert!(verify_publish_request(&req, 1_000).is_err());
+
+        // Unsigned nonce
+        let req = publish_request(&account, &device, &certificate, "");
+        assert!(verify_publish_request(&req, 1_000).is_err());
+    }
+
+    #[test]
+    fn test_publish_signature_covers_request() {
+        let account = Keypair::generate_ed25519();
+        let device = Keypair::generate_ed25519();
+        let certificate = certificate(&account, &device);
+
+        // An intercepted publication can't have its bundle swapped
+        let mut swapped = publish_request(&device, &device, &certificate, "n1");
+        swapped.prekey_bundle.signed_prekey = general_purpose::STANDARD.encode([9u8; 32]);
+        assert!(verify_publish_request(&swapped, 1_000).is_err());
+
+        let mut renamed = publish_request(&device, &device, &certificate, "n1");
+        renamed.certificate.device_name = "Phone".to_string();
+        assert!(verify_publish_request(&renamed, 1_000).is_err());
+
+        // or its nonce
+        let mut renonced = publish_request(&device, &device, &certificate, "n1");
+        renonced.nonce = "n2".to_string();
+        assert!(verify_publish_request(&renonced, 1_000).is_err());
+    }
+}
@@ -31,6 +31,15 @@ pub enum AppError {
     #[error("Invalid signature")]
     InvalidSignature,
 
+    #[error("Invalid device certificate: {0}")]
+    InvalidCertificate(String),
+
+    #[error("Device linked to another account: {0}")]
+    DeviceLinked(String),
+
+    #[error("Invalid prekeys: {0}")]
+    InvalidPrekeys(String),
+
     #[error("Rate limit exceeded")]
     RateLimitExceeded,
 
@@ -51,6 +60,9 @@ impl AppError {
             Self::UsernameTaken(_) => StatusCode::CONFLICT,
             Self::UsernameNotFound(_) => StatusCode::NOT_FOUND,
             Self::InvalidSignature => StatusCode::BAD_REQUEST,
+            Self::InvalidCertificate(_) => StatusCode::BAD_REQUEST,
+            Self::DeviceLinked(_) => StatusCode::CONFLICT,
+            Self::InvalidPrekeys(_) => StatusCode::BAD_REQUEST,
             Self::RateLimitExceeded => StatusCode::TOO_MANY_REQUESTS,
             Self::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::Redis(_) => StatusCode::INTERNAL_SERVER_ERROR,
@@ -64,6 +76,9 @@ impl AppError {
             Self::UsernameTaken(_) => "USERNAME_TAKEN",
             Self::UsernameNotFound(_) => "USERNAME_NOT_FOUND",
             Self::InvalidSignature => "INVALID_SIGNATURE",
+            Self::InvalidCertificate(_) => "INVALID_CERTIFICATE",
+            Self::DeviceLinked(_) => "DEVICE_LINKED",
+            Self::InvalidPrekeys(_) => "INVALID_PREKEYS",
             Self::RateLimitExceeded => "RATE_LIMIT_EXCEEDED",
             Self::Database(_) => "INTERNAL_ERROR",
             Self::Redis(_) => "INTERNAL_ERROR",
@@ -9,10 +9,10 @@ use serde::Deserialize;
 use std::sync::Arc;
 
 use crate::{
-    db,
+    db, devices,
     error::{AppError, Result},
     models::*,
-    AppState,
+    prekeys, AppState,
 };
 
 /// Register a new username
@@ -48,6 +48,8 @@ pub struct LookupQuery {
 }
 
 /// Lookup a username
+///
+/// Each lookup gets its own one-time prekey, if any are left.
 pub async fn lookup_handler(
     State(state): State<Arc<AppState>>,
     Query(query): Query<LookupQuery>,
@@ -56,17 +58,78 @@ pub async fn lookup_handler(
     Ok(Json(response))
 }
 
-/// Update prekeys for a username
+/// Update prekeys for a username and upload a batch of one-time prekeys
+///
+/// The request must be signed by the key the peer id embeds, and each
+/// signature is only accepted once.
 pub async fn update_prekeys_handler(
     State(state): State<Arc<AppState>>,
     Json(req): Json<UpdatePrekeysRequest>,
 ) -> Result<Json<UpdatePrekeysResponse>> {
-    // For now, we skip signature verification (would need to lookup public key first)
-    // In production, you'd:
-    // 1. Lookup peer_id to get public_key
-    // 2. Verify signature with that public_key
+    prekeys::verify_update_request(&req, chrono::Utc::now().timestamp())?;
+    if !state.claim_signature(&req.signature).await? {
+        tracing::warn!("Replayed prekey update for {}", req.peer_id);
+        return Err(AppError::InvalidSignature);
+    }
+    let one_time_prekeys =
+        prekeys::uploaded_one_time_prekeys(&req.prekey_bundle, &req.one_time_prekeys)?;
+
+    let response =
+        db::update_prekeys(&state.db, &req.peer_id, &req.prekey_bundle, &one_time_prekeys).await?;
+    Ok(Json(response))
+}
+
+/// Prekey count query parameters
+#[derive(Debug, Deserialize)]
+pub struct PrekeyCountQuery {
+    pub peer_id: String,
+}
+
+/// One-time prekeys a peer has left, so it knows when to upload more
+pub async fn prekey_count_handler(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<PrekeyCountQuery>,
+) -> Result<Json<PrekeyCountResponse>> {
+    let one_time_prekey_count = db::count_one_time_prekeys(&state.db, &query.peer_id).await?;
+    Ok(Json(PrekeyCountResponse {
+        peer_id: query.peer_id,
+        one_time_prekey_count,
+    }))
+}
 
-    let response = db::update_prekeys(&state.db, &req.peer_id, &req.prekey_bundle).await?;
+/// Publish (or refresh) a device linked to an account
+///
+/// The certificate must be signed by the account, the prekey bundle must be
+/// the device's own, and the request signed by the account or the device.
+/// Each signature is only accepted once.
+pub async fn publish_device_handler(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<PublishDeviceRequest>,
+) -> Result<Json<PublishDeviceResponse>> {
+    devices::verify_publish_request(&req, chrono::Utc::now().timestamp())?;
+    if !state.claim_signature(&req.signature).await? {
+        tracing::warn!("Replayed device publication for {}", req.certificate.device_peer_id);
+        return Err(AppError::InvalidSignature);
+    }
+    devices::verify_certificate(&req.certificate)?;
+    devices::verify_bundle(&req.certificate.device_peer_id, &req.prekey_bundle)?;
+
+    let response = db::publish_device(&state.db, &req.certificate, &req.prekey_bundle).await?;
+    Ok(Json(response))
+}
+
+/// Device list query parameters
+#[derive(Debug, Deserialize)]
+pub struct DevicesQuery {
+    pub peer_id: String,
+}
+
+/// Devices linked to an account
+pub async fn list_devices_handler(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<DevicesQuery>,
+) -> Result<Json<DevicesResponse>> {
+    let response = db::list_devices(&state.db, &query.peer_id).await?;
     Ok(Json(response))
 }
 
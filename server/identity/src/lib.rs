@@ -1,9 +1,11 @@
 //! Identity Server library
 
 pub mod db;
+pub mod devices;
 pub mod error;
 pub mod handlers;
 pub mod models;
+pub mod prekeys;
 pub mod rate_limit;
 
 use redis::aio::ConnectionManager;
@@ -25,4 +27,25 @@ impl AppState {
             start_time: std::time::Instant::now(),
         }
     }
+
+    /// Record a request signature; false if it was already used
+    ///
+    /// The key expires with the signature's validity window, after which the
+    /// timestamp check rejects it anyway.
+    pub async fn claim_signature(&self, signature: &str) -> Result<bool, redis::RedisError> {
+        let mut conn = self.redis.clone();
+
+        let key = format!("signature:{}", signature);
+        let claimed: bool = redis::cmd("SET")
+            .arg(&key)
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg(2 * mepassa_peer_auth::MAX_CLOCK_SKEW_SECS as u64)
+            .query_async::<Option<String>>(&mut conn)
+            .await?
+            .is_some();
+
+        Ok(claimed)
+    }
 }
@@ -55,6 +55,11 @@ async fn main() -> anyhow::Result<()> {
         .route("/api/v1/register", post(handlers::register_handler))
         .route("/api/v1/lookup", get(handlers::lookup_handler))
         .route("/api/v1/prekeys", put(handlers::update_prekeys_handler))
+        .route("/api/v1/prekeys/count", get(handlers::prekey_count_handler))
+        .route(
+            "/api/v1/devices",
+            get(handlers::list_devices_handler).put(handlers::publish_device_handler),
+        )
         // Rate limiting middleware for API routes
         .layer(middleware::from_fn_with_state(
             state.clone(),
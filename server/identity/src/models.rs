@@ -56,6 +56,12 @@ pub struct LookupResponse {
 pub struct UpdatePrekeysRequest {
     pub peer_id: String,
     pub prekey_bundle: PreKeyBundle,
+    /// One-time prekeys to add to the ones handed out by lookups
+    #[serde(default)]
+    pub one_time_prekeys: Vec<OneTimePreKey>,
+    /// Random value that makes each signed request unique
+    #[serde(default)]
+    pub nonce: String,
     pub signature: String,
     #[serde(default)]
     pub timestamp: i64,
@@ -65,6 +71,65 @@ pub struct UpdatePrekeysRequest {
 #[derive(Debug, Serialize)]
 pub struct UpdatePrekeysResponse {
     pub updated_at: DateTime<Utc>,
+    /// One-time prekeys left after the upload
+    pub one_time_prekey_count: i64,
+}
+
+/// Remaining one-time prekeys of a peer
+#[derive(Debug, Serialize)]
+pub struct PrekeyCountResponse {
+    pub peer_id: String,
+    pub one_time_prekey_count: i64,
+}
+
+/// Certificate binding a device to an account, signed by the account
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceCertificate {
+    pub account_peer_id: String,
+    pub device_peer_id: String,
+    pub device_name: String,
+    /// Unix timestamp (seconds) of the device's link request
+    pub requested_at: i64,
+    /// Base64 Ed25519 signature by the device over its link request
+    pub device_signature: String,
+    /// Unix timestamp (seconds)
+    pub issued_at: i64,
+    /// Base64 Ed25519 signature by the account
+    pub signature: String,
+}
+
+/// Publish device request (signed by the account or the device)
+#[derive(Debug, Deserialize)]
+pub struct PublishDeviceRequest {
+    pub signer_peer_id: String,
+    pub certificate: DeviceCertificate,
+    pub prekey_bundle: PreKeyBundle,
+    /// Random value that makes each signed request unique
+    #[serde(default)]
+    pub nonce: String,
+    pub signature: String,
+    pub timestamp: i64,
+}
+
+/// Publish device response
+#[derive(Debug, Serialize)]
+pub struct PublishDeviceResponse {
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A device of an account
+#[derive(Debug, Serialize)]
+pub struct LinkedDevice {
+    pub certificate: DeviceCertificate,
+    pub prekey_bundle: PreKeyBundle,
+    pub last_updated: DateTime<Utc>,
+}
+
+/// Device list response
+#[derive(Debug, Serialize)]
+pub struct DevicesResponse {
+    pub account_peer_id: String,
+    pub devices: Vec<LinkedDevice>,
 }
 
 /// Health check response
@@ -105,3 +170,35 @@ impl UsernameRow {
         })
     }
 }
+
+/// Device database row
+#[derive(Debug, FromRow)]
+pub struct DeviceRow {
+    pub device_peer_id: String,
+    pub account_peer_id: String,
+    pub device_name: String,
+    pub requested_at: i64,
+    pub device_signature: String,
+    pub issued_at: i64,
+    pub certificate_signature: String,
+    pub prekey_bundle: sqlx::types::JsonValue,
+    pub last_updated: chrono::NaiveDateTime,
+}
+
+impl DeviceRow {
+    pub fn to_linked_device(self) -> Result<LinkedDevice, serde_json::Error> {
+        Ok(LinkedDevice {
+            certificate: DeviceCertificate {
+                account_peer_id: self.account_peer_id,
+                device_peer_id: self.device_peer_id,
+                device_name: self.device_name,
+                requested_at: self.requested_at,
+                device_signature: self.device_signature,
+                issued_at: self.issued_at,
+                signature: self.certificate_signature,
+            },
+            prekey_bundle: serde_json::from_value(self.prekey_bundle)?,
+            last_updated: self.last_updated.and_utc(),
+        })
+    }
+}
@@ -0,0 +1,226 @@
+//! One-time prekey uploads
+//!
+//! X3DH needs each one-time prekey to reach a single initiator, so they are
+//! kept in their own table and every lookup pops one. Peers upload them in
+//! batches and top them up when the count runs low; uploads are signed by the
+//! key the peer id embeds, like device publications. The signature covers a
+//! digest of the uploaded keys and a random nonce, and is only accepted once
+//! (see `AppState::claim_signature`), so an intercepted upload can't be
+//! replayed or have its keys swapped.
+
+use base64::{engine::general_purpose, Engine as _};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::{AppError, Result},
+    models::{OneTimePreKey, PreKeyBundle, UpdatePrekeysRequest},
+};
+
+/// Most one-time prekeys accepted in one upload
+pub const MAX_ONE_TIME_PREKEYS_PER_UPLOAD: usize = 100;
+
+/// Digest of the keys an update uploads
+///
+/// Format: hex SHA-256 of one line per field: the bundle's identity key,
+/// signed prekey id, signed prekey and its signature, the bundle's one-time
+/// prekey ("{id}:{public_key}", or "-" without one), then each one-time
+/// prekey of the batch ("{id}:{public_key}")
+pub fn prekeys_digest(bundle: &PreKeyBundle, one_time_prekeys: &[OneTimePreKey]) -> String {
+    let prekey_line = |prekey: &OneTimePreKey| format!("{}:{}\n", prekey.id, prekey.public_key);
+
+    let mut hasher = Sha256::new();
+    hasher.update(format!(
+        "{}\n{}\n{}\n{}\n",
+        bundle.identity_key,
+        bundle.signed_prekey_id,
+        bundle.signed_prekey,
+        bundle.signed_prekey_signature
+    ));
+    match &bundle.one_time_prekey {
+        Some(prekey) => hasher.update(prekey_line(prekey)),
+        None => hasher.update("-\n"),
+    }
+    for prekey in one_time_prekeys {
+        hasher.update(prekey_line(prekey));
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Signed by the peer when updating its prekeys
+///
+/// Format: "update_prekeys:{peer_id}:{timestamp}:{nonce}:{prekeys_digest}"
+pub fn update_challenge(
+    peer_id: &str,
+    timestamp: i64,
+    nonce: &str,
+    prekeys_digest: &str,
+) -> String {
+    format!(
+        "update_prekeys:{}:{}:{}:{}",
+        peer_id, timestamp, nonce, prekeys_digest
+    )
+}
+
+/// Check an update request: signed by the peer itself over the keys it
+/// uploads, within `mepassa_peer_auth::MAX_CLOCK_SKEW_SECS` of `now`
+pub fn verify_update_request(req: &UpdatePrekeysRequest, now: i64) -> Result<()> {
+    if req.nonce.is_empty() {
+        return Err(AppError::InvalidSignature);
+    }
+
+    let digest = prekeys_digest(&req.prekey_bundle, &req.one_time_prekeys);
+    mepassa_peer_auth::verify(
+        &req.peer_id,
+        &update_challenge(&req.peer_id, req.timestamp, &req.nonce, &digest),
+        &req.signature,
+        req.timestamp,
+        now,
+    )
+    .map_err(|_| AppError::InvalidSignature)
+}
+
+/// All one-time prekeys of an upload: the batch plus the one in the bundle
+///
+/// # Errors
+///
+/// Returns `InvalidPrekeys` if there are too many, an ID repeats, or a key
+/// isn't a base64 X25519 public key
+pub fn uploaded_one_time_prekeys(
+    bundle: &PreKeyBundle,
+    batch: &[OneTimePreKey],
+) -> Result<Vec<OneTimePreKey>> {
+    if batch.len() > MAX_ONE_TIME_PREKEYS_PER_UPLOAD {
+        return Err(AppError::InvalidPrekeys(format!(
+            "at most {} one-time prekeys per upload",
+            MAX_ONE_TIME_PREKEYS_PER_UPLOAD
+        )));
+    }
+
+    let mut prekeys: Vec<OneTimePreKey> =
+        bundle.one_time_prekey.iter().chain(batch).cloned().collect();
+    prekeys.sort_by_key(|prekey| prekey.id);
+    // The bundle's prekey is usually the first of the batch too
+    prekeys.dedup_by(|a, b| a.id == b.id && a.public_key == b.public_key);
+    if prekeys.windows(2).any(|pair| pair[0].id == pair[1].id) {
+        return Err(AppError::InvalidPrekeys("duplicate one-time prekey id".to_string()));
+    }
+    for prekey in &prekeys {
+        let valid = general_purpose::STANDARD
+            .decode(&prekey.public_key)
+            .is_ok_and(|key| key.len() == 32);
+        if !valid {
+            return Err(AppError::InvalidPrekeys(format!(
+                "invalid one-time prekey {}",
+                prekey.id
+            )));
+        }
+    }
+
+    Ok(prekeys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p_identity::Keypair;
+
+    fn prekey(id: i32) -> OneTimePreKey {
+        OneTimePreKey {
+            id,
+            public_key: general_purpose::STANDARD.encode([id as u8; 32]),
+        }
+    }
+
+    fn bundle(one_time_prekey: Option<OneTimePreKey>) -> PreKeyBundle {
+        PreKeyBundle {
+            identity_key: general_purpose::STANDARD.encode([1u8; 32]),
+            signed_prekey_id: 1,
+            signed_prekey: general_purpose::STANDARD.encode([2u8; 32]),
+            signed_prekey_signature: general_purpose::STANDARD.encode([3u8; 64]),
+            one_time_prekey,
+        }
+    }
+
+    fn signed_request(keypair: &Keypair, nonce: &str) -> UpdatePrekeysRequest {
+        let mut req = UpdatePrekeysRequest {
+            peer_id: keypair.public().to_peer_id().to_string(),
+            prekey_bundle: bundle(Some(prekey(1))),
+            one_time_prekeys: vec![prekey(1), prekey(2)],
+            nonce: nonce.to_string(),
+            signature: String::new(),
+            timestamp: 1_000,
+        };
+        let digest = prekeys_digest(&req.prekey_bundle, &req.one_time_prekeys);
+        let challenge = update_challenge(&req.peer_id, req.timestamp, &req.nonce, &digest);
+        req.signature =
+            general_purpose::STANDARD.encode(keypair.sign(challenge.as_bytes()).unwrap());
+        req
+    }
+
+    #[test]
+    fn test_verify_update_request() {
+        let keypair = Keypair::generate_ed25519();
+        let req = signed_request(&keypair, "n1");
+
+        assert!(verify_update_request(&req, 1_100).is_ok());
+        // Stale
+        assert!(verify_update_request(&req, 2_000).is_err());
+
+        // Someone else can't update the peer's prekeys
+        let mallory = Keypair::generate_ed25519();
+        let forged = UpdatePrekeysRequest {
+            peer_id: req.peer_id.clone(),
+            ..signed_request(&mallory, "n1")
+        };
+        assert!(verify_update_request(&forged, 1_000).is_err());
+
+        // Unsigned nonce
+        let unsigned = UpdatePrekeysRequest {
+            nonce: String::new(),
+            ..signed_request(&keypair, "")
+        };
+        assert!(verify_update_request(&unsigned, 1_000).is_err());
+    }
+
+    #[test]
+    fn test_signature_covers_uploaded_keys() {
+        let keypair = Keypair::generate_ed25519();
+
+        // Mallory intercepts an upload and swaps in her own keys
+        let mut swapped = signed_request(&keypair, "n1");
+        swapped.one_time_prekeys[1].public_key = general_purpose::STANDARD.encode([9u8; 32]);
+        assert!(verify_update_request(&swapped, 1_000).is_err());
+
+        let mut swapped = signed_request(&keypair, "n1");
+        swapped.prekey_bundle.signed_prekey = general_purpose::STANDARD.encode([9u8; 32]);
+        assert!(verify_update_request(&swapped, 1_000).is_err());
+
+        let mut dropped = signed_request(&keypair, "n1");
+        dropped.prekey_bundle.one_time_prekey = None;
+        assert!(verify_update_request(&dropped, 1_000).is_err());
+
+        // or with a nonce of her choosing
+        let mut renonced = signed_request(&keypair, "n1");
+        renonced.nonce = "n2".to_string();
+        assert!(verify_update_request(&renonced, 1_000).is_err());
+    }
+
+    #[test]
+    fn test_uploaded_one_time_prekeys() {
+        let uploaded =
+            uploaded_one_time_prekeys(&bundle(Some(prekey(2))), &[prekey(2), prekey(3)]).unwrap();
+        assert_eq!(uploaded.iter().map(|p| p.id).collect::<Vec<_>>(), vec![2, 3]);
+
+        // Same ID, different key
+        let mut other = prekey(3);
+        other.public_key = general_purpose::STANDARD.encode([9u8; 32]);
+        assert!(uploaded_one_time_prekeys(&bundle(None), &[prekey(3), other]).is_err());
+
+        let mut short = prekey(4);
+        short.public_key = general_purpose::STANDARD.encode([4u8; 16]);
+        assert!(uploaded_one_time_prekeys(&bundle(None), &[short]).is_err());
+
+        let too_many: Vec<_> = (1..=MAX_ONE_TIME_PREKEYS_PER_UPLOAD as i32 + 1).map(prekey).collect();
+        assert!(uploaded_one_time_prekeys(&bundle(None), &too_many).is_err());
+    }
+}
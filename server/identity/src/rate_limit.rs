@@ -42,6 +42,22 @@ impl RateLimitConfig {
             window_seconds: 3600,
         }
     }
+
+    /// Prekey count: 100 requests per hour
+    pub fn prekey_count() -> Self {
+        Self {
+            max_requests: 100,
+            window_seconds: 3600,
+        }
+    }
+
+    /// Devices (publish and list): 100 requests per hour
+    pub fn devices() -> Self {
+        Self {
+            max_requests: 100,
+            window_seconds: 3600,
+        }
+    }
 }
 
 /// Extract client identifier (IP address for now)
@@ -67,7 +83,9 @@ pub async fn rate_limit_middleware(
     let config = match path {
         p if p.starts_with("/api/v1/register") => RateLimitConfig::register(),
         p if p.starts_with("/api/v1/lookup") => RateLimitConfig::lookup(),
+        p if p.starts_with("/api/v1/prekeys/count") => RateLimitConfig::prekey_count(),
         p if p.starts_with("/api/v1/prekeys") => RateLimitConfig::update_prekeys(),
+        p if p.starts_with("/api/v1/devices") => RateLimitConfig::devices(),
         _ => {
             // No rate limit for other endpoints (like /health)
             return Ok(next.run(req).await);
@@ -29,6 +29,9 @@ CREATE TABLE IF NOT EXISTS offline_messages (
     message_type TEXT NOT NULL DEFAULT 'text',
     message_id TEXT NOT NULL UNIQUE,
 
+    -- Source address of the store request (per-IP mailbox share)
+    sender_ip TEXT,
+
     -- Timestamps
     created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
     expires_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT (NOW() + INTERVAL '14 days'),
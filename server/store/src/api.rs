@@ -1,23 +1,137 @@
 //! REST API handlers
 
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse, Responder};
 use serde_json::json;
 
-use crate::database::Database;
+use crate::auth::{self, AuthError};
+use crate::database::{Database, StoreError};
 use crate::models::{
     DeleteMessagesRequest, DeleteMessagesResponse, HealthResponse, OfflineMessageDto,
     RetrieveMessagesRequest, RetrieveMessagesResponse, StoreMessageRequest, StoreMessageResponse,
 };
+use crate::quota::{QuotaConfig, QuotaError, QuotaStats, RateLimitConfig};
 use crate::redis_client::RedisClient;
 
+/// Explicit refusal of an over-quota request
+///
+/// Mailbox limits answer 507, sender limits 429 (with `Retry-After` for the
+/// rate limit) and oversized payloads 413.
+fn quota_response(stats: &QuotaStats, error: QuotaError) -> HttpResponse {
+    stats.record(&error);
+    tracing::warn!("Refused message: {}", error);
+
+    let status = match &error {
+        QuotaError::PayloadTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+        QuotaError::MailboxFull { .. } | QuotaError::MailboxBytesExceeded { .. } => {
+            StatusCode::INSUFFICIENT_STORAGE
+        }
+        QuotaError::SenderShareExceeded { .. }
+        | QuotaError::SourceShareExceeded { .. }
+        | QuotaError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+    };
+
+    let mut response = HttpResponse::build(status);
+    if let QuotaError::RateLimited { window_seconds, .. } = &error {
+        response.insert_header(("Retry-After", window_seconds.to_string()));
+    }
+    response.json(json!({
+        "error": error.to_string(),
+        "code": error.code()
+    }))
+}
+
+/// Count a store request against one of its rate limits
+async fn check_rate_limit(
+    redis: &RedisClient,
+    stats: &QuotaStats,
+    rate: RateLimitConfig,
+    key: &str,
+) -> Result<(), HttpResponse> {
+    match redis.increment_rate_limit(key, rate.window_seconds).await {
+        Ok(count) if count > rate.max_requests => Err(quota_response(
+            stats,
+            QuotaError::RateLimited {
+                max: rate.max_requests,
+                window_seconds: rate.window_seconds,
+            },
+        )),
+        Ok(_) => Ok(()),
+        Err(e) => {
+            tracing::error!("Failed to check rate limit: {:?}", e);
+            Err(HttpResponse::ServiceUnavailable().json(json!({
+                "error": "Failed to check rate limit"
+            })))
+        }
+    }
+}
+
+/// Verify a signed request and reject replays of its signature
+async fn authenticate(
+    redis: &RedisClient,
+    peer_id: &str,
+    challenge: &str,
+    signature: &str,
+    timestamp: i64,
+) -> Result<(), HttpResponse> {
+    let unauthorized = |e: AuthError| {
+        HttpResponse::Unauthorized().json(json!({
+            "error": e.to_string()
+        }))
+    };
+
+    auth::verify(
+        peer_id,
+        challenge,
+        signature,
+        timestamp,
+        chrono::Utc::now().timestamp(),
+    )
+    .map_err(unauthorized)?;
+
+    match redis
+        .claim_signature(signature, 2 * auth::MAX_CLOCK_SKEW_SECS as u64)
+        .await
+    {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(unauthorized(AuthError::Replayed)),
+        Err(e) => {
+            tracing::error!("Failed to record request signature: {:?}", e);
+            Err(HttpResponse::ServiceUnavailable().json(json!({
+                "error": "Failed to authenticate request"
+            })))
+        }
+    }
+}
+
 /// Store a new offline message
 ///
 /// POST /api/store
+///
+/// Signed by the sender, so nobody can store messages in another peer's name.
+/// Refused explicitly when over a payload, mailbox, sender or source IP limit
+/// (source IP limits apply overall and per recipient).
 pub async fn store_message(
     db: web::Data<Database>,
     redis: web::Data<RedisClient>,
+    quota: web::Data<QuotaConfig>,
+    quota_stats: web::Data<QuotaStats>,
+    http_req: HttpRequest,
     req: web::Json<StoreMessageRequest>,
 ) -> impl Responder {
+    let payload_size = req.payload_size();
+    if let Err(e) = quota.check_payload(payload_size) {
+        return quota_response(&quota_stats, e);
+    }
+
+    // Before verifying signatures: fresh peer ids don't get around this one
+    let source_ip = http_req.peer_addr().map(|addr| addr.ip().to_string());
+    if let Some(ip) = &source_ip {
+        let key = format!("store-ip:{}", ip);
+        if let Err(response) = check_rate_limit(&redis, &quota_stats, quota.ip_rate, &key).await {
+            return response;
+        }
+    }
+
     // Validate request
     if let Err(e) = req.validate() {
         return HttpResponse::BadRequest().json(json!({
@@ -25,8 +139,43 @@ pub async fn store_message(
         }));
     }
 
-    // Store in database
-    match db.store_message(&req).await {
+    // Nor do they get more of one recipient's share
+    if let Some(ip) = &source_ip {
+        let key = format!("store-ip:{}:{}", ip, req.recipient_peer_id);
+        if let Err(response) =
+            check_rate_limit(&redis, &quota_stats, quota.ip_recipient_rate, &key).await
+        {
+            return response;
+        }
+    }
+
+    let challenge = auth::store_challenge(
+        &req.sender_peer_id,
+        &req.recipient_peer_id,
+        &req.message_id,
+        &req.encrypted_payload,
+        req.timestamp,
+        &req.nonce,
+    );
+    if let Err(response) = authenticate(
+        &redis,
+        &req.sender_peer_id,
+        &challenge,
+        &req.signature,
+        req.timestamp,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let key = format!("store:{}", req.sender_peer_id);
+    if let Err(response) = check_rate_limit(&redis, &quota_stats, quota.sender_rate, &key).await {
+        return response;
+    }
+
+    // Store in database, within the mailbox quotas
+    match db.store_message(&req, source_ip.as_deref(), &quota).await {
         Ok((id, message_id)) => {
             // Get message details for response
             let expires_at = chrono::Utc::now() + chrono::Duration::days(14);
@@ -46,7 +195,8 @@ pub async fn store_message(
                 expires_at,
             })
         }
-        Err(e) => {
+        Err(StoreError::Quota(e)) => quota_response(&quota_stats, e),
+        Err(StoreError::Database(e)) => {
             tracing::error!("Failed to store message: {:?}", e);
             HttpResponse::InternalServerError().json(json!({
                 "error": "Failed to store message"
@@ -57,9 +207,12 @@ pub async fn store_message(
 
 /// Retrieve pending messages for a recipient
 ///
-/// GET /api/store?peer_id={peer_id}&limit={limit}
+/// GET /api/store?peer_id={peer_id}&limit={limit}&timestamp={timestamp}&nonce={nonce}&signature={signature}
+///
+/// Only the recipient itself can retrieve its messages.
 pub async fn retrieve_messages(
     db: web::Data<Database>,
+    redis: web::Data<RedisClient>,
     query: web::Query<RetrieveMessagesRequest>,
 ) -> impl Responder {
     if query.peer_id.is_empty() {
@@ -67,6 +220,24 @@ pub async fn retrieve_messages(
             "error": "peer_id is required"
         }));
     }
+    if query.nonce.is_empty() {
+        return HttpResponse::BadRequest().json(json!({
+            "error": "nonce is required"
+        }));
+    }
+
+    let challenge = auth::retrieve_challenge(&query.peer_id, query.timestamp, &query.nonce);
+    if let Err(response) = authenticate(
+        &redis,
+        &query.peer_id,
+        &challenge,
+        &query.signature,
+        query.timestamp,
+    )
+    .await
+    {
+        return response;
+    }
 
     match db.retrieve_messages(&query.peer_id, query.limit).await {
         Ok(messages) => {
@@ -91,17 +262,43 @@ pub async fn retrieve_messages(
 /// Delete (acknowledge) messages
 ///
 /// DELETE /api/store
+///
+/// Signed by the recipient; only messages addressed to it are deleted.
 pub async fn delete_messages(
     db: web::Data<Database>,
+    redis: web::Data<RedisClient>,
     req: web::Json<DeleteMessagesRequest>,
 ) -> impl Responder {
+    if req.peer_id.is_empty() {
+        return HttpResponse::BadRequest().json(json!({
+            "error": "peer_id is required"
+        }));
+    }
     if req.message_ids.is_empty() {
         return HttpResponse::BadRequest().json(json!({
             "error": "message_ids is required"
         }));
     }
+    if req.nonce.is_empty() {
+        return HttpResponse::BadRequest().json(json!({
+            "error": "nonce is required"
+        }));
+    }
+
+    let challenge = auth::delete_challenge(&req.peer_id, &req.message_ids, req.timestamp, &req.nonce);
+    if let Err(response) = authenticate(
+        &redis,
+        &req.peer_id,
+        &challenge,
+        &req.signature,
+        req.timestamp,
+    )
+    .await
+    {
+        return response;
+    }
 
-    match db.delete_messages(&req.message_ids).await {
+    match db.delete_messages(&req.peer_id, &req.message_ids).await {
         Ok(deleted_count) => HttpResponse::Ok().json(DeleteMessagesResponse { deleted_count }),
         Err(e) => {
             tracing::error!("Failed to delete messages: {:?}", e);
@@ -157,10 +354,25 @@ pub async fn health_check(
 /// Get statistics
 ///
 /// GET /api/stats
-pub async fn get_stats(db: web::Data<Database>) -> impl Responder {
+pub async fn get_stats(
+    db: web::Data<Database>,
+    quota: web::Data<QuotaConfig>,
+    quota_stats: web::Data<QuotaStats>,
+) -> impl Responder {
     match db.count_pending_messages().await {
         Ok(pending) => HttpResponse::Ok().json(json!({
             "pending_messages": pending,
+            "quota_rejections": quota_stats.snapshot(),
+            "quota_limits": {
+                "max_payload_bytes": quota.max_payload_bytes,
+                "max_pending_per_recipient": quota.max_pending_per_recipient,
+                "max_pending_bytes_per_recipient": quota.max_pending_bytes_per_recipient,
+                "max_pending_per_sender": quota.max_pending_per_sender,
+                "sender_rate_limit": quota.sender_rate.max_requests,
+                "sender_rate_window_seconds": quota.sender_rate.window_seconds,
+                "ip_rate_limit": quota.ip_rate.max_requests,
+                "ip_rate_window_seconds": quota.ip_rate.window_seconds
+            },
             "timestamp": chrono::Utc::now()
         })),
         Err(e) => {
@@ -0,0 +1,142 @@
+//! Request authentication
+//!
+//! Every request is signed with the Ed25519 key behind the caller's peer id:
+//! senders sign the messages they store, recipients sign their retrieve and
+//! delete requests. Ed25519 peer ids embed the public key, so no key
+//! registration is needed. A signature covers a timestamp and a random
+//! nonce, is only accepted within `MAX_CLOCK_SKEW_SECS` of the timestamp,
+//! and only once (see `RedisClient::claim_signature`). Ed25519 signatures
+//! are deterministic, so the nonce is what lets a client repeat the same
+//! request within one second.
+
+pub use mepassa_peer_auth::{verify, AuthError, MAX_CLOCK_SKEW_SECS};
+
+/// Signed by the sender when storing a message
+///
+/// Format: "store:{sender}:{recipient}:{message_id}:{timestamp}:{nonce}:{encrypted_payload}"
+pub fn store_challenge(
+    sender_peer_id: &str,
+    recipient_peer_id: &str,
+    message_id: &str,
+    encrypted_payload: &str,
+    timestamp: i64,
+    nonce: &str,
+) -> String {
+    format!(
+        "store:{}:{}:{}:{}:{}:{}",
+        sender_peer_id, recipient_peer_id, message_id, timestamp, nonce, encrypted_payload
+    )
+}
+
+/// Signed by the recipient when retrieving its messages
+///
+/// Format: "retrieve:{peer_id}:{timestamp}:{nonce}"
+pub fn retrieve_challenge(peer_id: &str, timestamp: i64, nonce: &str) -> String {
+    format!("retrieve:{}:{}:{}", peer_id, timestamp, nonce)
+}
+
+/// Signed by the recipient when acknowledging messages
+///
+/// Format: "delete:{peer_id}:{timestamp}:{nonce}:{message_id},{message_id},..."
+pub fn delete_challenge(peer_id: &str, message_ids: &[String], timestamp: i64, nonce: &str) -> String {
+    format!("delete:{}:{}:{}:{}", peer_id, timestamp, nonce, message_ids.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{engine::general_purpose, Engine as _};
+    use libp2p_identity::Keypair;
+
+    fn sign(keypair: &Keypair, challenge: &str) -> String {
+        general_purpose::STANDARD.encode(keypair.sign(challenge.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn test_verify_signed_challenge() {
+        let keypair = Keypair::generate_ed25519();
+        let peer_id = keypair.public().to_peer_id().to_string();
+        let challenge = retrieve_challenge(&peer_id, 1_000, "n1");
+
+        let signature = sign(&keypair, &challenge);
+        assert_eq!(verify(&peer_id, &challenge, &signature, 1_000, 1_100), Ok(()));
+    }
+
+    #[test]
+    fn test_rejects_other_peer() {
+        let keypair = Keypair::generate_ed25519();
+        let victim = Keypair::generate_ed25519().public().to_peer_id().to_string();
+
+        // Signed by someone else, or for another peer's challenge
+        let challenge = retrieve_challenge(&victim, 1_000, "n1");
+        let signature = sign(&keypair, &challenge);
+        assert_eq!(
+            verify(&victim, &challenge, &signature, 1_000, 1_000),
+            Err(AuthError::InvalidSignature)
+        );
+
+        let own = keypair.public().to_peer_id().to_string();
+        let signature = sign(&keypair, &retrieve_challenge(&own, 1_000, "n1"));
+        assert_eq!(
+            verify(&victim, &challenge, &signature, 1_000, 1_000),
+            Err(AuthError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn test_signature_bound_to_request() {
+        let keypair = Keypair::generate_ed25519();
+        let sender = keypair.public().to_peer_id().to_string();
+        let challenge = store_challenge(&sender, "recipient", "msg-1", "cGF5bG9hZA==", 1_000, "n1");
+        let signature = sign(&keypair, &challenge);
+
+        let tampered = store_challenge(&sender, "recipient", "msg-1", "b3RoZXI=", 1_000, "n1");
+        assert_eq!(
+            verify(&sender, &tampered, &signature, 1_000, 1_000),
+            Err(AuthError::InvalidSignature)
+        );
+
+        let ids = vec!["a".to_string(), "b".to_string()];
+        let signature = sign(&keypair, &delete_challenge(&sender, &ids, 1_000, "n1"));
+        let more = delete_challenge(&sender, &["a".to_string(), "b".to_string(), "c".to_string()], 1_000, "n1");
+        assert!(verify(&sender, &more, &signature, 1_000, 1_000).is_err());
+    }
+
+    #[test]
+    fn test_nonce_makes_requests_distinct() {
+        let keypair = Keypair::generate_ed25519();
+        let peer_id = keypair.public().to_peer_id().to_string();
+
+        // Two fetches in the same second differ only by their nonce
+        let first = sign(&keypair, &retrieve_challenge(&peer_id, 1_000, "n1"));
+        let second = sign(&keypair, &retrieve_challenge(&peer_id, 1_000, "n2"));
+        assert_ne!(first, second);
+        assert_eq!(
+            verify(&peer_id, &retrieve_challenge(&peer_id, 1_000, "n2"), &first, 1_000, 1_000),
+            Err(AuthError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn test_rejects_stale_timestamp() {
+        let keypair = Keypair::generate_ed25519();
+        let peer_id = keypair.public().to_peer_id().to_string();
+        let challenge = retrieve_challenge(&peer_id, 1_000, "n1");
+        let signature = sign(&keypair, &challenge);
+
+        let late = 1_000 + MAX_CLOCK_SKEW_SECS + 1;
+        assert_eq!(
+            verify(&peer_id, &challenge, &signature, 1_000, late),
+            Err(AuthError::Expired)
+        );
+    }
+
+    #[test]
+    fn test_rejects_invalid_peer_id() {
+        assert_eq!(mepassa_peer_auth::public_key_of("not-a-peer-id").err(), Some(AuthError::InvalidPeerId));
+        assert_eq!(
+            verify("not-a-peer-id", "retrieve:not-a-peer-id:1000:n1", "", 1_000, 1_000),
+            Err(AuthError::InvalidPeerId)
+        );
+    }
+}
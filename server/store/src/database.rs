@@ -4,9 +4,21 @@ use chrono::Utc;
 use sqlx::postgres::PgPoolOptions;
 use sqlx::{PgPool, Row};
 use std::time::Duration;
+use thiserror::Error;
 use uuid::Uuid;
 
 use crate::models::{MessageStatus, OfflineMessage, StoreMessageRequest};
+use crate::quota::{MailboxUsage, QuotaConfig, QuotaError};
+
+/// Why a message was not stored
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error(transparent)]
+    Quota(#[from] QuotaError),
+
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+}
 
 /// Database manager
 #[derive(Clone)]
@@ -33,16 +45,34 @@ impl Database {
         &self.pool
     }
 
-    /// Store a new offline message
+    /// Store a new offline message if the recipient's mailbox has room for it
+    ///
+    /// The quota check and the insert run in one transaction holding a lock
+    /// on the recipient's mailbox, so concurrent stores can't all pass the
+    /// check and overfill it together. `sender_ip` is the request's source
+    /// address, counted against its share of the mailbox.
     pub async fn store_message(
         &self,
         req: &StoreMessageRequest,
-    ) -> Result<(Uuid, String), sqlx::Error> {
+        sender_ip: Option<&str>,
+        quota: &QuotaConfig,
+    ) -> Result<(Uuid, String), StoreError> {
         let payload_bytes = base64::decode(&req.encrypted_payload)
             .map_err(|e| sqlx::Error::Decode(Box::new(e)))?;
 
         let message_type = req.message_type.clone().unwrap_or_else(|| "text".to_string());
 
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+            .bind(&req.recipient_peer_id)
+            .execute(&mut *tx)
+            .await?;
+
+        let usage =
+            Self::mailbox_usage(&mut tx, &req.recipient_peer_id, &req.sender_peer_id, sender_ip)
+                .await?;
+        quota.check_mailbox(&usage, payload_bytes.len())?;
+
         let row = sqlx::query(
             r#"
             INSERT INTO offline_messages (
@@ -51,9 +81,10 @@ impl Database {
                 encrypted_payload,
                 message_type,
                 message_id,
-                payload_size_bytes
+                payload_size_bytes,
+                sender_ip
             )
-            VALUES ($1, $2, $3, $4, $5, $6)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
             ON CONFLICT (message_id) DO NOTHING
             RETURNING id, created_at, expires_at
             "#,
@@ -64,8 +95,10 @@ impl Database {
         .bind(&message_type)
         .bind(&req.message_id)
         .bind(payload_bytes.len() as i32)
-        .fetch_one(&self.pool)
+        .bind(sender_ip)
+        .fetch_one(&mut *tx)
         .await?;
+        tx.commit().await?;
 
         let id: Uuid = row.get("id");
 
@@ -80,6 +113,41 @@ impl Database {
         Ok((id, req.message_id.clone()))
     }
 
+    /// Pending messages in a recipient's mailbox, and how many came from
+    /// `sender_peer_id` and from `sender_ip`
+    async fn mailbox_usage(
+        conn: &mut sqlx::PgConnection,
+        recipient_peer_id: &str,
+        sender_peer_id: &str,
+        sender_ip: Option<&str>,
+    ) -> Result<MailboxUsage, sqlx::Error> {
+        let row = sqlx::query(
+            r#"
+            SELECT
+                COUNT(*) AS pending_messages,
+                COALESCE(SUM(payload_size_bytes), 0)::BIGINT AS pending_bytes,
+                COUNT(*) FILTER (WHERE sender_peer_id = $2) AS pending_from_sender,
+                COUNT(*) FILTER (WHERE sender_ip = $3) AS pending_from_ip
+            FROM offline_messages
+            WHERE recipient_peer_id = $1
+              AND status = 'pending'
+              AND expires_at > NOW()
+            "#,
+        )
+        .bind(recipient_peer_id)
+        .bind(sender_peer_id)
+        .bind(sender_ip)
+        .fetch_one(conn)
+        .await?;
+
+        Ok(MailboxUsage {
+            pending_messages: row.get("pending_messages"),
+            pending_bytes: row.get("pending_bytes"),
+            pending_from_sender: row.get("pending_from_sender"),
+            pending_from_ip: row.get("pending_from_ip"),
+        })
+    }
+
     /// Retrieve pending messages for a recipient
     pub async fn retrieve_messages(
         &self,
@@ -127,17 +195,25 @@ impl Database {
     }
 
     /// Delete (acknowledge) messages by message IDs
-    pub async fn delete_messages(&self, message_ids: &[String]) -> Result<i64, sqlx::Error> {
+    ///
+    /// Only messages addressed to `recipient_peer_id` are affected.
+    pub async fn delete_messages(
+        &self,
+        recipient_peer_id: &str,
+        message_ids: &[String],
+    ) -> Result<i64, sqlx::Error> {
         let result = sqlx::query(
             r#"
             UPDATE offline_messages
             SET status = 'delivered',
                 delivered_at = NOW()
             WHERE message_id = ANY($1)
+              AND recipient_peer_id = $2
               AND status = 'pending'
             "#,
         )
         .bind(message_ids)
+        .bind(recipient_peer_id)
         .execute(&self.pool)
         .await?;
 
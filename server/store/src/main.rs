@@ -7,12 +7,15 @@ use actix_web::{middleware, web, App, HttpServer};
 use std::env;
 
 mod api;
+mod auth;
 mod database;
 mod models;
+mod quota;
 mod redis_client;
 mod ttl_cleanup;
 
 use database::Database;
+use quota::{QuotaConfig, QuotaStats};
 use redis_client::RedisClient;
 use ttl_cleanup::TtlCleanupJob;
 
@@ -64,9 +67,28 @@ async fn main() -> std::io::Result<()> {
         });
     }
 
+    let quota = QuotaConfig::from_env();
+    tracing::info!(
+        "📏 Quotas: {} bytes/message, {} messages and {} bytes per mailbox, {} per sender, {} per IP, {} stores per {}s per sender, {} per {}s per IP, {} per {}s per IP and recipient",
+        quota.max_payload_bytes,
+        quota.max_pending_per_recipient,
+        quota.max_pending_bytes_per_recipient,
+        quota.max_pending_per_sender,
+        quota.max_pending_per_ip,
+        quota.sender_rate.max_requests,
+        quota.sender_rate.window_seconds,
+        quota.ip_rate.max_requests,
+        quota.ip_rate.window_seconds,
+        quota.ip_recipient_rate.max_requests,
+        quota.ip_recipient_rate.window_seconds
+    );
+
     // Create shared state
     let db_data = web::Data::new(database);
     let redis_data = web::Data::new(redis);
+    let json_config = web::JsonConfig::default().limit(quota.max_request_bytes());
+    let quota_data = web::Data::new(quota);
+    let quota_stats = web::Data::new(QuotaStats::default());
 
     tracing::info!("🌐 Starting HTTP server on port {}", server_port);
     tracing::info!("   POST   /api/store           - Store offline message");
@@ -88,6 +110,9 @@ async fn main() -> std::io::Result<()> {
             // State
             .app_data(db_data.clone())
             .app_data(redis_data.clone())
+            .app_data(quota_data.clone())
+            .app_data(quota_stats.clone())
+            .app_data(json_config.clone())
             // Middleware
             .wrap(middleware::Logger::default())
             .wrap(middleware::Compress::default())
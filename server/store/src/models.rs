@@ -59,6 +59,12 @@ pub struct StoreMessageRequest {
     pub encrypted_payload: String,
     pub message_type: Option<String>,
     pub message_id: String,
+    /// Unix timestamp (seconds) the sender signed
+    pub timestamp: i64,
+    /// Random value making each signed request unique
+    pub nonce: String,
+    /// Base64 sender signature over `auth::store_challenge`
+    pub signature: String,
 }
 
 impl StoreMessageRequest {
@@ -76,6 +82,9 @@ impl StoreMessageRequest {
         if self.encrypted_payload.is_empty() {
             return Err("encrypted_payload is required".to_string());
         }
+        if self.nonce.is_empty() {
+            return Err("nonce is required".to_string());
+        }
 
         // Validate base64
         if base64::decode(&self.encrypted_payload).is_err() {
@@ -84,6 +93,17 @@ impl StoreMessageRequest {
 
         Ok(())
     }
+
+    /// Size of the decoded payload, computed without decoding it
+    pub fn payload_size(&self) -> usize {
+        let padding = self
+            .encrypted_payload
+            .bytes()
+            .rev()
+            .take_while(|&b| b == b'=')
+            .count();
+        (self.encrypted_payload.len() / 4 * 3).saturating_sub(padding)
+    }
 }
 
 /// Response after storing a message
@@ -100,6 +120,12 @@ pub struct StoreMessageResponse {
 pub struct RetrieveMessagesRequest {
     pub peer_id: String,
     pub limit: Option<i32>,
+    /// Unix timestamp (seconds) the recipient signed
+    pub timestamp: i64,
+    /// Random value making each signed request unique
+    pub nonce: String,
+    /// Base64 recipient signature over `auth::retrieve_challenge`
+    pub signature: String,
 }
 
 /// Response with pending messages
@@ -137,7 +163,15 @@ impl From<OfflineMessage> for OfflineMessageDto {
 /// Request to delete (acknowledge) messages
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeleteMessagesRequest {
+    /// Recipient acknowledging its messages
+    pub peer_id: String,
     pub message_ids: Vec<String>,
+    /// Unix timestamp (seconds) the recipient signed
+    pub timestamp: i64,
+    /// Random value making each signed request unique
+    pub nonce: String,
+    /// Base64 recipient signature over `auth::delete_challenge`
+    pub signature: String,
 }
 
 /// Response after deleting messages
@@ -174,3 +208,26 @@ mod base64_serde {
         base64::decode(&s).map_err(serde::de::Error::custom)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload_size_matches_decoded_length() {
+        for len in 0..8 {
+            let payload = vec![7u8; len];
+            let req = StoreMessageRequest {
+                recipient_peer_id: "recipient".to_string(),
+                sender_peer_id: "sender".to_string(),
+                encrypted_payload: base64::encode(&payload),
+                message_type: None,
+                message_id: "msg".to_string(),
+                timestamp: 0,
+                nonce: String::new(),
+                signature: String::new(),
+            };
+            assert_eq!(req.payload_size(), len);
+        }
+    }
+}
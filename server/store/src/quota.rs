@@ -0,0 +1,426 @@
+//! Mailbox quotas and sender rate limiting
+//!
+//! Limits what a mailbox can hold (pending messages and bytes per recipient)
+//! and what a single sender can put into one: its share of a recipient's
+//! mailbox and its request rate, so one spammer can't fill a victim's
+//! mailbox for the 14 days messages are kept. Peer ids cost nothing to
+//! generate, so store requests are also limited per source IP, overall and
+//! per recipient, and one source IP only gets a share of each mailbox.
+//!
+//! The payload limit matches the client's direct-delivery frame limit
+//! (`MAX_MESSAGE_FRAME_SIZE` in core): anything a peer could receive
+//! directly also fits in its mailbox.
+
+use serde::Serialize;
+use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use thiserror::Error;
+
+/// Rate limit for one sender
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitConfig {
+    pub max_requests: u32,
+    pub window_seconds: u64,
+}
+
+impl RateLimitConfig {
+    /// Storing messages: 300 requests per hour per sender
+    pub fn store() -> Self {
+        Self {
+            max_requests: 300,
+            window_seconds: 3600,
+        }
+    }
+
+    /// Storing messages: 1000 requests per hour per source IP
+    pub fn store_per_ip() -> Self {
+        Self {
+            max_requests: 1000,
+            window_seconds: 3600,
+        }
+    }
+
+    /// Storing messages: 300 requests per hour per source IP and recipient
+    pub fn store_per_ip_recipient() -> Self {
+        Self {
+            max_requests: 300,
+            window_seconds: 3600,
+        }
+    }
+}
+
+/// Largest message a client sends directly (`MAX_MESSAGE_FRAME_SIZE` in core)
+pub const MAX_MESSAGE_BYTES: usize = 1024 * 1024;
+
+/// Limits applied when storing a message
+#[derive(Debug, Clone, Copy)]
+pub struct QuotaConfig {
+    /// Largest accepted (decoded) payload
+    pub max_payload_bytes: usize,
+    /// Pending messages a recipient's mailbox holds
+    pub max_pending_per_recipient: i64,
+    /// Pending payload bytes a recipient's mailbox holds
+    pub max_pending_bytes_per_recipient: i64,
+    /// Pending messages one sender may have in a recipient's mailbox
+    pub max_pending_per_sender: i64,
+    /// Pending messages one source IP may have in a recipient's mailbox,
+    /// whatever sender ids it uses
+    pub max_pending_per_ip: i64,
+    /// Store requests per sender
+    pub sender_rate: RateLimitConfig,
+    /// Store requests per source IP
+    pub ip_rate: RateLimitConfig,
+    /// Store requests per source IP to one recipient
+    pub ip_recipient_rate: RateLimitConfig,
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        Self {
+            max_payload_bytes: MAX_MESSAGE_BYTES,
+            max_pending_per_recipient: 1000,
+            max_pending_bytes_per_recipient: 50 * 1024 * 1024,
+            max_pending_per_sender: 100,
+            max_pending_per_ip: 200,
+            sender_rate: RateLimitConfig::store(),
+            ip_rate: RateLimitConfig::store_per_ip(),
+            ip_recipient_rate: RateLimitConfig::store_per_ip_recipient(),
+        }
+    }
+}
+
+impl QuotaConfig {
+    /// Defaults, overridden by the `QUOTA_*` / `RATE_LIMIT_*` environment variables
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str, default: T) -> T {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        }
+
+        let defaults = Self::default();
+        Self {
+            max_payload_bytes: var("QUOTA_MAX_PAYLOAD_BYTES", defaults.max_payload_bytes),
+            max_pending_per_recipient: var(
+                "QUOTA_MAX_PENDING_PER_RECIPIENT",
+                defaults.max_pending_per_recipient,
+            ),
+            max_pending_bytes_per_recipient: var(
+                "QUOTA_MAX_PENDING_BYTES_PER_RECIPIENT",
+                defaults.max_pending_bytes_per_recipient,
+            ),
+            max_pending_per_sender: var(
+                "QUOTA_MAX_PENDING_PER_SENDER",
+                defaults.max_pending_per_sender,
+            ),
+            max_pending_per_ip: var("QUOTA_MAX_PENDING_PER_IP", defaults.max_pending_per_ip),
+            sender_rate: RateLimitConfig {
+                max_requests: var(
+                    "RATE_LIMIT_STORE_REQUESTS",
+                    defaults.sender_rate.max_requests,
+                ),
+                window_seconds: var(
+                    "RATE_LIMIT_STORE_WINDOW_SECONDS",
+                    defaults.sender_rate.window_seconds,
+                ),
+            },
+            ip_rate: RateLimitConfig {
+                max_requests: var(
+                    "RATE_LIMIT_STORE_IP_REQUESTS",
+                    defaults.ip_rate.max_requests,
+                ),
+                window_seconds: var(
+                    "RATE_LIMIT_STORE_IP_WINDOW_SECONDS",
+                    defaults.ip_rate.window_seconds,
+                ),
+            },
+            ip_recipient_rate: RateLimitConfig {
+                max_requests: var(
+                    "RATE_LIMIT_STORE_IP_RECIPIENT_REQUESTS",
+                    defaults.ip_recipient_rate.max_requests,
+                ),
+                window_seconds: var(
+                    "RATE_LIMIT_STORE_IP_RECIPIENT_WINDOW_SECONDS",
+                    defaults.ip_recipient_rate.window_seconds,
+                ),
+            },
+        }
+    }
+
+    /// Largest JSON body to accept: the base64 payload plus the other fields
+    ///
+    /// Bodies above it are cut off before `check_payload` could answer 413.
+    pub fn max_request_bytes(&self) -> usize {
+        self.max_payload_bytes.div_ceil(3) * 4 + 16 * 1024
+    }
+
+    /// Check a payload size before anything else is done with the request
+    pub fn check_payload(&self, payload_bytes: usize) -> Result<(), QuotaError> {
+        if payload_bytes > self.max_payload_bytes {
+            return Err(QuotaError::PayloadTooLarge {
+                size: payload_bytes,
+                max: self.max_payload_bytes,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check whether a message of `payload_bytes` fits in a mailbox
+    pub fn check_mailbox(
+        &self,
+        usage: &MailboxUsage,
+        payload_bytes: usize,
+    ) -> Result<(), QuotaError> {
+        if usage.pending_from_sender >= self.max_pending_per_sender {
+            return Err(QuotaError::SenderShareExceeded {
+                max: self.max_pending_per_sender,
+            });
+        }
+        if usage.pending_from_ip >= self.max_pending_per_ip {
+            return Err(QuotaError::SourceShareExceeded {
+                max: self.max_pending_per_ip,
+            });
+        }
+        if usage.pending_messages >= self.max_pending_per_recipient {
+            return Err(QuotaError::MailboxFull {
+                max: self.max_pending_per_recipient,
+            });
+        }
+        if usage.pending_bytes + payload_bytes as i64 > self.max_pending_bytes_per_recipient {
+            return Err(QuotaError::MailboxBytesExceeded {
+                max: self.max_pending_bytes_per_recipient,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Pending messages in a recipient's mailbox
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MailboxUsage {
+    pub pending_messages: i64,
+    pub pending_bytes: i64,
+    /// Of which stored by the sender of the new message
+    pub pending_from_sender: i64,
+    /// Of which stored from the source IP of the new message
+    pub pending_from_ip: i64,
+}
+
+/// Why a message was refused
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum QuotaError {
+    #[error("Payload of {size} bytes exceeds the {max} byte limit")]
+    PayloadTooLarge { size: usize, max: usize },
+
+    #[error("Recipient mailbox is full ({max} pending messages)")]
+    MailboxFull { max: i64 },
+
+    #[error("Recipient mailbox is full ({max} pending bytes)")]
+    MailboxBytesExceeded { max: i64 },
+
+    #[error("Too many pending messages from this sender ({max})")]
+    SenderShareExceeded { max: i64 },
+
+    #[error("Too many pending messages from this address ({max})")]
+    SourceShareExceeded { max: i64 },
+
+    #[error("Rate limit exceeded ({max} requests per {window_seconds} seconds)")]
+    RateLimited { max: u32, window_seconds: u64 },
+}
+
+impl QuotaError {
+    /// Machine-readable reason returned to clients
+    pub fn code(&self) -> &'static str {
+        match self {
+            QuotaError::PayloadTooLarge { .. } => "payload_too_large",
+            QuotaError::MailboxFull { .. } => "mailbox_full",
+            QuotaError::MailboxBytesExceeded { .. } => "mailbox_bytes_exceeded",
+            QuotaError::SenderShareExceeded { .. } => "sender_share_exceeded",
+            QuotaError::SourceShareExceeded { .. } => "source_share_exceeded",
+            QuotaError::RateLimited { .. } => "rate_limited",
+        }
+    }
+}
+
+/// Requests refused since the server started, per reason
+#[derive(Debug, Default)]
+pub struct QuotaStats {
+    payload_too_large: AtomicU64,
+    mailbox_full: AtomicU64,
+    mailbox_bytes_exceeded: AtomicU64,
+    sender_share_exceeded: AtomicU64,
+    source_share_exceeded: AtomicU64,
+    rate_limited: AtomicU64,
+}
+
+/// Snapshot of `QuotaStats` for `/api/stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct QuotaStatsSnapshot {
+    pub payload_too_large: u64,
+    pub mailbox_full: u64,
+    pub mailbox_bytes_exceeded: u64,
+    pub sender_share_exceeded: u64,
+    pub source_share_exceeded: u64,
+    pub rate_limited: u64,
+}
+
+impl QuotaStats {
+    /// Count a refused request
+    pub fn record(&self, error: &QuotaError) {
+        let counter = match error {
+            QuotaError::PayloadTooLarge { .. } => &self.payload_too_large,
+            QuotaError::MailboxFull { .. } => &self.mailbox_full,
+            QuotaError::MailboxBytesExceeded { .. } => &self.mailbox_bytes_exceeded,
+            QuotaError::SenderShareExceeded { .. } => &self.sender_share_exceeded,
+            QuotaError::SourceShareExceeded { .. } => &self.source_share_exceeded,
+            QuotaError::RateLimited { .. } => &self.rate_limited,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> QuotaStatsSnapshot {
+        QuotaStatsSnapshot {
+            payload_too_large: self.payload_too_large.load(Ordering::Relaxed),
+            mailbox_full: self.mailbox_full.load(Ordering::Relaxed),
+            mailbox_bytes_exceeded: self.mailbox_bytes_exceeded.load(Ordering::Relaxed),
+            sender_share_exceeded: self.sender_share_exceeded.load(Ordering::Relaxed),
+            source_share_exceeded: self.source_share_exceeded.load(Ordering::Relaxed),
+            rate_limited: self.rate_limited.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> QuotaConfig {
+        QuotaConfig {
+            max_payload_bytes: 1024,
+            max_pending_per_recipient: 10,
+            max_pending_bytes_per_recipient: 4096,
+            max_pending_per_sender: 3,
+            max_pending_per_ip: 5,
+            sender_rate: RateLimitConfig::store(),
+            ip_rate: RateLimitConfig::store_per_ip(),
+            ip_recipient_rate: RateLimitConfig::store_per_ip_recipient(),
+        }
+    }
+
+    #[test]
+    fn test_payload_limit() {
+        assert!(config().check_payload(1024).is_ok());
+        assert_eq!(
+            config().check_payload(1025),
+            Err(QuotaError::PayloadTooLarge { size: 1025, max: 1024 })
+        );
+    }
+
+    #[test]
+    fn test_default_payload_limit_fits_direct_messages() {
+        assert!(QuotaConfig::default().check_payload(MAX_MESSAGE_BYTES).is_ok());
+        assert!(QuotaConfig::default().check_payload(MAX_MESSAGE_BYTES + 1).is_err());
+        assert!(QuotaConfig::default().max_request_bytes() > MAX_MESSAGE_BYTES / 3 * 4);
+    }
+
+    #[test]
+    fn test_mailbox_limits() {
+        let config = config();
+        let usage = MailboxUsage {
+            pending_messages: 5,
+            pending_bytes: 2048,
+            pending_from_sender: 2,
+            pending_from_ip: 2,
+        };
+        assert!(config.check_mailbox(&usage, 1024).is_ok());
+
+        let full = MailboxUsage {
+            pending_messages: 10,
+            ..usage
+        };
+        assert_eq!(config.check_mailbox(&full, 1), Err(QuotaError::MailboxFull { max: 10 }));
+
+        assert_eq!(
+            config.check_mailbox(&usage, 2049),
+            Err(QuotaError::MailboxBytesExceeded { max: 4096 })
+        );
+    }
+
+    #[test]
+    fn test_single_sender_cannot_fill_mailbox() {
+        let config = config();
+        let usage = MailboxUsage {
+            pending_messages: 3,
+            pending_bytes: 300,
+            pending_from_sender: 3,
+            pending_from_ip: 3,
+        };
+
+        // The spammer is refused while the mailbox still has room for others
+        assert_eq!(
+            config.check_mailbox(&usage, 100),
+            Err(QuotaError::SenderShareExceeded { max: 3 })
+        );
+        let other_sender = MailboxUsage {
+            pending_from_sender: 0,
+            ..usage
+        };
+        assert!(config.check_mailbox(&other_sender, 100).is_ok());
+    }
+
+    #[test]
+    fn test_fresh_sender_ids_from_one_ip_cannot_fill_mailbox() {
+        let config = config();
+
+        // Every message from a new sender id, all from the same address
+        let mut usage = MailboxUsage::default();
+        let refused = loop {
+            let attempt = MailboxUsage {
+                pending_from_sender: 0,
+                ..usage
+            };
+            if let Err(e) = config.check_mailbox(&attempt, 100) {
+                break e;
+            }
+            usage.pending_messages += 1;
+            usage.pending_bytes += 100;
+            usage.pending_from_ip += 1;
+        };
+
+        assert_eq!(refused, QuotaError::SourceShareExceeded { max: 5 });
+        assert_eq!(usage.pending_messages, 5);
+        assert!(usage.pending_messages < config.max_pending_per_recipient);
+
+        // Senders elsewhere still get through
+        let other_ip = MailboxUsage {
+            pending_from_sender: 0,
+            pending_from_ip: 0,
+            ..usage
+        };
+        assert!(config.check_mailbox(&other_ip, 100).is_ok());
+    }
+
+    #[test]
+    fn test_stats_count_rejections() {
+        let stats = QuotaStats::default();
+        stats.record(&QuotaError::MailboxFull { max: 10 });
+        stats.record(&QuotaError::RateLimited {
+            max: 1,
+            window_seconds: 60,
+        });
+        stats.record(&QuotaError::RateLimited {
+            max: 1,
+            window_seconds: 60,
+        });
+
+        assert_eq!(
+            stats.snapshot(),
+            QuotaStatsSnapshot {
+                mailbox_full: 1,
+                rate_limited: 2,
+                ..Default::default()
+            }
+        );
+    }
+}
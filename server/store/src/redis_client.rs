@@ -37,6 +37,50 @@ impl RedisClient {
         Ok(())
     }
 
+    /// Record a request signature; false if it was already used
+    ///
+    /// The key expires with the signature's validity window, after which the
+    /// timestamp check rejects it anyway.
+    pub async fn claim_signature(
+        &self,
+        signature: &str,
+        ttl_seconds: u64,
+    ) -> Result<bool, redis::RedisError> {
+        let mut conn = self.get_connection().await?;
+
+        let key = format!("signature:{}", signature);
+        let claimed: bool = redis::cmd("SET")
+            .arg(&key)
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg(ttl_seconds)
+            .query_async::<_, Option<String>>(&mut conn)
+            .await?
+            .is_some();
+
+        Ok(claimed)
+    }
+
+    /// Count a request against a rate limit window; returns the count so far
+    pub async fn increment_rate_limit(
+        &self,
+        key: &str,
+        window_seconds: u64,
+    ) -> Result<u32, redis::RedisError> {
+        let mut conn = self.get_connection().await?;
+
+        let key = format!("ratelimit:{}", key);
+        let count: u32 = conn.incr(&key, 1).await?;
+
+        // Set expiry on first request
+        if count == 1 {
+            let _: () = conn.expire(&key, window_seconds as i64).await?;
+        }
+
+        Ok(count)
+    }
+
     /// Check if a peer is online (in presence set)
     pub async fn is_peer_online(&self, peer_id: &str) -> Result<bool, redis::RedisError> {
         let mut conn = self.get_connection().await?;